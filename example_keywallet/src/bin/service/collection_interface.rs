@@ -13,20 +13,14 @@ pub fn handle_collection_interface(
     ctx: &mut &mut super::Context,
     matches: Matches,
     msg: &MarshalledMessage,
-    _env: &mut super::MyHandleEnv,
+    env: &mut super::MyHandleEnv,
 ) -> HandleResult<()> {
     let col_id = matches
         .matches
         .get(":collection_id")
         .expect("Called collection interface without a match on \":collection_id\"");
 
-    match msg
-        .dynheader
-        .member
-        .as_ref()
-        .expect("NO MEMBER :(")
-        .as_str()
-    {
+    match env.member() {
         "SearchItems" => {
             let attrs: HashMap<&str, &str> = msg.body.parser().get().expect("Types did not match!");
             println!("Search items with attrs: {:?}", attrs);
@@ -8,7 +8,7 @@ pub fn handle_item_interface(
     ctx: &mut &mut super::Context,
     matches: Matches,
     msg: &MarshalledMessage,
-    _env: &mut super::MyHandleEnv,
+    env: &mut super::MyHandleEnv,
 ) -> HandleResult<()> {
     let col_id = matches
         .matches
@@ -19,15 +19,9 @@ pub fn handle_item_interface(
         .get(":item_id")
         .expect("Called item interface without a match on \":item_id\"");
 
-    match msg
-        .dynheader
-        .member
-        .as_ref()
-        .expect("NO MEMBER :(")
-        .as_str()
-    {
+    match env.member() {
         "Delete" => {
-            println!("Delete item: {:?}", msg.dynheader.object.as_ref().unwrap());
+            println!("Delete item: {:?}", env.object());
 
             ctx.service.delete_item(col_id, item_id).unwrap();
 
@@ -37,10 +31,7 @@ pub fn handle_item_interface(
         }
 
         "GetSecret" => {
-            println!(
-                "Get secret from item: {:?}",
-                msg.dynheader.object.as_ref().unwrap()
-            );
+            println!("Get secret from item: {:?}", env.object());
 
             let session: ObjectPath<&str> = msg.body.parser().get().expect("Types did not match");
             let secret = ctx.service.get_secret(col_id, item_id).unwrap();
@@ -57,10 +48,7 @@ pub fn handle_item_interface(
         }
 
         "SetSecret" => {
-            println!(
-                "Set secret for item: {:?}",
-                msg.dynheader.object.as_ref().unwrap()
-            );
+            println!("Set secret for item: {:?}", env.object());
 
             let secret: messages::Secret = msg.body.parser().get().expect("Types did not match");
             ctx.service
@@ -7,7 +7,9 @@ use rustbus::connection::dispatch_conn::HandleResult;
 use rustbus::connection::dispatch_conn::Matches;
 use rustbus::connection::get_session_bus_path;
 use rustbus::connection::ll_conn::DuplexConn;
+use rustbus::connection::properties::{Access, Properties, PropertyHandle};
 use rustbus::message_builder::MarshalledMessage;
+use rustbus::standard_messages::RequestNameReply;
 use rustbus::wire::ObjectPath;
 
 mod collection_interface;
@@ -16,6 +18,8 @@ mod service;
 mod service_interface;
 pub struct Context {
     service: service::SecretService,
+    properties: Properties,
+    collections: PropertyHandle<Vec<ObjectPath<String>>>,
 }
 pub type MyHandleEnv<'a, 'b> = HandleEnvironment<&'b mut Context, ()>;
 
@@ -30,7 +34,9 @@ fn default_handler(
         "Woohoo the default handler got called for: {:?}",
         msg.dynheader
     );
-    Ok(None)
+    Ok(Some(rustbus::standard_messages::unknown_method(
+        &msg.dynheader,
+    )))
 }
 
 enum ObjectType<'a> {
@@ -60,145 +66,23 @@ fn get_object_type_and_id<'a>(path: &'a ObjectPath<&'a str>) -> Option<ObjectTyp
     }
 }
 
-fn service_handler(
-    ctx: &mut &mut Context,
-    matches: Matches,
-    msg: &MarshalledMessage,
-    env: &mut MyHandleEnv,
-) -> HandleResult<()> {
-    println!(
-        "Woohoo the service handler got called for: {:?}",
-        msg.dynheader
-    );
-
-    match msg
-        .dynheader
-        .interface
-        .as_ref()
-        .expect("NO INTERFACE :(")
-        .as_str()
-    {
-        "org.freedesktop.Secret.Service" => {
-            service_interface::handle_service_interface(ctx, matches, msg, env)
-        }
-        other => {
-            println!("Unknown interface called: {}", other);
-            Ok(Some(rustbus::standard_messages::unknown_method(
-                &msg.dynheader,
-            )))
-        }
-    }
-}
-fn collection_handler(
-    ctx: &mut &mut Context,
-    matches: Matches,
-    msg: &MarshalledMessage,
-    env: &mut MyHandleEnv,
-) -> HandleResult<()> {
-    println!(
-        "Woohoo the collection handler got called for: {:?}",
-        msg.dynheader
-    );
-
-    match msg
-        .dynheader
-        .interface
-        .as_ref()
-        .expect("NO INTERFACE :(")
-        .as_str()
-    {
-        "org.freedesktop.Secret.Collection" => {
-            collection_interface::handle_collection_interface(ctx, matches, msg, env)
-        }
-        other => {
-            println!("Unknown interface called: {}", other);
-            Ok(Some(rustbus::standard_messages::unknown_method(
-                &msg.dynheader,
-            )))
-        }
-    }
-}
-fn item_handler(
-    ctx: &mut &mut Context,
-    matches: Matches,
-    msg: &MarshalledMessage,
-    env: &mut MyHandleEnv,
-) -> HandleResult<()> {
-    println!(
-        "Woohoo the item handler got called for: {:?}",
-        msg.dynheader
-    );
-
-    match msg
-        .dynheader
-        .interface
-        .as_ref()
-        .expect("NO INTERFACE :(")
-        .as_str()
-    {
-        "org.freedesktop.Secret.Item" => {
-            item_interface::handle_item_interface(ctx, matches, msg, env)
-        }
-        other => {
-            println!("Unknown interface called: {}", other);
-            Ok(Some(rustbus::standard_messages::unknown_method(
-                &msg.dynheader,
-            )))
-        }
-    }
-}
-
 #[allow(clippy::unnecessary_wraps)]
-fn session_handler(
+fn close_session(
     ctx: &mut &mut Context,
     matches: Matches,
-    msg: &MarshalledMessage,
+    _msg: &MarshalledMessage,
     _env: &mut MyHandleEnv,
 ) -> HandleResult<()> {
-    println!(
-        "Woohoo the session handler got called for: {:?}",
-        msg.dynheader
-    );
     let ses_id = matches
         .matches
-        .get(":collection_id")
+        .get(":session_id")
         .expect("Called session interface without a match on \":session_id\"");
-    match msg
-        .dynheader
-        .interface
-        .as_ref()
-        .expect("NO INTERFACE :(")
-        .as_str()
-    {
-        "org.freedesktop.Secret.Session" => {
-            match msg
-                .dynheader
-                .member
-                .as_ref()
-                .expect("NO MEMBER :(")
-                .as_str()
-            {
-                "Close" => {
-                    ctx.service.close_session(ses_id).unwrap();
-                    Ok(None)
-                }
-                other => {
-                    println!("Unknown method called: {}", other);
-                    Ok(Some(rustbus::standard_messages::unknown_method(
-                        &msg.dynheader,
-                    )))
-                }
-            }
-        }
-        other => {
-            println!("Unknown interface called: {}", other);
-            Ok(Some(rustbus::standard_messages::unknown_method(
-                &msg.dynheader,
-            )))
-        }
-    }
+    ctx.service.close_session(ses_id).unwrap();
+    Ok(None)
 }
 
+const SESSION_LIFETIME: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
 fn main() {
     let mut con = DuplexConn::connect_to_bus(get_session_bus_path().unwrap(), false).unwrap();
 
@@ -208,7 +92,8 @@ fn main() {
 
     println!("Unique name: {}", unique_name);
 
-    con.send
+    let serial = con
+        .send
         .send_message(&rustbus::standard_messages::request_name(
             "io.killingspark.secrets",
             rustbus::standard_messages::DBUS_NAME_FLAG_REPLACE_EXISTING,
@@ -217,35 +102,77 @@ fn main() {
         .write_all()
         .unwrap();
 
-    // The response content should be looked at. ATM we just assume the name acquisition worked...
-    let _resp = con
-        .recv
-        .get_next_message(rustbus::connection::Timeout::Infinite)
-        .unwrap();
+    // the bus may send signals like NameAcquired before the reply
+    let resp = loop {
+        let msg = con
+            .recv
+            .get_next_message(rustbus::connection::Timeout::Infinite)
+            .unwrap();
+        if msg.dynheader.response_serial == Some(serial) {
+            break msg;
+        }
+    };
+    match rustbus::standard_messages::parse_request_name_reply(&resp).unwrap() {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => {}
+        other => panic!(
+            "Could not become the owner of io.killingspark.secrets: {:?}",
+            other
+        ),
+    }
 
     let dh = Box::new(default_handler);
 
+    let properties = Properties::new();
+    let collections = properties
+        .register(
+            "/org/freedesktop/secrets",
+            "org.freedesktop.Secret.Service",
+            "Collections",
+            Access::Read,
+            Vec::<ObjectPath<String>>::new(),
+        )
+        .unwrap();
     let mut ctx = Context {
         service: service::SecretService::default(),
+        properties: properties.clone(),
+        collections,
     };
     let mut dp_con = DispatchConn::new(con, &mut ctx, dh);
+    dp_con.add_properties(properties);
 
-    let service_handler = Box::new(service_handler);
-    let collection_handler = Box::new(collection_handler);
-    let item_handler = Box::new(item_handler);
-    let session_handler = Box::new(session_handler);
-    dp_con.add_handler("/org/freedesktop/secrets", service_handler);
-    dp_con.add_handler(
+    // calls to other interfaces or members end up in the default handler
+    dp_con.add_interface_handler(
+        "/org/freedesktop/secrets",
+        "org.freedesktop.Secret.Service",
+        Box::new(service_interface::handle_service_interface),
+    );
+    dp_con.add_interface_handler(
         "/org/freedesktop/secrets/collection/:collection_id",
-        collection_handler,
+        "org.freedesktop.Secret.Collection",
+        Box::new(collection_interface::handle_collection_interface),
     );
-    dp_con.add_handler(
+    dp_con.add_interface_handler(
         "/org/freedesktop/secrets/collection/:collection_id/:item_id",
-        item_handler,
+        "org.freedesktop.Secret.Item",
+        Box::new(item_interface::handle_item_interface),
     );
-    dp_con.add_handler(
+    dp_con.add_member_handler(
         "/org/freedesktop/secrets/session/:session_id",
-        session_handler,
+        "org.freedesktop.Secret.Session",
+        "Close",
+        Box::new(close_session),
+    );
+
+    // clients that forget to close their sessions should not keep them forever
+    dp_con.add_timer(
+        std::time::Duration::from_secs(60),
+        Box::new(|ctx: &mut &mut Context, _env| {
+            let expired = ctx.service.expire_sessions(SESSION_LIFETIME);
+            if expired > 0 {
+                println!("Expired {} sessions", expired);
+            }
+            Ok(())
+        }),
     );
 
     dp_con.run().unwrap();
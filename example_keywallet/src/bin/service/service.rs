@@ -36,6 +36,7 @@ pub enum SessionAlg {
 pub struct Session {
     id: String,
     alg: SessionAlg,
+    opened: std::time::Instant,
 }
 
 #[derive(Default)]
@@ -188,6 +189,7 @@ impl SecretService {
             let session = Session {
                 alg: SessionAlg::Plain,
                 id: self.next_id(),
+                opened: std::time::Instant::now(),
             };
             let path = format!("/org/freedesktop/secrets/session/{}", session.id);
             self.sessions.push(session);
@@ -208,6 +210,12 @@ impl SecretService {
             Err(CloseSessionError::NotFound)
         }
     }
+    /// Close the sessions that were opened longer than `lifetime` ago, returns how many were closed
+    pub fn expire_sessions(&mut self, lifetime: std::time::Duration) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|s| s.opened.elapsed() < lifetime);
+        before - self.sessions.len()
+    }
     pub fn get_secret(&self, col_id: &str, item_id: &str) -> Result<Secret, GetSecretError> {
         let col = self
             .collections
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use rustbus::connection::dispatch_conn::HandleResult;
 use rustbus::connection::dispatch_conn::Matches;
+use rustbus::connection::properties::Access;
 use rustbus::message_builder::MarshalledMessage;
 use rustbus::wire::unmarshal::traits::Variant;
 use rustbus::wire::ObjectPath;
@@ -13,15 +14,9 @@ pub fn handle_service_interface(
     ctx: &mut &mut super::Context,
     _matches: Matches,
     msg: &MarshalledMessage,
-    _env: &mut super::MyHandleEnv,
+    env: &mut super::MyHandleEnv,
 ) -> HandleResult<()> {
-    match msg
-        .dynheader
-        .member
-        .as_ref()
-        .expect("NO MEMBER :(")
-        .as_str()
-    {
+    match env.member() {
         "OpenSession" => {
             let (alg, _input) = msg
                 .body
@@ -46,10 +41,26 @@ pub fn handle_service_interface(
                 props, alias
             );
 
-            ctx.service.create_collection("ABCD").unwrap();
+            let label = props
+                .get("org.freedesktop.Secret.Collection.Label")
+                .and_then(|label| label.get::<&str>().ok())
+                .unwrap_or("");
+            let path = ctx.service.create_collection(label).unwrap();
+
+            // Get, Set and GetAll on the new collection are answered by the DispatchConn
+            let iface = "org.freedesktop.Secret.Collection";
+            ctx.properties
+                .register(&path, iface, "Label", Access::ReadWrite, label.to_owned())?;
+            ctx.properties
+                .register(&path, iface, "Locked", Access::Read, true)?;
+            let mut collections = ctx.collections.get();
+            collections.push(ObjectPath::new(path.clone()).unwrap());
+            // emits PropertiesChanged
+            ctx.collections.set(collections)?;
+
             let mut resp = msg.dynheader.make_response();
             resp.body
-                .push_param(ObjectPath::new("/A/B/C").unwrap())
+                .push_param(ObjectPath::new(path.as_str()).unwrap())
                 .unwrap();
             resp.body.push_param(ObjectPath::new("/").unwrap()).unwrap();
             Ok(Some(resp))
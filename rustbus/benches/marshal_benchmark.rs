@@ -1,8 +1,14 @@
+// the stable-api feature leaves out the params API, only the helpers that do not use it are compiled then
+#![cfg_attr(not(rustbus_params), allow(dead_code, unused_imports))]
+
 use std::num::NonZeroU32;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+#[cfg(rustbus_params)]
 use rustbus::params::Container;
+#[cfg(rustbus_params)]
 use rustbus::params::DictMap;
+#[cfg(rustbus_params)]
 use rustbus::params::Param;
 use rustbus::wire::marshal::marshal;
 use rustbus::wire::unmarshal::unmarshal_dynamic_header;
@@ -23,6 +29,8 @@ fn unmarshal(buf: &[u8]) {
             .unwrap();
 }
 
+#[allow(clippy::mutable_key_type)]
+#[cfg(rustbus_params)]
 fn criterion_benchmark(c: &mut Criterion) {
     let mut params: Vec<Param> = Vec::new();
 
@@ -85,5 +93,10 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("unmarshal", |b| b.iter(|| unmarshal(black_box(&buf))));
 }
 
+#[cfg(rustbus_params)]
 criterion_group!(benches, criterion_benchmark);
+#[cfg(rustbus_params)]
 criterion_main!(benches);
+
+#[cfg(not(rustbus_params))]
+fn main() {}
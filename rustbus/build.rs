@@ -0,0 +1,10 @@
+//! `stable-api` takes precedence over `params` (which is enabled by default), so the experimental parts of the API are
+//! compiled with the `rustbus_params` cfg instead of checking the feature directly.
+
+fn main() {
+    let params = std::env::var_os("CARGO_FEATURE_PARAMS").is_some();
+    let stable_api = std::env::var_os("CARGO_FEATURE_STABLE_API").is_some();
+    if params && !stable_api {
+        println!("cargo:rustc-cfg=rustbus_params");
+    }
+}
@@ -1,7 +1,9 @@
+#[cfg(rustbus_params)]
 use rustbus::{
     connection::Timeout, get_session_bus_path, standard_messages, DuplexConn, MessageType, RpcConn,
 };
 
+#[cfg(rustbus_params)]
 fn main() -> Result<(), rustbus::connection::Error> {
     let session_path = get_session_bus_path()?;
     let con = DuplexConn::connect_to_bus(session_path, true)?;
@@ -99,3 +101,7 @@ fn main() -> Result<(), rustbus::connection::Error> {
         println!("\n");
     }
 }
+
+// the stable-api feature leaves out the params API this uses
+#[cfg(not(rustbus_params))]
+fn main() {}
@@ -37,6 +37,19 @@ struct SubTypeB<'a> {
     s: &'a str,
 }
 
+/// Marshalled as an `a{sv}` dict instead of a struct, like the property maps of most freedesktop APIs
+#[derive(Marshal, Unmarshal, Signature, Default, Debug)]
+#[rustbus(dict)]
+struct Props {
+    #[rustbus(rename = "Name")]
+    name: String,
+    #[rustbus(rename = "Volume")]
+    volume: u32,
+    // left out of the dict if None, and None if the key is missing
+    #[rustbus(rename = "Balance")]
+    balance: Option<f64>,
+}
+
 fn main() {
     let a = A {
         y: 0xAAAAAAAA,
@@ -64,4 +77,20 @@ fn main() {
 
     println!("{:#X?}", sig.body.parser().get::<A>().unwrap());
     println!("{:#X?}", sig.body.parser().get::<B>().unwrap());
+
+    let props = Props {
+        name: "speaker".into(),
+        volume: 42,
+        balance: None,
+    };
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(&props).unwrap();
+
+    println!(
+        "{:?}",
+        sig.body.parser().get::<rustbus::wire::VarDict>().unwrap()
+    );
+    println!("{:?}", sig.body.parser().get::<Props>().unwrap());
 }
@@ -64,13 +64,12 @@ fn send_fd() -> Result<(), rustbus::connection::Error> {
     use std::os::unix::io::AsRawFd;
     let stdin_fd = std::io::stdin();
     sig.body.push_param((&stdin_fd) as &dyn AsRawFd).unwrap();
-    sig.dynheader.num_fds = Some(1);
-    con.send.send_message(&mut sig)?.write_all().unwrap();
+    con.send.send_message(&sig)?.write_all().unwrap();
 
-    let mut sig = MessageBuilder::new()
+    let sig = MessageBuilder::new()
         .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
         .build();
-    con.send.send_message(&mut sig)?.write_all().unwrap();
+    con.send.send_message(&sig)?.write_all().unwrap();
 
     println!("Printing stuff from stdin. The following is input from the other process!");
     let mut line = String::new();
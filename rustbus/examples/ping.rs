@@ -0,0 +1,19 @@
+//! Measure the round trip times to a peer on the session bus, e.g. `cargo run --example ping -- org.freedesktop.DBus 100`
+
+use rustbus::{connection::Timeout, consts::DBUS_NAME, util, RpcConn};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let dest = args.next().unwrap_or_else(|| DBUS_NAME.to_owned());
+    let count = args
+        .next()
+        .map(|count| count.parse().expect("count must be a number"))
+        .unwrap_or(10);
+
+    let mut rpc_con = RpcConn::session_conn(Timeout::Infinite).unwrap();
+    let timeout = Timeout::Duration(std::time::Duration::from_secs(5));
+    match util::ping(&mut rpc_con, &dest, count, timeout) {
+        Ok(stats) => println!("{}: {}", dest, stats),
+        Err(e) => eprintln!("Pinging {} failed: {}", dest, e),
+    }
+}
@@ -0,0 +1,101 @@
+//! A portal-style service that answers calls with a request object and reports the outcome later.
+//!
+//! Run `cargo run --example portal server` in one terminal and `cargo run --example portal` in another.
+
+use std::time::Duration;
+
+use rustbus::connection::dispatch_conn::{DispatchConn, HandleEnvironment, HandleResult, Matches};
+use rustbus::connection::portal_request::{Requests, Response, REQUEST_INTERFACE};
+use rustbus::connection::{get_session_bus_path, Timeout};
+use rustbus::match_rule::MatchRule;
+use rustbus::message_builder::{MarshalledMessage, MessageType};
+use rustbus::standard_messages::{request_name, DBUS_NAME_FLAG_REPLACE_EXISTING};
+use rustbus::wire::{ObjectPath, VarDict};
+use rustbus::{DuplexConn, MessageBuilder, RpcConn};
+
+const NAME: &str = "io.killing.spark.portal";
+const BASE: &str = "/io/killing/spark/portal";
+
+fn pick_color(
+    requests: &mut Requests,
+    _matches: Matches,
+    call: &MarshalledMessage,
+    env: &mut HandleEnvironment<Requests, ()>,
+) -> HandleResult<()> {
+    let options: VarDict = call.body.parser().get()?;
+    let token = options.get::<&str>("handle_token")?;
+    let request = requests.create(call, token, env.conn.clone())?;
+    println!("Started {}", request.path());
+
+    let mut reply = call.dynheader.make_response();
+    reply
+        .body
+        .push_param(ObjectPath::new(request.path()).unwrap())?;
+
+    // stands in for the dialog the user interacts with
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_secs(2));
+        if request.is_closed() {
+            println!("{} was closed", request.path());
+            return;
+        }
+        let mut results = VarDict::new();
+        results.insert("color", (1.0f64, 0.5f64, 0.0f64)).unwrap();
+        request.respond(Response::Success, &results).unwrap();
+    });
+    Ok(Some(reply))
+}
+
+fn main() {
+    let mut con = DuplexConn::connect_to_bus(get_session_bus_path().unwrap(), false).unwrap();
+    let unique_name = con.send_hello(Timeout::Infinite).unwrap();
+
+    if std::env::args().any(|arg| "server".eq(&arg)) {
+        con.send
+            .send_message(&request_name(NAME, DBUS_NAME_FLAG_REPLACE_EXISTING))
+            .unwrap()
+            .write_all()
+            .unwrap();
+
+        let requests = Requests::new(BASE);
+        let mut dpcon = DispatchConn::new(con, requests.clone(), Box::new(|_, _, _, _| Ok(None)));
+        dpcon.add_handler(BASE, Box::new(pick_color));
+        dpcon.add_requests(requests);
+        dpcon.run().unwrap();
+    } else {
+        // subscribe to the Response before calling, it could arrive before the reply
+        let token = "pick_color";
+        let sender = unique_name.trim_start_matches(':').replace('.', "_");
+        let path = format!("{}/request/{}/{}", BASE, sender, token);
+        let mut rpc_con = RpcConn::new(con);
+        let rule = MatchRule::new()
+            .msg_type(MessageType::Signal)
+            .interface(REQUEST_INTERFACE)
+            .member("Response")
+            .path(path.as_str());
+        rpc_con.add_match(rule, Timeout::Infinite).unwrap();
+
+        let mut call = MessageBuilder::new()
+            .call("PickColor")
+            .on(BASE)
+            .at(NAME)
+            .build();
+        let mut options = VarDict::new();
+        options.insert("handle_token", token).unwrap();
+        call.body.push_param(&options).unwrap();
+        let reply = rpc_con.call_method(&mut call, Timeout::Infinite).unwrap();
+        let handle: ObjectPath<&str> = reply.body.parser().get().unwrap();
+        assert_eq!(handle.as_str(), path);
+
+        // the bus also sends NameAcquired for the unique name
+        let signal = loop {
+            let signal = rpc_con.wait_signal(Timeout::Infinite).unwrap();
+            if signal.classify().is_signal(REQUEST_INTERFACE, "Response") {
+                break signal;
+            }
+        };
+        let (response, results) = signal.body.parser().get2::<u32, VarDict>().unwrap();
+        let color = results.get::<(f64, f64, f64)>("color").unwrap();
+        println!("Response {}: {:?}", response, color);
+    }
+}
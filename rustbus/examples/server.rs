@@ -1,12 +1,15 @@
+#[cfg(rustbus_params)]
 use rustbus::{
     connection::Timeout, params::message::Message, standard_messages, MessageType, RpcConn,
 };
 
+#[cfg(rustbus_params)]
 pub enum Commands {
     Echo,
     Reverse(String),
 }
 
+#[cfg(rustbus_params)]
 impl<'a, 'e> Commands {
     fn execute(&self, call: &Message<'a, 'e>) -> rustbus::message_builder::MarshalledMessage {
         match self {
@@ -27,6 +30,7 @@ impl<'a, 'e> Commands {
     }
 }
 
+#[cfg(rustbus_params)]
 fn main() -> Result<(), rustbus::connection::Error> {
     // sends the obligatory hello message
     let mut rpc_con = RpcConn::session_conn(Timeout::Infinite)?;
@@ -118,3 +122,7 @@ fn main() -> Result<(), rustbus::connection::Error> {
         }
     }
 }
+
+// the stable-api feature leaves out the params API this uses
+#[cfg(not(rustbus_params))]
+fn main() {}
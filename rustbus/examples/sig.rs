@@ -33,9 +33,9 @@ fn main() -> Result<(), rustbus::connection::Error> {
 
     println!("{:?}", sig);
 
-    con.send.send_message(&mut sig)?.write_all().unwrap();
+    con.send.send_message(&sig)?.write_all().unwrap();
     std::thread::sleep(std::time::Duration::from_secs(1));
-    con.send.send_message(&mut sig)?.write_all().unwrap();
+    con.send.send_message(&sig)?.write_all().unwrap();
 
     Ok(())
 }
@@ -1,47 +1,73 @@
-//! Deals with authentication to the other side. You probably do not need this.
+//! Deals with authentication to the other side, as a client or as a server. You probably do not need this.
 
-use nix::sys::socket::{self, sendmsg};
+use crate::connection::{Deadline, Timeout};
+use nix::sys::socket::{self, sendmsg, MsgFlags};
 use nix::unistd::getuid;
 use std::io::{IoSlice, Read, Write};
 use std::os::fd::AsRawFd;
 use std::os::unix::net::UnixStream;
 
-fn write_message(msg: &str, stream: &mut UnixStream) -> std::io::Result<()> {
+fn write_message(msg: &str, stream: &mut UnixStream, deadline: Deadline) -> std::io::Result<()> {
     let mut buf = Vec::new();
     buf.extend(msg.bytes());
     buf.push(b'\r');
     buf.push(b'\n');
+    if let Some(timeout) = timeout_for(deadline)? {
+        stream.set_write_timeout(Some(timeout))?;
+    }
     stream.write_all(&buf)?;
     Ok(())
 }
 
-fn has_line_ending(buf: &[u8]) -> bool {
-    for idx in 1..buf.len() {
-        if buf[idx - 1] == b'\r' && buf[idx] == b'\n' {
-            return true;
-        }
+/// The socket timeout for the next step of the handshake, None if the timeouts of the socket should stay as they are
+fn timeout_for(deadline: Deadline) -> std::io::Result<Option<std::time::Duration>> {
+    match deadline.remaining_duration() {
+        Ok(Some(d)) if !d.is_zero() => Ok(Some(d)),
+        Ok(_) => Ok(None),
+        Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
     }
-    false
 }
 
-fn find_line_ending(buf: &[u8]) -> Option<usize> {
-    for idx in 1..buf.len() {
-        if buf[idx - 1] == b'\r' && buf[idx] == b'\n' {
-            return Some(idx - 1);
-        }
-    }
-    None
-}
+/// The reference implementation rejects longer lines too, a server that sends more is not speaking the auth protocol
+const MAX_LINE_LEN: usize = 16 * 1024;
 
-fn read_message(stream: &mut UnixStream, buf: &mut Vec<u8>) -> std::io::Result<String> {
+/// Read one line without its line ending. Nothing after the line ending is read, a client may send its first message
+/// right after `BEGIN` and the fds that come with it would be lost to a plain read.
+fn read_message(stream: &mut UnixStream, deadline: Deadline) -> std::io::Result<String> {
+    let mut line = Vec::new();
     let mut tmpbuf = [0u8; 512];
-    while !has_line_ending(buf) {
-        let bytes = stream.read(&mut tmpbuf[..])?;
-        buf.extend_from_slice(&tmpbuf[..bytes])
+    let mut flags = MsgFlags::MSG_PEEK;
+    if deadline == Deadline::Nonblock {
+        flags |= MsgFlags::MSG_DONTWAIT;
     }
-    let idx = find_line_ending(buf).unwrap();
-    let line = buf.drain(0..idx).collect::<Vec<_>>();
-    Ok(String::from_utf8(line).unwrap())
+    while !line.ends_with(b"\r\n") {
+        if line.len() > MAX_LINE_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "auth line too long",
+            ));
+        }
+        if let Some(timeout) = timeout_for(deadline)? {
+            stream.set_read_timeout(Some(timeout))?;
+        }
+        let peeked = match socket::recv(stream.as_raw_fd(), &mut tmpbuf, flags) {
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(nix::errno::Errno::EAGAIN) => return Err(std::io::ErrorKind::TimedOut.into()),
+            res => res?,
+        };
+        if peeked == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        // the \r may have been read with the bytes before
+        let len = tmpbuf[..peeked]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(peeked, |idx| idx + 1);
+        stream.read_exact(&mut tmpbuf[..len])?;
+        line.extend_from_slice(&tmpbuf[..len]);
+    }
+    line.truncate(line.len() - 2);
+    String::from_utf8(line).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
 }
 
 fn get_uid_as_hex() -> String {
@@ -87,19 +113,26 @@ pub fn do_auth(stream: &mut UnixStream) -> std::io::Result<AuthResult> {
     #[cfg(not(any(target_os = "freebsd", target_os = "dragonfly")))]
     let cmsgs = [];
 
-    // send a null byte as the first thing
-    sendmsg::<()>(
+    // send a null byte as the first thing, retry if a signal interrupted the call
+    while let Err(e) = sendmsg::<()>(
         stream.as_raw_fd(),
         &[IoSlice::new(&[0])],
         &cmsgs,
         socket::MsgFlags::empty(),
         None,
-    )?;
+    ) {
+        if e != nix::errno::Errno::EINTR {
+            return Err(e.into());
+        }
+    }
 
-    write_message(&format!("AUTH EXTERNAL {}", get_uid_as_hex()), stream)?;
+    write_message(
+        &format!("AUTH EXTERNAL {}", get_uid_as_hex()),
+        stream,
+        Deadline::Never,
+    )?;
 
-    let mut read_buf = Vec::new();
-    let msg = read_message(stream, &mut read_buf)?;
+    let msg = read_message(stream, Deadline::Never)?;
     if msg.starts_with("OK") {
         Ok(AuthResult::Ok)
     } else {
@@ -108,10 +141,9 @@ pub fn do_auth(stream: &mut UnixStream) -> std::io::Result<AuthResult> {
 }
 
 pub fn negotiate_unix_fds(stream: &mut UnixStream) -> std::io::Result<AuthResult> {
-    write_message("NEGOTIATE_UNIX_FD", stream)?;
+    write_message("NEGOTIATE_UNIX_FD", stream, Deadline::Never)?;
 
-    let mut read_buf = Vec::new();
-    let msg = read_message(stream, &mut read_buf)?;
+    let msg = read_message(stream, Deadline::Never)?;
     if msg.starts_with("AGREE_UNIX_FD") {
         Ok(AuthResult::Ok)
     } else {
@@ -120,6 +152,338 @@ pub fn negotiate_unix_fds(stream: &mut UnixStream) -> std::io::Result<AuthResult
 }
 
 pub fn send_begin(stream: &mut UnixStream) -> std::io::Result<()> {
-    write_message("BEGIN", stream)?;
+    write_message("BEGIN", stream, Deadline::Never)?;
     Ok(())
 }
+
+/// What the server side of the handshake agreed on with the client
+pub struct ServerAuth {
+    /// The uid the client authenticated as
+    pub uid: u32,
+    /// Whether the client asked for unix fds and was allowed to send them
+    pub unix_fds: bool,
+}
+
+/// Which users `AuthServer` accepts
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AllowedUsers {
+    /// Only the user this process runs as, what the reference implementation does for peer to peer connections
+    #[default]
+    SameUser,
+    /// Everyone whose uid is known from the socket credentials, e.g. for a system bus
+    Any,
+    /// Only these uids, checked against the socket credentials like for `Any`
+    Uids(Vec<u32>),
+}
+
+impl AllowedUsers {
+    fn allows(&self, uid: u32) -> bool {
+        match self {
+            AllowedUsers::SameUser => uid == getuid().as_raw(),
+            AllowedUsers::Any => true,
+            AllowedUsers::Uids(uids) => uids.contains(&uid),
+        }
+    }
+}
+
+/// A new server guid: 128 bits as 32 hex digits, the last 32 bits are the time like in the guids of the reference
+/// implementation
+pub fn generate_guid() -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    hasher.write_u128(now.as_nanos());
+    let first = hasher.finish();
+    hasher.write_u64(first);
+    let second = hasher.finish() as u32;
+    format!("{:016x}{:08x}{:08x}", first, second, now.as_secs() as u32)
+}
+
+/// The server side of the handshake, for accepting peer to peer connections or implementing a bus. Only the `EXTERNAL`
+/// mechanism is supported. The uid a client claims is checked against the credentials of the socket (`SO_PEERCRED` or
+/// `getpeereid`), then against the `AllowedUsers`. Clients are rejected if the credentials can not be read.
+///
+/// The handshake has to be finished within the timeout set with `set_timeout`, 30 seconds by default, so a client that
+/// connects and sends nothing does not block the server forever.
+///
+/// ```rust
+/// use rustbus::auth::{AllowedUsers, AuthServer};
+///
+/// let (mut client, mut server) = std::os::unix::net::UnixStream::pair().unwrap();
+/// let client = std::thread::spawn(move || {
+///     rustbus::auth::do_auth(&mut client).unwrap();
+///     rustbus::auth::send_begin(&mut client).unwrap();
+/// });
+/// let mut auth = AuthServer::new();
+/// auth.set_allowed_users(AllowedUsers::Any);
+/// let accepted = auth.authenticate(&mut server).unwrap().unwrap();
+/// assert_eq!(accepted.uid, nix::unistd::getuid().as_raw());
+/// assert!(!accepted.unix_fds);
+/// client.join().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct AuthServer {
+    guid: String,
+    allow_unix_fds: bool,
+    allowed_users: AllowedUsers,
+    timeout: Timeout,
+}
+
+impl Default for AuthServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuthServer {
+    /// Accepts the same user, allows unix fds and uses a new guid
+    pub fn new() -> Self {
+        Self::with_guid(generate_guid())
+    }
+
+    /// `guid` is sent to the clients in the `OK` line and has to be 32 hex digits. A bus keeps the same one for all
+    /// connections and puts it into its address.
+    pub fn with_guid(guid: String) -> Self {
+        AuthServer {
+            guid,
+            allow_unix_fds: true,
+            allowed_users: AllowedUsers::SameUser,
+            timeout: Timeout::Duration(DEFAULT_AUTH_TIMEOUT),
+        }
+    }
+
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+
+    pub fn set_allow_unix_fds(&mut self, allow: bool) {
+        self.allow_unix_fds = allow;
+    }
+
+    pub fn set_allowed_users(&mut self, allowed: AllowedUsers) {
+        self.allowed_users = allowed;
+    }
+
+    /// How long a client may take for the whole handshake. `Timeout::Nonblock` only accepts clients whose handshake
+    /// is already buffered.
+    pub fn set_timeout(&mut self, timeout: Timeout) {
+        self.timeout = timeout;
+    }
+
+    /// Run the handshake with a client that just connected. Returns `Ok(None)` if the client did not authenticate
+    /// successfully and an error of the kind `TimedOut` if it took too long.
+    ///
+    /// Nothing the client sends after `BEGIN` is read, so its first message can be read from the stream as usual.
+    pub fn authenticate(&self, stream: &mut UnixStream) -> std::io::Result<Option<ServerAuth>> {
+        let timeouts = (stream.read_timeout()?, stream.write_timeout()?);
+        let res = server_handshake(stream, self, peer_uid(stream), Deadline::new(self.timeout))
+            .map_err(|e| match e.kind() {
+                // what a socket timeout looks like
+                std::io::ErrorKind::WouldBlock => std::io::ErrorKind::TimedOut.into(),
+                _ => e,
+            });
+        stream.set_read_timeout(timeouts.0)?;
+        stream.set_write_timeout(timeouts.1)?;
+        res
+    }
+}
+
+/// The reference implementation gives clients the same time to authenticate
+const DEFAULT_AUTH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How often a client may try to authenticate before the server gives up on it, the reference implementation uses the same
+const MAX_AUTH_ATTEMPTS: usize = 6;
+
+/// Decode the argument of `AUTH EXTERNAL`, the uid as decimal digits which are hex encoded
+fn parse_hex_uid(hex: &str) -> Option<u32> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    let digits = (0..hex.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&hex[idx..idx + 2], 16)
+                .ok()
+                .map(char::from)
+        })
+        .collect::<Option<String>>()?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// The uid of the process on the other end of the socket, if the platform can tell
+fn peer_uid(stream: &UnixStream) -> Option<u32> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let uid = socket::getsockopt(stream, socket::sockopt::PeerCredentials)
+        .ok()
+        .map(|creds| creds.uid());
+    #[cfg(any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    let uid = nix::unistd::getpeereid(stream)
+        .ok()
+        .map(|(uid, _)| uid.as_raw());
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    let uid = {
+        let _ = stream;
+        None
+    };
+    uid
+}
+
+/// Shorthand for an `AuthServer` that accepts clients running as the same user as this process
+pub fn do_server_auth(
+    stream: &mut UnixStream,
+    guid: &str,
+    allow_unix_fds: bool,
+) -> std::io::Result<Option<ServerAuth>> {
+    let mut server = AuthServer::with_guid(guid.to_owned());
+    server.set_allow_unix_fds(allow_unix_fds);
+    server.authenticate(stream)
+}
+
+/// `peer_uid` is the uid from the socket credentials, None if they could not be read
+fn server_handshake(
+    stream: &mut UnixStream,
+    config: &AuthServer,
+    peer_uid: Option<u32>,
+    deadline: Deadline,
+) -> std::io::Result<Option<ServerAuth>> {
+    if let Some(timeout) = timeout_for(deadline)? {
+        stream.set_read_timeout(Some(timeout))?;
+    }
+    // the client starts with a null byte, it could carry credentials on some platforms
+    let mut nul = [0u8];
+    stream.read_exact(&mut nul)?;
+    if nul[0] != 0 {
+        return Ok(None);
+    }
+
+    // set after an `AUTH EXTERNAL` without an identity, the client sends it in a `DATA` line then
+    let mut waiting_for_data = false;
+    let mut authenticated = None;
+    let mut unix_fds = false;
+    let mut attempts = 0;
+
+    loop {
+        let line = read_message(stream, deadline)?;
+        let mut words = line.split(' ');
+        let command = words.next().unwrap_or("");
+        let claimed = match (command, authenticated) {
+            ("AUTH", None) => match (words.next(), words.next()) {
+                (Some("EXTERNAL"), Some(hex)) => Some(hex),
+                (Some("EXTERNAL"), None) => {
+                    waiting_for_data = true;
+                    write_message("DATA", stream, deadline)?;
+                    continue;
+                }
+                _ => None,
+            },
+            ("DATA", None) if waiting_for_data => Some(words.next().unwrap_or("")),
+            ("CANCEL", None) | ("ERROR", None) => None,
+            ("NEGOTIATE_UNIX_FD", Some(_)) => {
+                unix_fds = config.allow_unix_fds;
+                if config.allow_unix_fds {
+                    write_message("AGREE_UNIX_FD", stream, deadline)?;
+                } else {
+                    write_message("ERROR \"unix fds are not supported\"", stream, deadline)?;
+                }
+                continue;
+            }
+            ("BEGIN", Some(uid)) => {
+                return Ok(Some(ServerAuth { uid, unix_fds }));
+            }
+            _ => {
+                write_message("ERROR \"unexpected command\"", stream, deadline)?;
+                continue;
+            }
+        };
+        waiting_for_data = false;
+
+        // an empty identity asks to be authenticated as whatever the socket credentials say. Without credentials the
+        // claimed uid can not be verified, so nobody is accepted.
+        let uid = match claimed {
+            Some("") => peer_uid,
+            Some(hex) => parse_hex_uid(hex).filter(|uid| peer_uid == Some(*uid)),
+            None => None,
+        };
+        match uid {
+            Some(uid) if config.allowed_users.allows(uid) => {
+                authenticated = Some(uid);
+                write_message(&format!("OK {}", config.guid), stream, deadline)?;
+            }
+            _ => {
+                attempts += 1;
+                if attempts >= MAX_AUTH_ATTEMPTS {
+                    return Ok(None);
+                }
+                write_message("REJECTED EXTERNAL", stream, deadline)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the handshake as if the socket credentials could not be read
+    fn handshake_without_credentials(
+        client_lines: &[u8],
+        allowed: AllowedUsers,
+    ) -> (Option<ServerAuth>, String) {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(b"\0").unwrap();
+        client.write_all(client_lines).unwrap();
+        let mut auth = AuthServer::new();
+        auth.set_allowed_users(allowed);
+        let deadline = Deadline::new(Timeout::Duration(std::time::Duration::from_secs(10)));
+        let accepted = server_handshake(&mut server, &auth, None, deadline).unwrap();
+        drop(server);
+        let mut answers = String::new();
+        client.read_to_string(&mut answers).unwrap();
+        (accepted, answers)
+    }
+
+    #[test]
+    fn test_no_peer_credentials() {
+        let uid = format!("AUTH EXTERNAL {}\r\n", get_uid_as_hex()).repeat(MAX_AUTH_ATTEMPTS);
+        for allowed in [
+            AllowedUsers::SameUser,
+            AllowedUsers::Any,
+            AllowedUsers::Uids(vec![getuid().as_raw()]),
+        ] {
+            // the claimed uid can not be verified
+            let (accepted, answers) =
+                handshake_without_credentials(uid.as_bytes(), allowed.clone());
+            assert!(accepted.is_none());
+            assert!(!answers.contains("OK"));
+
+            // and there is nothing to take the uid from
+            let empty = "AUTH EXTERNAL\r\nDATA\r\n".repeat(MAX_AUTH_ATTEMPTS);
+            let (accepted, answers) = handshake_without_credentials(empty.as_bytes(), allowed);
+            assert!(accepted.is_none());
+            assert!(!answers.contains("OK"));
+        }
+    }
+}
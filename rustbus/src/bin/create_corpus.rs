@@ -1,20 +1,23 @@
 //! This is just a fuzzing helper. It creates some valid dbus messages and dumps them into files.
+//!
+//! The messages are written to the directory given as the first argument, `./fuzz/corpus/valid_dbus` by default. The
+//! corpus in `src/tests/corpus` was created with `cargo run --bin create_corpus -- src/tests/corpus`, the tests check
+//! that every message in there survives an unmarshal and marshal round trip unchanged.
 
 use rustbus::message_builder::MarshalledMessage;
 use rustbus::message_builder::MessageBuilder;
+use rustbus::wire::{ObjectPath, VarDict};
 use rustbus::Marshal;
 
-use std::io::Write;
-use std::num::NonZeroU32;
-
 fn main() {
+    let dir = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "./fuzz/corpus/valid_dbus".to_owned());
+    let path = |name: &str| format!("{}/{}", dir, name);
+
+    make_and_dump(&path("1.msg"), "ABCD", "asdöflasdölgkjsdfökl");
     make_and_dump(
-        "./fuzz/corpus/valid_dbus/1.msg",
-        "ABCD",
-        "asdöflasdölgkjsdfökl",
-    );
-    make_and_dump(
-        "./fuzz/corpus/valid_dbus/2.msg",
+        &path("2.msg"),
         vec!["ABCD", "EFGHI", "JKLMNOP"],
         vec![
             0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 100, 255, 123, 123, 123, 123, 123, 123,
@@ -27,19 +30,39 @@ fn main() {
     map.insert("X", (2u8, 300u32));
     map.insert("Y", (3u8, 400u32));
     make_and_dump(
-        "./fuzz/corpus/valid_dbus/3.msg",
+        &path("3.msg"),
         vec![&map, &map, &map],
         vec![
             0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 100, 255, 123, 123, 123, 123, 123, 123,
         ],
     );
     make_and_dump(
-        "./fuzz/corpus/valid_dbus/4.msg",
+        &path("4.msg"),
         &map,
         vec![
             0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 100, 255, 123, 123, 123, 123, 123, 123,
         ],
     );
+
+    // arrays of structs whose members have different alignments, the padding between the elements depends on where
+    // the array starts
+    make_and_dump(
+        &path("5.msg"),
+        1u8,
+        vec![(1u8, 2u64), (3u8, 4u64), (5u8, 6u64)],
+    );
+    make_and_dump(
+        &path("6.msg"),
+        vec![(1u16, vec![(2u8, 3i64)], 4u8), (5u16, vec![], 6u8)],
+        (7u8, 8.5f64, "end"),
+    );
+    let mut variants = VarDict::new();
+    variants.insert("byte", 1u8).unwrap();
+    variants
+        .insert("path", ObjectPath::new("/a/b").unwrap())
+        .unwrap();
+    variants.insert("pairs", vec![(1u8, 2u32)]).unwrap();
+    make_and_dump(&path("7.msg"), (1u8, variants), vec![3u8, 1, 2]);
 }
 
 fn make_and_dump<P1: Marshal, P2: Marshal>(path: &str, p1: P1, p2: P2) {
@@ -60,10 +83,6 @@ fn make_message() -> MarshalledMessage {
 }
 
 fn dump_message(path: &str, msg: &MarshalledMessage) {
-    let mut hdrbuf = vec![];
-    rustbus::wire::marshal::marshal(msg, NonZeroU32::MIN, &mut hdrbuf).unwrap();
-
-    let mut file = std::fs::File::create(path).unwrap();
-    file.write_all(&hdrbuf).unwrap();
-    file.write_all(msg.get_buf()).unwrap();
+    let file = std::fs::File::create(path).unwrap();
+    msg.write_to(file).unwrap();
 }
@@ -44,5 +44,7 @@ fn run_artifact(path: &str) {
 
     println!("Message: {:?}", msg);
 
+    // the params API is left out with the stable-api feature
+    #[cfg(rustbus_params)]
     msg.unmarshall_all().ok();
 }
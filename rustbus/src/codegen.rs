@@ -0,0 +1,372 @@
+//! Generate typed proxies from introspection XML (needs the `introspection` feature)
+//!
+//! For each interface in the XML a struct `<Name>Proxy` is generated, named after the last segment of the interface name.
+//! It borrows an RpcConn and has one method per D-Bus method, in snake_case, that takes the in-arguments as rust types,
+//! makes the call and returns the out-arguments. No out-arguments return `()`, one returns its value and more return a tuple.
+//! Errors are reported as `connection::proxy::CallError`.
+//!
+//! The types are mapped like this, arguments that are not `Copy` are taken by reference:
+//!
+//! | D-Bus         | Rust                                   |
+//! |---------------|----------------------------------------|
+//! | y b n q i u x t d | u8 bool i16 u16 i32 u32 i64 u64 f64 |
+//! | s             | String (`&str` as argument)            |
+//! | o, g          | `wire::ObjectPath`, `wire::SignatureWrapper` |
+//! | h             | `wire::UnixFd`                         |
+//! | v             | `params::Variant`                      |
+//! | aT            | `Vec<T>` (`&[T]` as argument)          |
+//! | a{KV}         | `HashMap<K, V>`                        |
+//! | (T1..T4)      | tuples                                 |
+//!
+//! Methods with types that can not be mapped, like structs with more than four fields, are left out with a comment
+//! saying why. Rust keywords in method and argument names get a `_` appended.
+//!
+//! The intended use is a build script that writes the generated code to `OUT_DIR`:
+//!
+//! ```rust,no_run
+//! // in the main function of build.rs
+//! let xml = std::fs::read_to_string("network_manager.xml").unwrap();
+//! let code = rustbus::codegen::generate_proxies(&xml).unwrap();
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! std::fs::write(std::path::Path::new(&out_dir).join("network_manager.rs"), code).unwrap();
+//! println!("cargo:rerun-if-changed=network_manager.xml");
+//! ```
+//!
+//! which is then included in the crate and used like this:
+//!
+//! ```rust,ignore
+//! include!(concat!(env!("OUT_DIR"), "/network_manager.rs"));
+//!
+//! let mut rpc_con = RpcConn::system_conn(Timeout::Infinite)?;
+//! let mut nm = NetworkManagerProxy::new(
+//!     &mut rpc_con,
+//!     "org.freedesktop.NetworkManager",
+//!     "/org/freedesktop/NetworkManager",
+//! );
+//! let devices = nm.get_devices()?;
+//! ```
+
+use std::fmt::Write;
+
+use crate::connection::dynamic_proxy::{parse_introspection, Arg, Interface, Method, ProxyError};
+use crate::signature::{Base, Container, Type};
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "crate", "do", "dyn", "else",
+    "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "self",
+    "Self", "static", "struct", "super", "trait", "true", "try", "type", "typeof", "unsafe",
+    "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Names the generated methods use themselves, arguments with these names are renamed
+const RESERVED_ARGS: &[&str] = &["call", "reply", "parser"];
+
+/// Names of the methods every proxy has, D-Bus methods with these names are renamed
+const RESERVED_METHODS: &[&str] = &["new", "set_timeout"];
+
+/// Generates proxies with settings other than the defaults of `generate_proxies`
+#[derive(Debug, Clone)]
+pub struct ProxyGenerator {
+    crate_path: String,
+}
+
+impl Default for ProxyGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProxyGenerator {
+    pub fn new() -> Self {
+        ProxyGenerator {
+            crate_path: "rustbus".to_owned(),
+        }
+    }
+
+    /// The path the generated code uses to refer to rustbus, `rustbus` by default. Set this if rustbus is only
+    /// available through a reexport.
+    pub fn crate_path<S: Into<String>>(mut self, path: S) -> Self {
+        self.crate_path = path.into();
+        self
+    }
+
+    /// Generate the code for all interfaces of the root node in `xml`
+    pub fn generate(&self, xml: &str) -> Result<String, ProxyError> {
+        let interfaces = parse_introspection(xml)?;
+        let mut code = String::from("// generated by rustbus::codegen, do not edit\n");
+        for iface in &interfaces {
+            code.push('\n');
+            self.write_interface(&mut code, iface);
+        }
+        Ok(code)
+    }
+
+    fn write_interface(&self, code: &mut String, iface: &Interface) {
+        let c = &self.crate_path;
+        let name = format!("{}Proxy", struct_name(&iface.name));
+        // writing to a String can not fail
+        let _ = write!(
+            code,
+            "/// Calls methods of the `{iface}` interface
+pub struct {name}<'a> {{
+    conn: &'a mut {c}::RpcConn,
+    destination: String,
+    object: String,
+    timeout: {c}::connection::Timeout,
+}}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+impl<'a> {name}<'a> {{
+    pub const INTERFACE: &'static str = \"{iface}\";
+
+    /// Create a proxy for `object` at `destination`. Calls wait for their reply without a timeout until `set_timeout` is called.
+    pub fn new<D: Into<String>, O: Into<String>>(conn: &'a mut {c}::RpcConn, destination: D, object: O) -> Self {{
+        {name} {{
+            conn,
+            destination: destination.into(),
+            object: object.into(),
+            timeout: {c}::connection::Timeout::Infinite,
+        }}
+    }}
+
+    pub fn set_timeout(&mut self, timeout: {c}::connection::Timeout) {{
+        self.timeout = timeout;
+    }}
+",
+            iface = iface.name,
+            name = name,
+            c = c,
+        );
+        for method in &iface.methods {
+            code.push('\n');
+            if let Err(reason) = self.write_method(code, &iface.name, method) {
+                let _ = writeln!(code, "    // {} is not generated: {}", method.name, reason);
+            }
+        }
+        code.push_str("}\n");
+    }
+
+    fn write_method(&self, code: &mut String, iface: &str, method: &Method) -> Result<(), String> {
+        let c = &self.crate_path;
+        let mut params = String::new();
+        let mut pushes = String::new();
+        for (idx, arg) in method.in_args.iter().enumerate() {
+            let name = arg_name(arg, idx);
+            let typ = self.input_type(&single_type(arg)?)?;
+            let _ = write!(params, ", {}: {}", name, typ);
+            let _ = writeln!(pushes, "        call.body.push_param({})?;", name);
+        }
+        let outputs = method
+            .out_args
+            .iter()
+            .map(|arg| self.owned_type(&single_type(arg)?, "'static"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ret = match outputs.len() {
+            1 => outputs[0].clone(),
+            _ => format!("({})", outputs.join(", ")),
+        };
+
+        let mut fn_name = snake_case(&method.name);
+        if KEYWORDS.contains(&fn_name.as_str()) || RESERVED_METHODS.contains(&fn_name.as_str()) {
+            fn_name.push('_');
+        }
+        let _ = write!(
+            code,
+            "    /// Calls `{iface}.{method}`
+    pub fn {fn_name}(&mut self{params}) -> Result<{ret}, {c}::connection::proxy::CallError> {{
+        let mut call = {c}::MessageBuilder::new()
+            .call(\"{method}\")
+            .with_interface(Self::INTERFACE)
+            .on(self.object.clone())
+            .at(self.destination.clone())
+            .build();
+{pushes}",
+            iface = iface,
+            method = method.name,
+            fn_name = fn_name,
+            params = params,
+            ret = ret,
+            c = c,
+            pushes = pushes,
+        );
+        let call = format!(
+            "{}::connection::proxy::call(self.conn, &mut call, self.timeout)?",
+            c
+        );
+        match outputs.len() {
+            0 => {
+                let _ = writeln!(code, "        {};\n        Ok(())", call);
+            }
+            1 => {
+                let _ = writeln!(code, "        let reply = {};", call);
+                code.push_str("        Ok(reply.body.parser().get()?)\n");
+            }
+            n => {
+                let _ = writeln!(code, "        let reply = {};", call);
+                code.push_str("        let mut parser = reply.body.parser();\n");
+                let gets = vec!["parser.get()?"; n].join(", ");
+                let _ = writeln!(code, "        Ok(({}))", gets);
+            }
+        }
+        code.push_str("    }\n");
+        Ok(())
+    }
+
+    /// The type an argument is passed as
+    fn input_type(&self, typ: &Type) -> Result<String, String> {
+        let c = &self.crate_path;
+        Ok(match typ {
+            Type::Base(Base::String) => "&str".to_owned(),
+            Type::Base(Base::ObjectPath) => format!("{}::wire::ObjectPath<&str>", c),
+            Type::Base(Base::Signature) => format!("{}::wire::SignatureWrapper<&str>", c),
+            Type::Base(Base::UnixFd) => format!("&{}::wire::UnixFd", c),
+            Type::Base(_) => self.owned_type(typ, "'_")?,
+            Type::Container(Container::Array(elem)) => {
+                format!("&[{}]", self.owned_type(elem, "'_")?)
+            }
+            other => format!("&{}", self.owned_type(other, "'_")?),
+        })
+    }
+
+    /// The type a value is returned as, `lifetime` is used for variants
+    fn owned_type(&self, typ: &Type, lifetime: &str) -> Result<String, String> {
+        let c = &self.crate_path;
+        Ok(match typ {
+            Type::Base(Base::String) => "String".to_owned(),
+            Type::Base(Base::ObjectPath) => format!("{}::wire::ObjectPath<String>", c),
+            Type::Base(Base::Signature) => format!("{}::wire::SignatureWrapper<String>", c),
+            Type::Base(Base::UnixFd) => format!("{}::wire::UnixFd", c),
+            Type::Base(Base::Byte) => "u8".to_owned(),
+            Type::Base(Base::Boolean) => "bool".to_owned(),
+            Type::Base(Base::Int16) => "i16".to_owned(),
+            Type::Base(Base::Uint16) => "u16".to_owned(),
+            Type::Base(Base::Int32) => "i32".to_owned(),
+            Type::Base(Base::Uint32) => "u32".to_owned(),
+            Type::Base(Base::Int64) => "i64".to_owned(),
+            Type::Base(Base::Uint64) => "u64".to_owned(),
+            Type::Base(Base::Double) => "f64".to_owned(),
+            Type::Container(Container::Variant) => {
+                format!("{}::params::Variant<{}, {}>", c, lifetime, lifetime)
+            }
+            Type::Container(Container::Array(elem)) => {
+                format!("Vec<{}>", self.owned_type(elem, lifetime)?)
+            }
+            Type::Container(Container::Dict(Base::Double, _)) => {
+                return Err("f64 can not be used as key of a HashMap".to_owned())
+            }
+            Type::Container(Container::Dict(key, value)) => format!(
+                "std::collections::HashMap<{}, {}>",
+                self.owned_type(&Type::Base(*key), lifetime)?,
+                self.owned_type(value, lifetime)?
+            ),
+            Type::Container(Container::Struct(fields)) => {
+                let fields = fields.as_ref();
+                if fields.len() > 4 {
+                    return Err("structs with more than four fields are not supported".to_owned());
+                }
+                let fields = fields
+                    .iter()
+                    .map(|field| self.owned_type(field, lifetime))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if fields.len() == 1 {
+                    format!("({},)", fields[0])
+                } else {
+                    format!("({})", fields.join(", "))
+                }
+            }
+        })
+    }
+}
+
+/// Generate proxies for all interfaces of the root node in `xml`, see the module docs
+pub fn generate_proxies(xml: &str) -> Result<String, ProxyError> {
+    ProxyGenerator::new().generate(xml)
+}
+
+fn single_type(arg: &Arg) -> Result<Type, String> {
+    let mut types = Type::parse_description(&arg.sig).map_err(|e| e.to_string())?;
+    if types.len() != 1 {
+        return Err(format!(
+            "the argument type '{}' is not a single type",
+            arg.sig
+        ));
+    }
+    Ok(types.remove(0))
+}
+
+fn arg_name(arg: &Arg, idx: usize) -> String {
+    let mut name = match &arg.name {
+        Some(name) if !name.is_empty() => snake_case(name),
+        _ => format!("arg{}", idx),
+    };
+    if KEYWORDS.contains(&name.as_str()) || RESERVED_ARGS.contains(&name.as_str()) {
+        name.push('_');
+    }
+    name
+}
+
+/// The last segment of the interface name in UpperCamelCase
+fn struct_name(iface: &str) -> String {
+    let last = iface.rsplit('.').next().unwrap_or(iface);
+    let mut name = String::new();
+    let mut upper = true;
+    for c in last.chars() {
+        if c == '_' || c == '-' {
+            upper = true;
+        } else if upper {
+            name.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
+
+/// `GetIPAddress` becomes `get_ip_address`
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (idx, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && idx > 0 {
+            let prev = chars[idx - 1];
+            let next_lower = chars.get(idx + 1).is_some_and(|next| next.is_lowercase());
+            if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_lower) {
+                snake.push('_');
+            }
+        }
+        if *c == '-' {
+            snake.push('_');
+        } else {
+            snake.extend(c.to_lowercase());
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        assert_eq!(snake_case("GetIPAddress"), "get_ip_address");
+        assert_eq!(snake_case("Ping"), "ping");
+        assert_eq!(snake_case("GetNameOwner"), "get_name_owner");
+        assert_eq!(snake_case("Ip4Config"), "ip4_config");
+        assert_eq!(snake_case("already_snake"), "already_snake");
+        assert_eq!(
+            struct_name("org.freedesktop.NetworkManager"),
+            "NetworkManager"
+        );
+        assert_eq!(struct_name("io.killing.spark.settings_v2"), "SettingsV2");
+        let arg = |name: Option<&str>| Arg {
+            name: name.map(str::to_owned),
+            sig: "s".to_owned(),
+        };
+        assert_eq!(arg_name(&arg(Some("type")), 0), "type_");
+        assert_eq!(arg_name(&arg(Some("call")), 0), "call_");
+        assert_eq!(arg_name(&arg(None), 3), "arg3");
+    }
+}
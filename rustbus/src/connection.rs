@@ -2,25 +2,50 @@
 //!
 //! * ll_conn is the basic send and recive primitives used to build the other connection types
 //! * dispatch_conn is meant for services that need to dispatch calls to different handlers
+//! * pooled_dispatch is a dispatch_conn that runs the handlers on a pool of worker threads
 //! * rpc_conn is meant for clients that make calls to services on the bus
+//! * middleware allows observing and modifying all messages going through a rpc_conn or dispatch_conn
+//! * dynamic_proxy calls methods of objects that are only known at runtime (needs the `introspection` feature)
+//! * async_conn has async versions of the DuplexConn and RpcConn that run on tokio (needs the `tokio` feature)
+//! * proxy contains what the proxies generated by `rustbus::codegen` need at runtime
+//! * streamed_call collects the results of calls that are answered with a series of signals
+//! * properties answers `org.freedesktop.DBus.Properties` and `org.freedesktop.DBus.ObjectManager` calls for a dispatch_conn
+//! * object_manager parses what object managers report about their objects
+//! * property_batch sets several properties of a remote object at once
+//! * portal_request provides portal-style request objects that report their outcome with a `Response` signal
+//! * signal_batch groups signals that a sender emits in bursts
 
+#[cfg(feature = "tokio")]
+pub mod async_conn;
 pub mod dispatch_conn;
+#[cfg(all(feature = "introspection", rustbus_params))]
+pub mod dynamic_proxy;
 pub mod ll_conn;
+pub mod middleware;
+pub mod object_manager;
+pub mod pooled_dispatch;
+pub mod portal_request;
+pub mod properties;
+pub mod property_batch;
+pub mod proxy;
 pub mod rpc_conn;
+pub mod signal_batch;
+pub mod streamed_call;
 
 use std::path::PathBuf;
 use std::{io, time};
 
 use thiserror::Error;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Timeout {
     Infinite,
     Nonblock,
     Duration(time::Duration),
 }
 
-use nix::sys::socket::UnixAddr;
+/// Re-exported so you do not need to depend on the exact same nix version as rustbus to name it
+pub use nix::sys::socket::UnixAddr;
 
 /// Errors that can occur when using the Conn/RpcConn
 #[derive(Debug, Error)]
@@ -49,43 +74,525 @@ pub enum Error {
     TimedOut,
     #[error("Connection has been closed by the other side")]
     ConnectionClosed,
+    #[error("A middleware refused to send the message")]
+    RejectedByMiddleware,
+    #[error("Messages queued with SendConn::queue_message have not been written completely yet")]
+    QueuedMessagesPending,
+    /// Returned for every send after `SendConn::send_message_with_byte_stream` failed in the middle of a message
+    #[error("A message was only written partially, the connection can not send anymore")]
+    SendConnBroken,
+    /// Only returned in debug builds, see `RpcConn::wait_response`
+    #[error("Waited for a reply to a signal, but signals never get replies. Did you mean to build a call?")]
+    NoReplyToSignal,
+    #[error("The response belongs to a PendingReply, take it from there")]
+    ResponseClaimed,
+    #[error("The PendingReply belongs to a call that was started on another RpcConn")]
+    PendingReplyOfOtherConnection,
+    #[error("The reply to {member:?} has the signature '{found}' but '{expected}' was expected")]
+    ReplySignatureMismatch {
+        member: Option<String>,
+        expected: String,
+        found: String,
+    },
+    #[error("The bus rejected the match rule {rule}: {error}")]
+    MatchRuleRejected {
+        rule: String,
+        error: crate::message_builder::ErrorReply,
+    },
+    #[error("The bus rejected the request for {bus_name}: {error}")]
+    NameRequestRejected {
+        bus_name: String,
+        error: crate::message_builder::ErrorReply,
+    },
+    #[error("The call returned the error {0}")]
+    ErrorReply(crate::message_builder::ErrorReply),
+    #[error("The bus answered the request for {bus_name} with the unknown code {code}")]
+    UnknownRequestNameReply { bus_name: String, code: u32 },
+    /// Only returned if `RecvConn::set_skip_invalid_messages` is enabled
+    #[error("{0}")]
+    ProtocolViolation(Box<ll_conn::ProtocolViolation>),
+    /// Sending a signal failed on some of the connections it is emitted on, it was still sent on the others
+    #[error("The signal could not be sent on {} of the connections", .0.len())]
+    SignalNotSent(Vec<Error>),
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
-fn parse_dbus_addr_str(addr: &str) -> Result<UnixAddr> {
-    // split the address string into <system>:rest
-    let (addr_system, addr_pairs) = addr.split_once(':').ok_or(Error::NoAddressFound)?;
-    if addr_system != "unix" {
-        return Err(Error::AddressTypeNotSupported(addr.to_owned()));
-    }
-
-    // split the rest of the address string into each <key>=<value> pair
-    for pair in addr_pairs.split(',') {
-        let (key, value) = pair
-            .split_once('=')
-            .ok_or_else(|| Error::AddressTypeNotSupported(addr.to_owned()))?;
-
-        match key {
-            "path" => {
-                let p = PathBuf::from(&value);
-                if p.exists() {
-                    return Ok(UnixAddr::new(&p).map_err(io::Error::from)?);
+/// A parsed D-Bus address, see [the spec](https://dbus.freedesktop.org/doc/dbus-specification.html#addresses)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusAddress {
+    /// `unix:path=...` or `unix:abstract=...`
+    Unix(UnixAddr),
+    /// `unixexec:path=...,argv0=...,argv1=...`: start the program and talk to it over its stdin and stdout.
+    /// `argv[0]` defaults to the path.
+    UnixExec { path: PathBuf, argv: Vec<String> },
+    /// `systemd:`: the socket systemd passed to this process with socket activation, see `sd_listen_fds(3)`. If it is a
+    /// listening socket it is kept and every connect accepts the next incoming connection, so reconnecting works. A
+    /// connected socket can only be taken once per process.
+    Systemd,
+    /// `launchd:env=...`: the unix socket whose path launchd keeps in the environment variable `env`
+    Launchd { env: String },
+}
+
+/// How long `BusAddress::connect_any` gives an attempt before it starts the next one in parallel
+pub const CONNECT_ATTEMPT_DELAY: time::Duration = time::Duration::from_millis(250);
+/// How many attempts `BusAddress::connect_any` runs at the same time at most
+pub const MAX_PARALLEL_CONNECTS: usize = 4;
+/// How often `BusAddress::connect_any` retries connecting to a listener that had no room for more connections
+const CONNECT_RETRY_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+/// The socket systemd passed to this process, see `BusAddress::Systemd`
+static SYSTEMD_SOCKET: std::sync::Mutex<PassedSocket> =
+    std::sync::Mutex::new(PassedSocket::Untouched);
+
+/// Values in addresses can contain any byte as `%xx`
+fn unescape_addr_value(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl BusAddress {
+    /// Parse an address like the ones in `$DBUS_SESSION_BUS_ADDRESS`. Keys that do not matter for connecting, like
+    /// `guid`, are ignored. For `unix:path=...` the path has to exist.
+    pub fn parse(addr: &str) -> Result<Self> {
+        let not_supported = || Error::AddressTypeNotSupported(addr.to_owned());
+        // split the address string into <transport>:rest
+        let (transport, addr_pairs) = addr.split_once(':').ok_or(Error::NoAddressFound)?;
+
+        // split the rest of the address string into each <key>=<value> pair
+        let mut pairs = Vec::new();
+        for pair in addr_pairs.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(not_supported)?;
+            pairs.push((key, unescape_addr_value(value).ok_or_else(not_supported)?));
+        }
+        let value = |wanted: &str| {
+            pairs
+                .iter()
+                .find(|(key, _)| *key == wanted)
+                .map(|(_, value)| value.clone())
+        };
+
+        match transport {
+            "unix" => {
+                for (key, value) in &pairs {
+                    match *key {
+                        "path" => {
+                            let p = PathBuf::from(value);
+                            if p.exists() {
+                                return Ok(BusAddress::Unix(
+                                    UnixAddr::new(&p).map_err(io::Error::from)?,
+                                ));
+                            } else {
+                                return Err(Error::PathDoesNotExist(value.to_string()));
+                            }
+                        }
+                        "abstract" => {
+                            #[cfg(target_os = "linux")]
+                            {
+                                return Ok(BusAddress::Unix(
+                                    UnixAddr::new_abstract(value.as_bytes())
+                                        .map_err(io::Error::from)?,
+                                ));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Err(not_supported())
+            }
+            "unixexec" => {
+                let path = value("path").ok_or_else(not_supported)?;
+                let mut argv = vec![value("argv0").unwrap_or_else(|| path.clone())];
+                for idx in 1.. {
+                    match value(&format!("argv{}", idx)) {
+                        Some(arg) => argv.push(arg),
+                        None => break,
+                    }
+                }
+                Ok(BusAddress::UnixExec {
+                    path: PathBuf::from(path),
+                    argv,
+                })
+            }
+            "systemd" => Ok(BusAddress::Systemd),
+            "launchd" => Ok(BusAddress::Launchd {
+                env: value("env").ok_or_else(not_supported)?,
+            }),
+            _ => Err(not_supported()),
+        }
+    }
+
+    /// Parse a list of addresses separated by `;`, like `$DBUS_SESSION_BUS_ADDRESS` may contain. Entries that can not
+    /// be parsed, e.g. because their transport is not supported, are skipped. If no entry is left the error of the
+    /// first one is returned.
+    pub fn parse_list(addrs: &str) -> Result<Vec<Self>> {
+        let mut addresses = Vec::new();
+        let mut first_err = None;
+        for addr in addrs.split(';').filter(|addr| !addr.is_empty()) {
+            match Self::parse(addr) {
+                Ok(addr) => addresses.push(addr),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        if addresses.is_empty() {
+            Err(first_err.unwrap_or(Error::NoAddressFound))
+        } else {
+            Ok(addresses)
+        }
+    }
+
+    /// Connect to the first of `addresses` that accepts the connection and return its index together with the stream.
+    ///
+    /// The attempts start in the order of the list and all run on the calling thread with nonblocking connects. If an
+    /// attempt neither succeeded nor failed after `CONNECT_ATTEMPT_DELAY` the next one is started alongside it, up to
+    /// `MAX_PARALLEL_CONNECTS` at a time, so one address that stalls does not hold up the others. The first stream that
+    /// connects wins, the sockets of the other attempts are closed right away.
+    ///
+    /// `systemd:` and `unixexec:` entries are never raced because they can not be undone if they lose: they only start
+    /// once all attempts before them have failed and then block until they are done.
+    ///
+    /// Returns the error of the last failed attempt if all of them fail and `Error::TimedOut` if none succeeded in time.
+    pub fn connect_any(
+        addresses: &[BusAddress],
+        timeout: Timeout,
+    ) -> Result<(usize, std::os::unix::net::UnixStream)> {
+        use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+        use nix::sys::socket::{self, getsockopt, sockopt};
+        use std::convert::TryFrom;
+        use std::os::fd::AsFd;
+        use std::os::unix::net::UnixStream;
+
+        /// A connect that has not finished yet
+        struct Attempt {
+            idx: usize,
+            addr: UnixAddr,
+            sock: UnixStream,
+            in_progress: bool,
+        }
+
+        let connected = |idx, sock: UnixStream| {
+            sock.set_nonblocking(false)?;
+            Ok((idx, sock))
+        };
+        let races =
+            |addr: &BusAddress| !matches!(addr, BusAddress::Systemd | BusAddress::UnixExec { .. });
+
+        let deadline = Deadline::new(timeout);
+        let mut attempts: Vec<Attempt> = Vec::new();
+        let mut next = 0;
+        let mut last_start = time::Instant::now();
+        let mut last_err = None;
+        loop {
+            let can_start = next < addresses.len()
+                && attempts.len() < MAX_PARALLEL_CONNECTS
+                && (attempts.is_empty() || races(&addresses[next]));
+            if can_start && (attempts.is_empty() || last_start.elapsed() >= CONNECT_ATTEMPT_DELAY) {
+                let idx = next;
+                next += 1;
+                last_start = time::Instant::now();
+                let addr = match &addresses[idx] {
+                    BusAddress::Unix(addr) => Ok(*addr),
+                    BusAddress::Launchd { env } => launchd_addr(env),
+                    // nothing else is running, see races
+                    one_shot => match one_shot.connect_timeout(deadline.remaining()?) {
+                        Ok(stream) => return Ok((idx, stream)),
+                        Err(e) => Err(e),
+                    },
+                };
+                let started = addr.and_then(|addr| {
+                    let sock = socket::socket(
+                        socket::AddressFamily::Unix,
+                        socket::SockType::Stream,
+                        socket::SockFlag::empty(),
+                        None,
+                    )
+                    .map_err(io::Error::from)?;
+                    let sock = UnixStream::from(sock);
+                    sock.set_nonblocking(true)?;
+                    let state = try_connect(&sock, &addr)?;
+                    Ok((addr, sock, state))
+                });
+                match started {
+                    Ok((_, sock, ConnectState::Done)) => return connected(idx, sock),
+                    Ok((addr, sock, state)) => attempts.push(Attempt {
+                        idx,
+                        addr,
+                        sock,
+                        in_progress: matches!(state, ConnectState::InProgress),
+                    }),
+                    Err(e) => last_err = Some(e),
+                }
+                continue;
+            }
+            if attempts.is_empty() {
+                return Err(last_err.unwrap_or(Error::NoAddressFound));
+            }
+
+            let mut wait = match deadline.remaining_duration()? {
+                Some(remaining) if remaining.is_zero() => return Err(Error::TimedOut),
+                Some(remaining) => remaining,
+                None => time::Duration::MAX,
+            };
+            if can_start {
+                wait = wait.min(CONNECT_ATTEMPT_DELAY.saturating_sub(last_start.elapsed()));
+            }
+            if attempts.iter().any(|attempt| !attempt.in_progress) {
+                wait = wait.min(CONNECT_RETRY_INTERVAL);
+            }
+            let ready: Vec<bool> = {
+                let mut fds: Vec<PollFd> = attempts
+                    .iter()
+                    .filter(|attempt| attempt.in_progress)
+                    .map(|attempt| PollFd::new(attempt.sock.as_fd(), PollFlags::POLLOUT))
+                    .collect();
+                match poll(
+                    &mut fds,
+                    PollTimeout::try_from(wait).unwrap_or(PollTimeout::MAX),
+                ) {
+                    Ok(_) | Err(nix::errno::Errno::EINTR) => {}
+                    Err(e) => return Err(io::Error::from(e).into()),
+                }
+                fds.iter().map(|fd| fd.any().unwrap_or(true)).collect()
+            };
+
+            let mut ready = ready.into_iter();
+            let mut i = 0;
+            while i < attempts.len() {
+                let attempt = &mut attempts[i];
+                let state = if attempt.in_progress {
+                    if !ready.next().unwrap_or(false) {
+                        i += 1;
+                        continue;
+                    }
+                    match getsockopt(&attempt.sock, sockopt::SocketError) {
+                        Ok(0) => Ok(ConnectState::Done),
+                        Ok(errno) => Err(io::Error::from_raw_os_error(errno).into()),
+                        Err(e) => Err(io::Error::from(e).into()),
+                    }
                 } else {
-                    return Err(Error::PathDoesNotExist(value.to_string()));
+                    try_connect(&attempt.sock, &attempt.addr)
+                };
+                match state {
+                    Ok(ConnectState::Done) => {
+                        let attempt = attempts.remove(i);
+                        return connected(attempt.idx, attempt.sock);
+                    }
+                    Ok(ConnectState::InProgress) => {
+                        attempt.in_progress = true;
+                        i += 1;
+                    }
+                    Ok(ConnectState::Retry) => i += 1,
+                    Err(e) => {
+                        attempts.remove(i);
+                        last_err = Some(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Open a stream to the address. Nothing has been sent over it yet, the authentication is up to the caller.
+    pub fn connect(&self) -> Result<std::os::unix::net::UnixStream> {
+        self.connect_timeout(Timeout::Infinite)
+    }
+
+    /// Like `connect` but waiting for an incoming connection on the socket passed by systemd gives up after `timeout`
+    pub fn connect_timeout(&self, timeout: Timeout) -> Result<std::os::unix::net::UnixStream> {
+        use nix::sys::socket::{self, connect, socket};
+        use std::os::fd::AsRawFd;
+        use std::os::unix::net::UnixStream;
+
+        match self {
+            BusAddress::Unix(addr) => {
+                let sock = socket(
+                    socket::AddressFamily::Unix,
+                    socket::SockType::Stream,
+                    socket::SockFlag::empty(),
+                    None,
+                )
+                .map_err(io::Error::from)?;
+                connect(sock.as_raw_fd(), addr).map_err(io::Error::from)?;
+                Ok(UnixStream::from(sock))
+            }
+            BusAddress::UnixExec { path, argv } => {
+                use std::os::unix::process::CommandExt;
+
+                let (ours, theirs) = UnixStream::pair()?;
+                let mut cmd = std::process::Command::new(path);
+                if let Some((argv0, args)) = argv.split_first() {
+                    cmd.arg0(argv0).args(args);
                 }
+                let mut child = cmd
+                    .stdin(std::os::fd::OwnedFd::from(theirs.try_clone()?))
+                    .stdout(std::os::fd::OwnedFd::from(theirs))
+                    .spawn()?;
+                // reap the child when it exits, the connection does not need anything else from it
+                std::thread::spawn(move || child.wait());
+                Ok(ours)
             }
-            "abstract" => {
-                #[cfg(target_os = "linux")]
-                {
-                    return Ok(UnixAddr::new_abstract(value.as_bytes()).map_err(io::Error::from)?);
+            BusAddress::Systemd => {
+                let (fd, listening) = SYSTEMD_SOCKET
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .take(take_systemd_fd)?;
+                if listening {
+                    accept_timeout(&fd, timeout)
+                } else {
+                    Ok(UnixStream::from(fd))
                 }
             }
-            _ => {}
+            BusAddress::Launchd { env } => BusAddress::Unix(launchd_addr(env)?).connect(),
         }
     }
+}
+
+/// The socket path launchd keeps in the environment variable `env`
+fn launchd_addr(env: &str) -> Result<UnixAddr> {
+    let output = std::process::Command::new("launchctl")
+        .arg("getenv")
+        .arg(env)
+        .output()?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if !output.status.success() || path.is_empty() {
+        return Err(Error::NoAddressFound);
+    }
+    Ok(UnixAddr::new(path.as_str()).map_err(io::Error::from)?)
+}
+
+/// How far a nonblocking connect got
+enum ConnectState {
+    Done,
+    /// The listener has no room for more connections, nonblocking unix sockets do not wait for it
+    Retry,
+    /// Wait until the socket is writable
+    InProgress,
+}
+
+fn try_connect(sock: &std::os::unix::net::UnixStream, addr: &UnixAddr) -> Result<ConnectState> {
+    use nix::errno::Errno;
+    use std::os::fd::AsRawFd;
+
+    match nix::sys::socket::connect(sock.as_raw_fd(), addr) {
+        Ok(()) | Err(Errno::EISCONN) => Ok(ConnectState::Done),
+        Err(Errno::EAGAIN) | Err(Errno::EINTR) => Ok(ConnectState::Retry),
+        Err(Errno::EINPROGRESS) | Err(Errno::EALREADY) => Ok(ConnectState::InProgress),
+        Err(e) => Err(io::Error::from(e).into()),
+    }
+}
+
+/// The first socket passed with socket activation, see `sd_listen_fds(3)`
+fn take_systemd_fd() -> Result<std::os::fd::OwnedFd> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    /// `SD_LISTEN_FDS_START`
+    const FIRST_FD: std::os::fd::RawFd = 3;
+
+    let env_number = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u32>().ok());
+    if env_number("LISTEN_PID") != Some(std::process::id())
+        || env_number("LISTEN_FDS").unwrap_or(0) == 0
+    {
+        return Err(Error::NoAddressFound);
+    }
+    // Safety: systemd passed this fd to this process and `PassedSocket` makes sure it is only taken once
+    Ok(unsafe { OwnedFd::from_raw_fd(FIRST_FD) })
+}
+
+/// What became of a socket passed to this process. A listening socket is kept to accept connections from it, a
+/// connected one can only end up in one connection.
+enum PassedSocket {
+    Untouched,
+    Listening(std::os::fd::OwnedFd),
+    Taken,
+}
+
+impl PassedSocket {
+    /// The connected socket the first time, or a duplicate of the listening socket every time. The bool tells which
+    /// one it is. `passed` returns the socket the first time, its errors leave the socket untouched.
+    fn take(
+        &mut self,
+        passed: impl FnOnce() -> Result<std::os::fd::OwnedFd>,
+    ) -> Result<(std::os::fd::OwnedFd, bool)> {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+        use nix::sys::socket::{getsockopt, sockopt};
+        use std::os::fd::AsRawFd;
+
+        match self {
+            PassedSocket::Listening(listener) => return Ok((listener.try_clone()?, true)),
+            PassedSocket::Taken => return Err(Error::NoAddressFound),
+            PassedSocket::Untouched => {}
+        }
+        let fd = passed()?;
+        *self = PassedSocket::Taken;
+        // systemd passes the fds without close-on-exec
+        fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(io::Error::from)?;
+        if !getsockopt(&fd, sockopt::AcceptConn).map_err(io::Error::from)? {
+            return Ok((fd, false));
+        }
+        // accept_timeout waits with poll()
+        fcntl(fd.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).map_err(io::Error::from)?;
+        let dup = fd.try_clone()?;
+        *self = PassedSocket::Listening(fd);
+        Ok((dup, true))
+    }
+}
+
+/// Accept the next connection from a nonblocking listening socket
+fn accept_timeout(
+    listener: &std::os::fd::OwnedFd,
+    timeout: Timeout,
+) -> Result<std::os::unix::net::UnixStream> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+    use nix::sys::socket::accept;
+    use std::convert::TryFrom;
+    use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+
+    let deadline = Deadline::new(timeout);
+    loop {
+        match accept(listener.as_raw_fd()) {
+            Ok(conn) => {
+                // Safety: accept just returned this fd
+                let conn = unsafe { OwnedFd::from_raw_fd(conn) };
+                fcntl(conn.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+                    .map_err(io::Error::from)?;
+                let stream = std::os::unix::net::UnixStream::from(conn);
+                // some platforms pass O_NONBLOCK on to the accepted socket
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(nix::errno::Errno::EAGAIN) | Err(nix::errno::Errno::EINTR) => {}
+            Err(e) => return Err(io::Error::from(e).into()),
+        }
+        let timeout = match deadline.remaining_duration()? {
+            Some(d) if d.is_zero() => return Err(Error::TimedOut),
+            Some(d) => PollTimeout::try_from(d).unwrap_or(PollTimeout::MAX),
+            None => PollTimeout::NONE,
+        };
+        let mut pollfd = [PollFd::new(listener.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut pollfd, timeout) {
+            Ok(_) | Err(nix::errno::Errno::EINTR) => {}
+            Err(e) => return Err(io::Error::from(e).into()),
+        }
+    }
+}
 
-    Err(Error::AddressTypeNotSupported(addr.to_owned()))
+fn parse_dbus_addr_str(addr: &str) -> Result<UnixAddr> {
+    match BusAddress::parse(addr)? {
+        BusAddress::Unix(addr) => Ok(addr),
+        _ => Err(Error::AddressTypeNotSupported(addr.to_owned())),
+    }
 }
 
 /// Convenience function that returns the UnixAddr of the session bus according to the env
@@ -98,6 +605,22 @@ pub fn get_session_bus_path() -> Result<UnixAddr> {
     }
 }
 
+/// Like `get_session_bus_path` but also supports the address types that are not a unix socket path. If the variable
+/// lists several addresses this is the first one that could be parsed, see `get_session_bus_addresses`.
+pub fn get_session_bus_address() -> Result<BusAddress> {
+    get_session_bus_addresses().map(|mut addresses| addresses.remove(0))
+}
+
+/// All addresses listed in $DBUS_SESSION_BUS_ADDRESS that could be parsed, in their order. Use
+/// `BusAddress::connect_any` to connect to the first one that works.
+pub fn get_session_bus_addresses() -> Result<Vec<BusAddress>> {
+    if let Ok(envvar) = std::env::var("DBUS_SESSION_BUS_ADDRESS") {
+        BusAddress::parse_list(&envvar)
+    } else {
+        Err(Error::NoAddressFound)
+    }
+}
+
 /// Convenience function that returns a path to the system bus at /run/dbus/systemd_bus_socket
 pub fn get_system_bus_path() -> Result<UnixAddr> {
     let ps = "/run/dbus/system_bus_socket";
@@ -109,17 +632,69 @@ pub fn get_system_bus_path() -> Result<UnixAddr> {
     }
 }
 
-pub(crate) fn calc_timeout_left(start_time: &time::Instant, timeout: Timeout) -> Result<Timeout> {
-    match timeout {
-        Timeout::Duration(timeout) => {
-            let elapsed = start_time.elapsed();
-            if elapsed >= timeout {
-                return Err(Error::TimedOut);
+/// The point in time at which an operation that was given a `Timeout` has to give up.
+///
+/// Operations that consist of multiple steps create one Deadline when they start and pass `remaining()` to each step,
+/// so the steps share the time budget instead of each getting the full timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deadline {
+    Never,
+    Nonblock,
+    At(time::Instant),
+}
+
+impl Deadline {
+    /// Starts the timeout now
+    pub fn new(timeout: Timeout) -> Self {
+        Self::starting_at(timeout, time::Instant::now())
+    }
+
+    pub fn starting_at(timeout: Timeout, start: time::Instant) -> Self {
+        match timeout {
+            Timeout::Infinite => Deadline::Never,
+            Timeout::Nonblock => Deadline::Nonblock,
+            // a timeout too large to be represented is as good as no timeout
+            Timeout::Duration(d) => start.checked_add(d).map_or(Deadline::Never, Deadline::At),
+        }
+    }
+
+    /// The timeout that is left for the next step. Returns `Error::TimedOut` if the deadline has passed.
+    pub fn remaining(&self) -> Result<Timeout> {
+        self.remaining_at(time::Instant::now())
+    }
+
+    pub fn remaining_at(&self, now: time::Instant) -> Result<Timeout> {
+        match self {
+            Deadline::Never => Ok(Timeout::Infinite),
+            Deadline::Nonblock => Ok(Timeout::Nonblock),
+            Deadline::At(deadline) => {
+                if now >= *deadline {
+                    Err(Error::TimedOut)
+                } else {
+                    Ok(Timeout::Duration(*deadline - now))
+                }
             }
-            let time_left = timeout - elapsed;
-            Ok(Timeout::Duration(time_left))
         }
-        other => Ok(other),
+    }
+
+    /// Like `remaining` but as the optional duration poll() like APIs expect. None means waiting
+    /// forever, a nonblocking deadline is a duration of zero.
+    pub fn remaining_duration(&self) -> Result<Option<time::Duration>> {
+        Ok(match self.remaining()? {
+            Timeout::Infinite => None,
+            Timeout::Nonblock => Some(time::Duration::ZERO),
+            Timeout::Duration(d) => Some(d),
+        })
+    }
+
+    pub fn expired(&self) -> bool {
+        self.remaining().is_err()
+    }
+}
+
+impl From<Timeout> for Deadline {
+    fn from(timeout: Timeout) -> Self {
+        Deadline::new(timeout)
     }
 }
 
@@ -127,6 +702,47 @@ pub(crate) fn calc_timeout_left(start_time: &time::Instant, timeout: Timeout) ->
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_deadline() {
+        let start = time::Instant::now();
+        let second = time::Duration::from_secs(1);
+        let deadline = Deadline::starting_at(Timeout::Duration(2 * second), start);
+
+        assert_eq!(
+            deadline.remaining_at(start).unwrap(),
+            Timeout::Duration(2 * second)
+        );
+        assert_eq!(
+            deadline.remaining_at(start + second).unwrap(),
+            Timeout::Duration(second)
+        );
+        assert!(matches!(
+            deadline.remaining_at(start + 2 * second),
+            Err(Error::TimedOut)
+        ));
+
+        let never = Deadline::starting_at(Timeout::Infinite, start);
+        assert_eq!(
+            never.remaining_at(start + 1000 * second).unwrap(),
+            Timeout::Infinite
+        );
+        assert!(!never.expired());
+        assert_eq!(never.remaining_duration().unwrap(), None);
+
+        let nonblock = Deadline::starting_at(Timeout::Nonblock, start);
+        assert_eq!(
+            nonblock.remaining_at(start + second).unwrap(),
+            Timeout::Nonblock
+        );
+        assert_eq!(
+            nonblock.remaining_duration().unwrap(),
+            Some(time::Duration::ZERO)
+        );
+
+        let too_far = Deadline::starting_at(Timeout::Duration(time::Duration::MAX), start);
+        assert_eq!(too_far, Deadline::Never);
+    }
+
     #[cfg(target_os = "linux")]
     #[test]
     fn test_get_session_bus_path() {
@@ -145,7 +761,7 @@ mod tests {
                 // stripped from the session bus' determined path.
                 assert_eq!("/tmp/dbus-test-not-exist", path);
             }
-            _ => assert!(false, "expected Error::PathDoesNotExist"),
+            _ => panic!("expected Error::PathDoesNotExist"),
         }
 
         let addr = parse_dbus_addr_str(abstract_path).unwrap();
@@ -154,6 +770,260 @@ mod tests {
         let addr = parse_dbus_addr_str(abstract_path_with_keys).unwrap();
         assert_eq!(addr, UnixAddr::new_abstract(b"/tmp/dbus-test").unwrap());
     }
+
+    #[test]
+    fn test_parse_bus_address() {
+        assert_eq!(
+            BusAddress::parse(
+                "unixexec:path=/usr/bin/ssh,argv1=-x,argv2=host%2cname%20x,argv4=skipped"
+            )
+            .unwrap(),
+            BusAddress::UnixExec {
+                path: PathBuf::from("/usr/bin/ssh"),
+                argv: vec!["/usr/bin/ssh".into(), "-x".into(), "host,name x".into()],
+            }
+        );
+        assert_eq!(
+            BusAddress::parse("unixexec:argv0=bridge,path=/bin/bridge").unwrap(),
+            BusAddress::UnixExec {
+                path: PathBuf::from("/bin/bridge"),
+                argv: vec!["bridge".into()],
+            }
+        );
+        assert_eq!(BusAddress::parse("systemd:").unwrap(), BusAddress::Systemd);
+        assert_eq!(
+            BusAddress::parse("launchd:env=DBUS_LAUNCHD_SESSION_BUS_SOCKET").unwrap(),
+            BusAddress::Launchd {
+                env: "DBUS_LAUNCHD_SESSION_BUS_SOCKET".into()
+            }
+        );
+        for invalid in [
+            "unixexec:argv0=x",
+            "launchd:",
+            "tcp:host=localhost,port=1234",
+            "unix:path=%zz",
+            "unix:path",
+        ] {
+            assert!(
+                matches!(
+                    BusAddress::parse(invalid),
+                    Err(Error::AddressTypeNotSupported(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+        assert!(matches!(
+            parse_dbus_addr_str("systemd:"),
+            Err(Error::AddressTypeNotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_connect_unixexec() {
+        use std::io::{Read, Write};
+
+        // cat echoes everything back over the socket
+        let mut stream = BusAddress::parse("unixexec:path=/bin/cat")
+            .unwrap()
+            .connect()
+            .unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn test_parse_address_list() {
+        assert_eq!(
+            BusAddress::parse_list("tcp:host=localhost,port=1234;systemd:;;unixexec:path=/bin/cat")
+                .unwrap(),
+            vec![
+                BusAddress::Systemd,
+                BusAddress::UnixExec {
+                    path: PathBuf::from("/bin/cat"),
+                    argv: vec!["/bin/cat".into()],
+                }
+            ]
+        );
+        assert!(matches!(
+            BusAddress::parse_list("tcp:host=localhost,port=1234;nonce-tcp:"),
+            Err(Error::AddressTypeNotSupported(addr)) if addr == "tcp:host=localhost,port=1234"
+        ));
+        assert!(matches!(
+            BusAddress::parse_list(""),
+            Err(Error::NoAddressFound)
+        ));
+    }
+
+    #[test]
+    fn test_connect_any() {
+        use nix::sys::socket::{self, bind, connect, listen, socket, Backlog};
+        use std::os::fd::AsRawFd;
+
+        let dir = std::env::temp_dir().join(format!("rustbus-connect-any-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let good_path = dir.join("good");
+        let stalled_path = dir.join("stalled");
+        let _ = std::fs::remove_file(&good_path);
+        let _ = std::fs::remove_file(&stalled_path);
+        let _listener = std::os::unix::net::UnixListener::bind(&good_path).unwrap();
+        let good = BusAddress::Unix(UnixAddr::new(&good_path).unwrap());
+        let missing = BusAddress::Unix(UnixAddr::new(&dir.join("missing")).unwrap());
+
+        // failed attempts do not hold up the next one
+        let second = time::Duration::from_secs(1);
+        let (idx, _stream) = BusAddress::connect_any(
+            &[missing.clone(), good.clone()],
+            Timeout::Duration(5 * second),
+        )
+        .unwrap();
+        assert_eq!(idx, 1);
+        assert!(matches!(
+            BusAddress::connect_any(
+                std::slice::from_ref(&missing),
+                Timeout::Duration(5 * second)
+            ),
+            Err(Error::IoError(_))
+        ));
+        assert!(matches!(
+            BusAddress::connect_any(&[], Timeout::Infinite),
+            Err(Error::NoAddressFound)
+        ));
+
+        // connecting to a listener whose backlog is full blocks
+        let stalled_addr = UnixAddr::new(&stalled_path).unwrap();
+        let unix_socket = |flags| {
+            socket(
+                socket::AddressFamily::Unix,
+                socket::SockType::Stream,
+                flags,
+                None,
+            )
+            .unwrap()
+        };
+        let stalled_listener = unix_socket(socket::SockFlag::empty());
+        bind(stalled_listener.as_raw_fd(), &stalled_addr).unwrap();
+        listen(&stalled_listener, Backlog::new(0).unwrap()).unwrap();
+        let mut pending = Vec::new();
+        loop {
+            let client = unix_socket(socket::SockFlag::SOCK_NONBLOCK);
+            if connect(client.as_raw_fd(), &stalled_addr).is_err() {
+                break;
+            }
+            pending.push(client);
+        }
+        let stalled = BusAddress::Unix(stalled_addr);
+
+        let start = time::Instant::now();
+        let (idx, _stream) =
+            BusAddress::connect_any(&[stalled.clone(), good], Timeout::Duration(5 * second))
+                .unwrap();
+        assert_eq!(idx, 1);
+        assert!(start.elapsed() >= CONNECT_ATTEMPT_DELAY);
+        assert!(start.elapsed() < 5 * second);
+        assert!(matches!(
+            BusAddress::connect_any(
+                std::slice::from_ref(&stalled),
+                Timeout::Duration(second / 10)
+            ),
+            Err(Error::TimedOut)
+        ));
+
+        // unixexec is not raced, it only starts once the attempts before it failed
+        let marker = dir.join("spawned");
+        let unixexec = BusAddress::UnixExec {
+            path: PathBuf::from("/bin/sh"),
+            argv: vec![
+                "sh".into(),
+                "-c".into(),
+                format!("touch {}", marker.display()),
+            ],
+        };
+        assert!(matches!(
+            BusAddress::connect_any(
+                &[stalled, unixexec.clone()],
+                Timeout::Duration(2 * CONNECT_ATTEMPT_DELAY)
+            ),
+            Err(Error::TimedOut)
+        ));
+        assert!(!marker.exists());
+        let (idx, _stream) =
+            BusAddress::connect_any(&[missing, unixexec], Timeout::Duration(5 * second)).unwrap();
+        assert_eq!(idx, 1);
+        let start = time::Instant::now();
+        while !marker.exists() && start.elapsed() < 5 * second {
+            std::thread::sleep(time::Duration::from_millis(10));
+        }
+        assert!(marker.exists());
+
+        // closing the listener lets the stalled attempts fail
+        drop(stalled_listener);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_systemd_without_socket() {
+        // LISTEN_PID is not set for the test process
+        assert!(matches!(
+            BusAddress::Systemd.connect(),
+            Err(Error::NoAddressFound)
+        ));
+    }
+
+    #[test]
+    fn test_passed_socket() {
+        use std::io::{Read, Write};
+        use std::os::fd::OwnedFd;
+
+        let path = std::env::temp_dir().join(format!("rustbus-passed-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        // failing to get the socket leaves it untouched
+        let mut passed = PassedSocket::Untouched;
+        assert!(matches!(
+            passed.take(|| Err(Error::NoAddressFound)),
+            Err(Error::NoAddressFound)
+        ));
+        let (fd, listening) = passed.take(|| Ok(OwnedFd::from(listener))).unwrap();
+        assert!(listening);
+        assert!(matches!(
+            accept_timeout(&fd, Timeout::Duration(time::Duration::from_millis(10))),
+            Err(Error::TimedOut)
+        ));
+
+        // every take accepts the next connection, like a reconnect would
+        for msg in [b"first", b"again"] {
+            let mut client = std::os::unix::net::UnixStream::connect(&path).unwrap();
+            let (fd, listening) = passed.take(|| panic!("taken twice")).unwrap();
+            assert!(listening);
+            let mut conn = accept_timeout(&fd, Timeout::Infinite).unwrap();
+            client.write_all(msg).unwrap();
+            let mut buf = [0; 5];
+            conn.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, msg);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        // a connected socket can only be taken once
+        let (conn, _peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut passed = PassedSocket::Untouched;
+        let (_, listening) = passed.take(|| Ok(OwnedFd::from(conn))).unwrap();
+        assert!(!listening);
+        assert!(matches!(
+            passed.take(|| panic!("taken twice")),
+            Err(Error::NoAddressFound)
+        ));
+    }
+
+    #[test]
+    fn test_connect_to_socket_path() {
+        let res = ll_conn::DuplexConn::connect_to_socket_path("/tmp/dbus-test-not-exist", true);
+        assert!(matches!(res, Err(Error::IoError(_))));
+    }
+
     #[cfg(not(target_os = "linux"))]
     #[test]
     fn test_get_session_bus_path() {
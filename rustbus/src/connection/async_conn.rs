@@ -0,0 +1,268 @@
+//! Async connections that run on the tokio reactor (needs the `tokio` feature)
+//!
+//! These wrap the same connections as the blocking API but wait for the socket to become readable or writable on the
+//! reactor instead of blocking a thread. Connecting and authenticating is still done with the blocking constructors of
+//! `DuplexConn`, which only exchange a few short lines with the bus.
+//!
+//! ```rust,no_run
+//! use rustbus::connection::async_conn::AsyncRpcConn;
+//! use rustbus::standard_messages;
+//!
+//! # async fn example() -> Result<(), rustbus::connection::Error> {
+//! let mut rpc_con = AsyncRpcConn::session_conn().await?;
+//! let reply = rpc_con.call_method(&mut standard_messages::list_names()).await?;
+//! let names: Vec<String> = reply.body.parser().get()?;
+//! println!("{:?}", names);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::num::NonZeroU32;
+
+use tokio::io::unix::AsyncFd;
+
+use super::ll_conn::{DuplexConn, SendMessageContext};
+use super::{get_session_bus_path, get_system_bus_path, Error, Result, Timeout, UnixAddr};
+use crate::message_builder::{MarshalledMessage, MessageType};
+
+/// Whether an error of a nonblocking operation only means that the socket is not ready yet
+fn would_block(err: &Error) -> bool {
+    match err {
+        Error::TimedOut => true,
+        Error::IoError(err) => err.kind() == io::ErrorKind::WouldBlock,
+        _ => false,
+    }
+}
+
+/// The async counterpart to `DuplexConn`
+///
+/// `get_next_message` is cancel safe, bytes that were already read stay buffered for the next call. `send_message` is not:
+/// dropping its future after part of a message was written leaves the connection in an unusable state.
+pub struct AsyncDuplexConn {
+    conn: AsyncFd<DuplexConn>,
+}
+
+impl AsyncDuplexConn {
+    /// Register the connection with the reactor. This has to be called from within a tokio runtime.
+    pub fn new(conn: DuplexConn) -> io::Result<Self> {
+        Ok(Self {
+            conn: AsyncFd::new(conn)?,
+        })
+    }
+
+    /// Connect and authenticate like `DuplexConn::connect_to_bus` and register the connection with the reactor.
+    /// Remember to send the hello message if this is a bus, e.g. with `send_hello`.
+    pub fn connect_to_bus(addr: UnixAddr, with_unix_fd: bool) -> Result<Self> {
+        Ok(Self::new(DuplexConn::connect_to_bus(addr, with_unix_fd)?)?)
+    }
+
+    pub fn get_ref(&self) -> &DuplexConn {
+        self.conn.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut DuplexConn {
+        self.conn.get_mut()
+    }
+
+    /// Unregister the connection from the reactor and return it
+    pub fn into_inner(self) -> DuplexConn {
+        self.conn.into_inner()
+    }
+
+    /// Send a message and wait until all bytes have been written. Returns the serial of the message.
+    pub async fn send_message(&mut self, msg: &MarshalledMessage) -> Result<NonZeroU32> {
+        let mut progress = self.conn.get_mut().send.send_message(msg)?.into_progress();
+        loop {
+            let ctx = SendMessageContext::resume(&mut self.conn.get_mut().send, msg, progress);
+            match ctx.write(Timeout::Nonblock) {
+                Ok(serial) => return Ok(serial),
+                Err((ctx, err)) if would_block(&err) => progress = ctx.into_progress(),
+                Err((ctx, err)) => {
+                    ctx.force_finish();
+                    return Err(err);
+                }
+            }
+            self.conn.writable_mut().await?.clear_ready();
+        }
+    }
+
+    /// Wait until the next message has been received
+    pub async fn get_next_message(&mut self) -> Result<MarshalledMessage> {
+        loop {
+            match self.conn.get_mut().recv.get_next_message(Timeout::Nonblock) {
+                Err(err) if would_block(&err) => {}
+                res => return res,
+            }
+            self.conn.readable_mut().await?.clear_ready();
+        }
+    }
+
+    /// Sends the obligatory hello message and returns the unique id the daemon assigned this connection
+    pub async fn send_hello(&mut self) -> Result<String> {
+        let serial = self
+            .send_message(&crate::standard_messages::hello())
+            .await?;
+        let resp = self.get_next_message().await?;
+        if resp.dynheader.response_serial != Some(serial) {
+            return Err(Error::AuthFailed);
+        }
+        Ok(resp.body.parser().get::<String>()?)
+    }
+}
+
+/// The async counterpart to `RpcConn`. Messages that arrive while waiting for something else are queued like in the
+/// RpcConn. There are no filters and middlewares, incoming calls are all queued.
+pub struct AsyncRpcConn {
+    signals: VecDeque<MarshalledMessage>,
+    calls: VecDeque<MarshalledMessage>,
+    responses: HashMap<NonZeroU32, MarshalledMessage>,
+    conn: AsyncDuplexConn,
+}
+
+impl AsyncRpcConn {
+    pub fn new(conn: AsyncDuplexConn) -> Self {
+        AsyncRpcConn {
+            signals: VecDeque::new(),
+            calls: VecDeque::new(),
+            responses: HashMap::new(),
+            conn,
+        }
+    }
+
+    pub async fn session_conn() -> Result<Self> {
+        Self::connect_to_path(get_session_bus_path()?).await
+    }
+
+    pub async fn system_conn() -> Result<Self> {
+        Self::connect_to_path(get_system_bus_path()?).await
+    }
+
+    /// Connect to the bus at `path` and send the hello message
+    pub async fn connect_to_path(path: UnixAddr) -> Result<Self> {
+        let mut conn = AsyncDuplexConn::connect_to_bus(path, true)?;
+        conn.send_hello().await?;
+        Ok(Self::new(conn))
+    }
+
+    pub fn conn(&self) -> &AsyncDuplexConn {
+        &self.conn
+    }
+
+    pub fn conn_mut(&mut self) -> &mut AsyncDuplexConn {
+        &mut self.conn
+    }
+
+    /// Send a message and wait until all bytes have been written. Returns the serial of the message.
+    pub async fn send_message(&mut self, msg: &MarshalledMessage) -> Result<NonZeroU32> {
+        self.conn.send_message(msg).await
+    }
+
+    /// Send a call and wait for the response. The response may be an error message, check its `typ`.
+    pub async fn call_method(&mut self, msg: &mut MarshalledMessage) -> Result<MarshalledMessage> {
+        let serial = self.send_message(msg).await?;
+        self.wait_response(serial).await
+    }
+
+    /// Return a response if one is there but dont wait
+    pub fn try_get_response(&mut self, serial: NonZeroU32) -> Option<MarshalledMessage> {
+        self.responses.remove(&serial)
+    }
+
+    /// Return a signal if one is there but dont wait
+    pub fn try_get_signal(&mut self) -> Option<MarshalledMessage> {
+        self.signals.pop_front()
+    }
+
+    /// Return a call if one is there but dont wait
+    pub fn try_get_call(&mut self) -> Option<MarshalledMessage> {
+        self.calls.pop_front()
+    }
+
+    pub async fn wait_response(&mut self, serial: NonZeroU32) -> Result<MarshalledMessage> {
+        loop {
+            if let Some(msg) = self.try_get_response(serial) {
+                return Ok(msg);
+            }
+            self.refill_once().await?;
+        }
+    }
+
+    pub async fn wait_signal(&mut self) -> Result<MarshalledMessage> {
+        loop {
+            if let Some(msg) = self.try_get_signal() {
+                return Ok(msg);
+            }
+            self.refill_once().await?;
+        }
+    }
+
+    pub async fn wait_call(&mut self) -> Result<MarshalledMessage> {
+        loop {
+            if let Some(msg) = self.try_get_call() {
+                return Ok(msg);
+            }
+            self.refill_once().await?;
+        }
+    }
+
+    /// Wait for the next message and put it into the matching queue. Returns which kind of message was received.
+    pub async fn refill_once(&mut self) -> Result<MessageType> {
+        let msg = self.conn.get_next_message().await?;
+        let typ = msg.typ;
+        match typ {
+            MessageType::Call => self.calls.push_back(msg),
+            MessageType::Signal => self.signals.push_back(msg),
+            MessageType::Reply | MessageType::Error => {
+                if let Some(serial) = msg.dynheader.response_serial {
+                    self.responses.insert(serial, msg);
+                }
+            }
+            MessageType::Invalid => return Err(Error::UnexpectedMessageTypeReceived),
+        }
+        Ok(typ)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageBuilder;
+
+    #[tokio::test]
+    async fn test_async_call() {
+        let (client, service) = DuplexConn::pair().unwrap();
+        let mut client = AsyncRpcConn::new(AsyncDuplexConn::new(client).unwrap());
+        let mut service = AsyncDuplexConn::new(service).unwrap();
+
+        // big enough to need multiple reads and writes
+        let payload = vec![7u8; 4 * 1024 * 1024];
+        let mut call = MessageBuilder::new().call("Echo").on("/").build();
+        call.body.push_param(payload.as_slice()).unwrap();
+
+        let serve = async {
+            let call = service.get_next_message().await.unwrap();
+            let mut signal = MessageBuilder::new()
+                .signal("io.killing.spark", "Sig", "/")
+                .build();
+            signal.body.push_param(1u32).unwrap();
+            service.send_message(&signal).await.unwrap();
+
+            let mut resp = call.dynheader.make_response();
+            resp.body
+                .push_param(call.body.parser().get::<&[u8]>().unwrap())
+                .unwrap();
+            service.send_message(&resp).await.unwrap();
+        };
+        let (resp, ()) = tokio::join!(client.call_method(&mut call), serve);
+
+        let resp = resp.unwrap();
+        assert_eq!(
+            resp.body.parser().get::<&[u8]>().unwrap(),
+            payload.as_slice()
+        );
+        let signal = client.wait_signal().await.unwrap();
+        assert_eq!(signal.body.parser().get::<u32>().unwrap(), 1);
+    }
+}
@@ -3,96 +3,360 @@
 //! The basic concept is similar to how http routers work. The object path is split up and can be matched against to determin which handler
 //! should be called. After setting up all the handlers you can call run() on the DispatchConnection. There is a simple example in the examples
 //! directory and an extensive example in the rustbus repo called `example_keywallet` which somewhat implements the freedesktop `secret service API`.
+//!
+//! Handlers can also be added for one interface or one member of an interface on the matching objects. The most specific
+//! handler is called: the one for the member, then the one for the interface, then the one for the object path and then
+//! the default handler.
+//!
+//! ## Timers
+//! Periodic maintenance (e.g. expiring sessions) can be done with `add_timer` and `add_timer_at` instead of an extra
+//! thread. The callbacks run on the thread that serves the connections, between the handled messages, so they get
+//! the same `HandlerCtx` as the handlers. A callback that is due is called after the message that is currently
+//! handled, it is not called if no one runs the DispatchConn.
+//!
+//! ## Testing a service without a bus
+//! `DuplexConn::pair` connects two connections over a socketpair, so the handlers can be tested from within the same
+//! process by serving one end and calling the service over the other one.
+//!
+//! ```rust
+//! use rustbus::connection::dispatch_conn::{DispatchConn, HandleEnvironment, HandleResult, Matches};
+//! use rustbus::message_builder::MarshalledMessage;
+//! use rustbus::{connection::Timeout, DuplexConn, MessageBuilder, RpcConn};
+//!
+//! fn pong(
+//!     _ctx: &mut (),
+//!     _matches: Matches,
+//!     msg: &MarshalledMessage,
+//!     _env: &mut HandleEnvironment<(), ()>,
+//! ) -> HandleResult<()> {
+//!     let mut resp = msg.dynheader.make_response();
+//!     resp.body.push_param("pong")?;
+//!     Ok(Some(resp))
+//! }
+//!
+//! let (service, client) = DuplexConn::pair().unwrap();
+//! // handlers do not have to be Send, so the DispatchConn is created on the thread that serves it
+//! std::thread::spawn(move || DispatchConn::new(service, (), Box::new(pong)).run());
+//!
+//! let mut client = RpcConn::new(client);
+//! let mut call = MessageBuilder::new().call("Ping").with_interface("io.killing.spark").on("/").build();
+//! let resp = client.call_method(&mut call, Timeout::Infinite).unwrap();
+//! assert_eq!(resp.body.parser().get::<&str>().unwrap(), "pong");
+//! ```
 
 use super::ll_conn::DuplexConn;
 use super::ll_conn::RecvConn;
 use super::ll_conn::SendConn;
+use super::middleware::{IncomingAction, Middleware, MiddlewareChain};
+use super::portal_request::Requests;
+use super::properties::Properties;
 use super::*;
 use crate::message_builder::MarshalledMessage;
 use crate::wire::errors::MarshalError;
 use crate::wire::errors::UnmarshalError;
 
+use crate::sync::{Arc, Mutex};
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::Mutex;
 
-#[derive(Eq, PartialEq, Hash)]
-enum PathPart {
-    MatchExact(String),
-    MatchAs(String),
+enum PathPart<'a> {
+    MatchExact(&'a str),
+    MatchAs(&'a str),
     AcceptAll,
 }
 
-impl PathPart {
-    fn is_accept_all(&self) -> bool {
-        matches!(self, PathPart::AcceptAll)
+impl<'a> PathPart<'a> {
+    fn parse(part: &'a str) -> Self {
+        if part.starts_with(':') {
+            PathPart::MatchAs(part)
+        } else if part.eq("*") {
+            PathPart::AcceptAll
+        } else {
+            PathPart::MatchExact(part)
+        }
     }
 }
 
-#[derive(Eq, PartialEq, Hash)]
-struct ObjectPathPattern(Vec<PathPart>);
 #[derive(Default)]
 pub struct Matches {
     pub matches: HashMap<String, String>,
 }
 
-impl ObjectPathPattern {
-    pub fn new(path: &str) -> Self {
-        let parts = path.split('/').map(|part| {
-            if part.starts_with(':') {
-                PathPart::MatchAs(part.to_owned())
-            } else if part.eq("*") {
-                PathPart::AcceptAll
-            } else {
-                PathPart::MatchExact(part.to_owned())
-            }
-        });
-        Self(parts.collect())
+/// What a handler was registered for: an object path pattern and optionally an interface and a member of it
+pub(super) struct HandlerKey {
+    path: String,
+    interface: Option<String>,
+    member: Option<String>,
+}
+
+impl HandlerKey {
+    pub(super) fn new(path_pattern: &str, interface: Option<&str>, member: Option<&str>) -> Self {
+        Self {
+            path: path_pattern.to_owned(),
+            interface: interface.map(str::to_owned),
+            member: member.map(str::to_owned),
+        }
     }
 
-    pub fn matches(&self, query: &str) -> Option<Matches> {
-        let parts = query.split('/').collect::<Vec<_>>();
-        if parts.len() < self.0.len() {
-            None
-        } else {
-            parts.into_iter().enumerate().try_fold(
-                Matches::default(),
-                |mut matches, (idx, part)| {
-                    if idx >= self.0.len() {
-                        // The path is too long. If the last member of the patter is a wildcard
-                        // this is acceptable.
-                        if self.0.last().unwrap().is_accept_all() {
-                            Some(matches)
-                        } else {
-                            None
-                        }
-                    } else {
-                        match &self.0[idx] {
-                            PathPart::AcceptAll => {
-                                // Nothing to do :)
-                                Some(matches)
-                            }
-                            PathPart::MatchExact(exact) => {
-                                if exact.eq(part) {
-                                    Some(matches)
-                                } else {
-                                    None
-                                }
-                            }
-                            PathPart::MatchAs(name) => {
-                                matches.matches.insert(name.clone(), part.to_owned());
-                                Some(matches)
-                            }
+    /// The object and interface the handler implements, if the path has no captures or wildcards
+    fn object_interface(&self) -> Option<(&str, &str)> {
+        let interface = self.interface.as_deref()?;
+        self.path
+            .split('/')
+            .all(|part| matches!(PathPart::parse(part), PathPart::MatchExact(_)))
+            .then_some((self.path.as_str(), interface))
+    }
+}
+
+/// A node of the `RouteTable` for one segment of the path patterns. The handlers are indices into `RouteTable::routes`.
+#[derive(Default)]
+struct RouteNode {
+    exact: HashMap<String, RouteNode>,
+    /// `:name` segments match every segment and capture it
+    captures: Vec<(String, RouteNode)>,
+    /// A `*` segment matches every segment. If a pattern ends with it the pattern matches all longer paths too.
+    wildcard: Option<Box<RouteNode>>,
+    object: Option<usize>,
+    interfaces: HashMap<String, InterfaceRoutes>,
+}
+
+#[derive(Default)]
+struct InterfaceRoutes {
+    handler: Option<usize>,
+    members: HashMap<String, Option<usize>>,
+}
+
+/// The call a handler is looked up for
+#[derive(Clone, Copy)]
+struct RouteQuery<'a> {
+    interface: Option<&'a str>,
+    member: Option<&'a str>,
+}
+
+/// The best handler found so far: how specific it is for the call, its index and the captured segments
+type RouteCandidate = (u8, usize, Matches);
+
+impl RouteNode {
+    /// The node of `path_pattern` if any handler was registered for it
+    fn existing_node_mut(&mut self, path_pattern: &str) -> Option<&mut RouteNode> {
+        path_pattern
+            .split('/')
+            .try_fold(self, |node, part| match PathPart::parse(part) {
+                PathPart::MatchExact(exact) => node.exact.get_mut(exact),
+                PathPart::MatchAs(name) => node
+                    .captures
+                    .iter_mut()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, node)| node),
+                PathPart::AcceptAll => node.wildcard.as_deref_mut(),
+            })
+    }
+
+    fn node_mut(&mut self, path_pattern: &str) -> &mut RouteNode {
+        path_pattern
+            .split('/')
+            .fold(self, |node, part| match PathPart::parse(part) {
+                PathPart::MatchExact(exact) => node.exact.entry(exact.to_owned()).or_default(),
+                PathPart::MatchAs(name) => {
+                    let idx = match node.captures.iter().position(|(n, _)| n == name) {
+                        Some(idx) => idx,
+                        None => {
+                            node.captures.push((name.to_owned(), RouteNode::default()));
+                            node.captures.len() - 1
                         }
-                    }
-                },
-            )
+                    };
+                    &mut node.captures[idx].1
+                }
+                PathPart::AcceptAll => node.wildcard.get_or_insert_with(Default::default),
+            })
+    }
+
+    /// The most specific handler of this node for the call: the one for the member, the interface or the whole object
+    fn handler_for(&self, query: RouteQuery) -> Option<(u8, usize)> {
+        let interface = query.interface.and_then(|i| self.interfaces.get(i));
+        let member = interface
+            .zip(query.member)
+            .and_then(|(interface, member)| interface.members.get(member).copied().flatten());
+        member
+            .map(|idx| (2, idx))
+            .or_else(|| interface.and_then(|i| i.handler).map(|idx| (1, idx)))
+            .or_else(|| self.object.map(|idx| (0, idx)))
+    }
+
+    /// Offer the handler of this node as the match for the path, it is taken if it is more specific than the best one
+    fn offer(
+        &self,
+        captures: &[(&str, &str)],
+        query: RouteQuery,
+        best: &mut Option<RouteCandidate>,
+    ) {
+        let Some((rank, idx)) = self.handler_for(query) else {
+            return;
+        };
+        if best.as_ref().is_some_and(|(best, _, _)| *best >= rank) {
+            return;
+        }
+        let matches = captures
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        *best = Some((rank, idx, Matches { matches }));
+    }
+
+    /// Look up the remaining `segments` below this node. Exact segments are tried before captures and wildcards, so
+    /// they win if two patterns are equally specific for the call.
+    fn search<'t, 'q>(
+        &'t self,
+        segments: &[&'q str],
+        captures: &mut Vec<(&'t str, &'q str)>,
+        query: RouteQuery,
+        best: &mut Option<RouteCandidate>,
+    ) {
+        let Some((segment, rest)) = segments.split_first() else {
+            self.offer(captures, query, best);
+            return;
+        };
+        if let Some(child) = self.exact.get(*segment) {
+            child.search(rest, captures, query, best);
+        }
+        for (name, child) in &self.captures {
+            captures.push((name, segment));
+            child.search(rest, captures, query, best);
+            captures.pop();
+        }
+        if let Some(child) = &self.wildcard {
+            child.search(rest, captures, query, best);
+            if !rest.is_empty() {
+                child.offer(captures, query, best);
+            }
+        }
+    }
+}
+
+/// The handlers by path pattern, compiled into a trie of the segments. A lookup does one hash lookup per segment of
+/// the path for the exact segments and only follows the captures and wildcards that are registered at that position,
+/// instead of matching every pattern against the path.
+pub(super) struct RouteTable<T> {
+    root: RouteNode,
+    /// Removed handlers leave None behind, so the indices of the others stay valid
+    routes: Vec<Option<(HandlerKey, T)>>,
+}
+
+impl<T> Default for RouteTable<T> {
+    fn default() -> Self {
+        Self {
+            root: RouteNode::default(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl<T> RouteTable<T> {
+    /// Add a handler, replacing the one with the same key
+    pub(super) fn insert(&mut self, key: HandlerKey, handler: T) {
+        let node = self.root.node_mut(&key.path);
+        let slot = match (&key.interface, &key.member) {
+            (None, _) => &mut node.object,
+            (Some(interface), member) => {
+                let routes = node.interfaces.entry(interface.clone()).or_default();
+                match member {
+                    None => &mut routes.handler,
+                    Some(member) => routes.members.entry(member.clone()).or_default(),
+                }
+            }
+        };
+        match *slot {
+            Some(idx) => self.routes[idx] = Some((key, handler)),
+            None => {
+                *slot = Some(self.routes.len());
+                self.routes.push(Some((key, handler)));
+            }
+        }
+    }
+
+    /// Remove the handlers that were added for exactly `path_pattern`, all of them or only the ones for `interface`
+    /// and its members. Returns false if there were none.
+    pub(super) fn remove(&mut self, path_pattern: &str, interface: Option<&str>) -> bool {
+        let Some(node) = self.root.existing_node_mut(path_pattern) else {
+            return false;
+        };
+        let mut removed = Vec::new();
+        let mut remove_interface = |routes: InterfaceRoutes| {
+            removed.extend(routes.handler);
+            removed.extend(routes.members.into_values().flatten());
+        };
+        match interface {
+            Some(interface) => {
+                if let Some(routes) = node.interfaces.remove(interface) {
+                    remove_interface(routes);
+                }
+            }
+            None => {
+                node.interfaces
+                    .drain()
+                    .for_each(|(_, routes)| remove_interface(routes));
+                removed.extend(node.object.take());
+            }
+        }
+        let mut any = false;
+        for idx in removed {
+            any |= self.routes[idx].take().is_some();
+        }
+        any
+    }
+
+    /// The objects and interfaces that handlers were added for, see `HandlerKey::object_interface`
+    fn object_interfaces(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.routes
+            .iter()
+            .flatten()
+            .filter_map(|(key, _)| key.object_interface())
+    }
+
+    /// The most specific handler for a call to `member` of `interface` on the object at `path`
+    fn find(
+        &self,
+        path: &str,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> Option<(Matches, usize)> {
+        let segments: Vec<&str> = path.split('/').collect();
+        let mut best = None;
+        let query = RouteQuery { interface, member };
+        self.root
+            .search(&segments, &mut Vec::new(), query, &mut best);
+        best.map(|(_, idx, matches)| (matches, idx))
+    }
+
+    pub(super) fn get(
+        &self,
+        path: &str,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> Option<(Matches, &T)> {
+        let (matches, idx) = self.find(path, interface, member)?;
+        Some((matches, &self.routes[idx].as_ref()?.1))
+    }
+
+    pub(super) fn get_mut(
+        &mut self,
+        path: &str,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> Option<(Matches, &mut T)> {
+        let (matches, idx) = self.find(path, interface, member)?;
+        Some((matches, &mut self.routes[idx].as_mut()?.1))
+    }
+
+    /// Move all handlers into `other`
+    fn move_into(self, other: &mut RouteTable<T>) {
+        for (key, handler) in self.routes.into_iter().flatten() {
+            other.insert(key, handler);
         }
     }
 }
 
 pub struct PathMatcher<UserData, UserError: std::fmt::Debug> {
-    pathes: HashMap<ObjectPathPattern, Box<HandleFn<UserData, UserError>>>,
+    routes: RouteTable<Box<HandleFn<UserData, UserError>>>,
 }
 
 impl<UserData, UserError: std::fmt::Debug> Default for PathMatcher<UserData, UserError> {
@@ -104,7 +368,7 @@ impl<UserData, UserError: std::fmt::Debug> Default for PathMatcher<UserData, Use
 impl<UserData, UserError: std::fmt::Debug> PathMatcher<UserData, UserError> {
     pub fn new() -> Self {
         Self {
-            pathes: HashMap::new(),
+            routes: RouteTable::default(),
         }
     }
 
@@ -118,20 +382,61 @@ impl<UserData, UserError: std::fmt::Debug> PathMatcher<UserData, UserError> {
     /// 1. /io.killingspark/API/v1/ManagedObjects/CoolID/SetName
     /// 1. /io.killingspark/API/v1/ManagedObjects/1D5_4R3_FUN/SetName
     pub fn insert(&mut self, path_pattern: &str, handler: Box<HandleFn<UserData, UserError>>) {
-        self.pathes
-            .insert(ObjectPathPattern::new(path_pattern), handler);
+        self.insert_key(path_pattern, None, None, handler);
+    }
+
+    /// Like `insert` but the handler only gets the calls to `interface` on the matching objects. It takes precedence
+    /// over handlers for the whole object.
+    pub fn insert_interface(
+        &mut self,
+        path_pattern: &str,
+        interface: &str,
+        handler: Box<HandleFn<UserData, UserError>>,
+    ) {
+        self.insert_key(path_pattern, Some(interface), None, handler);
+    }
+
+    /// Like `insert` but the handler only gets the calls to `member` of `interface` on the matching objects. It takes
+    /// precedence over handlers for the whole interface or object.
+    pub fn insert_member(
+        &mut self,
+        path_pattern: &str,
+        interface: &str,
+        member: &str,
+        handler: Box<HandleFn<UserData, UserError>>,
+    ) {
+        self.insert_key(path_pattern, Some(interface), Some(member), handler);
+    }
+
+    fn insert_key(
+        &mut self,
+        path_pattern: &str,
+        interface: Option<&str>,
+        member: Option<&str>,
+        handler: Box<HandleFn<UserData, UserError>>,
+    ) {
+        let key = HandlerKey::new(path_pattern, interface, member);
+        self.routes.insert(key, handler);
     }
 
+    /// The handler for the whole object at `query`, handlers for interfaces or members are not considered
     pub fn get_match(
         &mut self,
         query: &str,
     ) -> Option<(Matches, &mut HandleFn<UserData, UserError>)> {
-        for (path, fun) in &mut self.pathes {
-            if let Some(matches) = path.matches(query) {
-                return Some((matches, fun.as_mut()));
-            }
-        }
-        None
+        self.get_match_for(query, None, None)
+    }
+
+    /// The most specific handler for a call to `member` of `interface` on the object at `query`: one for the member,
+    /// then one for the interface, then one for the whole object.
+    pub fn get_match_for(
+        &mut self,
+        query: &str,
+        interface: Option<&str>,
+        member: Option<&str>,
+    ) -> Option<(Matches, &mut HandleFn<UserData, UserError>)> {
+        let (matches, fun) = self.routes.get_mut(query, interface, member)?;
+        Some((matches, fun.as_mut()))
     }
 }
 
@@ -159,10 +464,83 @@ impl<UserError: std::fmt::Debug> From<crate::connection::Error> for HandleError<
     }
 }
 
+/// Identifies one of the connections a DispatchConn serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionId(usize);
+
+impl ConnectionId {
+    /// The connection that was passed to `DispatchConn::new`
+    pub const PRIMARY: ConnectionId = ConnectionId(0);
+}
+
 pub struct HandleEnvironment<UserData, UserError: std::fmt::Debug> {
+    /// The connection the message was received on
     pub conn: Arc<Mutex<SendConn>>,
+    /// Which of the served connections the message was received on
+    pub connection: ConnectionId,
     pub new_dispatches: PathMatcher<UserData, UserError>,
+    /// Removed after the handler returned, like `new_dispatches` are added
+    removed_dispatches: Vec<(String, Option<String>)>,
+    object: String,
+    member: String,
 }
+
+impl<UserData, UserError: std::fmt::Debug> HandleEnvironment<UserData, UserError> {
+    /// The environment `DispatchConn` passes to the handler of `msg`. Useful to call handlers directly, e.g. in tests.
+    ///
+    /// ```rust
+    /// use rustbus::connection::dispatch_conn::{ConnectionId, HandleEnvironment};
+    /// use rustbus::{DuplexConn, MessageBuilder};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let (conn, _peer) = DuplexConn::pair().unwrap();
+    /// let call = MessageBuilder::new().call("Ping").on("/io/killing/spark").build();
+    /// let env = HandleEnvironment::<(), ()>::new(Arc::new(Mutex::new(conn.send)), ConnectionId::PRIMARY, &call);
+    /// assert_eq!(env.object(), "/io/killing/spark");
+    /// assert_eq!(env.member(), "Ping");
+    /// ```
+    pub fn new(
+        conn: Arc<Mutex<SendConn>>,
+        connection: ConnectionId,
+        msg: &MarshalledMessage,
+    ) -> Self {
+        HandleEnvironment {
+            conn,
+            connection,
+            new_dispatches: PathMatcher::new(),
+            removed_dispatches: Vec::new(),
+            object: msg.dynheader.object.clone().unwrap_or_default(),
+            member: msg.dynheader.member.clone().unwrap_or_default(),
+        }
+    }
+
+    /// The object path of the handled message. Method calls without an object path are answered with an error
+    /// before they reach a handler, so this is always set for calls. Replies and errors do not have one,
+    /// for them this is empty.
+    pub fn object(&self) -> &str {
+        &self.object
+    }
+
+    /// The member of the handled message. Method calls without a member are answered with an error
+    /// before they reach a handler, so this is always set for calls. Replies and errors do not have one,
+    /// for them this is empty.
+    pub fn member(&self) -> &str {
+        &self.member
+    }
+
+    /// Remove the handlers of the object at `path` once the handler returned, see `DispatchConn::remove_object`
+    pub fn remove_object(&mut self, path: &str) {
+        self.removed_dispatches.push((path.to_owned(), None));
+    }
+
+    /// Remove the handlers of `interface` on the object at `path` once the handler returned, see
+    /// `DispatchConn::remove_interface`
+    pub fn remove_interface(&mut self, path: &str, interface: &str) {
+        self.removed_dispatches
+            .push((path.to_owned(), Some(interface.to_owned())));
+    }
+}
+
 pub type HandleResult<UserError> =
     std::result::Result<Option<MarshalledMessage>, HandleError<UserError>>;
 pub type HandleFn<UserData, UserError> = dyn FnMut(
@@ -172,12 +550,71 @@ pub type HandleFn<UserData, UserError> = dyn FnMut(
     &mut HandleEnvironment<UserData, UserError>,
 ) -> HandleResult<UserError>;
 
-pub struct DispatchConn<HandlerCtx, HandlerError: std::fmt::Debug> {
+/// The error returned by `DispatchConn::serve`. Contains the connection on which the error happened and the
+/// offending message if there was one.
+pub type ServeError<UserError> = (
+    ConnectionId,
+    Option<MarshalledMessage>,
+    HandleError<UserError>,
+);
+
+/// Identifies a timer added to a DispatchConn, to remove it with `remove_timer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+/// What a timer callback can access besides the `HandlerCtx`
+pub struct TimerEnvironment {
+    /// The served connections, to send signals or calls from a timer
+    pub conns: Vec<(ConnectionId, Arc<Mutex<SendConn>>)>,
+    /// The timer that is called
+    pub timer: TimerId,
+    cancel: bool,
+}
+
+impl TimerEnvironment {
+    /// Do not call this timer again. One-shot timers are removed after they were called anyway.
+    pub fn cancel(&mut self) {
+        self.cancel = true;
+    }
+}
+
+pub type TimerFn<UserData, UserError> =
+    dyn FnMut(
+        &mut UserData,
+        &mut TimerEnvironment,
+    ) -> std::result::Result<(), HandleError<UserError>>;
+
+struct Timer<UserData, UserError: std::fmt::Debug> {
+    id: TimerId,
+    due: std::time::Instant,
+    /// None for one-shot timers
+    every: Option<std::time::Duration>,
+    callback: Box<TimerFn<UserData, UserError>>,
+}
+
+struct ServedConn {
     recv: RecvConn,
     send: Arc<Mutex<SendConn>>,
+}
+
+/// Dispatches calls to handlers. It can serve multiple connections (e.g. the system bus, the session bus and
+/// peer-to-peer connections) that share the same handlers. Handlers that should only be reachable on one of the
+/// connections can be added with `add_handler_for`.
+///
+/// Handlers do not have to be Send, so neither is a DispatchConn. Create it on the thread that serves it, or use a
+/// `PooledDispatchConn` whose handlers are Send.
+pub struct DispatchConn<HandlerCtx, HandlerError: std::fmt::Debug> {
+    conns: Vec<Option<ServedConn>>,
     objects: PathMatcher<HandlerCtx, HandlerError>,
+    conn_objects: HashMap<ConnectionId, PathMatcher<HandlerCtx, HandlerError>>,
     default_handler: Box<HandleFn<HandlerCtx, HandlerError>>,
     ctx: HandlerCtx,
+    middleware: MiddlewareChain,
+    properties: Option<Properties>,
+    requests: Option<Requests>,
+    answer_peer_calls: bool,
+    timers: Vec<Timer<HandlerCtx, HandlerError>>,
+    next_timer_id: u64,
 }
 
 impl<UserData, UserError: std::fmt::Debug> DispatchConn<UserData, UserError> {
@@ -186,12 +623,49 @@ impl<UserData, UserError: std::fmt::Debug> DispatchConn<UserData, UserError> {
         ctx: UserData,
         default_handler: Box<HandleFn<UserData, UserError>>,
     ) -> Self {
-        Self {
-            recv: conn.recv,
-            send: Arc::new(Mutex::new(conn.send)),
+        let mut dpcon = Self {
+            conns: Vec::new(),
             objects: PathMatcher::new(),
+            conn_objects: HashMap::new(),
             default_handler,
             ctx,
+            middleware: MiddlewareChain::new(),
+            properties: None,
+            requests: None,
+            answer_peer_calls: true,
+            timers: Vec::new(),
+            next_timer_id: 0,
+        };
+        dpcon.add_connection(conn);
+        dpcon
+    }
+
+    /// Serve another connection with the same handlers. Messages from all connections are dispatched
+    /// in the order they arrive and responses are sent back on the connection the call came from.
+    pub fn add_connection(&mut self, conn: DuplexConn) -> ConnectionId {
+        let send = Arc::new(Mutex::new(conn.send));
+        if let Some(properties) = &self.properties {
+            properties.add_conn(send.clone());
+        }
+        self.conns.push(Some(ServedConn {
+            recv: conn.recv,
+            send,
+        }));
+        ConnectionId(self.conns.len() - 1)
+    }
+
+    /// Stop serving a connection, e.g. after `serve` returned an error for it. The connection is closed
+    /// once all `SendConn`s handed out to handlers are dropped. Returns false if the connection was already removed.
+    pub fn remove_connection(&mut self, id: ConnectionId) -> bool {
+        self.conn_objects.remove(&id);
+        match self.conns.get_mut(id.0).and_then(Option::take) {
+            Some(conn) => {
+                if let Some(properties) = &self.properties {
+                    properties.remove_conn(&conn.send);
+                }
+                true
+            }
+            None => false,
         }
     }
 
@@ -199,6 +673,140 @@ impl<UserData, UserError: std::fmt::Debug> DispatchConn<UserData, UserError> {
         self.objects.insert(path, handler);
     }
 
+    /// Add a handler for the calls to one interface on the objects matching `path`. It takes precedence over the
+    /// handlers added with `add_handler` for the same objects, calls to other interfaces still reach those.
+    pub fn add_interface_handler(
+        &mut self,
+        path: &str,
+        interface: &str,
+        handler: Box<HandleFn<UserData, UserError>>,
+    ) {
+        self.objects.insert_interface(path, interface, handler);
+        self.try_announce_interface(path, interface);
+    }
+
+    /// Add a handler for the calls to one member of an interface on the objects matching `path`. It takes precedence
+    /// over the handlers added with `add_interface_handler` and `add_handler` for the same objects.
+    pub fn add_member_handler(
+        &mut self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        handler: Box<HandleFn<UserData, UserError>>,
+    ) {
+        self.objects.insert_member(path, interface, member, handler);
+        self.try_announce_interface(path, interface);
+    }
+
+    /// Add a handler that only receives calls from one connection. These are matched before
+    /// the handlers added with `add_handler`.
+    pub fn add_handler_for(
+        &mut self,
+        conn: ConnectionId,
+        path: &str,
+        handler: Box<HandleFn<UserData, UserError>>,
+    ) {
+        self.conn_objects
+            .entry(conn)
+            .or_default()
+            .insert(path, handler);
+    }
+
+    /// Answer the calls to `org.freedesktop.DBus.Properties` for the objects registered in `properties`, see the
+    /// `properties` module. These calls do not reach the handlers. `PropertiesChanged` is emitted on all served
+    /// connections. Replaces properties that were added before.
+    pub fn add_properties(&mut self, properties: Properties) {
+        for conn in self.conns.iter().flatten() {
+            properties.add_conn(conn.send.clone());
+        }
+        self.properties = Some(properties);
+        let handled = self
+            .objects
+            .routes
+            .object_interfaces()
+            .map(|(object, interface)| (object.to_owned(), interface.to_owned()))
+            .collect::<Vec<_>>();
+        for (object, interface) in handled {
+            self.try_announce_interface(&object, &interface);
+        }
+    }
+
+    /// Remove the handlers that were added for the object at `path`, including the ones for its interfaces and members.
+    /// If the DispatchConn has properties, the properties of the object are removed as well and a managed object is
+    /// announced with `InterfacesRemoved`. Returns false if the object had neither handlers nor properties.
+    ///
+    /// `path` has to be the same pattern the handlers were added with. Handlers can also remove objects with
+    /// `HandleEnvironment::remove_object`.
+    pub fn remove_object(&mut self, path: &str) -> Result<bool> {
+        self.remove_handlers(path, None)
+    }
+
+    /// Like `remove_object` but only for the handlers and properties of `interface`
+    pub fn remove_interface(&mut self, path: &str, interface: &str) -> Result<bool> {
+        self.remove_handlers(path, Some(interface))
+    }
+
+    /// By default calls to `org.freedesktop.DBus.Peer` (`Ping` and `GetMachineId`) are answered by the DispatchConn,
+    /// see `peer::peer_reply`, and do not reach the handlers. Turn this off to handle them yourself.
+    pub fn set_answer_peer_calls(&mut self, answer: bool) {
+        self.answer_peer_calls = answer;
+    }
+
+    /// Answer `Close` on the request objects created by `requests`, see the `portal_request` module. These calls do not
+    /// reach the handlers. Replaces requests that were added before.
+    pub fn add_requests(&mut self, requests: Requests) {
+        self.requests = Some(requests);
+    }
+
+    /// Call `callback` every `every`, the first time `every` from now. The interval is measured from the end of the
+    /// previous call, so calls that are late because a handler took long do not pile up.
+    pub fn add_timer(
+        &mut self,
+        every: std::time::Duration,
+        callback: Box<TimerFn<UserData, UserError>>,
+    ) -> TimerId {
+        self.push_timer(std::time::Instant::now() + every, Some(every), callback)
+    }
+
+    /// Call `callback` once at `at`, or as soon as possible if that is in the past
+    pub fn add_timer_at(
+        &mut self,
+        at: std::time::Instant,
+        callback: Box<TimerFn<UserData, UserError>>,
+    ) -> TimerId {
+        self.push_timer(at, None, callback)
+    }
+
+    /// Returns false if the timer was already removed, or was a one-shot timer that was called
+    pub fn remove_timer(&mut self, id: TimerId) -> bool {
+        let len = self.timers.len();
+        self.timers.retain(|timer| timer.id != id);
+        self.timers.len() != len
+    }
+
+    fn push_timer(
+        &mut self,
+        due: std::time::Instant,
+        every: Option<std::time::Duration>,
+        callback: Box<TimerFn<UserData, UserError>>,
+    ) -> TimerId {
+        let id = TimerId(self.next_timer_id);
+        self.next_timer_id += 1;
+        self.timers.push(Timer {
+            id,
+            due,
+            every,
+            callback,
+        });
+        id
+    }
+
+    /// Add a middleware that sees all incoming messages before they are dispatched and all responses
+    /// returned by the handlers. Messages that handlers send themselves over the `HandleEnvironment` do not pass the middlewares.
+    pub fn add_middleware(&mut self, middleware: Box<dyn Middleware>) {
+        self.middleware.push(middleware);
+    }
+
     /// Endless loop that takes messages and dispatches them to the setup
     /// handlers. If any errors occur they will be returned. Depending on the error you may
     /// choose to just call this function again. Note that you are expected to send a meaningful
@@ -206,106 +814,600 @@ impl<UserData, UserError: std::fmt::Debug> DispatchConn<UserData, UserError> {
     ///
     /// This also sends reponses back to the callers, returned by the handlers. If the handlers did
     /// return None, it sends a default response with no content.
+    ///
+    /// If multiple connections are served use `serve` instead, which also tells you which connection the error happened on.
     #[allow(clippy::result_large_err)]
     pub fn run(
         &mut self,
     ) -> std::result::Result<(), (Option<MarshalledMessage>, HandleError<UserError>)> {
+        self.serve().map_err(|(_, msg, err)| (msg, err))
+    }
+
+    /// Like `run` but the error also contains the connection it happened on. If that connection is broken
+    /// you can remove it with `remove_connection` and call this again to keep serving the other ones.
+    ///
+    /// Errors returned by timer callbacks are returned with `ConnectionId::PRIMARY` and without a message.
+    #[allow(clippy::result_large_err)]
+    pub fn serve(&mut self) -> std::result::Result<(), ServeError<UserError>> {
         loop {
-            match self.recv.get_next_message(Timeout::Infinite) {
-                Ok(msg) => {
-                    let mut env = HandleEnvironment {
-                        conn: self.send.clone(),
-                        new_dispatches: PathMatcher::new(),
-                    };
-                    let result = {
-                        if let Some(obj) = &msg.dynheader.object {
-                            if let Some((matches, handler)) = self.objects.get_match(obj) {
-                                handler(&mut self.ctx, matches, &msg, &mut env)
-                            } else {
-                                (self.default_handler)(
-                                    &mut self.ctx,
-                                    Matches::default(),
-                                    &msg,
-                                    &mut env,
-                                )
-                            }
-                        } else {
-                            (self.default_handler)(
-                                &mut self.ctx,
-                                Matches::default(),
-                                &msg,
-                                &mut env,
-                            )
-                        }
-                    };
+            self.run_due_timers()?;
+            // messages that are already buffered would not wake up poll()
+            let buffered = self
+                .conns
+                .iter()
+                .enumerate()
+                .filter(|(_, conn)| {
+                    conn.as_ref().is_some_and(|conn| {
+                        conn.recv.buffer_contains_whole_message().unwrap_or(true)
+                    })
+                })
+                .map(|(idx, _)| ConnectionId(idx))
+                .collect::<Vec<_>>();
+            let ready = if buffered.is_empty() {
+                self.wait_readable()?
+            } else {
+                buffered
+            };
+            for id in ready {
+                let Some(conn) = self.conns.get_mut(id.0).and_then(Option::as_mut) else {
+                    continue;
+                };
+                match conn.recv.get_next_message(Timeout::Nonblock) {
+                    Ok(msg) => self.handle_message(id, msg)?,
+                    // Only part of a message was available, the rest will follow
+                    Err(Error::TimedOut) => {}
+                    Err(error) => return Err((id, None, HandleError::Connection(error))),
+                }
+            }
+        }
+    }
 
-                    if result.is_ok() {
-                        // apply the new pathes established in the handler
-                        for (k, v) in env.new_dispatches.pathes.into_iter() {
-                            self.objects.pathes.insert(k, v);
-                        }
-                    }
-
-                    let mut send_conn = self.send.lock().unwrap();
-
-                    match result {
-                        Ok(Some(response)) => {
-                            let ctx = match send_conn.send_message(&response) {
-                                Ok(ctx) => ctx,
-                                Err(e) => return Err((Some(msg), e.into())),
-                            };
-                            ctx.write_all()
-                                .map_err(|(ctx, e)| ll_conn::force_finish_on_error((ctx, e)))
-                                .map_err(|e| (Some(msg), e.into()))?
-                        }
+    /// Call the timers that are due. Timers added by the callbacks are called in the next round at the earliest.
+    #[allow(clippy::result_large_err)]
+    fn run_due_timers(&mut self) -> std::result::Result<(), ServeError<UserError>> {
+        let now = std::time::Instant::now();
+        let due = self
+            .timers
+            .iter()
+            .filter(|timer| timer.due <= now)
+            .map(|timer| timer.id)
+            .collect::<Vec<_>>();
+        for id in due {
+            // a callback may have removed the timer in the meantime
+            let Some(idx) = self.timers.iter().position(|timer| timer.id == id) else {
+                continue;
+            };
+            let mut timer = self.timers.remove(idx);
+            let mut env = TimerEnvironment {
+                conns: self
+                    .conns
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(idx, conn)| {
+                        conn.as_ref()
+                            .map(|conn| (ConnectionId(idx), conn.send.clone()))
+                    })
+                    .collect(),
+                timer: id,
+                cancel: false,
+            };
+            let result = (timer.callback)(&mut self.ctx, &mut env);
+            if let (Some(every), false) = (timer.every, env.cancel) {
+                timer.due = std::time::Instant::now() + every;
+                self.timers.push(timer);
+            }
+            result.map_err(|e| (ConnectionId::PRIMARY, None, e))?;
+        }
+        Ok(())
+    }
 
-                        Ok(None) => {
-                            let response = msg.dynheader.make_response();
-                            let ctx = match send_conn.send_message(&response) {
-                                Ok(ctx) => ctx,
-                                Err(e) => return Err((Some(msg), e.into())),
-                            };
-                            ctx.write_all()
-                                .map_err(|(ctx, e)| ll_conn::force_finish_on_error((ctx, e)))
-                                .map_err(|e| (Some(msg), e.into()))?
-                        }
-                        Err(error) => return Err((Some(msg), error)),
-                    };
+    /// Blocks until at least one of the connections can be read from or the next timer is due
+    #[allow(clippy::result_large_err)]
+    fn wait_readable(&self) -> std::result::Result<Vec<ConnectionId>, ServeError<UserError>> {
+        use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+        use std::convert::TryFrom;
+        use std::os::fd::AsFd;
+
+        let served = self
+            .conns
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, conn)| conn.as_ref().map(|conn| (ConnectionId(idx), conn)))
+            .collect::<Vec<_>>();
+        if served.is_empty() {
+            return Err((
+                ConnectionId::PRIMARY,
+                None,
+                HandleError::Connection(Error::ConnectionClosed),
+            ));
+        }
+        let mut pollfds = served
+            .iter()
+            .map(|(_, conn)| PollFd::new(conn.recv.as_fd(), PollFlags::POLLIN))
+            .collect::<Vec<_>>();
+
+        let timeout = match self.timers.iter().map(|timer| timer.due).min() {
+            // round up, waking up early would only poll again
+            Some(due) => {
+                let wait = due.saturating_duration_since(std::time::Instant::now());
+                let millis = wait.as_nanos().div_ceil(1_000_000);
+                PollTimeout::try_from(millis).unwrap_or(PollTimeout::MAX)
+            }
+            None => PollTimeout::NONE,
+        };
+        loop {
+            match poll(&mut pollfds, timeout) {
+                Ok(_) => break,
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => {
+                    return Err((
+                        served[0].0,
+                        None,
+                        HandleError::Connection(Error::IoError(e.into())),
+                    ));
+                }
+            }
+        }
+
+        // errors and hangups are reported as readable so reading will surface them
+        Ok(served
+            .iter()
+            .zip(pollfds.iter())
+            .filter(|(_, pollfd)| pollfd.revents().is_some_and(|revents| !revents.is_empty()))
+            .map(|((id, _), _)| *id)
+            .collect())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn handle_message(
+        &mut self,
+        id: ConnectionId,
+        mut msg: MarshalledMessage,
+    ) -> std::result::Result<(), ServeError<UserError>> {
+        let result = match self.middleware.incoming(&mut msg) {
+            IncomingAction::Continue => match missing_call_header(&msg) {
+                Some(error) => Ok(Some(error)),
+                None => self.dispatch(id, &msg),
+            },
+            IncomingAction::Drop => return Ok(()),
+            IncomingAction::Respond(response) => Ok(Some(*response)),
+        };
+
+        let mut response = match result {
+            Ok(Some(response)) => response,
+            Ok(None) => msg.dynheader.make_response(),
+            Err(error) => return Err((id, Some(msg), error)),
+        };
+        if let Err(e) = self.middleware.outgoing(&mut response) {
+            return Err((id, Some(msg), e.into()));
+        }
+
+        let send = match self.send_conn(id) {
+            Ok(send) => send,
+            Err(e) => return Err((id, Some(msg), e)),
+        };
+        let mut send_conn = send.lock().unwrap_or_else(|e| e.into_inner());
+        let ctx = match send_conn.send_message(&response) {
+            Ok(ctx) => ctx,
+            Err(e) => return Err((id, Some(msg), e.into())),
+        };
+        ctx.write_all()
+            .map_err(|(ctx, e)| ll_conn::force_finish_on_error((ctx, e)))
+            .map_err(|e| (id, Some(msg), e.into()))?;
+        Ok(())
+    }
+
+    fn send_conn(
+        &self,
+        id: ConnectionId,
+    ) -> std::result::Result<Arc<Mutex<SendConn>>, HandleError<UserError>> {
+        self.conns
+            .get(id.0)
+            .and_then(Option::as_ref)
+            .map(|conn| conn.send.clone())
+            .ok_or(HandleError::Connection(Error::ConnectionClosed))
+    }
+
+    /// Call the handler matching the object path of the message. Handlers for the connection
+    /// the message came from take precedence over the shared ones.
+    fn dispatch(&mut self, id: ConnectionId, msg: &MarshalledMessage) -> HandleResult<UserError> {
+        if self.answer_peer_calls && msg.typ == crate::message_builder::MessageType::Call {
+            if let Some(response) = crate::peer::peer_reply(&msg.dynheader) {
+                return Ok(Some(response));
+            }
+        }
+        if let Some(properties) = &self.properties {
+            if let Some(response) = properties.handle_call(msg)? {
+                return Ok(Some(response));
+            }
+        }
+        if let Some(response) = self.requests.as_ref().and_then(|r| r.handle_call(msg)) {
+            return Ok(Some(response));
+        }
+        let mut env = HandleEnvironment::new(self.send_conn(id)?, id, msg);
+        let result = {
+            if let Some(obj) = &msg.dynheader.object {
+                let interface = msg.dynheader.interface.as_deref();
+                let member = msg.dynheader.member.as_deref();
+                let conn_match = self
+                    .conn_objects
+                    .get_mut(&id)
+                    .and_then(|objects| objects.get_match_for(obj, interface, member));
+                if let Some((matches, handler)) = conn_match {
+                    handler(&mut self.ctx, matches, msg, &mut env)
+                } else if let Some((matches, handler)) =
+                    self.objects.get_match_for(obj, interface, member)
+                {
+                    handler(&mut self.ctx, matches, msg, &mut env)
+                } else {
+                    (self.default_handler)(&mut self.ctx, Matches::default(), msg, &mut env)
                 }
-                Err(error) => return Err((None, HandleError::Connection(error))),
+            } else {
+                (self.default_handler)(&mut self.ctx, Matches::default(), msg, &mut env)
+            }
+        };
+
+        if result.is_ok() {
+            for (path, interface) in env.removed_dispatches {
+                self.remove_handlers(&path, interface.as_deref())?;
+            }
+            // apply the new pathes established in the handler
+            let added = env
+                .new_dispatches
+                .routes
+                .object_interfaces()
+                .map(|(object, interface)| (object.to_owned(), interface.to_owned()))
+                .collect::<Vec<_>>();
+            env.new_dispatches
+                .routes
+                .move_into(&mut self.objects.routes);
+            for (object, interface) in added {
+                self.announce_interface(&object, &interface)?;
             }
         }
+        result
+    }
+
+    /// Add the interface of a handler to the object manager, see `Properties::set_object_manager`
+    fn announce_interface(&self, object: &str, interface: &str) -> Result<()> {
+        match &self.properties {
+            Some(properties) => properties.add_handled_interface(object, interface),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `announce_interface` for the methods that can not return the error
+    fn try_announce_interface(&self, object: &str, interface: &str) {
+        if let Err(e) = self.announce_interface(object, interface) {
+            log::warn!(
+                "rustbus: could not announce the interface {} of {}: {}",
+                interface,
+                object,
+                e
+            );
+        }
+    }
+
+    fn remove_handlers(&mut self, path: &str, interface: Option<&str>) -> Result<bool> {
+        let mut removed = self.objects.routes.remove(path, interface);
+        if let Some(properties) = &self.properties {
+            removed |= match interface {
+                Some(interface) => properties.remove_interface(path, interface)?,
+                None => properties.remove_object(path)?,
+            };
+        }
+        Ok(removed)
     }
 }
 
+/// Method calls must have an object path and a member. Returns the error reply for calls that lack them.
+pub(super) fn missing_call_header(msg: &MarshalledMessage) -> Option<MarshalledMessage> {
+    use crate::standard_messages::{DBUS_ERROR_UNKNOWN_METHOD, DBUS_ERROR_UNKNOWN_OBJECT};
+
+    if msg.typ != crate::message_builder::MessageType::Call {
+        return None;
+    }
+    if msg.dynheader.object.is_none() {
+        return Some(msg.dynheader.make_error_response(
+            DBUS_ERROR_UNKNOWN_OBJECT,
+            Some("Method call without an object path".to_owned()),
+        ));
+    }
+    if msg.dynheader.member.is_none() {
+        return Some(msg.dynheader.make_error_response(
+            DBUS_ERROR_UNKNOWN_METHOD,
+            Some("Method call without a member".to_owned()),
+        ));
+    }
+    None
+}
+
+#[test]
+fn test_missing_call_header() {
+    use crate::message_builder::MessageBuilder;
+
+    let call = MessageBuilder::new().call("Member").on("/a").build();
+    assert!(missing_call_header(&call).is_none());
+
+    let mut no_member = MessageBuilder::new().call("Member").on("/a").build();
+    no_member.dynheader.member = None;
+    let error = missing_call_header(&no_member).unwrap();
+    assert_eq!(
+        error.dynheader.error_name.as_deref(),
+        Some(crate::standard_messages::DBUS_ERROR_UNKNOWN_METHOD)
+    );
+
+    let mut no_object = MessageBuilder::new().call("Member").on("/a").build();
+    no_object.dynheader.object = None;
+    let error = missing_call_header(&no_object).unwrap();
+    assert_eq!(
+        error.dynheader.error_name.as_deref(),
+        Some(crate::standard_messages::DBUS_ERROR_UNKNOWN_OBJECT)
+    );
+
+    // signals are not answered
+    let mut signal = MessageBuilder::new()
+        .signal("io.killing.spark", "Sig", "/a")
+        .build();
+    signal.dynheader.member = None;
+    assert!(missing_call_header(&signal).is_none());
+}
+
+#[test]
+fn test_timers() {
+    use crate::message_builder::MessageBuilder;
+    use std::time::{Duration, Instant};
+
+    #[derive(Default)]
+    struct Counts {
+        repeating: u32,
+        removed: u32,
+    }
+
+    let (service, mut client) = DuplexConn::pair().unwrap();
+    std::thread::spawn(move || {
+        let mut dpcon = DispatchConn::<Counts, ()>::new(
+            service,
+            Counts::default(),
+            Box::new(|ctx: &mut Counts, _, msg: &MarshalledMessage, _| {
+                let mut resp = msg.dynheader.make_response();
+                resp.body.push_param((ctx.repeating, ctx.removed))?;
+                Ok(Some(resp))
+            }),
+        );
+        dpcon.add_timer(
+            Duration::from_millis(1),
+            Box::new(|ctx: &mut Counts, env: &mut TimerEnvironment| {
+                ctx.repeating += 1;
+                if ctx.repeating == 3 {
+                    env.cancel();
+                }
+                Ok(())
+            }),
+        );
+        let removed = dpcon.add_timer(
+            Duration::from_millis(1),
+            Box::new(|ctx: &mut Counts, _: &mut TimerEnvironment| {
+                ctx.removed += 1;
+                Ok(())
+            }),
+        );
+        assert!(dpcon.remove_timer(removed));
+        assert!(!dpcon.remove_timer(removed));
+        // fires while no messages arrive and tells the client that the repeating timer is done
+        dpcon.add_timer_at(
+            Instant::now() + Duration::from_millis(20),
+            Box::new(|_: &mut Counts, env: &mut TimerEnvironment| {
+                let signal = MessageBuilder::new()
+                    .signal("io.killing.spark", "Expired", "/")
+                    .build();
+                let mut conn = env.conns[0].1.lock().unwrap();
+                conn.send_message(&signal)?
+                    .write_all()
+                    .map_err(|(_, e)| e)?;
+                Ok(())
+            }),
+        );
+        let _ = dpcon.run();
+    });
+
+    let signal = client.recv.get_next_message(Timeout::Infinite).unwrap();
+    assert_eq!(signal.dynheader.member.as_deref(), Some("Expired"));
+    let call = MessageBuilder::new().call("Count").on("/").build();
+    let serial = client
+        .send
+        .send_message(&call)
+        .unwrap()
+        .write_all()
+        .unwrap();
+    let resp = client.recv.get_next_message(Timeout::Infinite).unwrap();
+    assert_eq!(resp.dynheader.response_serial, Some(serial));
+    assert_eq!(resp.body.parser().get::<(u32, u32)>().unwrap(), (3, 0));
+}
+
+#[test]
+fn test_answer_peer_calls() {
+    use crate::message_builder::{MessageBuilder, MessageType};
+    use crate::standard_messages::unknown_method;
+    use crate::RpcConn;
+
+    let serve = |answer: bool| {
+        let (service, client) = DuplexConn::pair().unwrap();
+        std::thread::spawn(move || {
+            let mut dpcon = DispatchConn::<(), ()>::new(
+                service,
+                (),
+                Box::new(|_, _, msg: &MarshalledMessage, _| {
+                    Ok(Some(unknown_method(&msg.dynheader)))
+                }),
+            );
+            dpcon.set_answer_peer_calls(answer);
+            let _ = dpcon.run();
+        });
+        RpcConn::new(client)
+    };
+    let mut ping = MessageBuilder::new()
+        .call("Ping")
+        .with_interface(crate::peer::PEER_INTERFACE)
+        .on("/")
+        .build();
+
+    let mut client = serve(true);
+    let pong = client.call_method(&mut ping, Timeout::Infinite).unwrap();
+    assert_eq!(pong.typ, MessageType::Reply);
+
+    // the call reaches the default handler
+    let mut client = serve(false);
+    let pong = client.call_method(&mut ping, Timeout::Infinite).unwrap();
+    assert_eq!(pong.typ, MessageType::Error);
+}
+
+#[test]
+fn test_interface_handlers() {
+    use crate::message_builder::MessageBuilder;
+    use crate::RpcConn;
+
+    fn answer(name: &'static str) -> Box<HandleFn<(), ()>> {
+        Box::new(move |_, _, msg: &MarshalledMessage, _| {
+            let mut resp = msg.dynheader.make_response();
+            resp.body.push_param(name)?;
+            Ok(Some(resp))
+        })
+    }
+
+    let (service, client) = DuplexConn::pair().unwrap();
+    std::thread::spawn(move || {
+        let mut dpcon = DispatchConn::new(service, (), answer("default"));
+        dpcon.add_handler("/speaker/:id", answer("object"));
+        dpcon.add_interface_handler(
+            "/speaker/:id",
+            "io.killing.spark.Speaker",
+            answer("interface"),
+        );
+        dpcon.add_member_handler(
+            "/speaker/:id",
+            "io.killing.spark.Speaker",
+            "Mute",
+            answer("member"),
+        );
+        dpcon.add_member_handler(
+            "/other",
+            "io.killing.spark.Speaker",
+            "Mute",
+            answer("other"),
+        );
+        let _ = dpcon.run();
+    });
+
+    let mut client = RpcConn::new(client);
+    let mut call = |object: &str, interface: Option<&str>, member: &str| {
+        let mut builder = MessageBuilder::new().call(member).on(object);
+        if let Some(interface) = interface {
+            builder = builder.with_interface(interface);
+        }
+        let resp = client
+            .call_method(&mut builder.build(), Timeout::Infinite)
+            .unwrap();
+        resp.body.parser().get::<String>().unwrap()
+    };
+    let speaker = Some("io.killing.spark.Speaker");
+    assert_eq!(call("/speaker/1", speaker, "Mute"), "member");
+    assert_eq!(call("/speaker/1", speaker, "Unmute"), "interface");
+    assert_eq!(
+        call("/speaker/1", Some("io.killing.spark.Other"), "Mute"),
+        "object"
+    );
+    assert_eq!(call("/speaker/1", None, "Mute"), "object");
+    assert_eq!(call("/other", speaker, "Mute"), "other");
+    assert_eq!(call("/other", speaker, "Unmute"), "default");
+}
+
 #[test]
 fn test_path_matcher() {
-    let pattern = ObjectPathPattern::new("/ABCD/:1/:2/:3/DEF");
+    let table = |pattern: &str| {
+        let mut table = RouteTable::default();
+        table.insert(HandlerKey::new(pattern, None, None), ());
+        table
+    };
+    let pattern = table("/ABCD/:1/:2/:3/DEF");
 
     // happy path, just to be sure...
-    let matches = pattern.matches("/ABCD/A/B/C/DEF").unwrap();
+    let (matches, _) = pattern.get("/ABCD/A/B/C/DEF", None, None).unwrap();
     assert_eq!(matches.matches.get(":1").unwrap(), "A");
     assert_eq!(matches.matches.get(":2").unwrap(), "B");
     assert_eq!(matches.matches.get(":3").unwrap(), "C");
 
     // These are too short
-    assert!(pattern.matches("ABCD/A").is_none());
-    assert!(pattern.matches("ABCD/A/B").is_none());
-    assert!(pattern.matches("ABCD/A/B/C").is_none());
+    assert!(pattern.get("ABCD/A", None, None).is_none());
+    assert!(pattern.get("ABCD/A/B", None, None).is_none());
+    assert!(pattern.get("ABCD/A/B/C", None, None).is_none());
 
     // This is too long
-    assert!(pattern.matches("ABCD/A/B/C/DEF/GHI").is_none());
+    assert!(pattern.get("ABCD/A/B/C/DEF/GHI", None, None).is_none());
 
     // Test some wildcard stuff
-    let pattern = ObjectPathPattern::new("/ABCD/:1/:2/:3/DEF/*");
+    let pattern = table("/ABCD/:1/:2/:3/DEF/*");
     // One at the end is fine
-    assert!(pattern.matches("/ABCD/A/B/C/DEF/GHI").is_some());
+    assert!(pattern.get("/ABCD/A/B/C/DEF/GHI", None, None).is_some());
     // Multiple at the end are fine
-    assert!(pattern.matches("/ABCD/A/B/C/DEF/GHI/JKLMN").is_some());
+    assert!(pattern
+        .get("/ABCD/A/B/C/DEF/GHI/JKLMN", None, None)
+        .is_some());
 
-    let pattern = ObjectPathPattern::new("/ABCD/*/:1/:2/:3/DEF");
+    let pattern = table("/ABCD/*/:1/:2/:3/DEF");
     // One in the middle is fine
-    assert!(pattern.matches("/ABCD/WILD/A/B/C/DEF").is_some());
+    assert!(pattern.get("/ABCD/WILD/A/B/C/DEF", None, None).is_some());
     // Multiple in the middle are not fine
-    assert!(pattern.matches("/ABCD/TOO/WILD/A/B/C/DEF").is_none());
+    assert!(pattern
+        .get("/ABCD/TOO/WILD/A/B/C/DEF", None, None)
+        .is_none());
+}
+
+#[test]
+fn test_route_table() {
+    let mut table = RouteTable::default();
+    for (pattern, interface, member, name) in [
+        ("/devices/*", None, None, "all devices"),
+        ("/devices/:id", None, None, "device"),
+        ("/devices/disk", None, None, "disk"),
+        ("/devices/:id/*", None, None, "device children"),
+        ("/devices/*", Some("io.killing.spark.Power"), None, "power"),
+        ("/", None, None, "root"),
+    ] {
+        table.insert(HandlerKey::new(pattern, interface, member), name);
+    }
+    let lookup = |path: &str, interface: Option<&str>| {
+        table
+            .get(path, interface, Some("Member"))
+            .map(|(matches, name)| (*name, matches.matches.get(":id").cloned()))
+    };
+
+    // exact segments win over captures, captures over wildcards
+    assert_eq!(lookup("/devices/disk", None), Some(("disk", None)));
+    assert_eq!(
+        lookup("/devices/usb1", None),
+        Some(("device", Some("usb1".to_owned())))
+    );
+    assert_eq!(
+        lookup("/devices/usb1/port2/hub", None),
+        Some(("device children", Some("usb1".to_owned())))
+    );
+    // a handler for the interface is more specific than one for the object, wherever it is
+    assert_eq!(
+        lookup("/devices/disk", Some("io.killing.spark.Power")),
+        Some(("power", None))
+    );
+    assert_eq!(lookup("/", None), Some(("root", None)));
+    assert_eq!(lookup("/devices", None), None);
+    assert_eq!(lookup("/other/disk", None), None);
+
+    // replacing a handler keeps the others
+    table.insert(HandlerKey::new("/devices/disk", None, None), "new disk");
+    assert_eq!(
+        table
+            .get("/devices/disk", None, None)
+            .map(|(_, name)| *name),
+        Some("new disk")
+    );
+    assert_eq!(table.routes.len(), 6);
 }
@@ -0,0 +1,445 @@
+//! Call methods of remote objects that are only known at runtime
+//!
+//! The proxy fetches the introspection data of an object once and then checks every call against it before it is sent.
+//! Arguments and return values are the dynamic `Param` values from the params module, so this is meant for REPLs, test
+//! harnesses and similar tools where generating code for the interfaces is not practical.
+//!
+//! ```rust,no_run
+//! use rustbus::connection::dynamic_proxy::DynamicProxy;
+//! use rustbus::{connection::Timeout, RpcConn};
+//!
+//! let mut rpc_con = RpcConn::session_conn(Timeout::Infinite).unwrap();
+//! let mut proxy = DynamicProxy::new(
+//!     &mut rpc_con,
+//!     "org.freedesktop.DBus",
+//!     "/org/freedesktop/DBus",
+//!     Timeout::Infinite,
+//! )
+//! .unwrap();
+//! let owner = proxy
+//!     .call("GetNameOwner", &["org.freedesktop.DBus".into()])
+//!     .unwrap();
+//! println!("{:?}", owner);
+//! ```
+
+use thiserror::Error;
+
+use super::rpc_conn::RpcConn;
+use super::Timeout;
+use crate::message_builder::{ErrorReply, MarshalledMessage, MessageBuilder};
+use crate::params::Param;
+use crate::wire::errors::{MarshalError, UnmarshalError};
+
+use crate::consts::INTROSPECTABLE_INTERFACE;
+
+/// Errors that can occur when creating or using a DynamicProxy
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("An error occured on the connection: {0}")]
+    Connection(#[from] super::Error),
+    #[error("An error occured while marshalling: {0}")]
+    Marshal(#[from] MarshalError),
+    #[error("An error occured while unmarshalling: {0}")]
+    Unmarshal(#[from] UnmarshalError),
+    #[error("The introspection data could not be parsed: {0}")]
+    InvalidIntrospection(String),
+    #[error("The object has no method called {0}")]
+    UnknownMethod(String),
+    #[error("The method {0} exists on multiple interfaces, qualify it with the interface name")]
+    AmbiguousMethod(String),
+    #[error("Wrong arguments for {method}: expected signature '{expected}' but got '{found}'")]
+    ArgumentMismatch {
+        method: String,
+        expected: String,
+        found: String,
+    },
+    #[error("The reply to {method} has signature '{found}' but the introspection data said '{expected}'")]
+    ReplyMismatch {
+        method: String,
+        expected: String,
+        found: String,
+    },
+    #[error("The call returned the error {0}")]
+    ErrorReply(ErrorReply),
+}
+
+/// A method as described by the introspection data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Method {
+    pub name: String,
+    /// Signature of all arguments with direction "in"
+    pub in_sig: String,
+    /// Signature of all arguments with direction "out"
+    pub out_sig: String,
+    pub in_args: Vec<Arg>,
+    pub out_args: Vec<Arg>,
+}
+
+/// An argument of a method as described by the introspection data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arg {
+    /// Arguments do not need to have a name
+    pub name: Option<String>,
+    pub sig: String,
+}
+
+/// An interface as described by the introspection data. Signals and properties are not needed to make calls and are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub methods: Vec<Method>,
+}
+
+/// Parse the XML returned by `org.freedesktop.DBus.Introspectable.Introspect`. Only the interfaces of the node itself are
+/// returned, child nodes are ignored. The signatures of all arguments are validated.
+pub fn parse_introspection(xml: &str) -> Result<Vec<Interface>, ProxyError> {
+    // introspection data usually starts with a DOCTYPE, which roxmltree rejects by default
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let doc = roxmltree::Document::parse_with_options(xml, options)
+        .map_err(|e| ProxyError::InvalidIntrospection(e.to_string()))?;
+    let root = doc.root_element();
+    if !root.has_tag_name("node") {
+        return Err(ProxyError::InvalidIntrospection(format!(
+            "expected a node element but found {}",
+            root.tag_name().name()
+        )));
+    }
+
+    let mut interfaces = Vec::new();
+    for iface in root.children().filter(|n| n.has_tag_name("interface")) {
+        let name = required_attribute(&iface, "name")?;
+        let mut methods = Vec::new();
+        for method in iface.children().filter(|n| n.has_tag_name("method")) {
+            let mut in_sig = String::new();
+            let mut out_sig = String::new();
+            let mut in_args = Vec::new();
+            let mut out_args = Vec::new();
+            for arg in method.children().filter(|n| n.has_tag_name("arg")) {
+                let typ = required_attribute(&arg, "type")?;
+                let parsed = Arg {
+                    name: arg.attribute("name").map(str::to_owned),
+                    sig: typ.to_owned(),
+                };
+                match arg.attribute("direction").unwrap_or("in") {
+                    "in" => {
+                        in_sig.push_str(typ);
+                        in_args.push(parsed);
+                    }
+                    "out" => {
+                        out_sig.push_str(typ);
+                        out_args.push(parsed);
+                    }
+                    other => {
+                        return Err(ProxyError::InvalidIntrospection(format!(
+                            "unknown argument direction {}",
+                            other
+                        )))
+                    }
+                }
+            }
+            for sig in [&in_sig, &out_sig].iter().filter(|sig| !sig.is_empty()) {
+                crate::signature::Type::parse_description(sig)
+                    .map_err(|e| ProxyError::InvalidIntrospection(e.to_string()))?;
+            }
+            methods.push(Method {
+                name: required_attribute(&method, "name")?.to_owned(),
+                in_sig,
+                out_sig,
+                in_args,
+                out_args,
+            });
+        }
+        interfaces.push(Interface {
+            name: name.to_owned(),
+            methods,
+        });
+    }
+    Ok(interfaces)
+}
+
+fn required_attribute<'a>(
+    node: &roxmltree::Node<'a, '_>,
+    attribute: &str,
+) -> Result<&'a str, ProxyError> {
+    node.attribute(attribute).ok_or_else(|| {
+        ProxyError::InvalidIntrospection(format!(
+            "{} element without {} attribute",
+            node.tag_name().name(),
+            attribute
+        ))
+    })
+}
+
+/// Makes calls to one object of one peer on the bus, checking the arguments against the introspection data
+pub struct DynamicProxy<'a> {
+    conn: &'a mut RpcConn,
+    destination: String,
+    object: String,
+    interfaces: Vec<Interface>,
+    timeout: Timeout,
+}
+
+impl<'a> DynamicProxy<'a> {
+    /// Introspect `object` at `destination` and create a proxy for it. The timeout is used for the introspection and
+    /// for all calls made through the proxy, it can be changed with `set_timeout`.
+    pub fn new<D: Into<String>, O: Into<String>>(
+        conn: &'a mut RpcConn,
+        destination: D,
+        object: O,
+        timeout: Timeout,
+    ) -> Result<Self, ProxyError> {
+        let destination = destination.into();
+        let object = object.into();
+        let mut call = MessageBuilder::new()
+            .call("Introspect")
+            .with_interface(INTROSPECTABLE_INTERFACE)
+            .on(object.clone())
+            .at(destination.clone())
+            .build();
+        let reply = check_error_reply(conn.call_method(&mut call, timeout)?)?;
+        let xml: &str = reply.body.parser().get()?;
+        let interfaces = parse_introspection(xml)?;
+        Ok(Self::from_interfaces(
+            conn,
+            destination,
+            object,
+            interfaces,
+            timeout,
+        ))
+    }
+
+    /// Create a proxy from introspection data you already have, e.g. from a previous `new` or a file
+    pub fn from_interfaces<D: Into<String>, O: Into<String>>(
+        conn: &'a mut RpcConn,
+        destination: D,
+        object: O,
+        interfaces: Vec<Interface>,
+        timeout: Timeout,
+    ) -> Self {
+        Self {
+            conn,
+            destination: destination.into(),
+            object: object.into(),
+            interfaces,
+            timeout,
+        }
+    }
+
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    pub fn set_timeout(&mut self, timeout: Timeout) {
+        self.timeout = timeout;
+    }
+
+    /// Call a method and return the values of the reply. `method` is either just the name of the method, if it only exists on
+    /// one of the interfaces, or the name prefixed with the interface like `org.freedesktop.DBus.Peer.Ping`.
+    pub fn call(
+        &mut self,
+        method: &str,
+        args: &[Param],
+    ) -> Result<Vec<Param<'static, 'static>>, ProxyError> {
+        let (interface, method) = find_method(&self.interfaces, method)?;
+        let mut call = make_call(&self.destination, &self.object, interface, method, args)?;
+        let reply = check_error_reply(self.conn.call_method(&mut call, self.timeout)?)?;
+        check_reply(method, reply)
+    }
+}
+
+/// Find the method, either by its qualified name or by searching all interfaces
+fn find_method<'i>(
+    interfaces: &'i [Interface],
+    name: &str,
+) -> Result<(&'i str, &'i Method), ProxyError> {
+    let mut candidates = interfaces.iter().flat_map(|iface| {
+        iface
+            .methods
+            .iter()
+            .filter(move |method| match name.rsplit_once('.') {
+                Some((iface_name, method_name)) => {
+                    iface.name == iface_name && method.name == method_name
+                }
+                None => method.name == name,
+            })
+            .map(move |method| (iface.name.as_str(), method))
+    });
+    let found = candidates
+        .next()
+        .ok_or_else(|| ProxyError::UnknownMethod(name.to_owned()))?;
+    if candidates.next().is_some() {
+        return Err(ProxyError::AmbiguousMethod(name.to_owned()));
+    }
+    Ok(found)
+}
+
+fn make_call(
+    destination: &str,
+    object: &str,
+    interface: &str,
+    method: &Method,
+    args: &[Param],
+) -> Result<MarshalledMessage, ProxyError> {
+    let mut found = String::new();
+    for arg in args {
+        arg.sig().to_str(&mut found);
+    }
+    if found != method.in_sig {
+        return Err(ProxyError::ArgumentMismatch {
+            method: method.name.clone(),
+            expected: method.in_sig.clone(),
+            found,
+        });
+    }
+
+    let mut call = MessageBuilder::new()
+        .call(method.name.clone())
+        .with_interface(interface)
+        .on(object)
+        .at(destination)
+        .build();
+    call.body.push_old_params(args)?;
+    Ok(call)
+}
+
+fn check_error_reply(reply: MarshalledMessage) -> Result<MarshalledMessage, ProxyError> {
+    if let Some(error) = reply.error_reply() {
+        return Err(ProxyError::ErrorReply(error));
+    }
+    Ok(reply)
+}
+
+fn check_reply(
+    method: &Method,
+    reply: MarshalledMessage,
+) -> Result<Vec<Param<'static, 'static>>, ProxyError> {
+    if reply.get_sig() != method.out_sig {
+        return Err(ProxyError::ReplyMismatch {
+            method: method.name.clone(),
+            expected: method.out_sig.clone(),
+            found: reply.get_sig().to_owned(),
+        });
+    }
+    Ok(reply.unmarshall_all()?.params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="io.killing.spark.Test">
+    <method name="Echo">
+      <arg name="text" type="s" direction="in"/>
+      <arg name="times" type="u"/>
+      <arg name="echoed" type="as" direction="out"/>
+    </method>
+    <method name="Ping"/>
+    <signal name="Pinged"><arg type="s"/></signal>
+    <property name="Name" type="s" access="read"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+  <node name="child"/>
+</node>"#;
+
+    #[test]
+    fn test_parse_introspection() {
+        let interfaces = parse_introspection(XML).unwrap();
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(interfaces[0].name, "io.killing.spark.Test");
+        assert_eq!(
+            interfaces[0].methods[0],
+            Method {
+                name: "Echo".into(),
+                in_sig: "su".into(),
+                out_sig: "as".into(),
+                in_args: vec![
+                    Arg {
+                        name: Some("text".into()),
+                        sig: "s".into(),
+                    },
+                    Arg {
+                        name: Some("times".into()),
+                        sig: "u".into(),
+                    },
+                ],
+                out_args: vec![Arg {
+                    name: Some("echoed".into()),
+                    sig: "as".into(),
+                }],
+            }
+        );
+
+        assert!(matches!(
+            parse_introspection("<node><interface><method name=\"A\"/></interface></node>"),
+            Err(ProxyError::InvalidIntrospection(_))
+        ));
+        assert!(matches!(
+            parse_introspection(
+                "<node><interface name=\"a.b\"><method name=\"A\"><arg type=\"a\"/></method></interface></node>"
+            ),
+            Err(ProxyError::InvalidIntrospection(_))
+        ));
+    }
+
+    #[test]
+    fn test_find_method_and_check_args() {
+        let interfaces = parse_introspection(XML).unwrap();
+
+        assert!(matches!(
+            find_method(&interfaces, "Ping"),
+            Err(ProxyError::AmbiguousMethod(_))
+        ));
+        assert!(matches!(
+            find_method(&interfaces, "Pinged"),
+            Err(ProxyError::UnknownMethod(_))
+        ));
+        let (iface, _) = find_method(&interfaces, "org.freedesktop.DBus.Peer.Ping").unwrap();
+        assert_eq!(iface, "org.freedesktop.DBus.Peer");
+
+        let (iface, echo) = find_method(&interfaces, "Echo").unwrap();
+        let call = make_call(
+            "io.killing.spark",
+            "/",
+            iface,
+            echo,
+            &["text".into(), 2u32.into()],
+        )
+        .unwrap();
+        assert_eq!(call.get_sig(), "su");
+        assert_eq!(call.dynheader.member.as_deref(), Some("Echo"));
+        assert_eq!(call.dynheader.interface.as_deref(), Some(iface));
+
+        assert!(matches!(
+            make_call("io.killing.spark", "/", iface, echo, &["text".into()]),
+            Err(ProxyError::ArgumentMismatch { .. })
+        ));
+
+        let mut reply = call.dynheader.make_response();
+        reply.body.push_param(["a", "b"].as_slice()).unwrap();
+        let values = check_reply(echo, reply).unwrap();
+        assert_eq!(values.len(), 1);
+        assert!(matches!(
+            check_reply(echo, call.dynheader.make_response()),
+            Err(ProxyError::ReplyMismatch { .. })
+        ));
+
+        let error = call
+            .dynheader
+            .make_error_response("io.killing.spark.Error", Some("nope".into()));
+        match check_error_reply(error) {
+            Err(ProxyError::ErrorReply(ErrorReply { name, message })) => {
+                assert_eq!(name, "io.killing.spark.Error");
+                assert_eq!(message.as_deref(), Some("nope"));
+            }
+            other => panic!("expected an error reply, got {:?}", other.map(|_| ())),
+        }
+    }
+}
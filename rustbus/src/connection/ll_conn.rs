@@ -1,50 +1,244 @@
-use super::{Error, Result, Timeout};
+use super::{Deadline, Error, Result, Timeout};
 use crate::auth;
-use crate::message_builder::MarshalledMessage;
-use crate::wire::errors::UnmarshalError;
+use crate::message_builder::{MarshalledMessage, MessagePool};
+use crate::wire::errors::{MarshalError, UnmarshalError};
 use crate::wire::{marshal, unmarshal, UnixFd};
 
+use std::collections::VecDeque;
 use std::io::{self, IoSlice, IoSliceMut};
 use std::num::NonZeroU32;
-use std::os::fd::AsFd;
-use std::time;
+use std::os::fd::{AsFd, BorrowedFd};
 
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::RawFd;
-use std::os::unix::net::UnixStream;
+use std::os::unix::net::{UnixListener, UnixStream};
 
 use nix::cmsg_space;
 use nix::sys::socket::{
-    self, connect, recvmsg, sendmsg, socket, ControlMessage, ControlMessageOwned, MsgFlags,
-    SockaddrStorage, UnixAddr,
+    recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags, SockaddrStorage, UnixAddr,
 };
 
 use crate::wire::unmarshal_context::Cursor;
 
 /// A lowlevel abstraction over the raw unix socket
+///
+/// The sending half of a `DuplexConn`. It owns its own duplicate of the socket and all state it needs to send (the
+/// serial counter, the queued messages and buffers), nothing is shared with the `RecvConn`. So both halves can be used
+/// from different threads at the same time, see `DuplexConn::split`.
 #[derive(Debug)]
 pub struct SendConn {
     stream: UnixStream,
     header_buf: Vec<u8>,
+    max_message_size: usize,
 
     serial_counter: NonZeroU32,
+    /// Messages queued with `queue_message` that have not been written completely
+    queued: VecDeque<QueuedMessage>,
+    pool: MessagePool,
+    /// Set when a message could only be written partially, any further bytes would end up in the middle of it
+    broken: bool,
+}
+
+/// A marshalled message waiting in the queue of a SendConn. It owns its header and body, so the original message can be
+/// dropped while it waits. Header and body are written as separate slices, the body is never copied into the header.
+#[derive(Debug)]
+struct QueuedMessage {
+    header: Vec<u8>,
+    body: QueuedBody,
+    bytes_sent: usize,
+}
+
+#[derive(Debug)]
+enum QueuedBody {
+    /// Copied from a message that was only borrowed by `queue_message`
+    Copied { buf: Vec<u8>, fds: Vec<UnixFd> },
+    /// Moved into the queue by `queue_message_owned`
+    Owned(Box<MarshalledMessage>),
 }
 
+#[cfg(feature = "zeroize")]
+impl SendConn {
+    /// Overwrite the header buffer and the queued copies with zeros
+    fn scrub(&mut self) {
+        crate::wire::util::scrub(&mut self.header_buf);
+        self.queued.iter_mut().for_each(QueuedMessage::scrub);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for SendConn {
+    fn drop(&mut self) {
+        self.scrub();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl QueuedMessage {
+    fn scrub(&mut self) {
+        crate::wire::util::scrub(&mut self.header);
+        // owned messages scrub their body themselves when they are dropped
+        if let QueuedBody::Copied { buf, .. } = &mut self.body {
+            crate::wire::util::scrub(buf);
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for QueuedMessage {
+    fn drop(&mut self) {
+        self.scrub();
+    }
+}
+
+impl QueuedMessage {
+    fn body(&self) -> &[u8] {
+        match &self.body {
+            QueuedBody::Copied { buf, .. } => buf,
+            QueuedBody::Owned(msg) => msg.get_buf(),
+        }
+    }
+
+    fn fds(&self) -> &[UnixFd] {
+        match &self.body {
+            QueuedBody::Copied { fds, .. } => fds,
+            QueuedBody::Owned(msg) => msg.body.get_fds(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.header.len() + self.body().len()
+    }
+
+    /// The parts of the header and the body that are not written yet
+    fn unsent(&self) -> impl Iterator<Item = IoSlice<'_>> {
+        let header_sent = usize::min(self.bytes_sent, self.header.len());
+        let body_sent = self.bytes_sent - header_sent;
+        std::iter::once(&self.header[header_sent..])
+            .chain(std::iter::once(&self.body()[body_sent..]))
+            .filter(|slice| !slice.is_empty())
+            .map(IoSlice::new)
+    }
+}
+
+/// How many bytes `SendConn::send_message_with_byte_stream` reads and writes at once
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How many slices `SendConn::write_next_chunk` writes with one syscall at most, each queued message takes two
+const MAX_WRITE_SLICES: usize = 128;
+
+/// What `SendConn::write_next_chunk` achieved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteProgress {
+    /// This many bytes were written and more are queued
+    Written(usize),
+    /// The socket can not take any bytes right now. Wait until it is writable again, e.g. with epoll.
+    WouldBlock,
+    /// All queued messages have been written
+    Done,
+}
+
+/// The socket readiness a connection waits for, for registering its fd in event loops like mio, calloop or epoll
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReadinessHint {
+    /// Wait for the socket to become readable, then read with `RecvConn::read_once(Timeout::Nonblock)`
+    pub read: bool,
+    /// Wait for the socket to become writable, then call `SendConn::write_next_chunk`
+    pub write: bool,
+    /// A whole message is buffered already. It does not make the socket readable, so take it with
+    /// `RecvConn::get_next_message(Timeout::Nonblock)` before waiting.
+    pub message_buffered: bool,
+}
+
+/// The receiving half of a `DuplexConn`. Like the `SendConn` it owns its own duplicate of the socket and its buffers,
+/// so it can block in `get_next_message` on one thread while the `SendConn` sends on another one.
 pub struct RecvConn {
     stream: UnixStream,
 
     msg_buf_in: IncomingBuffer,
     fds_in: Vec<UnixFd>,
     cmsgspace: Vec<u8>,
+    strict_message_bounds: bool,
+    allow_missing_signature: bool,
+    relaxed_booleans: bool,
+    sanitized_booleans: u64,
+    unknown_header_fields: Option<Box<UnknownHeaderFieldFn>>,
+    skip_invalid_messages: bool,
+    protocol_violations: u64,
+    /// A violation was reported for the message at the start of the buffer, which could not be skipped
+    stuck_violation_reported: bool,
+}
+
+/// A message that violates the dbus protocol, see `RecvConn::set_skip_invalid_messages`
+#[derive(Debug)]
+pub struct ProtocolViolation {
+    /// What is wrong with the message
+    pub error: UnmarshalError,
+    /// The sender header field, if the header could be parsed that far. On a bus connection this is the unique name of
+    /// the peer that sent the message.
+    pub sender: Option<String>,
+    pub serial: Option<NonZeroU32>,
+    /// The process at the other end of the socket. On a bus connection that is the bus, on a peer to peer connection
+    /// the peer itself. Only available on linux.
+    pub peer_pid: Option<i32>,
+    pub peer_uid: Option<u32>,
+    /// Whether the message was skipped. Then the next message can be received as usual. Otherwise the length of the
+    /// message is unknown, the stream can not be resynchronized and the connection has to be closed.
+    pub skipped: bool,
 }
 
+impl std::fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Protocol violation")?;
+        if let Some(sender) = &self.sender {
+            write!(f, " by {}", sender)?;
+        }
+        if let Some(serial) = self.serial {
+            write!(f, " in message {}", serial)?;
+        }
+        if let Some(pid) = self.peer_pid {
+            write!(f, " (socket peer pid {})", pid)?;
+        }
+        write!(f, ": {}", self.error)?;
+        if self.skipped {
+            write!(f, ", the message was skipped")?;
+        }
+        Ok(())
+    }
+}
+
+/// The length of the message at the start of `buf` according to the length fields in its header, without validating
+/// anything else. Lets messages with otherwise broken headers be skipped. None if not even the byteorder is valid.
+fn raw_message_len(buf: &[u8]) -> Option<usize> {
+    let byteorder = match buf.first()? {
+        b'l' => crate::ByteOrder::LittleEndian,
+        b'B' => crate::ByteOrder::BigEndian,
+        _ => return None,
+    };
+    let read_len = |at: usize| {
+        let mut cursor = Cursor::new(buf.get(at..at + 4)?);
+        cursor.read_u32(byteorder).ok().map(|len| len as usize)
+    };
+    let body_len = read_len(4)?;
+    let header_fields_len = read_len(unmarshal::HEADER_LEN)?;
+    let len = (unmarshal::HEADER_LEN + 4 + header_fields_len).next_multiple_of(8) + body_len;
+    (len <= unmarshal::MAX_MESSAGE_LEN).then_some(len)
+}
+
+/// Called with the header fields that `RecvConn` skips because their code is unknown, see
+/// `RecvConn::set_unknown_header_field_handler`
+pub type UnknownHeaderFieldFn = dyn FnMut(crate::wire::UnknownHeaderField) + Send + Sync;
+
+/// Both halves of a connection. The socket is only closed once both halves are dropped.
 pub struct DuplexConn {
     pub send: SendConn,
     pub recv: RecvConn,
 }
 
+/// Bytes read from the socket. The unread bytes are `buf[start..filled]`, taking a message from the front only moves
+/// `start` so taking many small messages that were read at once does not shift the rest each time.
 struct IncomingBuffer {
     buf: Vec<u8>,
+    start: usize,
     filled: usize,
 }
 
@@ -52,13 +246,35 @@ impl IncomingBuffer {
     fn new() -> Self {
         IncomingBuffer {
             buf: Vec::new(),
+            start: 0,
             filled: 0,
         }
     }
 
+    /// Move the unread bytes to the front of the buffer
+    fn compact(&mut self) {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.filled, 0);
+            self.filled -= self.start;
+            self.start = 0;
+        }
+    }
+
+    /// Make room for exactly `new_len` unread bytes, so the next read does not read past that
     fn reserve(&mut self, new_len: usize) {
+        self.compact();
         if self.buf.len() < new_len {
+            // do the reallocation here so the old allocation can be scrubbed before it is freed
+            #[cfg(feature = "zeroize")]
+            if self.buf.capacity() < new_len {
+                let mut new_buf = Vec::with_capacity(new_len);
+                new_buf.extend_from_slice(&self.buf);
+                let mut old_buf = std::mem::replace(&mut self.buf, new_buf);
+                crate::wire::util::scrub(&mut old_buf);
+            }
             self.buf.resize(new_len, 0);
+        } else {
+            self.buf.truncate(usize::max(new_len, self.filled));
         }
     }
 
@@ -74,17 +290,37 @@ impl IncomingBuffer {
     }
 
     fn len(&self) -> usize {
-        self.filled
+        self.filled - self.start
     }
 
     fn take(&mut self) -> Vec<u8> {
+        self.compact();
         self.buf.truncate(self.filled);
         self.filled = 0;
         std::mem::take(&mut self.buf)
     }
 
+    /// Take the first len bytes and keep the rest buffered
+    fn take_prefix(&mut self, len: usize) -> Vec<u8> {
+        let len = usize::min(len, self.len());
+        let prefix = self.buf[self.start..self.start + len].to_vec();
+        self.start += len;
+        if self.start == self.filled {
+            self.start = 0;
+            self.filled = 0;
+        }
+        prefix
+    }
+
     fn peek(&self) -> &[u8] {
-        &self.buf[..self.filled]
+        &self.buf[self.start..self.filled]
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for IncomingBuffer {
+    fn drop(&mut self) {
+        crate::wire::util::scrub(&mut self.buf);
     }
 }
 
@@ -102,8 +338,10 @@ impl RecvConn {
     }
 
     /// Reads from the source once but takes care that the internal buffer only reaches at maximum max_buffer_size
-    /// so we can process messages separatly and avoid leaking file descriptors to wrong messages
+    /// so we can process messages separatly and avoid leaking file descriptors to wrong messages.
+    /// Reads interrupted by a signal are retried until the timeout is used up.
     fn refill_buffer(&mut self, max_buffer_size: usize, timeout: Timeout) -> Result<()> {
+        let deadline = Deadline::new(timeout);
         self.msg_buf_in.reserve(max_buffer_size);
 
         // Borrow all the fields because we can't use self in the closure...
@@ -118,24 +356,35 @@ impl RecvConn {
             let flags = MsgFlags::empty();
 
             let old_timeout = stream.read_timeout()?;
-            match timeout {
-                Timeout::Duration(d) => {
-                    stream.set_read_timeout(Some(d))?;
-                }
-                Timeout::Infinite => {
-                    stream.set_read_timeout(None)?;
+            let iovec_mut = &mut [iovec];
+            let msg = loop {
+                let timeout = match deadline.remaining() {
+                    Ok(timeout) => timeout,
+                    Err(e) => break Err(e),
+                };
+                match timeout {
+                    Timeout::Duration(d) => {
+                        stream.set_read_timeout(Some(d))?;
+                    }
+                    Timeout::Infinite => {
+                        stream.set_read_timeout(None)?;
+                    }
+                    Timeout::Nonblock => {
+                        stream.set_nonblocking(true)?;
+                    }
                 }
-                Timeout::Nonblock => {
-                    stream.set_nonblocking(true)?;
+                match recvmsg::<SockaddrStorage>(
+                    stream.as_raw_fd(),
+                    iovec_mut,
+                    Some(&mut *cmsgspace),
+                    flags,
+                ) {
+                    Err(nix::errno::Errno::EINTR) => continue,
+                    Err(nix::errno::Errno::EAGAIN) => break Err(Error::TimedOut),
+                    Err(e) => break Err(Error::IoError(e.into())),
+                    Ok(msg) => break Ok(msg),
                 }
-            }
-            let iovec_mut = &mut [iovec];
-            let msg =
-                recvmsg::<SockaddrStorage>(stream.as_raw_fd(), iovec_mut, Some(cmsgspace), flags)
-                    .map_err(|e| match e {
-                        nix::errno::Errno::EAGAIN => Error::TimedOut,
-                        _ => Error::IoError(e.into()),
-                    });
+            };
 
             stream.set_nonblocking(false)?;
             stream.set_read_timeout(old_timeout)?;
@@ -169,21 +418,18 @@ impl RecvConn {
             return Ok(16);
         }
         let msg_buf_in = &self.msg_buf_in.peek();
-        let header = unmarshal::unmarshal_header(&mut Cursor::new(msg_buf_in))?;
-        let header_fields_len =
-            crate::wire::util::parse_u32(&msg_buf_in[unmarshal::HEADER_LEN..], header.byteorder)?;
-        let complete_header_size = unmarshal::HEADER_LEN + header_fields_len as usize + 4; // +4 because the length of the header fields does not count
-
-        let padding_between_header_and_body = 8 - ((complete_header_size) % 8);
-        let padding_between_header_and_body = if padding_between_header_and_body == 8 {
-            0
-        } else {
-            padding_between_header_and_body
-        };
-
         let bytes_needed =
-            complete_header_size + padding_between_header_and_body + header.body_len as usize;
-        Ok(bytes_needed)
+            unmarshal::unmarshal_header(&mut Cursor::new(msg_buf_in)).and_then(|header| {
+                let header_fields_len =
+                    Cursor::new(&msg_buf_in[unmarshal::HEADER_LEN..]).read_u32(header.byteorder)?;
+                unmarshal::calc_message_len(&header, header_fields_len)
+            });
+        match bytes_needed {
+            Ok(bytes_needed) => Ok(bytes_needed),
+            // read the broken message completely, so get_next_message can skip it
+            Err(e) if self.skip_invalid_messages => raw_message_len(msg_buf_in).ok_or(e.into()),
+            Err(e) => Err(e.into()),
+        }
     }
 
     // Checks if the internal buffer currently holds a complete message
@@ -208,12 +454,12 @@ impl RecvConn {
         // This whole dance around reading exact amounts of bytes is necessary to read messages exactly at their bounds.
         // I think thats necessary so we can later add support for unixfd sending
         //calc timeout in reference to this point in time
-        let start_time = time::Instant::now();
+        let deadline = Deadline::new(timeout);
 
         while !self.buffer_contains_whole_message()? {
             self.refill_buffer(
                 self.bytes_needed_for_current_message()?,
-                super::calc_timeout_left(&start_time, timeout)?,
+                deadline.remaining()?,
             )?;
         }
         Ok(())
@@ -225,25 +471,205 @@ impl RecvConn {
         Ok(())
     }
 
+    /// By default the bytes following a message in the internal buffer are kept for the next call to `get_next_message`.
+    /// In strict mode they are treated as part of the message, which then fails to unmarshal with `NotAllBytesUsed`.
+    /// This is useful for tools that want to validate what peers send.
+    pub fn set_strict_message_bounds(&mut self, strict: bool) {
+        self.strict_message_bounds = strict;
+    }
+
+    /// The spec requires a signature header field for messages with a body, but some non-conforming peers omit it. By
+    /// default their messages are returned with an empty signature, the body can then only be inspected as bytes with
+    /// `MarshalledMessageBody::raw`. With this set to false such messages are rejected with
+    /// `UnmarshalError::MissingBodySignature`, which is useful for tools that validate what peers send.
+    pub fn set_allow_missing_signature(&mut self, allow: bool) {
+        self.allow_missing_signature = allow;
+    }
+
+    /// The spec only allows 0 and 1 as values for booleans, so by default messages containing other values fail to
+    /// unmarshal with `UnmarshalError::InvalidBoolean`. Some embedded stacks send any nonzero value for true. With this
+    /// set such values are rewritten to 1 when the message is received, `sanitized_booleans` counts how often that happened.
+    pub fn set_relaxed_booleans(&mut self, relaxed: bool) {
+        self.relaxed_booleans = relaxed;
+    }
+
+    /// How many booleans have been rewritten to true on this connection, see `set_relaxed_booleans`.
+    pub fn sanitized_booleans(&self) -> u64 {
+        self.sanitized_booleans
+    }
+
+    /// Header fields with a code that is not in the spec are skipped. With a handler set they are passed to it first, which
+    /// lets monitors and protocol tools see extensions instead of losing them. Replaces a handler that was set before.
+    pub fn set_unknown_header_field_handler(&mut self, handler: Option<Box<UnknownHeaderFieldFn>>) {
+        self.unknown_header_fields = handler;
+    }
+
+    /// By default a message that violates the protocol, e.g. with an invalid header or wrong padding, is reported as
+    /// `Error::UnmarshalError`. If its header is broken it stays in the buffer and every further call reports it again,
+    /// so the connection is unusable. With this set such messages are skipped whenever their length can still be
+    /// determined, and reported as `Error::ProtocolViolation` with the sender and the credentials of the socket peer.
+    /// If `skipped` is set the next message can be received as usual, a service can log the violation and go on
+    /// serving the other clients. A message that can not be skipped is reported once, further calls return the plain
+    /// `Error::UnmarshalError`.
+    pub fn set_skip_invalid_messages(&mut self, skip: bool) {
+        self.skip_invalid_messages = skip;
+    }
+
+    /// How many protocol violations have been reported with `set_skip_invalid_messages` enabled
+    pub fn protocol_violations(&self) -> u64 {
+        self.protocol_violations
+    }
+
+    /// The message at the start of the buffer could not be parsed. Skip it if possible.
+    fn reject_buffered_message(
+        &mut self,
+        error: UnmarshalError,
+        serial: Option<NonZeroU32>,
+    ) -> Error {
+        if !self.skip_invalid_messages {
+            return error.into();
+        }
+        let skipped = match raw_message_len(self.msg_buf_in.peek()) {
+            Some(len) if len <= self.msg_buf_in.len() => {
+                if self.strict_message_bounds {
+                    self.msg_buf_in.take();
+                } else {
+                    self.msg_buf_in.take_prefix(len);
+                }
+                self.fds_in.clear();
+                true
+            }
+            _ => false,
+        };
+        self.report_violation(error, None, serial, skipped)
+    }
+
+    fn report_violation(
+        &mut self,
+        error: UnmarshalError,
+        sender: Option<String>,
+        serial: Option<NonZeroU32>,
+        skipped: bool,
+    ) -> Error {
+        if !self.skip_invalid_messages {
+            return error.into();
+        }
+        if !skipped {
+            if self.stuck_violation_reported {
+                return error.into();
+            }
+            self.stuck_violation_reported = true;
+        }
+        self.protocol_violations += 1;
+        let (peer_pid, peer_uid) = self.peer_credentials();
+        Error::ProtocolViolation(Box::new(ProtocolViolation {
+            error,
+            sender,
+            serial,
+            peer_pid,
+            peer_uid,
+            skipped,
+        }))
+    }
+
+    fn peer_credentials(&self) -> (Option<i32>, Option<u32>) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Ok(creds) =
+            nix::sys::socket::getsockopt(&self.stream, nix::sys::socket::sockopt::PeerCredentials)
+        {
+            return (Some(creds.pid()), Some(creds.uid()));
+        }
+        (None, None)
+    }
+
+    /// What to wait for before reading. A connection can always receive more, so `read` is always set.
+    pub fn readiness_hint(&self) -> ReadinessHint {
+        ReadinessHint {
+            read: true,
+            write: false,
+            // a broken header is reported by get_next_message, so it counts as buffered
+            message_buffered: !matches!(self.buffer_contains_whole_message(), Ok(false)),
+        }
+    }
+
     /// Blocks until a message has been read from the conn or the timeout has been reached
     pub fn get_next_message(&mut self, timeout: Timeout) -> Result<MarshalledMessage> {
-        self.read_whole_message(timeout)?;
+        match self.read_whole_message(timeout) {
+            Ok(()) => {}
+            // not even the length of the message could be read
+            Err(Error::UnmarshalError(e)) => return Err(self.reject_buffered_message(e, None)),
+            Err(e) => return Err(e),
+        }
 
         let mut cursor = Cursor::new(self.msg_buf_in.peek());
-        let header = unmarshal::unmarshal_header(&mut cursor)?;
-        let dynheader = unmarshal::unmarshal_dynamic_header(&header, &mut cursor)?;
+        let header = match unmarshal::unmarshal_header(&mut cursor) {
+            Ok(header) => header,
+            Err(e) => return Err(self.reject_buffered_message(e, None)),
+        };
+        let dynheader = match self.unknown_header_fields.as_mut() {
+            Some(handler) => {
+                unmarshal::unmarshal_dynamic_header_with(&header, &mut cursor, handler)
+            }
+            None => unmarshal::unmarshal_dynamic_header(&header, &mut cursor),
+        };
+        let dynheader = match dynheader {
+            Ok(dynheader) => dynheader,
+            Err(e) => return Err(self.reject_buffered_message(e, Some(header.serial))),
+        };
         let header_bytes_consumed = cursor.consumed();
 
-        let buf = self.msg_buf_in.take();
+        let buf = if self.strict_message_bounds {
+            self.msg_buf_in.take()
+        } else {
+            let message_len = self.bytes_needed_for_current_message()?;
+            self.msg_buf_in.take_prefix(message_len)
+        };
         let raw_fds = std::mem::take(&mut self.fds_in);
 
-        Ok(unmarshal::unmarshal_next_message(
+        // checked after the message was taken from the buffer, so the next message can still be read
+        let no_signature = dynheader.signature.as_deref().unwrap_or("").is_empty();
+        if header.body_len > 0 && no_signature && !self.allow_missing_signature {
+            let sender = dynheader.sender.clone();
+            return Err(self.report_violation(
+                UnmarshalError::MissingBodySignature,
+                sender,
+                Some(header.serial),
+                true,
+            ));
+        }
+
+        let announced_fds = dynheader.num_fds.unwrap_or(0);
+        if raw_fds.len() != announced_fds as usize {
+            let sender = dynheader.sender.clone();
+            return Err(self.report_violation(
+                UnmarshalError::NumFdsMismatch {
+                    header: announced_fds,
+                    received: raw_fds.len() as u32,
+                },
+                sender,
+                Some(header.serial),
+                true,
+            ));
+        }
+
+        let sender = dynheader.sender.clone();
+        let mut msg = match unmarshal::unmarshal_next_message(
             &header,
             dynheader,
             buf,
             header_bytes_consumed,
             raw_fds,
-        )?)
+        ) {
+            Ok(msg) => msg,
+            Err(e) => return Err(self.report_violation(e, sender, Some(header.serial), true)),
+        };
+        if self.relaxed_booleans {
+            // bodies that are broken in other ways are returned as they are, the parser reports those errors
+            if let Ok(sanitized) = msg.body.sanitize_booleans() {
+                self.sanitized_booleans += sanitized as u64;
+            }
+        }
+        Ok(msg)
     }
 }
 
@@ -258,20 +684,34 @@ impl SendConn {
         serial
     }
 
+    /// Limit the size of the messages sent over this connection, including header and padding. Messages that are
+    /// bigger are rejected with `MarshalError::MessageExceedsLimit` before anything is written.
+    ///
+    /// Nothing sets this for you: neither the socket nor the bus tell the connection about a limit. Set it if you know
+    /// the peer accepts less than the spec allows, e.g. a bus configured with a lower `max_message_size`, which
+    /// disconnects clients that send bigger messages.
+    ///
+    /// The default is the maximum the dbus spec allows. Bigger limits are lowered to it.
+    pub fn set_max_message_size(&mut self, max: usize) {
+        self.max_message_size = usize::min(max, unmarshal::MAX_MESSAGE_LEN);
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
     /// send a message over the conn
+    ///
+    /// Returns `Error::QueuedMessagesPending` while messages queued with `queue_message` have not been written
+    /// completely, their bytes would get mixed up otherwise.
     pub fn send_message<'a>(
         &'a mut self,
         msg: &'a MarshalledMessage,
     ) -> Result<SendMessageContext<'a>> {
-        let serial = if let Some(serial) = msg.dynheader.serial {
-            serial
-        } else {
-            self.alloc_serial()
-        };
-
-        // clear the buf before marshalling the new header
-        self.header_buf.clear();
-        marshal::marshal(msg, serial, &mut self.header_buf)?;
+        if !self.queued.is_empty() {
+            return Err(Error::QueuedMessagesPending);
+        }
+        let serial = self.marshal_header(msg, 0)?;
 
         let ctx = SendMessageContext {
             msg,
@@ -291,6 +731,272 @@ impl SendConn {
         let ctx = self.send_message(msg)?;
         ctx.write_all().map_err(force_finish_on_error)
     }
+
+    /// Like `send_message_write_all` but the message is given to the pool of this connection afterwards, also if
+    /// sending failed. Build the messages with `message_pool().builder()` so their buffers get reused.
+    pub fn send_and_recycle(&mut self, msg: MarshalledMessage) -> Result<NonZeroU32> {
+        let res = self.send_message_write_all(&msg);
+        self.pool.give_back(msg);
+        res
+    }
+
+    /// The pool `send_and_recycle` puts the sent messages in
+    pub fn message_pool(&mut self) -> &mut MessagePool {
+        &mut self.pool
+    }
+
+    /// Send `msg` with one more param appended to its body: an `ay` of `len` bytes read from `data`. The bytes are
+    /// written to the socket in chunks while they are read, so the array never has to be in memory as a whole, e.g. to
+    /// transfer disk images over a peer to peer connection. Its length goes into the header, so it has to be known
+    /// before. The message is subject to the same size limits as any other.
+    ///
+    /// If `data` fails or ends before `len` bytes the message can not be completed. The SendConn is broken then and all
+    /// further sends return `Error::SendConnBroken`.
+    pub fn send_message_with_byte_stream<R: io::Read>(
+        &mut self,
+        mut msg: MarshalledMessage,
+        len: usize,
+        data: R,
+    ) -> Result<NonZeroU32> {
+        if !self.queued.is_empty() {
+            return Err(Error::QueuedMessagesPending);
+        }
+        // peers reject longer arrays
+        if len > unmarshal::MAX_ARRAY_LEN {
+            return Err(MarshalError::MessageTooLarge.into());
+        }
+        msg.body.push_streamed_bytes_header(len as u32)?;
+        let serial = self.marshal_header(&msg, len)?;
+        let ctx = SendMessageContext {
+            msg: &msg,
+            conn: self,
+            state: SendMessageState {
+                bytes_sent: 0,
+                serial,
+            },
+        };
+        let res = ctx
+            .write_all()
+            .map_err(force_finish_on_error)
+            .and_then(|_| self.write_byte_stream(len, data));
+        if res.is_err() {
+            self.broken = true;
+        }
+        res.map(|()| serial)
+    }
+
+    fn write_byte_stream<R: io::Read>(&mut self, len: usize, mut data: R) -> Result<()> {
+        use std::io::Write;
+
+        let mut chunk = vec![0; usize::min(len, STREAM_CHUNK_SIZE)];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = &mut chunk[..usize::min(remaining, STREAM_CHUNK_SIZE)];
+            data.read_exact(chunk)?;
+            (&self.stream).write_all(chunk)?;
+            remaining -= chunk.len();
+        }
+        Ok(())
+    }
+
+    /// Marshal the header of `msg` into the header buffer and return the serial of the message. `streamed` bytes are
+    /// sent after the body and count towards its length.
+    fn marshal_header(&mut self, msg: &MarshalledMessage, streamed: usize) -> Result<NonZeroU32> {
+        use std::convert::TryFrom;
+
+        if self.broken {
+            return Err(Error::SendConnBroken);
+        }
+        let serial = if let Some(serial) = msg.dynheader.serial {
+            serial
+        } else {
+            self.alloc_serial()
+        };
+
+        // clear the buf before marshalling the new header
+        self.header_buf.clear();
+        marshal::marshal(msg, serial, &mut self.header_buf)?;
+        if streamed > 0 {
+            let body_len = u32::try_from(msg.get_buf().len() + streamed)
+                .map_err(|_| MarshalError::MessageTooLarge)?;
+            crate::wire::util::insert_u32(
+                msg.body.byteorder(),
+                body_len,
+                &mut self.header_buf[4..8],
+            );
+        }
+        let size = self.header_buf.len() + msg.get_buf().len() + streamed;
+        if size > self.max_message_size {
+            return Err(MarshalError::MessageExceedsLimit {
+                size,
+                max: self.max_message_size,
+            }
+            .into());
+        }
+        Ok(serial)
+    }
+
+    /// Queue a message to be written by `write_next_chunk`, for event loops that wait for the socket to become
+    /// writable instead of blocking on it. The message is copied, so it can be dropped right away. Returns the serial of
+    /// the message to match the response.
+    pub fn queue_message(&mut self, msg: &MarshalledMessage) -> Result<NonZeroU32> {
+        let serial = self.marshal_header(msg, 0)?;
+        let body = QueuedBody::Copied {
+            buf: msg.get_buf().to_vec(),
+            fds: msg.body.get_fds().to_vec(),
+        };
+        self.push_queued(body);
+        Ok(serial)
+    }
+
+    /// Like `queue_message` but the message is moved into the queue instead of being copied. The body is written
+    /// straight from its buffer, which avoids copying large bodies like file contents or images.
+    pub fn queue_message_owned(&mut self, msg: MarshalledMessage) -> Result<NonZeroU32> {
+        let serial = self.marshal_header(&msg, 0)?;
+        self.push_queued(QueuedBody::Owned(Box::new(msg)));
+        Ok(serial)
+    }
+
+    fn push_queued(&mut self, body: QueuedBody) {
+        self.queued.push_back(QueuedMessage {
+            header: self.header_buf.clone(),
+            body,
+            bytes_sent: 0,
+        });
+    }
+
+    /// Whether there are queued messages left to write. An event loop should wait for the socket to become writable
+    /// while this is true.
+    pub fn has_queued(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    /// What to wait for before writing. `write` is set while there are queued messages.
+    pub fn readiness_hint(&self) -> ReadinessHint {
+        ReadinessHint {
+            write: self.has_queued(),
+            ..ReadinessHint::default()
+        }
+    }
+
+    /// Write as much of the queued messages as the socket takes without blocking. A message that was only written
+    /// partially is resumed by the next call, so this can be called every time the socket becomes writable.
+    ///
+    /// The messages following the next one are written with the same syscall, up to the next message that carries fds.
+    /// So many small messages, e.g. calls that are pipelined, do not need a syscall each.
+    pub fn write_next_chunk(&mut self) -> Result<WriteProgress> {
+        let Some(next) = self.queued.front() else {
+            return Ok(WriteProgress::Done);
+        };
+        // the fds are sent with the first byte of their message and only then
+        let raw_fds: Vec<RawFd> = if next.bytes_sent == 0 {
+            next.fds().iter().filter_map(UnixFd::get_raw_fd).collect()
+        } else {
+            Vec::new()
+        };
+        let iov: Vec<IoSlice> = std::iter::once(next)
+            .chain(
+                self.queued
+                    .iter()
+                    .skip(1)
+                    .take_while(|queued| queued.fds().is_empty()),
+            )
+            .flat_map(QueuedMessage::unsent)
+            .take(MAX_WRITE_SLICES)
+            .collect();
+        let mut written = loop {
+            match sendmsg::<SockaddrStorage>(
+                self.stream.as_raw_fd(),
+                &iov,
+                &[ControlMessage::ScmRights(&raw_fds)],
+                MsgFlags::MSG_DONTWAIT,
+                None,
+            ) {
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(nix::errno::Errno::EAGAIN) => return Ok(WriteProgress::WouldBlock),
+                res => break res.map_err(|e| Error::IoError(e.into()))?,
+            }
+        };
+        let total = written;
+        while let Some(next) = self.queued.front_mut() {
+            let rest = next.len() - next.bytes_sent;
+            if written < rest {
+                next.bytes_sent += written;
+                break;
+            }
+            written -= rest;
+            self.queued.pop_front();
+            if written == 0 {
+                break;
+            }
+        }
+        if self.queued.is_empty() {
+            Ok(WriteProgress::Done)
+        } else {
+            Ok(WriteProgress::Written(total))
+        }
+    }
+
+    /// Write all queued messages, waiting for the socket to become writable in between. Returns `Error::TimedOut` if
+    /// not everything could be written in time, the rest stays queued.
+    ///
+    /// This only writes. A peer that stops reading until its own messages were read makes this run into the timeout,
+    /// use `flush_or_readable` to read those in between.
+    pub fn flush(&mut self, timeout: Timeout) -> Result<()> {
+        self.flush_inner(timeout, false).map(|_| ())
+    }
+
+    /// Like `flush` but also returns once the socket becomes readable while the queued messages can not be written.
+    /// Returns true if everything was written and false if there is something to read first, e.g. the replies of a
+    /// peer that only reads the next call once its reply to the last one was read.
+    pub fn flush_or_readable(&mut self, timeout: Timeout) -> Result<bool> {
+        self.flush_inner(timeout, true)
+    }
+
+    fn flush_inner(&mut self, timeout: Timeout, stop_if_readable: bool) -> Result<bool> {
+        use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+        use std::convert::TryFrom;
+
+        let deadline = Deadline::new(timeout);
+        loop {
+            if self.write_next_chunk()? != WriteProgress::WouldBlock {
+                if !self.has_queued() {
+                    return Ok(true);
+                }
+                continue;
+            }
+            let remaining = deadline.remaining_duration()?;
+            let expired = remaining.is_some_and(|d| d.is_zero());
+            if expired && !stop_if_readable {
+                return Err(Error::TimedOut);
+            }
+            let timeout = match remaining {
+                Some(d) => PollTimeout::try_from(d).unwrap_or(PollTimeout::MAX),
+                None => PollTimeout::NONE,
+            };
+            let mut flags = PollFlags::POLLOUT;
+            if stop_if_readable {
+                flags |= PollFlags::POLLIN;
+            }
+            let mut pollfd = [PollFd::new(self.stream.as_fd(), flags)];
+            match poll(&mut pollfd, timeout) {
+                Ok(_) | Err(nix::errno::Errno::EINTR) => {}
+                Err(e) => return Err(Error::IoError(e.into())),
+            }
+            // a hangup is readable too, reading reports it
+            let readable = PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR;
+            if stop_if_readable
+                && pollfd[0]
+                    .revents()
+                    .is_some_and(|ev| ev.intersects(readable))
+            {
+                return Ok(false);
+            }
+            if expired {
+                return Err(Error::TimedOut);
+            }
+        }
+    }
 }
 
 /// only call if you deem the connection doomed by an error returned from writing.
@@ -390,11 +1096,11 @@ impl SendMessageContext<'_> {
         mut self,
         timeout: Timeout,
     ) -> std::result::Result<NonZeroU32, (Self, super::Error)> {
-        let start_time = std::time::Instant::now();
+        let deadline = Deadline::new(timeout);
 
         // loop until either the time is up or all bytes have been written
         let res = loop {
-            let iteration_timeout = super::calc_timeout_left(&start_time, timeout);
+            let iteration_timeout = deadline.remaining();
             let iteration_timeout = match iteration_timeout {
                 Err(e) => break Err(e),
                 Ok(t) => t,
@@ -430,7 +1136,10 @@ impl SendMessageContext<'_> {
 
     /// Basic routine to do a write to the fd once. Mostly useful if you are using a nonblocking timeout. But even then I would recommend using
     /// write() and not write_once()
+    ///
+    /// Writes interrupted by a signal before any bytes were written are retried until the timeout is used up.
     pub fn write_once(&mut self, timeout: Timeout) -> Result<usize> {
+        let deadline = Deadline::new(timeout);
         // This will result in a zero sized slice if the header has been sent. Actually we would not need to
         // include that anymore in the iov but that is harder than just giving it the zero sized slice.
         let header_bytes_sent = usize::min(self.state.bytes_sent, self.conn.header_buf.len());
@@ -445,19 +1154,6 @@ impl SendMessageContext<'_> {
         ];
         let flags = MsgFlags::empty();
 
-        let old_timeout = self.conn.stream.write_timeout()?;
-        match timeout {
-            Timeout::Duration(d) => {
-                self.conn.stream.set_write_timeout(Some(d))?;
-            }
-            Timeout::Infinite => {
-                self.conn.stream.set_write_timeout(None)?;
-            }
-            Timeout::Nonblock => {
-                self.conn.stream.set_nonblocking(true)?;
-            }
-        }
-
         // if this is not the first write for this message do not send the raw_fds again. This would lead to unexpected
         // duplicated FDs on the other end!
         let raw_fds = if self.state.bytes_sent == 0 {
@@ -465,18 +1161,41 @@ impl SendMessageContext<'_> {
         } else {
             vec![]
         };
-        let bytes_sent = sendmsg::<SockaddrStorage>(
-            self.conn.stream.as_raw_fd(),
-            &iov,
-            &[ControlMessage::ScmRights(&raw_fds)],
-            flags,
-            None,
-        );
+
+        let old_timeout = self.conn.stream.write_timeout()?;
+        let bytes_sent = loop {
+            let timeout = match deadline.remaining() {
+                Ok(timeout) => timeout,
+                Err(e) => break Err(e),
+            };
+            match timeout {
+                Timeout::Duration(d) => {
+                    self.conn.stream.set_write_timeout(Some(d))?;
+                }
+                Timeout::Infinite => {
+                    self.conn.stream.set_write_timeout(None)?;
+                }
+                Timeout::Nonblock => {
+                    self.conn.stream.set_nonblocking(true)?;
+                }
+            }
+            // An interrupted sendmsg did not send anything, so the fds need to be sent with the retry as well
+            match sendmsg::<SockaddrStorage>(
+                self.conn.stream.as_raw_fd(),
+                &iov,
+                &[ControlMessage::ScmRights(&raw_fds)],
+                flags,
+                None,
+            ) {
+                Err(nix::errno::Errno::EINTR) => continue,
+                res => break res.map_err(|e| Error::IoError(e.into())),
+            }
+        };
 
         self.conn.stream.set_write_timeout(old_timeout)?;
         self.conn.stream.set_nonblocking(false)?;
 
-        let bytes_sent = bytes_sent.map_err(io::Error::from)?;
+        let bytes_sent = bytes_sent?;
 
         self.state.bytes_sent += bytes_sent;
 
@@ -490,16 +1209,27 @@ impl DuplexConn {
     /// Remember to send the mandatory hello message before doing anything else with the connection!
     /// You can use the `send_hello` function for this.
     pub fn connect_to_bus(addr: UnixAddr, with_unix_fd: bool) -> super::Result<DuplexConn> {
-        let sock = socket(
-            socket::AddressFamily::Unix,
-            socket::SockType::Stream,
-            socket::SockFlag::empty(),
-            None,
-        )
-        .map_err(io::Error::from)?;
+        Self::connect_to_address(&super::BusAddress::Unix(addr), with_unix_fd)
+    }
 
-        connect(sock.as_raw_fd(), &addr).map_err(io::Error::from)?;
-        let mut stream = UnixStream::from(sock);
+    /// Connect to any kind of address that `BusAddress` supports, e.g. one parsed from `$DBUS_SESSION_BUS_ADDRESS`
+    ///
+    /// Like with `connect_to_bus` the hello message still needs to be sent.
+    pub fn connect_to_address(
+        addr: &super::BusAddress,
+        with_unix_fd: bool,
+    ) -> super::Result<DuplexConn> {
+        Self::connect_over_stream(addr.connect()?, with_unix_fd)
+    }
+
+    /// Authenticate over a stream that is already connected to a bus or a peer, e.g. a socket inherited from an
+    /// inetd-style launcher or one end of a socketpair handed to a sandbox. `IntoDbusConn` does the same for a fd.
+    ///
+    /// Like with `connect_to_bus` the hello message still needs to be sent if the other side is a bus.
+    pub fn connect_over_stream(
+        mut stream: UnixStream,
+        with_unix_fd: bool,
+    ) -> super::Result<DuplexConn> {
         match auth::do_auth(&mut stream)? {
             auth::AuthResult::Ok => {}
             auth::AuthResult::Rejected => return Err(Error::AuthFailed),
@@ -514,37 +1244,183 @@ impl DuplexConn {
 
         auth::send_begin(&mut stream)?;
 
+        Ok(Self::from_stream(stream)?)
+    }
+
+    /// Connect directly to another process instead of a bus, e.g. one that accepts connections with a `PeerListener`.
+    ///
+    /// This is the same as `connect_to_address`, there just must not be a hello message. There is no bus that could
+    /// assign names, so messages need neither a destination nor a sender and calls can not be made to well known names.
+    pub fn connect_to_peer(
+        addr: &super::BusAddress,
+        with_unix_fd: bool,
+    ) -> super::Result<DuplexConn> {
+        Self::connect_to_address(addr, with_unix_fd)
+    }
+
+    /// The server side of `connect_over_stream`: authenticate a client of a peer to peer connection, e.g. a child
+    /// process that got the other end of a socketpair. Only clients running as the same user as this process are
+    /// accepted. `guid` identifies the server, see `PeerListener::guid`.
+    pub fn accept_over_stream(
+        stream: UnixStream,
+        guid: &str,
+        with_unix_fd: bool,
+    ) -> super::Result<DuplexConn> {
+        let mut server = auth::AuthServer::with_guid(guid.to_owned());
+        server.set_allow_unix_fds(with_unix_fd);
+        Self::accept_with(stream, &server).map(|(conn, _)| conn)
+    }
+
+    /// Like `accept_over_stream` with the users, the guid and the timeout configured in `server`. Also returns who the
+    /// client authenticated as, e.g. for a bus that needs to know the uid of its clients.
+    pub fn accept_with(
+        mut stream: UnixStream,
+        server: &auth::AuthServer,
+    ) -> super::Result<(DuplexConn, auth::ServerAuth)> {
+        let accepted = server.authenticate(&mut stream)?.ok_or(Error::AuthFailed)?;
+        Ok((Self::from_stream(stream)?, accepted))
+    }
+
+    /// Listen for peer to peer connections on the unix socket at `addr`. A socket file that is in the way is not
+    /// removed, remove it yourself if it is left over from an earlier run.
+    ///
+    /// ```rust
+    /// use rustbus::connection::{BusAddress, Timeout};
+    /// use rustbus::{DuplexConn, MessageBuilder};
+    ///
+    /// let path = std::env::temp_dir().join(format!("rustbus-peer-doc-{}", std::process::id()));
+    /// # let _ = std::fs::remove_file(&path);
+    /// let addr = rustbus::connection::UnixAddr::new(&path).unwrap();
+    /// let listener = DuplexConn::listen(&addr).unwrap();
+    ///
+    /// let client = std::thread::spawn(move || {
+    ///     let mut conn = DuplexConn::connect_to_peer(&BusAddress::Unix(addr), false).unwrap();
+    ///     let call = MessageBuilder::new().call("Ping").on("/").build();
+    ///     conn.send.send_message_write_all(&call).unwrap();
+    /// });
+    ///
+    /// let mut conn = listener.accept().unwrap();
+    /// let call = conn.recv.get_next_message(Timeout::Infinite).unwrap();
+    /// assert_eq!(call.dynheader.member.as_deref(), Some("Ping"));
+    /// client.join().unwrap();
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn listen(addr: &UnixAddr) -> super::Result<PeerListener> {
+        use nix::sys::socket::{self, bind, listen, socket, Backlog};
+
+        let sock = socket(
+            socket::AddressFamily::Unix,
+            socket::SockType::Stream,
+            socket::SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .map_err(io::Error::from)?;
+        bind(sock.as_raw_fd(), addr).map_err(io::Error::from)?;
+        listen(&sock, Backlog::MAXCONN).map_err(io::Error::from)?;
+        Ok(PeerListener::from_listener(UnixListener::from(sock)))
+    }
+
+    /// Wrap a stream that is ready to send and receive messages, skipping the authentication.
+    ///
+    /// This is only useful if the other side does not expect the authentication either, like the other connection
+    /// returned by `pair` or a peer that was set up the same way. To talk to a bus use `connect_over_stream`.
+    pub fn from_stream(stream: UnixStream) -> io::Result<DuplexConn> {
         Ok(DuplexConn {
             send: SendConn {
                 stream: stream.try_clone()?,
                 header_buf: Vec::new(),
+                max_message_size: unmarshal::MAX_MESSAGE_LEN,
                 serial_counter: NonZeroU32::MIN,
+                queued: VecDeque::new(),
+                pool: MessagePool::default(),
+                broken: false,
             },
             recv: RecvConn {
                 msg_buf_in: IncomingBuffer::new(),
                 fds_in: Vec::new(),
                 cmsgspace: cmsg_space!([RawFd; 10]),
+                strict_message_bounds: false,
+                allow_missing_signature: true,
+                relaxed_booleans: false,
+                sanitized_booleans: 0,
+                unknown_header_fields: None,
+                skip_invalid_messages: false,
+                protocol_violations: 0,
+                stuck_violation_reported: false,
                 stream,
             },
         })
     }
 
+    /// Two connections that are connected to each other over a socketpair, without a bus in between and without
+    /// authentication. This is useful to test services and clients against each other in one process, see the
+    /// dispatch_conn module for an example.
+    pub fn pair() -> io::Result<(DuplexConn, DuplexConn)> {
+        let (left, right) = UnixStream::pair()?;
+        Ok((Self::from_stream(left)?, Self::from_stream(right)?))
+    }
+
+    /// Take the halves apart to use them from different threads, e.g. to block receiving signals on one thread while
+    /// another one sends calls. Replies to those calls arrive at the `RecvConn`, so the receiving thread has to pass them on.
+    ///
+    /// ```rust
+    /// use rustbus::{connection::Timeout, DuplexConn, MessageBuilder};
+    ///
+    /// let (conn, mut peer) = DuplexConn::pair().unwrap();
+    /// let (mut send, mut recv) = conn.split();
+    /// let receiver = std::thread::spawn(move || recv.get_next_message(Timeout::Infinite).unwrap());
+    ///
+    /// let signal = MessageBuilder::new().signal("io.killing.spark", "Ready", "/").build();
+    /// peer.send.send_message_write_all(&signal).unwrap();
+    /// let call = MessageBuilder::new().call("Start").on("/").build();
+    /// send.send_message_write_all(&call).unwrap();
+    ///
+    /// assert_eq!(receiver.join().unwrap().dynheader.member.as_deref(), Some("Ready"));
+    /// let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+    /// assert_eq!(call.dynheader.member.as_deref(), Some("Start"));
+    /// ```
+    pub fn split(self) -> (SendConn, RecvConn) {
+        (self.send, self.recv)
+    }
+
+    /// Put halves back together that were taken apart with `split`. Nothing checks that they belong to the same
+    /// connection, halves of different connections make a DuplexConn that sends on one and receives on the other.
+    pub fn from_parts(send: SendConn, recv: RecvConn) -> DuplexConn {
+        DuplexConn { send, recv }
+    }
+
+    /// Connect to the unix socket at `path` in the filesystem. This is the same as `connect_to_bus` but does not require
+    /// you to build a `UnixAddr` yourself.
+    pub fn connect_to_socket_path<P: AsRef<std::path::Path>>(
+        path: P,
+        with_unix_fd: bool,
+    ) -> super::Result<DuplexConn> {
+        let addr = UnixAddr::new(path.as_ref()).map_err(io::Error::from)?;
+        Self::connect_to_bus(addr, with_unix_fd)
+    }
+
+    /// The combined `readiness_hint` of both halves. They share one socket, so a single registration of the fd covers both.
+    pub fn readiness_hint(&self) -> ReadinessHint {
+        ReadinessHint {
+            write: self.send.has_queued(),
+            ..self.recv.readiness_hint()
+        }
+    }
+
     /// Sends the obligatory hello message and returns the unique id the daemon assigned this connection
     pub fn send_hello(&mut self, timeout: crate::connection::Timeout) -> super::Result<String> {
-        let start_time = time::Instant::now();
+        let deadline = Deadline::new(timeout);
 
         let hello = crate::standard_messages::hello();
         let serial = self
             .send
             .send_message(&hello)?
-            .write(super::calc_timeout_left(&start_time, timeout)?)
+            .write(deadline.remaining()?)
             .map_err(|(ctx, e)| {
                 ctx.force_finish();
                 e
             })?;
-        let resp = self
-            .recv
-            .get_next_message(super::calc_timeout_left(&start_time, timeout)?)?;
+        let resp = self.recv.get_next_message(deadline.remaining()?)?;
         if resp.dynheader.response_serial != Some(serial) {
             return Err(super::Error::AuthFailed);
         }
@@ -553,6 +1429,81 @@ impl DuplexConn {
     }
 }
 
+/// Accepts peer to peer connections, see `DuplexConn::listen`. Each accepted connection is authenticated before it is
+/// returned, so `accept` blocks until the client finished the handshake or its timeout ran out. Servers that should not
+/// wait for one client at all take the streams with `accept_unauthenticated` and authenticate them on another thread.
+#[derive(Debug)]
+pub struct PeerListener {
+    listener: UnixListener,
+    auth: auth::AuthServer,
+}
+
+impl PeerListener {
+    /// Accept connections on a socket that is already listening, e.g. one passed by systemd
+    pub fn from_listener(listener: UnixListener) -> Self {
+        PeerListener {
+            listener,
+            auth: auth::AuthServer::new(),
+        }
+    }
+
+    /// Identifies this server to the clients. It is generated when the listener is created and can be put into the
+    /// address given to clients as the `guid` key.
+    pub fn guid(&self) -> &str {
+        self.auth.guid()
+    }
+
+    /// Whether clients may send unix fds. Allowed by default.
+    pub fn set_allow_unix_fd(&mut self, allow: bool) {
+        self.auth.set_allow_unix_fds(allow);
+    }
+
+    /// Which users may connect. By default only the user this process runs as.
+    pub fn set_allowed_users(&mut self, allowed: auth::AllowedUsers) {
+        self.auth.set_allowed_users(allowed);
+    }
+
+    /// How long a client may take for the handshake, 30 seconds by default. `accept` fails with an `IoError` of the
+    /// kind `TimedOut` after that.
+    pub fn set_auth_timeout(&mut self, timeout: Timeout) {
+        self.auth.set_timeout(timeout);
+    }
+
+    /// The settings clients are authenticated with, e.g. to authenticate the streams from `accept_unauthenticated`
+    pub fn auth_server(&self) -> &auth::AuthServer {
+        &self.auth
+    }
+
+    /// Block until the next client connected and authenticated
+    pub fn accept(&self) -> Result<DuplexConn> {
+        self.accept_with_auth().map(|(conn, _)| conn)
+    }
+
+    /// Like `accept` but also returns who the client authenticated as, see `DuplexConn::accept_with`
+    pub fn accept_with_auth(&self) -> Result<(DuplexConn, auth::ServerAuth)> {
+        DuplexConn::accept_with(self.accept_unauthenticated()?, &self.auth)
+    }
+
+    /// Block until the next client connected, without running the handshake. Authenticate the stream with
+    /// `DuplexConn::accept_with` and `auth_server` before using it.
+    pub fn accept_unauthenticated(&self) -> Result<UnixStream> {
+        let (stream, _) = self.listener.accept()?;
+        Ok(stream)
+    }
+}
+
+impl AsRawFd for PeerListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl AsFd for PeerListener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.listener.as_fd()
+    }
+}
+
 impl AsRawFd for SendConn {
     /// Reading or writing to the `RawFd` may result in undefined behavior
     /// and break the `Conn`.
@@ -576,3 +1527,1005 @@ impl AsRawFd for DuplexConn {
         self.recv.stream.as_raw_fd()
     }
 }
+
+impl AsFd for SendConn {
+    /// Reading or writing to the fd may break the `Conn`. It is meant to be used with poll() or select().
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+impl AsFd for RecvConn {
+    /// Reading or writing to the fd may break the `Conn`. It is meant to be used with poll() or select().
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+impl AsFd for DuplexConn {
+    /// Reading or writing to the fd may break the `Conn`. It is meant to be used with poll() or select().
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.recv.stream.as_fd()
+    }
+}
+
+/// Upgrade a connected socket to a `DuplexConn` by running the authentication over it. Implemented for everything that
+/// converts into a `UnixStream`, like `UnixStream` itself and `OwnedFd`.
+///
+/// ```rust
+/// use std::os::unix::net::UnixStream;
+/// use rustbus::auth::AuthServer;
+/// use rustbus::connection::ll_conn::IntoDbusConn;
+/// use rustbus::connection::Timeout;
+/// use rustbus::MessageBuilder;
+///
+/// let (client, server) = UnixStream::pair().unwrap();
+/// let service = std::thread::spawn(move || {
+///     let (mut conn, _) = server.accept_dbus_conn(&AuthServer::new()).unwrap();
+///     conn.recv.get_next_message(Timeout::Infinite).unwrap()
+/// });
+///
+/// let mut conn = client.into_dbus_conn(true).unwrap();
+/// let call = MessageBuilder::new().call("Ping").on("/").build();
+/// conn.send.send_message_write_all(&call).unwrap();
+/// assert_eq!(service.join().unwrap().dynheader.member.as_deref(), Some("Ping"));
+/// ```
+pub trait IntoDbusConn {
+    /// Authenticate as the client, see `DuplexConn::connect_over_stream`
+    fn into_dbus_conn(self, with_unix_fd: bool) -> Result<DuplexConn>;
+
+    /// Authenticate a client, see `DuplexConn::accept_with`
+    fn accept_dbus_conn(self, server: &auth::AuthServer) -> Result<(DuplexConn, auth::ServerAuth)>;
+}
+
+impl<S: Into<UnixStream>> IntoDbusConn for S {
+    fn into_dbus_conn(self, with_unix_fd: bool) -> Result<DuplexConn> {
+        DuplexConn::connect_over_stream(self.into(), with_unix_fd)
+    }
+
+    fn accept_dbus_conn(self, server: &auth::AuthServer) -> Result<(DuplexConn, auth::ServerAuth)> {
+        DuplexConn::accept_with(self.into(), server)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::{MessageBuilder, MessageType};
+    use crate::wire::{ObjectPath, UnknownHeaderField};
+    use crate::ByteOrder;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    fn marshal_whole_message(text: &str) -> Vec<u8> {
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Test", "/")
+            .build();
+        msg.body.push_param(text).unwrap();
+        let mut buf = Vec::new();
+        marshal::marshal(&msg, NonZeroU32::MIN, &mut buf).unwrap();
+        buf.extend_from_slice(msg.get_buf());
+        buf
+    }
+
+    fn recv_conn_with_buffered(bytes: &[u8]) -> (RecvConn, UnixStream) {
+        let (stream, peer) = UnixStream::pair().unwrap();
+        let mut msg_buf_in = IncomingBuffer::new();
+        msg_buf_in.reserve(bytes.len());
+        msg_buf_in
+            .read(|buf| {
+                buf[..bytes.len()].copy_from_slice(bytes);
+                Ok(bytes.len())
+            })
+            .unwrap();
+        let recv = RecvConn {
+            stream,
+            msg_buf_in,
+            fds_in: Vec::new(),
+            cmsgspace: Vec::new(),
+            strict_message_bounds: false,
+            allow_missing_signature: true,
+            relaxed_booleans: false,
+            sanitized_booleans: 0,
+            unknown_header_fields: None,
+            skip_invalid_messages: false,
+            protocol_violations: 0,
+            stuck_violation_reported: false,
+        };
+        (recv, peer)
+    }
+
+    #[test]
+    fn test_incoming_buffer() {
+        let mut buffer = IncomingBuffer::new();
+        buffer.reserve(10);
+        buffer
+            .read(|buf| {
+                buf.copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+                Ok(10)
+            })
+            .unwrap();
+        assert_eq!(buffer.take_prefix(3), [0, 1, 2]);
+        assert_eq!(buffer.take_prefix(2), [3, 4]);
+        assert_eq!(buffer.peek(), [5, 6, 7, 8, 9]);
+
+        // the unread bytes are moved to the front and only the requested space is read into
+        buffer.reserve(7);
+        buffer
+            .read(|buf| {
+                assert_eq!(buf.len(), 2);
+                buf.copy_from_slice(&[10, 11]);
+                Ok(2)
+            })
+            .unwrap();
+        assert_eq!(buffer.len(), 7);
+        assert_eq!(buffer.take_prefix(100), [5, 6, 7, 8, 9, 10, 11]);
+        assert_eq!(buffer.len(), 0);
+        assert!(buffer.peek().is_empty());
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize_send_buffers() {
+        use crate::wire::util::is_scrubbed;
+
+        let (DuplexConn { mut send, .. }, _peer) = DuplexConn::pair().unwrap();
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Test", "/")
+            .build();
+        msg.body.push_param("secret").unwrap();
+        send.queue_message(&msg).unwrap();
+        send.queue_message_owned(msg).unwrap();
+
+        send.scrub();
+        assert!(is_scrubbed(&mut send.header_buf));
+        for queued in &mut send.queued {
+            assert!(is_scrubbed(&mut queued.header));
+            if let QueuedBody::Copied { buf, .. } = &mut queued.body {
+                assert!(is_scrubbed(buf));
+            }
+        }
+    }
+
+    #[test]
+    fn test_trailing_bytes_are_kept() {
+        let mut bytes = marshal_whole_message("first");
+        bytes.extend(marshal_whole_message("second"));
+
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.body.parser().get::<&str>().unwrap(), "first");
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.body.parser().get::<&str>().unwrap(), "second");
+        assert!(matches!(
+            recv.get_next_message(Timeout::Nonblock),
+            Err(Error::TimedOut)
+        ));
+
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        recv.set_strict_message_bounds(true);
+        assert!(matches!(
+            recv.get_next_message(Timeout::Nonblock),
+            Err(Error::UnmarshalError(UnmarshalError::NotAllBytesUsed))
+        ));
+    }
+
+    #[test]
+    fn test_missing_signature() {
+        let mut unsigned = MessageBuilder::new()
+            .signal("io.killing.spark", "Test", "/")
+            .build();
+        unsigned.body = crate::message_builder::MarshalledMessageBody::from_parts(
+            vec![1, 2, 3, 4],
+            0,
+            Vec::new(),
+            String::new(),
+            crate::ByteOrder::NATIVE,
+        );
+        let mut bytes = Vec::new();
+        marshal::marshal(&unsigned, NonZeroU32::MIN, &mut bytes).unwrap();
+        bytes.extend_from_slice(unsigned.get_buf());
+        let mut both = bytes.clone();
+        both.extend(marshal_whole_message("next"));
+
+        // the bad message is skipped, the one after it can still be read
+        let (mut recv, _peer) = recv_conn_with_buffered(&both);
+        recv.set_allow_missing_signature(false);
+        assert!(matches!(
+            recv.get_next_message(Timeout::Nonblock),
+            Err(Error::UnmarshalError(UnmarshalError::MissingBodySignature))
+        ));
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.body.parser().get::<&str>().unwrap(), "next");
+
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.get_sig(), "");
+        assert_eq!(msg.body.raw().1, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_relaxed_booleans() {
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Test", "/")
+            .build();
+        msg.body.push_param2(true, vec![false, true]).unwrap();
+        let mut bytes = Vec::new();
+        marshal::marshal(&msg, NonZeroU32::MIN, &mut bytes).unwrap();
+        let mut body = msg.get_buf().to_vec();
+        body[0] = 2;
+        // the second element of the array, after the boolean and the array length
+        body[12] = 0xff;
+        bytes.extend_from_slice(&body);
+
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert!(matches!(
+            msg.body.parser().get::<bool>(),
+            Err(UnmarshalError::InvalidBoolean)
+        ));
+        assert_eq!(recv.sanitized_booleans(), 0);
+
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        recv.set_relaxed_booleans(true);
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(
+            msg.body.parser().get2::<bool, Vec<bool>>().unwrap(),
+            (true, vec![false, true])
+        );
+        assert_eq!(recv.sanitized_booleans(), 2);
+    }
+
+    #[test]
+    fn test_unknown_header_fields() {
+        let msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Test", "/")
+            .build();
+        let mut bytes = Vec::new();
+        marshal::marshal(&msg, NonZeroU32::MIN, &mut bytes).unwrap();
+        assert_eq!(bytes.len() % 8, 0);
+        // append a header field with code 100 and a variant of signature (yt)
+        bytes.extend_from_slice(&[100, 4, b'(', b'y', b't', b')', 0, 0, 7, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&42u64.to_le_bytes());
+        let fields_len = (bytes.len() - 16) as u32;
+        bytes[12..16].copy_from_slice(&fields_len.to_le_bytes());
+
+        let mut cursor = Cursor::new(&bytes);
+        let header = unmarshal::unmarshal_header(&mut cursor).unwrap();
+        let mut unknown = Vec::new();
+        let dynheader =
+            unmarshal::unmarshal_dynamic_header_with(&header, &mut cursor, &mut |field| {
+                unknown.push(field)
+            })
+            .unwrap();
+        assert_eq!(dynheader.member.as_deref(), Some("Test"));
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].code, 100);
+        assert_eq!(
+            *unknown[0].signature(),
+            <(u8, u64) as crate::Signature>::signature()
+        );
+        assert_eq!(unknown[0].get::<(u8, u64)>().unwrap(), (7, 42));
+
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        let seen = Arc::new(crate::sync::Mutex::new(Vec::new()));
+        let seen2 = seen.clone();
+        recv.set_unknown_header_field_handler(Some(Box::new(move |field| {
+            seen2.lock().unwrap().push(field.code)
+        })));
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.dynheader.member.as_deref(), Some("Test"));
+        assert_eq!(*seen.lock().unwrap(), vec![100]);
+
+        // without a handler the field is only kept in the dynheader and marshalled again on passthrough
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        let mut msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.dynheader.member.as_deref(), Some("Test"));
+        assert_eq!(msg.dynheader.unknown_fields, unknown);
+        let mut again = Vec::new();
+        marshal::marshal(&msg, NonZeroU32::MIN, &mut again).unwrap();
+        assert_eq!(again, bytes);
+
+        // custom fields can be added to new messages, e.g. the container instance of dbus-broker
+        msg.dynheader.unknown_fields = vec![UnknownHeaderField::new(
+            10,
+            ObjectPath::new("/org/freedesktop/DBus/Container1/c1").unwrap(),
+        )
+        .unwrap()];
+        let mut custom = Vec::new();
+        marshal::marshal(&msg, NonZeroU32::MIN, &mut custom).unwrap();
+        let (mut recv, _peer) = recv_conn_with_buffered(&custom);
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        let field = &msg.dynheader.unknown_fields[0];
+        assert_eq!(field.code, 10);
+        assert_eq!(
+            field.get::<ObjectPath<&str>>().unwrap(),
+            ObjectPath::new("/org/freedesktop/DBus/Container1/c1").unwrap()
+        );
+
+        assert_eq!(
+            UnknownHeaderField::new(3, "Member"),
+            Err(MarshalError::InvalidHeaderField(3))
+        );
+        // received fields can be passed on in a message with another byteorder
+        let mut msg = MessageBuilder::with_byteorder(ByteOrder::BigEndian)
+            .signal("io.killing.spark", "Test", "/")
+            .build();
+        msg.dynheader.unknown_fields = unknown;
+        let mut swapped = Vec::new();
+        marshal::marshal(&msg, NonZeroU32::MIN, &mut swapped).unwrap();
+        let (mut recv, _peer) = recv_conn_with_buffered(&swapped);
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.dynheader.unknown_fields.len(), 1);
+        assert_eq!(
+            msg.dynheader.unknown_fields[0].get::<(u8, u64)>().unwrap(),
+            (7, 42)
+        );
+    }
+
+    #[test]
+    fn test_send_and_recycle() {
+        let (mut conn, mut peer) = DuplexConn::pair().unwrap();
+        for value in 0..3u32 {
+            let mut msg = conn
+                .send
+                .message_pool()
+                .builder()
+                .signal("io.killing.spark", "Value", "/")
+                .build();
+            assert!(msg.get_buf().is_empty() && msg.get_sig().is_empty());
+            msg.body.push_param(value).unwrap();
+            conn.send.send_and_recycle(msg).unwrap();
+
+            let received = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            assert_eq!(received.body.parser().get::<u32>().unwrap(), value);
+            assert_eq!(received.dynheader.serial, NonZeroU32::new(value + 1));
+        }
+        // the one message was reused every time
+        assert_eq!(conn.send.message_pool().len(), 1);
+        let msg = conn.send.message_pool().take();
+        assert_eq!(msg.typ, MessageType::Invalid);
+        assert!(msg.dynheader.member.is_none());
+        assert!(conn.send.message_pool().is_empty());
+
+        let mut pool = MessagePool::new(1);
+        pool.give_back(MarshalledMessage::new());
+        pool.give_back(MarshalledMessage::new());
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_skip_invalid_messages() {
+        let mut bytes = Vec::new();
+        for (serial, member) in [(1, "Broken"), (2, "Fine")] {
+            let msg = MessageBuilder::new()
+                .signal("io.killing.spark", member, "/")
+                .build();
+            marshal::marshal(&msg, NonZeroU32::new(serial).unwrap(), &mut bytes).unwrap();
+        }
+        // an unknown message type
+        bytes[1] = 9;
+
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        for _ in 0..2 {
+            match recv.get_next_message(Timeout::Nonblock) {
+                Err(Error::UnmarshalError(UnmarshalError::InvalidMessageType)) => {}
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+        assert_eq!(recv.protocol_violations(), 0);
+
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        recv.set_skip_invalid_messages(true);
+        match recv.get_next_message(Timeout::Nonblock) {
+            Err(Error::ProtocolViolation(violation)) => {
+                assert!(matches!(
+                    violation.error,
+                    UnmarshalError::InvalidMessageType
+                ));
+                assert!(violation.skipped);
+                #[cfg(target_os = "linux")]
+                assert_eq!(violation.peer_pid, Some(std::process::id() as i32));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.dynheader.member.as_deref(), Some("Fine"));
+        assert_eq!(recv.protocol_violations(), 1);
+
+        // without a valid byteorder the length is unknown, the message is reported once and then stays stuck
+        bytes[0] = b'x';
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        recv.set_skip_invalid_messages(true);
+        match recv.get_next_message(Timeout::Nonblock) {
+            Err(Error::ProtocolViolation(violation)) => {
+                assert!(matches!(violation.error, UnmarshalError::InvalidByteOrder));
+                assert!(!violation.skipped);
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        for _ in 0..2 {
+            match recv.get_next_message(Timeout::Nonblock) {
+                Err(Error::UnmarshalError(UnmarshalError::InvalidByteOrder)) => {}
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+        assert_eq!(recv.protocol_violations(), 1);
+    }
+
+    #[test]
+    fn test_num_fds_checked() {
+        use std::os::unix::io::IntoRawFd;
+
+        let mut bytes = Vec::new();
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "File", "/")
+            .build();
+        let file = std::fs::File::open("/dev/null").unwrap();
+        msg.body
+            .push_param(UnixFd::new(file.into_raw_fd()))
+            .unwrap();
+        marshal::marshal(&msg, NonZeroU32::MIN, &mut bytes).unwrap();
+        bytes.extend_from_slice(msg.get_buf());
+        let fine = MessageBuilder::new()
+            .signal("io.killing.spark", "Fine", "/")
+            .build();
+        let mut fine_bytes = Vec::new();
+        marshal::marshal(&fine, NonZeroU32::MIN, &mut fine_bytes).unwrap();
+        bytes.extend_from_slice(&fine_bytes);
+
+        // the fd was not sent along with the bytes
+        let (mut recv, _peer) = recv_conn_with_buffered(&bytes);
+        match recv.get_next_message(Timeout::Nonblock) {
+            Err(Error::UnmarshalError(UnmarshalError::NumFdsMismatch {
+                header: 1,
+                received: 0,
+            })) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        let msg = recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert_eq!(msg.dynheader.member.as_deref(), Some("Fine"));
+
+        // the unix fds header field: code 9, signature "u", padding and the value
+        let field = [9, 1, b'u', 0, 1, 0, 0, 0];
+        let pos = bytes.windows(8).position(|w| w == field).unwrap();
+        bytes[pos + 4..pos + 8].copy_from_slice(&(unmarshal::MAX_UNIX_FDS + 1).to_le_bytes());
+        let mut cursor = Cursor::new(&bytes);
+        let header = unmarshal::unmarshal_header(&mut cursor).unwrap();
+        assert_eq!(
+            unmarshal::unmarshal_dynamic_header(&header, &mut cursor).unwrap_err(),
+            UnmarshalError::TooManyUnixFds(unmarshal::MAX_UNIX_FDS + 1)
+        );
+    }
+
+    #[test]
+    fn test_max_message_size() {
+        let (mut conn, mut peer) = DuplexConn::pair().unwrap();
+        conn.send.set_max_message_size(256);
+        assert_eq!(conn.send.max_message_size(), 256);
+
+        let mut msg = MessageBuilder::new().call("Test").on("/").build();
+        msg.body.push_param("a".repeat(300).as_str()).unwrap();
+        match conn.send.send_message(&msg) {
+            Err(Error::MarshalError(MarshalError::MessageExceedsLimit { size, max })) => {
+                assert!(size > 300);
+                assert_eq!(max, 256);
+            }
+            other => panic!("expected the message to be rejected but got {:?}", other),
+        }
+        assert!(matches!(
+            peer.recv.get_next_message(Timeout::Nonblock),
+            Err(Error::TimedOut)
+        ));
+
+        let mut msg = MessageBuilder::new().call("Test").on("/").build();
+        msg.body.push_param("small").unwrap();
+        conn.send.send_message_write_all(&msg).unwrap();
+        let received = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        assert_eq!(received.body.parser().get::<&str>().unwrap(), "small");
+
+        conn.send.set_max_message_size(usize::MAX);
+        assert_eq!(conn.send.max_message_size(), unmarshal::MAX_MESSAGE_LEN);
+    }
+
+    extern "C" fn ignore_signal(_: nix::libc::c_int) {}
+
+    /// Send SIGALRM to the calling thread until the returned flag is set. The handler is installed without SA_RESTART so
+    /// blocking calls in this thread fail with EINTR.
+    fn interrupt_current_thread() -> (Arc<AtomicBool>, std::thread::JoinHandle<()>) {
+        static INSTALL_HANDLER: std::sync::Once = std::sync::Once::new();
+        INSTALL_HANDLER.call_once(|| unsafe {
+            let mut action: nix::libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore_signal as extern "C" fn(nix::libc::c_int) as usize;
+            action.sa_flags = 0;
+            nix::libc::sigemptyset(&mut action.sa_mask);
+            assert_eq!(
+                nix::libc::sigaction(nix::libc::SIGALRM, &action, std::ptr::null_mut()),
+                0
+            );
+        });
+
+        let target = unsafe { nix::libc::pthread_self() };
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_signals = stop.clone();
+        let signaller = std::thread::spawn(move || {
+            while !stop_signals.load(Ordering::SeqCst) {
+                unsafe { nix::libc::pthread_kill(target, nix::libc::SIGALRM) };
+                std::thread::sleep(std::time::Duration::from_micros(200));
+            }
+        });
+        (stop, signaller)
+    }
+
+    #[test]
+    fn test_recv_interrupted_by_signals() {
+        let text = "a".repeat(4 * 1024 * 1024);
+        let bytes = marshal_whole_message(&text);
+
+        let (mut recv, mut peer) = recv_conn_with_buffered(&[]);
+        let writer = std::thread::spawn(move || {
+            for chunk in bytes.chunks(64 * 1024) {
+                std::io::Write::write_all(&mut peer, chunk).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            peer
+        });
+
+        let (stop, signaller) = interrupt_current_thread();
+        let msg = recv.get_next_message(Timeout::Infinite);
+        stop.store(true, Ordering::SeqCst);
+        signaller.join().unwrap();
+
+        assert_eq!(msg.unwrap().body.parser().get::<&str>().unwrap(), text);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_queued_messages() {
+        let text = "a".repeat(4 * 1024 * 1024);
+        let mut big = MessageBuilder::new()
+            .signal("io.killing.spark", "Big", "/")
+            .build();
+        big.body.push_param(text.as_str()).unwrap();
+        let mut small = MessageBuilder::new()
+            .signal("io.killing.spark", "Small", "/")
+            .build();
+        small.body.push_param("after the big one").unwrap();
+
+        let (mut conn, mut peer) = DuplexConn::pair().unwrap();
+        assert_eq!(conn.send.write_next_chunk().unwrap(), WriteProgress::Done);
+        conn.send.queue_message(&big).unwrap();
+        conn.send.queue_message(&small).unwrap();
+        drop(big);
+        assert!(conn.send.has_queued());
+        assert!(matches!(
+            conn.send.send_message(&small),
+            Err(Error::QueuedMessagesPending)
+        ));
+
+        // the message does not fit into the socket buffer, so the writes stop until the peer reads
+        let mut progress = Vec::new();
+        while progress.last() != Some(&WriteProgress::WouldBlock) {
+            progress.push(conn.send.write_next_chunk().unwrap());
+        }
+        assert!(matches!(progress[0], WriteProgress::Written(_)));
+
+        let reader = std::thread::spawn(move || {
+            let big = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            let small = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            (
+                big.body.parser().get::<String>().unwrap().len(),
+                small.body.parser().get::<String>().unwrap(),
+            )
+        });
+        while conn.send.write_next_chunk().unwrap() != WriteProgress::Done {
+            std::thread::yield_now();
+        }
+        assert!(!conn.send.has_queued());
+        assert_eq!(
+            reader.join().unwrap(),
+            (text.len(), "after the big one".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_queue_message_owned() {
+        let text = "a".repeat(4 * 1024 * 1024);
+        let mut big = MessageBuilder::new()
+            .signal("io.killing.spark", "Big", "/")
+            .build();
+        big.body.push_param(text.as_str()).unwrap();
+
+        let (mut conn, mut peer) = DuplexConn::pair().unwrap();
+        conn.send.queue_message_owned(big).unwrap();
+        // the body is written from the moved message, it does not fit into the socket buffer at once
+        let mut progress = Vec::new();
+        while progress.last() != Some(&WriteProgress::WouldBlock) {
+            progress.push(conn.send.write_next_chunk().unwrap());
+        }
+        assert!(conn.send.has_queued());
+
+        let reader = std::thread::spawn(move || {
+            let big = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            big.body.parser().get::<String>().unwrap().len()
+        });
+        conn.send.flush(Timeout::Infinite).unwrap();
+        assert!(!conn.send.has_queued());
+        assert_eq!(reader.join().unwrap(), text.len());
+    }
+
+    #[test]
+    fn test_queued_messages_share_writes() {
+        let (mut conn, mut peer) = DuplexConn::pair().unwrap();
+        for idx in 0..10u32 {
+            let mut msg = MessageBuilder::new()
+                .signal("io.killing.spark", "Small", "/")
+                .build();
+            msg.body.push_param(idx).unwrap();
+            conn.send.queue_message(&msg).unwrap();
+        }
+        // all of them fit into the socket buffer, so one write is enough
+        assert_eq!(conn.send.write_next_chunk().unwrap(), WriteProgress::Done);
+        for idx in 0..10u32 {
+            let msg = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            assert_eq!(msg.body.parser().get::<u32>().unwrap(), idx);
+        }
+
+        conn.send
+            .queue_message(&MessageBuilder::new().call("A").on("/").build())
+            .unwrap();
+        conn.send.flush(Timeout::Infinite).unwrap();
+        assert!(!conn.send.has_queued());
+        peer.recv.get_next_message(Timeout::Infinite).unwrap();
+    }
+
+    #[test]
+    fn test_send_byte_stream() {
+        // bigger than the socket buffer, so the reader has to run at the same time
+        let image: Vec<u8> = (0..3 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+        let (mut conn, mut peer) = DuplexConn::pair().unwrap();
+        let reader = std::thread::spawn(move || {
+            let msg = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            assert_eq!(msg.get_sig(), "say");
+            let (name, data) = msg.body.parser().get2::<String, Vec<u8>>().unwrap();
+            let next = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            (name, data, next.dynheader.member)
+        });
+
+        let mut msg = MessageBuilder::new()
+            .call("Write")
+            .on("/io/killing/spark")
+            .build();
+        msg.body.push_param("disk.img").unwrap();
+        conn.send
+            .send_message_with_byte_stream(msg, image.len(), &image[..])
+            .unwrap();
+        // the connection is still in sync
+        conn.send
+            .send_message_write_all(&MessageBuilder::new().call("Done").on("/").build())
+            .unwrap();
+        let (name, data, next) = reader.join().unwrap();
+        assert_eq!(name, "disk.img");
+        assert!(data == image);
+        assert_eq!(next.as_deref(), Some("Done"));
+
+        // the limits apply before anything is sent
+        conn.send.set_max_message_size(1024);
+        let msg = MessageBuilder::new().call("Write").on("/").build();
+        assert!(matches!(
+            conn.send
+                .send_message_with_byte_stream(msg, 1024, std::io::repeat(0)),
+            Err(Error::MarshalError(
+                MarshalError::MessageExceedsLimit { .. }
+            ))
+        ));
+        let msg = MessageBuilder::new().call("Write").on("/").build();
+        assert!(matches!(
+            conn.send.send_message_with_byte_stream(
+                msg,
+                unmarshal::MAX_ARRAY_LEN + 1,
+                std::io::repeat(0)
+            ),
+            Err(Error::MarshalError(MarshalError::MessageTooLarge))
+        ));
+    }
+
+    #[test]
+    fn test_byte_stream_ends_early() {
+        let (mut conn, _peer) = DuplexConn::pair().unwrap();
+        let msg = MessageBuilder::new().call("Write").on("/").build();
+        assert!(matches!(
+            conn.send
+                .send_message_with_byte_stream(msg, 1024, &[0u8; 10][..]),
+            Err(Error::IoError(_))
+        ));
+
+        // the peer is still waiting for the rest of the array
+        let msg = MessageBuilder::new().call("Next").on("/").build();
+        assert!(matches!(
+            conn.send.send_message(&msg),
+            Err(Error::SendConnBroken)
+        ));
+        assert!(matches!(
+            conn.send.queue_message(&msg),
+            Err(Error::SendConnBroken)
+        ));
+    }
+
+    #[test]
+    fn test_readiness_hint() {
+        let msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Test", "/")
+            .build();
+        let (mut conn, mut peer) = DuplexConn::pair().unwrap();
+        let idle = ReadinessHint {
+            read: true,
+            write: false,
+            message_buffered: false,
+        };
+        assert_eq!(conn.readiness_hint(), idle);
+        assert!(!conn.send.readiness_hint().read);
+
+        conn.send.queue_message(&msg).unwrap();
+        assert!(conn.send.readiness_hint().write);
+        assert!(conn.readiness_hint().write);
+        assert_eq!(conn.send.write_next_chunk().unwrap(), WriteProgress::Done);
+        assert_eq!(conn.readiness_hint(), idle);
+
+        assert!(!peer.recv.readiness_hint().message_buffered);
+        peer.recv.read_whole_message(Timeout::Infinite).unwrap();
+        assert!(peer.recv.readiness_hint().message_buffered);
+        peer.recv.get_next_message(Timeout::Nonblock).unwrap();
+        assert!(!peer.recv.readiness_hint().message_buffered);
+    }
+
+    #[test]
+    fn test_send_interrupted_by_signals() {
+        let text = "a".repeat(4 * 1024 * 1024);
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Test", "/")
+            .build();
+        msg.body.push_param(text.as_str()).unwrap();
+
+        let (stream, peer) = UnixStream::pair().unwrap();
+        let mut send = SendConn {
+            stream,
+            header_buf: Vec::new(),
+            max_message_size: unmarshal::MAX_MESSAGE_LEN,
+            serial_counter: NonZeroU32::MIN,
+            queued: VecDeque::new(),
+            pool: MessagePool::default(),
+            broken: false,
+        };
+        let reader = std::thread::spawn(move || {
+            // give the sender time to fill the socket buffer and block
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            let mut recv = RecvConn {
+                stream: peer,
+                msg_buf_in: IncomingBuffer::new(),
+                fds_in: Vec::new(),
+                cmsgspace: Vec::new(),
+                strict_message_bounds: false,
+                allow_missing_signature: true,
+                relaxed_booleans: false,
+                sanitized_booleans: 0,
+                unknown_header_fields: None,
+                skip_invalid_messages: false,
+                protocol_violations: 0,
+                stuck_violation_reported: false,
+            };
+            recv.get_next_message(Timeout::Infinite)
+        });
+
+        let (stop, signaller) = interrupt_current_thread();
+        let res = send
+            .send_message(&msg)
+            .unwrap()
+            .write_all()
+            .map_err(force_finish_on_error);
+        stop.store(true, Ordering::SeqCst);
+        signaller.join().unwrap();
+
+        res.unwrap();
+        let received = reader.join().unwrap().unwrap();
+        assert_eq!(received.body.parser().get::<&str>().unwrap(), text);
+    }
+
+    #[test]
+    fn test_peer_to_peer() {
+        use std::io::{Read, Write};
+
+        let guid = "0123456789abcdef0123456789abcdef";
+        let (client, server) = UnixStream::pair().unwrap();
+        let service = std::thread::spawn(move || {
+            let mut conn = DuplexConn::accept_over_stream(server, guid, true).unwrap();
+            let call = conn.recv.get_next_message(Timeout::Infinite).unwrap();
+            let mut resp = call.dynheader.make_response();
+            resp.body.push_param("pong").unwrap();
+            conn.send.send_message_write_all(&resp).unwrap();
+        });
+        // the client sends its call right after BEGIN without waiting for anything
+        let mut conn = DuplexConn::connect_over_stream(client, true).unwrap();
+        let call = MessageBuilder::new().call("Ping").on("/").build();
+        conn.send.send_message_write_all(&call).unwrap();
+        let resp = conn.recv.get_next_message(Timeout::Infinite).unwrap();
+        assert_eq!(resp.body.parser().get::<&str>().unwrap(), "pong");
+        service.join().unwrap();
+
+        // sd-bus asks for the identity of the socket credentials and sends everything at once
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let service = std::thread::spawn(move || {
+            let mut conn = DuplexConn::accept_over_stream(server, guid, false).unwrap();
+            conn.recv.get_next_message(Timeout::Infinite).unwrap()
+        });
+        let mut bytes = b"\0AUTH EXTERNAL\r\nDATA\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n".to_vec();
+        let mut call = MessageBuilder::new().call("Ping").on("/").build();
+        call.dynheader.serial = NonZeroU32::new(1);
+        call.write_to(&mut bytes).unwrap();
+        client.write_all(&bytes).unwrap();
+        let mut answers = String::new();
+        client.read_to_string(&mut answers).unwrap();
+        assert_eq!(
+            answers,
+            format!(
+                "DATA\r\nOK {}\r\nERROR \"unix fds are not supported\"\r\n",
+                guid
+            )
+        );
+        let call = service.join().unwrap();
+        assert_eq!(call.dynheader.member.as_deref(), Some("Ping"));
+
+        // other users and other mechanisms are rejected
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let service =
+            std::thread::spawn(move || DuplexConn::accept_over_stream(server, guid, true));
+        let other_uid: String = (nix::unistd::getuid().as_raw() + 1)
+            .to_string()
+            .bytes()
+            .map(|digit| format!("{:02x}", digit))
+            .collect();
+        write!(
+            client,
+            "\0AUTH EXTERNAL {}\r\nAUTH ANONYMOUS\r\nAUTH EXTERNAL zz\r\nBEGIN\r\n",
+            other_uid
+        )
+        .unwrap();
+        let expected = "REJECTED EXTERNAL\r\n".repeat(3) + "ERROR \"unexpected command\"\r\n";
+        let mut answers = vec![0u8; expected.len()];
+        client.read_exact(&mut answers).unwrap();
+        assert_eq!(answers, expected.as_bytes());
+        drop(client);
+        assert!(service.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_into_dbus_conn() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (client, server) = (
+            std::os::fd::OwnedFd::from(client),
+            std::os::fd::OwnedFd::from(server),
+        );
+        let service = std::thread::spawn(move || {
+            let mut auth = auth::AuthServer::new();
+            auth.set_allow_unix_fds(false);
+            let (mut conn, accepted) = server.accept_dbus_conn(&auth).unwrap();
+            assert_eq!(accepted.uid, nix::unistd::getuid().as_raw());
+            assert!(!accepted.unix_fds);
+            conn.recv.get_next_message(Timeout::Infinite).unwrap()
+        });
+        let mut conn = client.into_dbus_conn(false).unwrap();
+        let call = MessageBuilder::new().call("Ping").on("/").build();
+        conn.send.send_message_write_all(&call).unwrap();
+        let call = service.join().unwrap();
+        assert_eq!(call.dynheader.member.as_deref(), Some("Ping"));
+
+        // nobody is allowed
+        let (client, server) = UnixStream::pair().unwrap();
+        let service = std::thread::spawn(move || {
+            let mut auth = auth::AuthServer::new();
+            auth.set_allowed_users(auth::AllowedUsers::Uids(vec![]));
+            server.accept_dbus_conn(&auth).map(|_| ())
+        });
+        assert!(matches!(
+            client.into_dbus_conn(false),
+            Err(Error::AuthFailed)
+        ));
+        assert!(service.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_fds_sent_right_after_begin() {
+        use std::io::Write;
+
+        // the client sends the whole handshake and a message with a fd before the server reads anything
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let uid: String = nix::unistd::getuid()
+            .as_raw()
+            .to_string()
+            .bytes()
+            .map(|digit| format!("{:02x}", digit))
+            .collect();
+        write!(
+            client,
+            "\0AUTH EXTERNAL {}\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n",
+            uid
+        )
+        .unwrap();
+        let mut client = DuplexConn::from_stream(client).unwrap();
+        let mut call = MessageBuilder::new().call("Fd").on("/").build();
+        call.body
+            .push_param(UnixFd::new(nix::unistd::dup(1).unwrap()))
+            .unwrap();
+        client.send.send_message_write_all(&call).unwrap();
+
+        let (mut conn, accepted) = server.accept_dbus_conn(&auth::AuthServer::new()).unwrap();
+        assert!(accepted.unix_fds);
+        let call = conn.recv.get_next_message(Timeout::Infinite).unwrap();
+        let fd = call.body.parser().get::<UnixFd>().unwrap();
+        assert!(fd.get_raw_fd().is_some());
+    }
+
+    #[test]
+    fn test_auth_timeout() {
+        // the client connects and never sends anything
+        let (_client, server) = UnixStream::pair().unwrap();
+        let mut auth = auth::AuthServer::new();
+        auth.set_timeout(Timeout::Duration(std::time::Duration::from_millis(50)));
+        assert!(matches!(
+            server.accept_dbus_conn(&auth),
+            Err(Error::IoError(e)) if e.kind() == io::ErrorKind::TimedOut
+        ));
+
+        // or stops after the first line
+        let (mut client, server) = UnixStream::pair().unwrap();
+        std::io::Write::write_all(&mut client, b"\0AUTH EXTERNAL\r\n").unwrap();
+        assert!(matches!(
+            server.accept_dbus_conn(&auth),
+            Err(Error::IoError(e)) if e.kind() == io::ErrorKind::TimedOut
+        ));
+    }
+
+    #[test]
+    fn test_peer_listener() {
+        let path = std::env::temp_dir().join(format!("rustbus-peer-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let addr = UnixAddr::new(&path).unwrap();
+        let mut listener = DuplexConn::listen(&addr).unwrap();
+        assert_eq!(listener.guid().len(), 32);
+        assert!(listener.guid().bytes().all(|b| b.is_ascii_hexdigit()));
+
+        let clients = std::thread::spawn(move || {
+            let addr = super::super::BusAddress::Unix(addr);
+            for member in ["First", "Second"] {
+                let mut conn = DuplexConn::connect_to_peer(&addr, true).unwrap();
+                let call = MessageBuilder::new().call(member).on("/").build();
+                conn.send.send_message_write_all(&call).unwrap();
+            }
+            // the listener only accepts another user now
+            assert!(matches!(
+                DuplexConn::connect_to_peer(&addr, false),
+                Err(Error::AuthFailed)
+            ));
+            let mut conn = DuplexConn::connect_to_peer(&addr, true).unwrap();
+            let call = MessageBuilder::new().call("Third").on("/").build();
+            conn.send.send_message_write_all(&call).unwrap();
+        });
+        for member in ["First", "Second"] {
+            let mut conn = listener.accept().unwrap();
+            let call = conn.recv.get_next_message(Timeout::Infinite).unwrap();
+            assert_eq!(call.dynheader.member.as_deref(), Some(member));
+        }
+
+        let uid = nix::unistd::getuid().as_raw();
+        listener.set_allowed_users(auth::AllowedUsers::Uids(vec![uid + 1]));
+        assert!(listener.accept().is_err());
+        listener.set_allowed_users(auth::AllowedUsers::Uids(vec![uid + 1, uid]));
+        let (mut conn, accepted) = listener.accept_with_auth().unwrap();
+        assert_eq!(accepted.uid, uid);
+        assert!(accepted.unix_fds);
+        let call = conn.recv.get_next_message(Timeout::Infinite).unwrap();
+        assert_eq!(call.dynheader.member.as_deref(), Some("Third"));
+        clients.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+//! Middleware that sees all messages going through a RpcConn or DispatchConn
+//!
+//! This is useful for things that should be applied uniformly to all messages, like logging, metrics,
+//! authorization checks or adding information to outgoing messages.
+//!
+//! ```rust,no_run
+//! use rustbus::connection::middleware::{IncomingAction, Middleware};
+//! use rustbus::message_builder::MarshalledMessage;
+//! use rustbus::{connection::Timeout, RpcConn};
+//!
+//! struct Logger;
+//! impl Middleware for Logger {
+//!     fn outgoing(&mut self, msg: &mut MarshalledMessage) -> Result<(), rustbus::connection::Error> {
+//!         println!("Send: {:?}", msg.dynheader);
+//!         Ok(())
+//!     }
+//!     fn incoming(&mut self, msg: &mut MarshalledMessage) -> IncomingAction {
+//!         println!("Received: {:?}", msg.dynheader);
+//!         IncomingAction::Continue
+//!     }
+//! }
+//!
+//! let mut rpc_con = RpcConn::session_conn(Timeout::Infinite).unwrap();
+//! rpc_con.add_middleware(Box::new(Logger));
+//! ```
+
+use std::time::{Duration, SystemTime};
+
+use super::Result;
+use crate::message_builder::{HeaderFlags, MarshalledMessage, MessageType};
+
+/// What should happen with an incoming message after a middleware has seen it
+#[derive(Debug)]
+pub enum IncomingAction {
+    /// Pass the message on to the next middleware and then to the connection
+    Continue,
+    /// Silently drop the message
+    Drop,
+    /// Drop the message and send this message instead. This is meant to answer calls directly
+    /// from a middleware, e.g. with an AccessDenied error.
+    Respond(Box<MarshalledMessage>),
+}
+
+/// A middleware can observe and modify messages or stop them from being processed further.
+///
+/// Both methods do nothing by default so you only need to implement the direction you care about. Middlewares only
+/// need to be Send, not Sync, because they are only ever called through `&mut`.
+pub trait Middleware: Send {
+    /// Called for every message before it is sent. Returning an error stops the message from being sent
+    /// and the error is returned to the caller that tried to send it.
+    fn outgoing(&mut self, _msg: &mut MarshalledMessage) -> Result<()> {
+        Ok(())
+    }
+    /// Called for every message after it has been received, before it is queued or dispatched
+    fn incoming(&mut self, _msg: &mut MarshalledMessage) -> IncomingAction {
+        IncomingAction::Continue
+    }
+}
+
+/// An ordered list of middlewares. Outgoing messages pass through them in the order they were added,
+/// incoming messages in the reverse order.
+#[derive(Default)]
+pub struct MiddlewareChain {
+    layers: Vec<Box<dyn Middleware>>,
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, middleware: Box<dyn Middleware>) {
+        self.layers.push(middleware);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Pass an outgoing message through all middlewares. Stops at the first error.
+    pub fn outgoing(&mut self, msg: &mut MarshalledMessage) -> Result<()> {
+        for layer in &mut self.layers {
+            layer.outgoing(msg)?;
+        }
+        Ok(())
+    }
+
+    /// Pass an incoming message through all middlewares. Stops at the first middleware that does not return `Continue`.
+    pub fn incoming(&mut self, msg: &mut MarshalledMessage) -> IncomingAction {
+        for layer in self.layers.iter_mut().rev() {
+            match layer.incoming(msg) {
+                IncomingAction::Continue => {}
+                action => return action,
+            }
+        }
+        IncomingAction::Continue
+    }
+}
+
+/// What `Deadlines` does with calls whose deadline has passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiredCalls {
+    /// Drop the call, the caller gave up on it anyway
+    Drop,
+    /// Answer with `org.freedesktop.DBus.Error.Timeout`. Calls flagged with `HeaderFlags::NoReplyExpected` are dropped.
+    Reject,
+}
+
+/// Enforces the deadlines callers set with `MarshalledMessage::set_deadline`. Added to a DispatchConn it stops calls
+/// that waited too long in the queue before their handler runs, so an overloaded service does not waste time on calls
+/// nobody waits for anymore. Calls without a deadline are not affected.
+///
+/// On the calling side it can give all calls without a deadline a default one.
+///
+/// ```rust
+/// use rustbus::connection::middleware::{Deadlines, ExpiredCalls};
+/// # let (conn, _) = rustbus::DuplexConn::pair().unwrap();
+/// # let handler = Box::new(|_: &mut (), _, _: &_, _: &mut _| Ok(None));
+/// let mut dpcon = rustbus::connection::dispatch_conn::DispatchConn::<(), ()>::new(conn, (), handler);
+/// dpcon.add_middleware(Box::new(Deadlines::new(ExpiredCalls::Reject)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Deadlines {
+    expired: ExpiredCalls,
+    default_timeout: Option<Duration>,
+}
+
+impl Deadlines {
+    pub fn new(expired: ExpiredCalls) -> Self {
+        Self {
+            expired,
+            default_timeout: None,
+        }
+    }
+
+    /// Outgoing calls without a deadline get one `timeout` from the time they are sent
+    pub fn with_default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+}
+
+impl Middleware for Deadlines {
+    fn outgoing(&mut self, msg: &mut MarshalledMessage) -> Result<()> {
+        if let Some(timeout) = self.default_timeout {
+            if msg.typ == MessageType::Call && msg.deadline().is_none() {
+                msg.set_deadline(SystemTime::now() + timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn incoming(&mut self, msg: &mut MarshalledMessage) -> IncomingAction {
+        let expired = msg.typ == MessageType::Call
+            && msg
+                .deadline()
+                .is_some_and(|deadline| deadline <= SystemTime::now());
+        match (expired, self.expired) {
+            (false, _) => IncomingAction::Continue,
+            (true, ExpiredCalls::Drop) => IncomingAction::Drop,
+            (true, ExpiredCalls::Reject) if HeaderFlags::NoReplyExpected.is_set(msg.flags) => {
+                IncomingAction::Drop
+            }
+            (true, ExpiredCalls::Reject) => {
+                IncomingAction::Respond(Box::new(msg.dynheader.make_error_response(
+                    crate::consts::DBUS_ERROR_TIMEOUT,
+                    Some("The deadline of the call passed before it was handled".to_owned()),
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Error;
+    use crate::message_builder::MessageBuilder;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(&'static str, Arc<Mutex<Vec<&'static str>>>);
+    impl Middleware for Recorder {
+        fn outgoing(&mut self, msg: &mut MarshalledMessage) -> Result<()> {
+            self.1.lock().unwrap().push(self.0);
+            msg.dynheader.destination = Some(self.0.to_owned());
+            Ok(())
+        }
+        fn incoming(&mut self, _msg: &mut MarshalledMessage) -> IncomingAction {
+            self.1.lock().unwrap().push(self.0);
+            IncomingAction::Continue
+        }
+    }
+
+    struct Deny;
+    impl Middleware for Deny {
+        fn outgoing(&mut self, _msg: &mut MarshalledMessage) -> Result<()> {
+            Err(Error::RejectedByMiddleware)
+        }
+        fn incoming(&mut self, msg: &mut MarshalledMessage) -> IncomingAction {
+            IncomingAction::Respond(Box::new(
+                msg.dynheader
+                    .make_error_response(crate::consts::DBUS_ERROR_ACCESS_DENIED, None),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_middleware_chain() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = MiddlewareChain::new();
+        chain.push(Box::new(Recorder("first", order.clone())));
+        chain.push(Box::new(Recorder("second", order.clone())));
+
+        let mut msg = MessageBuilder::new().call("Ping").on("/").build();
+        chain.outgoing(&mut msg).unwrap();
+        assert_eq!(msg.dynheader.destination.as_deref(), Some("second"));
+        assert!(matches!(chain.incoming(&mut msg), IncomingAction::Continue));
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["first", "second", "second", "first"]
+        );
+
+        chain.push(Box::new(Deny));
+        order.lock().unwrap().clear();
+        assert!(matches!(
+            chain.outgoing(&mut msg),
+            Err(Error::RejectedByMiddleware)
+        ));
+        assert!(matches!(
+            chain.incoming(&mut msg),
+            IncomingAction::Respond(_)
+        ));
+        // Deny was the last one added so it sees incoming messages first
+        assert_eq!(*order.lock().unwrap(), ["first", "second"]);
+    }
+
+    #[test]
+    fn test_deadlines() {
+        use crate::connection::dispatch_conn::DispatchConn;
+        use crate::connection::{rpc_conn::RpcConn, Timeout};
+        use crate::DuplexConn;
+
+        let (service, client) = DuplexConn::pair().unwrap();
+        std::thread::spawn(move || {
+            let mut dpcon =
+                DispatchConn::<(), ()>::new(service, (), Box::new(|_, _, _, _| Ok(None)));
+            dpcon.add_middleware(Box::new(Deadlines::new(ExpiredCalls::Reject)));
+            let _ = dpcon.run();
+        });
+        let mut client = RpcConn::new(client);
+
+        let mut call = MessageBuilder::new()
+            .call("Work")
+            .on("/")
+            .with_deadline(SystemTime::now() - Duration::from_secs(1))
+            .build();
+        let resp = client.call_method(&mut call, Timeout::Infinite).unwrap();
+        assert!(resp.is_error_named(crate::consts::DBUS_ERROR_TIMEOUT));
+
+        let mut chain = MiddlewareChain::new();
+        chain.push(Box::new(Deadlines::new(ExpiredCalls::Reject)));
+        let mut call = MessageBuilder::new()
+            .call("Work")
+            .on("/")
+            .with_deadline(SystemTime::now() - Duration::from_secs(1))
+            .with_flag(HeaderFlags::NoReplyExpected)
+            .build();
+        assert!(matches!(chain.incoming(&mut call), IncomingAction::Drop));
+
+        // the deadline survives the trip, calls in time are handled
+        client.add_middleware(Box::new(
+            Deadlines::new(ExpiredCalls::Drop).with_default_timeout(Duration::from_secs(60)),
+        ));
+        let mut call = MessageBuilder::new().call("Work").on("/").build();
+        let resp = client.call_method(&mut call, Timeout::Infinite).unwrap();
+        assert_eq!(resp.typ, MessageType::Reply);
+        let deadline = call.deadline().unwrap();
+        assert!(deadline > SystemTime::now() + Duration::from_secs(50));
+
+        let mut chain = MiddlewareChain::new();
+        chain.push(Box::new(Deadlines::new(ExpiredCalls::Drop)));
+        call.set_deadline(SystemTime::now() - Duration::from_millis(1));
+        assert_eq!(call.dynheader.unknown_fields.len(), 1);
+        assert!(matches!(chain.incoming(&mut call), IncomingAction::Drop));
+        let mut signal = MessageBuilder::new()
+            .signal("io.killing.spark", "Late", "/")
+            .build();
+        signal.set_deadline(SystemTime::UNIX_EPOCH);
+        assert!(matches!(
+            chain.incoming(&mut signal),
+            IncomingAction::Continue
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+//! The client side of `org.freedesktop.DBus.ObjectManager`.
+//!
+//! Services like BlueZ or UDisks2 expose their objects through an object manager. `GetManagedObjects` returns all of
+//! them with their interfaces and properties as `a{oa{sa{sv}}}`, which `ManagedObjects` unmarshals. It can then be
+//! kept up to date with the `InterfacesAdded` and `InterfacesRemoved` signals.
+//! See `properties::Properties::set_object_manager` for the service side.
+//!
+//! ```rust,no_run
+//! use rustbus::connection::object_manager::{get_managed_objects, ManagedObjects};
+//! use rustbus::{connection::Timeout, RpcConn};
+//!
+//! let mut rpc_con = RpcConn::system_conn(Timeout::Infinite).unwrap();
+//!
+//! let mut call = get_managed_objects("org.bluez", "/");
+//! let reply = rpc_con.call_method(&mut call, Timeout::Infinite).unwrap();
+//! let objects: ManagedObjects = reply.body.parser().get().unwrap();
+//! for (path, device) in objects.with_interface("org.bluez.Device1") {
+//!     let name = device.get::<&str>("Name").unwrap();
+//!     println!("{}: {:?}", path, name);
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use crate::message_builder::{MarshalledMessage, MessageBuilder};
+use crate::signature;
+use crate::wire::errors::UnmarshalError;
+use crate::wire::marshal::traits::SignatureBuffer;
+use crate::wire::unmarshal::UnmarshalResult;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::wire::{ObjectPath, VarDict};
+use crate::{Signature, Unmarshal};
+
+pub use crate::consts::OBJECT_MANAGER_INTERFACE;
+
+/// Call `GetManagedObjects` on the object manager at `root`
+pub fn get_managed_objects(destination: &str, root: &str) -> MarshalledMessage {
+    MessageBuilder::new()
+        .call("GetManagedObjects")
+        .with_interface(OBJECT_MANAGER_INTERFACE)
+        .on(root)
+        .at(destination)
+        .build()
+}
+
+/// The objects of an object manager, with the properties of each of their interfaces
+#[derive(Debug, Clone, Default)]
+pub struct ManagedObjects {
+    objects: HashMap<ObjectPath<String>, HashMap<String, VarDict>>,
+}
+
+impl ManagedObjects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The paths of all objects
+    pub fn objects(&self) -> impl Iterator<Item = &ObjectPath<String>> {
+        self.objects.keys()
+    }
+
+    /// The interfaces of an object with their properties
+    pub fn interfaces(&self, object: &str) -> Option<&HashMap<String, VarDict>> {
+        self.objects.get(object)
+    }
+
+    /// The properties of one interface of an object
+    pub fn properties(&self, object: &str, interface: &str) -> Option<&VarDict> {
+        self.objects.get(object)?.get(interface)
+    }
+
+    /// All objects that have `interface`, with the properties of that interface
+    pub fn with_interface<'a>(
+        &'a self,
+        interface: &'a str,
+    ) -> impl Iterator<Item = (&'a ObjectPath<String>, &'a VarDict)> + 'a {
+        self.objects
+            .iter()
+            .filter_map(move |(path, interfaces)| Some((path, interfaces.get(interface)?)))
+    }
+
+    /// Apply an `InterfacesAdded` or `InterfacesRemoved` signal. Returns false for all other messages.
+    pub fn apply_signal(&mut self, msg: &MarshalledMessage) -> Result<bool, UnmarshalError> {
+        let class = msg.classify();
+        if class.is_signal(OBJECT_MANAGER_INTERFACE, "InterfacesAdded") {
+            let (path, added) = msg
+                .body
+                .parser()
+                .get2::<ObjectPath<String>, HashMap<String, VarDict>>()?;
+            self.objects.entry(path).or_default().extend(added);
+            Ok(true)
+        } else if class.is_signal(OBJECT_MANAGER_INTERFACE, "InterfacesRemoved") {
+            let (path, removed) = msg.body.parser().get2::<ObjectPath<String>, Vec<&str>>()?;
+            if let Some(interfaces) = self.objects.get_mut(&path) {
+                for name in removed {
+                    interfaces.remove(name);
+                }
+                if interfaces.is_empty() {
+                    self.objects.remove(&path);
+                }
+            }
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn into_inner(self) -> HashMap<ObjectPath<String>, HashMap<String, VarDict>> {
+        self.objects
+    }
+}
+
+impl Signature for ManagedObjects {
+    fn signature() -> signature::Type {
+        <HashMap<ObjectPath<String>, HashMap<String, VarDict>>>::signature()
+    }
+    fn alignment() -> usize {
+        4
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        s_buf.push_static("a{oa{sa{sv}}}");
+    }
+    fn has_sig(sig: &str) -> bool {
+        sig == "a{oa{sa{sv}}}"
+    }
+}
+
+impl<'buf, 'fds> Unmarshal<'buf, 'fds> for ManagedObjects {
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> UnmarshalResult<Self> {
+        Ok(ManagedObjects {
+            objects: Unmarshal::unmarshal(ctx)?,
+        })
+    }
+}
@@ -0,0 +1,616 @@
+//! A variant of `DispatchConn` that runs the handlers on a pool of worker threads
+//!
+//! With a `DispatchConn` a slow handler keeps all other calls waiting. A `PooledDispatchConn` reads the messages on the
+//! thread that runs it and hands each call to one of its workers, so calls are handled concurrently. The replies are
+//! sent over one `SendConn` that the workers share, so they may be sent in a different order than the calls arrived.
+//!
+//! At most `set_max_queued_calls` calls wait for a free worker. While the queue is full no more messages are read from
+//! the connection, so a client that sends calls faster than they are handled is slowed down instead of filling the
+//! memory of the service.
+//!
+//! A handler that panics does not take its worker down. The caller gets an `org.freedesktop.DBus.Error.Failed` reply
+//! and the panic is logged.
+//!
+//! The handlers only get a shared reference to the context, state that handlers change has to be behind a `Mutex` or
+//! similar. Handlers are matched the same way as for a `DispatchConn`, see `dispatch_conn`. Calls to
+//! `org.freedesktop.DBus.Peer` are answered without reaching the handlers. Middlewares, properties and the other
+//! additions of a `DispatchConn` are not supported.
+//!
+//! ```rust
+//! use rustbus::connection::dispatch_conn::{HandleResult, Matches};
+//! use rustbus::connection::pooled_dispatch::{PooledDispatchConn, PooledEnvironment};
+//! use rustbus::message_builder::MarshalledMessage;
+//! use rustbus::{connection::Timeout, DuplexConn, MessageBuilder, RpcConn};
+//! use std::sync::atomic::{AtomicU32, Ordering};
+//!
+//! fn count(
+//!     calls: &AtomicU32,
+//!     _matches: Matches,
+//!     msg: &MarshalledMessage,
+//!     _env: &mut PooledEnvironment,
+//! ) -> HandleResult<()> {
+//!     let mut resp = msg.dynheader.make_response();
+//!     resp.body.push_param(calls.fetch_add(1, Ordering::Relaxed) + 1)?;
+//!     Ok(Some(resp))
+//! }
+//!
+//! let (service, client) = DuplexConn::pair().unwrap();
+//! let mut dpcon = PooledDispatchConn::new(service, AtomicU32::new(0), 4, Box::new(count));
+//! std::thread::spawn(move || dpcon.run());
+//!
+//! let mut client = RpcConn::new(client);
+//! let mut call = MessageBuilder::new().call("Count").on("/").build();
+//! let resp = client.call_method(&mut call, Timeout::Infinite).unwrap();
+//! assert_eq!(resp.body.parser().get::<u32>().unwrap(), 1);
+//! ```
+
+use super::dispatch_conn::{missing_call_header, HandleError, HandleResult};
+use super::dispatch_conn::{HandlerKey, Matches, RouteTable};
+use super::ll_conn::{DuplexConn, RecvConn, SendConn};
+use super::*;
+use crate::message_builder::{MarshalledMessage, MessageType};
+use crate::sync::{mpsc, sync_channel, thread, Arc, Mutex, SyncSender};
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+/// What a handler can access besides the context
+pub struct PooledEnvironment {
+    /// The connection the calls are answered on, shared by all workers
+    pub conn: Arc<Mutex<SendConn>>,
+    object: String,
+    member: String,
+}
+
+impl PooledEnvironment {
+    /// The object path of the handled call
+    pub fn object(&self) -> &str {
+        &self.object
+    }
+
+    /// The member of the handled call
+    pub fn member(&self) -> &str {
+        &self.member
+    }
+}
+
+/// Handlers are called from the worker threads, possibly at the same time
+pub type PooledHandleFn<UserData, UserError> = dyn Fn(&UserData, Matches, &MarshalledMessage, &mut PooledEnvironment) -> HandleResult<UserError>
+    + Send
+    + Sync;
+
+/// The error returned by `PooledDispatchConn::run`, with the offending message if there was one
+pub type PooledError<UserError> = (Option<MarshalledMessage>, HandleError<UserError>);
+
+struct Handlers<UserData, UserError: std::fmt::Debug> {
+    ctx: UserData,
+    objects: RouteTable<Box<PooledHandleFn<UserData, UserError>>>,
+    default_handler: Box<PooledHandleFn<UserData, UserError>>,
+}
+
+impl<UserData, UserError: std::fmt::Debug> Handlers<UserData, UserError> {
+    fn dispatch(
+        &self,
+        msg: &MarshalledMessage,
+        env: &mut PooledEnvironment,
+    ) -> HandleResult<UserError> {
+        let interface = msg.dynheader.interface.as_deref();
+        let member = msg.dynheader.member.as_deref();
+        let matched = msg
+            .dynheader
+            .object
+            .as_deref()
+            .and_then(|obj| self.objects.get(obj, interface, member));
+        match matched {
+            Some((matches, handler)) => handler(&self.ctx, matches, msg, env),
+            None => (self.default_handler)(&self.ctx, Matches::default(), msg, env),
+        }
+    }
+}
+
+/// How many calls wait for a free worker at most by default, per worker
+pub const DEFAULT_QUEUED_CALLS_PER_WORKER: usize = 4;
+
+/// The workers that are running while `PooledDispatchConn::run` is
+struct Pool<UserError: std::fmt::Debug> {
+    calls: Option<SyncSender<MarshalledMessage>>,
+    errors: mpsc::Receiver<PooledError<UserError>>,
+    /// A worker writes to this after it put an error into `errors`, to wake up the polling thread
+    wake: UnixStream,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl<UserError: std::fmt::Debug> Drop for Pool<UserError> {
+    fn drop(&mut self) {
+        // workers stop after the calls they already got
+        self.calls.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Dispatches calls to handlers that run on a pool of worker threads, see the module docs
+pub struct PooledDispatchConn<UserData, UserError: std::fmt::Debug> {
+    recv: RecvConn,
+    send: Arc<Mutex<SendConn>>,
+    handlers: Arc<Handlers<UserData, UserError>>,
+    workers: usize,
+    max_queued_calls: usize,
+    answer_peer_calls: bool,
+}
+
+impl<UserData, UserError> PooledDispatchConn<UserData, UserError>
+where
+    UserData: Send + Sync + 'static,
+    UserError: std::fmt::Debug + Send + 'static,
+{
+    /// `workers` is the number of calls that can be handled at the same time, at least one worker is used. At most
+    /// `DEFAULT_QUEUED_CALLS_PER_WORKER` calls per worker wait for a free worker, see `set_max_queued_calls`.
+    pub fn new(
+        conn: DuplexConn,
+        ctx: UserData,
+        workers: usize,
+        default_handler: Box<PooledHandleFn<UserData, UserError>>,
+    ) -> Self {
+        Self {
+            recv: conn.recv,
+            send: Arc::new(Mutex::new(conn.send)),
+            handlers: Arc::new(Handlers {
+                ctx,
+                objects: RouteTable::default(),
+                default_handler,
+            }),
+            workers: workers.max(1),
+            max_queued_calls: workers.max(1) * DEFAULT_QUEUED_CALLS_PER_WORKER,
+            answer_peer_calls: true,
+        }
+    }
+
+    /// The context that is passed to the handlers
+    pub fn ctx(&self) -> &UserData {
+        &self.handlers.ctx
+    }
+
+    /// See `DispatchConn::add_handler`
+    pub fn add_handler(&mut self, path: &str, handler: Box<PooledHandleFn<UserData, UserError>>) {
+        self.insert(HandlerKey::new(path, None, None), handler);
+    }
+
+    /// See `DispatchConn::add_interface_handler`
+    pub fn add_interface_handler(
+        &mut self,
+        path: &str,
+        interface: &str,
+        handler: Box<PooledHandleFn<UserData, UserError>>,
+    ) {
+        self.insert(HandlerKey::new(path, Some(interface), None), handler);
+    }
+
+    /// See `DispatchConn::add_member_handler`
+    pub fn add_member_handler(
+        &mut self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        handler: Box<PooledHandleFn<UserData, UserError>>,
+    ) {
+        self.insert(
+            HandlerKey::new(path, Some(interface), Some(member)),
+            handler,
+        );
+    }
+
+    fn insert(&mut self, key: HandlerKey, handler: Box<PooledHandleFn<UserData, UserError>>) {
+        // the workers only hold a reference while `run` is running, which needs &mut self too
+        let handlers = Arc::get_mut(&mut self.handlers).expect("the workers have stopped");
+        handlers.objects.insert(key, handler);
+    }
+
+    /// How many calls wait for a free worker at most. Once that many are waiting no more messages are read from the
+    /// connection until a worker takes the next one. With 0 each call is only read once a worker is free. Takes effect
+    /// the next time `run` is called.
+    pub fn set_max_queued_calls(&mut self, max: usize) {
+        self.max_queued_calls = max;
+    }
+
+    /// See `DispatchConn::set_answer_peer_calls`
+    pub fn set_answer_peer_calls(&mut self, answer: bool) {
+        self.answer_peer_calls = answer;
+    }
+
+    /// Start the workers and dispatch calls to them until an error happens. Other messages than calls are dropped.
+    ///
+    /// Errors of the handlers are returned together with the call, the caller did not get a reply then. Before this
+    /// returns the workers finish the calls they already got and stop, their replies are still sent. You may choose to
+    /// call this again, which starts new workers.
+    #[allow(clippy::result_large_err)]
+    pub fn run(&mut self) -> std::result::Result<(), PooledError<UserError>> {
+        let pool = self.start_workers()?;
+        loop {
+            if !self.recv.buffer_contains_whole_message().unwrap_or(true) {
+                self.wait_readable(&pool)?;
+            }
+            if let Ok(error) = pool.errors.try_recv() {
+                return Err(error);
+            }
+            let msg = match self.recv.get_next_message(Timeout::Nonblock) {
+                Ok(msg) => msg,
+                // Only part of a message was available or only the wake up, the rest will follow
+                Err(Error::TimedOut) => continue,
+                Err(error) => return Err((None, HandleError::Connection(error))),
+            };
+            if msg.typ != MessageType::Call {
+                continue;
+            }
+            let response = match missing_call_header(&msg) {
+                Some(error) => Some(error),
+                None if self.answer_peer_calls => crate::peer::peer_reply(&msg.dynheader),
+                None => None,
+            };
+            match response {
+                Some(response) => send_response(&self.send, &response)
+                    .map_err(|e| (Some(msg), HandleError::Connection(e)))?,
+                None => {
+                    let calls = pool.calls.as_ref().expect("calls are only closed on drop");
+                    // blocks while the queue is full. The workers only stop when the pool is dropped.
+                    let _ = calls.send(msg);
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn start_workers(&self) -> std::result::Result<Pool<UserError>, PooledError<UserError>> {
+        let (wake, wake_up) =
+            UnixStream::pair().map_err(|e| (None, HandleError::Connection(e.into())))?;
+        let (call_tx, call_rx) = sync_channel::<MarshalledMessage>(self.max_queued_calls);
+        let call_rx = Arc::new(Mutex::new(call_rx));
+        let (error_tx, errors) = mpsc::channel();
+
+        let workers = (0..self.workers)
+            .map(|_| {
+                let calls = call_rx.clone();
+                let errors = error_tx.clone();
+                let handlers = self.handlers.clone();
+                let send = self.send.clone();
+                let mut wake_up = wake_up.try_clone().ok();
+                thread::spawn(move || {
+                    while work_once(&calls, &handlers, &send, &errors, wake_up.as_mut()) {}
+                })
+            })
+            .collect();
+
+        wake.set_nonblocking(true)
+            .map_err(|e| (None, HandleError::Connection(e.into())))?;
+        Ok(Pool {
+            calls: Some(call_tx),
+            errors,
+            wake,
+            workers,
+        })
+    }
+
+    /// Blocks until the connection can be read from or a worker reported an error
+    #[allow(clippy::result_large_err)]
+    fn wait_readable(
+        &self,
+        pool: &Pool<UserError>,
+    ) -> std::result::Result<(), PooledError<UserError>> {
+        use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+        use std::os::fd::AsFd;
+
+        let mut pollfds = [
+            PollFd::new(self.recv.as_fd(), PollFlags::POLLIN),
+            PollFd::new(pool.wake.as_fd(), PollFlags::POLLIN),
+        ];
+        loop {
+            match poll(&mut pollfds, PollTimeout::NONE) {
+                Ok(_) => break,
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => return Err((None, HandleError::Connection(Error::IoError(e.into())))),
+            }
+        }
+        // the error itself is taken from the channel
+        let _ = (&pool.wake).read(&mut [0; 16]);
+        Ok(())
+    }
+}
+
+/// Take the next call from the shared channel and handle it. False once the channel is closed.
+fn work_once<UserData, UserError: std::fmt::Debug>(
+    calls: &Mutex<mpsc::Receiver<MarshalledMessage>>,
+    handlers: &Handlers<UserData, UserError>,
+    send: &Arc<Mutex<SendConn>>,
+    errors: &mpsc::Sender<PooledError<UserError>>,
+    wake_up: Option<&mut UnixStream>,
+) -> bool {
+    let msg = {
+        let calls = calls.lock().unwrap_or_else(|e| e.into_inner());
+        match calls.recv() {
+            Ok(msg) => msg,
+            // the pool was dropped
+            Err(_) => return false,
+        }
+    };
+    if let Err(error) = handle_call(handlers, send, &msg) {
+        let _ = errors.send((Some(msg), error));
+        if let Some(wake_up) = wake_up {
+            let _ = wake_up.write_all(&[0]);
+        }
+    }
+    true
+}
+
+fn handle_call<UserData, UserError: std::fmt::Debug>(
+    handlers: &Handlers<UserData, UserError>,
+    send: &Arc<Mutex<SendConn>>,
+    msg: &MarshalledMessage,
+) -> std::result::Result<(), HandleError<UserError>> {
+    let mut env = PooledEnvironment {
+        conn: send.clone(),
+        object: msg.dynheader.object.clone().unwrap_or_default(),
+        member: msg.dynheader.member.clone().unwrap_or_default(),
+    };
+    // the context may be left half changed, handlers that can panic should keep it consistent
+    let dispatched = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        handlers.dispatch(msg, &mut env)
+    }));
+    let response = match dispatched {
+        Ok(result) => result?.unwrap_or_else(|| msg.dynheader.make_response()),
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("no message");
+            log::error!(
+                "rustbus: the handler for {:?} on {:?} panicked: {}",
+                env.member,
+                env.object,
+                reason
+            );
+            msg.dynheader.make_error_response(
+                crate::consts::DBUS_ERROR_FAILED,
+                Some("The handler of the call panicked".to_owned()),
+            )
+        }
+    };
+    send_response(send, &response)?;
+    Ok(())
+}
+
+fn send_response(send: &Arc<Mutex<SendConn>>, response: &MarshalledMessage) -> Result<()> {
+    let mut send = send.lock().unwrap_or_else(|e| e.into_inner());
+    send.send_message_write_all(response)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(rustbus_loom))]
+    use crate::connection::rpc_conn::RpcConn;
+    use crate::MessageBuilder;
+    #[cfg(not(rustbus_loom))]
+    use std::sync::Condvar;
+
+    /// Blocks `Wait` calls until a `Release` call arrived
+    #[cfg(not(rustbus_loom))]
+    #[derive(Default)]
+    struct Gate {
+        released: std::sync::Mutex<bool>,
+        cond: Condvar,
+    }
+
+    /// Two workers take calls from the shared channel and answer them over the shared SendConn
+    #[cfg(rustbus_loom)]
+    #[test]
+    fn loom_workers_share_calls_and_conn() {
+        loom::model(|| {
+            let (service, mut client) = DuplexConn::pair().unwrap();
+            let handlers = Arc::new(Handlers::<(), ()> {
+                ctx: (),
+                objects: RouteTable::default(),
+                default_handler: Box::new(|_, _, msg, _| Ok(Some(msg.dynheader.make_response()))),
+            });
+            let send = Arc::new(Mutex::new(service.send));
+            let (call_tx, call_rx) = mpsc::channel();
+            let calls = Arc::new(Mutex::new(call_rx));
+            let (error_tx, errors) = mpsc::channel();
+
+            let mut serials = Vec::new();
+            for _ in 0..2 {
+                let mut call = MessageBuilder::new().call("Work").on("/").build();
+                let serial = client.send.alloc_serial();
+                call.dynheader.serial = Some(serial);
+                serials.push(serial);
+                call_tx.send(call).unwrap();
+            }
+
+            let workers: Vec<_> = (0..2)
+                .map(|_| {
+                    let calls = calls.clone();
+                    let handlers = handlers.clone();
+                    let send = send.clone();
+                    let errors = error_tx.clone();
+                    thread::spawn(move || {
+                        assert!(work_once(&calls, &handlers, &send, &errors, None));
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+
+            assert!(errors.try_recv().is_err());
+            let mut answered: Vec<_> = (0..2)
+                .map(|_| {
+                    let reply = client.recv.get_next_message(Timeout::Infinite).unwrap();
+                    reply.dynheader.response_serial.unwrap()
+                })
+                .collect();
+            answered.sort();
+            assert_eq!(answered, serials);
+        });
+    }
+
+    #[cfg(not(rustbus_loom))]
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn test_pooled_dispatch() {
+        let (service, client) = DuplexConn::pair().unwrap();
+        let mut dpcon = PooledDispatchConn::<Gate, &'static str>::new(
+            service,
+            Gate::default(),
+            2,
+            Box::new(|_, _, _, _| Err(HandleError::User("unknown call"))),
+        );
+        dpcon.add_member_handler(
+            "/gate",
+            "io.killing.spark.Gate",
+            "Wait",
+            Box::new(|gate: &Gate, _, _, _| {
+                let released = gate.released.lock().unwrap();
+                drop(
+                    gate.cond
+                        .wait_while(released, |released| !*released)
+                        .unwrap(),
+                );
+                Ok(None)
+            }),
+        );
+        dpcon.add_member_handler(
+            "/gate",
+            "io.killing.spark.Gate",
+            "Release",
+            Box::new(|gate: &Gate, _, _, _| {
+                *gate.released.lock().unwrap() = true;
+                gate.cond.notify_all();
+                Ok(None)
+            }),
+        );
+        let service = std::thread::spawn(move || dpcon.run());
+
+        let mut client = RpcConn::new(client);
+        let call = |member: &str| {
+            MessageBuilder::new()
+                .call(member)
+                .with_interface("io.killing.spark.Gate")
+                .on("/gate")
+                .build()
+        };
+        // the release is handled by the second worker while the first one waits
+        let mut wait = client
+            .start_call(&mut call("Wait"), Timeout::Infinite)
+            .unwrap();
+        let mut release = client
+            .start_call(&mut call("Release"), Timeout::Infinite)
+            .unwrap();
+        let released = release.wait(&mut client, Timeout::Infinite).unwrap();
+        assert_eq!(released.typ, MessageType::Reply);
+        let waited = wait.wait(&mut client, Timeout::Infinite).unwrap();
+        assert_eq!(waited.typ, MessageType::Reply);
+
+        let mut ping = MessageBuilder::new()
+            .call("Ping")
+            .with_interface(crate::peer::PEER_INTERFACE)
+            .on("/")
+            .build();
+        let pong = client.call_method(&mut ping, Timeout::Infinite).unwrap();
+        assert_eq!(pong.typ, MessageType::Reply);
+
+        // errors of the handlers end the run, even without further messages
+        let mut unknown = MessageBuilder::new().call("Unknown").on("/other").build();
+        client
+            .send_message(&mut unknown)
+            .unwrap()
+            .write_all()
+            .unwrap();
+        let (msg, error) = service.join().unwrap().unwrap_err();
+        assert_eq!(msg.unwrap().dynheader.member.as_deref(), Some("Unknown"));
+        assert!(matches!(error, HandleError::User("unknown call")));
+    }
+
+    #[cfg(not(rustbus_loom))]
+    #[test]
+    fn test_handler_panics() {
+        let (service, client) = DuplexConn::pair().unwrap();
+        let mut dpcon = PooledDispatchConn::<(), ()>::new(
+            service,
+            (),
+            1,
+            Box::new(|_, _, msg, _| match msg.dynheader.member.as_deref() {
+                Some("Panic") => panic!("the handler panicked on purpose"),
+                _ => Ok(None),
+            }),
+        );
+        std::thread::spawn(move || {
+            let _ = dpcon.run();
+        });
+
+        let mut client = RpcConn::new(client);
+        let mut call = |member: &str| {
+            let mut call = MessageBuilder::new().call(member).on("/").build();
+            client.call_method(&mut call, Timeout::Infinite).unwrap()
+        };
+        let reply = call("Panic");
+        assert_eq!(reply.typ, MessageType::Error);
+        assert_eq!(
+            reply.dynheader.error_name.as_deref(),
+            Some(crate::consts::DBUS_ERROR_FAILED)
+        );
+        // the only worker is still there
+        assert_eq!(call("Work").typ, MessageType::Reply);
+    }
+
+    #[cfg(not(rustbus_loom))]
+    #[test]
+    fn test_queue_is_bounded() {
+        const CALLS: usize = 2000;
+
+        let (service, client) = DuplexConn::pair().unwrap();
+        let gate = Arc::new(Gate::default());
+        let mut dpcon = PooledDispatchConn::<Arc<Gate>, ()>::new(
+            service,
+            gate.clone(),
+            1,
+            Box::new(|gate: &Arc<Gate>, _, _, _| {
+                let released = gate.released.lock().unwrap();
+                drop(
+                    gate.cond
+                        .wait_while(released, |released| !*released)
+                        .unwrap(),
+                );
+                Ok(None)
+            }),
+        );
+        dpcon.set_max_queued_calls(1);
+        std::thread::spawn(move || {
+            let _ = dpcon.run();
+        });
+
+        let DuplexConn { mut send, mut recv } = client;
+        for _ in 0..CALLS {
+            let mut call = MessageBuilder::new().call("Wait").on("/").build();
+            call.body.push_param(vec![0u8; 4096]).unwrap();
+            send.queue_message_owned(call).unwrap();
+        }
+        // the worker waits on the gate, so the service stops reading once one call is queued
+        assert!(matches!(
+            send.flush(Timeout::Duration(std::time::Duration::from_millis(200))),
+            Err(Error::TimedOut)
+        ));
+
+        *gate.released.lock().unwrap() = true;
+        gate.cond.notify_all();
+        let replies = std::thread::spawn(move || {
+            for _ in 0..CALLS {
+                let reply = recv.get_next_message(Timeout::Infinite).unwrap();
+                assert_eq!(reply.typ, MessageType::Reply);
+            }
+        });
+        send.flush(Timeout::Infinite).unwrap();
+        replies.join().unwrap();
+    }
+}
@@ -0,0 +1,404 @@
+//! Portal-style request objects for a `DispatchConn`.
+//!
+//! Portals like the ones of xdg-desktop-portal answer calls that need user interaction right away with the path of a
+//! request object and report the outcome later with the `Response` signal of that object. The caller can end the
+//! interaction early by calling `Close` on the object, no `Response` is emitted then. `Requests` allocates the object
+//! paths and answers `Close` once it was passed to `DispatchConn::add_requests`. The object is gone once the `Request`
+//! has been responded to, closed or dropped. A closed request no longer holds on to the connection, so the caller sees
+//! the connection closed once the service is done with it, even if the `Request` is still around.
+//!
+//! ```rust
+//! use std::sync::mpsc;
+//!
+//! use rustbus::connection::dispatch_conn::DispatchConn;
+//! use rustbus::connection::portal_request::{Requests, Response};
+//! use rustbus::wire::{ObjectPath, VarDict};
+//! use rustbus::{connection::Timeout, DuplexConn, MessageBuilder, RpcConn};
+//!
+//! let requests = Requests::new("/io/killing/spark/portal");
+//! let (service, client) = DuplexConn::pair().unwrap();
+//! let (sender, pending) = mpsc::channel();
+//! let handler_requests = requests.clone();
+//! std::thread::spawn(move || {
+//!     let mut dpcon = DispatchConn::<(), ()>::new(
+//!         service,
+//!         (),
+//!         Box::new(move |_, _, call, env| {
+//!             let options: VarDict = call.body.parser().get()?;
+//!             let token = options.get::<&str>("handle_token")?;
+//!             let request = handler_requests.create(call, token, env.conn.clone())?;
+//!             let mut reply = call.dynheader.make_response();
+//!             reply.body.push_param(ObjectPath::new(request.path()).unwrap())?;
+//!             // the user interaction happens elsewhere, it responds once it is done
+//!             sender.send(request).unwrap();
+//!             Ok(Some(reply))
+//!         }),
+//!     );
+//!     dpcon.add_requests(requests);
+//!     let _ = dpcon.run();
+//! });
+//!
+//! let mut client = RpcConn::new(client);
+//! let mut call = MessageBuilder::new().call("PickColor").on("/io/killing/spark/portal").build();
+//! let mut options = VarDict::new();
+//! options.insert("handle_token", "pick1").unwrap();
+//! call.body.push_param(&options).unwrap();
+//! let reply = client.call_method(&mut call, Timeout::Infinite).unwrap();
+//! let path: ObjectPath<&str> = reply.body.parser().get().unwrap();
+//! assert_eq!(path.as_str(), "/io/killing/spark/portal/request/pick1");
+//!
+//! let mut results = VarDict::new();
+//! results.insert("color", (0.5f64, 0.5f64, 0.5f64)).unwrap();
+//! pending.recv().unwrap().respond(Response::Success, &results).unwrap();
+//! let signal = client.wait_signal(Timeout::Infinite).unwrap();
+//! assert_eq!(signal.body.parser().get::<u32>(), Ok(0));
+//! ```
+
+use std::collections::HashMap;
+
+use super::ll_conn::{self, SendConn};
+use super::Error;
+use crate::message_builder::{MarshalledMessage, MessageBuilder};
+use crate::standard_messages::unknown_method;
+use crate::sync::{Arc, Mutex, MutexGuard};
+use crate::wire::errors::MarshalError;
+use crate::wire::VarDict;
+
+pub const REQUEST_INTERFACE: &str = "org.freedesktop.portal.Request";
+
+/// The outcome of a request, sent as the first parameter of `Response`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    Success,
+    /// The user cancelled the interaction
+    Cancelled,
+    /// The interaction ended some other way
+    Other,
+}
+
+impl Response {
+    pub fn code(self) -> u32 {
+        match self {
+            Response::Success => 0,
+            Response::Cancelled => 1,
+            Response::Other => 2,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// The paths of the requests that have neither been responded to nor closed, with the connections their
+    /// `Response` is sent on
+    open: HashMap<String, Arc<Mutex<SendConn>>>,
+    next_token: u64,
+}
+
+/// Allocates request objects below a base path and answers `Close` for them. Clones share the same requests.
+#[derive(Clone)]
+pub struct Requests {
+    base: String,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Requests {
+    /// Requests below `base`, e.g. `/org/freedesktop/portal/desktop`
+    pub fn new(base: &str) -> Self {
+        Requests {
+            base: base.trim_end_matches('/').to_owned(),
+            registry: Arc::default(),
+        }
+    }
+
+    /// Create the request object for `call`. Its path is `<base>/request/<sender>/<token>`, so callers can compute it
+    /// and subscribe to the `Response` before they make the call. `sender` is the unique name of the caller without
+    /// the leading ':' and with '.' replaced by '_', it is left out for calls without a sender. `token` is the
+    /// `handle_token` the caller passed in its options. A token is generated if there is none, if it is not a valid
+    /// path element or if a request with the same path is still open.
+    ///
+    /// `Response` is sent to the caller over `conn`, usually the `HandleEnvironment::conn` of the handler.
+    pub fn create(
+        &self,
+        call: &MarshalledMessage,
+        handle_token: Option<&str>,
+        conn: Arc<Mutex<SendConn>>,
+    ) -> Result<Request, Error> {
+        let mut prefix = format!("{}/request/", self.base);
+        if let Some(sender) = &call.dynheader.sender {
+            prefix.push_str(&sender.trim_start_matches(':').replace('.', "_"));
+            prefix.push('/');
+        }
+        let mut registry = self.lock();
+        let mut path = handle_token
+            .filter(|token| is_path_element(token))
+            .map(|token| format!("{}{}", prefix, token));
+        while path
+            .as_ref()
+            .is_none_or(|path| registry.open.contains_key(path))
+        {
+            path = Some(format!("{}rustbus{}", prefix, registry.next_token));
+            registry.next_token += 1;
+        }
+        let path = path.expect("the loop only ends with a path");
+        crate::params::validate_object_path(&path).map_err(MarshalError::from)?;
+        registry.open.insert(path.clone(), conn);
+        Ok(Request {
+            path,
+            destination: call.dynheader.sender.clone(),
+            registry: self.registry.clone(),
+            responded: false,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Answer a call to an open request object. Returns None for all other messages.
+    pub(crate) fn handle_call(&self, msg: &MarshalledMessage) -> Option<MarshalledMessage> {
+        let class = msg.classify();
+        let object = class.object?;
+        if class.typ != crate::message_builder::MessageType::Call
+            || class.interface != Some(REQUEST_INTERFACE)
+        {
+            return None;
+        }
+        let mut registry = self.lock();
+        if !registry.open.contains_key(object) {
+            return None;
+        }
+        if class.member == Some("Close") {
+            registry.open.remove(object);
+            Some(msg.dynheader.make_response())
+        } else {
+            Some(unknown_method(&msg.dynheader))
+        }
+    }
+}
+
+/// A request object. Dropping it without responding sends `Response::Other` to the caller, so it does not wait forever.
+pub struct Request {
+    path: String,
+    destination: Option<String>,
+    registry: Arc<Mutex<Registry>>,
+    responded: bool,
+}
+
+impl Request {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Whether the caller closed the request. The interaction should be ended then, there is nobody waiting for it.
+    pub fn is_closed(&self) -> bool {
+        !self.lock().open.contains_key(&self.path)
+    }
+
+    /// Send `Response` to the caller and remove the request object. Nothing is sent if the caller closed the request,
+    /// false is returned then.
+    pub fn respond(mut self, response: Response, results: &VarDict) -> Result<bool, Error> {
+        self.responded = true;
+        self.send_response(response, results)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send_response(&self, response: Response, results: &VarDict) -> Result<bool, Error> {
+        let Some(conn) = self.lock().open.remove(&self.path) else {
+            return Ok(false);
+        };
+        let mut signal = MessageBuilder::new()
+            .signal(REQUEST_INTERFACE, "Response", self.path.as_str())
+            .build();
+        signal.dynheader.destination = self.destination.clone();
+        signal.body.push_param(response.code())?;
+        signal.body.push_param(results)?;
+        conn.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send_message(&signal)?
+            .write_all()
+            .map_err(ll_conn::force_finish_on_error)?;
+        Ok(true)
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        if !self.responded {
+            // there is nobody to report an error to
+            let _ = self.send_response(Response::Other, &VarDict::new());
+        }
+    }
+}
+
+/// Whether `token` can be used as an element of an object path
+fn is_path_element(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::connection::dispatch_conn::DispatchConn;
+    use crate::connection::Timeout;
+    use crate::standard_messages::DBUS_ERROR_UNKNOWN_METHOD;
+    use crate::{DuplexConn, RpcConn};
+
+    #[test]
+    fn test_requests() {
+        let requests = Requests::new("/portal/");
+        let (service, client) = DuplexConn::pair().unwrap();
+        let (sender, pending) = mpsc::channel();
+        let handler_requests = requests.clone();
+        std::thread::spawn(move || {
+            let mut dpcon = DispatchConn::<(), ()>::new(
+                service,
+                (),
+                Box::new(move |_, _, call: &MarshalledMessage, env| {
+                    let token = call.body.parser().get::<&str>().ok();
+                    let request = handler_requests.create(call, token, env.conn.clone())?;
+                    let mut reply = call.dynheader.make_response();
+                    reply.body.push_param(request.path())?;
+                    sender.send(request).unwrap();
+                    Ok(Some(reply))
+                }),
+            );
+            dpcon.add_requests(requests);
+            let _ = dpcon.run();
+        });
+
+        let mut client = RpcConn::new(client);
+        let mut start = |token: &str| {
+            let mut call = MessageBuilder::new().call("Start").on("/portal").build();
+            if !token.is_empty() {
+                call.body.push_param(token).unwrap();
+            }
+            let reply = client.call_method(&mut call, Timeout::Infinite).unwrap();
+            let path = reply.body.parser().get::<String>().unwrap();
+            (path, pending.recv().unwrap())
+        };
+
+        let (path, first) = start("first");
+        assert_eq!(path, "/portal/request/first");
+        assert_eq!(first.path(), path);
+        // the path of an open request is not handed out twice, bad tokens are replaced
+        let (path, second) = start("first");
+        assert_eq!(path, "/portal/request/rustbus0");
+        let (path, third) = start("not-a-token");
+        assert_eq!(path, "/portal/request/rustbus1");
+        let (path, fourth) = start("");
+        assert_eq!(path, "/portal/request/rustbus2");
+
+        let mut results = VarDict::new();
+        results.insert("uri", "file:///tmp/shot.png").unwrap();
+        assert!(first.respond(Response::Success, &results).unwrap());
+
+        let mut request_call = |member: &str, path: &str| {
+            let mut call = MessageBuilder::new()
+                .call(member)
+                .with_interface(REQUEST_INTERFACE)
+                .on(path)
+                .build();
+            client.call_method(&mut call, Timeout::Infinite).unwrap()
+        };
+        assert!(request_call("Close", "/portal/request/rustbus0")
+            .dynheader
+            .error_name
+            .is_none());
+        assert!(second.is_closed());
+        assert!(!second.respond(Response::Success, &results).unwrap());
+        assert_eq!(
+            request_call("Close", "/portal/request/rustbus0")
+                .dynheader
+                .error_name
+                .as_deref(),
+            None,
+            "closed requests are left to the handlers"
+        );
+        assert_eq!(
+            request_call("Open", "/portal/request/rustbus1")
+                .dynheader
+                .error_name
+                .as_deref(),
+            Some(DBUS_ERROR_UNKNOWN_METHOD)
+        );
+        drop(third);
+        assert!(fourth
+            .respond(Response::Cancelled, &VarDict::new())
+            .unwrap());
+
+        let mut responses = Vec::new();
+        while responses.len() < 3 {
+            let signal = client.wait_signal(Timeout::Infinite).unwrap();
+            assert!(signal.classify().is_signal(REQUEST_INTERFACE, "Response"));
+            let (code, results) = signal.body.parser().get2::<u32, VarDict>().unwrap();
+            let uri = results.get::<String>("uri").unwrap();
+            let path = signal.dynheader.object.clone().unwrap();
+            responses.push((path, code, uri));
+        }
+        assert_eq!(
+            responses,
+            [
+                (
+                    "/portal/request/first".to_owned(),
+                    0,
+                    Some("file:///tmp/shot.png".to_owned())
+                ),
+                ("/portal/request/rustbus1".to_owned(), 2, None),
+                ("/portal/request/rustbus2".to_owned(), 1, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_close_releases_conn() {
+        let requests = Requests::new("/portal");
+        let (service, client) = DuplexConn::pair().unwrap();
+        let (sender, pending) = mpsc::channel();
+        let handler_requests = requests.clone();
+        std::thread::spawn(move || {
+            let mut dpcon = DispatchConn::<(), ()>::new(
+                service,
+                (),
+                Box::new(move |_, _, call: &MarshalledMessage, env| {
+                    if env.member() == "Stop" {
+                        return Err(crate::connection::dispatch_conn::HandleError::User(()));
+                    }
+                    let request = handler_requests.create(call, None, env.conn.clone())?;
+                    sender.send(request).unwrap();
+                    Ok(None)
+                }),
+            );
+            dpcon.add_requests(requests);
+            let _ = dpcon.run();
+        });
+
+        let mut client = RpcConn::new(client);
+        let mut call = MessageBuilder::new().call("Start").on("/portal").build();
+        client.call_method(&mut call, Timeout::Infinite).unwrap();
+        let request = pending.recv().unwrap();
+        let mut close = MessageBuilder::new()
+            .call("Close")
+            .with_interface(REQUEST_INTERFACE)
+            .on(request.path())
+            .build();
+        client.call_method(&mut close, Timeout::Infinite).unwrap();
+        assert!(request.is_closed());
+
+        // the service stops and drops its end, the closed request that is still around does not keep it open
+        let mut stop = MessageBuilder::new().call("Stop").on("/portal").build();
+        let timeout = Timeout::Duration(std::time::Duration::from_secs(10));
+        assert!(matches!(
+            client.call_method(&mut stop, timeout),
+            Err(Error::ConnectionClosed)
+        ));
+        drop(request);
+    }
+}
@@ -0,0 +1,920 @@
+//! The service side of `org.freedesktop.DBus.Properties` for a `DispatchConn`.
+//!
+//! Properties are registered per object path and interface. A DispatchConn that was given the `Properties` with
+//! `DispatchConn::add_properties` answers `Get`, `Set` and `GetAll` for the registered objects before the calls reach
+//! the handlers. Changing a value through its `PropertyHandle`, or a `Set` from a client, emits `PropertiesChanged`.
+//!
+//! With `Properties::set_object_manager` the registry also serves `org.freedesktop.DBus.ObjectManager` for the objects
+//! below a root path. Interfaces that are registered or removed later are announced with `InterfacesAdded` and
+//! `InterfacesRemoved`. `Properties::interface` registers an interface with all its properties at once, so it is
+//! announced with all of them. The interfaces that handlers are added for on a DispatchConn with these properties are
+//! objects of the object manager as well, if their path has no captures or wildcards. `DispatchConn::remove_object`
+//! and `HandleEnvironment::remove_object` remove them again.
+//!
+//! ```rust
+//! use rustbus::connection::dispatch_conn::DispatchConn;
+//! use rustbus::connection::properties::{Access, Properties};
+//! use rustbus::wire::unmarshal::traits::Variant;
+//! use rustbus::{connection::Timeout, DuplexConn, MessageBuilder, RpcConn};
+//!
+//! let properties = Properties::new();
+//! let volume = properties
+//!     .register("/io/killing/spark", "io.killing.spark.Speaker", "Volume", Access::ReadWrite, 42u32)
+//!     .unwrap();
+//!
+//! let (service, client) = DuplexConn::pair().unwrap();
+//! std::thread::spawn(move || {
+//!     let mut dpcon = DispatchConn::<(), ()>::new(service, (), Box::new(|_, _, _, _| Ok(None)));
+//!     dpcon.add_properties(properties);
+//!     dpcon.run()
+//! });
+//!
+//! let mut client = RpcConn::new(client);
+//! let mut call = MessageBuilder::new()
+//!     .call("Get")
+//!     .with_interface("org.freedesktop.DBus.Properties")
+//!     .on("/io/killing/spark")
+//!     .build();
+//! call.body.push_param("io.killing.spark.Speaker").unwrap();
+//! call.body.push_param("Volume").unwrap();
+//! let resp = client.call_method(&mut call, Timeout::Infinite).unwrap();
+//! assert_eq!(resp.body.parser().get::<Variant>().unwrap().get::<u32>(), Ok(42));
+//! assert_eq!(volume.get(), 42);
+//! ```
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use super::ll_conn::{self, SendConn};
+use super::object_manager::OBJECT_MANAGER_INTERFACE;
+use super::Error;
+use crate::consts::{
+    DBUS_ERROR_INVALID_ARGS, DBUS_ERROR_PROPERTY_READ_ONLY, DBUS_ERROR_UNKNOWN_INTERFACE,
+    DBUS_ERROR_UNKNOWN_PROPERTY,
+};
+use crate::message_builder::{MarshalledMessage, MessageBuilder, MessageType};
+use crate::standard_messages::{invalid_args, unknown_method};
+use crate::sync::{Arc, Mutex, MutexGuard};
+use crate::wire::errors::{MarshalError, UnmarshalError};
+use crate::wire::unmarshal::traits::Variant;
+use crate::wire::{ObjectPath, VarDict};
+use crate::{Marshal, Signature, Unmarshal};
+
+pub use crate::consts::PROPERTIES_INTERFACE;
+
+/// Whether clients may change a property with `Set`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    ReadWrite,
+}
+
+#[derive(Default)]
+struct Interface {
+    values: VarDict,
+    access: HashMap<String, Access>,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// object path -> interface name -> properties
+    objects: HashMap<String, HashMap<String, Interface>>,
+    /// The connections `PropertiesChanged` is emitted on
+    conns: Vec<Arc<Mutex<SendConn>>>,
+    /// The path the object manager is served at
+    object_manager: Option<String>,
+}
+
+impl Registry {
+    /// Whether `object` is managed by the object manager
+    fn is_managed(&self, object: &str) -> bool {
+        match self.object_manager.as_deref() {
+            Some("/") => object != "/",
+            Some(root) => object
+                .strip_prefix(root)
+                .is_some_and(|rest| rest.starts_with('/')),
+            None => false,
+        }
+    }
+
+    /// `InterfacesAdded` for the given interfaces of `object`, None if the object is not managed
+    fn interfaces_added(
+        &self,
+        object: &str,
+        names: &[&str],
+    ) -> Result<Option<MarshalledMessage>, MarshalError> {
+        let (Some(root), Some(interfaces)) = (&self.object_manager, self.objects.get(object))
+        else {
+            return Ok(None);
+        };
+        if !self.is_managed(object) {
+            return Ok(None);
+        }
+        let added: HashMap<&str, &VarDict> = names
+            .iter()
+            .filter_map(|name| Some((*name, &interfaces.get(*name)?.values)))
+            .collect();
+        let mut signal = MessageBuilder::new()
+            .signal(OBJECT_MANAGER_INTERFACE, "InterfacesAdded", root.as_str())
+            .build();
+        signal.body.push_param(ObjectPath::new(object)?)?;
+        signal.body.push_param(added)?;
+        Ok(Some(signal))
+    }
+
+    /// `InterfacesRemoved` for the given interfaces of `object`, None if the object is not managed
+    fn interfaces_removed(
+        &self,
+        object: &str,
+        names: &[&str],
+    ) -> Result<Option<MarshalledMessage>, MarshalError> {
+        let Some(root) = &self.object_manager else {
+            return Ok(None);
+        };
+        if !self.is_managed(object) {
+            return Ok(None);
+        }
+        let mut signal = MessageBuilder::new()
+            .signal(OBJECT_MANAGER_INTERFACE, "InterfacesRemoved", root.as_str())
+            .build();
+        signal.body.push_param(ObjectPath::new(object)?)?;
+        signal.body.push_param(names)?;
+        Ok(Some(signal))
+    }
+
+    /// The reply to `GetManagedObjects`
+    fn managed_objects(&self, call: &MarshalledMessage) -> Result<MarshalledMessage, MarshalError> {
+        let mut objects = HashMap::new();
+        for (object, interfaces) in &self.objects {
+            if self.is_managed(object) {
+                let interfaces: HashMap<&str, &VarDict> = interfaces
+                    .iter()
+                    .map(|(name, iface)| (name.as_str(), &iface.values))
+                    .collect();
+                objects.insert(ObjectPath::new(object.as_str())?, interfaces);
+            }
+        }
+        let mut resp = call.dynheader.make_response();
+        resp.body.push_param(objects)?;
+        Ok(resp)
+    }
+}
+
+/// The registered properties. Clones share the same properties.
+#[derive(Clone, Default)]
+pub struct Properties {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Properties {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a property with its initial value, replacing a previous property with the same name. The returned
+    /// handle reads and changes the value. If this adds an interface to a managed object it is announced with
+    /// `InterfacesAdded`, use `interface` to announce an interface together with all its properties.
+    pub fn register<T: Marshal + Signature>(
+        &self,
+        object: &str,
+        interface: &str,
+        name: &str,
+        access: Access,
+        value: T,
+    ) -> Result<PropertyHandle<T>, Error> {
+        let mut registry = self.lock();
+        let interfaces = registry.objects.entry(object.to_owned()).or_default();
+        let added = !interfaces.contains_key(interface);
+        let iface = interfaces.entry(interface.to_owned()).or_default();
+        iface.values.insert(name, value)?;
+        iface.access.insert(name.to_owned(), access);
+        let signal = if added {
+            registry.interfaces_added(object, &[interface])?
+        } else {
+            None
+        };
+        emit(registry, signal)?;
+        Ok(self.handle(object, interface, name))
+    }
+
+    /// Register an interface together with its properties, replacing an interface with the same name. Objects can
+    /// also have interfaces without properties, which the object manager lists all the same.
+    pub fn interface(&self, object: &str, interface: &str) -> InterfaceBuilder<'_> {
+        InterfaceBuilder {
+            properties: self,
+            object: object.to_owned(),
+            interface: interface.to_owned(),
+            iface: Interface::default(),
+        }
+    }
+
+    /// Remove all properties of an object, e.g. when it is deleted. Returns false if it had none.
+    pub fn remove_object(&self, object: &str) -> Result<bool, Error> {
+        let mut registry = self.lock();
+        let Some(interfaces) = registry.objects.remove(object) else {
+            return Ok(false);
+        };
+        let names: Vec<&str> = interfaces.keys().map(String::as_str).collect();
+        let signal = registry.interfaces_removed(object, &names)?;
+        emit(registry, signal)?;
+        Ok(true)
+    }
+
+    /// Remove one interface of an object. Returns false if the object did not have it.
+    pub fn remove_interface(&self, object: &str, interface: &str) -> Result<bool, Error> {
+        let mut registry = self.lock();
+        let Some(interfaces) = registry.objects.get_mut(object) else {
+            return Ok(false);
+        };
+        if interfaces.remove(interface).is_none() {
+            return Ok(false);
+        }
+        if interfaces.is_empty() {
+            registry.objects.remove(object);
+        }
+        let signal = registry.interfaces_removed(object, &[interface])?;
+        emit(registry, signal)?;
+        Ok(true)
+    }
+
+    /// Add `interface` to `object` without properties, unless the object already has it. This is how the interfaces
+    /// of the handlers of a DispatchConn are announced with `InterfacesAdded` and listed by `GetManagedObjects`.
+    pub(crate) fn add_handled_interface(&self, object: &str, interface: &str) -> Result<(), Error> {
+        crate::params::validate_object_path(object).map_err(MarshalError::from)?;
+        let mut registry = self.lock();
+        let interfaces = registry.objects.entry(object.to_owned()).or_default();
+        if interfaces.contains_key(interface) {
+            return Ok(());
+        }
+        interfaces.insert(interface.to_owned(), Interface::default());
+        let signal = registry.interfaces_added(object, &[interface])?;
+        emit(registry, signal)
+    }
+
+    /// Serve `org.freedesktop.DBus.ObjectManager` at `root` for the registered objects below it. Interfaces that are
+    /// registered or removed from now on are announced with `InterfacesAdded` and `InterfacesRemoved`.
+    pub fn set_object_manager(&self, root: &str) {
+        self.lock().object_manager = Some(root.to_owned());
+    }
+
+    fn handle<T>(&self, object: &str, interface: &str, name: &str) -> PropertyHandle<T> {
+        PropertyHandle {
+            registry: self.registry.clone(),
+            object: object.to_owned(),
+            interface: interface.to_owned(),
+            name: name.to_owned(),
+            typ: PhantomData,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn add_conn(&self, conn: Arc<Mutex<SendConn>>) {
+        self.lock().conns.push(conn);
+    }
+
+    pub(crate) fn remove_conn(&self, conn: &Arc<Mutex<SendConn>>) {
+        self.lock().conns.retain(|other| !Arc::ptr_eq(other, conn));
+    }
+
+    /// Answer a call to the properties interface of a registered object or to the object manager. Returns None for all
+    /// other messages.
+    pub(crate) fn handle_call(
+        &self,
+        msg: &MarshalledMessage,
+    ) -> Result<Option<MarshalledMessage>, Error> {
+        if msg.typ != MessageType::Call {
+            return Ok(None);
+        }
+        let Some(object) = msg.dynheader.object.as_deref() else {
+            return Ok(None);
+        };
+        match msg.dynheader.interface.as_deref() {
+            Some(PROPERTIES_INTERFACE) => self.handle_properties_call(object, msg),
+            Some(OBJECT_MANAGER_INTERFACE) => {
+                let registry = self.lock();
+                if registry.object_manager.as_deref() != Some(object) {
+                    return Ok(None);
+                }
+                match msg.dynheader.member.as_deref() {
+                    Some("GetManagedObjects") => Ok(Some(registry.managed_objects(msg)?)),
+                    _ => Ok(Some(unknown_method(&msg.dynheader))),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn handle_properties_call(
+        &self,
+        object: &str,
+        msg: &MarshalledMessage,
+    ) -> Result<Option<MarshalledMessage>, Error> {
+        let mut registry = self.lock();
+        let Some(interfaces) = registry.objects.get_mut(object) else {
+            return Ok(None);
+        };
+        let call = &msg.dynheader;
+
+        let mut parser = msg.body.parser();
+        let interface_name = match parser.get::<&str>() {
+            Ok(name) => name,
+            Err(_) => return Ok(Some(invalid_args(call, Some("s")))),
+        };
+        let Some(iface) = interfaces.get_mut(interface_name) else {
+            let text = format!("{} has no interface {}", object, interface_name);
+            return Ok(Some(
+                call.make_error_response(DBUS_ERROR_UNKNOWN_INTERFACE, Some(text)),
+            ));
+        };
+        let unknown_property = |name: &str| {
+            let text = format!("{} has no property {}", interface_name, name);
+            call.make_error_response(DBUS_ERROR_UNKNOWN_PROPERTY, Some(text))
+        };
+
+        match call.member.as_deref() {
+            Some("Get") => {
+                let Ok(name) = parser.get::<&str>() else {
+                    return Ok(Some(invalid_args(call, Some("ss"))));
+                };
+                let Some(value) = iface.values.get_variant(name) else {
+                    return Ok(Some(unknown_property(name)));
+                };
+                let mut resp = call.make_response();
+                resp.body.push_param(value)?;
+                Ok(Some(resp))
+            }
+            Some("GetAll") => {
+                let mut resp = call.make_response();
+                resp.body.push_param(&iface.values)?;
+                Ok(Some(resp))
+            }
+            Some("Set") => {
+                let Ok((name, value)) = parser.get2::<&str, Variant>() else {
+                    return Ok(Some(invalid_args(call, Some("ssv"))));
+                };
+                match iface.access.get(name) {
+                    None => return Ok(Some(unknown_property(name))),
+                    Some(Access::Read) => {
+                        let text = format!("{}.{} is read-only", interface_name, name);
+                        return Ok(Some(
+                            call.make_error_response(DBUS_ERROR_PROPERTY_READ_ONLY, Some(text)),
+                        ));
+                    }
+                    Some(Access::ReadWrite) => {}
+                }
+                if iface.values.value_sig(name) != Some(value.get_value_sig()) {
+                    let text = format!("{}.{} has a different type", interface_name, name);
+                    return Ok(Some(
+                        call.make_error_response(DBUS_ERROR_INVALID_ARGS, Some(text)),
+                    ));
+                }
+                iface.values.insert_variant(name, &value)?;
+                let signal = changed_signal(object, interface_name, &iface.values, name)?;
+                emit(registry, Some(signal))?;
+                Ok(Some(call.make_response()))
+            }
+            _ => Ok(Some(unknown_method(call))),
+        }
+    }
+}
+
+/// `PropertiesChanged` for the property `name`
+fn changed_signal(
+    object: &str,
+    interface: &str,
+    values: &VarDict,
+    name: &str,
+) -> Result<MarshalledMessage, MarshalError> {
+    let changed = values.select(name);
+    let mut signal = MessageBuilder::new()
+        .signal(PROPERTIES_INTERFACE, "PropertiesChanged", object)
+        .build();
+    signal.body.push_param(interface)?;
+    signal.body.push_param(&changed)?;
+    signal.body.push_param(Vec::<&str>::new())?;
+    Ok(signal)
+}
+
+/// Send `signal` on the connections of the registry, which is unlocked first. A connection that fails does not keep
+/// the signal from the others, the errors are returned together as `Error::SignalNotSent`.
+fn emit(
+    registry: MutexGuard<'_, Registry>,
+    signal: Option<MarshalledMessage>,
+) -> Result<(), Error> {
+    let Some(signal) = signal else {
+        return Ok(());
+    };
+    let conns = registry.conns.clone();
+    drop(registry);
+    let errors: Vec<Error> = conns
+        .iter()
+        .filter_map(|conn| {
+            conn.lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .send_message(&signal)
+                .and_then(|ctx| ctx.write_all().map_err(ll_conn::force_finish_on_error))
+                .err()
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::SignalNotSent(errors))
+    }
+}
+
+/// Collects the properties of an interface, see `Properties::interface`
+pub struct InterfaceBuilder<'a> {
+    properties: &'a Properties,
+    object: String,
+    interface: String,
+    iface: Interface,
+}
+
+impl InterfaceBuilder<'_> {
+    /// Add a property with its initial value. The handle only finds the value once the interface has been added.
+    pub fn property<T: Marshal + Signature>(
+        &mut self,
+        name: &str,
+        access: Access,
+        value: T,
+    ) -> Result<PropertyHandle<T>, MarshalError> {
+        self.iface.values.insert(name, value)?;
+        self.iface.access.insert(name.to_owned(), access);
+        Ok(self.properties.handle(&self.object, &self.interface, name))
+    }
+
+    /// Register the interface and announce it with `InterfacesAdded` if the object is managed
+    pub fn add(self) -> Result<(), Error> {
+        let mut registry = self.properties.lock();
+        registry
+            .objects
+            .entry(self.object.clone())
+            .or_default()
+            .insert(self.interface.clone(), self.iface);
+        let signal = registry.interfaces_added(&self.object, &[self.interface.as_str()])?;
+        emit(registry, signal)
+    }
+}
+
+/// Reads and changes the value of a registered property
+pub struct PropertyHandle<T> {
+    registry: Arc<Mutex<Registry>>,
+    object: String,
+    interface: String,
+    name: String,
+    typ: PhantomData<fn() -> T>,
+}
+
+impl<T: Marshal + Signature> PropertyHandle<T> {
+    /// The current value, which a client may have changed with `Set`. Returns `Ok(None)` if the object was removed and
+    /// `UnmarshalError::WrongSignature` if the property was registered again with another type.
+    pub fn try_get(&self) -> Result<Option<T>, UnmarshalError>
+    where
+        T: for<'a> Unmarshal<'a, 'a>,
+    {
+        let registry = self.registry.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(iface) = registry
+            .objects
+            .get(&self.object)
+            .and_then(|ifaces| ifaces.get(&self.interface))
+        else {
+            return Ok(None);
+        };
+        iface.values.get(&self.name)
+    }
+
+    /// The current value, which a client may have changed with `Set`.
+    ///
+    /// Panics if the object was removed or the value has another type, see `try_get`.
+    pub fn get(&self) -> T
+    where
+        T: for<'a> Unmarshal<'a, 'a>,
+    {
+        self.try_get()
+            .expect("the property has another type")
+            .expect("the object of the property has been removed")
+    }
+
+    /// Change the value and emit `PropertiesChanged` on the connections of the DispatchConn. Nothing is emitted if the
+    /// object was removed. If some of the connections fail the signal is still sent on the others and their errors are
+    /// returned as `Error::SignalNotSent`, the value is changed either way.
+    pub fn set(&self, value: T) -> Result<(), Error> {
+        let mut registry = self.registry.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(iface) = registry
+            .objects
+            .get_mut(&self.object)
+            .and_then(|interfaces| interfaces.get_mut(&self.interface))
+        else {
+            return Ok(());
+        };
+        iface.values.insert(self.name.as_str(), value)?;
+        let signal = changed_signal(&self.object, &self.interface, &iface.values, &self.name)?;
+        emit(registry, Some(signal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::dispatch_conn::DispatchConn;
+    use crate::connection::Timeout;
+    use crate::{DuplexConn, RpcConn};
+
+    fn properties_call(member: &str, object: &str) -> MarshalledMessage {
+        MessageBuilder::new()
+            .call(member)
+            .with_interface(PROPERTIES_INTERFACE)
+            .on(object)
+            .build()
+    }
+
+    #[test]
+    fn test_properties() {
+        let properties = Properties::new();
+        let volume = properties
+            .register(
+                "/speaker",
+                "io.killing.spark.Speaker",
+                "Volume",
+                Access::ReadWrite,
+                42u32,
+            )
+            .unwrap();
+        let name = properties
+            .register(
+                "/speaker",
+                "io.killing.spark.Speaker",
+                "Name",
+                Access::Read,
+                "front".to_owned(),
+            )
+            .unwrap();
+
+        let (service, client) = DuplexConn::pair().unwrap();
+        std::thread::spawn(move || {
+            let mut dpcon = DispatchConn::<(), ()>::new(
+                service,
+                (),
+                Box::new(|_, _, msg: &MarshalledMessage, _| {
+                    Ok(Some(unknown_method(&msg.dynheader)))
+                }),
+            );
+            dpcon.add_properties(properties);
+            let _ = dpcon.run();
+        });
+        let mut client = RpcConn::new(client);
+        let mut call = |member: &str, object: &str, params: &dyn Fn(&mut MarshalledMessage)| {
+            let mut call = properties_call(member, object);
+            params(&mut call);
+            client.call_method(&mut call, Timeout::Infinite).unwrap()
+        };
+        let error_name = |msg: &MarshalledMessage| msg.dynheader.error_name.clone();
+
+        let resp = call("GetAll", "/speaker", &|call| {
+            call.body.push_param("io.killing.spark.Speaker").unwrap()
+        });
+        let all: VarDict = resp.body.parser().get().unwrap();
+        assert_eq!(all.get::<u32>("Volume"), Ok(Some(42)));
+        assert_eq!(all.get::<&str>("Name"), Ok(Some("front")));
+
+        let resp = call("Set", "/speaker", &|call| {
+            call.body.push_param("io.killing.spark.Speaker").unwrap();
+            call.body.push_param("Volume").unwrap();
+            call.body.push_variant(7u32).unwrap();
+        });
+        assert_eq!(resp.typ, MessageType::Reply);
+        assert_eq!(volume.get(), 7);
+
+        let resp = call("Get", "/speaker", &|call| {
+            call.body.push_param("io.killing.spark.Speaker").unwrap();
+            call.body.push_param("Volume").unwrap();
+        });
+        assert_eq!(resp.body.parser().get::<Variant>().unwrap().get(), Ok(7u32));
+
+        // errors
+        let resp = call("Set", "/speaker", &|call| {
+            call.body.push_param("io.killing.spark.Speaker").unwrap();
+            call.body.push_param("Name").unwrap();
+            call.body.push_variant("back").unwrap();
+        });
+        assert_eq!(
+            error_name(&resp).as_deref(),
+            Some(DBUS_ERROR_PROPERTY_READ_ONLY)
+        );
+        let resp = call("Set", "/speaker", &|call| {
+            call.body.push_param("io.killing.spark.Speaker").unwrap();
+            call.body.push_param("Volume").unwrap();
+            call.body.push_variant("loud").unwrap();
+        });
+        assert_eq!(error_name(&resp).as_deref(), Some(DBUS_ERROR_INVALID_ARGS));
+        let resp = call("Get", "/speaker", &|call| {
+            call.body.push_param("io.killing.spark.Speaker").unwrap();
+            call.body.push_param("Balance").unwrap();
+        });
+        assert_eq!(
+            error_name(&resp).as_deref(),
+            Some(DBUS_ERROR_UNKNOWN_PROPERTY)
+        );
+        let resp = call("GetAll", "/speaker", &|call| {
+            call.body.push_param("io.killing.spark.Microphone").unwrap()
+        });
+        assert_eq!(
+            error_name(&resp).as_deref(),
+            Some(DBUS_ERROR_UNKNOWN_INTERFACE)
+        );
+        // objects without properties are left to the handlers
+        let resp = call("GetAll", "/microphone", &|call| {
+            call.body.push_param("io.killing.spark.Microphone").unwrap()
+        });
+        assert_eq!(
+            error_name(&resp).as_deref(),
+            Some(crate::standard_messages::DBUS_ERROR_UNKNOWN_METHOD)
+        );
+
+        // the changes by the client and the service were both announced
+        name.set("rear".to_owned()).unwrap();
+        let mut changes = Vec::new();
+        while changes.len() < 2 {
+            let signal = client.wait_signal(Timeout::Infinite).unwrap();
+            assert_eq!(
+                signal.dynheader.member.as_deref(),
+                Some("PropertiesChanged")
+            );
+            let (iface, changed, invalidated) = signal
+                .body
+                .parser()
+                .get3::<&str, VarDict, Vec<&str>>()
+                .unwrap();
+            assert_eq!(iface, "io.killing.spark.Speaker");
+            assert!(invalidated.is_empty());
+            let key = changed.keys().next().unwrap().to_owned();
+            changes.push(key);
+        }
+        assert_eq!(changes, ["Volume", "Name"]);
+        assert_eq!(name.get(), "rear");
+    }
+
+    #[test]
+    fn test_emit_on_all_conns() {
+        let properties = Properties::new();
+        let volume = properties
+            .register(
+                "/speaker",
+                "io.killing.spark.Speaker",
+                "Volume",
+                Access::Read,
+                42u32,
+            )
+            .unwrap();
+
+        // the peer of the first connection is gone, the signal still reaches the second one
+        let (closed, peer) = DuplexConn::pair().unwrap();
+        drop(peer);
+        let (open, mut peer) = DuplexConn::pair().unwrap();
+        properties.add_conn(Arc::new(Mutex::new(closed.send)));
+        properties.add_conn(Arc::new(Mutex::new(open.send)));
+
+        match volume.set(7) {
+            Err(Error::SignalNotSent(errors)) => assert_eq!(errors.len(), 1),
+            other => panic!("expected SignalNotSent, got {:?}", other),
+        }
+        let signal = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        assert_eq!(
+            signal.dynheader.member.as_deref(),
+            Some("PropertiesChanged")
+        );
+        assert_eq!(volume.get(), 7);
+    }
+
+    #[test]
+    fn test_handle_type_changed() {
+        let properties = Properties::new();
+        let volume = properties
+            .register(
+                "/speaker",
+                "io.killing.spark.Speaker",
+                "Volume",
+                Access::Read,
+                42u32,
+            )
+            .unwrap();
+        properties
+            .register(
+                "/speaker",
+                "io.killing.spark.Speaker",
+                "Volume",
+                Access::Read,
+                "loud",
+            )
+            .unwrap();
+        assert_eq!(volume.try_get(), Err(UnmarshalError::WrongSignature));
+    }
+
+    #[test]
+    fn test_object_manager() {
+        use crate::connection::object_manager::{get_managed_objects, ManagedObjects};
+
+        let properties = Properties::new();
+        properties.set_object_manager("/disks");
+        properties
+            .register(
+                "/disks",
+                "io.killing.spark.Manager",
+                "Count",
+                Access::Read,
+                1u32,
+            )
+            .unwrap();
+        properties
+            .register(
+                "/disks/sda",
+                "io.killing.spark.Disk",
+                "Size",
+                Access::Read,
+                512u64,
+            )
+            .unwrap();
+
+        let (service, client) = DuplexConn::pair().unwrap();
+        let served = properties.clone();
+        std::thread::spawn(move || {
+            let mut dpcon = DispatchConn::<(), ()>::new(
+                service,
+                (),
+                Box::new(|_, _, msg: &MarshalledMessage, _| {
+                    Ok(Some(unknown_method(&msg.dynheader)))
+                }),
+            );
+            dpcon.add_properties(served);
+            let _ = dpcon.run();
+        });
+        let mut client = RpcConn::new(client);
+        let get_objects = |client: &mut RpcConn| {
+            let mut call = get_managed_objects("io.killing.spark", "/disks");
+            let resp = client.call_method(&mut call, Timeout::Infinite).unwrap();
+            resp.body.parser().get::<ManagedObjects>().unwrap()
+        };
+
+        // the manager itself is not one of its objects
+        let mut objects = get_objects(&mut client);
+        assert_eq!(objects.objects().count(), 1);
+        let sda = objects
+            .properties("/disks/sda", "io.killing.spark.Disk")
+            .unwrap();
+        assert_eq!(sda.get::<u64>("Size"), Ok(Some(512)));
+
+        let mut disk = properties.interface("/disks/sdb", "io.killing.spark.Disk");
+        let size = disk.property("Size", Access::Read, 1024u64).unwrap();
+        disk.property("Label", Access::ReadWrite, "backup").unwrap();
+        assert_eq!(size.try_get(), Ok(None));
+        disk.add().unwrap();
+        assert_eq!(size.get(), 1024);
+        properties
+            .interface("/disks/sdb", "io.killing.spark.Mountable")
+            .add()
+            .unwrap();
+        properties
+            .remove_interface("/disks/sdb", "io.killing.spark.Mountable")
+            .unwrap();
+        assert!(properties.remove_object("/disks/sda").unwrap());
+        // not managed, so there is no signal
+        properties
+            .register(
+                "/other",
+                "io.killing.spark.Disk",
+                "Size",
+                Access::Read,
+                0u64,
+            )
+            .unwrap();
+
+        let mut applied = 0;
+        while applied < 4 {
+            let signal = client.wait_signal(Timeout::Infinite).unwrap();
+            assert_eq!(signal.dynheader.object.as_deref(), Some("/disks"));
+            assert!(objects.apply_signal(&signal).unwrap());
+            applied += 1;
+        }
+        assert!(objects.interfaces("/disks/sda").is_none());
+        let sdb = objects.interfaces("/disks/sdb").unwrap();
+        assert_eq!(sdb.len(), 1);
+        assert_eq!(
+            sdb["io.killing.spark.Disk"].get::<&str>("Label"),
+            Ok(Some("backup"))
+        );
+        let with_disk: Vec<_> = objects.with_interface("io.killing.spark.Disk").collect();
+        assert_eq!(with_disk.len(), 1);
+        assert_eq!(with_disk[0].0.as_str(), "/disks/sdb");
+        assert_eq!(get_objects(&mut client).into_inner().len(), 1);
+    }
+
+    #[test]
+    fn test_object_manager_handlers() {
+        use crate::connection::object_manager::{get_managed_objects, ManagedObjects};
+
+        let properties = Properties::new();
+        properties.set_object_manager("/disks");
+
+        let (service, client) = DuplexConn::pair().unwrap();
+        std::thread::spawn(move || {
+            let mut dpcon = DispatchConn::<(), ()>::new(
+                service,
+                (),
+                Box::new(|_, _, msg: &MarshalledMessage, _| {
+                    Ok(Some(unknown_method(&msg.dynheader)))
+                }),
+            );
+            // added before the properties, announced once they are there
+            dpcon.add_interface_handler(
+                "/disks/sda",
+                "io.killing.spark.Disk",
+                Box::new(|_, _, _, env| {
+                    let object = env.object().to_owned();
+                    env.remove_object(&object);
+                    Ok(None)
+                }),
+            );
+            dpcon.add_properties(properties);
+            dpcon.add_member_handler(
+                "/disks",
+                "io.killing.spark.Manager",
+                "Add",
+                Box::new(|_, _, _, env| {
+                    env.new_dispatches.insert_interface(
+                        "/disks/sdb",
+                        "io.killing.spark.Disk",
+                        Box::new(|_, _, _, _| Ok(None)),
+                    );
+                    Ok(None)
+                }),
+            );
+            // patterns are no objects
+            dpcon.add_interface_handler(
+                "/disks/:name",
+                "io.killing.spark.Partitions",
+                Box::new(|_, _, _, _| Ok(None)),
+            );
+            let _ = dpcon.run();
+        });
+        let mut client = RpcConn::new(client);
+        let get_objects = |client: &mut RpcConn| {
+            let mut call = get_managed_objects("io.killing.spark", "/disks");
+            let resp = client.call_method(&mut call, Timeout::Infinite).unwrap();
+            resp.body.parser().get::<ManagedObjects>().unwrap()
+        };
+        let call = |client: &mut RpcConn, object: &str, interface: &str, member: &str| {
+            let mut call = MessageBuilder::new()
+                .call(member)
+                .with_interface(interface)
+                .on(object)
+                .build();
+            client.call_method(&mut call, Timeout::Infinite).unwrap()
+        };
+
+        let mut objects = get_objects(&mut client);
+        assert_eq!(objects.objects().count(), 1);
+        assert!(objects
+            .properties("/disks/sda", "io.killing.spark.Disk")
+            .unwrap()
+            .is_empty());
+
+        // sda was announced when the properties were added, then sdb by the handler
+        call(&mut client, "/disks", "io.killing.spark.Manager", "Add");
+        for object in ["/disks/sda", "/disks/sdb"] {
+            let added = client.wait_signal(Timeout::Infinite).unwrap();
+            assert_eq!(added.dynheader.member.as_deref(), Some("InterfacesAdded"));
+            assert_eq!(
+                added
+                    .body
+                    .parser()
+                    .get::<ObjectPath<&str>>()
+                    .unwrap()
+                    .as_ref(),
+                object
+            );
+            assert!(objects.apply_signal(&added).unwrap());
+        }
+
+        call(&mut client, "/disks/sda", "io.killing.spark.Disk", "Eject");
+        let removed = client.wait_signal(Timeout::Infinite).unwrap();
+        assert_eq!(
+            removed.dynheader.member.as_deref(),
+            Some("InterfacesRemoved")
+        );
+        assert!(objects.apply_signal(&removed).unwrap());
+
+        assert!(objects.interfaces("/disks/sda").is_none());
+        assert!(objects.interfaces("/disks/sdb").is_some());
+        assert_eq!(get_objects(&mut client).into_inner().len(), 1);
+        // the handler of the removed object is gone too
+        let resp = call(&mut client, "/disks/sda", "io.killing.spark.Disk", "Eject");
+        assert_eq!(
+            resp.dynheader.error_name.as_deref(),
+            Some(crate::standard_messages::DBUS_ERROR_UNKNOWN_METHOD)
+        );
+    }
+}
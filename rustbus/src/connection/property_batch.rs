@@ -0,0 +1,288 @@
+//! Set several properties of one interface of a remote object at once
+//!
+//! `org.freedesktop.DBus.Properties` has no call to set more than one property, so a `PropertyBatch` sends one `Set`
+//! call per property. The calls are sent before any reply is waited for, so the batch takes about one round trip instead
+//! of one per property. Failures are reported per property.
+//!
+//! ```rust
+//! use rustbus::connection::dispatch_conn::DispatchConn;
+//! use rustbus::connection::properties::{Access, Properties};
+//! use rustbus::connection::property_batch::PropertyBatch;
+//! use rustbus::{connection::Timeout, DuplexConn, RpcConn};
+//!
+//! let properties = Properties::new();
+//! let volume = properties
+//!     .register("/speaker", "io.killing.spark.Speaker", "Volume", Access::ReadWrite, 42u32)
+//!     .unwrap();
+//! let muted = properties
+//!     .register("/speaker", "io.killing.spark.Speaker", "Muted", Access::ReadWrite, false)
+//!     .unwrap();
+//!
+//! let (service, client) = DuplexConn::pair().unwrap();
+//! std::thread::spawn(move || {
+//!     let mut dpcon = DispatchConn::<(), ()>::new(service, (), Box::new(|_, _, _, _| Ok(None)));
+//!     dpcon.add_properties(properties);
+//!     dpcon.run()
+//! });
+//!
+//! let mut client = RpcConn::new(client);
+//! let mut batch = PropertyBatch::new("io.killing.spark", "/speaker", "io.killing.spark.Speaker");
+//! batch.set("Volume", 7u32).unwrap().set("Muted", true).unwrap();
+//! let failures = batch.apply(&mut client, Timeout::Infinite).unwrap();
+//! assert!(failures.is_empty());
+//! assert_eq!(volume.get(), 7);
+//! assert!(muted.get());
+//! ```
+
+use super::rpc_conn::{PendingReply, RpcConn};
+use super::{Deadline, Result, Timeout};
+use crate::message_builder::{ErrorReply, MarshalledMessage, MessageBuilder};
+use crate::wire::errors::MarshalError;
+use crate::wire::unmarshal::traits::Variant;
+use crate::wire::VarDict;
+use crate::{Marshal, Signature};
+
+use crate::consts::PROPERTIES_INTERFACE;
+
+/// A property that could not be set, or read before setting it in `PropertyBatch::apply_all_or_nothing`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyFailure {
+    pub property: String,
+    /// The error the service answered with
+    pub error: ErrorReply,
+    /// Whether this happened while setting the property back to its old value
+    pub during_rollback: bool,
+}
+
+/// The values to set on one interface of a remote object
+#[derive(Debug, Clone)]
+pub struct PropertyBatch {
+    destination: String,
+    object: String,
+    interface: String,
+    values: VarDict,
+    /// The properties in the order they were added, the calls are sent in this order
+    names: Vec<String>,
+}
+
+impl PropertyBatch {
+    pub fn new<D: Into<String>, O: Into<String>, I: Into<String>>(
+        destination: D,
+        object: O,
+        interface: I,
+    ) -> Self {
+        Self {
+            destination: destination.into(),
+            object: object.into(),
+            interface: interface.into(),
+            values: VarDict::new(),
+            names: Vec::new(),
+        }
+    }
+
+    /// Add a property to the batch, replacing the value if it was added before
+    pub fn set<T: Marshal + Signature>(
+        &mut self,
+        property: &str,
+        value: T,
+    ) -> std::result::Result<&mut Self, MarshalError> {
+        self.values.insert(property, value)?;
+        if !self.names.iter().any(|name| name == property) {
+            self.names.push(property.to_owned());
+        }
+        Ok(self)
+    }
+
+    /// The values that will be set
+    pub fn values(&self) -> &VarDict {
+        &self.values
+    }
+
+    /// Set all properties and return those that could not be set. A failure does not keep the other properties from
+    /// being set.
+    ///
+    /// `timeout` applies to the whole batch. Errors of the connection are returned as errors, nothing is known about the
+    /// properties whose replies have not arrived then.
+    pub fn apply(&self, conn: &mut RpcConn, timeout: Timeout) -> Result<Vec<PropertyFailure>> {
+        let deadline = Deadline::new(timeout);
+        let replies = self.set_all(conn, &self.values, &self.names, deadline)?;
+        Ok(replies
+            .iter()
+            .filter_map(|(name, reply)| failure(name, reply, false))
+            .collect())
+    }
+
+    /// Like `apply` but either all properties are set or none. The current values are read first, if one can not be
+    /// read nothing is set. If one of the properties can not be set, those that were set are set back to their old
+    /// values and all failures are returned, including those of setting the old values.
+    ///
+    /// D-Bus has no transactions, other clients can see the new values before they are set back.
+    pub fn apply_all_or_nothing(
+        &self,
+        conn: &mut RpcConn,
+        timeout: Timeout,
+    ) -> Result<Vec<PropertyFailure>> {
+        let deadline = Deadline::new(timeout);
+
+        let mut pending = Vec::with_capacity(self.names.len());
+        for name in &self.names {
+            let mut call = self.properties_call("Get");
+            call.body.push_param(name.as_str())?;
+            pending.push((name, conn.start_call(&mut call, deadline.remaining()?)?));
+        }
+        let mut old_values = VarDict::new();
+        let mut failures = Vec::new();
+        for (name, reply) in wait_all(conn, pending, deadline)? {
+            match failure(name, &reply, false) {
+                Some(failure) => failures.push(failure),
+                None => old_values.insert_variant(name, &reply.body.parser().get::<Variant>()?)?,
+            }
+        }
+        if !failures.is_empty() {
+            return Ok(failures);
+        }
+
+        let mut set = Vec::new();
+        for (name, reply) in self.set_all(conn, &self.values, &self.names, deadline)? {
+            match failure(name, &reply, false) {
+                Some(failure) => failures.push(failure),
+                None => set.push(name.clone()),
+            }
+        }
+        if failures.is_empty() {
+            return Ok(failures);
+        }
+
+        for (name, reply) in self.set_all(conn, &old_values, &set, deadline)? {
+            failures.extend(failure(name, &reply, true));
+        }
+        Ok(failures)
+    }
+
+    fn properties_call(&self, member: &str) -> MarshalledMessage {
+        let mut call = MessageBuilder::new()
+            .call(member)
+            .with_interface(PROPERTIES_INTERFACE)
+            .on(self.object.clone())
+            .at(self.destination.clone())
+            .build();
+        call.body.push_param(self.interface.as_str()).unwrap();
+        call
+    }
+
+    /// Send a Set call for each of `names` with its value from `values`, then wait for all replies
+    fn set_all<'a>(
+        &self,
+        conn: &mut RpcConn,
+        values: &VarDict,
+        names: &'a [String],
+        deadline: Deadline,
+    ) -> Result<Vec<(&'a String, MarshalledMessage)>> {
+        let mut pending = Vec::with_capacity(names.len());
+        for name in names {
+            let Some(value) = values.get_variant(name) else {
+                continue;
+            };
+            let mut call = self.properties_call("Set");
+            call.body.push_param(name.as_str())?;
+            call.body.push_param(value)?;
+            pending.push((name, conn.start_call(&mut call, deadline.remaining()?)?));
+        }
+        wait_all(conn, pending, deadline)
+    }
+}
+
+fn wait_all<'a>(
+    conn: &mut RpcConn,
+    pending: Vec<(&'a String, PendingReply)>,
+    deadline: Deadline,
+) -> Result<Vec<(&'a String, MarshalledMessage)>> {
+    let mut replies = Vec::with_capacity(pending.len());
+    for (name, mut reply) in pending {
+        replies.push((name, reply.wait(conn, deadline.remaining()?)?));
+    }
+    Ok(replies)
+}
+
+fn failure(
+    property: &str,
+    reply: &MarshalledMessage,
+    during_rollback: bool,
+) -> Option<PropertyFailure> {
+    Some(PropertyFailure {
+        property: property.to_owned(),
+        error: reply.error_reply()?,
+        during_rollback,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::dispatch_conn::DispatchConn;
+    use crate::connection::properties::{Access, Properties};
+    use crate::standard_messages::{DBUS_ERROR_PROPERTY_READ_ONLY, DBUS_ERROR_UNKNOWN_PROPERTY};
+    use crate::DuplexConn;
+
+    #[test]
+    fn test_property_batch() {
+        let properties = Properties::new();
+        let register = |name: &str, access, value: u32| {
+            properties
+                .register("/speaker", "io.killing.spark.Speaker", name, access, value)
+                .unwrap()
+        };
+        let volume = register("Volume", Access::ReadWrite, 42);
+        let balance = register("Balance", Access::ReadWrite, 50);
+        let channels = register("Channels", Access::Read, 2);
+
+        let (service, client) = DuplexConn::pair().unwrap();
+        std::thread::spawn(move || {
+            let mut dpcon =
+                DispatchConn::<(), ()>::new(service, (), Box::new(|_, _, _, _| Ok(None)));
+            dpcon.add_properties(properties);
+            let _ = dpcon.run();
+        });
+        let mut client = RpcConn::new(client);
+        let batch = |values: &[(&str, u32)]| {
+            let mut batch =
+                PropertyBatch::new("io.killing.spark", "/speaker", "io.killing.spark.Speaker");
+            for (name, value) in values {
+                batch.set(name, *value).unwrap();
+            }
+            batch
+        };
+
+        // the read only property fails, the others are set anyway
+        let failures = batch(&[("Volume", 7), ("Channels", 6), ("Balance", 60)])
+            .apply(&mut client, Timeout::Infinite)
+            .unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].property, "Channels");
+        assert_eq!(failures[0].error.name, DBUS_ERROR_PROPERTY_READ_ONLY);
+        assert!(!failures[0].during_rollback);
+        assert_eq!((volume.get(), balance.get(), channels.get()), (7, 60, 2));
+
+        // the volume is set back after the read only property failed
+        let failures = batch(&[("Volume", 8), ("Channels", 6)])
+            .apply_all_or_nothing(&mut client, Timeout::Infinite)
+            .unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].property, "Channels");
+        assert_eq!(volume.get(), 7);
+
+        // an unknown property fails while reading, so nothing is set
+        let failures = batch(&[("Volume", 9), ("Bass", 1)])
+            .apply_all_or_nothing(&mut client, Timeout::Infinite)
+            .unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].error.name, DBUS_ERROR_UNKNOWN_PROPERTY);
+        assert_eq!(volume.get(), 7);
+
+        let failures = batch(&[("Volume", 9), ("Balance", 40)])
+            .apply_all_or_nothing(&mut client, Timeout::Infinite)
+            .unwrap();
+        assert!(failures.is_empty());
+        assert_eq!((volume.get(), balance.get()), (9, 40));
+    }
+}
@@ -0,0 +1,36 @@
+//! What the proxies generated by `rustbus::codegen` need at runtime
+//!
+//! This is not behind the `introspection` feature, so crates that only include generated code do not need it.
+
+use thiserror::Error;
+
+use super::rpc_conn::RpcConn;
+use super::Timeout;
+use crate::message_builder::{ErrorReply, MarshalledMessage};
+use crate::wire::errors::{MarshalError, UnmarshalError};
+
+/// Errors that can occur when calling a method through a generated proxy
+#[derive(Debug, Error)]
+pub enum CallError {
+    #[error("An error occured on the connection: {0}")]
+    Connection(#[from] super::Error),
+    #[error("An error occured while marshalling: {0}")]
+    Marshal(#[from] MarshalError),
+    #[error("An error occured while unmarshalling the reply: {0}")]
+    Unmarshal(#[from] UnmarshalError),
+    #[error("The call returned the error {0}")]
+    ErrorReply(ErrorReply),
+}
+
+/// Send `call` and wait for the reply. Error replies are turned into `CallError::ErrorReply`.
+pub fn call(
+    conn: &mut RpcConn,
+    call: &mut MarshalledMessage,
+    timeout: Timeout,
+) -> Result<MarshalledMessage, CallError> {
+    let reply = conn.call_method(call, timeout)?;
+    if let Some(error) = reply.error_reply() {
+        return Err(CallError::ErrorReply(error));
+    }
+    Ok(reply)
+}
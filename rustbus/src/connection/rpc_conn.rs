@@ -2,12 +2,18 @@
 //! over the Conn struct.
 
 use super::ll_conn::DuplexConn;
+use super::middleware::{IncomingAction, Middleware, MiddlewareChain};
 use super::*;
-use crate::message_builder::{MarshalledMessage, MessageType};
-use std::collections::{HashMap, VecDeque};
+use crate::match_rule::MatchRule;
+use crate::message_builder::{HeaderFlags, MarshalledMessage, MessageBuilder, MessageType};
+use crate::standard_messages::RequestNameReply;
+use crate::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::num::NonZeroU32;
 
 /// Convenience wrapper around the lowlevel connection
+///
+/// RpcConn is Send but not Sync, because middlewares only need to be Send. Put it into a Mutex to share it between threads.
 /// ```rust,no_run
 /// use rustbus::{connection::{Timeout, ll_conn::force_finish_on_error}, standard_messages, MessageBuilder, MessageType, RpcConn};
 /// // Connect to the session bus. This also takes care of the mandatory hello messages
@@ -34,8 +40,327 @@ pub struct RpcConn {
     signals: VecDeque<MarshalledMessage>,
     calls: VecDeque<MarshalledMessage>,
     responses: HashMap<NonZeroU32, MarshalledMessage>,
+    /// Serials of cancelled calls whose responses are dropped when they arrive
+    ignored_responses: HashSet<NonZeroU32>,
+    /// Filled by dropped PendingReplys, processed the next time the RpcConn receives or starts a call
+    cancelled: Arc<Mutex<Vec<CancelledCall>>>,
+    /// Serials of calls whose responses can only be taken through their PendingReply
+    claimed_responses: HashSet<NonZeroU32>,
+    cancel_member: Option<String>,
+    answer_peer_calls: bool,
     conn: DuplexConn,
     filter: MessageFilter,
+    middleware: MiddlewareChain,
+    /// Where the bus address came from, None for connections that were passed to `new`
+    source: Option<BusSource>,
+    address: Option<BusAddress>,
+    /// Added with `add_match`, added again after a reconnect
+    match_rules: Vec<MatchRule>,
+    /// Requested with `request_name` together with their flags, requested again after a reconnect
+    names: Vec<(String, u32)>,
+    subscriptions: Vec<SignalSubscription>,
+    next_subscription_id: u64,
+    /// Filled by dropped Subscriptions, processed the next time the RpcConn receives messages
+    unsubscribed: Arc<Mutex<Vec<u64>>>,
+    #[cfg(debug_assertions)]
+    sent_signals: VecDeque<NonZeroU32>,
+}
+
+/// Where the address of a bus comes from. It is resolved again on every reconnect, so a helper that outlives a login
+/// session finds the session bus of the next one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusSource {
+    /// `$DBUS_SESSION_BUS_ADDRESS`
+    Session,
+    /// The well known path of the system bus
+    System,
+    /// A fixed address
+    Address(BusAddress),
+}
+
+impl BusSource {
+    /// The first address, if the source lists several
+    pub fn resolve(&self) -> Result<BusAddress> {
+        self.resolve_all().map(|mut addresses| addresses.remove(0))
+    }
+
+    /// All addresses in the order they should be tried, `$DBUS_SESSION_BUS_ADDRESS` can list several
+    pub fn resolve_all(&self) -> Result<Vec<BusAddress>> {
+        match self {
+            BusSource::Session => get_session_bus_addresses(),
+            BusSource::System => Ok(vec![BusAddress::Unix(get_system_bus_path()?)]),
+            BusSource::Address(addr) => Ok(vec![addr.clone()]),
+        }
+    }
+
+    /// Connect to the first of the addresses that works, see `BusAddress::connect_any`
+    fn connect(&self, timeout: Timeout) -> Result<(BusAddress, DuplexConn)> {
+        let deadline = Deadline::new(timeout);
+        let addresses = self.resolve_all()?;
+        let (idx, stream) = BusAddress::connect_any(&addresses, deadline.remaining()?)?;
+        let conn = DuplexConn::connect_over_stream(stream, true)?;
+        Ok((addresses[idx].clone(), conn))
+    }
+}
+
+/// A reply that holds one array of strings, like the one to `ListNames`. The strings are borrowed from the reply while
+/// iterating instead of being collected into a `Vec<String>` up front.
+#[derive(Debug)]
+pub struct StringArrayReply {
+    reply: MarshalledMessage,
+}
+
+impl StringArrayReply {
+    /// Returns `Error::UnexpectedMessageTypeReceived` for error replies and `Error::ReplySignatureMismatch` if the reply
+    /// holds anything but an array of strings
+    pub fn new(reply: MarshalledMessage) -> Result<Self> {
+        if reply.typ != MessageType::Reply {
+            return Err(Error::UnexpectedMessageTypeReceived);
+        }
+        if reply.get_sig() != "as" {
+            return Err(Error::ReplySignatureMismatch {
+                member: None,
+                expected: "as".to_owned(),
+                found: reply.get_sig().to_owned(),
+            });
+        }
+        Ok(Self { reply })
+    }
+
+    pub fn iter(&self) -> Result<crate::message_builder::ArrayIter<'_, '_, &str>> {
+        Ok(self.reply.body.parser().get_array_iter()?)
+    }
+
+    /// The strings in batches of up to `size`
+    pub fn chunks(&self, size: usize) -> Result<crate::message_builder::ArrayChunks<'_, '_, &str>> {
+        Ok(self.iter()?.chunks(size))
+    }
+
+    pub fn into_message(self) -> MarshalledMessage {
+        self.reply
+    }
+}
+
+/// How many serials of signals with a destination are remembered to catch waits for replies to them
+#[cfg(debug_assertions)]
+const REMEMBERED_SIGNALS: usize = 32;
+
+/// A call that was sent with `RpcConn::start_call` and whose response has not been taken yet.
+///
+/// Dropping it (or calling `cancel`) cancels the call: the RpcConn forgets the serial, removes the response if it already
+/// arrived and drops it if it arrives later. This way abandoned calls do not pile up responses nobody will ever take.
+///
+/// D-Bus itself has no way to abort a call the peer is already working on. If a service offers a method for that,
+/// `RpcConn::set_cancel_member` makes the RpcConn call it for every call that is cancelled before its response arrived.
+///
+/// The response can only be taken through the PendingReply, `RpcConn::try_get_response` and `RpcConn::wait_response`
+/// do not hand it out. So no other code that waits on the same RpcConn can take it by accident.
+#[must_use = "dropping a PendingReply cancels the call"]
+pub struct PendingReply {
+    serial: NonZeroU32,
+    /// Set with `with_timeout`, waits give up once it has passed
+    deadline: Deadline,
+    /// None once the response was taken, then there is nothing left to cancel
+    call: Option<CancelledCall>,
+    cancelled: Arc<Mutex<Vec<CancelledCall>>>,
+}
+
+/// What is needed to clean up after a cancelled call and to tell the peer about it
+struct CancelledCall {
+    serial: NonZeroU32,
+    destination: Option<String>,
+    object: Option<String>,
+    interface: Option<String>,
+}
+
+impl PendingReply {
+    pub fn serial(&self) -> NonZeroU32 {
+        self.serial
+    }
+
+    /// Give the call `timeout` from now to be answered. Waiting for the response fails with `Error::TimedOut` after that,
+    /// regardless of the timeout passed to `wait`. The call is not cancelled by this, drop the PendingReply for that.
+    pub fn with_timeout(mut self, timeout: Timeout) -> Self {
+        self.deadline = Deadline::new(timeout);
+        self
+    }
+
+    /// Whether the timeout set with `with_timeout` has passed
+    pub fn is_expired(&self) -> bool {
+        self.deadline.expired()
+    }
+
+    /// Return the response if it is there but dont block. `conn` must be the RpcConn that started the call, None is
+    /// returned for all others.
+    pub fn try_get(&mut self, conn: &mut RpcConn) -> Option<MarshalledMessage> {
+        if !self.started_on(conn) {
+            return None;
+        }
+        let msg = conn.take_claimed_response(self.serial)?;
+        self.call = None;
+        Some(msg)
+    }
+
+    /// Return the response if it is there or block until it arrives. `conn` must be the RpcConn that started the call,
+    /// `Error::PendingReplyOfOtherConnection` is returned for all others.
+    ///
+    /// This waits for `timeout` or until the timeout set with `with_timeout` runs out, whichever comes first. Running into
+    /// the timeout does not cancel the call, it can be waited for again. The response can only be taken once.
+    pub fn wait(&mut self, conn: &mut RpcConn, timeout: Timeout) -> Result<MarshalledMessage> {
+        if !self.started_on(conn) {
+            return Err(Error::PendingReplyOfOtherConnection);
+        }
+        let deadline = earlier(self.deadline, Deadline::new(timeout));
+        loop {
+            if let Some(msg) = self.try_get(conn) {
+                return Ok(msg);
+            }
+            conn.refill_once(deadline.remaining()?)?;
+        }
+    }
+
+    /// Cancel the call, same as dropping the PendingReply
+    pub fn cancel(self) {}
+
+    fn started_on(&self, conn: &RpcConn) -> bool {
+        Arc::ptr_eq(&self.cancelled, &conn.cancelled)
+    }
+}
+
+/// The PendingReplys of the calls sent with `RpcConn::start_calls`. Dropping it cancels the calls whose responses have
+/// not been collected.
+#[must_use = "dropping PendingReplies cancels the calls"]
+pub struct PendingReplies {
+    /// In the order of the calls, None once the response was collected
+    pending: Vec<Option<PendingReply>>,
+    remaining: usize,
+}
+
+impl PendingReplies {
+    /// How many responses have not been collected yet
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Iterate over the responses in the order they arrive. Each comes with the index of its call in the slice that was
+    /// passed to `start_calls`. `conn` must be the RpcConn that sent the calls.
+    ///
+    /// `timeout` applies to all responses together. After an error the iterator ends, the responses that were not
+    /// collected can be collected with another call to this.
+    pub fn collect_replies<'a>(
+        &'a mut self,
+        conn: &'a mut RpcConn,
+        timeout: Timeout,
+    ) -> CollectReplies<'a> {
+        CollectReplies {
+            replies: self,
+            conn,
+            deadline: Deadline::new(timeout),
+            failed: false,
+        }
+    }
+
+    /// Take the response of one of the calls if it is there
+    fn try_take(&mut self, conn: &mut RpcConn) -> Option<(usize, MarshalledMessage)> {
+        for (idx, pending) in self.pending.iter_mut().enumerate() {
+            let Some(reply) = pending.as_mut() else {
+                continue;
+            };
+            if let Some(msg) = reply.try_get(conn) {
+                *pending = None;
+                self.remaining -= 1;
+                return Some((idx, msg));
+            }
+        }
+        None
+    }
+}
+
+/// Returned by `PendingReplies::collect_replies`
+pub struct CollectReplies<'a> {
+    replies: &'a mut PendingReplies,
+    conn: &'a mut RpcConn,
+    deadline: Deadline,
+    failed: bool,
+}
+
+impl Iterator for CollectReplies<'_> {
+    type Item = Result<(usize, MarshalledMessage)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.replies.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(reply) = self.replies.try_take(self.conn) {
+                return Some(Ok(reply));
+            }
+            let refilled = self
+                .deadline
+                .remaining()
+                .and_then(|timeout| self.conn.refill_once(timeout));
+            if let Err(e) = refilled {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// The deadline that passes first
+fn earlier(a: Deadline, b: Deadline) -> Deadline {
+    match (a, b) {
+        (Deadline::Nonblock, _) | (_, Deadline::Nonblock) => Deadline::Nonblock,
+        (Deadline::Never, other) | (other, Deadline::Never) => other,
+        (Deadline::At(a), Deadline::At(b)) => Deadline::At(a.min(b)),
+    }
+}
+
+impl Drop for PendingReply {
+    fn drop(&mut self) {
+        if let Some(call) = self.call.take() {
+            self.cancelled
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(call);
+        }
+    }
+}
+
+/// Called with every signal that matches the rule of a subscription, see `RpcConn::subscribe`
+pub type SignalHandler = Box<dyn FnMut(&MarshalledMessage) + Send>;
+
+struct SignalSubscription {
+    id: u64,
+    rule: MatchRule,
+    handler: SignalHandler,
+}
+
+/// Returned by `RpcConn::subscribe`. Dropping it ends the subscription: the next time the RpcConn receives messages it
+/// forgets the handler and removes the match rule from the bus, without waiting for the reply. Use
+/// `RpcConn::unsubscribe` to do this right away.
+#[must_use = "dropping a Subscription unsubscribes"]
+pub struct Subscription {
+    id: u64,
+    /// None once the subscription was ended with `RpcConn::unsubscribe`
+    unsubscribed: Option<Arc<Mutex<Vec<u64>>>>,
+}
+
+impl Subscription {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(unsubscribed) = self.unsubscribed.take() {
+            unsubscribed
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(self.id);
+        }
+    }
 }
 
 /// Filter out messages you dont want in your RpcConn.
@@ -74,14 +399,41 @@ pub struct RpcConn {
 /// ```
 pub type MessageFilter = Box<dyn Fn(&MarshalledMessage) -> bool + Sync + Send>;
 
+/// Calls without a destination go to the bus, which only implements the org.freedesktop.DBus interfaces. Anything else
+/// was probably meant for a service on the bus.
+fn lacks_destination(msg: &MarshalledMessage) -> bool {
+    msg.typ == MessageType::Call
+        && msg.dynheader.destination.is_none()
+        && !msg
+            .dynheader
+            .interface
+            .as_deref()
+            .is_some_and(|interface| interface.starts_with(crate::consts::DBUS_INTERFACE))
+}
+
 impl RpcConn {
     pub fn new(conn: DuplexConn) -> Self {
         RpcConn {
             signals: VecDeque::new(),
             calls: VecDeque::new(),
             responses: HashMap::new(),
+            ignored_responses: HashSet::new(),
+            cancelled: Arc::new(Mutex::new(Vec::new())),
+            claimed_responses: HashSet::new(),
+            cancel_member: None,
+            answer_peer_calls: false,
             conn,
             filter: Box::new(|_| true),
+            middleware: MiddlewareChain::new(),
+            source: None,
+            address: None,
+            match_rules: Vec::new(),
+            names: Vec::new(),
+            subscriptions: Vec::new(),
+            next_subscription_id: 0,
+            unsubscribed: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(debug_assertions)]
+            sent_signals: VecDeque::new(),
         }
     }
     pub fn conn(&self) -> &DuplexConn {
@@ -97,50 +449,533 @@ impl RpcConn {
     }
 
     pub fn session_conn(timeout: Timeout) -> Result<Self> {
-        let session_path = get_session_bus_path()?;
-        Self::connect_to_path(session_path, timeout)
+        Self::connect_to_source(BusSource::Session, timeout)
     }
 
     pub fn system_conn(timeout: Timeout) -> Result<Self> {
-        let session_path = get_system_bus_path()?;
-        Self::connect_to_path(session_path, timeout)
+        Self::connect_to_source(BusSource::System, timeout)
     }
 
     pub fn connect_to_path(path: UnixAddr, timeout: Timeout) -> Result<Self> {
-        let con = DuplexConn::connect_to_bus(path, true)?;
-        let mut con = Self::new(con);
+        Self::connect_to_source(BusSource::Address(BusAddress::Unix(path)), timeout)
+    }
 
+    /// Resolve the address of the bus, connect to it and send the hello message. The source is remembered for `reconnect`.
+    pub fn connect_to_source(source: BusSource, timeout: Timeout) -> Result<Self> {
+        let deadline = Deadline::new(timeout);
+        let (address, conn) = source.connect(deadline.remaining()?)?;
+        let mut con = Self::new(conn);
+        con.source = Some(source);
+        con.address = Some(address);
+        con.send_hello(deadline.remaining()?)?;
+        Ok(con)
+    }
+
+    fn send_hello(&mut self, timeout: Timeout) -> Result<()> {
+        let deadline = Deadline::new(timeout);
         let mut hello = crate::standard_messages::hello();
-        let serial = con
+        let serial = self
             .send_message(&mut hello)?
-            .write(timeout)
+            .write(deadline.remaining()?)
             .map_err(super::ll_conn::force_finish_on_error)?;
 
-        con.wait_response(serial, timeout)?;
-        Ok(con)
+        self.wait_response(serial, deadline.remaining()?)?;
+        Ok(())
+    }
+
+    /// The address this RpcConn is connected to, if it was connected with one of the constructors
+    pub fn address(&self) -> Option<&BusAddress> {
+        self.address.as_ref()
+    }
+
+    /// Where the address of the bus comes from, if this RpcConn was connected with one of the constructors
+    pub fn source(&self) -> Option<&BusSource> {
+        self.source.as_ref()
+    }
+
+    /// Resolve the address of the bus again, e.g. read `$DBUS_SESSION_BUS_ADDRESS` anew, connect to it and send the hello
+    /// message. Returns `Error::NoAddressFound` for RpcConns that were created with `new`.
+    ///
+    /// The new connection is a new client of the bus. Match rules that were added with `add_match` are added again and
+    /// names that were requested with `request_name` are requested again, with the same flags. Other match rules and
+    /// names have to be restored by the caller. Responses to calls on the old connection will never arrive, so they
+    /// are forgotten. PendingReplys of those calls are treated like ones of another RpcConn. Queued signals and calls
+    /// are kept. Filter and middlewares stay in place.
+    ///
+    /// If another connection took one of the names in the meantime, the name is forgotten and `Error::NameTaken` is
+    /// returned after everything else has been restored. If requesting a name fails otherwise, that name and the ones
+    /// that were not requested yet are kept, so the next `reconnect` requests them again.
+    pub fn reconnect(&mut self, timeout: Timeout) -> Result<()> {
+        let deadline = Deadline::new(timeout);
+        let source = self.source.as_ref().ok_or(Error::NoAddressFound)?;
+        let (address, conn) = source.connect(deadline.remaining()?)?;
+        self.conn = conn;
+        self.address = Some(address);
+        self.responses.clear();
+        self.ignored_responses.clear();
+        self.claimed_responses.clear();
+        // serials start at 1 again, PendingReplys of the old connection must not take or cancel the new calls
+        self.cancelled = Arc::new(Mutex::new(Vec::new()));
+        #[cfg(debug_assertions)]
+        self.sent_signals.clear();
+        self.send_hello(deadline.remaining()?)?;
+        for rule in self.match_rules.clone() {
+            self.send_match_call(&rule, true, deadline.remaining()?)?;
+        }
+        let mut taken = false;
+        let mut names = std::mem::take(&mut self.names).into_iter();
+        while let Some((name, flags)) = names.next() {
+            let res = deadline
+                .remaining()
+                .and_then(|timeout| self.request_name(&name, flags, timeout));
+            match res {
+                Ok(_) => {}
+                Err(Error::NameTaken) => taken = true,
+                Err(e) => {
+                    // keep the names that were not acquired, so the next reconnect tries them again
+                    self.names.push((name, flags));
+                    self.names.extend(names);
+                    return Err(e);
+                }
+            }
+        }
+        if taken {
+            return Err(Error::NameTaken);
+        }
+        Ok(())
+    }
+
+    /// Request `name` on the bus with the `DBUS_NAME_FLAG_*` flags from `standard_messages`. If the connection now owns
+    /// the name or is queued for it, the name is remembered and requested again by `reconnect`.
+    ///
+    /// Returns `Error::NameTaken` if the name has another owner and the connection could neither replace it nor be
+    /// queued, which never happens without `DBUS_NAME_FLAG_DO_NOT_QUEUE`. Returns `Error::NameRequestRejected` if the
+    /// bus answers with an error, e.g. because the name is malformed.
+    pub fn request_name(
+        &mut self,
+        name: &str,
+        flags: u32,
+        timeout: Timeout,
+    ) -> Result<RequestNameReply> {
+        let mut call = crate::standard_messages::request_name(name, flags);
+        let code = self.name_call(name, &mut call, timeout)?;
+        self.names.retain(|(known, _)| known != name);
+        match RequestNameReply::from_code(code) {
+            Some(RequestNameReply::Exists) => Err(Error::NameTaken),
+            Some(reply) => {
+                self.names.push((name.to_owned(), flags));
+                Ok(reply)
+            }
+            None => Err(Error::UnknownRequestNameReply {
+                bus_name: name.to_owned(),
+                code,
+            }),
+        }
+    }
+
+    /// Release a name that was requested before and return the reply code, one of the `DBUS_RELEASE_NAME_REPLY_*`
+    /// constants in `standard_messages`. The name is forgotten even if the bus answers with an error.
+    pub fn release_name(&mut self, name: &str, timeout: Timeout) -> Result<u32> {
+        self.names.retain(|(known, _)| known != name);
+        let mut call = crate::standard_messages::release_name(name);
+        self.name_call(name, &mut call, timeout)
+    }
+
+    /// The names requested with `request_name` that this connection owns or is queued for
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|(name, _)| name.as_str())
+    }
+
+    fn name_call(
+        &mut self,
+        name: &str,
+        call: &mut MarshalledMessage,
+        timeout: Timeout,
+    ) -> Result<u32> {
+        let reply = self.call_method(call, timeout)?;
+        if let Some(error) = reply.error_reply() {
+            return Err(Error::NameRequestRejected {
+                bus_name: name.to_owned(),
+                error,
+            });
+        }
+        Ok(reply.body.parser().get::<u32>()?)
+    }
+
+    /// Ask the bus to forward the messages that match `rule` to this connection. The rule is remembered and added
+    /// again by `reconnect`. A rule that is added twice has to be removed twice, like on the bus.
+    ///
+    /// Returns `Error::MatchRuleRejected` if the bus answers with an error, e.g. because the rule is malformed.
+    pub fn add_match(&mut self, rule: MatchRule, timeout: Timeout) -> Result<()> {
+        self.send_match_call(&rule, true, timeout)?;
+        self.match_rules.push(rule);
+        Ok(())
+    }
+
+    /// Remove a rule that was added before. The rule is forgotten even if the bus answers with an error, which it does
+    /// for rules that are not known to it.
+    pub fn remove_match(&mut self, rule: &MatchRule, timeout: Timeout) -> Result<()> {
+        if let Some(idx) = self.match_rules.iter().position(|known| known == rule) {
+            self.match_rules.remove(idx);
+        }
+        self.send_match_call(rule, false, timeout)
+    }
+
+    /// Add `rule` like `add_match` and call `handler` with every signal that matches it, instead of queueing the signal.
+    /// Signals that match multiple subscriptions are passed to each of them, signals that match none are queued as usual.
+    /// Handlers are called while the RpcConn receives messages, e.g. in `wait_response` or `refill_once`.
+    ///
+    /// The rule is checked locally with `MatchRule::matches` because the bus forwards signals that match any of the rules
+    /// of the connection. A handler can forward the signals (or values parsed from them) into a channel, if they need to
+    /// be processed elsewhere.
+    ///
+    /// ```rust,no_run
+    /// use rustbus::match_rule::MatchRule;
+    /// use rustbus::{connection::Timeout, MessageType, RpcConn};
+    ///
+    /// let mut rpc_con = RpcConn::session_conn(Timeout::Infinite).unwrap();
+    /// let (tx, rx) = std::sync::mpsc::channel();
+    /// let rule = MatchRule::new()
+    ///     .msg_type(MessageType::Signal)
+    ///     .interface("org.freedesktop.DBus")
+    ///     .member("NameOwnerChanged");
+    /// let subscription = rpc_con
+    ///     .subscribe(rule, move |signal| {
+    ///         if let Ok(name) = signal.body.parser().get::<String>() {
+    ///             let _ = tx.send(name);
+    ///         }
+    ///     }, Timeout::Infinite)
+    ///     .unwrap();
+    /// rpc_con.refill_once(Timeout::Infinite).unwrap();
+    /// println!("{:?}", rx.try_recv());
+    /// drop(subscription);
+    /// ```
+    pub fn subscribe<F>(
+        &mut self,
+        rule: MatchRule,
+        handler: F,
+        timeout: Timeout,
+    ) -> Result<Subscription>
+    where
+        F: FnMut(&MarshalledMessage) + Send + 'static,
+    {
+        self.process_unsubscribed()?;
+        self.add_match(rule.clone(), timeout)?;
+        let id = self.next_subscription_id;
+        self.next_subscription_id += 1;
+        self.subscriptions.push(SignalSubscription {
+            id,
+            rule,
+            handler: Box::new(handler),
+        });
+        Ok(Subscription {
+            id,
+            unsubscribed: Some(self.unsubscribed.clone()),
+        })
+    }
+
+    /// End a subscription now and wait for the bus to remove its match rule
+    pub fn unsubscribe(&mut self, mut subscription: Subscription, timeout: Timeout) -> Result<()> {
+        subscription.unsubscribed = None;
+        match self.take_subscription(subscription.id) {
+            Some(rule) => self.remove_match(&rule, timeout),
+            None => Ok(()),
+        }
+    }
+
+    fn take_subscription(&mut self, id: u64) -> Option<MatchRule> {
+        let idx = self.subscriptions.iter().position(|sub| sub.id == id)?;
+        Some(self.subscriptions.remove(idx).rule)
+    }
+
+    /// Remove the subscriptions whose Subscription was dropped. Their RemoveMatch calls are sent but not waited for.
+    fn process_unsubscribed(&mut self) -> Result<()> {
+        let unsubscribed =
+            std::mem::take(&mut *self.unsubscribed.lock().unwrap_or_else(|e| e.into_inner()));
+        for rule in unsubscribed
+            .into_iter()
+            .filter_map(|id| self.take_subscription(id))
+            .collect::<Vec<_>>()
+        {
+            if let Some(idx) = self.match_rules.iter().position(|known| *known == rule) {
+                self.match_rules.remove(idx);
+            }
+            let msg = crate::standard_messages::remove_match(&rule.to_string());
+            let serial = self.send_internal(msg)?;
+            self.cancel(serial);
+        }
+        Ok(())
+    }
+
+    /// Pass a signal to the handlers of the matching subscriptions or queue it if there are none
+    fn dispatch_signal(&mut self, msg: MarshalledMessage) {
+        let mut handled = false;
+        for sub in &mut self.subscriptions {
+            if sub.rule.matches(&msg) {
+                (sub.handler)(&msg);
+                handled = true;
+            }
+        }
+        if !handled {
+            self.signals.push_back(msg);
+        }
+    }
+
+    /// The rules added with `add_match` that have not been removed yet
+    pub fn match_rules(&self) -> &[MatchRule] {
+        &self.match_rules
+    }
+
+    fn send_match_call(&mut self, rule: &MatchRule, add: bool, timeout: Timeout) -> Result<()> {
+        let rule = rule.to_string();
+        let mut call = if add {
+            crate::standard_messages::add_match(&rule)
+        } else {
+            crate::standard_messages::remove_match(&rule)
+        };
+        let reply = self.call_method(&mut call, timeout)?;
+        if let Some(error) = reply.error_reply() {
+            return Err(Error::MatchRuleRejected { rule, error });
+        }
+        Ok(())
+    }
+
+    /// Connect to the unix socket at `path` in the filesystem and send the hello message.
+    pub fn connect_to_socket_path<P: AsRef<std::path::Path>>(
+        path: P,
+        timeout: Timeout,
+    ) -> Result<Self> {
+        let addr = UnixAddr::new(path.as_ref()).map_err(io::Error::from)?;
+        Self::connect_to_path(addr, timeout)
     }
 
     pub fn set_filter(&mut self, filter: MessageFilter) {
         self.filter = filter;
     }
 
-    /// Return a response if one is there but dont block
+    /// Add a middleware that sees all messages sent and received by this RpcConn. Incoming messages pass the middlewares
+    /// before the filter is applied.
+    pub fn add_middleware(&mut self, middleware: Box<dyn Middleware>) {
+        self.middleware.push(middleware);
+    }
+
+    /// Call a method named `member` on the same destination, object and interface for every call that is cancelled before
+    /// its response arrived. The only argument is the serial of the cancelled call as u32 and no reply is expected.
+    ///
+    /// There is no standard for this, so only set it for services that implement such a method. By default no
+    /// message is sent for cancelled calls.
+    pub fn set_cancel_member(&mut self, member: Option<String>) {
+        self.cancel_member = member;
+    }
+
+    /// Answer calls to `org.freedesktop.DBus.Peer` (`Ping` and `GetMachineId`) when they are received, see
+    /// `peer::peer_reply`. They are not queued then and the filter does not see them. Off by default.
+    pub fn set_answer_peer_calls(&mut self, answer: bool) {
+        self.answer_peer_calls = answer;
+    }
+
+    /// Stop tracking the response to `serial`. If it already arrived it is dropped now, otherwise it is dropped when it arrives.
+    ///
+    /// This is the counterpart to dropping a PendingReply for serials from `send_message`. It never sends a message
+    /// to the peer, even if a cancel member is set.
+    pub fn cancel(&mut self, serial: NonZeroU32) {
+        if self.responses.remove(&serial).is_none() {
+            self.ignored_responses.insert(serial);
+        }
+    }
+
+    /// Send a call and return a PendingReply to take the response with, or to cancel the call by dropping it.
+    pub fn start_call(
+        &mut self,
+        msg: &mut crate::message_builder::MarshalledMessage,
+        timeout: Timeout,
+    ) -> Result<PendingReply> {
+        let deadline = Deadline::new(timeout);
+        self.flush(deadline.remaining()?)?;
+        self.process_cancelled()?;
+        let serial = self
+            .send_message(msg)?
+            .write(deadline.remaining()?)
+            .map_err(super::ll_conn::force_finish_on_error)?;
+        Ok(self.pending_reply(serial, msg))
+    }
+
+    /// Send all `calls` and return their PendingReplys, to collect the responses as they arrive. The calls are queued
+    /// first and then written together, so many small calls only take a few syscalls and none of them waits for the
+    /// response to the one before.
+    ///
+    /// If one of the calls can not be sent, the calls queued before it are still written but their responses are
+    /// dropped when they arrive. If the timeout runs out before all calls are written, the rest of them is written by
+    /// the next call, wait or `flush` on this RpcConn.
+    ///
+    /// Messages that arrive while the calls are written are read and queued, so a peer that answers the first calls
+    /// before it reads the next ones does not block the writing.
+    ///
+    /// ```rust
+    /// use rustbus::{connection::Timeout, DuplexConn, MessageBuilder, RpcConn};
+    ///
+    /// let (conn, peer) = DuplexConn::pair().unwrap();
+    /// std::thread::spawn(move || {
+    ///     let mut peer = RpcConn::new(peer);
+    ///     loop {
+    ///         let call = peer.wait_call(Timeout::Infinite).unwrap();
+    ///         let mut reply = call.dynheader.make_response();
+    ///         reply.body.push_param(call.dynheader.member.unwrap()).unwrap();
+    ///         peer.send_message(&mut reply).unwrap().write_all().unwrap();
+    ///     }
+    /// });
+    ///
+    /// let mut rpc_con = RpcConn::new(conn);
+    /// let mut calls: Vec<_> = ["A", "B", "C"]
+    ///     .iter()
+    ///     .map(|member| MessageBuilder::new().call(*member).on("/").build())
+    ///     .collect();
+    /// let mut pending = rpc_con.start_calls(&mut calls, Timeout::Infinite).unwrap();
+    /// for reply in pending.collect_replies(&mut rpc_con, Timeout::Infinite) {
+    ///     let (idx, reply) = reply.unwrap();
+    ///     assert_eq!(reply.body.parser().get::<&str>().unwrap(), calls[idx].dynheader.member.as_deref().unwrap());
+    /// }
+    /// ```
+    pub fn start_calls(
+        &mut self,
+        calls: &mut [MarshalledMessage],
+        timeout: Timeout,
+    ) -> Result<PendingReplies> {
+        self.process_cancelled()?;
+        let mut pending = Vec::with_capacity(calls.len());
+        let mut queued = Ok(());
+        for call in calls.iter_mut() {
+            match self.queue_call(call) {
+                Ok(serial) => pending.push(Some(self.pending_reply(serial, call))),
+                Err(e) => {
+                    queued = Err(e);
+                    break;
+                }
+            }
+        }
+        let flushed = self.flush(timeout);
+        // dropping the PendingReplys on an error cancels the calls that were queued
+        queued?;
+        flushed?;
+        Ok(PendingReplies {
+            remaining: pending.len(),
+            pending,
+        })
+    }
+
+    /// Queue a message with the middlewares applied and return its serial
+    fn queue_call(&mut self, call: &mut MarshalledMessage) -> Result<NonZeroU32> {
+        self.middleware.outgoing(call)?;
+        self.conn.send.queue_message(call)
+    }
+
+    fn pending_reply(&mut self, serial: NonZeroU32, msg: &MarshalledMessage) -> PendingReply {
+        self.claimed_responses.insert(serial);
+        PendingReply {
+            serial,
+            deadline: Deadline::Never,
+            call: Some(CancelledCall {
+                serial,
+                destination: msg.dynheader.destination.clone(),
+                object: msg.dynheader.object.clone(),
+                interface: msg.dynheader.interface.clone(),
+            }),
+            cancelled: self.cancelled.clone(),
+        }
+    }
+
+    /// Forget the calls of dropped PendingReplys and send the cancel calls for them if a cancel member is set
+    fn process_cancelled(&mut self) -> Result<()> {
+        let cancelled =
+            std::mem::take(&mut *self.cancelled.lock().unwrap_or_else(|e| e.into_inner()));
+        let mut cancelled = cancelled.into_iter();
+        while let Some(call) = cancelled.next() {
+            self.claimed_responses.remove(&call.serial);
+            if self.responses.remove(&call.serial).is_some() {
+                // already answered, there is nothing to tell the peer
+                continue;
+            }
+            self.ignored_responses.insert(call.serial);
+            if let (Some(member), Some(object)) = (&self.cancel_member, &call.object) {
+                let mut builder = MessageBuilder::new()
+                    .call(member.clone())
+                    .on(object.clone());
+                if let Some(interface) = &call.interface {
+                    builder = builder.with_interface(interface.clone());
+                }
+                if let Some(destination) = &call.destination {
+                    builder = builder.at(destination.clone());
+                }
+                let mut msg = builder.build();
+                HeaderFlags::NoReplyExpected.set(&mut msg.flags);
+                let res = msg
+                    .body
+                    .push_param(call.serial.get())
+                    .map_err(Error::from)
+                    .and_then(|()| self.send_internal(msg).map(|_| ()));
+                if let Err(e) = res {
+                    // the failed call and the ones after it are cancelled the next time
+                    let remaining: Vec<_> = std::iter::once(call).chain(cancelled).collect();
+                    self.cancelled
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .splice(0..0, remaining);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `msg` answers a cancelled call. The serial is forgotten, since there is only one response per call.
+    fn is_ignored_response(&mut self, msg: &MarshalledMessage) -> bool {
+        matches!(msg.typ, MessageType::Reply | MessageType::Error)
+            && msg
+                .dynheader
+                .response_serial
+                .is_some_and(|serial| self.ignored_responses.remove(&serial))
+    }
+
+    /// Return a response if one is there but dont block. Responses to calls started with `start_call` are only handed
+    /// out by their PendingReply.
     pub fn try_get_response(&mut self, serial: NonZeroU32) -> Option<MarshalledMessage> {
+        if self.claimed_responses.contains(&serial) {
+            return None;
+        }
         self.responses.remove(&serial)
     }
 
+    /// Take the response for a PendingReply, it is no longer claimed afterwards
+    fn take_claimed_response(&mut self, serial: NonZeroU32) -> Option<MarshalledMessage> {
+        let msg = self.responses.remove(&serial)?;
+        self.claimed_responses.remove(&serial);
+        Some(msg)
+    }
+
     /// Return a response if one is there or block until it arrives
+    ///
+    /// In debug builds this returns `Error::NoReplyToSignal` if the serial belongs to a signal that was sent with a destination.
+    /// This usually means a signal was built where a call was meant, and waiting would take until the timeout runs out.
+    ///
+    /// Returns `Error::ResponseClaimed` for calls started with `start_call`, wait with their PendingReply instead.
     pub fn wait_response(
         &mut self,
         serial: NonZeroU32,
         timeout: Timeout,
     ) -> Result<MarshalledMessage> {
-        let start_time = time::Instant::now();
+        if self.claimed_responses.contains(&serial) {
+            return Err(Error::ResponseClaimed);
+        }
+        #[cfg(debug_assertions)]
+        if self.sent_signals.contains(&serial) {
+            return Err(Error::NoReplyToSignal);
+        }
+        let deadline = Deadline::new(timeout);
         loop {
             if let Some(msg) = self.try_get_response(serial) {
                 return Ok(msg);
             }
-            self.refill_once(calc_timeout_left(&start_time, timeout)?)?;
+            self.refill_once(deadline.remaining()?)?;
         }
     }
 
@@ -149,14 +984,28 @@ impl RpcConn {
         self.signals.pop_front()
     }
 
+    /// Take the first queued signal for which `pred` returns true, leaving the others in the queue
+    pub(crate) fn take_signal<F: Fn(&MarshalledMessage) -> bool>(
+        &mut self,
+        pred: F,
+    ) -> Option<MarshalledMessage> {
+        let idx = self.signals.iter().position(pred)?;
+        self.signals.remove(idx)
+    }
+
+    /// Drop all queued signals for which `pred` returns true
+    pub(crate) fn drop_signals<F: Fn(&MarshalledMessage) -> bool>(&mut self, pred: F) {
+        self.signals.retain(|msg| !pred(msg));
+    }
+
     /// Return a sginal if one is there or block until it arrives
     pub fn wait_signal(&mut self, timeout: Timeout) -> Result<MarshalledMessage> {
-        let start_time = time::Instant::now();
+        let deadline = Deadline::new(timeout);
         loop {
             if let Some(msg) = self.try_get_signal() {
                 return Ok(msg);
             }
-            self.refill_once(calc_timeout_left(&start_time, timeout)?)?;
+            self.refill_once(deadline.remaining()?)?;
         }
     }
 
@@ -167,21 +1016,181 @@ impl RpcConn {
 
     /// Return a call if one is there or block until it arrives
     pub fn wait_call(&mut self, timeout: Timeout) -> Result<MarshalledMessage> {
-        let start_time = time::Instant::now();
+        let deadline = Deadline::new(timeout);
         loop {
             if let Some(msg) = self.try_get_call() {
                 return Ok(msg);
             }
-            self.refill_once(calc_timeout_left(&start_time, timeout)?)?;
+            self.refill_once(deadline.remaining()?)?;
         }
     }
 
     /// Send a message to the bus
+    ///
+    /// RpcConns that were connected to a bus with one of the constructors log a warning (through the `log` crate) for calls
+    /// without a destination, unless they are meant for the bus itself.
+    ///
+    /// Calls that `start_calls` could not write in time and automatic replies come first. They are written without
+    /// blocking, if that is not possible `Error::QueuedMessagesPending` is returned. Use `flush` to wait for them.
     pub fn send_message<'a>(
         &'a mut self,
         msg: &'a mut crate::message_builder::MarshalledMessage,
     ) -> Result<super::ll_conn::SendMessageContext<'a>> {
-        self.conn.send.send_message(msg)
+        self.middleware.outgoing(msg)?;
+        match self.flush(Timeout::Nonblock) {
+            Err(Error::TimedOut) => return Err(Error::QueuedMessagesPending),
+            res => res?,
+        }
+        if self.source.is_some() && lacks_destination(msg) {
+            log::warn!(
+                "rustbus: the call {:?} on {:?} has no destination, so only the bus itself will see it",
+                msg.dynheader.member, msg.dynheader.interface
+            );
+        }
+        let ctx = self.conn.send.send_message(msg)?;
+        #[cfg(debug_assertions)]
+        if msg.typ == MessageType::Signal && msg.dynheader.destination.is_some() {
+            if self.sent_signals.len() == REMEMBERED_SIGNALS {
+                self.sent_signals.pop_front();
+            }
+            self.sent_signals.push_back(ctx.serial());
+        }
+        Ok(ctx)
+    }
+
+    /// Queue a message that was created by the RpcConn itself, e.g. an automatic error reply. As much of it is written
+    /// as the socket takes without blocking, the rest is written by the next flush.
+    fn send_internal(&mut self, mut msg: MarshalledMessage) -> Result<NonZeroU32> {
+        let serial = self.queue_call(&mut msg)?;
+        self.conn.send.write_next_chunk()?;
+        Ok(serial)
+    }
+
+    /// Write the messages that are still queued, see `start_calls`. Messages that arrive in the meantime are read and
+    /// queued, so a peer that stops reading until its own messages are read can not block this.
+    pub fn flush(&mut self, timeout: Timeout) -> Result<()> {
+        self.flush_receiving(timeout).map(|_| ())
+    }
+
+    /// Like `flush` but returns the type of the last message that was read and queued while flushing
+    fn flush_receiving(&mut self, timeout: Timeout) -> Result<Option<MessageType>> {
+        let deadline = Deadline::new(timeout);
+        let mut received = None;
+        while !self.conn.send.flush_or_readable(deadline.remaining()?)? {
+            loop {
+                let msg = match self.conn.recv.get_next_message(Timeout::Nonblock) {
+                    Err(Error::TimedOut) => break,
+                    Err(e) => return Err(e),
+                    Ok(msg) => msg,
+                };
+                if let Some(typ) = self.handle_incoming(msg)? {
+                    received = Some(typ);
+                }
+            }
+        }
+        Ok(received)
+    }
+
+    /// Send a call and block until the response arrives. The response may be an error message, check its `typ`.
+    ///
+    /// Flags like NoAutoStart can be set on the call with the CallBuilder or directly on `msg.flags`.
+    pub fn call_method(
+        &mut self,
+        msg: &mut crate::message_builder::MarshalledMessage,
+        timeout: Timeout,
+    ) -> Result<MarshalledMessage> {
+        let deadline = Deadline::new(timeout);
+        self.flush(deadline.remaining()?)?;
+        let serial = self
+            .send_message(msg)?
+            .write(deadline.remaining()?)
+            .map_err(super::ll_conn::force_finish_on_error)?;
+        self.wait_response(serial, deadline.remaining()?)
+    }
+
+    /// Like `call_method`, but if the service answers with org.freedesktop.DBus.Error.InteractiveAuthorizationRequired
+    /// `allow_interaction` is asked whether the user may be bothered. If it returns true the call is sent again with
+    /// `HeaderFlags::AllowInteractiveAuthorization` set and the reply to that is returned, otherwise the error is.
+    ///
+    /// Each attempt gets the full `timeout`. The retry waits for the user to answer the authorization prompt, so it
+    /// should be generous. A serial set on `msg` is cleared for the retry, it can not be used twice.
+    pub fn call_method_with_interactive_auth(
+        &mut self,
+        msg: &mut crate::message_builder::MarshalledMessage,
+        timeout: Timeout,
+        allow_interaction: impl FnOnce(&MarshalledMessage) -> bool,
+    ) -> Result<MarshalledMessage> {
+        let reply = self.call_method(msg, timeout)?;
+        if HeaderFlags::AllowInteractiveAuthorization.is_set(msg.flags)
+            || !reply.requires_interactive_authorization()
+            || !allow_interaction(&reply)
+        {
+            return Ok(reply);
+        }
+        HeaderFlags::AllowInteractiveAuthorization.set(&mut msg.flags);
+        msg.dynheader.serial = None;
+        self.call_method(msg, timeout)
+    }
+
+    /// Like `call_method` but a reply that does not have the signature `expected` is turned into
+    /// `Error::ReplySignatureMismatch`, which names both signatures. Error replies are returned unchecked.
+    pub fn call_method_expecting(
+        &mut self,
+        msg: &mut crate::message_builder::MarshalledMessage,
+        expected: &str,
+        timeout: Timeout,
+    ) -> Result<MarshalledMessage> {
+        let reply = self.call_method(msg, timeout)?;
+        if reply.typ == MessageType::Reply && reply.get_sig() != expected {
+            return Err(Error::ReplySignatureMismatch {
+                member: msg.dynheader.member.clone(),
+                expected: expected.to_owned(),
+                found: reply.get_sig().to_owned(),
+            });
+        }
+        Ok(reply)
+    }
+
+    /// Like `call_method_expecting` for replies that contain one value of type `T`
+    pub fn call_method_expecting_type<T: crate::Signature>(
+        &mut self,
+        msg: &mut crate::message_builder::MarshalledMessage,
+        timeout: Timeout,
+    ) -> Result<MarshalledMessage> {
+        let mut expected = String::new();
+        T::signature().to_str(&mut expected);
+        self.call_method_expecting(msg, &expected, timeout)
+    }
+
+    /// Check whether a service currently owns `name` on the bus, without activating it. If the bus answers with an error,
+    /// e.g. because a policy denies the call, it is returned as `Error::ErrorReply`.
+    pub fn is_service_running(&mut self, name: &str, timeout: Timeout) -> Result<bool> {
+        let mut msg = crate::standard_messages::name_has_owner(name);
+        crate::message_builder::HeaderFlags::NoAutoStart.set(&mut msg.flags);
+        let resp = self.call_method(&mut msg, timeout)?;
+        if let Some(error) = resp.error_reply() {
+            return Err(Error::ErrorReply(error));
+        }
+        match resp.typ {
+            MessageType::Reply => Ok(resp.body.parser().get::<bool>()?),
+            _ => Err(Error::UnexpectedMessageTypeReceived),
+        }
+    }
+
+    /// Ask the bus for the names that currently have an owner. The names are unmarshalled lazily while iterating over
+    /// the returned reply, which keeps the memory use down on busy buses.
+    pub fn list_names(&mut self, timeout: Timeout) -> Result<StringArrayReply> {
+        let mut msg = crate::standard_messages::list_names();
+        StringArrayReply::new(self.call_method_expecting_type::<Vec<&str>>(&mut msg, timeout)?)
+    }
+
+    /// The reply to `msg` if it is a peer call that should be answered, see `set_answer_peer_calls`
+    fn peer_reply(&self, msg: &MarshalledMessage) -> Option<MarshalledMessage> {
+        if self.answer_peer_calls && msg.typ == MessageType::Call {
+            crate::peer::peer_reply(&msg.dynheader)
+        } else {
+            None
+        }
     }
 
     fn insert_message_or_send_error(&mut self, msg: MarshalledMessage) -> Result<()> {
@@ -191,27 +1200,21 @@ impl RpcConn {
                     self.calls.push_back(msg);
                 }
                 MessageType::Invalid => return Err(Error::UnexpectedMessageTypeReceived),
-                MessageType::Error => {
-                    self.responses
-                        .insert(msg.dynheader.response_serial.unwrap(), msg);
-                }
-                MessageType::Reply => {
-                    self.responses
-                        .insert(msg.dynheader.response_serial.unwrap(), msg);
+                MessageType::Error | MessageType::Reply => {
+                    // received replies always have a serial, but a middleware might have removed it
+                    if let Some(serial) = msg.dynheader.response_serial {
+                        self.responses.insert(serial, msg);
+                    }
                 }
                 MessageType::Signal => {
-                    self.signals.push_back(msg);
+                    self.dispatch_signal(msg);
                 }
             }
         } else {
             match msg.typ {
                 MessageType::Call => {
                     let reply = crate::standard_messages::unknown_method(&msg.dynheader);
-                    self.conn
-                        .send
-                        .send_message(&reply)?
-                        .write_all()
-                        .map_err(ll_conn::force_finish_on_error)?;
+                    self.send_internal(reply)?;
                 }
                 MessageType::Invalid => return Err(Error::UnexpectedMessageTypeReceived),
                 MessageType::Error => {
@@ -228,17 +1231,43 @@ impl RpcConn {
         Ok(())
     }
 
-    /// This processes ONE message. This might be an ignored message. The result will tell you which
+    /// This processes ONE message. This might be an ignored message, e.g. the response to a cancelled call. The result will tell you which
     /// if any message type was received. The message will be placed into the appropriate queue in the RpcConn.
     ///
+    /// If messages are still queued (see `start_calls`) they are written first. The messages that arrive while writing
+    /// them are all processed, the result tells the type of the last one.
+    ///
     /// If a call is received that should be filtered out an error message is sent automatically
     pub fn try_refill_once(&mut self, timeout: Timeout) -> Result<Option<MessageType>> {
-        let start_time = time::Instant::now();
-        let msg = self
-            .conn
-            .recv
-            .get_next_message(calc_timeout_left(&start_time, timeout)?)?;
+        let deadline = Deadline::new(timeout);
+        // the responses to calls that start_calls could not write in time can only arrive once they are written
+        if let Some(typ) = self.flush_receiving(deadline.remaining()?)? {
+            return Ok(Some(typ));
+        }
+        self.process_cancelled()?;
+        self.process_unsubscribed()?;
+        let msg = self.conn.recv.get_next_message(deadline.remaining()?)?;
+        self.handle_incoming(msg)
+    }
 
+    /// Pass a received message through the middlewares and the filter and put it into the queue it belongs in
+    fn handle_incoming(&mut self, mut msg: MarshalledMessage) -> Result<Option<MessageType>> {
+        match self.middleware.incoming(&mut msg) {
+            IncomingAction::Continue => {}
+            IncomingAction::Drop => return Ok(None),
+            IncomingAction::Respond(reply) => {
+                self.send_internal(*reply)?;
+                return Ok(None);
+            }
+        }
+
+        if self.is_ignored_response(&msg) {
+            return Ok(None);
+        }
+        if let Some(reply) = self.peer_reply(&msg) {
+            self.send_internal(reply)?;
+            return Ok(None);
+        }
         let typ = msg.typ;
         self.insert_message_or_send_error(msg)?;
         Ok(Some(typ))
@@ -250,9 +1279,9 @@ impl RpcConn {
     ///
     /// If calls are received that should be filtered out an error message is sent automatically
     pub fn refill_once(&mut self, timeout: Timeout) -> Result<MessageType> {
-        let start_time = time::Instant::now();
+        let deadline = Deadline::new(timeout);
         loop {
-            if let Some(typ) = self.try_refill_once(calc_timeout_left(&start_time, timeout)?)? {
+            if let Some(typ) = self.try_refill_once(deadline.remaining()?)? {
                 break Ok(typ);
             }
         }
@@ -266,31 +1295,48 @@ impl RpcConn {
     /// but error replies should always be sent. For this reason replies to all filtered calls are collected and returned.
     /// The original messages are dropped immediatly, so it should keep memory usage
     /// relatively low. The caller is responsible to send these error replies over the RpcConn, at a convenient time.
+    /// Responses returned by middlewares and replies to peer calls (see `set_answer_peer_calls`) are collected in the same way.
     pub fn refill_all(&mut self) -> Result<Vec<crate::message_builder::MarshalledMessage>> {
+        self.conn.send.write_next_chunk()?;
+        self.process_cancelled()?;
+        self.process_unsubscribed()?;
         let mut filtered_out = Vec::new();
         loop {
             //  break if the call would block (aka no more io is possible), or return if an actual error occured
-            let msg = match self.conn.recv.get_next_message(Timeout::Nonblock) {
+            let mut msg = match self.conn.recv.get_next_message(Timeout::Nonblock) {
                 Err(Error::TimedOut) => break,
                 Err(e) => return Err(e),
                 Ok(m) => m,
             };
+            match self.middleware.incoming(&mut msg) {
+                IncomingAction::Continue => {}
+                IncomingAction::Drop => continue,
+                IncomingAction::Respond(reply) => {
+                    filtered_out.push(*reply);
+                    continue;
+                }
+            }
+            if self.is_ignored_response(&msg) {
+                continue;
+            }
+            if let Some(reply) = self.peer_reply(&msg) {
+                filtered_out.push(reply);
+                continue;
+            }
             if self.filter.as_ref()(&msg) {
                 match msg.typ {
                     MessageType::Call => {
                         self.calls.push_back(msg);
                     }
                     MessageType::Invalid => return Err(Error::UnexpectedMessageTypeReceived),
-                    MessageType::Error => {
-                        self.responses
-                            .insert(msg.dynheader.response_serial.unwrap(), msg);
-                    }
-                    MessageType::Reply => {
-                        self.responses
-                            .insert(msg.dynheader.response_serial.unwrap(), msg);
+                    MessageType::Error | MessageType::Reply => {
+                        // received replies always have a serial, but a middleware might have removed it
+                        if let Some(serial) = msg.dynheader.response_serial {
+                            self.responses.insert(serial, msg);
+                        }
                     }
                     MessageType::Signal => {
-                        self.signals.push_back(msg);
+                        self.dispatch_signal(msg);
                     }
                 }
             } else {
@@ -316,3 +1362,939 @@ impl RpcConn {
         Ok(filtered_out)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::{ErrorReply, MessageBuilder};
+
+    #[test]
+    fn test_wait_for_signal_reply() {
+        let (conn, _peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+
+        let mut signal = MessageBuilder::new()
+            .signal("io.killing.spark", "Sig", "/")
+            .build();
+        signal.dynheader.destination = Some("io.killing.spark".into());
+        let serial = rpc_con
+            .send_message(&mut signal)
+            .unwrap()
+            .write_all()
+            .map_err(ll_conn::force_finish_on_error)
+            .unwrap();
+        // release builds do not keep track of the signals and just wait
+        let res = rpc_con.wait_response(serial, Timeout::Nonblock);
+        if cfg!(debug_assertions) {
+            assert!(matches!(res, Err(Error::NoReplyToSignal)));
+        } else {
+            assert!(matches!(res, Err(Error::TimedOut)));
+        }
+
+        let mut call = MessageBuilder::new()
+            .call("Member")
+            .on("/")
+            .at("io.killing.spark")
+            .build();
+        let serial = rpc_con
+            .send_message(&mut call)
+            .unwrap()
+            .write_all()
+            .map_err(ll_conn::force_finish_on_error)
+            .unwrap();
+        assert!(matches!(
+            rpc_con.wait_response(serial, Timeout::Nonblock),
+            Err(Error::TimedOut)
+        ));
+    }
+
+    fn send_to(conn: &mut DuplexConn, msg: MarshalledMessage) {
+        conn.send
+            .send_message(&msg)
+            .unwrap()
+            .write_all()
+            .map_err(ll_conn::force_finish_on_error)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_cancel_call() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let make_call = || {
+            MessageBuilder::new()
+                .call("Member")
+                .with_interface("io.killing.spark")
+                .on("/")
+                .at("io.killing.spark")
+                .build()
+        };
+
+        // the response arrives after the call was cancelled
+        let pending = rpc_con
+            .start_call(&mut make_call(), Timeout::Infinite)
+            .unwrap();
+        let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        pending.cancel();
+        rpc_con.refill_all().unwrap();
+        send_to(&mut peer, call.dynheader.make_response());
+        assert!(matches!(
+            rpc_con.try_refill_once(Timeout::Infinite),
+            Ok(None)
+        ));
+        assert!(rpc_con.responses.is_empty());
+        assert!(rpc_con.ignored_responses.is_empty());
+
+        // the response arrived before the call was cancelled
+        let pending = rpc_con
+            .start_call(&mut make_call(), Timeout::Infinite)
+            .unwrap();
+        let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        send_to(&mut peer, call.dynheader.make_response());
+        rpc_con.refill_once(Timeout::Infinite).unwrap();
+        assert_eq!(rpc_con.responses.len(), 1);
+        drop(pending);
+        rpc_con.refill_all().unwrap();
+        assert!(rpc_con.responses.is_empty());
+        assert!(rpc_con.ignored_responses.is_empty());
+
+        // taken responses are not cancelled
+        let mut pending = rpc_con
+            .start_call(&mut make_call(), Timeout::Infinite)
+            .unwrap();
+        let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        send_to(&mut peer, call.dynheader.make_response());
+        let resp = pending.wait(&mut rpc_con, Timeout::Infinite).unwrap();
+        assert_eq!(resp.dynheader.response_serial, Some(pending.serial()));
+        drop(pending);
+        rpc_con.refill_all().unwrap();
+        assert!(rpc_con.ignored_responses.is_empty());
+    }
+
+    #[test]
+    fn test_start_calls() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let mut calls: Vec<_> = (0..5)
+            .map(|idx| {
+                MessageBuilder::new()
+                    .call(format!("Member{}", idx))
+                    .on("/")
+                    .build()
+            })
+            .collect();
+        let mut pending = rpc_con.start_calls(&mut calls, Timeout::Infinite).unwrap();
+        assert_eq!(pending.remaining(), 5);
+
+        let received: Vec<_> = (0..5)
+            .map(|_| peer.recv.get_next_message(Timeout::Infinite).unwrap())
+            .collect();
+        // answer in reverse order, the replies are matched to the calls by their serials
+        for call in received.iter().skip(2).rev() {
+            let mut reply = call.dynheader.make_response();
+            reply
+                .body
+                .push_param(call.dynheader.member.as_deref().unwrap())
+                .unwrap();
+            send_to(&mut peer, reply);
+        }
+        let mut collected = Vec::new();
+        for reply in pending.collect_replies(
+            &mut rpc_con,
+            Timeout::Duration(std::time::Duration::from_millis(50)),
+        ) {
+            match reply {
+                Ok((idx, reply)) => {
+                    assert_eq!(
+                        reply.body.parser().get::<&str>().unwrap(),
+                        format!("Member{}", idx)
+                    );
+                    collected.push(idx);
+                }
+                Err(e) => assert!(matches!(e, Error::TimedOut)),
+            }
+        }
+        assert_eq!(collected, [4, 3, 2]);
+        assert_eq!(pending.remaining(), 2);
+
+        // dropping the rest cancels the calls, so their responses are dropped too
+        drop(pending);
+        for call in &received[..2] {
+            send_to(&mut peer, call.dynheader.make_response());
+        }
+        assert!(matches!(
+            rpc_con.try_refill_once(Timeout::Infinite),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn test_send_after_start_calls_timed_out() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        // too big for the socket buffer, so the write times out while the peer does not read
+        let mut call = MessageBuilder::new().call("Big").on("/").build();
+        call.body.push_param("a".repeat(4 * 1024 * 1024)).unwrap();
+        assert!(matches!(
+            rpc_con.start_calls(&mut [call], Timeout::Nonblock),
+            Err(Error::TimedOut)
+        ));
+        let mut next = MessageBuilder::new().call("Next").on("/").build();
+        assert!(matches!(
+            rpc_con.send_message(&mut next),
+            Err(Error::QueuedMessagesPending)
+        ));
+
+        let reader = std::thread::spawn(move || {
+            (0..2)
+                .map(|_| {
+                    let msg = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+                    msg.dynheader.member.unwrap()
+                })
+                .collect::<Vec<_>>()
+        });
+        rpc_con.flush(Timeout::Infinite).unwrap();
+        rpc_con
+            .send_message(&mut next)
+            .unwrap()
+            .write_all()
+            .map_err(ll_conn::force_finish_on_error)
+            .unwrap();
+        assert_eq!(reader.join().unwrap(), ["Big", "Next"]);
+    }
+
+    #[test]
+    fn test_start_calls_while_peer_answers() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        // the peer only reads the next call once its reply to the last one is written
+        let answering = std::thread::spawn(move || {
+            for _ in 0..5000 {
+                let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+                let mut reply = call.dynheader.make_response();
+                reply.body.push_param("a".repeat(1024)).unwrap();
+                send_to(&mut peer, reply);
+            }
+        });
+
+        let mut rpc_con = RpcConn::new(conn);
+        let mut calls: Vec<_> = (0..5000)
+            .map(|_| MessageBuilder::new().call("Member").on("/").build())
+            .collect();
+        let timeout = Timeout::Duration(std::time::Duration::from_secs(30));
+        let mut pending = rpc_con.start_calls(&mut calls, timeout).unwrap();
+        let replies = pending
+            .collect_replies(&mut rpc_con, timeout)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(replies.len(), 5000);
+        answering.join().unwrap();
+    }
+
+    #[test]
+    fn test_answer_peer_calls() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let ping = || {
+            MessageBuilder::new()
+                .call("Ping")
+                .with_interface(crate::peer::PEER_INTERFACE)
+                .on("/")
+                .build()
+        };
+
+        send_to(&mut peer, ping());
+        assert_eq!(
+            rpc_con.refill_once(Timeout::Infinite).unwrap(),
+            MessageType::Call
+        );
+        assert!(rpc_con.try_get_call().is_some());
+
+        rpc_con.set_answer_peer_calls(true);
+        send_to(&mut peer, ping());
+        assert_eq!(rpc_con.try_refill_once(Timeout::Infinite).unwrap(), None);
+        assert!(rpc_con.try_get_call().is_none());
+        let pong = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        assert_eq!(pong.typ, MessageType::Reply);
+    }
+
+    #[test]
+    fn test_pending_reply() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let (other, _other_peer) = DuplexConn::pair().unwrap();
+        let mut other = RpcConn::new(other);
+        let mut call = MessageBuilder::new()
+            .call("Member")
+            .with_interface("io.killing.spark")
+            .on("/")
+            .at("io.killing.spark")
+            .build();
+
+        let mut pending = rpc_con
+            .start_call(&mut call, Timeout::Infinite)
+            .unwrap()
+            .with_timeout(Timeout::Duration(std::time::Duration::from_millis(10)));
+        let received = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        assert!(matches!(
+            pending.wait(&mut rpc_con, Timeout::Infinite),
+            Err(Error::TimedOut)
+        ));
+        assert!(pending.is_expired());
+
+        // the response can only be taken through the PendingReply of the RpcConn that started the call
+        send_to(&mut peer, received.dynheader.make_response());
+        rpc_con.refill_once(Timeout::Infinite).unwrap();
+        assert!(rpc_con.try_get_response(pending.serial()).is_none());
+        assert!(matches!(
+            rpc_con.wait_response(pending.serial(), Timeout::Nonblock),
+            Err(Error::ResponseClaimed)
+        ));
+        assert!(pending.try_get(&mut other).is_none());
+        assert!(matches!(
+            pending.wait(&mut other, Timeout::Nonblock),
+            Err(Error::PendingReplyOfOtherConnection)
+        ));
+        // a response that is already there is handed out even after the timeout
+        let resp = pending.wait(&mut rpc_con, Timeout::Infinite).unwrap();
+        assert_eq!(resp.dynheader.response_serial, Some(pending.serial()));
+        assert!(rpc_con.claimed_responses.is_empty());
+
+        // the serial is released when the call is cancelled
+        let pending = rpc_con.start_call(&mut call, Timeout::Infinite).unwrap();
+        drop(pending);
+        rpc_con.refill_all().unwrap();
+        assert!(rpc_con.claimed_responses.is_empty());
+    }
+
+    #[test]
+    fn test_interactive_auth_retry() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let service = std::thread::spawn(move || {
+            // refuse the call that does not allow interaction, accept the one that does
+            let mut flags = Vec::new();
+            for _ in 0..3 {
+                let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+                let interactive = HeaderFlags::AllowInteractiveAuthorization.is_set(call.flags);
+                flags.push(interactive);
+                let resp = if interactive {
+                    call.dynheader.make_response()
+                } else {
+                    call.dynheader.make_error_response(
+                        crate::standard_messages::DBUS_ERROR_INTERACTIVE_AUTHORIZATION_REQUIRED,
+                        None,
+                    )
+                };
+                send_to(&mut peer, resp);
+            }
+            flags
+        });
+        let make_call = || {
+            MessageBuilder::new()
+                .call("Reboot")
+                .with_interface("io.killing.spark")
+                .on("/")
+                .at("io.killing.spark")
+                .build()
+        };
+
+        let reply = rpc_con
+            .call_method_with_interactive_auth(&mut make_call(), Timeout::Infinite, |_| true)
+            .unwrap();
+        assert_eq!(reply.typ, MessageType::Reply);
+
+        let reply = rpc_con
+            .call_method_with_interactive_auth(&mut make_call(), Timeout::Infinite, |err| {
+                assert!(err.requires_interactive_authorization());
+                false
+            })
+            .unwrap();
+        assert!(reply.requires_interactive_authorization());
+        assert!(!reply.is_error_named(crate::standard_messages::DBUS_ERROR_ACCESS_DENIED));
+
+        assert_eq!(service.join().unwrap(), [false, true, false]);
+    }
+
+    #[test]
+    fn test_cancel_member() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        rpc_con.set_cancel_member(Some("Cancel".into()));
+
+        let mut call = MessageBuilder::new()
+            .call("Member")
+            .with_interface("io.killing.spark")
+            .on("/io/killing/spark")
+            .at("io.killing.spark")
+            .build();
+        let pending = rpc_con.start_call(&mut call, Timeout::Infinite).unwrap();
+        let serial = pending.serial();
+        peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        drop(pending);
+        rpc_con.refill_all().unwrap();
+
+        let cancel = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+        assert_eq!(cancel.typ, MessageType::Call);
+        assert_eq!(cancel.dynheader.member.as_deref(), Some("Cancel"));
+        assert_eq!(
+            cancel.dynheader.interface.as_deref(),
+            Some("io.killing.spark")
+        );
+        assert_eq!(
+            cancel.dynheader.object.as_deref(),
+            Some("/io/killing/spark")
+        );
+        assert_eq!(
+            cancel.dynheader.destination.as_deref(),
+            Some("io.killing.spark")
+        );
+        assert!(HeaderFlags::NoReplyExpected.is_set(cancel.flags));
+        assert_eq!(cancel.body.parser().get::<u32>().unwrap(), serial.get());
+
+        // explicitly cancelled serials are only forgotten
+        rpc_con.cancel(serial);
+        rpc_con.refill_all().unwrap();
+        assert!(matches!(
+            peer.recv.get_next_message(Timeout::Nonblock),
+            Err(Error::TimedOut)
+        ));
+    }
+
+    #[test]
+    fn test_cancel_send_fails() {
+        /// Fails to send the first cancel call
+        struct FailOnce(bool);
+        impl Middleware for FailOnce {
+            fn outgoing(&mut self, msg: &mut MarshalledMessage) -> Result<()> {
+                if msg.dynheader.member.as_deref() == Some("Cancel") && !self.0 {
+                    self.0 = true;
+                    return Err(Error::TimedOut);
+                }
+                Ok(())
+            }
+        }
+
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        rpc_con.set_cancel_member(Some("Cancel".into()));
+        rpc_con.add_middleware(Box::new(FailOnce(false)));
+
+        let pending: Vec<_> = (0..2)
+            .map(|_| {
+                let mut call = MessageBuilder::new().call("Member").on("/").build();
+                let pending = rpc_con.start_call(&mut call, Timeout::Infinite).unwrap();
+                peer.recv.get_next_message(Timeout::Infinite).unwrap();
+                pending
+            })
+            .collect();
+        let serials: Vec<_> = pending.iter().map(|p| p.serial().get()).collect();
+        drop(pending);
+        assert!(matches!(rpc_con.refill_all(), Err(Error::TimedOut)));
+
+        // both calls are still cancelled
+        rpc_con.refill_all().unwrap();
+        for serial in serials {
+            let cancel = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            assert_eq!(cancel.dynheader.member.as_deref(), Some("Cancel"));
+            assert_eq!(cancel.body.parser().get::<u32>().unwrap(), serial);
+        }
+    }
+
+    #[test]
+    fn test_reply_signature() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let service = std::thread::spawn(move || {
+            for _ in 0..3 {
+                let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+                let mut resp = call.dynheader.make_response();
+                resp.body.push_param2("name", 42u32).unwrap();
+                send_to(&mut peer, resp);
+            }
+        });
+        let make_call = || MessageBuilder::new().call("Get").on("/").build();
+
+        let reply = rpc_con
+            .call_method_expecting(&mut make_call(), "su", Timeout::Infinite)
+            .unwrap();
+        assert_eq!(
+            reply.body.parser().get2::<&str, u32>().unwrap(),
+            ("name", 42)
+        );
+
+        match rpc_con.call_method_expecting(&mut make_call(), "sx", Timeout::Infinite) {
+            Err(Error::ReplySignatureMismatch {
+                member,
+                expected,
+                found,
+            }) => {
+                assert_eq!(member.as_deref(), Some("Get"));
+                assert_eq!(expected, "sx");
+                assert_eq!(found, "su");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(matches!(
+            rpc_con.call_method_expecting_type::<String>(&mut make_call(), Timeout::Infinite),
+            Err(Error::ReplySignatureMismatch { .. })
+        ));
+        service.join().unwrap();
+    }
+
+    #[test]
+    fn test_match_rules() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let service = std::thread::spawn(move || {
+            let mut calls = Vec::new();
+            for _ in 0..3 {
+                let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+                let rule = call.body.parser().get::<String>().unwrap();
+                let resp = if rule.contains("bad") {
+                    call.dynheader.make_error_response(
+                        "org.freedesktop.DBus.Error.MatchRuleInvalid",
+                        Some("bad rule".to_owned()),
+                    )
+                } else {
+                    call.dynheader.make_response()
+                };
+                calls.push((call.dynheader.member.clone().unwrap(), rule));
+                send_to(&mut peer, resp);
+            }
+            calls
+        });
+
+        let rule = MatchRule::new()
+            .msg_type(MessageType::Signal)
+            .interface("io.killing.spark");
+        rpc_con.add_match(rule.clone(), Timeout::Infinite).unwrap();
+        assert_eq!(rpc_con.match_rules(), std::slice::from_ref(&rule));
+
+        let bad = MatchRule::new().member("bad");
+        match rpc_con.add_match(bad, Timeout::Infinite) {
+            Err(Error::MatchRuleRejected {
+                rule,
+                error: ErrorReply { name, message },
+            }) => {
+                assert_eq!(rule, "member='bad'");
+                assert_eq!(name, "org.freedesktop.DBus.Error.MatchRuleInvalid");
+                assert_eq!(message.as_deref(), Some("bad rule"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(rpc_con.match_rules(), std::slice::from_ref(&rule));
+
+        rpc_con.remove_match(&rule, Timeout::Infinite).unwrap();
+        assert!(rpc_con.match_rules().is_empty());
+
+        let rule = "type='signal',interface='io.killing.spark'".to_owned();
+        assert_eq!(
+            service.join().unwrap(),
+            [
+                ("AddMatch".to_owned(), rule.clone()),
+                ("AddMatch".to_owned(), "member='bad'".to_owned()),
+                ("RemoveMatch".to_owned(), rule),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_service_running() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let bus = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+                let name = call.body.parser().get::<String>().unwrap();
+                let resp = if name == "io.killing.spark" {
+                    let mut resp = call.dynheader.make_response();
+                    resp.body.push_param(true).unwrap();
+                    resp
+                } else {
+                    call.dynheader.make_error_response(
+                        crate::consts::DBUS_ERROR_ACCESS_DENIED,
+                        Some("denied".to_owned()),
+                    )
+                };
+                send_to(&mut peer, resp);
+            }
+        });
+
+        assert!(rpc_con
+            .is_service_running("io.killing.spark", Timeout::Infinite)
+            .unwrap());
+        match rpc_con.is_service_running("io.killing.secret", Timeout::Infinite) {
+            Err(Error::ErrorReply(ErrorReply { name, message })) => {
+                assert_eq!(name, crate::consts::DBUS_ERROR_ACCESS_DENIED);
+                assert_eq!(message.as_deref(), Some("denied"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        bus.join().unwrap();
+    }
+
+    #[test]
+    fn test_list_names() {
+        let bus = crate::testing::TestBus::start().unwrap();
+        let mut first = bus.connect().unwrap();
+        let mut second = bus.connect().unwrap();
+        first
+            .request_name("io.killing.spark", 0, Timeout::Infinite)
+            .unwrap();
+
+        let reply = second.list_names(Timeout::Infinite).unwrap();
+        let names = reply
+            .iter()
+            .unwrap()
+            .collect::<std::result::Result<Vec<&str>, _>>()
+            .unwrap();
+        assert_eq!(names.len(), 4);
+        assert!(names.contains(&crate::consts::DBUS_NAME));
+        assert!(names.contains(&"io.killing.spark"));
+        assert_eq!(names.iter().filter(|name| name.starts_with(':')).count(), 2);
+
+        let chunks = reply
+            .chunks(3)
+            .unwrap()
+            .collect::<std::result::Result<Vec<Vec<&str>>, _>>()
+            .unwrap();
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), [3, 1]);
+        assert_eq!(chunks.concat(), names);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let (conn, mut peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        let service = std::thread::spawn(move || {
+            let mut calls = Vec::new();
+            loop {
+                let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+                let member = call.dynheader.member.clone().unwrap();
+                match member.as_str() {
+                    "AddMatch" | "RemoveMatch" => {
+                        send_to(&mut peer, call.dynheader.make_response());
+                    }
+                    _ => {
+                        for (member, value) in [("A", 1u32), ("B", 2), ("A", 3), ("C", 4)] {
+                            let mut sig = MessageBuilder::new()
+                                .signal("io.killing.spark", member, "/")
+                                .build();
+                            sig.body.push_param(value).unwrap();
+                            send_to(&mut peer, sig);
+                        }
+                        send_to(&mut peer, call.dynheader.make_response());
+                    }
+                }
+                calls.push(member);
+                if calls.len() == 5 {
+                    return calls;
+                }
+            }
+        });
+
+        let rule = |member: &str| {
+            MatchRule::new()
+                .msg_type(MessageType::Signal)
+                .interface("io.killing.spark")
+                .member(member)
+        };
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let collect = |received: &Arc<Mutex<Vec<(String, u32)>>>| {
+            let received = received.clone();
+            move |sig: &MarshalledMessage| {
+                let member = sig.dynheader.member.clone().unwrap();
+                let value = sig.body.parser().get::<u32>().unwrap();
+                received.lock().unwrap().push((member, value));
+            }
+        };
+        let sub_a = rpc_con
+            .subscribe(rule("A"), collect(&received), Timeout::Infinite)
+            .unwrap();
+        let sub_b = rpc_con
+            .subscribe(rule("B"), collect(&received), Timeout::Infinite)
+            .unwrap();
+        assert_ne!(sub_a.id(), sub_b.id());
+        assert_eq!(rpc_con.match_rules().len(), 2);
+
+        let mut call = MessageBuilder::new().call("Emit").on("/").build();
+        rpc_con.call_method(&mut call, Timeout::Infinite).unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                ("A".to_owned(), 1),
+                ("B".to_owned(), 2),
+                ("A".to_owned(), 3)
+            ]
+        );
+        // signals without a subscription are queued
+        let unhandled = rpc_con.try_get_signal().unwrap();
+        assert_eq!(unhandled.dynheader.member.as_deref(), Some("C"));
+        assert!(rpc_con.try_get_signal().is_none());
+
+        rpc_con.unsubscribe(sub_a, Timeout::Infinite).unwrap();
+        drop(sub_b);
+        // the dropped subscription is removed the next time messages are received
+        assert!(matches!(
+            rpc_con.try_refill_once(Timeout::Infinite),
+            Ok(None)
+        ));
+        assert!(rpc_con.match_rules().is_empty());
+        assert_eq!(
+            service.join().unwrap(),
+            ["AddMatch", "AddMatch", "Emit", "RemoveMatch", "RemoveMatch"]
+        );
+    }
+
+    #[test]
+    fn test_reconnect_without_source() {
+        let (conn, _peer) = DuplexConn::pair().unwrap();
+        let mut rpc_con = RpcConn::new(conn);
+        assert!(rpc_con.address().is_none());
+        assert!(matches!(
+            rpc_con.reconnect(Timeout::Nonblock),
+            Err(Error::NoAddressFound)
+        ));
+    }
+
+    #[test]
+    fn test_pending_reply_across_reconnect() {
+        let path =
+            std::env::temp_dir().join(format!("rustbus-reconnect-pending-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        // the first call is never answered, the one after the reconnect gets the same serial and is answered
+        let bus = std::thread::spawn(move || {
+            for answer in [false, true] {
+                let mut client = accept_bus_client(&listener);
+                let hello = client.recv.get_next_message(Timeout::Infinite).unwrap();
+                let mut resp = hello.dynheader.make_response();
+                resp.body.push_param(":1.1").unwrap();
+                send_to(&mut client, resp);
+                let call = client.recv.get_next_message(Timeout::Infinite).unwrap();
+                if answer {
+                    send_to(&mut client, call.dynheader.make_response());
+                }
+            }
+        });
+
+        let make_call = || {
+            MessageBuilder::new()
+                .call("Member")
+                .on("/")
+                .at("io.killing.spark")
+                .build()
+        };
+        let addr = UnixAddr::new(&path).unwrap();
+        let mut rpc_con = RpcConn::connect_to_path(addr, Timeout::Infinite).unwrap();
+        let mut old = rpc_con
+            .start_call(&mut make_call(), Timeout::Infinite)
+            .unwrap();
+        rpc_con.reconnect(Timeout::Infinite).unwrap();
+        let mut new = rpc_con
+            .start_call(&mut make_call(), Timeout::Infinite)
+            .unwrap();
+        assert_eq!(old.serial(), new.serial());
+        bus.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        rpc_con.refill_once(Timeout::Infinite).unwrap();
+        assert!(old.try_get(&mut rpc_con).is_none());
+        assert!(matches!(
+            old.wait(&mut rpc_con, Timeout::Nonblock),
+            Err(Error::PendingReplyOfOtherConnection)
+        ));
+        // dropping the old handle does not cancel the new call
+        drop(old);
+        let resp = new.wait(&mut rpc_con, Timeout::Nonblock).unwrap();
+        assert_eq!(resp.dynheader.response_serial, Some(new.serial()));
+    }
+
+    /// Accept a client on `listener` and authenticate it like a bus would
+    fn accept_bus_client(listener: &std::os::unix::net::UnixListener) -> DuplexConn {
+        use std::io::{Read, Write};
+
+        let (mut stream, _) = listener.accept().unwrap();
+        // the client starts with a null byte
+        stream.read_exact(&mut [0]).unwrap();
+        loop {
+            let mut line = Vec::new();
+            while !line.ends_with(b"\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).unwrap();
+                line.push(byte[0]);
+            }
+            if line.starts_with(b"AUTH") {
+                stream
+                    .write_all(b"OK 0123456789abcdef0123456789abcdef\r\n")
+                    .unwrap();
+            } else if line.starts_with(b"NEGOTIATE_UNIX_FD") {
+                stream.write_all(b"AGREE_UNIX_FD\r\n").unwrap();
+            } else if line.starts_with(b"BEGIN") {
+                return DuplexConn::from_stream(stream).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_reconnect_keeps_names_on_error() {
+        let path =
+            std::env::temp_dir().join(format!("rustbus-reconnect-error-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        // the restarted bus rejects the first RequestName
+        let bus = std::thread::spawn(move || {
+            for calls in [3, 2] {
+                let mut client = accept_bus_client(&listener);
+                for _ in 0..calls {
+                    let call = client.recv.get_next_message(Timeout::Infinite).unwrap();
+                    let resp = match call.dynheader.member.as_deref().unwrap() {
+                        "Hello" => {
+                            let mut resp = call.dynheader.make_response();
+                            resp.body.push_param(":1.1").unwrap();
+                            resp
+                        }
+                        _ if calls == 2 => call
+                            .dynheader
+                            .make_error_response("org.freedesktop.DBus.Error.AccessDenied", None),
+                        _ => {
+                            let mut resp = call.dynheader.make_response();
+                            resp.body
+                                .push_param(
+                                    crate::standard_messages::DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER,
+                                )
+                                .unwrap();
+                            resp
+                        }
+                    };
+                    send_to(&mut client, resp);
+                }
+            }
+        });
+
+        let addr = UnixAddr::new(&path).unwrap();
+        let mut rpc_con = RpcConn::connect_to_path(addr, Timeout::Infinite).unwrap();
+        let a = "io.killing.spark.A";
+        let b = "io.killing.spark.B";
+        rpc_con.request_name(a, 0, Timeout::Infinite).unwrap();
+        rpc_con.request_name(b, 0, Timeout::Infinite).unwrap();
+
+        assert!(rpc_con.reconnect(Timeout::Infinite).is_err());
+        assert_eq!(rpc_con.names().collect::<Vec<_>>(), [a, b]);
+
+        bus.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_reconnect_restores_names() {
+        use crate::standard_messages::{
+            DBUS_NAME_FLAG_DO_NOT_QUEUE, DBUS_RELEASE_NAME_REPLY_RELEASED,
+            DBUS_REQUEST_NAME_REPLY_EXISTS, DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER,
+        };
+
+        let path = std::env::temp_dir().join(format!("rustbus-reconnect-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        // the bus restarts after the first client sent 4 calls. After that io.killing.spark.B belongs to someone else.
+        let bus = std::thread::spawn(move || {
+            let mut rounds = Vec::new();
+            for calls in [4, 5] {
+                let mut client = accept_bus_client(&listener);
+                let mut received = Vec::new();
+                for _ in 0..calls {
+                    let call = client.recv.get_next_message(Timeout::Infinite).unwrap();
+                    let member = call.dynheader.member.clone().unwrap();
+                    let mut resp = call.dynheader.make_response();
+                    match member.as_str() {
+                        "Hello" => {
+                            resp.body.push_param(":1.1").unwrap();
+                            received.push(member);
+                        }
+                        "RequestName" => {
+                            let (name, flags) = call.body.parser().get2::<&str, u32>().unwrap();
+                            let code = if name.ends_with('B') && rounds.len() == 1 {
+                                DBUS_REQUEST_NAME_REPLY_EXISTS
+                            } else {
+                                DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER
+                            };
+                            resp.body.push_param(code).unwrap();
+                            received.push(format!("{} {} {}", member, name, flags));
+                        }
+                        _ => {
+                            let arg = call.body.parser().get::<String>().unwrap();
+                            if member == "ReleaseName" {
+                                resp.body
+                                    .push_param(DBUS_RELEASE_NAME_REPLY_RELEASED)
+                                    .unwrap();
+                            }
+                            received.push(format!("{} {}", member, arg));
+                        }
+                    }
+                    send_to(&mut client, resp);
+                }
+                rounds.push(received);
+            }
+            rounds
+        });
+
+        let addr = UnixAddr::new(&path).unwrap();
+        let mut rpc_con = RpcConn::connect_to_path(addr, Timeout::Infinite).unwrap();
+        let a = "io.killing.spark.A";
+        let b = "io.killing.spark.B";
+        assert_eq!(
+            rpc_con
+                .request_name(a, DBUS_NAME_FLAG_DO_NOT_QUEUE, Timeout::Infinite)
+                .unwrap(),
+            RequestNameReply::PrimaryOwner
+        );
+        rpc_con.request_name(b, 0, Timeout::Infinite).unwrap();
+        let rule = MatchRule::new().interface("io.killing.spark");
+        rpc_con.add_match(rule, Timeout::Infinite).unwrap();
+        assert_eq!(rpc_con.names().collect::<Vec<_>>(), [a, b]);
+
+        assert!(matches!(
+            rpc_con.reconnect(Timeout::Infinite),
+            Err(Error::NameTaken)
+        ));
+        assert_eq!(rpc_con.names().collect::<Vec<_>>(), [a]);
+        assert_eq!(
+            rpc_con.release_name(a, Timeout::Infinite).unwrap(),
+            DBUS_RELEASE_NAME_REPLY_RELEASED
+        );
+        assert_eq!(rpc_con.names().count(), 0);
+
+        let rounds = bus.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            rounds[1],
+            [
+                "Hello",
+                "AddMatch interface='io.killing.spark'",
+                "RequestName io.killing.spark.A 4",
+                "RequestName io.killing.spark.B 0",
+                "ReleaseName io.killing.spark.A",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lacks_destination() {
+        let call = MessageBuilder::new()
+            .call("evaluateScript")
+            .with_interface("org.kde.PlasmaShell")
+            .on("/PlasmaShell")
+            .build();
+        assert!(lacks_destination(&call));
+        assert!(!lacks_destination(&crate::standard_messages::ping_bus()));
+        assert!(!lacks_destination(&crate::standard_messages::hello()));
+
+        let signal = MessageBuilder::new()
+            .signal("org.kde.PlasmaShell", "Sig", "/PlasmaShell")
+            .build();
+        assert!(!lacks_destination(&signal));
+    }
+}
@@ -0,0 +1,328 @@
+//! Group signals that arrive in bursts
+//!
+//! Services like BlueZ or UDisks2 emit dozens of `InterfacesAdded` and `PropertiesChanged` signals when a device is
+//! plugged in. A `SignalBatcher` collects the signals of each sender for a short window and hands them out as one
+//! `SignalGroup`, so the application wakes up once per burst instead of once per signal.
+//!
+//! The window starts with the first signal of a group, so no signal is delayed by more than the window, even if the
+//! sender never stops sending. Groups of different senders are independent of each other.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use rustbus::connection::object_manager::{get_managed_objects, ManagedObjects};
+//! use rustbus::connection::signal_batch::SignalBatcher;
+//! use rustbus::match_rule::MatchRule;
+//! use rustbus::{connection::Timeout, RpcConn};
+//!
+//! let mut rpc_con = RpcConn::system_conn(Timeout::Infinite).unwrap();
+//! let rule = MatchRule::new()
+//!     .sender("org.bluez")
+//!     .interface(rustbus::connection::object_manager::OBJECT_MANAGER_INTERFACE);
+//! rpc_con.add_match(rule, Timeout::Infinite).unwrap();
+//! let reply = rpc_con
+//!     .call_method(&mut get_managed_objects("org.bluez", "/"), Timeout::Infinite)
+//!     .unwrap();
+//! let mut objects: ManagedObjects = reply.body.parser().get().unwrap();
+//!
+//! let mut batcher = SignalBatcher::new(Duration::from_millis(50));
+//! loop {
+//!     let group = batcher.wait_group(&mut rpc_con, Timeout::Infinite).unwrap();
+//!     if group.apply_to(&mut objects).unwrap() {
+//!         println!("{} objects after {} signals", objects.objects().count(), group.len());
+//!     }
+//! }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use super::object_manager::ManagedObjects;
+use super::rpc_conn::RpcConn;
+use super::{Deadline, Error, Result, Timeout};
+use crate::message_builder::MarshalledMessage;
+use crate::wire::errors::UnmarshalError;
+
+/// How many signals a group holds by default before it is handed out without waiting for the rest of the window
+pub const DEFAULT_MAX_SIGNALS: usize = 256;
+
+/// Signals of one sender that arrived within one window, in the order they arrived
+#[derive(Debug)]
+pub struct SignalGroup {
+    sender: Option<String>,
+    signals: Vec<MarshalledMessage>,
+}
+
+impl SignalGroup {
+    /// The unique name of the sender, or `None` on connections without a bus
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_deref()
+    }
+
+    pub fn signals(&self) -> &[MarshalledMessage] {
+        &self.signals
+    }
+
+    pub fn into_signals(self) -> Vec<MarshalledMessage> {
+        self.signals
+    }
+
+    /// The number of signals, a group holds at least one
+    pub fn len(&self) -> usize {
+        self.signals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signals.is_empty()
+    }
+
+    /// Apply all `InterfacesAdded` and `InterfacesRemoved` signals of the group, see `ManagedObjects::apply_signal`.
+    /// Returns whether any of them was applied. Stops at the first signal that can not be unmarshalled.
+    pub fn apply_to(
+        &self,
+        objects: &mut ManagedObjects,
+    ) -> std::result::Result<bool, UnmarshalError> {
+        let mut applied = false;
+        for signal in &self.signals {
+            applied |= objects.apply_signal(signal)?;
+        }
+        Ok(applied)
+    }
+}
+
+#[derive(Debug)]
+struct PendingGroup {
+    started: Instant,
+    group: SignalGroup,
+}
+
+/// Collects signals into groups per sender, see the module docs
+#[derive(Debug)]
+pub struct SignalBatcher {
+    window: Duration,
+    max_signals: usize,
+    /// Ordered by the time they were started, so the first one is due first
+    pending: Vec<PendingGroup>,
+}
+
+impl SignalBatcher {
+    /// Group the signals of each sender that arrive within `window` after the first one
+    pub fn new(window: Duration) -> Self {
+        SignalBatcher {
+            window,
+            max_signals: DEFAULT_MAX_SIGNALS,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Hand out a group as soon as it holds this many signals. Defaults to `DEFAULT_MAX_SIGNALS`.
+    pub fn with_max_signals(mut self, max_signals: usize) -> Self {
+        self.max_signals = max_signals.max(1);
+        self
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Add a signal to the group of its sender. Returns the group if it is full now.
+    pub fn push(&mut self, signal: MarshalledMessage) -> Option<SignalGroup> {
+        self.push_at(signal, Instant::now())
+    }
+
+    /// Like `push` with the time the signal arrived
+    pub fn push_at(&mut self, signal: MarshalledMessage, now: Instant) -> Option<SignalGroup> {
+        let sender = &signal.dynheader.sender;
+        let idx = match self
+            .pending
+            .iter()
+            .position(|pending| pending.group.sender == *sender)
+        {
+            Some(idx) => idx,
+            None => {
+                self.pending.push(PendingGroup {
+                    started: now,
+                    group: SignalGroup {
+                        sender: sender.clone(),
+                        signals: Vec::new(),
+                    },
+                });
+                self.pending.len() - 1
+            }
+        };
+        let signals = &mut self.pending[idx].group.signals;
+        signals.push(signal);
+        if signals.len() >= self.max_signals {
+            Some(self.pending.remove(idx).group)
+        } else {
+            None
+        }
+    }
+
+    /// When the oldest group is due, `None` if there are no signals
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending
+            .first()
+            .map(|pending| pending.started + self.window)
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Take the oldest group if its window has passed
+    pub fn take_ready(&mut self) -> Option<SignalGroup> {
+        self.take_ready_at(Instant::now())
+    }
+
+    /// Like `take_ready` at the time `now`
+    pub fn take_ready_at(&mut self, now: Instant) -> Option<SignalGroup> {
+        if self.next_due()? <= now {
+            Some(self.pending.remove(0).group)
+        } else {
+            None
+        }
+    }
+
+    /// Take all groups without waiting for their windows, e.g. before the connection is closed
+    pub fn take_all(&mut self) -> Vec<SignalGroup> {
+        self.pending
+            .drain(..)
+            .map(|pending| pending.group)
+            .collect()
+    }
+
+    /// Receive signals from `conn` until a group is due or full and return it. Returns `Error::TimedOut` if no group
+    /// became ready in time, the signals received so far stay in their groups.
+    pub fn wait_group(&mut self, conn: &mut RpcConn, timeout: Timeout) -> Result<SignalGroup> {
+        let deadline = Deadline::new(timeout);
+        loop {
+            if let Some(group) = self.take_ready() {
+                return Ok(group);
+            }
+            let remaining = deadline.remaining()?;
+            // wait for the next signal, but not past the time the oldest group is due
+            let (wait, until_due) = match self.next_due() {
+                Some(due) => {
+                    let until = due.saturating_duration_since(Instant::now());
+                    match remaining {
+                        Timeout::Infinite => (Timeout::Duration(until), true),
+                        Timeout::Duration(d) if until < d => (Timeout::Duration(until), true),
+                        other => (other, false),
+                    }
+                }
+                None => (remaining, false),
+            };
+            match conn.wait_signal(wait) {
+                Ok(signal) => {
+                    if let Some(group) = self.push(signal) {
+                        return Ok(group);
+                    }
+                }
+                Err(Error::TimedOut) if until_due => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::MessageBuilder;
+    use crate::wire::{ObjectPath, VarDict};
+    use crate::DuplexConn;
+    use std::collections::HashMap;
+
+    fn signal(sender: &str, member: &str) -> MarshalledMessage {
+        let mut signal = MessageBuilder::new()
+            .signal("io.killing.spark", member, "/")
+            .build();
+        signal.dynheader.sender = Some(sender.to_owned());
+        signal
+    }
+
+    fn members(group: &SignalGroup) -> Vec<&str> {
+        group
+            .signals()
+            .iter()
+            .map(|signal| signal.dynheader.member.as_deref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_grouping() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut batcher = SignalBatcher::new(Duration::from_millis(50)).with_max_signals(3);
+
+        assert!(batcher.push_at(signal(":1.1", "A"), ms(0)).is_none());
+        assert!(batcher.push_at(signal(":1.2", "X"), ms(10)).is_none());
+        assert!(batcher.push_at(signal(":1.1", "B"), ms(40)).is_none());
+        assert_eq!(batcher.next_due(), Some(ms(50)));
+        assert!(batcher.take_ready_at(ms(49)).is_none());
+
+        // the window does not move with later signals
+        let group = batcher.take_ready_at(ms(50)).unwrap();
+        assert_eq!(group.sender(), Some(":1.1"));
+        assert_eq!(members(&group), ["A", "B"]);
+        assert!(batcher.push_at(signal(":1.1", "C"), ms(55)).is_none());
+        let group = batcher.take_ready_at(ms(60)).unwrap();
+        assert_eq!(members(&group), ["X"]);
+        assert!(batcher.take_ready_at(ms(60)).is_none());
+
+        // full groups are handed out right away
+        assert!(batcher.push_at(signal(":1.1", "D"), ms(70)).is_none());
+        let group = batcher.push_at(signal(":1.1", "E"), ms(71)).unwrap();
+        assert_eq!(members(&group), ["C", "D", "E"]);
+        assert!(!batcher.has_pending());
+
+        batcher.push_at(signal(":1.3", "F"), ms(80));
+        let all = batcher.take_all();
+        assert_eq!(all.len(), 1);
+        assert_eq!(members(&all[0]), ["F"]);
+    }
+
+    #[test]
+    fn test_wait_group() {
+        let (service, client) = DuplexConn::pair().unwrap();
+        let mut service = service;
+        let mut client = RpcConn::new(client);
+
+        for path in ["/dev/1", "/dev/2", "/dev/3"] {
+            let mut added = MessageBuilder::new()
+                .signal(
+                    crate::consts::OBJECT_MANAGER_INTERFACE,
+                    "InterfacesAdded",
+                    "/",
+                )
+                .build();
+            let mut interfaces = HashMap::new();
+            let mut properties = VarDict::new();
+            properties.insert("Name", path).unwrap();
+            interfaces.insert("io.killing.spark.Device", properties);
+            added
+                .body
+                .push_param2(ObjectPath::new(path).unwrap(), interfaces)
+                .unwrap();
+            service.send.send_message_write_all(&added).unwrap();
+        }
+
+        let mut batcher = SignalBatcher::new(Duration::from_millis(20));
+        let group = batcher
+            .wait_group(&mut client, Timeout::Duration(Duration::from_secs(10)))
+            .unwrap();
+        assert_eq!(group.len(), 3);
+        assert_eq!(group.sender(), None);
+        let mut objects = ManagedObjects::new();
+        assert!(group.apply_to(&mut objects).unwrap());
+        assert_eq!(objects.objects().count(), 3);
+
+        assert!(matches!(
+            batcher.wait_group(&mut client, Timeout::Duration(Duration::from_millis(10))),
+            Err(Error::TimedOut)
+        ));
+        assert!(matches!(
+            batcher.wait_group(&mut client, Timeout::Nonblock),
+            Err(Error::TimedOut)
+        ));
+    }
+}
@@ -0,0 +1,343 @@
+//! Calls that stream their results as a series of signals
+//!
+//! Some services do not answer a call with one big reply but emit many signals, each carrying a token the caller chose
+//! as its first argument, so concurrent streams can be told apart. A StreamedCall subscribes to these signals with a
+//! match rule, issues the call and yields the signals as an iterator. The match rule is removed again when it is dropped.
+//!
+//! Services that want the caller to confirm that it keeps up can be acked every `window` items, see `StreamedCall::with_ack`.
+//!
+//! ```rust,no_run
+//! use rustbus::connection::streamed_call::{StreamSignals, StreamedCall};
+//! use rustbus::{connection::Timeout, MessageBuilder, RpcConn};
+//!
+//! let mut rpc_con = RpcConn::session_conn(Timeout::Infinite).unwrap();
+//! let mut call = MessageBuilder::new()
+//!     .call("Search")
+//!     .with_interface("io.killing.spark.Search")
+//!     .on("/io/killing/spark")
+//!     .at("io.killing.spark")
+//!     .build();
+//! call.body.push_param("my-token").unwrap();
+//! call.body.push_param("needle").unwrap();
+//!
+//! let signals = StreamSignals {
+//!     interface: "io.killing.spark.Search",
+//!     item: "Result",
+//!     end: Some("Done"),
+//! };
+//! let results = StreamedCall::start(&mut rpc_con, &mut call, "my-token", signals, Timeout::Infinite)
+//!     .unwrap()
+//!     .with_ack(100, |rpc_con, received| {
+//!         let mut ack = MessageBuilder::new()
+//!             .call("Ack")
+//!             .with_interface("io.killing.spark.Search")
+//!             .on("/io/killing/spark")
+//!             .at("io.killing.spark")
+//!             .build();
+//!         ack.body.push_param(("my-token", received as u64))?;
+//!         rpc_con.call_method(&mut ack, Timeout::Infinite)?;
+//!         Ok(())
+//!     });
+//! for result in results {
+//!     println!("{:?}", result.unwrap().body.parser().get2::<&str, &str>());
+//! }
+//! ```
+
+use thiserror::Error;
+
+use super::rpc_conn::RpcConn;
+use super::{Deadline, Timeout};
+use crate::match_rule::MatchRule;
+use crate::message_builder::{ErrorReply, MarshalledMessage, MessageType};
+
+/// Errors that can occur while streaming the results of a call
+#[derive(Debug, Error)]
+pub enum StreamError {
+    #[error("An error occured on the connection: {0}")]
+    Connection(#[from] super::Error),
+    #[error("The call was answered with the error {0}")]
+    ErrorReply(ErrorReply),
+    #[error("No match rule can be built for the signals of the stream")]
+    InvalidMatchRule,
+}
+
+/// Describes the signals a streamed call is answered with. They all carry the token as their first argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamSignals<'s> {
+    pub interface: &'s str,
+    /// The member of the signals that carry the items
+    pub item: &'s str,
+    /// The member of the signal that ends the stream. Without one the stream ends when the iterator is dropped.
+    pub end: Option<&'s str>,
+}
+
+/// Called with the connection and the number of items received so far, see `StreamedCall::with_ack`
+pub type AckFn<'a> = Box<dyn FnMut(&mut RpcConn, usize) -> super::Result<()> + 'a>;
+
+/// The items of a streamed call, in the order they arrived. Dropping it removes the match rule and the queued items.
+pub struct StreamedCall<'a> {
+    conn: &'a mut RpcConn,
+    match_rule: String,
+    token: String,
+    interface: String,
+    item: String,
+    end: Option<String>,
+    timeout: Timeout,
+    window: usize,
+    unacked: usize,
+    received: usize,
+    ack: Option<AckFn<'a>>,
+    finished: bool,
+}
+
+fn error_reply(msg: &MarshalledMessage) -> Option<StreamError> {
+    msg.error_reply().map(StreamError::ErrorReply)
+}
+
+impl<'a> StreamedCall<'a> {
+    /// Subscribe to the signals, send `call` and wait for its reply. `token` has to be the one the call passes to the service.
+    ///
+    /// The signals are only accepted from the destination of the call. The timeout applies to each of the two
+    /// round trips and, until `set_timeout` is called, to waiting for each item.
+    pub fn start(
+        conn: &'a mut RpcConn,
+        call: &mut MarshalledMessage,
+        token: &str,
+        signals: StreamSignals<'_>,
+        timeout: Timeout,
+    ) -> Result<Self, StreamError> {
+        let mut rule = MatchRule::new()
+            .msg_type(MessageType::Signal)
+            .interface(signals.interface)
+            .arg(0, token)
+            .ok_or(StreamError::InvalidMatchRule)?;
+        if let Some(dest) = &call.dynheader.destination {
+            rule = rule.sender(dest.as_str());
+        }
+        let match_rule = rule.to_string();
+
+        let reply = conn.call_method(
+            &mut crate::standard_messages::add_match(&match_rule),
+            timeout,
+        )?;
+        if let Some(err) = error_reply(&reply) {
+            return Err(err);
+        }
+
+        // from here on dropping self takes care of removing the match rule, also if the call fails
+        let stream = StreamedCall {
+            conn,
+            match_rule,
+            token: token.to_owned(),
+            interface: signals.interface.to_owned(),
+            item: signals.item.to_owned(),
+            end: signals.end.map(str::to_owned),
+            timeout,
+            window: 0,
+            unacked: 0,
+            received: 0,
+            ack: None,
+            finished: false,
+        };
+        let reply = stream.conn.call_method(call, timeout)?;
+        if let Some(err) = error_reply(&reply) {
+            return Err(err);
+        }
+        Ok(stream)
+    }
+
+    /// Call `ack` every time the iterator is advanced after `window` more items were taken from it. Because this
+    /// happens when the next item is requested and not when the items arrive, a service that waits for the ack before
+    /// sending the next window never gets ahead of the consumer by more than one window.
+    ///
+    /// An error returned by `ack` is returned by the iterator and ends the stream.
+    pub fn with_ack<F>(mut self, window: usize, ack: F) -> Self
+    where
+        F: FnMut(&mut RpcConn, usize) -> super::Result<()> + 'a,
+    {
+        self.window = window;
+        self.ack = Some(Box::new(ack));
+        self
+    }
+
+    /// Set how long to wait for each item
+    pub fn set_timeout(&mut self, timeout: Timeout) {
+        self.timeout = timeout;
+    }
+
+    /// The number of items taken from the iterator so far
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    fn next_item(&mut self) -> Result<Option<MarshalledMessage>, StreamError> {
+        if self.window > 0 && self.unacked >= self.window {
+            if let Some(ack) = &mut self.ack {
+                ack(self.conn, self.received)?;
+            }
+            self.unacked = 0;
+        }
+
+        let deadline = Deadline::new(self.timeout);
+        loop {
+            let (token, interface, item, end) =
+                (&self.token, &self.interface, &self.item, &self.end);
+            let signal = self
+                .conn
+                .take_signal(|msg| belongs_to_stream(msg, token, interface, item, end.as_deref()));
+            match signal {
+                Some(msg) if msg.dynheader.member.as_deref() == Some(item) => {
+                    self.received += 1;
+                    self.unacked += 1;
+                    return Ok(Some(msg));
+                }
+                Some(_) => return Ok(None),
+                None => {
+                    self.conn.refill_once(deadline.remaining()?)?;
+                }
+            }
+        }
+    }
+}
+
+fn belongs_to_stream(
+    msg: &MarshalledMessage,
+    token: &str,
+    interface: &str,
+    item: &str,
+    end: Option<&str>,
+) -> bool {
+    msg.typ == MessageType::Signal
+        && msg.dynheader.interface.as_deref() == Some(interface)
+        && msg
+            .dynheader
+            .member
+            .as_deref()
+            .is_some_and(|member| member == item || Some(member) == end)
+        && msg.body.parser().get::<&str>().ok() == Some(token)
+}
+
+impl Iterator for StreamedCall<'_> {
+    type Item = Result<MarshalledMessage, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let item = self.next_item().transpose();
+        if !matches!(item, Some(Ok(_))) {
+            self.finished = true;
+        }
+        item
+    }
+}
+
+impl Drop for StreamedCall<'_> {
+    fn drop(&mut self) {
+        // dont block in drop, the reply to RemoveMatch is dropped when it arrives
+        let mut msg = crate::standard_messages::remove_match(&self.match_rule);
+        let serial = match self.conn.send_message(&mut msg) {
+            Ok(ctx) => ctx
+                .write_all()
+                .map_err(super::ll_conn::force_finish_on_error)
+                .ok(),
+            Err(_) => None,
+        };
+        if let Some(serial) = serial {
+            self.conn.cancel(serial);
+        }
+        let (token, interface, item, end) = (&self.token, &self.interface, &self.item, &self.end);
+        self.conn
+            .drop_signals(|msg| belongs_to_stream(msg, token, interface, item, end.as_deref()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ll_conn::{force_finish_on_error, DuplexConn};
+    use crate::MessageBuilder;
+
+    const IFACE: &str = "io.killing.spark.Search";
+
+    fn send(conn: &mut DuplexConn, msg: MarshalledMessage) {
+        conn.send
+            .send_message(&msg)
+            .unwrap()
+            .write_all()
+            .map_err(force_finish_on_error)
+            .unwrap();
+    }
+
+    fn signal(member: &str, token: &str, idx: u32) -> MarshalledMessage {
+        let mut sig = MessageBuilder::new().signal(IFACE, member, "/").build();
+        sig.body.push_param2(token, idx).unwrap();
+        sig
+    }
+
+    /// Plays both the bus and the service. Returns the acks it received.
+    fn serve(mut peer: DuplexConn) -> Vec<u64> {
+        let mut acks = Vec::new();
+        loop {
+            let msg = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            send(&mut peer, msg.dynheader.make_response());
+            match msg.dynheader.member.as_deref().unwrap() {
+                "AddMatch" => {
+                    let rule = msg.body.parser().get::<String>().unwrap();
+                    assert_eq!(
+                        rule,
+                        "type='signal',sender='io.killing.spark',interface='io.killing.spark.Search',arg0='tok'"
+                    );
+                }
+                "Search" => {
+                    for idx in 0..5 {
+                        send(&mut peer, signal("Result", "tok", idx));
+                    }
+                    send(&mut peer, signal("Result", "other", 0));
+                    send(&mut peer, signal("Done", "tok", 0));
+                }
+                "Ack" => acks.push(msg.body.parser().get::<u64>().unwrap()),
+                "RemoveMatch" => return acks,
+                member => panic!("unexpected call {}", member),
+            }
+        }
+    }
+
+    #[test]
+    fn test_streamed_call() {
+        let (conn, peer) = DuplexConn::pair().unwrap();
+        let service = std::thread::spawn(move || serve(peer));
+        let mut rpc_con = RpcConn::new(conn);
+
+        let mut call = MessageBuilder::new()
+            .call("Search")
+            .with_interface(IFACE)
+            .on("/")
+            .at("io.killing.spark")
+            .build();
+        let signals = StreamSignals {
+            interface: IFACE,
+            item: "Result",
+            end: Some("Done"),
+        };
+        let stream =
+            StreamedCall::start(&mut rpc_con, &mut call, "tok", signals, Timeout::Infinite)
+                .unwrap()
+                .with_ack(2, |conn, received| {
+                    let mut ack = MessageBuilder::new().call("Ack").on("/").build();
+                    ack.body.push_param(received as u64)?;
+                    conn.call_method(&mut ack, Timeout::Infinite)?;
+                    Ok(())
+                });
+        let items: Vec<u32> = stream
+            .map(|item| item.unwrap().body.parser().get2::<&str, u32>().unwrap().1)
+            .collect();
+        assert_eq!(items, [0, 1, 2, 3, 4]);
+
+        assert_eq!(service.join().unwrap(), [2, 4]);
+        // signals of other streams are left alone
+        let other = rpc_con.try_get_signal().unwrap();
+        assert_eq!(other.body.parser().get::<&str>().unwrap(), "other");
+        assert!(rpc_con.try_get_signal().is_none());
+    }
+}
@@ -0,0 +1,70 @@
+//! Names defined by the dbus specification: the bus itself, the standard interfaces and the well known errors
+//!
+//! ```rust
+//! use rustbus::consts::{DBUS_ERROR_UNKNOWN_METHOD, PEER_INTERFACE};
+//! use rustbus::MessageBuilder;
+//!
+//! let call = MessageBuilder::new()
+//!     .call("Frobnicate")
+//!     .on("/")
+//!     .with_interface(PEER_INTERFACE)
+//!     .build();
+//! let reply = call.dynheader.make_error_response(DBUS_ERROR_UNKNOWN_METHOD, None);
+//! assert!(reply.is_error_named(DBUS_ERROR_UNKNOWN_METHOD));
+//! ```
+
+/// The well known name of the message bus
+pub const DBUS_NAME: &str = "org.freedesktop.DBus";
+/// The object path the message bus serves its interfaces at
+pub const DBUS_PATH: &str = "/org/freedesktop/DBus";
+/// The interface of the message bus (Hello, RequestName, AddMatch, ...)
+pub const DBUS_INTERFACE: &str = "org.freedesktop.DBus";
+/// Lets monitors receive all messages on the bus
+pub const MONITORING_INTERFACE: &str = "org.freedesktop.DBus.Monitoring";
+
+pub const PEER_INTERFACE: &str = "org.freedesktop.DBus.Peer";
+pub const INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+pub const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+pub const OBJECT_MANAGER_INTERFACE: &str = "org.freedesktop.DBus.ObjectManager";
+
+// The well known error names
+pub const DBUS_ERROR_FAILED: &str = "org.freedesktop.DBus.Error.Failed";
+pub const DBUS_ERROR_NO_MEMORY: &str = "org.freedesktop.DBus.Error.NoMemory";
+pub const DBUS_ERROR_SERVICE_UNKNOWN: &str = "org.freedesktop.DBus.Error.ServiceUnknown";
+pub const DBUS_ERROR_NAME_HAS_NO_OWNER: &str = "org.freedesktop.DBus.Error.NameHasNoOwner";
+pub const DBUS_ERROR_NO_REPLY: &str = "org.freedesktop.DBus.Error.NoReply";
+pub const DBUS_ERROR_IO_ERROR: &str = "org.freedesktop.DBus.Error.IOError";
+pub const DBUS_ERROR_BAD_ADDRESS: &str = "org.freedesktop.DBus.Error.BadAddress";
+pub const DBUS_ERROR_NOT_SUPPORTED: &str = "org.freedesktop.DBus.Error.NotSupported";
+pub const DBUS_ERROR_LIMITS_EXCEEDED: &str = "org.freedesktop.DBus.Error.LimitsExceeded";
+pub const DBUS_ERROR_ACCESS_DENIED: &str = "org.freedesktop.DBus.Error.AccessDenied";
+pub const DBUS_ERROR_AUTH_FAILED: &str = "org.freedesktop.DBus.Error.AuthFailed";
+pub const DBUS_ERROR_NO_SERVER: &str = "org.freedesktop.DBus.Error.NoServer";
+pub const DBUS_ERROR_TIMEOUT: &str = "org.freedesktop.DBus.Error.Timeout";
+pub const DBUS_ERROR_NO_NETWORK: &str = "org.freedesktop.DBus.Error.NoNetwork";
+pub const DBUS_ERROR_ADDRESS_IN_USE: &str = "org.freedesktop.DBus.Error.AddressInUse";
+pub const DBUS_ERROR_DISCONNECTED: &str = "org.freedesktop.DBus.Error.Disconnected";
+pub const DBUS_ERROR_INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+pub const DBUS_ERROR_FILE_NOT_FOUND: &str = "org.freedesktop.DBus.Error.FileNotFound";
+pub const DBUS_ERROR_FILE_EXISTS: &str = "org.freedesktop.DBus.Error.FileExists";
+pub const DBUS_ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+pub const DBUS_ERROR_UNKNOWN_OBJECT: &str = "org.freedesktop.DBus.Error.UnknownObject";
+pub const DBUS_ERROR_UNKNOWN_INTERFACE: &str = "org.freedesktop.DBus.Error.UnknownInterface";
+pub const DBUS_ERROR_UNKNOWN_PROPERTY: &str = "org.freedesktop.DBus.Error.UnknownProperty";
+pub const DBUS_ERROR_PROPERTY_READ_ONLY: &str = "org.freedesktop.DBus.Error.PropertyReadOnly";
+pub const DBUS_ERROR_TIMED_OUT: &str = "org.freedesktop.DBus.Error.TimedOut";
+pub const DBUS_ERROR_MATCH_RULE_NOT_FOUND: &str = "org.freedesktop.DBus.Error.MatchRuleNotFound";
+pub const DBUS_ERROR_MATCH_RULE_INVALID: &str = "org.freedesktop.DBus.Error.MatchRuleInvalid";
+pub const DBUS_ERROR_UNIX_PROCESS_ID_UNKNOWN: &str =
+    "org.freedesktop.DBus.Error.UnixProcessIdUnknown";
+pub const DBUS_ERROR_INVALID_SIGNATURE: &str = "org.freedesktop.DBus.Error.InvalidSignature";
+pub const DBUS_ERROR_INVALID_FILE_CONTENT: &str = "org.freedesktop.DBus.Error.InvalidFileContent";
+pub const DBUS_ERROR_SELINUX_SECURITY_CONTEXT_UNKNOWN: &str =
+    "org.freedesktop.DBus.Error.SELinuxSecurityContextUnknown";
+pub const DBUS_ERROR_ADT_AUDIT_DATA_UNKNOWN: &str =
+    "org.freedesktop.DBus.Error.AdtAuditDataUnknown";
+pub const DBUS_ERROR_OBJECT_PATH_IN_USE: &str = "org.freedesktop.DBus.Error.ObjectPathInUse";
+pub const DBUS_ERROR_INCONSISTENT_MESSAGE: &str = "org.freedesktop.DBus.Error.InconsistentMessage";
+pub const DBUS_ERROR_INTERACTIVE_AUTHORIZATION_REQUIRED: &str =
+    "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired";
+pub const DBUS_ERROR_NOT_CONTAINER: &str = "org.freedesktop.DBus.Error.NotContainer";
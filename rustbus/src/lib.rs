@@ -75,6 +75,8 @@
 //!
 //! For structs there is a derive proc-macro that derives the necessary trait impls for you. Look into rustbus_derive if this is of need for you.
 //!
+//! Structs marked with `#[rustbus(dict)]` are mapped to the `a{sv}` property maps most freedesktop APIs use instead, with one entry per field.
+//!
 //! For Variants there is a macro dbus_variant_sig! and dbus_variant_var! which will generate an enum and the Marshal and Unmarshal impls for you. These might get
 //! replaced with a proc-macro derive like it exists already for structs.
 //!
@@ -88,18 +90,65 @@
 //! the pitfalls of sending and receiving filedescriptors in a sensible way. If you see any issues with the API or have wishes for extensions to the API please
 //! open an issue.
 //!
+//! ## Threads
+//! Messages, bodies, parsers and the params are Send and Sync, including messages that contain filedescriptors. The low level
+//! connections are Send and Sync too. The RpcConn is only Send because middlewares only need to be Send, wrap it in a
+//! Mutex if multiple threads need to use it. The DispatchConn is not Send because its handlers do not have to be, create
+//! it on the thread that serves it. The handlers of the PooledDispatchConn are Send so it can serve calls on multiple threads.
+//!
+//! ## Optional features
+//! * `params` (enabled by default) contains the old enum based API in the params module. If you only use the Marshal and
+//!   Unmarshal traits you can disable default features to compile it out. The validation functions stay available.
+//! * `chrono` and `time` add Marshal and Unmarshal impls for `chrono::DateTime<Utc>` and `time::OffsetDateTime`. They are
+//!   mapped to a u64 counting microseconds since the unix epoch, like systemd does. For other resolutions and epochs see `wire::Timestamp`.
+//! * `introspection` adds `connection::dynamic_proxy`, which calls methods of objects that are only known at runtime, checking
+//!   the arguments against the introspection data of the object, and `codegen`, which generates typed proxies from the
+//!   introspection XML, e.g. in a build script. It needs `params` and pulls in `roxmltree` to parse the XML. The generated
+//!   code itself only needs `params` if it uses variants.
+//! * `tokio` adds `connection::async_conn`, async versions of the DuplexConn and RpcConn that wait on the tokio reactor
+//!   instead of blocking a thread.
+//! * `testing` adds `testing::TestBus`, a message bus running on a background thread for integration tests that should not
+//!   depend on a running dbus-daemon.
+//! * `mock-fds` adds `wire::mock_fds`, fake fds that can be used in tests that do not have access to real fds.
+//! * `zeroize` overwrites the buffers of message bodies, the header buffer and queued messages of the `SendConn` and the
+//!   receive buffer of connections with zeros before they are freed, so secrets do not linger in freed memory. It uses the
+//!   `zeroize` crate so the writes are not optimized away. Buffers that grow while params are pushed are reallocated by `Vec`, so
+//!   reserve enough space upfront if a body contains secrets. Bodies created with `MarshalledMessageBody::from_shared`
+//!   are not scrubbed, they belong to the caller.
+//! * `stable-api` leaves out the parts of the API that do not follow semver, see below.
+//!
+//! ## Stability
+//! The trait based API (`Marshal`, `Unmarshal`, `Signature` and their derives), `message_builder`, the connections in
+//! `connection`, `match_rule`, `standard_messages`, `auth`, `signature` and `wire` follow semver. The enum based API in
+//! `params` (except `params::validation`) and the iterator in `wire::unmarshal::iter` are experimental, they can change
+//! in minor versions.
+//!
+//! Enable the `stable-api` feature to make sure a crate does not depend on the experimental parts by accident. They are
+//! not compiled then, even if `params` or `introspection` are enabled as well: this includes
+//! `MessageBodyParser::get_param`, `MarshalledMessageBody::push_old_param` and `MarshalledMessage::unmarshall_all`.
+//! Everything else stays available. Note that features are unified across the dependency graph, so this also applies to
+//! other crates in the same build that use rustbus.
+//!
 //! ## Byteorders
 //! Dbus supports both big and little endian and so does rustbus. You can specify how a message should be marshalled when you create the MessageBuilder. Messages
 //! can be received in any byteorder and will be transparently unmarshalled into the byteorder you CPU uses. Note that unmarshalling from/to the native byteorder will
 //! be faster. The default byteorder is little endian.
 
 pub mod auth;
+#[cfg(all(feature = "introspection", rustbus_params))]
+pub mod codegen;
 pub mod connection;
+pub mod consts;
+pub mod match_rule;
 pub mod message_builder;
 pub mod params;
 pub mod peer;
 pub mod signature;
 pub mod standard_messages;
+mod sync;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod util;
 pub mod wire;
 
 // reexport derive macros
@@ -0,0 +1,480 @@
+//! Build match rules for `org.freedesktop.DBus.AddMatch`
+//!
+//! The bus only forwards signals to a connection if they match one of the rules the connection added. A MatchRule
+//! renders itself into the string format the bus expects, quoting all values.
+//!
+//! ```rust,no_run
+//! use rustbus::match_rule::MatchRule;
+//! use rustbus::{connection::Timeout, MessageType, RpcConn};
+//!
+//! let mut rpc_con = RpcConn::session_conn(Timeout::Infinite).unwrap();
+//! let rule = MatchRule::new()
+//!     .msg_type(MessageType::Signal)
+//!     .interface("org.freedesktop.DBus")
+//!     .member("NameOwnerChanged")
+//!     .arg(0, "io.killing.spark")
+//!     .unwrap();
+//! assert_eq!(
+//!     rule.to_string(),
+//!     "type='signal',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0='io.killing.spark'"
+//! );
+//! // the rule is added again if the connection is reconnected
+//! rpc_con.add_match(rule, Timeout::Infinite).unwrap();
+//! let signal = rpc_con.wait_signal(Timeout::Infinite).unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::message_builder::{MarshalledMessage, MessageType};
+use crate::signature::{Base, Type};
+use crate::wire::unmarshal_context::UnmarshalContext;
+
+/// The highest argument index a match rule can refer to
+pub const MAX_ARG_INDEX: u8 = 63;
+
+/// A match rule. Each condition that is set has to be met by a message for the rule to match, an empty rule matches
+/// all messages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MatchRule {
+    msg_type: Option<MessageType>,
+    sender: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    path: Option<String>,
+    path_namespace: Option<String>,
+    destination: Option<String>,
+    args: BTreeMap<u8, String>,
+    arg_paths: BTreeMap<u8, String>,
+    arg0_namespace: Option<String>,
+}
+
+/// Quote a value for a match rule. Single quotes can not be escaped inside of quotes, so they are written as `'\''`.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+impl MatchRule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn msg_type(mut self, typ: MessageType) -> Self {
+        self.msg_type = Some(typ);
+        self
+    }
+
+    /// A unique or well-known name. Well-known names match the messages of their current owner.
+    pub fn sender<S: Into<String>>(mut self, sender: S) -> Self {
+        self.sender = Some(sender.into());
+        self
+    }
+
+    pub fn interface<S: Into<String>>(mut self, interface: S) -> Self {
+        self.interface = Some(interface.into());
+        self
+    }
+
+    pub fn member<S: Into<String>>(mut self, member: S) -> Self {
+        self.member = Some(member.into());
+        self
+    }
+
+    /// Only messages about this exact object
+    pub fn path<S: Into<String>>(mut self, path: S) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Messages about this object and all objects below it. Can not be combined with `path`.
+    pub fn path_namespace<S: Into<String>>(mut self, path_namespace: S) -> Self {
+        self.path_namespace = Some(path_namespace.into());
+        self
+    }
+
+    /// The unique name of the connection the message is addressed to
+    pub fn destination<S: Into<String>>(mut self, destination: S) -> Self {
+        self.destination = Some(destination.into());
+        self
+    }
+
+    /// The argument at `idx` has to be the string `value`. Setting the same index again replaces the value.
+    ///
+    /// Returns None if `idx` is bigger than `MAX_ARG_INDEX`.
+    pub fn arg<S: Into<String>>(mut self, idx: u8, value: S) -> Option<Self> {
+        if idx > MAX_ARG_INDEX {
+            return None;
+        }
+        self.args.insert(idx, value.into());
+        Some(self)
+    }
+
+    /// The argument at `idx` has to be a string or object path that is equal to `value`, or one of them has to end
+    /// with `/` and be a prefix of the other. Setting the same index again replaces the value.
+    ///
+    /// Returns None if `idx` is bigger than `MAX_ARG_INDEX`.
+    pub fn arg_path<S: Into<String>>(mut self, idx: u8, value: S) -> Option<Self> {
+        if idx > MAX_ARG_INDEX {
+            return None;
+        }
+        self.arg_paths.insert(idx, value.into());
+        Some(self)
+    }
+
+    /// The first argument has to be a bus name that is `namespace` or below it, e.g. `com.example` matches
+    /// `com.example.Foo` but not `com.examples`
+    pub fn arg0_namespace<S: Into<String>>(mut self, namespace: S) -> Self {
+        self.arg0_namespace = Some(namespace.into());
+        self
+    }
+
+    /// The sender the rule asks for, a unique or well-known name
+    pub fn get_sender(&self) -> Option<&str> {
+        self.sender.as_deref()
+    }
+
+    /// Parse a rule in the format `AddMatch` takes, the way the bus does. Returns `None` for unknown keys, keys that
+    /// appear twice and values that are not valid for their key. The `eavesdrop` key is accepted and ignored.
+    ///
+    /// ```rust
+    /// use rustbus::match_rule::MatchRule;
+    /// use rustbus::MessageType;
+    ///
+    /// let rule = MatchRule::parse("type='signal',arg0='it'\\''s'").unwrap();
+    /// assert_eq!(rule, MatchRule::new().msg_type(MessageType::Signal).arg(0, "it's").unwrap());
+    /// assert_eq!(MatchRule::parse(&rule.to_string()), Some(rule));
+    /// ```
+    pub fn parse(rule: &str) -> Option<MatchRule> {
+        let mut parsed = MatchRule::new();
+        let mut seen = std::collections::HashSet::new();
+        for (key, value) in split_rule(rule)? {
+            if !seen.insert(key.clone()) {
+                return None;
+            }
+            let arg_idx = |suffix: &str| {
+                key.strip_prefix("arg")?
+                    .strip_suffix(suffix)?
+                    .parse::<u8>()
+                    .ok()
+            };
+            parsed = match key.as_str() {
+                "type" => parsed.msg_type(match value.as_str() {
+                    "signal" => MessageType::Signal,
+                    "method_call" => MessageType::Call,
+                    "method_return" => MessageType::Reply,
+                    "error" => MessageType::Error,
+                    _ => return None,
+                }),
+                "sender" => parsed.sender(value),
+                "interface" => parsed.interface(value),
+                "member" => parsed.member(value),
+                "path" => parsed.path(value),
+                "path_namespace" => parsed.path_namespace(value),
+                "destination" => parsed.destination(value),
+                "arg0namespace" => parsed.arg0_namespace(value),
+                "eavesdrop" => parsed,
+                _ => {
+                    if let Some(idx) = arg_idx("path") {
+                        parsed.arg_path(idx, value)?
+                    } else if let Some(idx) = arg_idx("") {
+                        parsed.arg(idx, value)?
+                    } else {
+                        return None;
+                    }
+                }
+            };
+        }
+        if parsed.path.is_some() && parsed.path_namespace.is_some() {
+            return None;
+        }
+        Some(parsed)
+    }
+
+    /// Check the rule against a received message, the way the bus would.
+    ///
+    /// The one exception is a well-known name as sender: only the bus knows which unique name owns it, so this
+    /// condition is ignored here. Rules with a unique name as sender are checked.
+    pub fn matches(&self, msg: &MarshalledMessage) -> bool {
+        let hdr = &msg.dynheader;
+        if self.msg_type.is_some_and(|typ| typ != msg.typ) {
+            return false;
+        }
+        if let Some(sender) = &self.sender {
+            if sender.starts_with(':') && hdr.sender.as_ref() != Some(sender) {
+                return false;
+            }
+        }
+        let exact = [
+            (&self.interface, &hdr.interface),
+            (&self.member, &hdr.member),
+            (&self.path, &hdr.object),
+            (&self.destination, &hdr.destination),
+        ];
+        if exact
+            .iter()
+            .any(|(wanted, found)| wanted.is_some() && wanted != found)
+        {
+            return false;
+        }
+        if let Some(namespace) = &self.path_namespace {
+            let in_namespace = hdr.object.as_deref().is_some_and(|path| {
+                namespace == "/"
+                    || path == namespace
+                    || path.starts_with(&format!("{}/", namespace))
+            });
+            if !in_namespace {
+                return false;
+            }
+        }
+
+        let args_match = self
+            .args
+            .iter()
+            .all(|(idx, value)| string_arg(msg, *idx, false) == Some(value.as_str()));
+        let arg_paths_match = self.arg_paths.iter().all(|(idx, value)| {
+            string_arg(msg, *idx, true).is_some_and(|arg| {
+                arg == value
+                    || (arg.ends_with('/') && value.starts_with(arg))
+                    || (value.ends_with('/') && arg.starts_with(value.as_str()))
+            })
+        });
+        let arg0_namespace_matches = self.arg0_namespace.as_ref().is_none_or(|namespace| {
+            string_arg(msg, 0, false)
+                .is_some_and(|arg| arg == namespace || arg.starts_with(&format!("{}.", namespace)))
+        });
+        args_match && arg_paths_match && arg0_namespace_matches
+    }
+}
+
+/// Split a rule into its keys and unquoted values. Inside of quotes everything is taken literally, outside of them `\'`
+/// is a quote and `,` separates the pairs.
+fn split_rule(rule: &str) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut chars = rule.chars().peekable();
+    while chars.peek().is_some() {
+        let mut key = String::new();
+        loop {
+            match chars.next()? {
+                '=' => break,
+                c => key.push(c),
+            }
+        }
+        let key = key.trim().to_owned();
+        if key.is_empty() {
+            return None;
+        }
+        let mut value = String::new();
+        let mut quoted = false;
+        loop {
+            match (chars.next(), quoted) {
+                (None, true) => return None,
+                (None, false) | (Some(','), false) => break,
+                (Some('\''), _) => quoted = !quoted,
+                (Some('\\'), false) if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    value.push('\'');
+                }
+                (Some(c), _) => value.push(c),
+            }
+        }
+        pairs.push((key, value));
+    }
+    Some(pairs)
+}
+
+/// The argument at `idx` if it is a string, or an object path if `allow_path` is set
+fn string_arg(msg: &MarshalledMessage, idx: u8, allow_path: bool) -> Option<&str> {
+    let (sig, buf, fds) = msg.body.raw();
+    let types = Type::parse_description(sig).ok()?;
+    let mut offset = 0;
+    for typ in types.iter().take(idx as usize) {
+        offset +=
+            crate::wire::validate_raw::validate_marshalled(msg.body.byteorder(), offset, buf, typ)
+                .ok()?;
+    }
+    match types.get(idx as usize)? {
+        Type::Base(Base::String) => {}
+        Type::Base(Base::ObjectPath) if allow_path => {}
+        _ => return None,
+    }
+    UnmarshalContext::new(fds, msg.body.byteorder(), buf, offset)
+        .read_str()
+        .ok()
+}
+
+impl fmt::Display for MatchRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(typ) = self.msg_type {
+            let typ = match typ {
+                MessageType::Signal => "signal",
+                MessageType::Call => "method_call",
+                MessageType::Reply => "method_return",
+                MessageType::Error => "error",
+                MessageType::Invalid => "invalid",
+            };
+            parts.push(format!("type={}", quote(typ)));
+        }
+        let keys = [
+            ("sender", &self.sender),
+            ("interface", &self.interface),
+            ("member", &self.member),
+            ("path", &self.path),
+            ("path_namespace", &self.path_namespace),
+            ("destination", &self.destination),
+        ];
+        for (key, value) in keys.iter() {
+            if let Some(value) = value {
+                parts.push(format!("{}={}", key, quote(value)));
+            }
+        }
+        for (idx, value) in &self.args {
+            parts.push(format!("arg{}={}", idx, quote(value)));
+        }
+        for (idx, value) in &self.arg_paths {
+            parts.push(format!("arg{}path={}", idx, quote(value)));
+        }
+        if let Some(namespace) = &self.arg0_namespace {
+            parts.push(format!("arg0namespace={}", quote(namespace)));
+        }
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_rule() {
+        assert_eq!(MatchRule::new().to_string(), "");
+        let rule = MatchRule::new()
+            .arg_path(1, "/io/killing/")
+            .unwrap()
+            .arg(2, "it's")
+            .unwrap()
+            .arg(0, "first")
+            .unwrap()
+            .destination(":1.42")
+            .path_namespace("/io/killing")
+            .sender("io.killing.spark")
+            .msg_type(MessageType::Signal)
+            .arg0_namespace("io.killing");
+        assert_eq!(
+            rule.to_string(),
+            "type='signal',sender='io.killing.spark',path_namespace='/io/killing',destination=':1.42',\
+             arg0='first',arg2='it'\\''s',arg1path='/io/killing/',arg0namespace='io.killing'"
+        );
+        assert_eq!(rule.clone().arg(0, "first").unwrap(), rule);
+        assert_ne!(rule.clone().arg(0, "other").unwrap(), rule);
+    }
+
+    #[test]
+    fn test_matches() {
+        use crate::message_builder::MessageBuilder;
+        use crate::wire::ObjectPath;
+
+        let mut sig = MessageBuilder::new()
+            .signal("io.killing.spark", "Changed", "/io/killing/spark/1")
+            .build();
+        sig.dynheader.sender = Some(":1.7".to_owned());
+        sig.body
+            .push_param3(
+                "io.killing.spark.Thing",
+                vec![1u32, 2],
+                ObjectPath::new("/io/killing/spark/1").unwrap(),
+            )
+            .unwrap();
+        sig.body.push_param("last").unwrap();
+
+        let rule = || MatchRule::new().msg_type(MessageType::Signal);
+        let matching = [
+            MatchRule::new(),
+            rule().interface("io.killing.spark").member("Changed"),
+            rule().sender(":1.7"),
+            // only the bus can resolve well-known names
+            rule().sender("io.killing.spark"),
+            rule().path("/io/killing/spark/1"),
+            rule().path_namespace("/io/killing"),
+            rule().path_namespace("/"),
+            rule()
+                .arg(0, "io.killing.spark.Thing")
+                .unwrap()
+                .arg(3, "last")
+                .unwrap(),
+            rule().arg_path(2, "/io/killing/").unwrap(),
+            rule().arg_path(0, "io.killing.spark.Thing").unwrap(),
+            rule().arg0_namespace("io.killing.spark"),
+            rule().arg0_namespace("io.killing.spark.Thing"),
+        ];
+        for rule in &matching {
+            assert!(rule.matches(&sig), "{} should match", rule);
+        }
+        let not_matching = [
+            MatchRule::new().msg_type(MessageType::Call),
+            rule().sender(":1.8"),
+            rule().member("Other"),
+            rule().path("/io/killing"),
+            rule().path_namespace("/io/kill"),
+            rule().destination(":1.1"),
+            rule().arg(0, "io.killing").unwrap(),
+            // arg matches only strings
+            rule().arg(1, "1").unwrap(),
+            rule().arg(2, "/io/killing/spark/1").unwrap(),
+            rule().arg(4, "missing").unwrap(),
+            rule().arg_path(2, "/io/killing").unwrap(),
+            rule().arg0_namespace("io.killing.spa"),
+        ];
+        for rule in &not_matching {
+            assert!(!rule.matches(&sig), "{} should not match", rule);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule = MatchRule::new()
+            .arg_path(1, "/io/killing/")
+            .unwrap()
+            .arg(2, "it's, quoted")
+            .unwrap()
+            .destination(":1.42")
+            .path_namespace("/io/killing")
+            .sender("io.killing.spark")
+            .msg_type(MessageType::Signal)
+            .arg0_namespace("io.killing");
+        assert_eq!(MatchRule::parse(&rule.to_string()), Some(rule));
+        assert_eq!(MatchRule::parse(""), Some(MatchRule::new()));
+        assert_eq!(
+            MatchRule::parse("member=Changed, eavesdrop='true',arg63=x"),
+            Some(MatchRule::new().member("Changed").arg(63, "x").unwrap())
+        );
+        assert_eq!(
+            MatchRule::parse("arg0=\\'a"),
+            Some(MatchRule::new().arg(0, "'a").unwrap())
+        );
+
+        for invalid in [
+            "type='signals'",
+            "member='a',member='b'",
+            "path='/a',path_namespace='/'",
+            "arg64='x'",
+            "arg64path='x'",
+            "argpath='x'",
+            "unknown='x'",
+            "member='open",
+            "='x'",
+            "member",
+        ] {
+            assert_eq!(MatchRule::parse(invalid), None, "{}", invalid);
+        }
+        assert_eq!(MatchRule::new().arg(64, "x"), None);
+        assert_eq!(MatchRule::new().arg_path(64, "/"), None);
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("abc"), "'abc'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote("a,b\\c"), "'a,b\\c'");
+    }
+}
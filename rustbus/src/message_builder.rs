@@ -2,17 +2,24 @@
 use std::num::NonZeroU32;
 use std::os::fd::RawFd;
 
+#[cfg(rustbus_params)]
 use crate::params::message;
 use crate::signature::SignatureIter;
+use crate::sync::{Arc, OnceLock};
 use crate::wire::errors::MarshalError;
 use crate::wire::errors::UnmarshalError;
-use crate::wire::marshal::traits::{Marshal, SignatureBuffer};
+use crate::wire::marshal::traits::{Marshal, Signature, SignatureBuffer};
 use crate::wire::marshal::MarshalContext;
 use crate::wire::unmarshal_context::UnmarshalContext;
 use crate::wire::validate_raw;
 use crate::wire::UnixFd;
 use crate::ByteOrder;
 
+/// The first bytes of a file written by `MarshalledMessage::save`, the last one is the version of the format
+pub const FIXTURE_MAGIC: &[u8; 8] = b"rbusmsg\x01";
+/// The magic bytes, the number of fds and the length of the message
+const FIXTURE_HEADER_LEN: usize = 16;
+
 /// Types a message might have
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MessageType {
@@ -41,7 +48,7 @@ impl HeaderFlags {
     }
 
     pub fn is_set(self, flags: u8) -> bool {
-        flags & self.into_raw() == 1
+        flags & self.into_raw() != 0
     }
 
     pub fn set(self, flags: &mut u8) {
@@ -61,6 +68,9 @@ impl HeaderFlags {
 }
 
 /// The dynamic part of a dbus message header
+///
+/// New fields may be added in minor releases before 1.0, use `..Default::default()` in struct literals to fill in
+/// the fields you do not set.
 #[derive(Debug, Clone, Default)]
 pub struct DynamicHeader {
     pub interface: Option<String>,
@@ -72,7 +82,13 @@ pub struct DynamicHeader {
     pub signature: Option<String>,
     pub error_name: Option<String>,
     pub response_serial: Option<NonZeroU32>,
+    /// Set for received messages. There is no need to set this for messages you send, the header field is filled in
+    /// from the fds that were pushed into the body. If it is set anyway it has to match them.
     pub num_fds: Option<u32>,
+    /// Header fields with codes the spec does not define (yet), in the order they were received. They are marshalled
+    /// after the known fields, so messages can be passed on without losing them. See `UnknownHeaderField::new` to add
+    /// your own.
+    pub unknown_fields: Vec<crate::wire::UnknownHeaderField>,
 }
 
 impl DynamicHeader {
@@ -95,6 +111,7 @@ impl DynamicHeader {
                 signature: None,
                 response_serial: self.serial,
                 error_name: Some(error_name.into()),
+                unknown_fields: Vec::new(),
             },
             flags: 0,
             body: crate::message_builder::MarshalledMessageBody::new(),
@@ -104,6 +121,20 @@ impl DynamicHeader {
         }
         err_resp
     }
+    /// Like make_error_response but formats the error message. Use `format_args!` to create the arguments:
+    /// ```rust
+    /// use rustbus::consts::DBUS_ERROR_FILE_NOT_FOUND;
+    /// # let call = rustbus::message_builder::DynamicHeader::default();
+    /// # let path = "/tmp/file";
+    /// let err = call.make_error_response_fmt(DBUS_ERROR_FILE_NOT_FOUND, format_args!("{} does not exist", path));
+    /// ```
+    pub fn make_error_response_fmt<S: Into<String>>(
+        &self,
+        error_name: S,
+        error_msg: std::fmt::Arguments,
+    ) -> crate::message_builder::MarshalledMessage {
+        self.make_error_response(error_name, Some(std::fmt::format(error_msg)))
+    }
     /// Make a correctly addressed response with the correct response serial
     pub fn make_response(&self) -> crate::message_builder::MarshalledMessage {
         crate::message_builder::MarshalledMessage {
@@ -119,6 +150,7 @@ impl DynamicHeader {
                 signature: None,
                 response_serial: self.serial,
                 error_name: None,
+                unknown_fields: Vec::new(),
             },
             flags: 0,
             body: crate::message_builder::MarshalledMessageBody::new(),
@@ -192,6 +224,30 @@ impl CallBuilder {
         self
     }
 
+    /// Set a flag in the header of the call
+    pub fn with_flag(mut self, flag: HeaderFlags) -> Self {
+        flag.set(&mut self.msg.flags);
+        self
+    }
+
+    /// Tell the bus to not start the destination service if it is not already running.
+    /// The call will then fail with org.freedesktop.DBus.Error.ServiceUnknown instead.
+    pub fn no_auto_start(self) -> Self {
+        self.with_flag(HeaderFlags::NoAutoStart)
+    }
+
+    /// See `MarshalledMessage::set_deadline`
+    pub fn with_deadline(mut self, deadline: std::time::SystemTime) -> Self {
+        self.msg.set_deadline(deadline);
+        self
+    }
+
+    /// Tell the receiver that it may ask the user to authorize the call (e.g. with a polkit dialog) instead of failing
+    /// with org.freedesktop.DBus.Error.InteractiveAuthorizationRequired. The call can take much longer then.
+    pub fn allow_interactive_authorization(self) -> Self {
+        self.with_flag(HeaderFlags::AllowInteractiveAuthorization)
+    }
+
     pub fn build(self) -> MarshalledMessage {
         self.msg
     }
@@ -208,6 +264,68 @@ impl SignalBuilder {
     }
 }
 
+/// The header fields routing decisions are usually made on, borrowed from a message, see `MarshalledMessage::classify`.
+/// Comparing these does not clone any of the header strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageClassifier<'a> {
+    pub typ: MessageType,
+    pub interface: Option<&'a str>,
+    pub member: Option<&'a str>,
+    pub object: Option<&'a str>,
+    pub sender: Option<&'a str>,
+    pub destination: Option<&'a str>,
+    pub error_name: Option<&'a str>,
+}
+
+impl MessageClassifier<'_> {
+    /// Whether this is a call of `member` on `interface`. Calls without an interface may be answered by any
+    /// interface that has the member, so they match every interface.
+    pub fn is_call_to(&self, interface: &str, member: &str) -> bool {
+        self.typ == MessageType::Call
+            && self.member == Some(member)
+            && self.interface.is_none_or(|own| own == interface)
+    }
+
+    /// Whether this is the signal `member` of `interface`
+    pub fn is_signal(&self, interface: &str, member: &str) -> bool {
+        self.typ == MessageType::Signal
+            && self.interface == Some(interface)
+            && self.member == Some(member)
+    }
+
+    /// Whether this is an error message with the error name `name`
+    pub fn is_error(&self, name: &str) -> bool {
+        self.typ == MessageType::Error && self.error_name == Some(name)
+    }
+
+    /// Whether the object path is `path` or lies below it
+    pub fn is_below(&self, path: &str) -> bool {
+        let Some(object) = self.object else {
+            return false;
+        };
+        match object.strip_prefix(path) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || path == "/",
+            None => false,
+        }
+    }
+}
+
+/// The error name of an error message and the human readable message most services put as a string into its body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorReply {
+    pub name: String,
+    pub message: Option<String>,
+}
+
+impl std::fmt::Display for ErrorReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.name, message),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
 /// Message received by a connection or in preparation before being sent over a connection.
 ///
 /// This represents a message while it is being built before it is sent over the connection.
@@ -238,6 +356,68 @@ impl MarshalledMessage {
         &self.body.sig
     }
 
+    /// Borrow the header fields routing decisions are usually made on
+    pub fn classify(&self) -> MessageClassifier<'_> {
+        MessageClassifier {
+            typ: self.typ,
+            interface: self.dynheader.interface.as_deref(),
+            member: self.dynheader.member.as_deref(),
+            object: self.dynheader.object.as_deref(),
+            sender: self.dynheader.sender.as_deref(),
+            destination: self.dynheader.destination.as_deref(),
+            error_name: self.dynheader.error_name.as_deref(),
+        }
+    }
+
+    /// Tell the receiver until when the call has to be answered, so it can skip calls nobody waits for anymore, see
+    /// `middleware::Deadlines`. This is not part of the spec, the deadline is sent in a custom header field which is
+    /// ignored by receivers that do not know it. Replaces a deadline that was set before.
+    pub fn set_deadline(&mut self, deadline: std::time::SystemTime) {
+        let micros = deadline
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        let field = crate::wire::UnknownHeaderField::new(crate::wire::DEADLINE_FIELD_CODE, micros)
+            .expect("a u64 can always be marshalled");
+        self.dynheader
+            .unknown_fields
+            .retain(|field| field.code != crate::wire::DEADLINE_FIELD_CODE);
+        self.dynheader.unknown_fields.push(field);
+    }
+
+    /// The deadline the sender put into the message with `set_deadline`
+    pub fn deadline(&self) -> Option<std::time::SystemTime> {
+        let field = self
+            .dynheader
+            .unknown_fields
+            .iter()
+            .find(|field| field.code == crate::wire::DEADLINE_FIELD_CODE)?;
+        let micros = field.get::<u64>().ok()?;
+        std::time::UNIX_EPOCH.checked_add(std::time::Duration::from_micros(micros))
+    }
+
+    /// Whether this is an error message with the error name `name`
+    pub fn is_error_named(&self, name: &str) -> bool {
+        self.classify().is_error(name)
+    }
+
+    /// The error name and message if this is an error message, `None` for all other message types
+    pub fn error_reply(&self) -> Option<ErrorReply> {
+        if self.typ != MessageType::Error {
+            return None;
+        }
+        Some(ErrorReply {
+            name: self.dynheader.error_name.clone().unwrap_or_default(),
+            message: self.body.parser().get::<String>().ok(),
+        })
+    }
+
+    /// Whether this is the error a service returns if the call needs interactive authorization but the call did not
+    /// allow it. The call can be sent again with `HeaderFlags::AllowInteractiveAuthorization` set, see
+    /// `RpcConn::call_method_with_interactive_auth`.
+    pub fn requires_interactive_authorization(&self) -> bool {
+        self.is_error_named(crate::consts::DBUS_ERROR_INTERACTIVE_AUTHORIZATION_REQUIRED)
+    }
+
     /// New message with the default native byteorder
     pub fn new() -> Self {
         MarshalledMessage {
@@ -266,13 +446,165 @@ impl MarshalledMessage {
         self.body.reserve(additional)
     }
 
+    /// Calculate how many bytes this message will occupy on the wire (header, padding and body). This can be compared against
+    /// `wire::unmarshal::MAX_MESSAGE_LEN` or your own limits before trying to send a message the bus would reject.
+    ///
+    /// This marshals the header into a scratch buffer, so it fails with the same errors sending the message would.
+    pub fn estimated_wire_size(&self) -> Result<usize, MarshalError> {
+        Ok(self.marshal_header()?.len() + self.get_buf().len())
+    }
+
+    /// Marshal the whole message, header and body, and append it to `buf`. This needs no connection, e.g. to embed dbus
+    /// frames in other protocols or to write fixtures. The serial from the dynheader is used, `NonZeroU32::MIN` if it is
+    /// not set. Filedescriptors are not part of the bytes, the body only contains their indices.
+    ///
+    /// ```rust
+    /// use rustbus::MessageBuilder;
+    ///
+    /// let mut msg = MessageBuilder::new().signal("io.killing.spark", "Fixture", "/").build();
+    /// msg.body.push_param("payload").unwrap();
+    /// let mut frame = b"FRAME".to_vec();
+    /// msg.marshal_into(&mut frame).unwrap();
+    /// assert_eq!(frame.len(), 5 + msg.estimated_wire_size().unwrap());
+    /// ```
+    pub fn marshal_into(&self, buf: &mut Vec<u8>) -> Result<(), MarshalError> {
+        let header = self.marshal_header()?;
+        buf.reserve(header.len() + self.get_buf().len());
+        buf.extend_from_slice(&header);
+        buf.extend_from_slice(self.get_buf());
+        Ok(())
+    }
+
+    /// Like `marshal_into` but write the message to `w`
+    pub fn write_to<W: std::io::Write>(&self, mut w: W) -> Result<(), crate::connection::Error> {
+        w.write_all(&self.marshal_header()?)?;
+        w.write_all(self.get_buf())?;
+        Ok(())
+    }
+
+    /// Save the message to a file, e.g. as a fixture for a test or to attach it to a bug report. The file contains the
+    /// message exactly as it would be sent, so `load` gives back the same bytes even if the body is malformed.
+    ///
+    /// The fds can not be saved, only their number is. The format is:
+    /// * the magic bytes `FIXTURE_MAGIC`
+    /// * the number of fds as a little endian u32
+    /// * the length of the message as a little endian u32
+    /// * the message
+    ///
+    /// ```rust
+    /// use rustbus::message_builder::MarshalledMessage;
+    /// use rustbus::MessageBuilder;
+    ///
+    /// let mut msg = MessageBuilder::new().signal("io.killing.spark", "Fixture", "/").build();
+    /// msg.body.push_param((1u8, 2u64)).unwrap();
+    /// let path = std::env::temp_dir().join(format!("rustbus-fixture-doc-{}", std::process::id()));
+    /// msg.save(&path).unwrap();
+    ///
+    /// let loaded = MarshalledMessage::load(&path).unwrap();
+    /// assert_eq!(loaded.body.parser().get::<(u8, u64)>().unwrap(), (1, 2));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), crate::connection::Error> {
+        let mut bytes = FIXTURE_MAGIC.to_vec();
+        bytes.extend_from_slice(&(self.body.get_fds().len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        self.marshal_into(&mut bytes)?;
+        let len = (bytes.len() - FIXTURE_HEADER_LEN) as u32;
+        bytes[FIXTURE_HEADER_LEN - 4..FIXTURE_HEADER_LEN].copy_from_slice(&len.to_le_bytes());
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Load a message saved with `save`. Only the header is checked, the body is loaded as it is so errors in it can be
+    /// reproduced. Each fd the message had is replaced by an fd of `/dev/null`, so a body containing fds can still be
+    /// unmarshalled.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, crate::connection::Error> {
+        use std::convert::TryInto;
+        use std::os::fd::IntoRawFd;
+
+        let invalid = |what: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("not a message fixture: {}", what),
+            )
+        };
+        let mut bytes = std::fs::read(path)?;
+        if bytes.len() < FIXTURE_HEADER_LEN || !bytes.starts_with(FIXTURE_MAGIC) {
+            return Err(invalid("the magic bytes are missing").into());
+        }
+        let read_u32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let fd_count = read_u32(FIXTURE_MAGIC.len());
+        let len = read_u32(FIXTURE_MAGIC.len() + 4) as usize;
+        if bytes.len() - FIXTURE_HEADER_LEN != len {
+            return Err(invalid("the length does not match").into());
+        }
+        // there are never more fds than the number of 4 byte indices fitting into the message
+        if fd_count as usize > len / 4 {
+            return Err(invalid("too many fds").into());
+        }
+        let fds = (0..fd_count)
+            .map(|_| {
+                let null = std::fs::File::open("/dev/null")?;
+                Ok(UnixFd::new(null.into_raw_fd()))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let msg_bytes = bytes.split_off(FIXTURE_HEADER_LEN);
+        let mut cursor = crate::wire::unmarshal_context::Cursor::new(&msg_bytes);
+        let header = crate::wire::unmarshal::unmarshal_header(&mut cursor)?;
+        let dynheader = crate::wire::unmarshal::unmarshal_dynamic_header(&header, &mut cursor)?;
+        let offset = cursor.consumed();
+        Ok(crate::wire::unmarshal::unmarshal_next_message(
+            &header, dynheader, msg_bytes, offset, fds,
+        )?)
+    }
+
+    /// The marshalled header including the padding before the body. It is marshalled on its own because the padding is
+    /// relative to the start of the message.
+    fn marshal_header(&self) -> Result<Vec<u8>, MarshalError> {
+        let mut header_buf = Vec::new();
+        let serial = self.dynheader.serial.unwrap_or(NonZeroU32::MIN);
+        crate::wire::marshal::marshal(self, serial, &mut header_buf)?;
+        Ok(header_buf)
+    }
+
+    /// Decode the body and print the message like dbus-monitor does, one param per line and nested containers
+    /// indented. This is the same as the `Display` impl. Bodies that do not match their signature are printed as
+    /// the decoding error and a hex dump of their bytes. Strings that are not valid UTF-8 are printed lossily with a
+    /// note, so one misbehaving peer does not hide the contents of its messages.
+    #[cfg(rustbus_params)]
+    pub fn to_pretty_string(&self) -> String {
+        self.to_string()
+    }
+
+    #[cfg(rustbus_params)]
     pub fn unmarshall_all<'a, 'e>(self) -> Result<message::Message<'a, 'e>, UnmarshalError> {
+        self.unmarshall_all_with(false)
+    }
+
+    /// Like `unmarshall_all` but strings that are not valid UTF-8 do not fail the message, their invalid sequences are
+    /// replaced by U+FFFD. Use `wire::RawStr` with the typed API to get the bytes as they were sent.
+    #[cfg(rustbus_params)]
+    pub fn unmarshall_all_lossy<'a, 'e>(self) -> Result<message::Message<'a, 'e>, UnmarshalError> {
+        self.unmarshall_all_with(true)
+    }
+
+    #[cfg(rustbus_params)]
+    fn unmarshall_all_with<'a, 'e>(
+        self,
+        lossy: bool,
+    ) -> Result<message::Message<'a, 'e>, UnmarshalError> {
         let params = if self.body.sig.is_empty() {
             vec![]
         } else {
             let sigs: Vec<_> = crate::signature::Type::parse_description(&self.body.sig)?;
 
-            crate::wire::unmarshal::unmarshal_body(
+            let unmarshal_body = if lossy {
+                crate::wire::unmarshal::unmarshal_body_lossy
+            } else {
+                crate::wire::unmarshal::unmarshal_body
+            };
+            unmarshal_body(
                 self.body.byteorder,
                 &sigs,
                 self.body.get_buf(),
@@ -289,17 +621,103 @@ impl MarshalledMessage {
         })
     }
 }
+
+/// Keeps sent messages around so their buffers can be reused for the next ones. This is like `MarshalledMessageBody::reset`
+/// but for senders that build many different messages, it saves allocating a new body buffer and signature for each
+/// of them. Each `SendConn` has one, see `SendConn::send_and_recycle`.
+///
+/// ```rust
+/// use rustbus::message_builder::MessagePool;
+///
+/// let mut pool = MessagePool::default();
+/// for value in 0..3u32 {
+///     let mut msg = pool.builder().signal("io.killing.spark", "Value", "/").build();
+///     msg.body.push_param(value).unwrap();
+///     // ... send the message
+///     pool.give_back(msg);
+/// }
+/// assert_eq!(pool.len(), 1);
+/// ```
+#[derive(Debug)]
+pub struct MessagePool {
+    messages: Vec<MarshalledMessage>,
+    max_messages: usize,
+    byteorder: ByteOrder,
+}
+
+impl Default for MessagePool {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_MESSAGES)
+    }
+}
+
+impl MessagePool {
+    /// How many messages a pool made with `default` keeps at most
+    pub const DEFAULT_MAX_MESSAGES: usize = 16;
+
+    /// Keep at most `max_messages` messages, more are dropped when they are given back
+    pub fn new(max_messages: usize) -> Self {
+        Self::with_byteorder(max_messages, ByteOrder::NATIVE)
+    }
+
+    /// Like `new` but the messages handed out use the chosen byteorder
+    pub fn with_byteorder(max_messages: usize, byteorder: ByteOrder) -> Self {
+        MessagePool {
+            messages: Vec::new(),
+            max_messages,
+            byteorder,
+        }
+    }
+
+    /// A message that is as empty as `MarshalledMessage::new()` but may have allocated buffers already
+    pub fn take(&mut self) -> MarshalledMessage {
+        match self.messages.pop() {
+            Some(msg) => msg,
+            None => MarshalledMessage::with_byteorder(self.byteorder),
+        }
+    }
+
+    /// A `MessageBuilder` that builds into a message from the pool
+    pub fn builder(&mut self) -> MessageBuilder {
+        MessageBuilder { msg: self.take() }
+    }
+
+    /// Clear the message and keep it for reuse, unless the pool is full already
+    pub fn give_back(&mut self, mut msg: MarshalledMessage) {
+        if self.messages.len() >= self.max_messages {
+            return;
+        }
+        msg.body.reset();
+        msg.body.byteorder = self.byteorder;
+        msg.dynheader = DynamicHeader::default();
+        msg.typ = MessageType::Invalid;
+        msg.flags = 0;
+        self.messages.push(msg);
+    }
+
+    /// How many messages are ready to be reused
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
 /// The body accepts everything that implements the Marshal trait (e.g. all basic types, strings, slices, Hashmaps,.....)
 /// And you can of course write an Marshal impl for your own datastrcutures
 #[derive(Debug)]
 pub struct MarshalledMessageBody {
-    buf: Vec<u8>,
+    buf: BodyBuf,
     buf_offset: usize,
 
     // out of band data
     raw_fds: Vec<crate::wire::UnixFd>,
 
     sig: SignatureBuffer,
+    /// The parsed `sig`, filled by `signature_types` and cleared whenever `sig` changes
+    sig_types: OnceLock<Vec<crate::signature::Type>>,
     byteorder: ByteOrder,
 }
 
@@ -309,6 +727,56 @@ impl Default for MarshalledMessageBody {
     }
 }
 
+/// The bytes of a body. Shared buffers are copied into an owned one as soon as the body gets modified.
+#[derive(Debug)]
+enum BodyBuf {
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>),
+}
+
+impl BodyBuf {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            BodyBuf::Owned(buf) => buf,
+            BodyBuf::Shared(buf) => buf,
+        }
+    }
+
+    fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let BodyBuf::Shared(buf) = self {
+            *self = BodyBuf::Owned(buf.to_vec());
+        }
+        match self {
+            BodyBuf::Owned(buf) => buf,
+            BodyBuf::Shared(_) => unreachable!(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn clear(&mut self) {
+        match self {
+            BodyBuf::Owned(buf) => {
+                #[cfg(feature = "zeroize")]
+                crate::wire::util::scrub(buf);
+                buf.clear()
+            }
+            BodyBuf::Shared(_) => *self = BodyBuf::Owned(Vec::new()),
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for BodyBuf {
+    fn drop(&mut self) {
+        if let BodyBuf::Owned(buf) = self {
+            crate::wire::util::scrub(buf);
+        }
+    }
+}
+
 /// Helper function you might need, if the dbus API you use has Variants somewhere inside nested structures. If the the
 /// API has a Variant at the top-level you can use MarshalledMessageBody::push_variant.
 pub fn marshal_as_variant<P: Marshal>(
@@ -339,10 +807,11 @@ impl MarshalledMessageBody {
     /// New messagebody with the default native byteorder
     pub fn new() -> Self {
         MarshalledMessageBody {
-            buf: Vec::new(),
+            buf: BodyBuf::Owned(Vec::new()),
             buf_offset: 0,
             raw_fds: Vec::new(),
             sig: SignatureBuffer::new(),
+            sig_types: OnceLock::new(),
             byteorder: ByteOrder::NATIVE,
         }
     }
@@ -350,10 +819,11 @@ impl MarshalledMessageBody {
     /// New messagebody with a chosen byteorder
     pub fn with_byteorder(b: ByteOrder) -> Self {
         MarshalledMessageBody {
-            buf: Vec::new(),
+            buf: BodyBuf::Owned(Vec::new()),
             buf_offset: 0,
             raw_fds: Vec::new(),
             sig: SignatureBuffer::new(),
+            sig_types: OnceLock::new(),
             byteorder: b,
         }
     }
@@ -367,16 +837,82 @@ impl MarshalledMessageBody {
     ) -> Self {
         let sig = SignatureBuffer::from_string(sig);
         Self {
-            buf,
+            buf: BodyBuf::Owned(buf),
             buf_offset,
             raw_fds,
             sig,
+            sig_types: OnceLock::new(),
+            byteorder,
+        }
+    }
+
+    /// Use a body that has been marshalled before without copying it. Only the header needs to be marshalled when a message
+    /// with this body is sent, which is useful for replies that are sent over and over again with the same content.
+    ///
+    /// Like `from_parts` this does not check that `buf` actually matches `sig`, use `validate` if the buffer comes from
+    /// an untrusted source. Pushing more params to the body copies the buffer first.
+    ///
+    /// ```rust
+    /// use rustbus::message_builder::{MarshalledMessageBody, MessageBuilder};
+    ///
+    /// let mut template = MarshalledMessageBody::new();
+    /// template.push_param("a reply that is expensive to marshal").unwrap();
+    /// let (buf, sig) = template.make_shared().unwrap();
+    ///
+    /// let mut reply = MessageBuilder::new().signal("io.killing.spark", "Cached", "/").build();
+    /// reply.body = MarshalledMessageBody::from_shared(buf, sig, template.byteorder());
+    /// assert_eq!(reply.body.parser().get::<&str>().unwrap(), "a reply that is expensive to marshal");
+    /// ```
+    pub fn from_shared(buf: Arc<[u8]>, sig: String, byteorder: ByteOrder) -> Self {
+        Self {
+            buf: BodyBuf::Shared(buf),
+            buf_offset: 0,
+            raw_fds: Vec::new(),
+            sig: SignatureBuffer::from_string(sig),
+            sig_types: OnceLock::new(),
             byteorder,
         }
     }
 
+    /// Turn the buffer of this body into a shared one (this copies it once) and return it together with the signature,
+    /// so it can be used for other bodies with `from_shared`. Filedescriptors are not part of the buffer, so bodies that
+    /// contain filedescriptors are rejected with `MarshalError::SharedBodyWithFds` and left unchanged.
+    pub fn make_shared(&mut self) -> Result<(Arc<[u8]>, String), MarshalError> {
+        if !self.raw_fds.is_empty() {
+            return Err(MarshalError::SharedBodyWithFds);
+        }
+        let shared = match &self.buf {
+            BodyBuf::Shared(buf) if self.buf_offset == 0 => buf.clone(),
+            _ => Arc::from(self.get_buf()),
+        };
+        self.buf = BodyBuf::Shared(shared.clone());
+        self.buf_offset = 0;
+        Ok((shared, self.sig.as_str().to_owned()))
+    }
+
     pub(crate) fn get_buf(&self) -> &[u8] {
-        &self.buf[self.buf_offset..]
+        &self.buf.as_slice()[self.buf_offset..]
+    }
+
+    /// The signature, the marshalled bytes and the fds of the body, for decoders that do not go through the parser.
+    /// The bytes start at an 8 byte aligned position of the message, so alignment can be computed from offset 0. They
+    /// are in the byteorder returned by `byteorder`, and values of type `h` are indexes into the fds.
+    ///
+    /// ```rust
+    /// use rustbus::wire::unmarshal_context::UnmarshalContext;
+    /// use rustbus::{MessageBuilder, Unmarshal};
+    ///
+    /// let mut msg = MessageBuilder::new().signal("io.killing.spark", "Raw", "/").build();
+    /// msg.body.push_param2(42u32, "text").unwrap();
+    ///
+    /// let (sig, bytes, fds) = msg.body.raw();
+    /// assert_eq!(sig, "us");
+    /// let mut ctx = UnmarshalContext::new(fds, msg.body.byteorder(), bytes, 0);
+    /// assert_eq!(u32::unmarshal(&mut ctx).unwrap(), 42);
+    /// assert_eq!(<&str>::unmarshal(&mut ctx).unwrap(), "text");
+    /// ```
+    pub fn raw(&self) -> (&str, &[u8], &[UnixFd]) {
+        (self.sig.as_str(), self.get_buf(), &self.raw_fds)
     }
 
     pub fn get_raw_fds(&self) -> Vec<RawFd> {
@@ -396,50 +932,97 @@ impl MarshalledMessageBody {
     pub fn get_fds(&self) -> &[UnixFd] {
         &self.raw_fds
     }
-    /// Clears the buffer and signature but holds on to the memory allocations. You can now start pushing new
+    /// Clears the buffer, signature and fds but holds on to the memory allocations. You can now start pushing new
     /// params as if this were a new message. This allows to reuse the OutMessage for the same dbus-message with different
     /// parameters without allocating the buffer every time.
     pub fn reset(&mut self) {
-        self.sig.clear();
+        self.sig_mut().clear();
         self.buf.clear();
         self.buf_offset = 0;
+        self.raw_fds.clear();
     }
 
     /// Reserves space for `additional` bytes in the internal buffer. This is useful to reduce the amount of allocations done while marshalling,
     /// if you can predict somewhat accuratly how many bytes you will be marshalling.
     pub fn reserve(&mut self, additional: usize) {
-        self.buf.reserve(additional)
+        self.buf.to_mut().reserve(additional)
     }
 
     /// Push a Param with the old nested enum/struct approach. This is still supported for the case that in some corner cases
     /// the new trait/type based API does not work.
+    #[cfg(rustbus_params)]
     pub fn push_old_param(&mut self, p: &crate::params::Param) -> Result<(), MarshalError> {
         let mut ctx = self.create_ctx();
         crate::wire::marshal::container::marshal_param(p, &mut ctx)?;
-        p.sig().to_str(self.sig.to_string_mut());
+        p.sig().to_str(self.sig_mut().to_string_mut());
         Ok(())
     }
 
     /// Convenience function to call push_old_param on a slice of Param
+    #[cfg(rustbus_params)]
     pub fn push_old_params(&mut self, ps: &[crate::params::Param]) -> Result<(), MarshalError> {
         for p in ps {
             self.push_old_param(p)?;
         }
         Ok(())
     }
-    fn create_ctx(&mut self) -> MarshalContext {
+    /// The signature for modification. The cached types no longer match it afterwards.
+    fn sig_mut(&mut self) -> &mut SignatureBuffer {
+        self.sig_types.take();
+        &mut self.sig
+    }
+
+    /// The types of the top-level params of the body, e.g. to handle APIs that return different types depending on
+    /// their version. The signature is parsed on the first call only, bodies without params have no types.
+    ///
+    /// ```rust
+    /// use rustbus::signature::{Base, Type};
+    /// use rustbus::MessageBuilder;
+    ///
+    /// let mut msg = MessageBuilder::new().signal("io.killing.spark", "Versioned", "/").build();
+    /// msg.body.push_param(("name", "version")).unwrap();
+    /// let (name, version) = match msg.body.signature_types().unwrap() {
+    ///     [Type::Base(Base::String)] => (msg.body.parser().get::<&str>().unwrap(), None),
+    ///     _ => {
+    ///         let (name, version) = msg.body.parser().get::<(&str, &str)>().unwrap();
+    ///         (name, Some(version))
+    ///     }
+    /// };
+    /// assert_eq!((name, version), ("name", Some("version")));
+    /// ```
+    pub fn signature_types(&self) -> Result<&[crate::signature::Type], crate::signature::Error> {
+        if let Some(types) = self.sig_types.get() {
+            return Ok(types);
+        }
+        let types = if self.sig.is_empty() {
+            Vec::new()
+        } else {
+            crate::signature::Type::parse_description(&self.sig)?
+        };
+        Ok(self.sig_types.get_or_init(|| types))
+    }
+
+    fn create_ctx(&mut self) -> MarshalContext<'_, '_> {
         MarshalContext {
-            buf: &mut self.buf,
+            buf: self.buf.to_mut(),
             fds: &mut self.raw_fds,
             byteorder: self.byteorder,
         }
     }
 
+    /// Start an `ay` with `len` bytes that are not put into the buffer, they are streamed after the body by
+    /// `SendConn::send_message_with_byte_stream`
+    pub(crate) fn push_streamed_bytes_header(&mut self, len: u32) -> Result<(), MarshalError> {
+        len.marshal(&mut self.create_ctx())?;
+        self.sig_mut().to_string_mut().push_str("ay");
+        Ok(())
+    }
+
     /// Append something that is Marshal to the message body
     pub fn push_param<P: Marshal>(&mut self, p: P) -> Result<(), MarshalError> {
         let mut ctx = self.create_ctx();
         p.marshal(&mut ctx)?;
-        P::sig_str(&mut self.sig);
+        P::sig_str(self.sig_mut());
         Ok(())
     }
 
@@ -457,8 +1040,8 @@ impl MarshalledMessageBody {
             Ok(ret) => Ok(ret),
             Err(e) => {
                 // reset state to before any of the push calls happened
-                self.sig.truncate(sig_len)?;
-                self.buf.truncate(buf_len);
+                self.sig_mut().truncate(sig_len)?;
+                self.buf.to_mut().truncate(buf_len);
                 self.raw_fds.truncate(fds_len);
                 Err(e)
             }
@@ -535,7 +1118,7 @@ impl MarshalledMessageBody {
 
     /// Append something that is Marshal to the body but use a dbus Variant in the signature. This is necessary for some APIs
     pub fn push_variant<P: Marshal>(&mut self, p: P) -> Result<(), MarshalError> {
-        self.sig.push_static("v");
+        self.sig_mut().push_static("v");
         let mut ctx = self.create_ctx();
         p.marshal_as_variant(&mut ctx)
     }
@@ -550,20 +1133,91 @@ impl MarshalledMessageBody {
             used += validate_raw::validate_marshalled(self.byteorder, used, self.get_buf(), &typ)
                 .map_err(|(_, e)| e)?;
         }
-        if used == self.get_buf().len() {
-            Ok(())
-        } else {
-            Err(UnmarshalError::NotAllBytesUsed)
+        crate::wire::util::check_body_end(self.get_buf(), used)
+    }
+    /// Rewrite booleans with a value other than 0 or 1 to 1 (true) and return how many were rewritten. Some peers send
+    /// any nonzero value for true, which fails to unmarshal with `UnmarshalError::InvalidBoolean` otherwise. Like
+    /// `validate` this returns an error if the body is invalid in any other way, nothing is rewritten then.
+    pub fn sanitize_booleans(&mut self) -> Result<usize, UnmarshalError> {
+        // variants can hold booleans with any signature
+        if !self.sig.as_str().contains(['b', 'v']) {
+            return Ok(0);
         }
+        let types = crate::signature::Type::parse_description(&self.sig)?;
+        let mut invalid_bools = Vec::new();
+        let mut used = 0;
+        for typ in types {
+            used += validate_raw::find_invalid_booleans(
+                self.byteorder,
+                used,
+                self.get_buf(),
+                &typ,
+                &mut invalid_bools,
+            )
+            .map_err(|(_, e)| e)?;
+        }
+        crate::wire::util::check_body_end(self.get_buf(), used)?;
+
+        let byteorder = self.byteorder;
+        let buf_offset = self.buf_offset;
+        let buf = self.buf.to_mut();
+        for pos in &invalid_bools {
+            crate::wire::util::insert_u32(byteorder, 1, &mut buf[buf_offset + pos..]);
+        }
+        Ok(invalid_bools.len())
     }
     /// Create a parser to retrieve parameters from the body.
     #[inline]
-    pub fn parser(&self) -> MessageBodyParser {
+    pub fn parser(&self) -> MessageBodyParser<'_> {
         MessageBodyParser::new(self)
     }
+    /// Deserialize the whole body into a type that implements `serde::Deserialize`, see `wire::deserialize` for how
+    /// the dbus types are mapped.
+    #[cfg(feature = "serde")]
+    pub fn parse_into<'a, T: serde::Deserialize<'a>>(
+        &'a self,
+    ) -> Result<T, crate::wire::deserialize::DeserializeError> {
+        crate::wire::deserialize::from_body(self)
+    }
+    /// Serialize a value that implements `serde::Serialize` as params with the signature `sig`, see
+    /// `wire::serialize` for how the values are mapped. Multiple params are serialized from a tuple or a struct. If
+    /// an error occurs the body is left as it was.
+    ///
+    /// ```rust
+    /// use rustbus::message_builder::MarshalledMessageBody;
+    ///
+    /// let mut body = MarshalledMessageBody::new();
+    /// body.push_serialized(&("name", 5u8, vec![1, 2]), "svat").unwrap();
+    /// assert_eq!(body.raw().0, "svat");
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn push_serialized<T: serde::Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+        sig: &str,
+    ) -> Result<(), crate::wire::serialize::SerializeError> {
+        let types = crate::signature::Type::parse_description(sig).map_err(|e| {
+            MarshalError::from(crate::params::validation::Error::InvalidSignature(e))
+        })?;
+        let buf_len = self.buf.len();
+        let fds_len = self.raw_fds.len();
+        let res = crate::wire::serialize::serialize_params(&mut self.create_ctx(), &types, value);
+        match res {
+            Ok(()) => {
+                self.sig_mut().push_str(sig);
+                Ok(())
+            }
+            Err(e) => {
+                self.buf.to_mut().truncate(buf_len);
+                self.raw_fds.truncate(fds_len);
+                Err(e)
+            }
+        }
+    }
 }
 
 #[test]
+#[cfg(rustbus_params)]
 fn test_marshal_trait() {
     let mut body = MarshalledMessageBody::new();
     let bytes: &[&[_]] = &[&[4u64]];
@@ -798,8 +1452,17 @@ impl<'fds, 'body: 'fds> MessageBodyParser<'body> {
     /// Get the next param, use get::<TYPE> to specify what type you expect. For example `let s = parser.get::<String>()?;`
     /// This checks if there are params left in the message and if the type you requested fits the signature of the message.
     pub fn get<T: Unmarshal<'body, 'fds>>(&mut self) -> Result<T, UnmarshalError> {
+        self.get_with(T::has_sig, T::unmarshal)
+    }
+
+    /// Run `f` on the next param if its signature is accepted by `has_sig`. The parser only advances if `f` succeeds.
+    fn get_with<R>(
+        &mut self,
+        has_sig: impl FnOnce(&str) -> bool,
+        f: impl FnOnce(&mut UnmarshalContext<'fds, 'body>) -> Result<R, UnmarshalError>,
+    ) -> Result<R, UnmarshalError> {
         if let Some(expected_sig) = self.get_next_sig() {
-            if !T::has_sig(expected_sig) {
+            if !has_sig(expected_sig) {
                 return Err(UnmarshalError::WrongSignature);
             }
 
@@ -809,7 +1472,7 @@ impl<'fds, 'body: 'fds> MessageBodyParser<'body> {
                 self.body.get_buf(),
                 self.buf_idx,
             );
-            match T::unmarshal(&mut ctx) {
+            match f(&mut ctx) {
                 Ok(res) => {
                     self.buf_idx = self.body.get_buf().len() - ctx.remainder().len();
                     self.sig_idx += expected_sig.len();
@@ -821,6 +1484,85 @@ impl<'fds, 'body: 'fds> MessageBodyParser<'body> {
             Err(UnmarshalError::EndOfMessage)
         }
     }
+
+    /// Copy the next param, which must be a string, into `buf` instead of allocating a new String.
+    ///
+    /// If the string does not fit into `buf` this returns `UnmarshalError::BufferTooSmall` and the parser does not advance,
+    /// so you can retry with a bigger buffer.
+    pub fn get_str_into<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a str, UnmarshalError> {
+        let len = self.get_with(<&str>::has_sig, |ctx| {
+            let s = ctx.read_str()?;
+            if s.len() > buf.len() {
+                return Err(UnmarshalError::BufferTooSmall {
+                    needed: s.len(),
+                    capacity: buf.len(),
+                });
+            }
+            buf[..s.len()].copy_from_slice(s.as_bytes());
+            Ok(s.len())
+        })?;
+        // this was copied from a valid &str
+        Ok(std::str::from_utf8(&buf[..len]).unwrap())
+    }
+
+    /// Unmarshal the elements of the next param, which must be an array, into `buf` instead of allocating a new Vec.
+    /// Returns the number of elements written to the start of `buf`.
+    ///
+    /// If the array has more elements than `buf` can hold this returns `UnmarshalError::BufferTooSmall`
+    /// and the parser does not advance. `buf` may have been partially overwritten in that case.
+    pub fn get_array_into<T: Unmarshal<'body, 'fds>>(
+        &mut self,
+        buf: &mut [T],
+    ) -> Result<usize, UnmarshalError> {
+        let capacity = buf.len();
+        let mut slots = buf.iter_mut();
+        self.get_with(<Vec<T>>::has_sig, |ctx| {
+            unmarshal_array_elements(ctx, capacity, |element| {
+                // unmarshal_array_elements never passes more than capacity elements
+                *slots.next().unwrap() = element;
+            })
+        })
+    }
+
+    /// Like [`get_array_into`](Self::get_array_into) but appends the elements to `vec`. The vec is never grown beyond its
+    /// current capacity, so this does not allocate.
+    ///
+    /// If the elements do not fit into the spare capacity of `vec` this returns `UnmarshalError::BufferTooSmall`,
+    /// `vec` is left unchanged and the parser does not advance.
+    pub fn get_array_into_vec<T: Unmarshal<'body, 'fds>>(
+        &mut self,
+        vec: &mut Vec<T>,
+    ) -> Result<usize, UnmarshalError> {
+        let old_len = vec.len();
+        let capacity = vec.capacity() - old_len;
+        let res = self.get_with(<Vec<T>>::has_sig, |ctx| {
+            unmarshal_array_elements(ctx, capacity, |element| vec.push(element))
+        });
+        if res.is_err() {
+            vec.truncate(old_len);
+        }
+        res
+    }
+
+    /// Iterate over the elements of the next param, which must be an array, instead of collecting them into a Vec.
+    /// Elements are only unmarshalled when the iterator gets to them, which keeps the memory use flat for huge arrays.
+    ///
+    /// The parser moves past the whole array right away. An element that can not be unmarshalled ends the iteration
+    /// with an error.
+    pub fn get_array_iter<T: Unmarshal<'body, 'fds>>(
+        &mut self,
+    ) -> Result<ArrayIter<'body, 'fds, T>, UnmarshalError> {
+        self.get_with(<Vec<T>>::has_sig, |ctx| {
+            let bytes_in_array = ctx.read_u32()? as usize;
+            ctx.align_to(T::alignment())?;
+            Ok(ArrayIter {
+                ctx: ctx.sub_context(bytes_in_array)?,
+                failed: false,
+                _element: std::marker::PhantomData,
+            })
+        })
+    }
+
     /// Perform error handling for `get2(), get3()...` if `get_calls` fails.
     fn get_mult_helper<T, F>(&mut self, count: usize, get_calls: F) -> Result<T, UnmarshalError>
     where
@@ -913,9 +1655,17 @@ impl<'fds, 'body: 'fds> MessageBodyParser<'body> {
         self.get_mult_helper(5, get_calls)
     }
 
+    /// Get the next params as a tuple, use get_tuple::<(TYPE, TYPE, ...)> to specify what types you expect.
+    /// For example `let (a, b, c, d, e, f) = parser.get_tuple::<(String, i32, u64, u8, bool, &str)>()?;`
+    /// This works like get2..get5 but for up to 12 params. If any of the params can not be unmarshalled the parser does not advance.
+    pub fn get_tuple<T: UnmarshalParams<'body, 'fds>>(&mut self) -> Result<T, UnmarshalError> {
+        self.get_mult_helper(T::COUNT, T::get_params)
+    }
+
     /// Get the next (old_style) param.
     /// This checks if there are params left in the message and if the type you requested fits the signature of the message.
-    pub fn get_param(&mut self) -> Result<crate::params::Param, UnmarshalError> {
+    #[cfg(rustbus_params)]
+    pub fn get_param(&mut self) -> Result<crate::params::Param<'_, '_>, UnmarshalError> {
         if let Some(sig_str) = self.get_next_sig() {
             let mut ctx = UnmarshalContext::new(
                 &self.body.raw_fds,
@@ -924,7 +1674,7 @@ impl<'fds, 'body: 'fds> MessageBodyParser<'body> {
                 self.buf_idx,
             );
 
-            let sig = &crate::signature::Type::parse_description(sig_str).unwrap()[0];
+            let sig = &crate::signature::Type::parse_description(sig_str)?[0];
 
             match crate::wire::unmarshal::container::unmarshal_with_sig(sig, &mut ctx) {
                 Ok(res) => {
@@ -940,8 +1690,221 @@ impl<'fds, 'body: 'fds> MessageBodyParser<'body> {
     }
 }
 
+/// Tuples of types that implement Unmarshal. Used by [`MessageBodyParser::get_tuple`] to get multiple params at once.
+pub trait UnmarshalParams<'body, 'fds>: Sized {
+    /// The number of params in the tuple
+    const COUNT: usize;
+    fn get_params(parser: &mut MessageBodyParser<'body>) -> Result<Self, UnmarshalError>;
+}
+
+macro_rules! impl_unmarshal_params {
+    ($count:expr; $($name:ident),+) => {
+        impl<'body: 'fds, 'fds, $($name: Unmarshal<'body, 'fds>),+> UnmarshalParams<'body, 'fds>
+            for ($($name,)+)
+        {
+            const COUNT: usize = $count;
+            fn get_params(parser: &mut MessageBodyParser<'body>) -> Result<Self, UnmarshalError> {
+                Ok(($(parser.get::<$name>()?,)+))
+            }
+        }
+    };
+}
+
+impl_unmarshal_params!(1; T1);
+impl_unmarshal_params!(2; T1, T2);
+impl_unmarshal_params!(3; T1, T2, T3);
+impl_unmarshal_params!(4; T1, T2, T3, T4);
+impl_unmarshal_params!(5; T1, T2, T3, T4, T5);
+impl_unmarshal_params!(6; T1, T2, T3, T4, T5, T6);
+impl_unmarshal_params!(7; T1, T2, T3, T4, T5, T6, T7);
+impl_unmarshal_params!(8; T1, T2, T3, T4, T5, T6, T7, T8);
+impl_unmarshal_params!(9; T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_unmarshal_params!(10; T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_unmarshal_params!(11; T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_unmarshal_params!(12; T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+
+/// The elements of an array param, see [`MessageBodyParser::get_array_iter`]
+#[derive(Debug)]
+pub struct ArrayIter<'body, 'fds, T> {
+    ctx: UnmarshalContext<'fds, 'body>,
+    failed: bool,
+    _element: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<'body, 'fds, T: Unmarshal<'body, 'fds>> ArrayIter<'body, 'fds, T> {
+    /// Collect the elements into Vecs of up to `size` elements, so they can be processed in batches without holding
+    /// all of them at once.
+    ///
+    /// Panics if `size` is 0.
+    pub fn chunks(self, size: usize) -> ArrayChunks<'body, 'fds, T> {
+        assert!(size > 0, "chunks need to hold at least one element");
+        ArrayChunks { iter: self, size }
+    }
+}
+
+impl<'body, 'fds, T: Unmarshal<'body, 'fds>> Iterator for ArrayIter<'body, 'fds, T> {
+    type Item = Result<T, UnmarshalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.ctx.remainder().is_empty() {
+            return None;
+        }
+        let element = self
+            .ctx
+            .align_to(T::alignment())
+            .and_then(|_| T::unmarshal(&mut self.ctx));
+        self.failed = element.is_err();
+        Some(element)
+    }
+}
+
+/// The elements of an array param in batches, see [`ArrayIter::chunks`]
+#[derive(Debug)]
+pub struct ArrayChunks<'body, 'fds, T> {
+    iter: ArrayIter<'body, 'fds, T>,
+    size: usize,
+}
+
+impl<'body, 'fds, T: Unmarshal<'body, 'fds>> Iterator for ArrayChunks<'body, 'fds, T> {
+    type Item = Result<Vec<T>, UnmarshalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = Vec::new();
+        for element in self.iter.by_ref() {
+            match element {
+                Ok(element) => chunk.push(element),
+                Err(err) => return Some(Err(err)),
+            }
+            if chunk.len() == self.size {
+                break;
+            }
+        }
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(Ok(chunk))
+        }
+    }
+}
+
+/// Unmarshal the elements of an array one by one and pass them to `sink`. If the array holds more than `capacity` elements,
+/// the remaining elements are only counted and an error is returned.
+fn unmarshal_array_elements<'buf, 'fds, T: Unmarshal<'buf, 'fds>>(
+    ctx: &mut UnmarshalContext<'fds, 'buf>,
+    capacity: usize,
+    mut sink: impl FnMut(T),
+) -> Result<usize, UnmarshalError> {
+    ctx.align_to(4)?;
+    let bytes_in_array = ctx.read_u32()? as usize;
+    ctx.align_to(T::alignment())?;
+
+    let mut ctx = ctx.sub_context(bytes_in_array)?;
+    let mut count = 0;
+    while !ctx.remainder().is_empty() {
+        ctx.align_to(T::alignment())?;
+        let element = T::unmarshal(&mut ctx)?;
+        if count < capacity {
+            sink(element);
+        }
+        count += 1;
+    }
+    if count > capacity {
+        return Err(UnmarshalError::BufferTooSmall {
+            needed: count,
+            capacity,
+        });
+    }
+    Ok(count)
+}
+
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn header_flags() {
+        use super::HeaderFlags;
+
+        let call = super::MessageBuilder::new()
+            .call("Ping")
+            .on("/io/killingspark")
+            .at("io.killingspark")
+            .no_auto_start()
+            .build();
+        assert!(HeaderFlags::NoAutoStart.is_set(call.flags));
+        assert!(!HeaderFlags::NoReplyExpected.is_set(call.flags));
+        assert!(!HeaderFlags::AllowInteractiveAuthorization.is_set(call.flags));
+
+        let mut flags = call.flags;
+        HeaderFlags::NoAutoStart.toggle(&mut flags);
+        HeaderFlags::AllowInteractiveAuthorization.toggle(&mut flags);
+        assert_eq!(flags, 4);
+    }
+
+    #[test]
+    fn sanitize_booleans_in_variants() {
+        use crate::wire::errors::UnmarshalError;
+        use std::collections::HashMap;
+
+        let mut body = super::MarshalledMessageBody::new();
+        let props: HashMap<&str, crate::wire::marshal::traits::Variant<bool>> =
+            [("a", crate::wire::marshal::traits::Variant(true))].into();
+        body.push_param(props).unwrap();
+        let (sig, buf, _) = body.raw();
+        let mut buf = buf.to_vec();
+        // the boolean is the last value in the body
+        let len = buf.len();
+        buf[len - 4] = 2;
+        let mut body = super::MarshalledMessageBody::from_parts(
+            buf,
+            0,
+            Vec::new(),
+            sig.to_owned(),
+            body.byteorder(),
+        );
+        assert!(matches!(
+            body.parser()
+                .get::<HashMap<&str, crate::wire::unmarshal::traits::Variant>>(),
+            Err(UnmarshalError::InvalidBoolean)
+        ));
+
+        assert_eq!(body.sanitize_booleans().unwrap(), 1);
+        let props = body
+            .parser()
+            .get::<HashMap<&str, crate::wire::unmarshal::traits::Variant>>()
+            .unwrap();
+        assert!(props["a"].get::<bool>().unwrap());
+    }
+
+    #[test]
+    fn classify() {
+        let call = super::MessageBuilder::new()
+            .call("Ping")
+            .with_interface("org.freedesktop.DBus.Peer")
+            .on("/io/killingspark/child")
+            .build();
+        let class = call.classify();
+        assert_eq!(class.object, Some("/io/killingspark/child"));
+        assert!(class.is_call_to("org.freedesktop.DBus.Peer", "Ping"));
+        assert!(!class.is_call_to("org.freedesktop.DBus.Peer", "GetMachineId"));
+        assert!(!class.is_signal("org.freedesktop.DBus.Peer", "Ping"));
+        assert!(class.is_below("/io/killingspark"));
+        assert!(class.is_below("/io/killingspark/child"));
+        assert!(class.is_below("/"));
+        assert!(!class.is_below("/io/killing"));
+
+        // calls without an interface match any interface
+        let mut call = call;
+        call.dynheader.interface = None;
+        assert!(call.classify().is_call_to("io.killingspark.Other", "Ping"));
+
+        let error = call
+            .dynheader
+            .make_error_response("io.killingspark.Error", None);
+        assert!(error.classify().is_error("io.killingspark.Error"));
+        assert!(!error
+            .classify()
+            .is_call_to("org.freedesktop.DBus.Peer", "Ping"));
+    }
+
     #[test]
     fn parser_get() {
         use crate::wire::errors::UnmarshalError;
@@ -979,4 +1942,347 @@ mod tests {
         assert!(parser.get::<(u32, i32, &str)>().is_ok());
         assert!(parser.get2::<(u32, i32, &str), (u32, i32, &str)>().is_ok());
     }
+
+    #[test]
+    fn error_response_fmt() {
+        use crate::standard_messages::DBUS_ERROR_FILE_NOT_FOUND;
+
+        let call = super::MessageBuilder::new()
+            .call("Open")
+            .on("/io/killingspark")
+            .at("io.killingspark")
+            .build();
+        let err = call
+            .dynheader
+            .make_error_response_fmt(DBUS_ERROR_FILE_NOT_FOUND, format_args!("{} is gone", 42));
+        assert_eq!(err.typ, super::MessageType::Error);
+        assert_eq!(
+            err.dynheader.error_name.as_deref(),
+            Some("org.freedesktop.DBus.Error.FileNotFound")
+        );
+        assert_eq!(err.body.parser().get(), Ok("42 is gone"));
+    }
+
+    #[test]
+    fn parser_get_tuple() {
+        use crate::wire::errors::UnmarshalError;
+
+        let mut sig = super::MessageBuilder::new()
+            .signal("io.killingspark", "Signal", "/io/killingspark/Signaler")
+            .build();
+        sig.body.push_param5(1u8, 2u16, 3u32, 4u64, "5").unwrap();
+        sig.body.push_param3(6i16, 7i32, 8i64).unwrap();
+
+        let mut parser = sig.body.parser();
+        assert_eq!(
+            parser.get_tuple::<(u8, u16, u32, u64, &str, i16, i32, i64, u8)>(),
+            Err(UnmarshalError::EndOfMessage)
+        );
+        assert_eq!(
+            parser.get_tuple::<(u8, u16, u32, u64, &str, i16, i32, u64)>(),
+            Err(UnmarshalError::WrongSignature)
+        );
+        assert_eq!(
+            parser.get_tuple::<(u8, u16, u32, u64, &str, i16, i32, i64)>(),
+            Ok((1, 2, 3, 4, "5", 6, 7, 8))
+        );
+        assert_eq!(
+            parser.get_tuple::<(u8,)>(),
+            Err(UnmarshalError::EndOfMessage)
+        );
+    }
+
+    #[test]
+    fn parser_get_into_buffers() {
+        use crate::wire::errors::UnmarshalError;
+
+        let mut sig = super::MessageBuilder::new()
+            .signal("io.killingspark", "Signal", "/io/killingspark/Signaler")
+            .build();
+        sig.body.push_param("ABCDEFGH").unwrap();
+        sig.body.push_param(&[1u32, 2, 3][..]).unwrap();
+        sig.body.push_param(vec!["a", "b"]).unwrap();
+
+        let mut parser = sig.body.parser();
+        let mut small = [0u8; 4];
+        assert_eq!(
+            parser.get_str_into(&mut small),
+            Err(UnmarshalError::BufferTooSmall {
+                needed: 8,
+                capacity: 4
+            })
+        );
+        let mut big = [0u8; 16];
+        assert_eq!(parser.get_str_into(&mut big), Ok("ABCDEFGH"));
+
+        let mut small = [0u32; 2];
+        assert_eq!(
+            parser.get_array_into(&mut small),
+            Err(UnmarshalError::BufferTooSmall {
+                needed: 3,
+                capacity: 2
+            })
+        );
+        let mut big = [0u32; 4];
+        assert_eq!(parser.get_array_into(&mut big), Ok(3));
+        assert_eq!(big, [1, 2, 3, 0]);
+
+        let mut vec: Vec<&str> = Vec::with_capacity(2);
+        vec.push("x");
+        assert_eq!(
+            parser.get_array_into_vec(&mut vec),
+            Err(UnmarshalError::BufferTooSmall {
+                needed: 2,
+                capacity: 1
+            })
+        );
+        assert_eq!(vec, ["x"]);
+        vec.clear();
+        assert_eq!(parser.get_array_into_vec(&mut vec), Ok(2));
+        assert_eq!(vec, ["a", "b"]);
+        assert_eq!(parser.get::<u32>(), Err(UnmarshalError::EndOfMessage));
+    }
+
+    #[test]
+    fn array_iter() {
+        use crate::wire::errors::UnmarshalError;
+
+        let names: Vec<String> = (0..1000).map(|idx| format!(":1.{}", idx)).collect();
+        let mut sig = super::MessageBuilder::new()
+            .signal("io.killingspark", "Signal", "/io/killingspark/Signaler")
+            .build();
+        sig.body.push_param(&names).unwrap();
+        sig.body.push_param(Vec::<u64>::new()).unwrap();
+        sig.body.push_param(7u8).unwrap();
+
+        let mut parser = sig.body.parser();
+        assert_eq!(
+            parser.get_array_iter::<u32>().err(),
+            Some(UnmarshalError::WrongSignature)
+        );
+        let chunks = parser
+            .get_array_iter::<&str>()
+            .unwrap()
+            .chunks(300)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            [300, 300, 300, 100]
+        );
+        assert_eq!(chunks.concat(), names);
+        assert_eq!(parser.get_array_iter::<u64>().unwrap().count(), 0);
+        assert_eq!(parser.get::<u8>(), Ok(7));
+
+        // a string with invalid utf-8 ends the iteration
+        let mut buf = sig.body.get_buf().to_vec();
+        let pos = buf.windows(5).position(|w| w == b":1.42").unwrap();
+        buf[pos] = 0xff;
+        let body = super::MarshalledMessageBody::from_parts(
+            buf,
+            0,
+            vec![],
+            sig.get_sig().to_owned(),
+            sig.body.byteorder(),
+        );
+        let results: Vec<_> = body.parser().get_array_iter::<&str>().unwrap().collect();
+        assert_eq!(results.len(), 43);
+        assert!(results[..42].iter().all(Result::is_ok));
+        assert!(results[42].is_err());
+    }
+
+    #[test]
+    fn estimated_wire_size() {
+        use crate::wire::errors::MarshalError;
+
+        let mut sig = super::MessageBuilder::new()
+            .signal("io.killingspark", "Signal", "/io/killingspark/Signaler")
+            .build();
+        sig.body.push_param3(100u32, 200i32, "ABCDEFGH").unwrap();
+
+        // compare against the bytes that actually end up on the socket
+        let (stream, mut peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut conn = crate::connection::ll_conn::DuplexConn::from_stream(stream).unwrap();
+        conn.send.send_message_write_all(&sig).unwrap();
+        drop(conn);
+        let mut wire = Vec::new();
+        std::io::Read::read_to_end(&mut peer, &mut wire).unwrap();
+        assert_eq!(sig.estimated_wire_size(), Ok(wire.len()));
+
+        assert_eq!(
+            super::MarshalledMessage::new().estimated_wire_size(),
+            Err(MarshalError::InvalidMessageType)
+        );
+    }
+
+    #[test]
+    fn marshal_into() {
+        let mut msg = super::MessageBuilder::new()
+            .call("Fixture")
+            .on("/io/killing/spark")
+            .at("io.killing.spark")
+            .build();
+        msg.dynheader.serial = Some(std::num::NonZeroU32::new(42).unwrap());
+        msg.body.push_param2("payload", 7u64).unwrap();
+
+        let mut buf = b"prefix".to_vec();
+        msg.marshal_into(&mut buf).unwrap();
+        let mut written = Vec::new();
+        msg.write_to(&mut written).unwrap();
+        assert_eq!(&buf[6..], &written[..]);
+        assert_eq!(written.len(), msg.estimated_wire_size().unwrap());
+
+        let mut cursor = crate::wire::unmarshal_context::Cursor::new(&written);
+        let header = crate::wire::unmarshal::unmarshal_header(&mut cursor).unwrap();
+        let dynheader =
+            crate::wire::unmarshal::unmarshal_dynamic_header(&header, &mut cursor).unwrap();
+        let offset = cursor.consumed();
+        let received = crate::wire::unmarshal::unmarshal_next_message(
+            &header,
+            dynheader,
+            written,
+            offset,
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(received.dynheader.serial, msg.dynheader.serial);
+        assert_eq!(received.dynheader.member.as_deref(), Some("Fixture"));
+        assert_eq!(
+            received.body.parser().get2::<&str, u64>(),
+            Ok(("payload", 7))
+        );
+    }
+
+    #[test]
+    fn shared_body() {
+        use super::MarshalledMessageBody;
+
+        let mut template = MarshalledMessageBody::new();
+        template.push_param2(1u32, "shared").unwrap();
+        let (buf, sig) = template.make_shared().unwrap();
+        assert_eq!(sig, "us");
+        assert_eq!(template.get_buf(), &buf[..]);
+
+        let mut body = MarshalledMessageBody::from_shared(buf.clone(), sig, template.byteorder());
+        assert!(std::ptr::eq(body.get_buf(), &buf[..]));
+        assert_eq!(body.parser().get2::<u32, &str>(), Ok((1, "shared")));
+
+        // modifying the body must not change the shared buffer
+        body.push_param(2u8).unwrap();
+        assert_eq!(body.parser().get3::<u32, &str, u8>(), Ok((1, "shared", 2)));
+        assert_eq!(template.get_buf(), &buf[..]);
+        assert_eq!(template.parser().get2::<u32, &str>(), Ok((1, "shared")));
+
+        // the fds would end up owned by every body that uses the buffer
+        let mut with_fd = MarshalledMessageBody::new();
+        let fd = crate::wire::UnixFd::new(nix::unistd::dup(1).unwrap());
+        with_fd.push_param(fd).unwrap();
+        assert_eq!(
+            with_fd.make_shared().map(|_| ()),
+            Err(crate::wire::errors::MarshalError::SharedBodyWithFds)
+        );
+        assert_eq!(with_fd.get_fds().len(), 1);
+        assert!(matches!(with_fd.buf, super::BodyBuf::Owned(_)));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_body() {
+        use super::{BodyBuf, MarshalledMessageBody};
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param("secret").unwrap();
+        body.reset();
+        let BodyBuf::Owned(buf) = &mut body.buf else {
+            panic!("the body owns its buffer");
+        };
+        assert!(buf.capacity() > 0);
+        assert!(crate::wire::util::is_scrubbed(buf));
+    }
+
+    #[test]
+    fn signature_types() {
+        use super::MarshalledMessageBody;
+        use crate::signature::{Base, Container, Type};
+
+        let mut body = MarshalledMessageBody::new();
+        assert_eq!(body.signature_types(), Ok(&[][..]));
+        body.push_param("first").unwrap();
+        assert_eq!(body.signature_types(), Ok(&[Type::Base(Base::String)][..]));
+
+        // the cached types follow changes of the signature
+        body.push_param(vec![1u32]).unwrap();
+        let array = Type::Container(Container::Array(Box::new(Type::Base(Base::Uint32))));
+        assert_eq!(
+            body.signature_types(),
+            Ok(&[Type::Base(Base::String), array][..])
+        );
+        body.reset();
+        assert_eq!(body.signature_types(), Ok(&[][..]));
+
+        let invalid = MarshalledMessageBody::from_parts(
+            Vec::new(),
+            0,
+            Vec::new(),
+            "a".to_owned(),
+            crate::ByteOrder::NATIVE,
+        );
+        assert!(invalid.signature_types().is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        use super::{MarshalledMessage, MarshalledMessageBody};
+        use crate::wire::UnixFd;
+        use crate::ByteOrder;
+
+        let path = std::env::temp_dir().join(format!("rustbus-fixture-{}", std::process::id()));
+        let mut msg = super::MessageBuilder::with_byteorder(ByteOrder::BigEndian)
+            .call("Open")
+            .on("/io/killingspark")
+            .at("io.killingspark")
+            .build();
+        msg.dynheader.serial = std::num::NonZeroU32::new(42);
+        let file = std::fs::File::open("/dev/zero").unwrap();
+        msg.body
+            .push_param3(
+                "file",
+                UnixFd::new(std::os::fd::IntoRawFd::into_raw_fd(file)),
+                7u16,
+            )
+            .unwrap();
+        msg.save(&path).unwrap();
+
+        let loaded = MarshalledMessage::load(&path).unwrap();
+        assert_eq!(loaded.dynheader.serial, msg.dynheader.serial);
+        assert_eq!(
+            loaded.dynheader.destination.as_deref(),
+            Some("io.killingspark")
+        );
+        assert_eq!(loaded.body.byteorder(), ByteOrder::BigEndian);
+        assert_eq!(loaded.get_buf(), msg.get_buf());
+        let (name, fd, number) = loaded.body.parser().get3::<&str, UnixFd, u16>().unwrap();
+        assert_eq!((name, number), ("file", 7));
+        assert!(fd.get_raw_fd().is_some());
+
+        // a malformed body is kept as it is
+        msg.body = MarshalledMessageBody::from_shared(
+            vec![9, 0, 0, 0, b'a', 0].into(),
+            "s".to_owned(),
+            ByteOrder::BigEndian,
+        );
+        msg.save(&path).unwrap();
+        let loaded = MarshalledMessage::load(&path).unwrap();
+        assert_eq!(loaded.get_buf(), &[9, 0, 0, 0, b'a', 0]);
+        assert!(loaded.body.validate().is_err());
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.pop();
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(MarshalledMessage::load(&path).is_err());
+        bytes[..8].copy_from_slice(b"rbusmsg\x02");
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(MarshalledMessage::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -4,12 +4,24 @@
 //! and way less ergonomic but it allows to do everything dbus can do for you. It also allows for a more explorative approach
 //! if you do not know what content to expect in received messages (e.g. you implement a tool similar to dbus-monitor).
 
+//!
+//! Everything except the validation functions is only available with the `params` feature, which is enabled by default.
+//! The `stable-api` feature leaves it out.
+
+#[cfg(rustbus_params)]
 mod container_constructors;
+#[cfg(rustbus_params)]
 mod conversion;
+#[cfg(rustbus_params)]
 pub mod message;
+#[cfg(rustbus_params)]
+mod pretty;
+#[cfg(rustbus_params)]
 mod types;
 pub mod validation;
 
+#[cfg(rustbus_params)]
 pub use conversion::*;
+#[cfg(rustbus_params)]
 pub use types::*;
 pub use validation::*;
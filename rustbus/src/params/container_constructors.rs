@@ -1,7 +1,23 @@
 //! A bit more convenient ways to make containers
 //!
 //! These allow for easier construction of containers. Note that empty containers require you to specify the
-//! signature.
+//! signature. The `*_inferred` constructors take the signature from the first element and check all other
+//! elements against it.
+//!
+//! ```rust
+//! use rustbus::params::{Container, Param};
+//!
+//! let props = Container::make_dict_inferred(vec![
+//!     ("Name", Param::from(Container::make_variant("rustbus"))),
+//!     ("Answer", Param::from(Container::make_variant(42u32))),
+//! ])
+//! .unwrap();
+//! let args = Container::try_make_struct(vec![Param::from("Update"), Param::from(props)]).unwrap();
+//! let mut sig = String::new();
+//! Param::from(args).make_signature(&mut sig);
+//! assert_eq!(sig, "(sa{sv})");
+//! ```
+use crate::params::validation::Error as ValidationError;
 use crate::params::*;
 use crate::signature;
 use crate::wire::errors::MarshalError;
@@ -41,7 +57,7 @@ impl<'e, 'a: 'e> Container<'a, 'e> {
                     return Err(crate::params::validation::Error::DictKeyTypesDiffer.into());
                 }
                 if !val.sig().eq(&dict.value_sig) {
-                    return Err(crate::params::validation::Error::DictKeyTypesDiffer.into());
+                    return Err(crate::params::validation::Error::DictValueTypesDiffer.into());
                 }
                 dict.map.insert(key, val);
                 Ok(())
@@ -58,6 +74,15 @@ impl<'e, 'a: 'e> Container<'a, 'e> {
     pub fn make_struct<P: Into<Param<'a, 'e>>>(elements: Vec<P>) -> Container<'a, 'e> {
         Container::Struct(elements.into_iter().map(std::convert::Into::into).collect())
     }
+    /// Like `make_struct` but fails for empty structs, which dbus does not allow
+    pub fn try_make_struct<P: Into<Param<'a, 'e>>>(
+        elements: Vec<P>,
+    ) -> Result<Container<'a, 'e>, MarshalError> {
+        if elements.is_empty() {
+            return Err(ValidationError::InvalidSignature(signature::Error::EmptyStruct).into());
+        }
+        Ok(Self::make_struct(elements))
+    }
     pub fn make_struct_ref(elements: &'a [Param<'a, 'e>]) -> Container<'a, 'e> {
         Container::StructRef(elements)
     }
@@ -133,6 +158,33 @@ impl<'e, 'a: 'e> Container<'a, 'e> {
         Self::make_array_with_sig(sig, elements)
     }
 
+    /// Make an array with the signature of the first element. Fails with `ElementTypeMismatch` naming the first element
+    /// that has another signature, and with `CannotInferSignature` if there are no elements.
+    pub fn make_array_inferred<P: Into<Param<'a, 'e>>, I: IntoIterator<Item = P>>(
+        elements: I,
+    ) -> Result<Container<'a, 'e>, MarshalError> {
+        let values: Vec<Param<'a, 'e>> = elements.into_iter().map(Into::into).collect();
+        let element_sig = values
+            .first()
+            .ok_or(ValidationError::CannotInferSignature)?
+            .sig();
+        for (index, value) in values.iter().enumerate().skip(1) {
+            let found = value.sig();
+            if found != element_sig {
+                return Err(ValidationError::ElementTypeMismatch {
+                    index,
+                    expected: sig_string(&element_sig),
+                    found: sig_string(&found),
+                }
+                .into());
+            }
+        }
+        Ok(Container::Array(Array {
+            element_sig,
+            values,
+        }))
+    }
+
     pub fn make_array_with_sig<P: Into<Param<'a, 'e>>, I: Iterator<Item = P>>(
         element_sig: signature::Type,
         elements: I,
@@ -193,6 +245,56 @@ impl<'e, 'a: 'e> Container<'a, 'e> {
 
         Ok(Container::Dict(dict))
     }
+    /// Make a dict with the key and value signatures of the first entry. Fails with `DictKeyTypeMismatch` or
+    /// `DictValueTypeMismatch` naming the first entry that differs, and with `CannotInferSignature` if there are no entries.
+    pub fn make_dict_inferred<
+        K: Into<Base<'e>>,
+        V: Into<Param<'a, 'e>>,
+        I: IntoIterator<Item = (K, V)>,
+    >(
+        entries: I,
+    ) -> Result<Container<'a, 'e>, MarshalError> {
+        let entries: Vec<(Base<'e>, Param<'a, 'e>)> = entries
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+        let (first_key, first_value) = entries
+            .first()
+            .ok_or(ValidationError::CannotInferSignature)?;
+        let key_sig = first_key.sig();
+        let value_sig = first_value.sig();
+        for (index, (key, value)) in entries.iter().enumerate().skip(1) {
+            let found = key.sig();
+            if found != key_sig {
+                return Err(ValidationError::DictKeyTypeMismatch {
+                    index,
+                    expected: sig_string(&key_sig),
+                    found: sig_string(&found),
+                }
+                .into());
+            }
+            let found = value.sig();
+            if found != value_sig {
+                return Err(ValidationError::DictValueTypeMismatch {
+                    index,
+                    expected: sig_string(&value_sig),
+                    found: sig_string(&found),
+                }
+                .into());
+            }
+        }
+        let key_sig = match key_sig {
+            signature::Type::Base(sig) => sig,
+            _ => unreachable!("keys are base types"),
+        };
+        Ok(Container::Dict(Dict {
+            key_sig,
+            value_sig,
+            map: entries.into_iter().collect(),
+        }))
+    }
+
+    #[allow(clippy::mutable_key_type)]
     pub fn make_dict_ref(
         key_sig: &str,
         val_sig: &str,
@@ -220,6 +322,7 @@ impl<'e, 'a: 'e> Container<'a, 'e> {
         Self::make_dict_ref_with_sig(key_sig, value_sig, map)
     }
 
+    #[allow(clippy::mutable_key_type)]
     pub fn make_dict_ref_with_sig(
         key_sig: signature::Base,
         value_sig: signature::Type,
@@ -236,3 +339,59 @@ impl<'e, 'a: 'e> Container<'a, 'e> {
         Ok(Container::DictRef(dict))
     }
 }
+
+fn sig_string(sig: &signature::Type) -> String {
+    let mut buf = String::new();
+    sig.to_str(&mut buf);
+    buf
+}
+
+#[test]
+fn test_inferred_containers() {
+    let arr = Container::make_array_inferred(vec![1u32, 2, 3]).unwrap();
+    assert_eq!(
+        Param::from(arr).sig(),
+        signature::Type::parse_description("au").unwrap()[0]
+    );
+
+    let err = Container::make_array_inferred(vec![Param::from(1u32), Param::from("two")]);
+    match err {
+        Err(MarshalError::Validation(ValidationError::ElementTypeMismatch {
+            index,
+            expected,
+            found,
+        })) => {
+            assert_eq!(index, 1);
+            assert_eq!(expected, "u");
+            assert_eq!(found, "s");
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(matches!(
+        Container::make_array_inferred(Vec::<u32>::new()),
+        Err(MarshalError::Validation(
+            ValidationError::CannotInferSignature
+        ))
+    ));
+
+    let dict = Container::make_dict_inferred(vec![("a", 1u8), ("b", 2u8)]).unwrap();
+    assert_eq!(
+        Param::from(dict).sig(),
+        signature::Type::parse_description("a{sy}").unwrap()[0]
+    );
+    assert!(matches!(
+        Container::make_dict_inferred(vec![(Base::from("a"), 1u8), (Base::from(2u8), 2u8)]),
+        Err(MarshalError::Validation(
+            ValidationError::DictKeyTypeMismatch { index: 1, .. }
+        ))
+    ));
+    assert!(matches!(
+        Container::make_dict_inferred(vec![("a", Param::from(1u8)), ("b", Param::from(2u16))]),
+        Err(MarshalError::Validation(
+            ValidationError::DictValueTypeMismatch { index: 1, .. }
+        ))
+    ));
+
+    assert!(Container::try_make_struct(Vec::<Param>::new()).is_err());
+    assert!(Container::try_make_struct(vec![1u8]).is_ok());
+}
@@ -0,0 +1,352 @@
+//! Textual representation of messages and params in the style of dbus-monitor
+//!
+//! ```text
+//! signal sender=:1.5 -> destination=(null destination) serial=3 path=/io/killing/spark; interface=io.killing.spark; member=Changed
+//!    string "name"
+//!    array [
+//!       dict entry(
+//!          string "key"
+//!          variant uint32 5
+//!       )
+//!    ]
+//! ```
+//!
+//! Strings are escaped like Rust string literals, e.g. a newline is printed as `\n`, so strings from peers can not forge
+//! extra lines.
+
+use std::fmt::{self, Write};
+
+use super::*;
+use crate::message_builder::{MarshalledMessage, MessageType};
+use crate::params::validation::Error as ValidationError;
+use crate::wire::errors::UnmarshalError;
+
+const INDENT: &str = "   ";
+
+/// Prints the param in the dbus-monitor style, nested containers span multiple lines
+impl fmt::Display for Param<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_param(f, self, 0)
+    }
+}
+
+/// Prints the header on the first line and each param of the body on the following ones. If the body can not be
+/// decoded according to its signature the error and the raw bytes are printed instead.
+impl fmt::Display for MarshalledMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_header(f, self)?;
+        let (sig, buf, fds) = self.body.raw();
+        if sig.is_empty() {
+            return Ok(());
+        }
+        let byteorder = self.body.byteorder();
+        let params = self
+            .body
+            .signature_types()
+            .map_err(UnmarshalError::from)
+            .and_then(|sigs| {
+                match crate::wire::unmarshal::unmarshal_body(byteorder, sigs, buf, fds, 0) {
+                    Err(UnmarshalError::Validation(ValidationError::InvalidUtf8)) => {
+                        crate::wire::unmarshal::unmarshal_body_lossy(byteorder, sigs, buf, fds, 0)
+                            .map(|params| (params, true))
+                    }
+                    params => params.map(|params| (params, false)),
+                }
+            });
+        match params {
+            Ok((params, lossy)) => {
+                if lossy {
+                    write!(
+                        f,
+                        "\n{}(strings that are not valid utf-8 were decoded lossily)",
+                        INDENT
+                    )?;
+                }
+                for param in &params {
+                    f.write_char('\n')?;
+                    f.write_str(INDENT)?;
+                    write_param(f, param, 1)?;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                write!(
+                    f,
+                    "\n{}malformed body with signature \"{}\": {}",
+                    INDENT, sig, e
+                )?;
+                write_bytes(f, buf, 2)
+            }
+        }
+    }
+}
+
+fn write_header(f: &mut fmt::Formatter<'_>, msg: &MarshalledMessage) -> fmt::Result {
+    let hdr = &msg.dynheader;
+    let typ = match msg.typ {
+        MessageType::Call => "method call",
+        MessageType::Reply => "method return",
+        MessageType::Signal => "signal",
+        MessageType::Error => "error",
+        MessageType::Invalid => "invalid",
+    };
+    write!(
+        f,
+        "{} sender={} -> destination={}",
+        typ,
+        hdr.sender.as_deref().unwrap_or("(null sender)"),
+        hdr.destination.as_deref().unwrap_or("(null destination)")
+    )?;
+    if let Some(serial) = hdr.serial {
+        write!(f, " serial={}", serial)?;
+    }
+    if let Some(error_name) = &hdr.error_name {
+        write!(f, " error_name={}", error_name)?;
+    }
+    if let Some(reply_serial) = hdr.response_serial {
+        write!(f, " reply_serial={}", reply_serial)?;
+    }
+    if let Some(path) = &hdr.object {
+        write!(f, " path={};", path)?;
+    }
+    if let Some(interface) = &hdr.interface {
+        write!(f, " interface={};", interface)?;
+    }
+    if let Some(member) = &hdr.member {
+        write!(f, " member={}", member)?;
+    }
+    Ok(())
+}
+
+/// Writes the param starting at the current position, lines of nested values are indented by `depth + 1` levels
+fn write_param(f: &mut fmt::Formatter<'_>, param: &Param, depth: usize) -> fmt::Result {
+    match param {
+        Param::Base(base) => write_base(f, base),
+        Param::Container(Container::Array(array)) => {
+            write_array(f, &array.element_sig, &array.values, depth)
+        }
+        Param::Container(Container::ArrayRef(array)) => {
+            write_array(f, &array.element_sig, array.values, depth)
+        }
+        Param::Container(Container::Struct(fields)) => write_struct(f, fields, depth),
+        Param::Container(Container::StructRef(fields)) => write_struct(f, fields, depth),
+        Param::Container(Container::Dict(dict)) => write_dict(f, &dict.map, depth),
+        Param::Container(Container::DictRef(dict)) => write_dict(f, dict.map, depth),
+        Param::Container(Container::Variant(variant)) => {
+            f.write_str("variant ")?;
+            write_param(f, &variant.value, depth)
+        }
+    }
+}
+
+fn write_base(f: &mut fmt::Formatter<'_>, base: &Base) -> fmt::Result {
+    match base {
+        Base::Double(bits) => write!(f, "double {}", f64::from_bits(*bits)),
+        Base::Byte(b) => write!(f, "byte {}", b),
+        Base::Int16(i) => write!(f, "int16 {}", i),
+        Base::Uint16(u) => write!(f, "uint16 {}", u),
+        Base::Int32(i) => write!(f, "int32 {}", i),
+        Base::Uint32(u) => write!(f, "uint32 {}", u),
+        Base::Int64(i) => write!(f, "int64 {}", i),
+        Base::Uint64(u) => write!(f, "uint64 {}", u),
+        Base::Boolean(b) => write!(f, "boolean {}", b),
+        Base::UnixFd(fd) => match fd.get_raw_fd() {
+            Some(raw) => write!(f, "file descriptor {}", raw),
+            None => f.write_str("file descriptor (taken)"),
+        },
+        Base::String(s) => write!(f, "string {:?}", s),
+        Base::StringRef(s) => write!(f, "string {:?}", s),
+        Base::Signature(s) => write!(f, "signature \"{}\"", s),
+        Base::SignatureRef(s) => write!(f, "signature \"{}\"", s),
+        Base::ObjectPath(s) => write!(f, "object path \"{}\"", s),
+        Base::ObjectPathRef(s) => write!(f, "object path \"{}\"", s),
+    }
+}
+
+fn new_line(f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+    f.write_char('\n')?;
+    for _ in 0..depth {
+        f.write_str(INDENT)?;
+    }
+    Ok(())
+}
+
+fn write_array(
+    f: &mut fmt::Formatter<'_>,
+    element_sig: &crate::signature::Type,
+    values: &[Param],
+    depth: usize,
+) -> fmt::Result {
+    if *element_sig == crate::signature::Type::Base(crate::signature::Base::Byte) {
+        let bytes: Vec<u8> = values
+            .iter()
+            .filter_map(|value| match value {
+                Param::Base(Base::Byte(b)) => Some(*b),
+                _ => None,
+            })
+            .collect();
+        if bytes.len() == values.len() {
+            f.write_str("array of bytes [")?;
+            write_bytes(f, &bytes, depth + 1)?;
+            new_line(f, depth)?;
+            return f.write_char(']');
+        }
+    }
+    f.write_str("array [")?;
+    for value in values {
+        new_line(f, depth + 1)?;
+        write_param(f, value, depth + 1)?;
+    }
+    new_line(f, depth)?;
+    f.write_char(']')
+}
+
+fn write_struct(f: &mut fmt::Formatter<'_>, fields: &[Param], depth: usize) -> fmt::Result {
+    f.write_str("struct {")?;
+    for field in fields {
+        new_line(f, depth + 1)?;
+        write_param(f, field, depth + 1)?;
+    }
+    new_line(f, depth)?;
+    f.write_char('}')
+}
+
+#[allow(clippy::mutable_key_type)]
+fn write_dict(f: &mut fmt::Formatter<'_>, map: &DictMap, depth: usize) -> fmt::Result {
+    f.write_str("array [")?;
+    for (key, value) in map {
+        new_line(f, depth + 1)?;
+        f.write_str("dict entry(")?;
+        new_line(f, depth + 2)?;
+        write_base(f, key)?;
+        new_line(f, depth + 2)?;
+        write_param(f, value, depth + 2)?;
+        new_line(f, depth + 1)?;
+        f.write_char(')')?;
+    }
+    new_line(f, depth)?;
+    f.write_char(']')
+}
+
+/// Hex dump with 16 bytes per line
+fn write_bytes(f: &mut fmt::Formatter<'_>, bytes: &[u8], depth: usize) -> fmt::Result {
+    for line in bytes.chunks(16) {
+        new_line(f, depth)?;
+        for (idx, b) in line.iter().enumerate() {
+            if idx > 0 {
+                f.write_char(' ')?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_builder::{MarshalledMessageBody, MessageBuilder};
+    use crate::params::Param;
+    use crate::signature;
+    use crate::wire::marshal::traits::Variant;
+    use crate::wire::ObjectPath;
+    use crate::ByteOrder;
+
+    #[test]
+    fn test_pretty_print() {
+        let mut msg = MessageBuilder::new()
+            .call("Frobnicate")
+            .on("/io/killing/spark")
+            .with_interface("io.killing.spark")
+            .at("io.killing.spark.Service")
+            .build();
+        msg.dynheader.serial = Some(std::num::NonZeroU32::new(7).unwrap());
+        let mut dict = std::collections::HashMap::new();
+        dict.insert("key", Variant(5u32));
+        msg.body
+            .push_param4(
+                "name",
+                ObjectPath::new("/a").unwrap(),
+                (true, -3i16, 1.5f64),
+                &[1u8, 2, 255][..],
+            )
+            .unwrap();
+        msg.body
+            .push_param2(dict, vec![vec![1u64], vec![]])
+            .unwrap();
+
+        let expected = "\
+method call sender=(null sender) -> destination=io.killing.spark.Service serial=7 path=/io/killing/spark; interface=io.killing.spark; member=Frobnicate
+   string \"name\"
+   object path \"/a\"
+   struct {
+      boolean true
+      int16 -3
+      double 1.5
+   }
+   array of bytes [
+      01 02 ff
+   ]
+   array [
+      dict entry(
+         string \"key\"
+         variant uint32 5
+      )
+   ]
+   array [
+      array [
+         uint64 1
+      ]
+      array [
+      ]
+   ]";
+        assert_eq!(msg.to_pretty_string(), expected);
+    }
+
+    #[test]
+    fn test_pretty_print_escapes_strings() {
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Changed", "/")
+            .build();
+        msg.body.push_param2("a\nsignal", "\"quoted\"").unwrap();
+        let text = msg.to_pretty_string();
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.ends_with("\n   string \"a\\nsignal\"\n   string \"\\\"quoted\\\"\""));
+    }
+
+    #[test]
+    fn test_pretty_print_malformed() {
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Broken", "/")
+            .build();
+        // a string whose length runs past the end of the body
+        msg.body = MarshalledMessageBody::from_shared(
+            vec![2, 0, 0, 0].into(),
+            "s".to_owned(),
+            ByteOrder::LittleEndian,
+        );
+        let text = msg.to_string();
+        assert!(text.starts_with("signal sender=(null sender) -> destination=(null destination) path=/; interface=io.killing.spark; member=Broken\n"));
+        assert!(text.contains("malformed body with signature \"s\""));
+        assert!(text.ends_with("\n      02 00 00 00"));
+
+        msg.body = MarshalledMessageBody::from_shared(
+            vec![2, 0, 0, 0, 0xc3, b'x', 0].into(),
+            "s".to_owned(),
+            ByteOrder::LittleEndian,
+        );
+        assert!(msg.to_string().ends_with(
+            "\n   (strings that are not valid utf-8 were decoded lossily)\n   string \"\u{fffd}x\""
+        ));
+
+        let variant = Param::Container(crate::params::Container::Variant(Box::new(
+            crate::params::Variant {
+                sig: signature::Type::Container(signature::Container::Struct(
+                    signature::StructTypes::new(vec![signature::Type::Base(signature::Base::Byte)])
+                        .unwrap(),
+                )),
+                value: Param::Container(crate::params::Container::Struct(vec![1u8.into()])),
+            },
+        )));
+        assert_eq!(variant.to_string(), "variant struct {\n   byte 1\n}");
+    }
+}
@@ -225,7 +225,8 @@ impl Marshal for Variant<'_, '_> {
         crate::wire::marshal::container::marshal_param(&self.value, ctx)
     }
 }
-impl<'buf, 'fds> Unmarshal<'buf, 'fds> for Variant<'buf, 'fds> {
+// the unmarshalled variant owns its content, so it can outlive the message, e.g. as Variant<'static, 'static>
+impl<'buf, 'fds, 'a, 'e> Unmarshal<'buf, 'fds> for Variant<'a, 'e> {
     fn unmarshal(
         ctx: &mut crate::wire::unmarshal_context::UnmarshalContext<'fds, 'buf>,
     ) -> crate::wire::unmarshal::UnmarshalResult<Self> {
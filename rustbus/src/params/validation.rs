@@ -1,8 +1,6 @@
 //! Various validation functions for e.g. ObjectPath constraints
 
-use super::*;
 use crate::message_builder::MessageType;
-use crate::params;
 use crate::signature;
 use crate::wire::HeaderField;
 
@@ -36,6 +34,30 @@ pub enum Error {
     DictKeyTypesDiffer,
     #[error("Dict values differ in type")]
     DictValueTypesDiffer,
+    #[error("Invalid environment variable name")]
+    InvalidEnvironmentVariable,
+    #[error("Element {index} has the signature '{found}' but the first element has '{expected}'")]
+    ElementTypeMismatch {
+        index: usize,
+        expected: String,
+        found: String,
+    },
+    #[error(
+        "The key of entry {index} has the signature '{found}' but the first key has '{expected}'"
+    )]
+    DictKeyTypeMismatch {
+        index: usize,
+        expected: String,
+        found: String,
+    },
+    #[error("The value of entry {index} has the signature '{found}' but the first value has '{expected}'")]
+    DictValueTypeMismatch {
+        index: usize,
+        expected: String,
+        found: String,
+    },
+    #[error("The signature of an empty container can not be inferred")]
+    CannotInferSignature,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -245,7 +267,8 @@ pub fn validate_signature(sig: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn validate_array(array: &[Param<'_, '_>], sig: &signature::Type) -> Result<()> {
+#[cfg(rustbus_params)]
+pub fn validate_array(array: &[crate::params::Param<'_, '_>], sig: &signature::Type) -> Result<()> {
     if array.is_empty() {
         return Ok(());
     }
@@ -257,8 +280,10 @@ pub fn validate_array(array: &[Param<'_, '_>], sig: &signature::Type) -> Result<
     Ok(())
 }
 
+#[cfg(rustbus_params)]
+#[allow(clippy::mutable_key_type)]
 pub fn validate_dict(
-    dict: &params::DictMap,
+    dict: &crate::params::DictMap,
     key_sig: signature::Base,
     val_sig: &signature::Type,
 ) -> Result<()> {
@@ -280,7 +305,10 @@ pub fn validate_dict(
     Ok(())
 }
 
-pub fn validate_header_fields(msg_type: MessageType, header_fields: &[HeaderField]) -> Result<()> {
+pub fn validate_header_fields<S: AsRef<str>>(
+    msg_type: MessageType,
+    header_fields: &[HeaderField<S>],
+) -> Result<()> {
     let mut have_path = false;
     let mut have_interface = false;
     let mut have_member = false;
@@ -1,6 +1,7 @@
 //! This module implemets the org.freedesktop.DBus.Peer API for the RpcConn
 //!
-//! This might be useful for users of this library, but is kept optional
+//! A DispatchConn answers these calls by default. An RpcConn does so after `RpcConn::set_answer_peer_calls`, or the
+//! calls can be let through its filter with `filter_peer` and answered with `handle_peer_message`.
 
 mod peer_handling;
 pub use peer_handling::*;
@@ -2,34 +2,34 @@ use crate::connection::ll_conn::DuplexConn;
 use crate::message_builder::DynamicHeader;
 use crate::message_builder::MarshalledMessage;
 
+pub use crate::consts::PEER_INTERFACE;
+
+/// Where the system keeps the machine id, in the order they are tried
+static SYSTEM_MACHINE_ID_PATHS: &[&str] = &["/etc/machine-id", "/var/lib/dbus/machine-id"];
 static MACHINE_ID_FILE_PATH: &str = "/tmp/dbus_machine_uuid";
 
 /// Can be used in the RpcConn filters to allow for peer messages
 pub fn filter_peer(msg: &DynamicHeader) -> bool {
-    if let Some(interface) = &msg.interface {
-        if interface.eq("org.freedesktop.DBus.Peer") {
-            if let Some(member) = &msg.member {
-                // anything else is not in this interface and thus not handled here
-                matches!(member.as_str(), "Ping" | "GetMachineId")
-            } else {
-                false
-            }
-        } else {
-            false
-        }
-    } else {
-        false
+    match (msg.interface.as_deref(), msg.member.as_deref()) {
+        // anything else is not in this interface and thus not handled here
+        (Some(PEER_INTERFACE), Some("Ping" | "GetMachineId")) => true,
+        // the interface field is optional, a Ping without one is answered like libdbus does
+        (None, Some("Ping")) => true,
+        _ => false,
     }
 }
 
 fn create_and_store_machine_uuid() -> Result<(), std::io::Error> {
     let now = std::time::SystemTime::now();
-    let secs = now.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as u32;
+    let secs = now
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0);
     let mut rand = [0u8; 12];
 
-    let mut rand_file = std::fs::File::open("/dev/urandom").unwrap();
+    let mut rand_file = std::fs::File::open("/dev/urandom")?;
     use std::io::Read;
-    rand_file.read_exact(&mut rand[..]).unwrap();
+    rand_file.read_exact(&mut rand[..])?;
 
     let rand1 = rand[0] as u64
         | ((rand[1] as u64) << 8)
@@ -52,11 +52,53 @@ fn create_and_store_machine_uuid() -> Result<(), std::io::Error> {
     std::fs::write(MACHINE_ID_FILE_PATH, uuid)
 }
 
-fn get_machine_id() -> Result<String, std::io::Error> {
+fn get_machine_id(paths: &[&str]) -> Result<String, std::io::Error> {
+    for path in paths {
+        if let Ok(id) = std::fs::read_to_string(path) {
+            let id = id.trim();
+            if !id.is_empty() {
+                return Ok(id.to_owned());
+            }
+        }
+    }
+    // systems without a machine id get one that is made up once
     if !std::path::PathBuf::from(MACHINE_ID_FILE_PATH).exists() {
         create_and_store_machine_uuid()?;
     }
-    std::fs::read(MACHINE_ID_FILE_PATH).map(|vec| String::from_utf8(vec).unwrap())
+    let id = std::fs::read(MACHINE_ID_FILE_PATH)?;
+    String::from_utf8(id).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// The reply to a call of the org.freedesktop.DBus.Peer interface, None for all other messages. The machine id is read
+/// from /etc/machine-id. A `Ping` without an interface is answered too.
+pub fn peer_reply(call: &DynamicHeader) -> Option<MarshalledMessage> {
+    peer_reply_with_ids(call, SYSTEM_MACHINE_ID_PATHS)
+}
+
+/// `peer_reply` with the machine id read from the first of `machine_id_paths` that has one
+fn peer_reply_with_ids(
+    call: &DynamicHeader,
+    machine_id_paths: &[&str],
+) -> Option<MarshalledMessage> {
+    if !filter_peer(call) {
+        return None;
+    }
+    match call.member.as_deref()? {
+        "Ping" => Some(call.make_response()),
+        "GetMachineId" => Some(match get_machine_id(machine_id_paths) {
+            Ok(id) => {
+                let mut reply = call.make_response();
+                reply.body.push_param(id).unwrap();
+                reply
+            }
+            Err(e) => call.make_error_response_fmt(
+                crate::consts::DBUS_ERROR_FAILED,
+                format_args!("Could not read the machine id: {}", e),
+            ),
+        }),
+        // anything else is not in this interface and thus not handled here
+        _ => None,
+    }
 }
 
 /// Handles messages that are of the org.freedesktop.DBus.Peer interface. Returns as a bool whether the message was actually
@@ -65,38 +107,54 @@ pub fn handle_peer_message(
     msg: &MarshalledMessage,
     con: &mut DuplexConn,
 ) -> Result<bool, crate::connection::Error> {
-    if let Some(interface) = &msg.dynheader.interface {
-        if interface.eq("org.freedesktop.DBus.Peer") {
-            if let Some(member) = &msg.dynheader.member {
-                match member.as_str() {
-                    "Ping" => {
-                        let reply = msg.dynheader.make_response();
-                        con.send
-                            .send_message(&reply)?
-                            .write_all()
-                            .map_err(crate::connection::ll_conn::force_finish_on_error)?;
-                        Ok(true)
-                    }
-                    "GetMachineId" => {
-                        let mut reply = msg.dynheader.make_response();
-                        reply.body.push_param(get_machine_id().unwrap()).unwrap();
-                        con.send
-                            .send_message(&reply)?
-                            .write_all()
-                            .map_err(crate::connection::ll_conn::force_finish_on_error)?;
-                        Ok(true)
-                    }
-
-                    // anything else is not in this interface and thus not handled here
-                    _ => Ok(false),
-                }
-            } else {
-                Ok(false)
-            }
-        } else {
-            Ok(false)
+    match peer_reply(&msg.dynheader) {
+        Some(reply) => {
+            con.send
+                .send_message(&reply)?
+                .write_all()
+                .map_err(crate::connection::ll_conn::force_finish_on_error)?;
+            Ok(true)
         }
-    } else {
-        Ok(false)
+        None => Ok(false),
     }
 }
+
+#[test]
+fn test_peer_reply() {
+    use crate::message_builder::{MessageBuilder, MessageType};
+
+    let call = |member: &str, interface: &str| {
+        MessageBuilder::new()
+            .call(member)
+            .with_interface(interface)
+            .on("/")
+            .build()
+            .dynheader
+    };
+    let pong = peer_reply(&call("Ping", PEER_INTERFACE)).unwrap();
+    assert_eq!(pong.typ, MessageType::Reply);
+    assert_eq!(pong.get_sig(), "");
+
+    let mut ping = call("Ping", PEER_INTERFACE);
+    ping.interface = None;
+    assert_eq!(peer_reply(&ping).unwrap().typ, MessageType::Reply);
+    let mut get_id = call("GetMachineId", PEER_INTERFACE);
+    get_id.interface = None;
+    assert!(peer_reply(&get_id).is_none());
+
+    // the first file that has an id is used
+    let fixture = std::env::temp_dir().join(format!("rustbus-machine-id-{}", std::process::id()));
+    std::fs::write(&fixture, "0123456789abcdef0123456789abcdef\n").unwrap();
+    let missing = "/nonexistent/rustbus/machine-id";
+    let paths = [missing, fixture.to_str().unwrap()];
+    let id = peer_reply_with_ids(&call("GetMachineId", PEER_INTERFACE), &paths).unwrap();
+    std::fs::remove_file(&fixture).unwrap();
+    assert_eq!(id.typ, MessageType::Reply);
+    assert_eq!(
+        id.body.parser().get::<&str>().unwrap(),
+        "0123456789abcdef0123456789abcdef"
+    );
+
+    assert!(peer_reply(&call("Ping", "io.killing.spark")).is_none());
+    assert!(peer_reply(&call("Other", PEER_INTERFACE)).is_none());
+}
@@ -1,4 +1,9 @@
 //! Everything needed to deal with dbus signatures
+//!
+//! Rust tuples and structs have struct signatures, `(String, u32)` is `(su)`. The body of a message with the signature
+//! `su` however contains two separate values, not one struct, so getting a `(String, u32)` from it fails with
+//! `WrongSignature`. Such signatures only differ in the outer struct, `Type::eq_ignoring_outer_struct` and
+//! `body_eq_ignoring_outer_struct` compare them accordingly, which helps to tell this mistake apart from a real mismatch.
 
 mod signature_iter;
 use std::iter::Peekable;
@@ -293,6 +298,30 @@ impl Type {
             Type::Container(c) => c.get_alignment(),
         }
     }
+
+    /// The fields of a struct, for every other type a slice containing just this type. This is the canonical form
+    /// of a type that is compared to the types of a message body, e.g. `(su)` becomes `[s, u]`.
+    pub fn without_outer_struct(&self) -> &[Type] {
+        match self {
+            Type::Container(Container::Struct(fields)) => fields.as_ref(),
+            other => std::slice::from_ref(other),
+        }
+    }
+
+    /// Compare two types but ignore whether they are wrapped in a struct, so `(s)` equals `s` and `(su)` equals
+    /// `(su)`. Only the outermost struct is ignored, `a(s)` still differs from `as`.
+    ///
+    /// ```rust
+    /// use rustbus::signature::Type;
+    ///
+    /// let wrapped = &Type::parse_description("(s)").unwrap()[0];
+    /// let plain = &Type::parse_description("s").unwrap()[0];
+    /// assert!(wrapped.eq_ignoring_outer_struct(plain));
+    /// assert!(wrapped != plain);
+    /// ```
+    pub fn eq_ignoring_outer_struct(&self, other: &Type) -> bool {
+        self.without_outer_struct() == other.without_outer_struct()
+    }
     /// If every bit-pattern is valid for a type and
     /// and the length of the type is equal to its alignment
     /// return true.
@@ -384,6 +413,7 @@ impl Type {
             let token = token?;
             match token {
                 Token::Byte => Ok(Base::Byte),
+                Token::Boolean => Ok(Base::Boolean),
                 Token::Int16 => Ok(Base::Int16),
                 Token::Uint16 => Ok(Base::Uint16),
                 Token::Int32 => Ok(Base::Int32),
@@ -413,6 +443,38 @@ impl Type {
     }
 }
 
+/// Turn the types of a message body into their canonical form: a body containing exactly one struct is replaced by
+/// the fields of that struct, every other body is kept as it is.
+pub fn canonicalize_body(mut types: Vec<Type>) -> Vec<Type> {
+    if let [Type::Container(Container::Struct(_))] = types.as_slice() {
+        if let Some(Type::Container(Container::Struct(fields))) = types.pop() {
+            return fields.0;
+        }
+    }
+    types
+}
+
+/// Compare two body signatures in their canonical form, see `canonicalize_body`. So `(su)` equals `su`, while `(s)(u)`
+/// still differs from `su`. Returns an error if one of the signatures is invalid.
+///
+/// ```rust
+/// use rustbus::signature::body_eq_ignoring_outer_struct;
+///
+/// assert!(body_eq_ignoring_outer_struct("(su)", "su").unwrap());
+/// assert!(!body_eq_ignoring_outer_struct("(s)(u)", "su").unwrap());
+/// assert!(!body_eq_ignoring_outer_struct("a(su)", "asu").unwrap());
+/// ```
+pub fn body_eq_ignoring_outer_struct(a: &str, b: &str) -> Result<bool> {
+    let parse = |sig: &str| -> Result<Vec<Type>> {
+        if sig.is_empty() {
+            Ok(Vec::new())
+        } else {
+            Type::parse_description(sig).map(canonicalize_body)
+        }
+    };
+    Ok(parse(a)? == parse(b)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -527,5 +589,27 @@ mod tests {
         assert_parse_and_back!("a{s(dv)}");
         assert_parse_and_back!("aa{si}");
         assert_parse_and_back!("aaaa{si}");
+        assert_parse_and_back!("a{bo}");
+    }
+
+    #[test]
+    fn test_outer_struct() {
+        let parse = |sig| Type::parse_description(sig).unwrap();
+        assert!(parse("(si)")[0].eq_ignoring_outer_struct(&parse("(si)")[0]));
+        assert!(parse("(s)")[0].eq_ignoring_outer_struct(&parse("s")[0]));
+        assert!(parse("a{sv}")[0].eq_ignoring_outer_struct(&parse("(a{sv})")[0]));
+        assert!(!parse("a(s)")[0].eq_ignoring_outer_struct(&parse("as")[0]));
+        assert!(!parse("((s))")[0].eq_ignoring_outer_struct(&parse("s")[0]));
+        assert_eq!(parse("(si)")[0].without_outer_struct(), parse("si"));
+
+        assert_eq!(canonicalize_body(parse("(si)")), parse("si"));
+        assert_eq!(canonicalize_body(parse("(s)(i)")), parse("(s)(i)"));
+        assert!(body_eq_ignoring_outer_struct("", "").unwrap());
+        assert!(body_eq_ignoring_outer_struct("si", "si").unwrap());
+        assert!(!body_eq_ignoring_outer_struct("si", "is").unwrap());
+        assert_eq!(
+            body_eq_ignoring_outer_struct("(s", "s"),
+            Err(Error::InvalidSignature)
+        );
     }
 }
@@ -41,7 +41,11 @@ impl<'a> Iterator for SignatureIter<'a> {
         loop {
             // A valid siganture consists of only ascii characters and
             // `.chars().nth(end_pos)` takes linear time.
-            let current = *self.sigs.as_bytes().get(end_pos).unwrap();
+            let Some(&current) = self.sigs.as_bytes().get(end_pos) else {
+                // An invalid signature with unbalanced brackets or a trailing `a`. Return the rest as one signature,
+                // it will not match any type.
+                return Some(std::mem::take(&mut self.sigs));
+            };
             end_pos += 1;
 
             match current {
@@ -55,6 +59,9 @@ impl<'a> Iterator for SignatureIter<'a> {
                 break;
             }
         }
+        if !self.sigs.is_char_boundary(end_pos) {
+            return Some(std::mem::take(&mut self.sigs));
+        }
         let (sig, rest) = self.sigs.split_at(end_pos);
         self.sigs = rest;
         Some(sig)
@@ -75,4 +82,18 @@ mod tests {
         assert_eq!(iter.next(), Some("(((((x)))))"));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn signature_iterator_invalid() {
+        let mut iter = super::SignatureIter::new("s(ss");
+        assert_eq!(iter.next(), Some("s"));
+        assert_eq!(iter.next(), Some("(ss"));
+        assert_eq!(iter.next(), None);
+        for sig in ["sa", ")s", "}", "aä", "(ä)"] {
+            assert_eq!(
+                super::SignatureIter::new(sig).last(),
+                Some(sig.trim_start_matches('s'))
+            );
+        }
+    }
 }
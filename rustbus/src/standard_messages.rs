@@ -1,8 +1,14 @@
 //! Some standard messages that are often needed
 
 use crate::message_builder::DynamicHeader;
+use crate::message_builder::ErrorReply;
 use crate::message_builder::MarshalledMessage;
 use crate::message_builder::MessageBuilder;
+use crate::message_builder::MessageType;
+use crate::wire::errors::UnmarshalError;
+use crate::Unmarshal;
+
+use thiserror::Error;
 
 pub fn hello() -> MarshalledMessage {
     make_standard_msg("Hello")
@@ -11,8 +17,8 @@ pub fn hello() -> MarshalledMessage {
 pub fn ping(dest: String) -> MarshalledMessage {
     MessageBuilder::new()
         .call("Ping")
-        .on("/org/freedesktop/DBus")
-        .with_interface("org.freedesktop.DBus.Peer")
+        .on(DBUS_PATH)
+        .with_interface(PEER_INTERFACE)
         .at(dest)
         .build()
 }
@@ -20,8 +26,8 @@ pub fn ping(dest: String) -> MarshalledMessage {
 pub fn ping_bus() -> MarshalledMessage {
     MessageBuilder::new()
         .call("Ping")
-        .on("/org/freedesktop/DBus")
-        .with_interface("org.freedesktop.DBus.Peer")
+        .on(DBUS_PATH)
+        .with_interface(PEER_INTERFACE)
         .build()
 }
 
@@ -29,6 +35,11 @@ pub fn list_names() -> MarshalledMessage {
     make_standard_msg("ListNames")
 }
 
+/// List the names that the bus could start a service for
+pub fn list_activatable_names() -> MarshalledMessage {
+    make_standard_msg("ListActivatableNames")
+}
+
 pub const DBUS_NAME_FLAG_ALLOW_REPLACEMENT: u32 = 1;
 pub const DBUS_NAME_FLAG_REPLACE_EXISTING: u32 = 1 << 1;
 pub const DBUS_NAME_FLAG_DO_NOT_QUEUE: u32 = 1 << 2;
@@ -38,12 +49,58 @@ pub const DBUS_REQUEST_NAME_REPLY_IN_QUEUE: u32 = 2;
 pub const DBUS_REQUEST_NAME_REPLY_EXISTS: u32 = 3;
 pub const DBUS_REQUEST_NAME_REPLY_ALREADY_OWNER: u32 = 4;
 
+/// The reply of the bus to `request_name`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestNameReply {
+    /// The caller is now the primary owner of the name
+    PrimaryOwner,
+    /// The name had an owner already, the caller was put into the queue for it
+    InQueue,
+    /// The name had an owner already and the caller could neither replace it nor be queued
+    Exists,
+    /// The caller was the primary owner of the name already
+    AlreadyOwner,
+}
+
+impl RequestNameReply {
+    /// Map one of the `DBUS_REQUEST_NAME_REPLY_*` constants to the reply
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER => Some(Self::PrimaryOwner),
+            DBUS_REQUEST_NAME_REPLY_IN_QUEUE => Some(Self::InQueue),
+            DBUS_REQUEST_NAME_REPLY_EXISTS => Some(Self::Exists),
+            DBUS_REQUEST_NAME_REPLY_ALREADY_OWNER => Some(Self::AlreadyOwner),
+            _ => None,
+        }
+    }
+
+    /// The `DBUS_REQUEST_NAME_REPLY_*` constant of the reply
+    pub fn code(self) -> u32 {
+        match self {
+            Self::PrimaryOwner => DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER,
+            Self::InQueue => DBUS_REQUEST_NAME_REPLY_IN_QUEUE,
+            Self::Exists => DBUS_REQUEST_NAME_REPLY_EXISTS,
+            Self::AlreadyOwner => DBUS_REQUEST_NAME_REPLY_ALREADY_OWNER,
+        }
+    }
+}
+
+pub const DBUS_RELEASE_NAME_REPLY_RELEASED: u32 = 1;
+pub const DBUS_RELEASE_NAME_REPLY_NON_EXISTENT: u32 = 2;
+pub const DBUS_RELEASE_NAME_REPLY_NOT_OWNER: u32 = 3;
+
+pub const DBUS_START_REPLY_SUCCESS: u32 = 1;
+pub const DBUS_START_REPLY_ALREADY_RUNNING: u32 = 2;
+
+// The error names used to be defined here, they moved to `consts` with the other names of the spec
+pub use crate::consts::*;
+
 fn make_standard_msg(name: &str) -> MarshalledMessage {
     MessageBuilder::new()
         .call(name)
-        .on("/org/freedesktop/DBus")
-        .with_interface("org.freedesktop.DBus")
-        .at("org.freedesktop.DBus")
+        .on(DBUS_PATH)
+        .with_interface(DBUS_INTERFACE)
+        .at(DBUS_NAME)
         .build()
 }
 /// Request a name on the bus
@@ -61,6 +118,73 @@ pub fn release_name(name: &str) -> MarshalledMessage {
     msg
 }
 
+/// Ask the bus whether a name currently has an owner
+pub fn name_has_owner(name: &str) -> MarshalledMessage {
+    let mut msg = make_standard_msg("NameHasOwner");
+    msg.body.push_param(name).unwrap();
+    msg
+}
+
+/// Ask the bus for the unique name of the connection that owns `name`
+pub fn get_name_owner(name: &str) -> MarshalledMessage {
+    let mut msg = make_standard_msg("GetNameOwner");
+    msg.body.push_param(name).unwrap();
+    msg
+}
+
+/// Ask the bus to start the service for `name`. The flags are currently unused by the spec and should be 0.
+pub fn start_service_by_name(name: &str, flags: u32) -> MarshalledMessage {
+    let mut msg = make_standard_msg("StartServiceByName");
+    msg.body.push_param(name).unwrap();
+    msg.body.push_param(flags).unwrap();
+    msg
+}
+
+/// Ask the bus for the unix user id of the process owning `name`
+pub fn get_connection_unix_user(name: &str) -> MarshalledMessage {
+    let mut msg = make_standard_msg("GetConnectionUnixUser");
+    msg.body.push_param(name).unwrap();
+    msg
+}
+
+/// Ask the bus for the process id of the process owning `name`
+pub fn get_connection_unix_process_id(name: &str) -> MarshalledMessage {
+    let mut msg = make_standard_msg("GetConnectionUnixProcessID");
+    msg.body.push_param(name).unwrap();
+    msg
+}
+
+/// Add or change variables in the environment of services activated by the bus. Session managers use this to propagate
+/// e.g. DISPLAY or WAYLAND_DISPLAY.
+///
+/// The variable names must be non-empty and must not contain a '='. Neither names nor values may contain null bytes.
+pub fn update_activation_environment<I, K, V>(
+    vars: I,
+) -> Result<MarshalledMessage, crate::params::validation::Error>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    use crate::params::validation::Error;
+
+    let mut env = std::collections::HashMap::new();
+    for (key, value) in vars {
+        let (key, value) = (key.as_ref().to_owned(), value.as_ref().to_owned());
+        if key.is_empty() || key.contains('=') {
+            return Err(Error::InvalidEnvironmentVariable);
+        }
+        if key.contains('\0') || value.contains('\0') {
+            return Err(Error::StringContainsNullByte);
+        }
+        env.insert(key, value);
+    }
+
+    let mut msg = make_standard_msg("UpdateActivationEnvironment");
+    msg.body.push_param(env).unwrap();
+    Ok(msg)
+}
+
 /// Add a match rule to receive signals. e.g. match_rule = "type='signal'" to get all signals
 pub fn add_match(match_rule: &str) -> MarshalledMessage {
     let mut msg = make_standard_msg("AddMatch");
@@ -81,10 +205,7 @@ pub fn unknown_method(call: &DynamicHeader) -> MarshalledMessage {
         call.member.clone().unwrap_or_else(|| "".to_owned()),
         call.object.clone().unwrap_or_else(|| "".to_owned()),
     );
-    call.make_error_response(
-        "org.freedesktop.DBus.Error.UnknownMethod".to_owned(),
-        Some(text),
-    )
+    call.make_error_response(DBUS_ERROR_UNKNOWN_METHOD, Some(text))
 }
 
 /// Error message to tell the caller that this method uses a different interface than what the caller provided as parameters
@@ -101,8 +222,159 @@ pub fn invalid_args(call: &DynamicHeader, sig: Option<&str>) -> MarshalledMessag
         }
     );
 
-    call.make_error_response(
-        "org.freedesktop.DBus.Error.InvalidArgs".to_owned(),
-        Some(text),
-    )
+    call.make_error_response(DBUS_ERROR_INVALID_ARGS, Some(text))
+}
+
+/// Errors that can occur when parsing the reply to one of the calls in this module
+#[derive(Debug, Error)]
+pub enum ReplyError {
+    #[error("The bus returned the error {0}")]
+    ErrorReply(ErrorReply),
+    #[error("Expected a reply but got a message of type {0:?}")]
+    UnexpectedMessageType(MessageType),
+    #[error("The reply contained the unknown code {0}")]
+    UnknownReplyCode(u32),
+    #[error("An error occured while unmarshalling the reply: {0}")]
+    Unmarshal(#[from] UnmarshalError),
+}
+
+fn parse_reply<'a, T: Unmarshal<'a, 'a>>(reply: &'a MarshalledMessage) -> Result<T, ReplyError> {
+    if let Some(error) = reply.error_reply() {
+        return Err(ReplyError::ErrorReply(error));
+    }
+    match reply.typ {
+        MessageType::Reply => Ok(reply.body.parser().get()?),
+        typ => Err(ReplyError::UnexpectedMessageType(typ)),
+    }
+}
+
+/// Parse the reply to `request_name`
+pub fn parse_request_name_reply(reply: &MarshalledMessage) -> Result<RequestNameReply, ReplyError> {
+    let code = parse_reply(reply)?;
+    RequestNameReply::from_code(code).ok_or(ReplyError::UnknownReplyCode(code))
+}
+
+/// Parse the reply to `release_name` into one of the `DBUS_RELEASE_NAME_REPLY_*` constants
+pub fn parse_release_name_reply(reply: &MarshalledMessage) -> Result<u32, ReplyError> {
+    parse_reply(reply)
+}
+
+/// Parse the reply to `list_names` or `list_activatable_names`
+pub fn parse_list_names_reply(reply: &MarshalledMessage) -> Result<Vec<String>, ReplyError> {
+    parse_reply(reply)
+}
+
+/// Parse the reply to `get_name_owner` into the unique name of the owner. If the name has no owner the bus answers
+/// with `DBUS_ERROR_NAME_HAS_NO_OWNER`.
+pub fn parse_get_name_owner_reply(reply: &MarshalledMessage) -> Result<String, ReplyError> {
+    parse_reply(reply)
+}
+
+/// Parse the reply to `name_has_owner`
+pub fn parse_name_has_owner_reply(reply: &MarshalledMessage) -> Result<bool, ReplyError> {
+    parse_reply(reply)
+}
+
+/// Parse the reply to `start_service_by_name` into one of the `DBUS_START_REPLY_*` constants
+pub fn parse_start_service_by_name_reply(reply: &MarshalledMessage) -> Result<u32, ReplyError> {
+    parse_reply(reply)
+}
+
+/// Parse the reply to `get_connection_unix_user` into the user id
+pub fn parse_get_connection_unix_user_reply(reply: &MarshalledMessage) -> Result<u32, ReplyError> {
+    parse_reply(reply)
+}
+
+/// Parse the reply to `get_connection_unix_process_id` into the process id
+pub fn parse_get_connection_unix_process_id_reply(
+    reply: &MarshalledMessage,
+) -> Result<u32, ReplyError> {
+    parse_reply(reply)
+}
+
+#[test]
+fn test_bus_management_replies() {
+    let call = get_name_owner("io.killing.spark");
+    assert_eq!(call.dynheader.member.as_deref(), Some("GetNameOwner"));
+    assert_eq!(call.get_sig(), "s");
+    assert_eq!(start_service_by_name("io.killing.spark", 0).get_sig(), "su");
+
+    let mut reply = call.dynheader.make_response();
+    reply.body.push_param(":1.42").unwrap();
+    assert_eq!(parse_get_name_owner_reply(&reply).unwrap(), ":1.42");
+    // the reply does not match the call it is parsed for
+    assert!(matches!(
+        parse_get_connection_unix_user_reply(&reply),
+        Err(ReplyError::Unmarshal(UnmarshalError::WrongSignature))
+    ));
+
+    let mut reply = list_activatable_names().dynheader.make_response();
+    reply
+        .body
+        .push_param(vec!["org.freedesktop.DBus", "io.killing.spark"])
+        .unwrap();
+    assert_eq!(
+        parse_list_names_reply(&reply).unwrap(),
+        ["org.freedesktop.DBus", "io.killing.spark"]
+    );
+
+    let error = call
+        .dynheader
+        .make_error_response(DBUS_ERROR_NAME_HAS_NO_OWNER, Some("no owner".to_owned()));
+    match parse_get_name_owner_reply(&error) {
+        Err(ReplyError::ErrorReply(ErrorReply { name, message })) => {
+            assert_eq!(name, DBUS_ERROR_NAME_HAS_NO_OWNER);
+            assert_eq!(message.as_deref(), Some("no owner"));
+        }
+        other => panic!("expected an error reply but got {:?}", other),
+    }
+    assert!(matches!(
+        parse_name_has_owner_reply(&call),
+        Err(ReplyError::UnexpectedMessageType(MessageType::Call))
+    ));
+
+    let mut reply = request_name("io.killing.spark", 0)
+        .dynheader
+        .make_response();
+    reply
+        .body
+        .push_param(DBUS_REQUEST_NAME_REPLY_IN_QUEUE)
+        .unwrap();
+    assert_eq!(
+        parse_request_name_reply(&reply).unwrap(),
+        RequestNameReply::InQueue
+    );
+    let mut reply = request_name("io.killing.spark", 0)
+        .dynheader
+        .make_response();
+    reply.body.push_param(42u32).unwrap();
+    assert!(matches!(
+        parse_request_name_reply(&reply),
+        Err(ReplyError::UnknownReplyCode(42))
+    ));
+}
+
+#[test]
+fn test_update_activation_environment() {
+    use crate::params::validation::Error;
+    use std::collections::HashMap;
+
+    let msg = update_activation_environment([("DISPLAY", ":0"), ("LANG", "C.UTF-8")]).unwrap();
+    assert_eq!(msg.get_sig(), "a{ss}");
+    let env: HashMap<String, String> = msg.body.parser().get().unwrap();
+    assert_eq!(env["DISPLAY"], ":0");
+    assert_eq!(env["LANG"], "C.UTF-8");
+
+    assert!(matches!(
+        update_activation_environment([("A=B", "C")]),
+        Err(Error::InvalidEnvironmentVariable)
+    ));
+    assert!(matches!(
+        update_activation_environment([("", "C")]),
+        Err(Error::InvalidEnvironmentVariable)
+    ));
+    assert!(matches!(
+        update_activation_environment([("A", "B\0C")]),
+        Err(Error::StringContainsNullByte)
+    ));
 }
@@ -0,0 +1,36 @@
+//! The synchronization primitives used for state that is shared between threads.
+//!
+//! Everything that is shared (UnixFds, the SendConn of a DispatchConn and the worker pool of a PooledDispatchConn, the
+//! buffers of shared bodies and the cached signature types of bodies) uses these instead of naming `std::sync` directly.
+//! When compiled with `--cfg rustbus_loom` the locks, atomics, channels and threads are the instrumented ones of loom, so
+//! the `loom_*` tests can check all interleavings:
+//!
+//! `RUSTFLAGS="--cfg rustbus_loom" cargo test --release --lib loom`
+//!
+//! `Arc` and `OnceLock` stay the ones of std, `Arc` appears in the public API and loom has no `OnceLock`.
+
+pub(crate) use std::sync::{Arc, OnceLock};
+
+#[cfg(not(rustbus_loom))]
+pub(crate) use std::sync::atomic::{AtomicI32, Ordering};
+#[cfg(not(rustbus_loom))]
+pub(crate) use std::sync::mpsc::{sync_channel, SyncSender};
+#[cfg(not(rustbus_loom))]
+pub(crate) use std::sync::{mpsc, Mutex, MutexGuard};
+#[cfg(not(rustbus_loom))]
+pub(crate) use std::thread;
+
+#[cfg(rustbus_loom)]
+pub(crate) use loom::sync::atomic::{AtomicI32, Ordering};
+#[cfg(rustbus_loom)]
+pub(crate) use loom::sync::{mpsc, Mutex, MutexGuard};
+#[cfg(rustbus_loom)]
+pub(crate) use loom::thread;
+
+/// loom has no bounded channel, the models never fill one anyway
+#[cfg(rustbus_loom)]
+pub(crate) type SyncSender<T> = mpsc::Sender<T>;
+#[cfg(rustbus_loom)]
+pub(crate) fn sync_channel<T>(_bound: usize) -> (SyncSender<T>, mpsc::Receiver<T>) {
+    mpsc::channel()
+}
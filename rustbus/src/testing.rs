@@ -0,0 +1,1023 @@
+//! An in-process bus for integration tests that should not depend on a running dbus-daemon
+//!
+//! `TestBus` listens on a socket in the temp dir and routes messages between its clients on a background thread. It
+//! speaks the part of `org.freedesktop.DBus` that clients and services usually need:
+//!
+//! * `Hello`, `RequestName` (with queueing and replacement), `ReleaseName`, `ListQueuedOwners`
+//! * `GetNameOwner`, `NameHasOwner`, `ListNames`, `GetId`
+//! * `AddMatch` and `RemoveMatch`, signals without a destination are forwarded to all clients with a matching rule
+//! * the `NameOwnerChanged`, `NameAcquired` and `NameLost` signals
+//!
+//! Messages with a destination are forwarded to its owner, calls to names nobody owns are answered with
+//! `org.freedesktop.DBus.Error.ServiceUnknown`. There is no policy, no activation and no eavesdropping.
+//!
+//! ```rust
+//! use rustbus::connection::Timeout;
+//! use rustbus::testing::TestBus;
+//! use rustbus::MessageBuilder;
+//!
+//! let bus = TestBus::start().unwrap();
+//! let mut service = bus.connect().unwrap();
+//! service.request_name("io.killing.spark.Test", 0, Timeout::Infinite).unwrap();
+//!
+//! let mut client = bus.connect().unwrap();
+//! let mut call = MessageBuilder::new()
+//!     .call("Ping")
+//!     .on("/")
+//!     .with_interface("org.freedesktop.DBus.Peer")
+//!     .at("io.killing.spark.Test")
+//!     .build();
+//! let serial = client.send_message(&mut call).unwrap().write_all().unwrap();
+//!
+//! let call = service.wait_call(Timeout::Infinite).unwrap();
+//! service.send_message(&mut call.dynheader.make_response()).unwrap().write_all().unwrap();
+//! client.wait_response(serial, Timeout::Infinite).unwrap();
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use nix::sys::socket::UnixAddr;
+
+use crate::connection::ll_conn::{DuplexConn, PeerListener, WriteProgress};
+use crate::connection::rpc_conn::BusSource;
+use crate::connection::{BusAddress, Error, Timeout};
+use crate::consts;
+use crate::match_rule::MatchRule;
+use crate::message_builder::{HeaderFlags, MarshalledMessage, MessageBuilder, MessageType};
+use crate::standard_messages::{
+    DBUS_NAME_FLAG_ALLOW_REPLACEMENT, DBUS_NAME_FLAG_DO_NOT_QUEUE, DBUS_NAME_FLAG_REPLACE_EXISTING,
+    DBUS_RELEASE_NAME_REPLY_NON_EXISTENT, DBUS_RELEASE_NAME_REPLY_NOT_OWNER,
+    DBUS_RELEASE_NAME_REPLY_RELEASED, DBUS_REQUEST_NAME_REPLY_ALREADY_OWNER,
+    DBUS_REQUEST_NAME_REPLY_EXISTS, DBUS_REQUEST_NAME_REPLY_IN_QUEUE,
+    DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER,
+};
+use crate::RpcConn;
+
+/// A message bus running on a background thread. It is stopped and its socket is removed when it is dropped.
+#[derive(Debug)]
+pub struct TestBus {
+    path: PathBuf,
+    guid: String,
+    wake: UnixStream,
+    router: Option<JoinHandle<()>>,
+}
+
+impl TestBus {
+    /// Listen on a new socket in the temp dir and start routing
+    pub fn start() -> Result<TestBus, Error> {
+        static NEXT_BUS: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "rustbus-testbus-{}-{}",
+            std::process::id(),
+            NEXT_BUS.fetch_add(1, Ordering::Relaxed)
+        ));
+        // left over from a process with the same pid
+        let _ = std::fs::remove_file(&path);
+        let addr = UnixAddr::new(&path).map_err(std::io::Error::from)?;
+        let listener = DuplexConn::listen(&addr)?;
+        let guid = listener.guid().to_owned();
+        let (wake, wake_router) = UnixStream::pair()?;
+        let (authenticated_wake, authenticated_poll) = UnixStream::pair()?;
+        authenticated_poll.set_nonblocking(true)?;
+        let (authenticated_send, authenticated) = mpsc::channel();
+
+        let router = Router {
+            listener,
+            wake: wake_router,
+            authenticated,
+            authenticated_send,
+            authenticated_wake,
+            authenticated_poll,
+            guid: guid.clone(),
+            clients: BTreeMap::new(),
+            names: HashMap::new(),
+            next_id: 1,
+            dead: Vec::new(),
+        };
+        let router = std::thread::Builder::new()
+            .name("rustbus-testbus".to_owned())
+            .spawn(move || router.run())?;
+
+        Ok(TestBus {
+            path,
+            guid,
+            wake,
+            router: Some(router),
+        })
+    }
+
+    /// The path of the socket
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The id of the bus, as returned by `GetId`
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+
+    pub fn address(&self) -> BusAddress {
+        BusAddress::Unix(UnixAddr::new(&self.path).unwrap())
+    }
+
+    /// The address in the format of `DBUS_SESSION_BUS_ADDRESS`, e.g. to start a service under test in a child process
+    pub fn address_string(&self) -> String {
+        let mut address = String::from("unix:path=");
+        for byte in self.path.as_os_str().as_bytes() {
+            if byte.is_ascii_alphanumeric() || b"-_/.\\*".contains(byte) {
+                address.push(char::from(*byte));
+            } else {
+                address.push_str(&format!("%{:02x}", byte));
+            }
+        }
+        address.push_str(",guid=");
+        address.push_str(&self.guid);
+        address
+    }
+
+    /// Connect a new client, the hello message has already been answered when this returns
+    pub fn connect(&self) -> Result<RpcConn, Error> {
+        RpcConn::connect_to_source(BusSource::Address(self.address()), Timeout::Infinite)
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        // any byte (or the closed socket) stops the router
+        let _ = self.wake.write_all(&[0]);
+        if let Some(router) = self.router.take() {
+            let _ = router.join();
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// What the bus answers with if a method call to it fails
+type BusError = (&'static str, String);
+
+struct Client {
+    conn: DuplexConn,
+    unique: String,
+    /// Whether the client sent its hello message yet
+    registered: bool,
+    rules: Vec<MatchRule>,
+}
+
+struct Router {
+    listener: PeerListener,
+    wake: UnixStream,
+    /// Connections whose handshake finished on a thread of their own. A byte is written to `authenticated_wake` for
+    /// each so the router notices them.
+    authenticated: mpsc::Receiver<DuplexConn>,
+    authenticated_send: mpsc::Sender<DuplexConn>,
+    authenticated_wake: UnixStream,
+    authenticated_poll: UnixStream,
+    guid: String,
+    clients: BTreeMap<u64, Client>,
+    /// Well-known names, the first entry is the owner and the rest are queued. Each entry also stores the flags of the
+    /// `RequestName` call.
+    names: HashMap<String, Vec<(u64, u32)>>,
+    next_id: u64,
+    /// Clients whose connection failed while other clients were served, they are removed after each round
+    dead: Vec<u64>,
+}
+
+impl Router {
+    fn run(mut self) {
+        use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+        let revents = |pollfd: &PollFd| pollfd.revents().unwrap_or(PollFlags::empty());
+        loop {
+            let (wake, incoming, authenticated, ready) = {
+                let mut pollfds = vec![
+                    PollFd::new(self.wake.as_fd(), PollFlags::POLLIN),
+                    PollFd::new(self.listener.as_fd(), PollFlags::POLLIN),
+                    PollFd::new(self.authenticated_poll.as_fd(), PollFlags::POLLIN),
+                ];
+                for client in self.clients.values() {
+                    let mut flags = PollFlags::POLLIN;
+                    if client.conn.send.has_queued() {
+                        flags |= PollFlags::POLLOUT;
+                    }
+                    pollfds.push(PollFd::new(client.conn.as_fd(), flags));
+                }
+                match poll(&mut pollfds, PollTimeout::NONE) {
+                    Ok(_) | Err(nix::errno::Errno::EINTR) => {}
+                    Err(_) => return,
+                }
+                let ready: Vec<_> = self
+                    .clients
+                    .keys()
+                    .copied()
+                    .zip(pollfds[3..].iter().map(revents))
+                    .collect();
+                (
+                    revents(&pollfds[0]),
+                    revents(&pollfds[1]),
+                    revents(&pollfds[2]),
+                    ready,
+                )
+            };
+
+            if !wake.is_empty() {
+                return;
+            }
+            if incoming.contains(PollFlags::POLLIN) {
+                self.accept();
+            }
+            if authenticated.contains(PollFlags::POLLIN) {
+                self.add_authenticated();
+            }
+            for (id, events) in ready {
+                if events.contains(PollFlags::POLLOUT) {
+                    self.flush(id);
+                }
+                if events.intersects(PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR) {
+                    self.read(id);
+                }
+            }
+            while let Some(id) = self.dead.pop() {
+                self.disconnect(id);
+            }
+        }
+    }
+
+    /// Accept the next client and run its handshake on a thread of its own, so a client that does not finish it can
+    /// not stall the bus
+    fn accept(&mut self) {
+        let Ok(stream) = self.listener.accept_unauthenticated() else {
+            return;
+        };
+        let (Ok(wake), server) = (
+            self.authenticated_wake.try_clone(),
+            self.listener.auth_server().clone(),
+        ) else {
+            return;
+        };
+        let authenticated = self.authenticated_send.clone();
+        let _ = std::thread::Builder::new()
+            .name("rustbus-testbus-auth".to_owned())
+            .spawn(move || {
+                if let Ok((conn, _)) = DuplexConn::accept_with(stream, &server) {
+                    // both fail only if the bus stopped in the meantime
+                    if authenticated.send(conn).is_ok() {
+                        let _ = (&wake).write_all(&[0]);
+                    }
+                }
+            });
+    }
+
+    fn add_authenticated(&mut self) {
+        let mut buf = [0; 64];
+        while matches!(std::io::Read::read(&mut &self.authenticated_poll, &mut buf), Ok(n) if n > 0)
+        {
+        }
+        while let Ok(conn) = self.authenticated.try_recv() {
+            self.add_client(conn);
+        }
+    }
+
+    fn add_client(&mut self, conn: DuplexConn) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.clients.insert(
+            id,
+            Client {
+                conn,
+                unique: format!(":1.{}", id),
+                registered: false,
+                rules: Vec::new(),
+            },
+        );
+        // the hello message may have been sent together with BEGIN
+        self.process(id);
+    }
+
+    fn read(&mut self, id: u64) {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        match client.conn.recv.read_once(Timeout::Nonblock) {
+            Ok(()) | Err(Error::TimedOut) => self.process(id),
+            Err(_) => self.dead.push(id),
+        }
+    }
+
+    /// Route all messages that were received completely
+    fn process(&mut self, id: u64) {
+        loop {
+            let Some(client) = self.clients.get_mut(&id) else {
+                return;
+            };
+            match client.conn.recv.buffer_contains_whole_message() {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(_) => return self.dead.push(id),
+            }
+            match client.conn.recv.get_next_message(Timeout::Nonblock) {
+                Ok(msg) => self.route(id, msg),
+                Err(_) => return self.dead.push(id),
+            }
+        }
+    }
+
+    fn flush(&mut self, id: u64) {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        loop {
+            match client.conn.send.write_next_chunk() {
+                Ok(WriteProgress::Written(_)) => {}
+                Ok(WriteProgress::Done) | Ok(WriteProgress::WouldBlock) => return,
+                Err(_) => return self.dead.push(id),
+            }
+        }
+    }
+
+    /// Queue the message for the client and write as much as possible right away. Messages to clients that
+    /// disconnected in the meantime are dropped.
+    fn send(&mut self, id: u64, msg: MarshalledMessage) {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        if client.conn.send.queue_message_owned(msg).is_ok() {
+            self.flush(id);
+        }
+    }
+
+    fn send_from_bus(&mut self, id: u64, mut msg: MarshalledMessage) {
+        msg.dynheader.sender = Some(consts::DBUS_NAME.to_owned());
+        self.send(id, msg);
+    }
+
+    fn route(&mut self, id: u64, mut msg: MarshalledMessage) {
+        let client = &self.clients[&id];
+        if !client.registered && !is_hello(&msg) {
+            // like dbus-daemon, the first message has to be the hello
+            return self.dead.push(id);
+        }
+        msg.dynheader.sender = Some(client.unique.clone());
+
+        match msg.dynheader.destination.clone() {
+            Some(destination) if destination == consts::DBUS_NAME => self.bus_call(id, msg),
+            Some(destination) => match self.resolve(&destination) {
+                Some(target) => self.send(target, msg),
+                None => {
+                    if msg.typ == MessageType::Call
+                        && !HeaderFlags::NoReplyExpected.is_set(msg.flags)
+                    {
+                        let err = msg.dynheader.make_error_response(
+                            consts::DBUS_ERROR_SERVICE_UNKNOWN,
+                            Some(format!(
+                                "The name {} was not provided by any .service files",
+                                destination
+                            )),
+                        );
+                        self.send_from_bus(id, err);
+                    }
+                }
+            },
+            None => self.broadcast(&msg, Some(id)),
+        }
+    }
+
+    /// Send a copy of the message to each client with a matching rule. `sender` is the client that sent it, or `None`
+    /// for signals of the bus itself.
+    fn broadcast(&mut self, msg: &MarshalledMessage, sender: Option<u64>) {
+        let receivers: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                client.registered
+                    && client.rules.iter().any(|rule| {
+                        rule.matches(msg)
+                            && rule.get_sender().is_none_or(|name| {
+                                name.starts_with(':')
+                                    || msg.dynheader.sender.as_deref() == Some(name)
+                                    || (sender.is_some() && self.owner(name) == sender)
+                            })
+                    })
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in receivers {
+            let client = self.clients.get_mut(&id).unwrap();
+            if client.conn.send.queue_message(msg).is_ok() {
+                self.flush(id);
+            }
+        }
+    }
+
+    fn owner(&self, name: &str) -> Option<u64> {
+        self.names.get(name).map(|queue| queue[0].0)
+    }
+
+    /// The client a destination refers to, either by its unique name or by a well-known name it owns
+    fn resolve(&self, name: &str) -> Option<u64> {
+        if name.starts_with(':') {
+            self.clients
+                .iter()
+                .find(|(_, client)| client.registered && client.unique == name)
+                .map(|(id, _)| *id)
+        } else {
+            self.owner(name)
+        }
+    }
+
+    fn unique_name(&self, id: Option<u64>) -> String {
+        id.and_then(|id| self.clients.get(&id))
+            .map(|client| client.unique.clone())
+            .unwrap_or_default()
+    }
+
+    fn bus_call(&mut self, id: u64, msg: MarshalledMessage) {
+        // replies and signals sent to the bus are dropped
+        if msg.typ != MessageType::Call {
+            return;
+        }
+        let mut signals = Vec::new();
+        let reply = self
+            .bus_method(id, &msg, &mut signals)
+            .unwrap_or_else(|(name, text)| msg.dynheader.make_error_response(name, Some(text)));
+        if !HeaderFlags::NoReplyExpected.is_set(msg.flags) {
+            self.send_from_bus(id, reply);
+        }
+        self.send_signals(signals);
+    }
+
+    /// Send the signals collected by `bus_method`. Signals to a single client are addressed to its unique name like
+    /// dbus-daemon does, so clients can tell them from broadcasts.
+    fn send_signals(&mut self, signals: Vec<(Option<u64>, MarshalledMessage)>) {
+        for (destination, mut signal) in signals {
+            match destination {
+                Some(destination) => {
+                    signal.dynheader.destination = Some(self.unique_name(Some(destination)));
+                    self.send_from_bus(destination, signal);
+                }
+                None => self.broadcast(&signal, None),
+            }
+        }
+    }
+
+    /// Handle a call to the bus. Signals that have to be sent after the reply are pushed to `signals`, with the client
+    /// they are sent to or `None` for broadcasts.
+    fn bus_method(
+        &mut self,
+        id: u64,
+        msg: &MarshalledMessage,
+        signals: &mut Vec<(Option<u64>, MarshalledMessage)>,
+    ) -> Result<MarshalledMessage, BusError> {
+        let member = msg.dynheader.member.as_deref().unwrap_or_default();
+        let interface = msg.dynheader.interface.as_deref();
+        if interface == Some(consts::PEER_INTERFACE) && member == "Ping" {
+            return Ok(msg.dynheader.make_response());
+        }
+        if interface.is_some_and(|interface| interface != consts::DBUS_INTERFACE) {
+            return Err(unknown_method(msg));
+        }
+
+        let mut reply = msg.dynheader.make_response();
+        match member {
+            "Hello" => {
+                let client = self.clients.get_mut(&id).unwrap();
+                if client.registered {
+                    return Err((
+                        consts::DBUS_ERROR_FAILED,
+                        "Already handled an Hello message".to_owned(),
+                    ));
+                }
+                client.registered = true;
+                let unique = client.unique.clone();
+                reply.body.push_param(&unique).map_err(invalid_args)?;
+                signals.push((None, name_owner_changed(&unique, "", &unique)));
+                signals.push((Some(id), name_signal("NameAcquired", &unique)));
+            }
+            "RequestName" => {
+                let (name, flags): (String, u32) =
+                    msg.body.parser().get2().map_err(invalid_args)?;
+                let code = self.request_name(id, name, flags, signals)?;
+                reply.body.push_param(code).map_err(invalid_args)?;
+            }
+            "ReleaseName" => {
+                let name: String = msg.body.parser().get().map_err(invalid_args)?;
+                let code = self.release_name(id, &name, signals)?;
+                reply.body.push_param(code).map_err(invalid_args)?;
+            }
+            "ListQueuedOwners" => {
+                let name: String = msg.body.parser().get().map_err(invalid_args)?;
+                let queue = self.names.get(&name).ok_or_else(|| no_owner(&name))?;
+                let owners: Vec<String> = queue
+                    .iter()
+                    .map(|(owner, _)| self.unique_name(Some(*owner)))
+                    .collect();
+                reply.body.push_param(owners).map_err(invalid_args)?;
+            }
+            "GetNameOwner" => {
+                let name: String = msg.body.parser().get().map_err(invalid_args)?;
+                let owner = if name == consts::DBUS_NAME {
+                    name
+                } else {
+                    let owner = self.resolve(&name).ok_or_else(|| no_owner(&name))?;
+                    self.unique_name(Some(owner))
+                };
+                reply.body.push_param(owner).map_err(invalid_args)?;
+            }
+            "NameHasOwner" => {
+                let name: String = msg.body.parser().get().map_err(invalid_args)?;
+                let has_owner = name == consts::DBUS_NAME || self.resolve(&name).is_some();
+                reply.body.push_param(has_owner).map_err(invalid_args)?;
+            }
+            "ListNames" => {
+                let mut names = vec![consts::DBUS_NAME.to_owned()];
+                names.extend(self.names.keys().cloned());
+                names.extend(
+                    self.clients
+                        .values()
+                        .filter(|client| client.registered)
+                        .map(|client| client.unique.clone()),
+                );
+                reply.body.push_param(names).map_err(invalid_args)?;
+            }
+            "GetId" => {
+                reply.body.push_param(&self.guid).map_err(invalid_args)?;
+            }
+            "AddMatch" => {
+                let rule: &str = msg.body.parser().get().map_err(invalid_args)?;
+                let parsed = MatchRule::parse(rule).ok_or_else(|| {
+                    (
+                        consts::DBUS_ERROR_MATCH_RULE_INVALID,
+                        format!("Invalid match rule: {}", rule),
+                    )
+                })?;
+                self.clients.get_mut(&id).unwrap().rules.push(parsed);
+            }
+            "RemoveMatch" => {
+                let rule: &str = msg.body.parser().get().map_err(invalid_args)?;
+                let not_found = || {
+                    (
+                        consts::DBUS_ERROR_MATCH_RULE_NOT_FOUND,
+                        "The given match rule wasn't found and can't be removed".to_owned(),
+                    )
+                };
+                let parsed = MatchRule::parse(rule).ok_or_else(not_found)?;
+                let rules = &mut self.clients.get_mut(&id).unwrap().rules;
+                let idx = rules
+                    .iter()
+                    .position(|known| *known == parsed)
+                    .ok_or_else(not_found)?;
+                rules.remove(idx);
+            }
+            _ => return Err(unknown_method(msg)),
+        }
+        Ok(reply)
+    }
+
+    fn request_name(
+        &mut self,
+        id: u64,
+        name: String,
+        flags: u32,
+        signals: &mut Vec<(Option<u64>, MarshalledMessage)>,
+    ) -> Result<u32, BusError> {
+        if name.starts_with(':') {
+            return Err((
+                consts::DBUS_ERROR_INVALID_ARGS,
+                format!(
+                    "Cannot acquire a service starting with ':' such as \"{}\"",
+                    name
+                ),
+            ));
+        }
+        if name == consts::DBUS_NAME {
+            return Err((
+                consts::DBUS_ERROR_INVALID_ARGS,
+                format!("Connection is not allowed to own the service \"{}\"", name),
+            ));
+        }
+        crate::params::validate_busname(&name).map_err(invalid_args)?;
+
+        let Some(queue) = self.names.get_mut(&name) else {
+            self.names.insert(name.clone(), vec![(id, flags)]);
+            self.owner_changed(&name, None, Some(id), signals);
+            return Ok(DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER);
+        };
+
+        let (owner, owner_flags) = queue[0];
+        if owner == id {
+            queue[0].1 = flags;
+            return Ok(DBUS_REQUEST_NAME_REPLY_ALREADY_OWNER);
+        }
+        // a new request replaces the queued one
+        queue.retain(|(queued, _)| *queued != id);
+        let replace = flags & DBUS_NAME_FLAG_REPLACE_EXISTING != 0
+            && owner_flags & DBUS_NAME_FLAG_ALLOW_REPLACEMENT != 0;
+        if replace {
+            queue.insert(0, (id, flags));
+            // the old owner stays in the queue unless it did not want to wait
+            if owner_flags & DBUS_NAME_FLAG_DO_NOT_QUEUE != 0 {
+                queue.remove(1);
+            }
+            self.owner_changed(&name, Some(owner), Some(id), signals);
+            Ok(DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER)
+        } else if flags & DBUS_NAME_FLAG_DO_NOT_QUEUE != 0 {
+            Ok(DBUS_REQUEST_NAME_REPLY_EXISTS)
+        } else {
+            queue.push((id, flags));
+            Ok(DBUS_REQUEST_NAME_REPLY_IN_QUEUE)
+        }
+    }
+
+    fn release_name(
+        &mut self,
+        id: u64,
+        name: &str,
+        signals: &mut Vec<(Option<u64>, MarshalledMessage)>,
+    ) -> Result<u32, BusError> {
+        if name.starts_with(':') || name == consts::DBUS_NAME {
+            return Err((
+                consts::DBUS_ERROR_INVALID_ARGS,
+                format!("Cannot release the name \"{}\"", name),
+            ));
+        }
+        let Some(queue) = self.names.get(name) else {
+            return Ok(DBUS_RELEASE_NAME_REPLY_NON_EXISTENT);
+        };
+        if !queue.iter().any(|(queued, _)| *queued == id) {
+            return Ok(DBUS_RELEASE_NAME_REPLY_NOT_OWNER);
+        }
+        self.remove_from_queue(id, name, signals);
+        Ok(DBUS_RELEASE_NAME_REPLY_RELEASED)
+    }
+
+    /// Remove the client from the queue of `name`, the next one in the queue becomes the owner if it was the owner
+    fn remove_from_queue(
+        &mut self,
+        id: u64,
+        name: &str,
+        signals: &mut Vec<(Option<u64>, MarshalledMessage)>,
+    ) {
+        let Some(queue) = self.names.get_mut(name) else {
+            return;
+        };
+        let was_owner = queue[0].0 == id;
+        queue.retain(|(queued, _)| *queued != id);
+        let new_owner = queue.first().map(|(owner, _)| *owner);
+        if new_owner.is_none() {
+            self.names.remove(name);
+        }
+        if was_owner {
+            self.owner_changed(name, Some(id), new_owner, signals);
+        }
+    }
+
+    fn owner_changed(
+        &self,
+        name: &str,
+        old: Option<u64>,
+        new: Option<u64>,
+        signals: &mut Vec<(Option<u64>, MarshalledMessage)>,
+    ) {
+        if old.is_some() {
+            signals.push((old, name_signal("NameLost", name)));
+        }
+        if new.is_some() {
+            signals.push((new, name_signal("NameAcquired", name)));
+        }
+        let (old, new) = (self.unique_name(old), self.unique_name(new));
+        signals.push((None, name_owner_changed(name, &old, &new)));
+    }
+
+    /// Remove the client, release its names and tell the other clients that it is gone
+    fn disconnect(&mut self, id: u64) {
+        let Some(client) = self.clients.get(&id) else {
+            return;
+        };
+        let (unique, registered) = (client.unique.clone(), client.registered);
+        let mut signals = Vec::new();
+        let names: Vec<String> = self
+            .names
+            .iter()
+            .filter(|(_, queue)| queue.iter().any(|(queued, _)| *queued == id))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in names {
+            self.remove_from_queue(id, &name, &mut signals);
+        }
+        self.clients.remove(&id);
+        if registered {
+            signals.push((None, name_owner_changed(&unique, &unique, "")));
+        }
+        // the signals to the client itself are not sent anymore
+        self.send_signals(signals);
+    }
+}
+
+fn is_hello(msg: &MarshalledMessage) -> bool {
+    msg.typ == MessageType::Call
+        && msg.dynheader.destination.as_deref() == Some(consts::DBUS_NAME)
+        && msg.dynheader.member.as_deref() == Some("Hello")
+        && msg
+            .dynheader
+            .interface
+            .as_deref()
+            .is_none_or(|interface| interface == consts::DBUS_INTERFACE)
+}
+
+fn bus_signal(member: &str) -> MarshalledMessage {
+    let mut signal = MessageBuilder::new()
+        .signal(consts::DBUS_INTERFACE, member, consts::DBUS_PATH)
+        .build();
+    signal.dynheader.sender = Some(consts::DBUS_NAME.to_owned());
+    signal
+}
+
+/// `NameAcquired` or `NameLost`
+fn name_signal(member: &str, name: &str) -> MarshalledMessage {
+    let mut signal = bus_signal(member);
+    signal.body.push_param(name).unwrap();
+    signal
+}
+
+fn name_owner_changed(name: &str, old: &str, new: &str) -> MarshalledMessage {
+    let mut signal = bus_signal("NameOwnerChanged");
+    signal.body.push_param3(name, old, new).unwrap();
+    signal
+}
+
+fn unknown_method(msg: &MarshalledMessage) -> BusError {
+    (
+        consts::DBUS_ERROR_UNKNOWN_METHOD,
+        format!(
+            "{}.{} with signature \"{}\" doesn't exist",
+            msg.dynheader
+                .interface
+                .as_deref()
+                .unwrap_or(consts::DBUS_INTERFACE),
+            msg.dynheader.member.as_deref().unwrap_or_default(),
+            msg.get_sig()
+        ),
+    )
+}
+
+fn no_owner(name: &str) -> BusError {
+    (
+        consts::DBUS_ERROR_NAME_HAS_NO_OWNER,
+        format!("Could not get owner of name '{}': no such name", name),
+    )
+}
+
+fn invalid_args<E: std::fmt::Display>(e: E) -> BusError {
+    (consts::DBUS_ERROR_INVALID_ARGS, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::standard_messages::RequestNameReply;
+
+    const NAME: &str = "io.killing.spark.TestBus";
+
+    fn bus_call(conn: &mut RpcConn, member: &str, name: &str) -> MarshalledMessage {
+        let mut call = MessageBuilder::new()
+            .call(member)
+            .on(consts::DBUS_PATH)
+            .with_interface(consts::DBUS_INTERFACE)
+            .at(consts::DBUS_NAME)
+            .build();
+        call.body.push_param(name).unwrap();
+        conn.call_method(&mut call, Timeout::Infinite).unwrap()
+    }
+
+    /// The next signal that is not the `NameAcquired` every client receives after its hello
+    fn next_signal(conn: &mut RpcConn) -> MarshalledMessage {
+        loop {
+            let signal = conn.wait_signal(Timeout::Infinite).unwrap();
+            if signal.dynheader.member.as_deref() != Some("NameAcquired") {
+                return signal;
+            }
+        }
+    }
+
+    fn name_owner(conn: &mut RpcConn, name: &str) -> Result<String, String> {
+        let reply = bus_call(conn, "GetNameOwner", name);
+        match reply.typ {
+            MessageType::Error => Err(reply.dynheader.error_name.unwrap()),
+            _ => Ok(reply.body.parser().get().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        let bus = TestBus::start().unwrap();
+        assert!(bus.address_string().starts_with("unix:path=/"));
+        let mut first = bus.connect().unwrap();
+        let mut second = bus.connect().unwrap();
+
+        assert_eq!(
+            first.request_name(NAME, 0, Timeout::Infinite).unwrap(),
+            RequestNameReply::PrimaryOwner
+        );
+        assert_eq!(
+            first.request_name(NAME, 0, Timeout::Infinite).unwrap(),
+            RequestNameReply::AlreadyOwner
+        );
+        assert!(matches!(
+            second.request_name(NAME, DBUS_NAME_FLAG_DO_NOT_QUEUE, Timeout::Infinite),
+            Err(Error::NameTaken)
+        ));
+        assert_eq!(
+            second.request_name(NAME, 0, Timeout::Infinite).unwrap(),
+            RequestNameReply::InQueue
+        );
+        let first_unique = name_owner(&mut second, NAME).unwrap();
+        assert!(first_unique.starts_with(":1."));
+
+        let names = second.list_names(Timeout::Infinite).unwrap();
+        let names: Vec<&str> = names.iter().unwrap().map(Result::unwrap).collect();
+        assert!(names.contains(&NAME));
+        assert!(names.contains(&first_unique.as_str()));
+        assert!(names.contains(&consts::DBUS_NAME));
+
+        // the queued connection takes over
+        assert_eq!(
+            first.release_name(NAME, Timeout::Infinite).unwrap(),
+            DBUS_RELEASE_NAME_REPLY_RELEASED
+        );
+        let second_unique = name_owner(&mut first, NAME).unwrap();
+        assert_ne!(second_unique, first_unique);
+        assert_eq!(
+            first.release_name(NAME, Timeout::Infinite).unwrap(),
+            DBUS_RELEASE_NAME_REPLY_NOT_OWNER
+        );
+
+        drop(second);
+        assert_eq!(
+            name_owner(&mut first, NAME),
+            Err(consts::DBUS_ERROR_NAME_HAS_NO_OWNER.to_owned())
+        );
+        assert_eq!(
+            name_owner(&mut first, &first_unique),
+            Ok(first_unique.clone())
+        );
+    }
+
+    #[test]
+    fn test_name_signals_are_addressed() {
+        let bus = TestBus::start().unwrap();
+        let mut first = bus.connect().unwrap();
+        let mut second = bus.connect().unwrap();
+        first.request_name(NAME, 0, Timeout::Infinite).unwrap();
+        second.request_name(NAME, 0, Timeout::Infinite).unwrap();
+        first.release_name(NAME, Timeout::Infinite).unwrap();
+
+        let acquired = first.wait_signal(Timeout::Infinite).unwrap();
+        assert_eq!(acquired.dynheader.member.as_deref(), Some("NameAcquired"));
+        let first_unique: String = acquired.body.parser().get().unwrap();
+        assert_eq!(acquired.dynheader.destination, Some(first_unique.clone()));
+        for member in ["NameAcquired", "NameLost"] {
+            let signal = first.wait_signal(Timeout::Infinite).unwrap();
+            assert_eq!(signal.dynheader.member.as_deref(), Some(member));
+            assert_eq!(signal.body.parser().get::<&str>().unwrap(), NAME);
+            assert_eq!(signal.dynheader.destination, Some(first_unique.clone()));
+        }
+
+        let acquired = second.wait_signal(Timeout::Infinite).unwrap();
+        let second_unique: String = acquired.body.parser().get().unwrap();
+        assert_eq!(acquired.dynheader.destination, Some(second_unique.clone()));
+        let acquired = second.wait_signal(Timeout::Infinite).unwrap();
+        assert_eq!(acquired.body.parser().get::<&str>().unwrap(), NAME);
+        assert_eq!(acquired.dynheader.destination, Some(second_unique));
+    }
+
+    #[test]
+    fn test_stalled_handshake() {
+        let bus = TestBus::start().unwrap();
+        // never sends the nul byte or AUTH
+        let _stalled = UnixStream::connect(bus.path()).unwrap();
+        let mut client = bus.connect().unwrap();
+        assert!(name_owner(&mut client, consts::DBUS_NAME).is_ok());
+    }
+
+    #[test]
+    fn test_routing() {
+        let bus = TestBus::start().unwrap();
+        let mut service = bus.connect().unwrap();
+        service.request_name(NAME, 0, Timeout::Infinite).unwrap();
+        let mut client = bus.connect().unwrap();
+
+        let mut call = MessageBuilder::new()
+            .call("Echo")
+            .on("/io/killing/spark")
+            .with_interface("io.killing.spark")
+            .at(NAME)
+            .build();
+        call.body.push_param("hello").unwrap();
+        let serial = client.send_message(&mut call).unwrap().write_all().unwrap();
+
+        let call = service.wait_call(Timeout::Infinite).unwrap();
+        assert_eq!(call.dynheader.serial, Some(serial));
+        assert_eq!(call.dynheader.member.as_deref(), Some("Echo"));
+        let mut reply = call.dynheader.make_response();
+        reply
+            .body
+            .push_param(call.body.parser().get::<&str>().unwrap())
+            .unwrap();
+        service
+            .send_message(&mut reply)
+            .unwrap()
+            .write_all()
+            .unwrap();
+
+        let reply = client.wait_response(serial, Timeout::Infinite).unwrap();
+        assert_eq!(reply.body.parser().get::<&str>().unwrap(), "hello");
+        assert_eq!(
+            reply.dynheader.sender,
+            Some(name_owner(&mut client, NAME).unwrap())
+        );
+
+        // nobody owns this name
+        let mut call = MessageBuilder::new()
+            .call("Echo")
+            .on("/io/killing/spark")
+            .at("io.killing.spark.Missing")
+            .build();
+        let reply = client.call_method(&mut call, Timeout::Infinite).unwrap();
+        assert_eq!(reply.typ, MessageType::Error);
+        assert_eq!(
+            reply.dynheader.error_name.as_deref(),
+            Some(consts::DBUS_ERROR_SERVICE_UNKNOWN)
+        );
+        let reply = bus_call(&mut client, "AddMatch", "type='signal");
+        assert_eq!(
+            reply.dynheader.error_name.as_deref(),
+            Some(consts::DBUS_ERROR_MATCH_RULE_INVALID)
+        );
+    }
+
+    #[test]
+    fn test_signals() {
+        let bus = TestBus::start().unwrap();
+        let mut service = bus.connect().unwrap();
+        service.request_name(NAME, 0, Timeout::Infinite).unwrap();
+        let mut listener = bus.connect().unwrap();
+        listener
+            .add_match(
+                MatchRule::new()
+                    .msg_type(MessageType::Signal)
+                    .sender(NAME)
+                    .interface("io.killing.spark"),
+                Timeout::Infinite,
+            )
+            .unwrap();
+        listener
+            .add_match(
+                MatchRule::new()
+                    .sender(consts::DBUS_NAME)
+                    .member("NameOwnerChanged")
+                    .arg(0, NAME)
+                    .unwrap(),
+                Timeout::Infinite,
+            )
+            .unwrap();
+
+        let mut other = MessageBuilder::new()
+            .signal("io.killing.spark.Other", "Ignored", "/")
+            .build();
+        service
+            .send_message(&mut other)
+            .unwrap()
+            .write_all()
+            .unwrap();
+        let mut signal = MessageBuilder::new()
+            .signal("io.killing.spark", "Changed", "/")
+            .build();
+        signal.body.push_param(5u32).unwrap();
+        service
+            .send_message(&mut signal)
+            .unwrap()
+            .write_all()
+            .unwrap();
+
+        let signal = next_signal(&mut listener);
+        assert_eq!(signal.dynheader.member.as_deref(), Some("Changed"));
+        assert_eq!(signal.body.parser().get::<u32>().unwrap(), 5);
+
+        let unique = name_owner(&mut listener, NAME).unwrap();
+        drop(service);
+        let signal = next_signal(&mut listener);
+        assert_eq!(signal.dynheader.member.as_deref(), Some("NameOwnerChanged"));
+        assert_eq!(
+            signal.body.parser().get3::<&str, &str, &str>().unwrap(),
+            (NAME, unique.as_str(), "")
+        );
+    }
+}
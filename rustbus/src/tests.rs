@@ -1,20 +1,41 @@
+#[cfg(rustbus_params)]
 use std::num::NonZeroU32;
 
+#[cfg(rustbus_params)]
 use crate::params::Base;
+#[cfg(rustbus_params)]
 use crate::params::Param;
+#[cfg(rustbus_params)]
 use crate::wire::marshal::marshal;
+#[cfg(rustbus_params)]
 use crate::wire::unmarshal::unmarshal_dynamic_header;
 use crate::wire::unmarshal::unmarshal_header;
+#[cfg(rustbus_params)]
 use crate::wire::unmarshal::unmarshal_next_message;
 use crate::wire::unmarshal_context::Cursor;
 
+mod adversarial;
+mod auto_traits;
+#[cfg(all(feature = "introspection", rustbus_params))]
+mod codegen;
+#[cfg(not(rustbus_loom))]
+mod concurrency;
+#[cfg(rustbus_params)]
 mod dbus_send;
 mod fdpassing;
+#[cfg(rustbus_loom)]
+mod loom;
+#[cfg(rustbus_params)]
+mod roundtrip;
+#[cfg(rustbus_params)]
 mod verify_marshalling;
+#[cfg(rustbus_params)]
 mod verify_padding;
 
 // this tests the happy path
 #[test]
+#[cfg(rustbus_params)]
+#[allow(clippy::vec_init_then_push)]
 fn test_marshal_unmarshal() {
     let mut params: Vec<Param> = Vec::new();
 
@@ -63,6 +84,7 @@ fn test_marshal_unmarshal() {
 
 // this tests that invalid inputs return appropriate errors
 #[test]
+#[cfg(rustbus_params)]
 fn test_invalid_stuff() {
     // invalid signature
     let mut msg = crate::message_builder::MessageBuilder::new()
@@ -125,3 +147,37 @@ fn test_invalid_stuff() {
         marshal(&msg, NonZeroU32::MIN, &mut buf)
     );
 }
+
+// this tests that lengths announced in a message are checked before they are used
+#[test]
+fn test_huge_lengths() {
+    use crate::wire::errors::UnmarshalError;
+    use crate::wire::unmarshal::calc_message_len;
+    use crate::ByteOrder;
+
+    let mut header_bytes = vec![b'l', 1, 0, 1];
+    header_bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+    header_bytes.extend_from_slice(&1u32.to_le_bytes());
+    let header = unmarshal_header(&mut Cursor::new(&header_bytes)).unwrap();
+
+    assert_eq!(
+        calc_message_len(&header, 0),
+        Err(UnmarshalError::MessageTooLarge)
+    );
+
+    let mut header = header;
+    header.body_len = 8;
+    assert_eq!(
+        calc_message_len(&header, u32::MAX),
+        Err(UnmarshalError::MessageTooLarge)
+    );
+    // 12 bytes fixed header + 4 bytes fields length + 10 bytes fields + 6 bytes padding + 8 bytes body
+    assert_eq!(calc_message_len(&header, 10), Ok(40));
+
+    let mut string_bytes = u32::MAX.to_le_bytes().to_vec();
+    string_bytes.extend_from_slice(b"abc\0");
+    assert_eq!(
+        Cursor::new(&string_bytes).read_str(ByteOrder::LittleEndian),
+        Err(UnmarshalError::NotEnoughBytes)
+    );
+}
@@ -0,0 +1,202 @@
+//! Feed hostile input into the public entry points. None of this has to produce anything useful, it just must not
+//! panic or hang.
+
+use std::io::{Read, Write};
+use std::num::NonZeroU32;
+use std::os::unix::net::UnixStream;
+
+use crate::auth;
+use crate::connection::ll_conn::DuplexConn;
+use crate::connection::Timeout;
+use crate::match_rule::MatchRule;
+use crate::message_builder::{MarshalledMessage, MarshalledMessageBody, MessageBuilder};
+use crate::signature::{SignatureIter, Type};
+use crate::wire::errors::UnmarshalError;
+use crate::wire::marshal::marshal;
+use crate::wire::unmarshal::{unmarshal_dynamic_header, unmarshal_header, unmarshal_next_message};
+use crate::wire::unmarshal_context::Cursor;
+use crate::wire::ObjectPath;
+use crate::ByteOrder;
+
+const SIGNATURES: &[&str] = &[
+    "", "(", ")", "{", "}", "a", "aa", "sa", "(ss", "ss)", "a{s}", "a{vs}", "{ss}", "()", "a()",
+    "ä", "aä", "(ä)", "\0", "z", "s\0s", "a{sa{sv}", ")))(((",
+];
+
+fn valid_message() -> Vec<u8> {
+    let mut msg = MessageBuilder::new()
+        .signal("io.killing.spark", "Adversarial", "/io/killing/spark")
+        .build();
+    msg.body
+        .push_param3(
+            "text",
+            vec![(1u8, 2u64)],
+            std::collections::HashMap::from([("k", 3u32)]),
+        )
+        .unwrap();
+    msg.body
+        .push_variant(ObjectPath::new("/a/b").unwrap())
+        .unwrap();
+    let mut buf = Vec::new();
+    marshal(&msg, NonZeroU32::MIN, &mut buf).unwrap();
+    buf.extend_from_slice(msg.get_buf());
+    buf
+}
+
+/// Run the whole unmarshalling pipeline and then poke the body the way a user would
+fn unmarshal_all_the_ways(data: &[u8]) {
+    let mut cursor = Cursor::new(data);
+    let Ok(header) = unmarshal_header(&mut cursor) else {
+        return;
+    };
+    let Ok(dynheader) = unmarshal_dynamic_header(&header, &mut cursor) else {
+        return;
+    };
+    let Ok(msg) =
+        unmarshal_next_message(&header, dynheader, data.to_vec(), cursor.consumed(), vec![])
+    else {
+        return;
+    };
+    poke_body(msg);
+}
+
+fn poke_body(msg: MarshalledMessage) {
+    let _ = MatchRule::new()
+        .arg(1, "x")
+        .and_then(|rule| rule.arg_path(2, "/"))
+        .unwrap()
+        .arg0_namespace("a")
+        .matches(&msg);
+    let _ = msg.body.validate();
+    let mut parser = msg.body.parser();
+    let _ = parser.sigs_left();
+    let _ = parser.get::<&str>();
+    let _ = parser.get::<Vec<(u8, u64)>>();
+    let _ = parser.get::<std::collections::HashMap<&str, u32>>();
+    let _ = parser.get2::<u32, crate::wire::unmarshal::traits::Variant>();
+    #[cfg(rustbus_params)]
+    {
+        let mut parser = msg.body.parser();
+        while parser.get_param().is_ok() {}
+        let _ = msg.unmarshall_all();
+    }
+}
+
+#[test]
+fn test_mangled_messages() {
+    let valid = valid_message();
+    unmarshal_all_the_ways(&valid);
+    for len in 0..valid.len() {
+        unmarshal_all_the_ways(&valid[..len]);
+    }
+    let mut mangled = valid.clone();
+    for idx in 0..valid.len() {
+        for byte in [0x00, 0x01, 0x7f, 0x80, 0xff, b'(', b'a', b'{'] {
+            mangled[idx] = byte;
+            unmarshal_all_the_ways(&mangled);
+        }
+        mangled[idx] = valid[idx];
+    }
+}
+
+#[test]
+fn test_reply_without_serial() {
+    let reply = MessageBuilder::new().call("Method").on("/").build();
+    let mut buf = Vec::new();
+    marshal(&reply, NonZeroU32::MIN, &mut buf).unwrap();
+    // turn the call into a reply that does not say what it replies to
+    buf[1] = 2;
+    let mut cursor = Cursor::new(&buf);
+    let header = unmarshal_header(&mut cursor).unwrap();
+    assert_eq!(
+        unmarshal_dynamic_header(&header, &mut cursor).unwrap_err(),
+        UnmarshalError::InvalidHeaderFields
+    );
+}
+
+#[test]
+fn test_invalid_body_signatures() {
+    for sig in SIGNATURES {
+        let _ = Type::parse_description(sig);
+        let _ = SignatureIter::new(sig).count();
+        let _ = crate::params::validate_signature(sig);
+
+        let body = MarshalledMessageBody::from_parts(
+            vec![1, 0, 0, 0, b'a', 0],
+            0,
+            vec![],
+            sig.to_string(),
+            ByteOrder::LittleEndian,
+        );
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Sig", "/")
+            .build();
+        msg.body = body;
+        poke_body(msg);
+    }
+}
+
+#[test]
+fn test_recv_garbage() {
+    let garbage: &[&[u8]] = &[
+        b"l\x02\x01\x01\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00",
+        &[0xff; 64],
+        &[b'l'; 1024],
+        b"B\x01\x00\x01\xff\xff\xff\xff\x01\x00\x00\x00\xff\xff\xff\xff",
+    ];
+    for data in garbage {
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        let mut conn = DuplexConn::from_stream(stream).unwrap();
+        peer.write_all(data).unwrap();
+        drop(peer);
+        assert!(conn
+            .recv
+            .get_next_message(Timeout::Duration(std::time::Duration::from_secs(5)))
+            .is_err());
+    }
+}
+
+/// Reads the initial null byte and the AUTH line, then answers with `answer`
+fn fake_auth_server(answer: &'static [u8]) -> (UnixStream, std::thread::JoinHandle<()>) {
+    let (client, mut server) = UnixStream::pair().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        let mut read = Vec::new();
+        while !read.ends_with(b"\r\n") {
+            match server.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => read.extend_from_slice(&buf[..n]),
+            }
+        }
+        // the client gives up before reading everything of an endless answer
+        let _ = server.write_all(answer);
+    });
+    (client, handle)
+}
+
+#[test]
+fn test_auth_garbage() {
+    let (mut client, server) = fake_auth_server(b"");
+    let err = auth::do_auth(&mut client).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    server.join().unwrap();
+
+    let (mut client, server) = fake_auth_server(b"OK \xff\xfe\r\n");
+    let err = auth::do_auth(&mut client).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    server.join().unwrap();
+
+    static ENDLESS: [u8; 64 * 1024] = [b'O'; 64 * 1024];
+    let (mut client, server) = fake_auth_server(&ENDLESS);
+    let err = auth::do_auth(&mut client).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    drop(client);
+    server.join().unwrap();
+
+    let (mut client, server) = fake_auth_server(b"REJECTED EXTERNAL\r\n");
+    assert!(matches!(
+        auth::do_auth(&mut client).unwrap(),
+        auth::AuthResult::Rejected
+    ));
+    server.join().unwrap();
+}
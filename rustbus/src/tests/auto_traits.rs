@@ -0,0 +1,63 @@
+//! Compile time checks for the Send and Sync impls of public types. Losing one of these is a breaking change
+//! for users that move these types between threads, so it should not happen by accident.
+
+use crate::connection::dispatch_conn::TimerEnvironment;
+use crate::connection::ll_conn::{DuplexConn, RecvConn, SendConn, SendMessageContext};
+use crate::connection::middleware::MiddlewareChain;
+use crate::connection::pooled_dispatch::{PooledDispatchConn, PooledEnvironment};
+use crate::connection::rpc_conn::{PendingReply, RpcConn, Subscription};
+use crate::message_builder::{
+    DynamicHeader, MarshalledMessage, MarshalledMessageBody, MessageBodyParser, MessageBuilder,
+};
+use crate::wire::{LargeData, UnixFd};
+
+fn is_send<T: Send>() {}
+fn is_sync<T: Sync>() {}
+
+#[test]
+fn messages_are_send_and_sync() {
+    is_send::<MarshalledMessage>();
+    is_sync::<MarshalledMessage>();
+    is_send::<MarshalledMessageBody>();
+    is_sync::<MarshalledMessageBody>();
+    is_send::<MessageBodyParser<'_>>();
+    is_sync::<MessageBodyParser<'_>>();
+    is_send::<DynamicHeader>();
+    is_sync::<DynamicHeader>();
+    is_send::<MessageBuilder>();
+    is_sync::<MessageBuilder>();
+    is_send::<UnixFd>();
+    is_sync::<UnixFd>();
+    is_send::<LargeData>();
+    is_sync::<LargeData>();
+    #[cfg(rustbus_params)]
+    {
+        is_send::<crate::params::Param<'_, '_>>();
+        is_sync::<crate::params::Param<'_, '_>>();
+        is_send::<crate::params::message::Message<'_, '_>>();
+        is_sync::<crate::params::message::Message<'_, '_>>();
+    }
+}
+
+#[test]
+fn connections_are_send() {
+    is_send::<SendConn>();
+    is_sync::<SendConn>();
+    is_send::<RecvConn>();
+    is_sync::<RecvConn>();
+    is_send::<DuplexConn>();
+    is_sync::<DuplexConn>();
+    is_send::<SendMessageContext<'_>>();
+    is_send::<MiddlewareChain>();
+    is_send::<RpcConn>();
+    is_send::<PendingReply>();
+    is_sync::<PendingReply>();
+    is_send::<Subscription>();
+    is_sync::<Subscription>();
+    // DispatchConn, its PathMatcher and the HandleEnvironment are not Send because handlers do not have to be
+    is_send::<TimerEnvironment>();
+    is_send::<PooledDispatchConn<(), ()>>();
+    is_send::<PooledEnvironment>();
+    #[cfg(all(feature = "introspection", rustbus_params))]
+    is_send::<crate::connection::dynamic_proxy::DynamicProxy<'_>>();
+}
@@ -0,0 +1,125 @@
+use crate::codegen::ProxyGenerator;
+use crate::connection::ll_conn::{force_finish_on_error, DuplexConn};
+use crate::connection::proxy::CallError;
+use crate::connection::Timeout;
+use crate::message_builder::ErrorReply;
+use crate::RpcConn;
+
+// not everything the generator emits is used by the tests
+#[allow(dead_code)]
+mod generated {
+    include!("codegen_proxy.rs");
+}
+
+const XML: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="io.killing.spark.Settings">
+    <method name="GetIPAddress">
+      <arg name="device" type="s" direction="in"/>
+      <arg name="address" type="s" direction="out"/>
+    </method>
+    <method name="SetValues">
+      <arg name="type" type="u" direction="in"/>
+      <arg name="values" type="a{sv}" direction="in"/>
+      <arg type="ao" direction="in"/>
+    </method>
+    <method name="Stats">
+      <arg type="(ut)" direction="out"/>
+      <arg name="per_device" type="a{s(ut)}" direction="out"/>
+    </method>
+    <method name="TooWide">
+      <arg type="(yyyyy)" direction="out"/>
+    </method>
+    <signal name="Changed">
+      <arg type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>"#;
+
+fn generate() -> String {
+    ProxyGenerator::new()
+        .crate_path("crate")
+        .generate(XML)
+        .unwrap()
+}
+
+#[test]
+fn test_generated_code_is_up_to_date() {
+    // after changing the generator print the new code with
+    // cargo test --features introspection print_generated_code -- --ignored --nocapture
+    assert_eq!(generate(), include_str!("codegen_proxy.rs"));
+}
+
+#[test]
+#[ignore]
+fn print_generated_code() {
+    print!("{}", generate());
+}
+
+#[test]
+fn test_generated_proxy() {
+    let (conn, mut peer) = DuplexConn::pair().unwrap();
+    let service = std::thread::spawn(move || {
+        for _ in 0..3 {
+            let call = peer.recv.get_next_message(Timeout::Infinite).unwrap();
+            assert_eq!(
+                call.dynheader.interface.as_deref(),
+                Some("io.killing.spark.Settings")
+            );
+            assert_eq!(call.dynheader.object.as_deref(), Some("/io/killing/spark"));
+            assert_eq!(
+                call.dynheader.destination.as_deref(),
+                Some("io.killing.spark")
+            );
+            let mut reply = call.dynheader.make_response();
+            match call.dynheader.member.as_deref().unwrap() {
+                "GetIPAddress" => {
+                    assert_eq!(call.body.parser().get::<&str>().unwrap(), "eth0");
+                    reply.body.push_param("10.0.0.1").unwrap();
+                }
+                "SetValues" => {
+                    assert_eq!(call.get_sig(), "ua{sv}ao");
+                    reply = call.dynheader.make_error_response(
+                        "io.killing.spark.Error.ReadOnly",
+                        Some("nope".to_owned()),
+                    );
+                }
+                "Stats" => {
+                    let mut per_device = std::collections::HashMap::new();
+                    per_device.insert("eth0", (2u32, 20u64));
+                    reply.body.push_param2((3u32, 30u64), per_device).unwrap();
+                }
+                member => panic!("unexpected call {}", member),
+            }
+            peer.send
+                .send_message(&reply)
+                .unwrap()
+                .write_all()
+                .map_err(force_finish_on_error)
+                .unwrap();
+        }
+    });
+
+    let mut rpc_con = RpcConn::new(conn);
+    let mut proxy =
+        generated::SettingsProxy::new(&mut rpc_con, "io.killing.spark", "/io/killing/spark");
+    assert_eq!(proxy.get_ip_address("eth0").unwrap(), "10.0.0.1");
+
+    let values = std::collections::HashMap::new();
+    match proxy.set_values(1, &values, &[]) {
+        Err(CallError::ErrorReply(ErrorReply { name, message })) => {
+            assert_eq!(name, "io.killing.spark.Error.ReadOnly");
+            assert_eq!(message.as_deref(), Some("nope"));
+        }
+        other => panic!("expected an error reply but got {:?}", other),
+    }
+
+    let (total, per_device) = proxy.stats().unwrap();
+    assert_eq!(total, (3, 30));
+    assert_eq!(per_device["eth0"], (2, 20));
+    service.join().unwrap();
+}
@@ -0,0 +1,110 @@
+// generated by rustbus::codegen, do not edit
+
+/// Calls methods of the `io.killing.spark.Settings` interface
+pub struct SettingsProxy<'a> {
+    conn: &'a mut crate::RpcConn,
+    destination: String,
+    object: String,
+    timeout: crate::connection::Timeout,
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+impl<'a> SettingsProxy<'a> {
+    pub const INTERFACE: &'static str = "io.killing.spark.Settings";
+
+    /// Create a proxy for `object` at `destination`. Calls wait for their reply without a timeout until `set_timeout` is called.
+    pub fn new<D: Into<String>, O: Into<String>>(conn: &'a mut crate::RpcConn, destination: D, object: O) -> Self {
+        SettingsProxy {
+            conn,
+            destination: destination.into(),
+            object: object.into(),
+            timeout: crate::connection::Timeout::Infinite,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: crate::connection::Timeout) {
+        self.timeout = timeout;
+    }
+
+    /// Calls `io.killing.spark.Settings.GetIPAddress`
+    pub fn get_ip_address(&mut self, device: &str) -> Result<String, crate::connection::proxy::CallError> {
+        let mut call = crate::MessageBuilder::new()
+            .call("GetIPAddress")
+            .with_interface(Self::INTERFACE)
+            .on(self.object.clone())
+            .at(self.destination.clone())
+            .build();
+        call.body.push_param(device)?;
+        let reply = crate::connection::proxy::call(self.conn, &mut call, self.timeout)?;
+        Ok(reply.body.parser().get()?)
+    }
+
+    /// Calls `io.killing.spark.Settings.SetValues`
+    pub fn set_values(&mut self, type_: u32, values: &std::collections::HashMap<String, crate::params::Variant<'_, '_>>, arg2: &[crate::wire::ObjectPath<String>]) -> Result<(), crate::connection::proxy::CallError> {
+        let mut call = crate::MessageBuilder::new()
+            .call("SetValues")
+            .with_interface(Self::INTERFACE)
+            .on(self.object.clone())
+            .at(self.destination.clone())
+            .build();
+        call.body.push_param(type_)?;
+        call.body.push_param(values)?;
+        call.body.push_param(arg2)?;
+        crate::connection::proxy::call(self.conn, &mut call, self.timeout)?;
+        Ok(())
+    }
+
+    /// Calls `io.killing.spark.Settings.Stats`
+    pub fn stats(&mut self) -> Result<((u32, u64), std::collections::HashMap<String, (u32, u64)>), crate::connection::proxy::CallError> {
+        let mut call = crate::MessageBuilder::new()
+            .call("Stats")
+            .with_interface(Self::INTERFACE)
+            .on(self.object.clone())
+            .at(self.destination.clone())
+            .build();
+        let reply = crate::connection::proxy::call(self.conn, &mut call, self.timeout)?;
+        let mut parser = reply.body.parser();
+        Ok((parser.get()?, parser.get()?))
+    }
+
+    // TooWide is not generated: structs with more than four fields are not supported
+}
+
+/// Calls methods of the `org.freedesktop.DBus.Peer` interface
+pub struct PeerProxy<'a> {
+    conn: &'a mut crate::RpcConn,
+    destination: String,
+    object: String,
+    timeout: crate::connection::Timeout,
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+impl<'a> PeerProxy<'a> {
+    pub const INTERFACE: &'static str = "org.freedesktop.DBus.Peer";
+
+    /// Create a proxy for `object` at `destination`. Calls wait for their reply without a timeout until `set_timeout` is called.
+    pub fn new<D: Into<String>, O: Into<String>>(conn: &'a mut crate::RpcConn, destination: D, object: O) -> Self {
+        PeerProxy {
+            conn,
+            destination: destination.into(),
+            object: object.into(),
+            timeout: crate::connection::Timeout::Infinite,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: crate::connection::Timeout) {
+        self.timeout = timeout;
+    }
+
+    /// Calls `org.freedesktop.DBus.Peer.Ping`
+    pub fn ping(&mut self) -> Result<(), crate::connection::proxy::CallError> {
+        let mut call = crate::MessageBuilder::new()
+            .call("Ping")
+            .with_interface(Self::INTERFACE)
+            .on(self.object.clone())
+            .at(self.destination.clone())
+            .build();
+        crate::connection::proxy::call(self.conn, &mut call, self.timeout)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Tests for the state that is shared between threads. They run many interleavings with real threads,
+//! a barrier makes sure the threads actually start racing at the same time.
+
+use crate::connection::middleware::{IncomingAction, Middleware, MiddlewareChain};
+use crate::message_builder::{MarshalledMessage, MarshalledMessageBody, MessageBuilder};
+use crate::sync::{Arc, Mutex};
+use std::sync::Barrier;
+
+const NUM_THREADS: usize = 8;
+const NUM_RUNS: usize = 50;
+
+/// Run `f` on NUM_THREADS threads at once and wait for all of them to finish
+fn race<F: Fn(usize) + Send + Sync + 'static>(f: F) {
+    let f = Arc::new(f);
+    let barrier = Arc::new(Barrier::new(NUM_THREADS));
+    let threads = (0..NUM_THREADS)
+        .map(|idx| {
+            let f = f.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                f(idx)
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn shared_body_buffer() {
+    let mut template = MarshalledMessageBody::new();
+    template.push_param2(42u64, "shared").unwrap();
+    let (buf, sig) = template.make_shared().unwrap();
+    let byteorder = template.byteorder();
+
+    for _ in 0..NUM_RUNS {
+        let buf = buf.clone();
+        let sig = sig.clone();
+        race(move |idx| {
+            let mut body = MarshalledMessageBody::from_shared(buf.clone(), sig.clone(), byteorder);
+            // half of the threads modify their body which must not affect the others
+            if idx % 2 == 0 {
+                body.push_param(idx as u32).unwrap();
+            }
+            assert_eq!(body.parser().get2::<u64, &str>(), Ok((42, "shared")));
+        });
+    }
+    // only the template and this test hold on to the buffer after all bodies are dropped
+    assert_eq!(Arc::strong_count(&buf), 2);
+}
+
+#[test]
+fn middleware_chain_behind_mutex() {
+    struct Counter(Arc<Mutex<usize>>);
+    impl Middleware for Counter {
+        fn outgoing(
+            &mut self,
+            _msg: &mut MarshalledMessage,
+        ) -> Result<(), crate::connection::Error> {
+            *self.0.lock().unwrap() += 1;
+            Ok(())
+        }
+        fn incoming(&mut self, _msg: &mut MarshalledMessage) -> IncomingAction {
+            *self.0.lock().unwrap() += 1;
+            IncomingAction::Continue
+        }
+    }
+
+    let count = Arc::new(Mutex::new(0));
+    let mut chain = MiddlewareChain::new();
+    chain.push(Box::new(Counter(count.clone())));
+    chain.push(Box::new(Counter(count.clone())));
+    let chain = Arc::new(Mutex::new(chain));
+
+    for _ in 0..NUM_RUNS {
+        let chain = chain.clone();
+        race(move |_| {
+            let mut msg = MessageBuilder::new().call("Ping").on("/").build();
+            let mut chain = chain.lock().unwrap();
+            chain.outgoing(&mut msg).unwrap();
+            chain.incoming(&mut msg);
+        });
+    }
+    assert_eq!(*count.lock().unwrap(), NUM_RUNS * NUM_THREADS * 4);
+}
+
+#[cfg(feature = "mock-fds")]
+#[test]
+fn unixfd_clones_close_once() {
+    use crate::wire::mock_fds;
+
+    for _ in 0..NUM_RUNS {
+        let fd = mock_fds::new_mock_fd();
+        let raw_fd = fd.get_raw_fd().unwrap();
+        let taken = Arc::new(Mutex::new(Vec::new()));
+
+        let shared = fd.clone();
+        let taken_by_threads = taken.clone();
+        race(move |idx| {
+            let local = shared.clone();
+            match idx % 3 {
+                0 => {
+                    if let Some(raw) = local.take_raw_fd() {
+                        taken_by_threads.lock().unwrap().push(raw);
+                    }
+                }
+                1 => {
+                    // dups of a taken fd fail, all others must refer to the same file
+                    if let Ok(dupped) = local.dup() {
+                        let dupped_raw = dupped.get_raw_fd().unwrap();
+                        assert!(mock_fds::is_open(dupped_raw));
+                        drop(dupped);
+                        assert!(!mock_fds::is_open(dupped_raw));
+                    }
+                }
+                _ => drop(local),
+            }
+        });
+
+        // exactly one thread got the fd, so it is still open and owned by that thread now
+        assert_eq!(*taken.lock().unwrap(), [raw_fd]);
+        assert!(fd.get_raw_fd().is_none());
+        drop(fd);
+        assert!(mock_fds::is_open(raw_fd));
+        drop(crate::wire::UnixFd::new(raw_fd));
+        assert!(!mock_fds::is_open(raw_fd));
+    }
+}
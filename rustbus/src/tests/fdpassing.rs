@@ -9,26 +9,14 @@ const TEST_STRING: &str = "This will be sent over the fd\n";
 
 #[test]
 fn test_fd_passing() {
-    let Ok(mut con1) = connection::rpc_conn::RpcConn::system_conn(connection::Timeout::Infinite)
-    else {
-        return;
-    };
-    let mut con2 =
-        connection::rpc_conn::RpcConn::system_conn(connection::Timeout::Infinite).unwrap();
-    con1.send_message(&mut crate::standard_messages::hello())
-        .unwrap()
-        .write_all()
-        .unwrap();
-    con2.send_message(&mut crate::standard_messages::hello())
-        .unwrap()
-        .write_all()
-        .unwrap();
-    con2.send_message(&mut crate::standard_messages::add_match("type='signal'"))
-        .unwrap()
-        .write_all()
-        .unwrap();
-
-    std::thread::sleep(std::time::Duration::from_secs(1));
+    let bus = crate::testing::TestBus::start().unwrap();
+    let mut con1 = bus.connect().unwrap();
+    let mut con2 = bus.connect().unwrap();
+    con2.add_match(
+        crate::match_rule::MatchRule::new().msg_type(crate::MessageType::Signal),
+        connection::Timeout::Infinite,
+    )
+    .unwrap();
 
     let rw = nix::unistd::pipe().unwrap();
     let mut readfile = std::fs::File::from(rw.0);
@@ -68,8 +56,6 @@ fn send_fd(
         .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
         .build();
 
-    sig.dynheader.num_fds = Some(1);
-
     sig.body.push_param(fd).unwrap();
 
     con.send_message(&mut sig)?
@@ -89,6 +75,7 @@ fn send_fd(
 }
 
 #[test]
+#[cfg(rustbus_params)]
 fn test_fd_marshalling() {
     use crate::wire::UnixFd;
     let test_fd1: UnixFd = UnixFd::new(nix::unistd::dup(0).unwrap());
@@ -118,12 +105,10 @@ fn test_fd_marshalling() {
     let mut parser = sig.body.parser();
     let _fd1: crate::wire::UnixFd = parser.get().unwrap();
     // get _fd2
-    assert!(match parser.get_param().unwrap() {
-        crate::params::Param::Base(crate::params::Base::UnixFd(_fd)) => {
-            true
-        }
-        _ => false,
-    });
+    assert!(matches!(
+        parser.get_param().unwrap(),
+        crate::params::Param::Base(crate::params::Base::UnixFd(_fd))
+    ));
     let _fd3: crate::wire::UnixFd = parser.get().unwrap();
 
     // Take all fds back to prevent accidental closing of actual FDs
@@ -131,3 +116,77 @@ fn test_fd_marshalling() {
     test_fd2.take_raw_fd().unwrap();
     test_fd3.take_raw_fd().unwrap();
 }
+
+#[cfg(feature = "mock-fds")]
+#[test]
+fn test_fd_marshalling_mocked() {
+    use crate::wire::mock_fds;
+    use crate::wire::UnixFd;
+    let test_fd = mock_fds::new_mock_fd();
+    let raw_fd = test_fd.get_raw_fd().unwrap();
+
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(&test_fd).unwrap();
+
+    // the message holds a dup of the original fd
+    let fd_in_msg = sig.body.get_fds()[0].get_raw_fd().unwrap();
+    assert_ne!(fd_in_msg, raw_fd);
+    assert!(mock_fds::is_same_file(fd_in_msg, raw_fd));
+
+    let unmarshalled: UnixFd = sig.body.parser().get().unwrap();
+    assert_eq!(unmarshalled.get_raw_fd(), Some(fd_in_msg));
+    drop(unmarshalled);
+
+    // dropping the message closes its dup, but not the original
+    drop(sig);
+    assert!(!mock_fds::is_open(fd_in_msg));
+    assert!(mock_fds::is_open(raw_fd));
+    drop(test_fd);
+    assert!(!mock_fds::is_open(raw_fd));
+}
+
+#[test]
+fn test_num_fds_header() {
+    use crate::wire::errors::MarshalError;
+    use crate::wire::unmarshal::{unmarshal_dynamic_header, unmarshal_header};
+    use crate::wire::unmarshal_context::Cursor;
+    use crate::wire::UnixFd;
+    use std::num::NonZeroU32;
+
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body
+        .push_param(UnixFd::new(nix::unistd::dup(1).unwrap()))
+        .unwrap();
+    sig.body
+        .push_param(UnixFd::new(nix::unistd::dup(1).unwrap()))
+        .unwrap();
+
+    // the header field is taken from the body without having to set it
+    let mut buf = Vec::new();
+    crate::wire::marshal::marshal(&sig, NonZeroU32::MIN, &mut buf).unwrap();
+    let mut cursor = Cursor::new(&buf);
+    let header = unmarshal_header(&mut cursor).unwrap();
+    let dynheader = unmarshal_dynamic_header(&header, &mut cursor).unwrap();
+    assert_eq!(dynheader.num_fds, Some(2));
+
+    sig.dynheader.num_fds = Some(1);
+    assert!(matches!(
+        crate::wire::marshal::marshal(&sig, NonZeroU32::MIN, &mut Vec::new()),
+        Err(MarshalError::NumFdsMismatch { header: 1, body: 2 })
+    ));
+
+    // resetting the body also drops the fds it referenced
+    sig.dynheader.num_fds = None;
+    sig.body.reset();
+    assert!(sig.body.get_fds().is_empty());
+    let mut buf = Vec::new();
+    crate::wire::marshal::marshal(&sig, NonZeroU32::MIN, &mut buf).unwrap();
+    let mut cursor = Cursor::new(&buf);
+    let header = unmarshal_header(&mut cursor).unwrap();
+    let dynheader = unmarshal_dynamic_header(&header, &mut cursor).unwrap();
+    assert_eq!(dynheader.num_fds, None);
+}
@@ -0,0 +1,55 @@
+//! Model checked tests for the state that is shared between threads, see `crate::sync`. They only exist when compiled
+//! with `--cfg rustbus_loom`, the threaded tests in `concurrency` cover the same state with real threads otherwise.
+
+use crate::sync::thread;
+use crate::wire::UnixFd;
+use std::os::fd::IntoRawFd;
+
+/// Whether `fd` is still an open file descriptor of this process
+fn is_open(fd: i32) -> bool {
+    nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_GETFD).is_ok()
+}
+
+#[test]
+fn loom_unixfd_take_races_with_dup_and_drop() {
+    loom::model(|| {
+        let (read, write) = nix::unistd::pipe().unwrap();
+        drop(write);
+        let fd = UnixFd::new(read.into_raw_fd());
+        let raw = fd.get_raw_fd().unwrap();
+
+        let clone = fd.clone();
+        let taker = thread::spawn(move || clone.take_raw_fd());
+        // a dup either happens before the fd was taken or fails
+        let dupped = fd.dup();
+        drop(fd);
+
+        assert_eq!(taker.join().unwrap(), Some(raw));
+        // dropping the last UnixFd must not close the fd that was taken
+        assert!(is_open(raw));
+        if let Ok(dupped) = dupped {
+            let dupped_raw = dupped.get_raw_fd().unwrap();
+            assert_ne!(dupped_raw, raw);
+            drop(dupped);
+            assert!(!is_open(dupped_raw));
+        }
+        nix::unistd::close(raw).unwrap();
+    });
+}
+
+#[test]
+fn loom_unixfd_clones_close_once() {
+    loom::model(|| {
+        let (read, write) = nix::unistd::pipe().unwrap();
+        drop(write);
+        let fd = UnixFd::new(read.into_raw_fd());
+        let raw = fd.get_raw_fd().unwrap();
+
+        let clone = fd.clone();
+        let dropper = thread::spawn(move || drop(clone));
+        drop(fd);
+        dropper.join().unwrap();
+        // whichever clone is dropped last closes the fd
+        assert!(!is_open(raw));
+    });
+}
@@ -0,0 +1,284 @@
+//! Round trips of random values and of the vendored message corpus through marshalling and unmarshalling
+//!
+//! The random values are generated for random signatures and marshalled after a random number of bytes, so the
+//! padding in front of and between values of mixed alignment (e.g. in arrays of structs) is exercised at every offset.
+//! Dicts are unmarshalled into HashMaps, which do not keep the order of the entries, so bodies containing dicts are only
+//! compared by their values and not byte for byte. Even their length can change, the padding between the entries
+//! depends on their order.
+
+use crate::message_builder::{MarshalledMessage, MessageBuilder};
+use crate::params::{Array, Base, Container, Dict, Param, Variant};
+use crate::signature;
+use crate::wire::gvariant;
+use crate::wire::unmarshal::{unmarshal_dynamic_header, unmarshal_header, unmarshal_next_message};
+use crate::wire::unmarshal_context::Cursor;
+use crate::ByteOrder;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+fn arb_base_sig() -> impl Strategy<Value = signature::Base> {
+    use signature::Base::*;
+    // UnixFds would need real fds to be passed along
+    prop_oneof![
+        Just(Byte),
+        Just(Int16),
+        Just(Uint16),
+        Just(Int32),
+        Just(Uint32),
+        Just(Int64),
+        Just(Uint64),
+        Just(Double),
+        Just(String),
+        Just(Signature),
+        Just(ObjectPath),
+        Just(Boolean),
+    ]
+}
+
+fn arb_sig() -> impl Strategy<Value = signature::Type> {
+    use signature::{Container as C, Type as T};
+    arb_base_sig()
+        .prop_map(T::Base)
+        .prop_recursive(4, 16, 4, |inner| {
+            prop_oneof![
+                inner
+                    .clone()
+                    .prop_map(|elem| T::Container(C::Array(Box::new(elem)))),
+                vec(inner.clone(), 1..4).prop_map(|types| T::Container(C::Struct(
+                    signature::StructTypes::new(types).unwrap()
+                ))),
+                (arb_base_sig(), inner)
+                    .prop_map(|(key, value)| T::Container(C::Dict(key, Box::new(value)))),
+                Just(T::Container(C::Variant)),
+            ]
+        })
+}
+
+fn arb_base(sig: signature::Base) -> BoxedStrategy<Base<'static>> {
+    use signature::Base as S;
+    match sig {
+        S::Byte => any::<u8>().prop_map(Base::Byte).boxed(),
+        S::Int16 => any::<i16>().prop_map(Base::Int16).boxed(),
+        S::Uint16 => any::<u16>().prop_map(Base::Uint16).boxed(),
+        S::Int32 => any::<i32>().prop_map(Base::Int32).boxed(),
+        S::Uint32 => any::<u32>().prop_map(Base::Uint32).boxed(),
+        S::Int64 => any::<i64>().prop_map(Base::Int64).boxed(),
+        S::Uint64 => any::<u64>().prop_map(Base::Uint64).boxed(),
+        S::Double => any::<u64>().prop_map(Base::Double).boxed(),
+        S::Boolean => any::<bool>().prop_map(Base::Boolean).boxed(),
+        S::String => "[a-zA-Z0-9 äöß]{0,10}".prop_map(Base::String).boxed(),
+        S::ObjectPath => "/|(/[A-Za-z0-9_]{1,5}){1,3}"
+            .prop_map(Base::ObjectPath)
+            .boxed(),
+        S::Signature => vec(arb_sig(), 0..3)
+            .prop_map(|types| {
+                let mut sig = std::string::String::new();
+                for typ in types {
+                    typ.to_str(&mut sig);
+                }
+                Base::Signature(sig)
+            })
+            .boxed(),
+        S::UnixFd => unreachable!("no UnixFds are generated"),
+    }
+}
+
+/// A value of type `sig`. Variants contain a value of a new random type, `variant_depth` limits how deep they nest.
+fn arb_param(sig: &signature::Type, variant_depth: u32) -> BoxedStrategy<Param<'static, 'static>> {
+    use signature::{Container as C, Type as T};
+    match sig {
+        T::Base(base) => arb_base(*base).prop_map(Param::Base).boxed(),
+        T::Container(C::Array(elem)) => {
+            let element_sig = (**elem).clone();
+            vec(arb_param(elem, variant_depth), 0..4)
+                .prop_map(move |values| {
+                    Param::Container(Container::Array(Array {
+                        element_sig: element_sig.clone(),
+                        values,
+                    }))
+                })
+                .boxed()
+        }
+        T::Container(C::Struct(types)) => types
+            .as_ref()
+            .iter()
+            .map(|typ| arb_param(typ, variant_depth))
+            .collect::<Vec<_>>()
+            .prop_map(|fields| Param::Container(Container::Struct(fields)))
+            .boxed(),
+        T::Container(C::Dict(key, value)) => {
+            let (key_sig, value_sig) = (*key, (**value).clone());
+            vec((arb_base(*key), arb_param(value, variant_depth)), 0..4)
+                .prop_map(move |entries| {
+                    Param::Container(Container::Dict(Dict {
+                        key_sig,
+                        value_sig: value_sig.clone(),
+                        map: entries.into_iter().collect(),
+                    }))
+                })
+                .boxed()
+        }
+        T::Container(C::Variant) => {
+            let inner = if variant_depth == 0 {
+                arb_base_sig().prop_map(T::Base).boxed()
+            } else {
+                arb_sig().boxed()
+            };
+            inner
+                .prop_flat_map(move |sig| {
+                    arb_param(&sig, variant_depth.saturating_sub(1)).prop_map(move |value| {
+                        Param::Container(Container::Variant(Box::new(Variant {
+                            sig: sig.clone(),
+                            value,
+                        })))
+                    })
+                })
+                .boxed()
+        }
+    }
+}
+
+/// A few bytes to shift the alignment of the following values and the values themselves
+fn arb_body() -> impl Strategy<Value = (Vec<u8>, Vec<Param<'static, 'static>>)> {
+    let params = vec(arb_sig(), 1..4)
+        .prop_flat_map(|sigs| sigs.iter().map(|sig| arb_param(sig, 2)).collect::<Vec<_>>());
+    (vec(any::<u8>(), 0..8), params)
+}
+
+fn parse_message(bytes: &[u8]) -> MarshalledMessage {
+    let mut cursor = Cursor::new(bytes);
+    let header = unmarshal_header(&mut cursor).unwrap();
+    let dynheader = unmarshal_dynamic_header(&header, &mut cursor).unwrap();
+    unmarshal_next_message(
+        &header,
+        dynheader,
+        bytes.to_vec(),
+        cursor.consumed(),
+        vec![],
+    )
+    .unwrap()
+}
+
+/// Unmarshal the body of `msg` and marshal it again, the result has to be the same. Returns the unmarshalled params.
+fn assert_roundtrip(msg: MarshalledMessage) -> Vec<Param<'static, 'static>> {
+    msg.body.validate().unwrap();
+    let byteorder = msg.body.byteorder();
+    let sig = msg.get_sig().to_owned();
+    let buf = msg.get_buf().to_vec();
+    let params = msg.unmarshall_all().unwrap().params;
+
+    let mut again = MessageBuilder::with_byteorder(byteorder)
+        .signal("io.killing.spark", "Again", "/")
+        .build();
+    again.body.push_old_params(&params).unwrap();
+    again.body.validate().unwrap();
+    assert_eq!(again.get_sig(), sig);
+    if params.iter().any(contains_dict) {
+        assert_eq!(again.unmarshall_all().unwrap().params, params);
+    } else {
+        assert_eq!(again.get_buf(), &buf[..]);
+    }
+    params
+}
+
+/// Also looks into variants, their signature does not show up in the signature of the body
+fn contains_dict(param: &Param) -> bool {
+    match param {
+        Param::Base(_) => false,
+        Param::Container(Container::Dict(_)) => true,
+        Param::Container(Container::Array(array)) => array.values.iter().any(contains_dict),
+        Param::Container(Container::Struct(fields)) => fields.iter().any(contains_dict),
+        Param::Container(Container::Variant(variant)) => contains_dict(&variant.value),
+        Param::Container(_) => unreachable!("only owned params are unmarshalled"),
+    }
+}
+
+proptest! {
+    #[test]
+    fn marshal_unmarshal_roundtrip((prefix, params) in arb_body(), big_endian in any::<bool>()) {
+        let byteorder = if big_endian { ByteOrder::BigEndian } else { ByteOrder::LittleEndian };
+        let mut msg = MessageBuilder::with_byteorder(byteorder)
+            .signal("io.killing.spark", "Test", "/io/killing/spark")
+            .build();
+        for byte in &prefix {
+            msg.body.push_param(*byte).unwrap();
+        }
+        msg.body.push_old_params(&params).unwrap();
+
+        let mut bytes = Vec::new();
+        msg.write_to(&mut bytes).unwrap();
+        let unmarshalled = assert_roundtrip(parse_message(&bytes));
+        prop_assert_eq!(&unmarshalled[prefix.len()..], &params[..]);
+    }
+}
+
+proptest! {
+    /// The same for the Marshal and Unmarshal impls of rust types
+    #[test]
+    fn typed_roundtrip(
+        prefix in vec(any::<u8>(), 0..8),
+        pairs in vec((any::<u8>(), any::<u64>(), any::<i16>()), 0..4),
+        names in vec(("[a-z]{0,6}", any::<bool>(), any::<f64>()), 0..4),
+        nested in vec(vec((any::<u16>(), any::<u8>()), 0..3), 0..3),
+    ) {
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Test", "/io/killing/spark")
+            .build();
+        for byte in &prefix {
+            msg.body.push_param(*byte).unwrap();
+        }
+        msg.body.push_param3(&pairs, &names, &nested).unwrap();
+
+        let mut bytes = Vec::new();
+        msg.write_to(&mut bytes).unwrap();
+        let parsed = parse_message(&bytes);
+        let mut parser = parsed.body.parser();
+        for byte in &prefix {
+            prop_assert_eq!(parser.get::<u8>().unwrap(), *byte);
+        }
+        let (pairs_again, names_again, nested_again) = parser
+            .get3::<Vec<(u8, u64, i16)>, Vec<(String, bool, f64)>, Vec<Vec<(u16, u8)>>>()
+            .unwrap();
+        prop_assert_eq!(pairs_again, pairs);
+        // compare the bits, NaN is not equal to itself
+        let bits = |names: &[(String, bool, f64)]| {
+            names.iter().map(|(s, b, d)| (s.clone(), *b, d.to_bits())).collect::<Vec<_>>()
+        };
+        prop_assert_eq!(bits(&names_again), bits(&names));
+        prop_assert_eq!(nested_again, nested);
+        assert_roundtrip(parsed);
+    }
+}
+
+proptest! {
+    /// The same values in the GVariant format
+    #[test]
+    fn gvariant_roundtrip((_, params) in arb_body(), big_endian in any::<bool>()) {
+        let byteorder = if big_endian { ByteOrder::BigEndian } else { ByteOrder::LittleEndian };
+        let mut buf = Vec::new();
+        let mut fds = Vec::new();
+        gvariant::marshal_body(&params, byteorder, &mut buf, &mut fds).unwrap();
+        let sigs: Vec<_> = params.iter().map(Param::sig).collect();
+        let unmarshalled = gvariant::unmarshal_body(&sigs, byteorder, &buf, &fds).unwrap();
+        prop_assert_eq!(unmarshalled, params);
+    }
+}
+
+#[test]
+fn corpus_roundtrip() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/corpus");
+    let mut checked = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let bytes = std::fs::read(&path).unwrap();
+        let msg = parse_message(&bytes);
+        // the header round trips too
+        let mut again = Vec::new();
+        msg.write_to(&mut again).unwrap();
+        assert_eq!(again.len(), bytes.len(), "{:?}", path);
+        assert_roundtrip(msg);
+        checked += 1;
+    }
+    assert!(checked > 0);
+}
@@ -166,3 +166,56 @@ fn verify_padding() {
     );
     ctx.buf.clear();
 }
+
+#[test]
+fn trailing_body_padding() {
+    use crate::message_builder::MessageBuilder;
+    use crate::wire::errors::UnmarshalError;
+    use crate::wire::unmarshal::{
+        unmarshal_dynamic_header, unmarshal_header, unmarshal_next_message,
+    };
+    use crate::wire::unmarshal_context::Cursor;
+
+    // a struct ending at a non 8 byte boundary, followed by the padding some peers add to the body
+    let mut msg = MessageBuilder::new()
+        .signal("io.killing.spark", "Padded", "/")
+        .build();
+    msg.body.push_param((1u64, 2u8)).unwrap();
+    let mut header = Vec::new();
+    crate::wire::marshal::marshal(&msg, std::num::NonZeroU32::MIN, &mut header).unwrap();
+    assert_eq!(msg.get_buf().len(), 9);
+
+    let unmarshal_padded = |padding: &[u8]| {
+        let mut bytes = header.clone();
+        bytes.extend_from_slice(msg.get_buf());
+        bytes.extend_from_slice(padding);
+        let body_len = (msg.get_buf().len() + padding.len()) as u32;
+        crate::wire::util::insert_u32(msg.body.byteorder(), body_len, &mut bytes[4..8]);
+
+        let mut cursor = Cursor::new(&bytes);
+        let hdr = unmarshal_header(&mut cursor).unwrap();
+        let dynheader = unmarshal_dynamic_header(&hdr, &mut cursor).unwrap();
+        unmarshal_next_message(&hdr, dynheader, bytes.clone(), cursor.consumed(), vec![]).unwrap()
+    };
+
+    let padded = unmarshal_padded(&[0; 7]);
+    assert_eq!(padded.body.validate(), Ok(()));
+    assert_eq!(
+        padded.body.parser().get::<(u64, u8)>().unwrap(),
+        (1u64, 2u8)
+    );
+
+    assert_eq!(
+        unmarshal_padded(&[0, 0, 0, 0, 0, 1, 0]).body.validate(),
+        Err(UnmarshalError::PaddingContainedData)
+    );
+    // padding that does not end at the boundary or goes past it is not padding
+    assert_eq!(
+        unmarshal_padded(&[0; 3]).body.validate(),
+        Err(UnmarshalError::NotAllBytesUsed)
+    );
+    assert_eq!(
+        unmarshal_padded(&[0; 15]).body.validate(),
+        Err(UnmarshalError::NotAllBytesUsed)
+    );
+}
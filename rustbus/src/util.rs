@@ -0,0 +1,136 @@
+//! Tools to diagnose problems with the bus or a peer on it
+//!
+//! ```rust,no_run
+//! use rustbus::{connection::Timeout, util, RpcConn};
+//!
+//! let mut rpc_con = RpcConn::session_conn(Timeout::Infinite).unwrap();
+//! let stats = util::ping(&mut rpc_con, "org.freedesktop.DBus", 10, Timeout::Infinite).unwrap();
+//! println!("{}", stats);
+//! ```
+
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::connection::{self, rpc_conn::RpcConn, Timeout};
+use crate::message_builder::ErrorReply;
+
+/// Errors that can occur while pinging a peer
+#[derive(Debug, Error)]
+pub enum PingError {
+    #[error("An error occured on the connection: {0}")]
+    Connection(#[from] connection::Error),
+    #[error("The peer answered with the error {0}")]
+    ErrorReply(ErrorReply),
+}
+
+/// Round trip times of a series of pings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingStats {
+    rtts: Vec<Duration>,
+}
+
+impl PingStats {
+    pub fn from_samples(mut rtts: Vec<Duration>) -> Self {
+        rtts.sort();
+        Self { rtts }
+    }
+
+    /// All round trip times, sorted from fastest to slowest
+    pub fn samples(&self) -> &[Duration] {
+        &self.rtts
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.rtts.first().copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.rtts.last().copied()
+    }
+
+    pub fn avg(&self) -> Option<Duration> {
+        if self.rtts.is_empty() {
+            return None;
+        }
+        let total: Duration = self.rtts.iter().sum();
+        Some(total / self.rtts.len() as u32)
+    }
+
+    /// The round trip time that `percent` percent of the pings did not exceed (nearest rank)
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        if self.rtts.is_empty() {
+            return None;
+        }
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * self.rtts.len() as f64).ceil() as usize;
+        Some(self.rtts[rank.max(1) - 1])
+    }
+
+    pub fn p99(&self) -> Option<Duration> {
+        self.percentile(99.0)
+    }
+}
+
+impl std::fmt::Display for PingStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.min(), self.avg(), self.p99(), self.max()) {
+            (Some(min), Some(avg), Some(p99), Some(max)) => write!(
+                f,
+                "{} pings: min {:?} avg {:?} p99 {:?} max {:?}",
+                self.rtts.len(),
+                min,
+                avg,
+                p99,
+                max
+            ),
+            _ => write!(f, "no pings"),
+        }
+    }
+}
+
+/// Call `org.freedesktop.DBus.Peer.Ping` on `dest` `count` times, one after the other, and measure the round trip times.
+/// The timeout applies to each ping on its own. Every peer on the bus has to implement Ping, so this works with any
+/// destination and helps to tell apart a slow bus from a slow service.
+pub fn ping(
+    conn: &mut RpcConn,
+    dest: &str,
+    count: usize,
+    timeout: Timeout,
+) -> Result<PingStats, PingError> {
+    let mut rtts = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut msg = crate::standard_messages::ping(dest.to_owned());
+        let start = Instant::now();
+        let reply = conn.call_method(&mut msg, timeout)?;
+        rtts.push(start.elapsed());
+
+        if let Some(error) = reply.error_reply() {
+            return Err(PingError::ErrorReply(error));
+        }
+    }
+    Ok(PingStats::from_samples(rtts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_stats() {
+        let stats = PingStats::from_samples((1..=100).rev().map(Duration::from_millis).collect());
+        assert_eq!(stats.min(), Some(Duration::from_millis(1)));
+        assert_eq!(stats.max(), Some(Duration::from_millis(100)));
+        assert_eq!(stats.avg(), Some(Duration::from_micros(50_500)));
+        assert_eq!(stats.p99(), Some(Duration::from_millis(99)));
+        assert_eq!(stats.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(stats.percentile(50.0), Some(Duration::from_millis(50)));
+
+        let stats = PingStats::from_samples(vec![Duration::from_millis(3)]);
+        assert_eq!(stats.p99(), Some(Duration::from_millis(3)));
+
+        let stats = PingStats::from_samples(vec![]);
+        assert_eq!(stats.avg(), None);
+        assert_eq!(stats.p99(), None);
+        assert_eq!(stats.to_string(), "no pings");
+    }
+}
@@ -1,7 +1,14 @@
 //! Everything that deals with converting from/to raw bytes. You probably only need the various wrapper types.
 
+#[cfg(feature = "serde")]
+pub mod deserialize;
 pub mod errors;
+#[cfg(rustbus_params)]
+pub mod gvariant;
+mod header_field;
 pub mod marshal;
+#[cfg(feature = "serde")]
+pub mod serialize;
 pub mod unmarshal;
 pub mod unmarshal_context;
 pub mod util;
@@ -10,22 +17,20 @@ pub mod variant_macros;
 
 mod wrapper_types;
 
-use std::num::NonZeroU32;
-
+pub use header_field::{HeaderField, UnknownHeaderField, DEADLINE_FIELD_CODE};
+pub use wrapper_types::large_data::{LargeData, LargeDataError, DEFAULT_INLINE_THRESHOLD};
+#[cfg(feature = "mock-fds")]
+pub use wrapper_types::mock_fds;
+pub use wrapper_types::path::{Lossy, PathAsString, Strict, Utf8Policy};
+pub use wrapper_types::raw_str::RawStr;
+pub use wrapper_types::settings_map::SettingsMap;
+#[cfg(any(feature = "chrono", feature = "time"))]
+pub use wrapper_types::timestamp::{
+    Epoch, Microseconds, Milliseconds, Nanoseconds, PointInTime, Resolution, Seconds, Timestamp,
+    UnixEpoch,
+};
 pub use wrapper_types::unixfd::UnixFd;
+pub use wrapper_types::var_dict::VarDict;
 pub use wrapper_types::ObjectPath;
 pub use wrapper_types::SignatureWrapper;
-
-/// The different header fields a message may or maynot have
-#[derive(Debug)]
-pub enum HeaderField {
-    Path(String),
-    Interface(String),
-    Member(String),
-    ErrorName(String),
-    ReplySerial(NonZeroU32),
-    Destination(String),
-    Sender(String),
-    Signature(String),
-    UnixFds(u32),
-}
+pub use wrapper_types::{BusName, ErrorName, InterfaceName, MemberName};
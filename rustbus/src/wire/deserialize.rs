@@ -0,0 +1,496 @@
+//! A serde `Deserializer` over the wire format, available with the `serde` feature. It lets types that already
+//! implement `serde::Deserialize` be read from a message without also deriving `Unmarshal` for them.
+//!
+//! The signature decides how a value is handed to the visitor:
+//! * base types are visited as the matching rust primitive, strings, object paths and signatures are borrowed from
+//!   the message
+//! * arrays and structs are visited as sequences, `ay` is also available as bytes
+//! * dicts are visited as maps, so an `a{sv}` dict can be deserialized into a struct with one field per key
+//! * variants are transparent, their value is visited as if it was not wrapped
+//! * strings can be deserialized into enums with unit variants
+//!
+//! Unix fds can not be deserialized, their ownership can not be expressed through serde.
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use thiserror::Error;
+
+use crate::message_builder::MarshalledMessageBody;
+use crate::signature::{Base, Container, StructTypes, Type};
+use crate::wire::errors::UnmarshalError;
+use crate::wire::unmarshal_context::UnmarshalContext;
+
+/// Errors that can occur while deserializing a value with serde
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum DeserializeError {
+    /// The message could not be unmarshalled
+    #[error("The message could not be unmarshalled: {0}")]
+    Unmarshal(#[from] UnmarshalError),
+    /// The type that is deserialized into rejected the value
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl de::Error for DeserializeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        DeserializeError::Custom(msg.to_string())
+    }
+}
+
+type Result<T> = std::result::Result<T, DeserializeError>;
+
+/// Deserialize the whole body into `T`. A body with a single param is deserialized as that param, a body with
+/// multiple params as a sequence of them (so as a tuple or a struct with one field per param) and an empty body as `()`.
+pub fn from_body<'de, T: de::Deserialize<'de>>(body: &'de MarshalledMessageBody) -> Result<T> {
+    let (sig, buf, fds) = body.raw();
+    if sig.is_empty() {
+        return T::deserialize(().into_deserializer());
+    }
+    let mut types = Type::parse_description(sig).map_err(UnmarshalError::from)?;
+    let sig = match types.len() {
+        1 => types.remove(0),
+        // the params are packed like the fields of a struct, which starts at offset 0 so no padding is added
+        _ => Type::Container(Container::Struct(
+            StructTypes::new(types).map_err(|_| UnmarshalError::WrongSignature)?,
+        )),
+    };
+    let mut ctx = UnmarshalContext::new(fds, body.byteorder(), buf, 0);
+    let value = T::deserialize(Deserializer::new(&mut ctx, &sig))?;
+    let used = buf.len() - ctx.remainder().len();
+    crate::wire::util::check_body_end(buf, used)?;
+    Ok(value)
+}
+
+/// Deserializes the next value of type `sig` from the context
+pub struct Deserializer<'a, 'fds, 'de> {
+    ctx: &'a mut UnmarshalContext<'fds, 'de>,
+    sig: &'a Type,
+}
+
+impl<'a, 'fds, 'de> Deserializer<'a, 'fds, 'de> {
+    pub fn new(ctx: &'a mut UnmarshalContext<'fds, 'de>, sig: &'a Type) -> Self {
+        Self { ctx, sig }
+    }
+
+    /// If the next value is a variant, read its signature. The context is then positioned at the contained value.
+    fn variant_sig(&mut self) -> Result<Option<Type>> {
+        if *self.sig != Type::Container(Container::Variant) {
+            return Ok(None);
+        }
+        let desc = self.ctx.read_signature()?;
+        let mut sigs = Type::parse_description(desc).map_err(|_| UnmarshalError::WrongSignature)?;
+        if sigs.len() != 1 {
+            return Err(UnmarshalError::WrongSignature.into());
+        }
+        Ok(Some(sigs.remove(0)))
+    }
+
+    fn deserialize_base<V: Visitor<'de>>(self, base: Base, visitor: V) -> Result<V::Value> {
+        match base {
+            Base::Byte => visitor.visit_u8(self.ctx.read_u8()?),
+            Base::Int16 => visitor.visit_i16(self.ctx.read_i16()?),
+            Base::Uint16 => visitor.visit_u16(self.ctx.read_u16()?),
+            Base::Int32 => visitor.visit_i32(self.ctx.read_i32()?),
+            Base::Uint32 => visitor.visit_u32(self.ctx.read_u32()?),
+            Base::Int64 => visitor.visit_i64(self.ctx.read_i64()?),
+            Base::Uint64 => visitor.visit_u64(self.ctx.read_u64()?),
+            Base::Double => visitor.visit_f64(self.ctx.read_f64()?),
+            Base::Boolean => visitor.visit_bool(self.ctx.read_bool()?),
+            Base::String => visitor.visit_borrowed_str(self.ctx.read_str()?),
+            Base::ObjectPath => {
+                let path = self.ctx.read_str()?;
+                crate::params::validate_object_path(path).map_err(UnmarshalError::from)?;
+                visitor.visit_borrowed_str(path)
+            }
+            Base::Signature => visitor.visit_borrowed_str(self.ctx.read_signature()?),
+            Base::UnixFd => Err(de::Error::custom(
+                "unix fds can not be deserialized with serde",
+            )),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_, '_, 'de> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value> {
+        match self.sig {
+            Type::Base(base) => self.deserialize_base(*base, visitor),
+            Type::Container(Container::Array(elem)) => {
+                let len = self.ctx.read_u32()? as usize;
+                // the padding before the first element is not part of the length
+                self.ctx.align_to(elem.get_alignment())?;
+                let mut access = ArrayAccess {
+                    ctx: self.ctx.sub_context(len)?,
+                    elem,
+                };
+                let value = visitor.visit_seq(&mut access)?;
+                access.end()?;
+                Ok(value)
+            }
+            Type::Container(Container::Dict(key, value)) => {
+                let len = self.ctx.read_u32()? as usize;
+                self.ctx.align_to(8)?;
+                let mut access = DictAccess {
+                    ctx: self.ctx.sub_context(len)?,
+                    key: Type::Base(*key),
+                    value,
+                };
+                let value = visitor.visit_map(&mut access)?;
+                access.end()?;
+                Ok(value)
+            }
+            Type::Container(Container::Struct(fields)) => {
+                self.ctx.align_to(8)?;
+                let mut access = StructAccess {
+                    ctx: self.ctx,
+                    fields: fields.as_ref().iter(),
+                };
+                let value = visitor.visit_seq(&mut access)?;
+                access.end()?;
+                Ok(value)
+            }
+            Type::Container(Container::Variant) => {
+                let Some(sig) = self.variant_sig()? else {
+                    unreachable!("checked by the match")
+                };
+                Deserializer::new(self.ctx, &sig).deserialize_any(visitor)
+            }
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value> {
+        if let Some(sig) = self.variant_sig()? {
+            return Deserializer::new(self.ctx, &sig).deserialize_bytes(visitor);
+        }
+        if *self.sig == Type::Container(Container::Array(Box::new(Type::Base(Base::Byte)))) {
+            visitor.visit_borrowed_bytes(self.ctx.read_u8_slice()?)
+        } else {
+            self.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    // dbus has no null value, so every value is present
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        mut self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if let Some(sig) = self.variant_sig()? {
+            return Deserializer::new(self.ctx, &sig).deserialize_enum(name, variants, visitor);
+        }
+        match self.sig {
+            Type::Base(Base::String) => {
+                let variant: &'de str = self.ctx.read_str()?;
+                visitor.visit_enum(variant.into_deserializer())
+            }
+            _ => Err(de::Error::custom(
+                "only strings can be deserialized into an enum",
+            )),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.ctx.validate_next(self.sig)?;
+        self.ctx.read_raw(len)?;
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+struct ArrayAccess<'a, 'fds, 'de> {
+    ctx: UnmarshalContext<'fds, 'de>,
+    elem: &'a Type,
+}
+
+impl ArrayAccess<'_, '_, '_> {
+    /// Fails if the visitor did not consume all elements
+    fn end(&self) -> Result<()> {
+        if self.ctx.remainder().is_empty() {
+            Ok(())
+        } else {
+            Err(de::Error::custom(
+                "the array has more elements than were deserialized",
+            ))
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for ArrayAccess<'_, '_, 'de> {
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.ctx.remainder().is_empty() {
+            return Ok(None);
+        }
+        seed.deserialize(Deserializer::new(&mut self.ctx, self.elem))
+            .map(Some)
+    }
+}
+
+struct DictAccess<'a, 'fds, 'de> {
+    ctx: UnmarshalContext<'fds, 'de>,
+    key: Type,
+    value: &'a Type,
+}
+
+impl DictAccess<'_, '_, '_> {
+    /// Fails if the visitor did not consume all entries
+    fn end(&self) -> Result<()> {
+        if self.ctx.remainder().is_empty() {
+            Ok(())
+        } else {
+            Err(de::Error::custom(
+                "the dict has more entries than were deserialized",
+            ))
+        }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for DictAccess<'_, '_, 'de> {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.ctx.remainder().is_empty() {
+            return Ok(None);
+        }
+        // dict entries are aligned like structs
+        self.ctx.align_to(8)?;
+        seed.deserialize(Deserializer::new(&mut self.ctx, &self.key))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(Deserializer::new(&mut self.ctx, self.value))
+    }
+}
+
+struct StructAccess<'a, 'fds, 'de> {
+    ctx: &'a mut UnmarshalContext<'fds, 'de>,
+    fields: std::slice::Iter<'a, Type>,
+}
+
+impl StructAccess<'_, '_, '_> {
+    /// Fails if the visitor did not consume all fields
+    fn end(&self) -> Result<()> {
+        match self.fields.len() {
+            0 => Ok(()),
+            left => Err(de::Error::custom(format!(
+                "the struct has {} more fields than were deserialized",
+                left
+            ))),
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for StructAccess<'_, '_, 'de> {
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.fields.next() {
+            Some(field) => seed
+                .deserialize(Deserializer::new(self.ctx, field))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::DeserializeError;
+    use crate::message_builder::MarshalledMessageBody;
+    use crate::wire::errors::UnmarshalError;
+    use crate::wire::marshal::traits::Variant;
+    use crate::wire::{ObjectPath, VarDict};
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum State {
+        Active,
+        Inactive,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Unit<'a> {
+        name: &'a str,
+        path: String,
+        pid: u32,
+        state: State,
+        ratios: Vec<f64>,
+        #[serde(with = "serde_bytes_like")]
+        data: Vec<u8>,
+    }
+
+    mod serde_bytes_like {
+        pub fn deserialize<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Vec<u8>, D::Error> {
+            struct Bytes;
+            impl<'de> serde::de::Visitor<'de> for Bytes {
+                type Value = Vec<u8>;
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("bytes")
+                }
+                fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Vec<u8>, E> {
+                    Ok(v.to_vec())
+                }
+            }
+            de.deserialize_bytes(Bytes)
+        }
+    }
+
+    #[test]
+    fn test_deserialize_params() {
+        let mut body = MarshalledMessageBody::new();
+        body.push_param("sshd.service").unwrap();
+        body.push_param(ObjectPath::new("/unit/sshd").unwrap())
+            .unwrap();
+        body.push_param(Variant(42u32)).unwrap();
+        body.push_param("Active").unwrap();
+        body.push_param(&[0.5f64, 1.5][..]).unwrap();
+        body.push_param(&[1u8, 2, 3][..]).unwrap();
+
+        let unit: Unit = body.parse_into().unwrap();
+        assert_eq!(
+            unit,
+            Unit {
+                name: "sshd.service",
+                path: "/unit/sshd".into(),
+                pid: 42,
+                state: State::Active,
+                ratios: vec![0.5, 1.5],
+                data: vec![1, 2, 3],
+            }
+        );
+
+        // the same params as a tuple
+        let (name, _, pid, ..): (String, &str, u64, State, Vec<f32>, Vec<u8>) =
+            body.parse_into().unwrap();
+        assert_eq!((name.as_str(), pid), ("sshd.service", 42));
+
+        // a struct with fewer fields than the body has params
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Short {
+            name: String,
+        }
+        assert!(matches!(
+            body.parse_into::<Short>(),
+            Err(DeserializeError::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_dict() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(rename_all = "PascalCase")]
+        struct Props {
+            name: String,
+            volume: u32,
+            #[serde(default)]
+            muted: bool,
+            tags: Option<HashMap<String, (u8, bool)>>,
+        }
+
+        let mut tags = HashMap::new();
+        tags.insert("front".to_owned(), (1u8, true));
+        let mut props = VarDict::new();
+        props.insert("Name", "speaker").unwrap();
+        props.insert("Volume", 7u32).unwrap();
+        props.insert("Tags", &tags).unwrap();
+        props.insert("Unknown", 0.5f64).unwrap();
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(&props).unwrap();
+        let parsed: Props = body.parse_into().unwrap();
+        assert_eq!(
+            parsed,
+            Props {
+                name: "speaker".into(),
+                volume: 7,
+                muted: false,
+                tags: Some(tags),
+            }
+        );
+
+        // a wrong type is reported by serde
+        props.insert("Volume", "loud").unwrap();
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(&props).unwrap();
+        assert!(matches!(
+            body.parse_into::<Props>(),
+            Err(DeserializeError::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(crate::wire::UnixFd::new(nix::unistd::dup(1).unwrap()))
+            .unwrap();
+        assert!(matches!(
+            body.parse_into::<u32>(),
+            Err(DeserializeError::Custom(_))
+        ));
+
+        let body = MarshalledMessageBody::new();
+        assert_eq!(body.parse_into::<()>(), Ok(()));
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(true).unwrap();
+        let mut raw = body.raw().1.to_vec();
+        raw[0] = 2;
+        let body = MarshalledMessageBody::from_parts(raw, 0, vec![], "b".into(), body.byteorder());
+        assert_eq!(
+            body.parse_into::<bool>(),
+            Err(DeserializeError::Unmarshal(UnmarshalError::InvalidBoolean))
+        );
+
+        // zeroed padding up to the next 8 byte boundary may follow the last param, anything else may not
+        let mut body = MarshalledMessageBody::new();
+        body.push_param((1u64, 2u8)).unwrap();
+        let mut raw = body.raw().1.to_vec();
+        raw.extend_from_slice(&[0; 7]);
+        let padded = MarshalledMessageBody::from_parts(
+            raw.clone(),
+            0,
+            vec![],
+            "(ty)".into(),
+            body.byteorder(),
+        );
+        assert_eq!(padded.parse_into::<(u64, u8)>(), Ok((1, 2)));
+        raw.push(0);
+        let padded =
+            MarshalledMessageBody::from_parts(raw, 0, vec![], "(ty)".into(), body.byteorder());
+        assert_eq!(
+            padded.parse_into::<(u64, u8)>(),
+            Err(DeserializeError::Unmarshal(UnmarshalError::NotAllBytesUsed))
+        );
+    }
+}
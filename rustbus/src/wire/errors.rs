@@ -15,6 +15,27 @@ pub enum MarshalError {
     /// Errors occuring while validating the input
     #[error("Errors occured while validating: {0}")]
     Validation(#[from] crate::params::validation::Error),
+    /// The message is too big to be represented in the dbus wire format
+    #[error("The message is too big to be represented in the dbus wire format")]
+    MessageTooLarge,
+    /// The message is bigger than the connection allows, see `SendConn::set_max_message_size`
+    #[error("The message has {size} bytes but the connection only allows {max}")]
+    MessageExceedsLimit { size: usize, max: usize },
+    /// Tried to marshal a timestamp that does not fit into the wire representation (e.g. it lies before the unix epoch)
+    #[error("Tried to marshal a timestamp that does not fit into the wire representation")]
+    TimestampOutOfRange,
+    /// The num_fds field of the header was set, but does not match the number of fds in the body
+    #[error("The num_fds header field says {header} but the body contains {body} fds")]
+    NumFdsMismatch { header: u32, body: u32 },
+    /// A value that is kept marshalled, like the values of a `VarDict`, does not match its signature
+    #[error("A marshalled value does not match its signature: {0}")]
+    InvalidValue(UnmarshalError),
+    /// A custom header field uses a code defined by the spec or another byteorder than the message
+    #[error("The custom header field {0} uses a reserved code or the wrong byteorder")]
+    InvalidHeaderField(u8),
+    /// Only bodies without filedescriptors can be shared, see `MarshalledMessageBody::make_shared`
+    #[error("Bodies that contain filedescriptors can not be shared")]
+    SharedBodyWithFds,
 }
 
 //--------
@@ -79,10 +100,39 @@ pub enum UnmarshalError {
     /// A message did not contain a signature for a header field
     #[error("A message did not contain a signature for a header field")]
     NoSignature,
+    /// A message has a body but no signature header field, see `RecvConn::set_allow_missing_signature`
+    #[error("A message has a body but no signature header field")]
+    MissingBodySignature,
+    /// The unix fds header field announces more fds than `unmarshal::MAX_UNIX_FDS`
+    #[error("The message announces {0} unix fds, more than a message may carry")]
+    TooManyUnixFds(u32),
+    /// The unix fds header field does not match the number of fds that were received with the message
+    #[error(
+        "The unix fds header field says {header} but {received} fds were received with the message"
+    )]
+    NumFdsMismatch { header: u32, received: u32 },
     /// A unix fd member had an index that is bigger than the size of the list of unix fds passed along with the message
     #[error("A unix fd member had an index that is bigger than the size of the list of unix fds passed along with the message")]
     BadFdIndex(usize),
     /// When unmarshalling a Variant and there is not matching variant in the enum that had the unmarshal impl derived
     #[error("When unmarshalling a Variant and there is not matching variant in the enum that had the unmarshal impl derived")]
     NoMatchingVariantFound,
+    /// A message announced a length that exceeds the limits set by the dbus spec
+    #[error("A message announced a length that exceeds the limits set by the dbus spec")]
+    MessageTooLarge,
+    /// A timestamp in the message can not be represented by the requested type
+    #[error("A timestamp in the message can not be represented by the requested type")]
+    TimestampOutOfRange,
+    /// A caller provided buffer could not hold the unmarshalled value. For strings the sizes are in bytes, for arrays in elements
+    #[error("A caller provided buffer of size {capacity} is too small, {needed} would be needed")]
+    BufferTooSmall { needed: usize, capacity: usize },
+    /// A dict that is unmarshalled into a struct with `#[rustbus(dict)]` is missing the key of a field
+    #[error("The dict is missing the key {0}")]
+    MissingDictKey(String),
+    /// A framing offset of a GVariant container points outside of the container or before the previous element
+    #[error("A framing offset of a GVariant container is invalid")]
+    InvalidFramingOffset,
+    /// A GVariant value could not be converted to the dbus format its `Unmarshal` impl reads, see `wire::gvariant`
+    #[error("A GVariant value could not be converted to the dbus format: {0}")]
+    FormatConversion(Box<MarshalError>),
 }
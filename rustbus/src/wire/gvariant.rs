@@ -0,0 +1,819 @@
+//! The GVariant serialization format
+//!
+//! GVariant is the format GLib uses for its values. Some sd-bus and GLib based peers exchange blobs in this format (e.g.
+//! in files or as byte arrays in dbus messages), and for deeply nested values it is more compact than the dbus format
+//! because it needs no length prefixes and less padding.
+//!
+//! The differences to the dbus format:
+//! * Values are aligned to their natural alignment relative to the start of their container, booleans are one byte
+//!   and strings are only terminated by a null byte.
+//! * Containers do not start with their length. Instead the ends of elements with a variable size are stored as
+//!   framing offsets at the end of the container. The offsets are always little endian, their size depends on the
+//!   size of the container.
+//! * A variant stores its value first, followed by a null byte and the signature of the value.
+//! * A message body is serialized as one struct containing all params.
+//!
+//! The dbus connections of this crate always use the dbus format. The bus daemons do not offer the GVariant based
+//! message format, so this module only converts values and bodies.
+//!
+//! The codec itself works on the params of the `params` module. `marshal` and `unmarshal` convert any type through its
+//! `Marshal` and `Unmarshal` impls, and `marshal_dbus_body` and `unmarshal_dbus_body` convert whole
+//! `MarshalledMessageBody`s. They go through the dbus format and params on the way, so they copy the value twice.
+//!
+//! ```rust
+//! use rustbus::params::Param;
+//! use rustbus::signature::Type;
+//! use rustbus::wire::gvariant;
+//! use rustbus::ByteOrder;
+//!
+//! let params: Vec<Param> = vec!["hello".to_owned().into(), 42u32.into()];
+//! let mut buf = Vec::new();
+//! let mut fds = Vec::new();
+//! gvariant::marshal_body(&params, ByteOrder::LittleEndian, &mut buf, &mut fds).unwrap();
+//! // the string, its null byte, padding, the u32 and the framing offset marking the end of the string
+//! assert_eq!(buf, b"hello\0\0\0\x2a\0\0\0\x06");
+//!
+//! let sigs = Type::parse_description("su").unwrap();
+//! let again = gvariant::unmarshal_body(&sigs, ByteOrder::LittleEndian, &buf, &fds).unwrap();
+//! assert_eq!(again, params);
+//!
+//! // the same with types that implement Marshal and Unmarshal
+//! let mut typed = Vec::new();
+//! gvariant::marshal(&("hello", 42u32), ByteOrder::LittleEndian, &mut typed, &mut fds).unwrap();
+//! assert_eq!(typed, buf);
+//! let (s, u): (String, u32) = gvariant::unmarshal(ByteOrder::LittleEndian, &typed, &fds).unwrap();
+//! assert_eq!((s.as_str(), u), ("hello", 42));
+//! ```
+
+use crate::message_builder::MarshalledMessageBody;
+use crate::params::{self, Base, Container, DictMap, Param};
+use crate::signature::{self, Type};
+use crate::wire::errors::{MarshalError, UnmarshalError};
+use crate::wire::marshal::traits::Marshal;
+use crate::wire::marshal::MarshalContext;
+use crate::wire::unmarshal::traits::Unmarshal;
+use crate::wire::unmarshal::UnmarshalResult;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::wire::util::*;
+use crate::wire::UnixFd;
+use crate::ByteOrder;
+
+/// Variants can contain variants, this limits how deep they may nest when unmarshalling
+const MAX_VARIANT_DEPTH: usize = 64;
+
+/// The alignment of a type in the GVariant format
+fn alignment(typ: &Type) -> usize {
+    match typ {
+        Type::Base(base) => base_alignment(*base),
+        Type::Container(signature::Container::Array(elem)) => alignment(elem),
+        Type::Container(signature::Container::Dict(key, value)) => {
+            usize::max(base_alignment(*key), alignment(value))
+        }
+        Type::Container(signature::Container::Struct(fields)) => {
+            fields.as_ref().iter().map(alignment).max().unwrap_or(1)
+        }
+        Type::Container(signature::Container::Variant) => 8,
+    }
+}
+
+fn base_alignment(base: signature::Base) -> usize {
+    match base {
+        signature::Base::String | signature::Base::ObjectPath | signature::Base::Signature => 1,
+        // booleans are a single byte
+        signature::Base::Boolean => 1,
+        other => other.get_alignment(),
+    }
+}
+
+/// The size of values of this type, None if it varies
+fn fixed_size(typ: &Type) -> Option<usize> {
+    match typ {
+        Type::Base(base) => base_fixed_size(*base),
+        Type::Container(signature::Container::Struct(fields)) => {
+            struct_fixed_size(fields.as_ref().iter(), alignment(typ))
+        }
+        // arrays of dict entries are still arrays
+        Type::Container(_) => None,
+    }
+}
+
+fn base_fixed_size(base: signature::Base) -> Option<usize> {
+    match base {
+        signature::Base::String | signature::Base::ObjectPath | signature::Base::Signature => None,
+        other => Some(base_alignment(other)),
+    }
+}
+
+/// Structs are fixed if all fields are, they are padded to their alignment and occupy at least one byte
+fn struct_fixed_size<'a>(fields: impl Iterator<Item = &'a Type>, align: usize) -> Option<usize> {
+    let mut size: usize = 0;
+    for field in fields {
+        size = size.next_multiple_of(alignment(field)) + fixed_size(field)?;
+    }
+    Some(usize::max(size.next_multiple_of(align), 1))
+}
+
+fn dict_entry_fixed_size(key: signature::Base, value: &Type) -> Option<usize> {
+    let key = Type::Base(key);
+    let align = usize::max(alignment(&key), alignment(value));
+    struct_fixed_size([&key, value].iter().copied(), align)
+}
+
+/// The size of the framing offsets in a container of `len` bytes
+fn offset_size(len: usize) -> usize {
+    if len == 0 {
+        0
+    } else if len <= u8::MAX as usize {
+        1
+    } else if len <= u16::MAX as usize {
+        2
+    } else if len <= u32::MAX as usize {
+        4
+    } else {
+        8
+    }
+}
+
+/// Append the framing offsets to the container that starts at `start`. The offsets are relative to `start`, they are
+/// as small as possible while still being able to address the whole container including themselves.
+fn write_offsets(buf: &mut Vec<u8>, start: usize, offsets: &[usize]) {
+    let body = buf.len() - start;
+    let size = [1, 2, 4]
+        .iter()
+        .copied()
+        .find(|size| body + size * offsets.len() < (1usize << (8 * size)))
+        .unwrap_or(8);
+    for offset in offsets {
+        buf.extend_from_slice(&offset.to_le_bytes()[..size]);
+    }
+}
+
+/// The number in `buf`, which is as long as the type it encodes
+fn read_number(buf: &[u8], byteorder: ByteOrder) -> u64 {
+    let mut bytes = [0u8; 8];
+    match byteorder {
+        ByteOrder::LittleEndian => {
+            bytes[..buf.len()].copy_from_slice(buf);
+            u64::from_le_bytes(bytes)
+        }
+        ByteOrder::BigEndian => {
+            bytes[8 - buf.len()..].copy_from_slice(buf);
+            u64::from_be_bytes(bytes)
+        }
+    }
+}
+
+fn read_offset(bytes: &[u8]) -> usize {
+    let mut le = [0u8; 8];
+    le[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(le) as usize
+}
+
+/// Marshal one param in the GVariant format. Values are aligned relative to the start of `ctx.buf`, so it should
+/// either be empty or end at a multiple of 8 bytes.
+pub fn marshal_param(p: &Param, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+    match p {
+        Param::Base(base) => marshal_base(base, ctx),
+        Param::Container(Container::Array(array)) => {
+            marshal_array(&array.element_sig, &array.values, ctx)
+        }
+        Param::Container(Container::ArrayRef(array)) => {
+            marshal_array(&array.element_sig, array.values, ctx)
+        }
+        Param::Container(Container::Struct(fields)) => marshal_struct(fields, ctx),
+        Param::Container(Container::StructRef(fields)) => marshal_struct(fields, ctx),
+        Param::Container(Container::Dict(dict)) => {
+            marshal_dict(dict.key_sig, &dict.value_sig, &dict.map, ctx)
+        }
+        Param::Container(Container::DictRef(dict)) => {
+            marshal_dict(dict.key_sig, &dict.value_sig, dict.map, ctx)
+        }
+        Param::Container(Container::Variant(variant)) => {
+            pad_to_align(8, ctx.buf);
+            marshal_param(&variant.value, ctx)?;
+            ctx.buf.push(0);
+            let mut sig = String::new();
+            variant.sig.to_str(&mut sig);
+            ctx.buf.extend_from_slice(sig.as_bytes());
+            Ok(())
+        }
+    }
+}
+
+/// Marshal the params of a message body, which is serialized like a struct of all params
+pub fn marshal_body(
+    params: &[Param],
+    byteorder: ByteOrder,
+    buf: &mut Vec<u8>,
+    fds: &mut Vec<UnixFd>,
+) -> Result<(), MarshalError> {
+    let mut ctx = MarshalContext {
+        fds,
+        buf,
+        byteorder,
+    };
+    marshal_struct(params, &mut ctx)
+}
+
+fn marshal_base(base: &Base, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+    pad_to_align(base_alignment(base.into()), ctx.buf);
+    match base {
+        Base::Boolean(b) => ctx.buf.push(*b as u8),
+        Base::Byte(b) => ctx.buf.push(*b),
+        Base::Int16(i) => write_u16(*i as u16, ctx.byteorder, ctx.buf),
+        Base::Uint16(u) => write_u16(*u, ctx.byteorder, ctx.buf),
+        Base::Int32(i) => write_u32(*i as u32, ctx.byteorder, ctx.buf),
+        Base::Uint32(u) => write_u32(*u, ctx.byteorder, ctx.buf),
+        Base::Int64(i) => write_u64(*i as u64, ctx.byteorder, ctx.buf),
+        Base::Uint64(u) | Base::Double(u) => write_u64(*u, ctx.byteorder, ctx.buf),
+        Base::UnixFd(fd) => marshal_unixfd(fd, ctx)?,
+        Base::String(s) => marshal_str(s, ctx.buf)?,
+        Base::StringRef(s) => marshal_str(s, ctx.buf)?,
+        Base::ObjectPath(s) => {
+            params::validate_object_path(s)?;
+            marshal_str(s, ctx.buf)?
+        }
+        Base::ObjectPathRef(s) => {
+            params::validate_object_path(s)?;
+            marshal_str(s, ctx.buf)?
+        }
+        Base::Signature(s) => {
+            params::validate_signature(s)?;
+            marshal_str(s, ctx.buf)?
+        }
+        Base::SignatureRef(s) => {
+            params::validate_signature(s)?;
+            marshal_str(s, ctx.buf)?
+        }
+    }
+    Ok(())
+}
+
+fn marshal_str(s: &str, buf: &mut Vec<u8>) -> Result<(), MarshalError> {
+    if s.contains('\0') {
+        return Err(params::validation::Error::StringContainsNullByte.into());
+    }
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+    Ok(())
+}
+
+fn marshal_array(
+    element_sig: &Type,
+    values: &[Param],
+    ctx: &mut MarshalContext,
+) -> Result<(), MarshalError> {
+    pad_to_align(alignment(element_sig), ctx.buf);
+    let start = ctx.buf.len();
+    if fixed_size(element_sig).is_some() {
+        for value in values {
+            marshal_param(value, ctx)?;
+        }
+        return Ok(());
+    }
+    let mut offsets = Vec::with_capacity(values.len());
+    for value in values {
+        marshal_param(value, ctx)?;
+        offsets.push(ctx.buf.len() - start);
+    }
+    write_offsets(ctx.buf, start, &offsets);
+    Ok(())
+}
+
+#[allow(clippy::mutable_key_type)]
+fn marshal_dict(
+    key_sig: signature::Base,
+    value_sig: &Type,
+    map: &DictMap,
+    ctx: &mut MarshalContext,
+) -> Result<(), MarshalError> {
+    let key_type = Type::Base(key_sig);
+    let align = usize::max(alignment(&key_type), alignment(value_sig));
+    let fixed = dict_entry_fixed_size(key_sig, value_sig).is_some();
+    pad_to_align(align, ctx.buf);
+    let start = ctx.buf.len();
+    let mut offsets = Vec::with_capacity(map.len());
+    for (key, value) in map {
+        pad_to_align(align, ctx.buf);
+        let entry_start = ctx.buf.len();
+        marshal_base(key, ctx)?;
+        let key_end = ctx.buf.len() - entry_start;
+        marshal_param(value, ctx)?;
+        if fixed {
+            while (ctx.buf.len() - entry_start) % align != 0 {
+                ctx.buf.push(0);
+            }
+        } else {
+            if fixed_size(&key_type).is_none() {
+                write_offsets(ctx.buf, entry_start, &[key_end]);
+            }
+            offsets.push(ctx.buf.len() - start);
+        }
+    }
+    if !fixed {
+        write_offsets(ctx.buf, start, &offsets);
+    }
+    Ok(())
+}
+
+fn marshal_struct(fields: &[Param], ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+    let types: Vec<Type> = fields.iter().map(Param::sig).collect();
+    let align = types.iter().map(alignment).max().unwrap_or(1);
+    pad_to_align(align, ctx.buf);
+    let start = ctx.buf.len();
+    let mut offsets = Vec::new();
+    for (idx, (field, typ)) in fields.iter().zip(&types).enumerate() {
+        marshal_param(field, ctx)?;
+        if fixed_size(typ).is_none() && idx + 1 < fields.len() {
+            offsets.push(ctx.buf.len() - start);
+        }
+    }
+    if struct_fixed_size(types.iter(), align).is_some() {
+        if ctx.buf.len() == start {
+            ctx.buf.push(0);
+        }
+        pad_to_align(align, ctx.buf);
+    } else {
+        offsets.reverse();
+        write_offsets(ctx.buf, start, &offsets);
+    }
+    Ok(())
+}
+
+/// Unmarshal one value of type `sig`. `buf` has to contain exactly this value, GVariant values do not know their own
+/// size. Unix fds are looked up in `fds` by their index.
+pub fn unmarshal_param(
+    sig: &Type,
+    byteorder: ByteOrder,
+    buf: &[u8],
+    fds: &[UnixFd],
+) -> UnmarshalResult<Param<'static, 'static>> {
+    Unmarshaller { byteorder, fds }.param(sig, buf, 0)
+}
+
+/// Unmarshal a message body that was serialized as a struct of values with the types `sigs`
+pub fn unmarshal_body(
+    sigs: &[Type],
+    byteorder: ByteOrder,
+    buf: &[u8],
+    fds: &[UnixFd],
+) -> UnmarshalResult<Vec<Param<'static, 'static>>> {
+    if sigs.is_empty() {
+        return Ok(Vec::new());
+    }
+    Unmarshaller { byteorder, fds }.fields(sigs, buf, 0)
+}
+
+/// Marshal a value in the GVariant format with its `Marshal` impl
+pub fn marshal<T: Marshal>(
+    value: &T,
+    byteorder: ByteOrder,
+    buf: &mut Vec<u8>,
+    fds: &mut Vec<UnixFd>,
+) -> Result<(), MarshalError> {
+    let mut body = MarshalledMessageBody::with_byteorder(byteorder);
+    body.push_param(value)?;
+    let params = dbus_params(&body)?;
+    marshal_param(
+        &params[0],
+        &mut MarshalContext {
+            fds,
+            buf,
+            byteorder,
+        },
+    )
+}
+
+/// Unmarshal a value in the GVariant format with its `Unmarshal` impl. Like for `unmarshal_param`, `buf` has to
+/// contain exactly this value.
+pub fn unmarshal<T>(byteorder: ByteOrder, buf: &[u8], fds: &[UnixFd]) -> UnmarshalResult<T>
+where
+    T: for<'a> Unmarshal<'a, 'a>,
+{
+    let param = unmarshal_param(&T::signature(), byteorder, buf, fds)?;
+    let mut body = MarshalledMessageBody::with_byteorder(byteorder);
+    body.push_old_param(&param)
+        .map_err(|e| UnmarshalError::FormatConversion(Box::new(e)))?;
+    let (_, buf, fds) = body.raw();
+    T::unmarshal(&mut UnmarshalContext::new(fds, byteorder, buf, 0))
+}
+
+/// Marshal a body that is in the dbus format, e.g. one built with `MarshalledMessageBody::push_param`, in the GVariant
+/// format
+pub fn marshal_dbus_body(
+    body: &MarshalledMessageBody,
+    buf: &mut Vec<u8>,
+    fds: &mut Vec<UnixFd>,
+) -> Result<(), MarshalError> {
+    marshal_body(&dbus_params(body)?, body.byteorder(), buf, fds)
+}
+
+/// Unmarshal a body in the GVariant format with the signature `sig` into a body in the dbus format. Its `parser`
+/// unmarshals the values with their `Unmarshal` impls.
+pub fn unmarshal_dbus_body(
+    sig: &str,
+    byteorder: ByteOrder,
+    buf: &[u8],
+    fds: &[UnixFd],
+) -> UnmarshalResult<MarshalledMessageBody> {
+    let sigs = Type::parse_description(sig)
+        .map_err(|e| UnmarshalError::Validation(params::validation::Error::InvalidSignature(e)))?;
+    let params = unmarshal_body(&sigs, byteorder, buf, fds)?;
+    let mut body = MarshalledMessageBody::with_byteorder(byteorder);
+    body.push_old_params(&params)
+        .map_err(|e| UnmarshalError::FormatConversion(Box::new(e)))?;
+    Ok(body)
+}
+
+/// The params of a body in the dbus format
+fn dbus_params(body: &MarshalledMessageBody) -> Result<Vec<Param<'static, 'static>>, MarshalError> {
+    let (sig, buf, fds) = body.raw();
+    let sigs = Type::parse_description(sig)?;
+    let mut ctx = UnmarshalContext::new(fds, body.byteorder(), buf, 0);
+    sigs.iter()
+        .map(|sig| crate::wire::unmarshal::container::unmarshal_with_sig(sig, &mut ctx))
+        .collect::<UnmarshalResult<_>>()
+        .map_err(MarshalError::InvalidValue)
+}
+
+struct Unmarshaller<'a> {
+    byteorder: ByteOrder,
+    fds: &'a [UnixFd],
+}
+
+impl Unmarshaller<'_> {
+    #[allow(clippy::mutable_key_type)]
+    fn param(
+        &self,
+        sig: &Type,
+        buf: &[u8],
+        depth: usize,
+    ) -> UnmarshalResult<Param<'static, 'static>> {
+        match sig {
+            Type::Base(base) => self.base(*base, buf).map(Param::Base),
+            Type::Container(signature::Container::Array(elem)) => {
+                let values = self
+                    .elements(fixed_size(elem), alignment(elem), buf)?
+                    .into_iter()
+                    .map(|elem_buf| self.param(elem, elem_buf, depth))
+                    .collect::<UnmarshalResult<_>>()?;
+                Ok(Param::Container(Container::Array(params::Array {
+                    element_sig: (**elem).clone(),
+                    values,
+                })))
+            }
+            Type::Container(signature::Container::Dict(key, value)) => {
+                let key_type = Type::Base(*key);
+                let align = usize::max(alignment(&key_type), alignment(value));
+                let entry_types = [key_type, (**value).clone()];
+                let mut map = DictMap::new();
+                for entry in self.elements(dict_entry_fixed_size(*key, value), align, buf)? {
+                    let mut entry = self.fields(&entry_types, entry, depth)?.into_iter();
+                    let (Some(Param::Base(key)), Some(value)) = (entry.next(), entry.next()) else {
+                        unreachable!("dict entries are unmarshalled as a base key and a value");
+                    };
+                    map.insert(key, value);
+                }
+                Ok(Param::Container(Container::Dict(params::Dict {
+                    key_sig: *key,
+                    value_sig: (**value).clone(),
+                    map,
+                })))
+            }
+            Type::Container(signature::Container::Struct(fields)) => {
+                let fields = self.fields(fields.as_ref(), buf, depth)?;
+                Ok(Param::Container(Container::Struct(fields)))
+            }
+            Type::Container(signature::Container::Variant) => {
+                if depth >= MAX_VARIANT_DEPTH {
+                    return Err(signature::Error::NestingTooDeep.into());
+                }
+                let split = buf
+                    .iter()
+                    .rposition(|b| *b == 0)
+                    .ok_or(UnmarshalError::NotEnoughBytes)?;
+                let sig = std::str::from_utf8(&buf[split + 1..])
+                    .map_err(|_| params::validation::Error::InvalidUtf8)?;
+                let mut types = Type::parse_description(sig)?;
+                if types.len() != 1 {
+                    return Err(signature::Error::TooManyTypes.into());
+                }
+                let sig = types.remove(0);
+                let value = self.param(&sig, &buf[..split], depth + 1)?;
+                Ok(Param::Container(Container::Variant(Box::new(
+                    params::Variant { sig, value },
+                ))))
+            }
+        }
+    }
+
+    fn base(&self, sig: signature::Base, buf: &[u8]) -> UnmarshalResult<Base<'static>> {
+        if let Some(size) = base_fixed_size(sig) {
+            if buf.len() < size {
+                return Err(UnmarshalError::NotEnoughBytes);
+            }
+            if buf.len() > size {
+                return Err(UnmarshalError::NotAllBytesUsed);
+            }
+        }
+        let byteorder = self.byteorder;
+        Ok(match sig {
+            signature::Base::Byte => Base::Byte(buf[0]),
+            signature::Base::Boolean => match buf[0] {
+                0 => Base::Boolean(false),
+                1 => Base::Boolean(true),
+                _ => return Err(UnmarshalError::InvalidBoolean),
+            },
+            signature::Base::Int16 => Base::Int16(read_number(buf, byteorder) as i16),
+            signature::Base::Uint16 => Base::Uint16(read_number(buf, byteorder) as u16),
+            signature::Base::Int32 => Base::Int32(read_number(buf, byteorder) as i32),
+            signature::Base::Uint32 => Base::Uint32(read_number(buf, byteorder) as u32),
+            signature::Base::Int64 => Base::Int64(read_number(buf, byteorder) as i64),
+            signature::Base::Uint64 => Base::Uint64(read_number(buf, byteorder)),
+            signature::Base::Double => Base::Double(read_number(buf, byteorder)),
+            signature::Base::UnixFd => {
+                let idx = read_number(buf, byteorder) as usize;
+                let fd = self.fds.get(idx).ok_or(UnmarshalError::BadFdIndex(idx))?;
+                Base::UnixFd(fd.clone())
+            }
+            signature::Base::String => Base::String(self.str(buf)?.to_owned()),
+            signature::Base::ObjectPath => {
+                let path = self.str(buf)?;
+                params::validate_object_path(path)?;
+                Base::ObjectPath(path.to_owned())
+            }
+            signature::Base::Signature => {
+                let sig = self.str(buf)?;
+                params::validate_signature(sig)?;
+                Base::Signature(sig.to_owned())
+            }
+        })
+    }
+
+    fn str<'b>(&self, buf: &'b [u8]) -> UnmarshalResult<&'b str> {
+        let (last, content) = buf.split_last().ok_or(UnmarshalError::NotEnoughBytes)?;
+        if *last != 0 {
+            return Err(UnmarshalError::NotEnoughBytes);
+        }
+        if content.contains(&0) {
+            return Err(params::validation::Error::StringContainsNullByte.into());
+        }
+        std::str::from_utf8(content).map_err(|_| params::validation::Error::InvalidUtf8.into())
+    }
+
+    /// Split an array into its elements
+    fn elements<'b>(
+        &self,
+        fixed: Option<usize>,
+        align: usize,
+        buf: &'b [u8],
+    ) -> UnmarshalResult<Vec<&'b [u8]>> {
+        if let Some(size) = fixed {
+            if buf.len() % size != 0 {
+                return Err(UnmarshalError::NotEnoughBytesForCollection);
+            }
+            return Ok(buf.chunks(size).collect());
+        }
+        if buf.is_empty() {
+            return Ok(Vec::new());
+        }
+        let osize = offset_size(buf.len());
+        let last_end = read_offset(&buf[buf.len() - osize..]);
+        if last_end > buf.len() || (buf.len() - last_end) % osize != 0 {
+            return Err(UnmarshalError::InvalidFramingOffset);
+        }
+        let mut elements = Vec::new();
+        let mut pos: usize = 0;
+        for offset in buf[last_end..].chunks(osize) {
+            let end = read_offset(offset);
+            let start = pos.next_multiple_of(align);
+            if start > end || end > last_end {
+                return Err(UnmarshalError::InvalidFramingOffset);
+            }
+            elements.push(&buf[start..end]);
+            pos = end;
+        }
+        Ok(elements)
+    }
+
+    /// The fields of a struct or dict entry
+    fn fields(
+        &self,
+        types: &[Type],
+        buf: &[u8],
+        depth: usize,
+    ) -> UnmarshalResult<Vec<Param<'static, 'static>>> {
+        let align = types.iter().map(alignment).max().unwrap_or(1);
+        if let Some(size) = struct_fixed_size(types.iter(), align) {
+            if buf.len() != size {
+                return Err(UnmarshalError::NotEnoughBytes);
+            }
+        }
+        let osize = offset_size(buf.len());
+        // the offsets are read from the back, the end of the first variable sized field is the last offset
+        let mut frame_end = buf.len();
+        let mut pos: usize = 0;
+        let mut fields = Vec::with_capacity(types.len());
+        for (idx, typ) in types.iter().enumerate() {
+            let start = pos.next_multiple_of(alignment(typ));
+            let end = match fixed_size(typ) {
+                Some(size) => start + size,
+                None if idx + 1 == types.len() => frame_end,
+                None => {
+                    if frame_end < osize {
+                        return Err(UnmarshalError::InvalidFramingOffset);
+                    }
+                    frame_end -= osize;
+                    read_offset(&buf[frame_end..frame_end + osize])
+                }
+            };
+            if start > end || end > frame_end {
+                return Err(UnmarshalError::InvalidFramingOffset);
+            }
+            fields.push(self.param(typ, &buf[start..end], depth)?);
+            pos = end;
+        }
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::{Array, Dict, Variant};
+
+    fn roundtrip(sig: &str, params: Vec<Param<'static, 'static>>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut fds = Vec::new();
+        marshal_body(&params, ByteOrder::LittleEndian, &mut buf, &mut fds).unwrap();
+        let sigs = Type::parse_description(sig).unwrap();
+        assert_eq!(
+            unmarshal_body(&sigs, ByteOrder::LittleEndian, &buf, &fds).unwrap(),
+            params
+        );
+        buf
+    }
+
+    fn array(element_sig: &str, values: Vec<Param<'static, 'static>>) -> Param<'static, 'static> {
+        Param::Container(Container::Array(Array {
+            element_sig: Type::parse_description(element_sig).unwrap().remove(0),
+            values,
+        }))
+    }
+
+    fn strct(fields: Vec<Param<'static, 'static>>) -> Param<'static, 'static> {
+        Param::Container(Container::Struct(fields))
+    }
+
+    fn variant(value: Param<'static, 'static>) -> Param<'static, 'static> {
+        Param::Container(Container::Variant(Box::new(Variant {
+            sig: value.sig(),
+            value,
+        })))
+    }
+
+    #[test]
+    fn test_marshal_traits() {
+        use std::collections::HashMap;
+
+        type Value = (String, Vec<u32>, HashMap<String, (u8, bool)>);
+        let value: Value = (
+            "name".to_owned(),
+            vec![1, 2, 3],
+            // one entry, params keep dicts in a HashMap
+            HashMap::from([("a".to_owned(), (1, true))]),
+        );
+        for byteorder in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut buf = Vec::new();
+            let mut fds = Vec::new();
+            marshal(&value, byteorder, &mut buf, &mut fds).unwrap();
+            assert_eq!(unmarshal::<Value>(byteorder, &buf, &fds).unwrap(), value);
+            // the same bytes as marshalling the params directly
+            let sig = Type::parse_description("(saua{s(yb)})").unwrap().remove(0);
+            let param = unmarshal_param(&sig, byteorder, &buf, &fds).unwrap();
+            let mut direct = Vec::new();
+            marshal_param(
+                &param,
+                &mut MarshalContext {
+                    fds: &mut fds,
+                    buf: &mut direct,
+                    byteorder,
+                },
+            )
+            .unwrap();
+            assert_eq!(direct, buf);
+        }
+        assert!(unmarshal::<Value>(ByteOrder::LittleEndian, b"\x01", &[]).is_err());
+    }
+
+    #[test]
+    fn test_dbus_bodies() {
+        let mut body = MarshalledMessageBody::new();
+        body.push_param("hello").unwrap();
+        body.push_param(42u32).unwrap();
+        body.push_param(vec![(1u8, "x")]).unwrap();
+        let mut buf = Vec::new();
+        let mut fds = Vec::new();
+        marshal_dbus_body(&body, &mut buf, &mut fds).unwrap();
+
+        let again = unmarshal_dbus_body("sua(ys)", ByteOrder::LittleEndian, &buf, &fds).unwrap();
+        assert_eq!(again.raw().0, "sua(ys)");
+        assert_eq!(again.raw().1, body.raw().1);
+        let (s, u, list) = again.parser().get3::<&str, u32, Vec<(u8, &str)>>().unwrap();
+        assert_eq!((s, u, list), ("hello", 42, vec![(1, "x")]));
+        assert!(matches!(
+            unmarshal_dbus_body("(", ByteOrder::LittleEndian, &buf, &fds),
+            Err(UnmarshalError::Validation(_))
+        ));
+    }
+
+    /// Byte layouts from the examples of the GVariant specification
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn test_spec_examples() {
+        let strings = ["i", "can", "has", "strings?"]
+            .map(|s| s.to_owned().into())
+            .to_vec();
+        let buf = roundtrip("as", vec![array("s", strings)]);
+        assert_eq!(buf, b"i\0can\0has\0strings?\0\x02\x06\x0a\x13");
+
+        let buf = roundtrip(
+            "(si)",
+            vec![strct(vec!["foo".to_owned().into(), (-1i32).into()])],
+        );
+        assert_eq!(buf, b"foo\0\xff\xff\xff\xff\x04");
+
+        let buf = roundtrip("(iy)", vec![strct(vec![96i32.into(), 0x41u8.into()])]);
+        assert_eq!(buf, b"\x60\0\0\0\x41\0\0\0");
+
+        let bools = [true, false, true].map(Param::from).to_vec();
+        assert_eq!(roundtrip("ab", vec![array("b", bools)]), [1, 0, 1]);
+
+        let mut map = DictMap::new();
+        map.insert("a key".to_owned().into(), variant(514i32.into()));
+        let dict = Param::Container(Container::Dict(Dict {
+            key_sig: signature::Base::String,
+            value_sig: Type::Container(signature::Container::Variant),
+            map,
+        }));
+        let buf = roundtrip("a{sv}", vec![dict]);
+        assert_eq!(buf, b"a key\0\0\0\x02\x02\0\0\0i\x06\x0f");
+    }
+
+    #[test]
+    fn test_nested_roundtrip() {
+        let nested = array(
+            "aq",
+            vec![
+                array("q", vec![1u16.into(), 2u16.into()]),
+                array("q", vec![]),
+                array("q", vec![3u16.into()]),
+            ],
+        );
+        let strings = array(
+            "as",
+            vec![array("s", vec!["a".to_owned().into()]), array("s", vec![])],
+        );
+        let inner = variant(strct(vec![
+            Base::ObjectPath("/io/killing/spark".into()).into(),
+            Base::Signature("a{sv}".into()).into(),
+            1.5f64.into(),
+        ]));
+        roundtrip(
+            "aaqaasvxb",
+            vec![nested, strings, variant(inner), (-5i64).into(), true.into()],
+        );
+
+        // big enough to need two byte framing offsets
+        let long = (0..100).map(|i| format!("string {}", i).into()).collect();
+        let buf = roundtrip("asy", vec![array("s", long), 7u8.into()]);
+        assert!(buf.len() > u8::MAX as usize);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let parse = |sig| Type::parse_description(sig).unwrap();
+        // the last offset points beyond the array
+        assert_eq!(
+            unmarshal_body(&parse("as"), ByteOrder::LittleEndian, b"a\0\x09", &[]),
+            Err(UnmarshalError::InvalidFramingOffset)
+        );
+        assert_eq!(
+            unmarshal_body(&parse("ab"), ByteOrder::LittleEndian, &[2], &[]),
+            Err(UnmarshalError::InvalidBoolean)
+        );
+        assert_eq!(
+            unmarshal_body(&parse("h"), ByteOrder::LittleEndian, &[0, 0, 0, 0], &[]),
+            Err(UnmarshalError::BadFdIndex(0))
+        );
+
+        // variants containing variants, one level too deep
+        let mut buf = vec![1, 0, b'y'];
+        for _ in 0..MAX_VARIANT_DEPTH {
+            buf.extend_from_slice(b"\0v");
+        }
+        assert!(unmarshal_body(&parse("v"), ByteOrder::LittleEndian, &buf, &[]).is_err());
+        buf.truncate(buf.len() - 2);
+        assert!(unmarshal_body(&parse("v"), ByteOrder::LittleEndian, &buf, &[]).is_ok());
+    }
+}
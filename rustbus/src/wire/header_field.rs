@@ -0,0 +1,318 @@
+//! The fields of the message header. They are marshalled as an array of `(yv)` structs: the field code and the value
+//! in a variant.
+
+use std::num::NonZeroU32;
+
+use crate::signature;
+use crate::wire::errors::{MarshalError, UnmarshalError};
+use crate::wire::marshal::traits::SignatureBuffer;
+use crate::wire::marshal::MarshalContext;
+use crate::wire::unmarshal::traits::Variant;
+use crate::wire::unmarshal::UnmarshalResult;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::wire::util;
+use crate::wire::wrapper_types::var_dict;
+use crate::wire::{BusName, ErrorName, InterfaceName, MemberName, ObjectPath, SignatureWrapper};
+use crate::{ByteOrder, Marshal, Signature, Unmarshal};
+
+/// The different header fields a message may or maynot have. Use `HeaderField<&str>` to borrow the values from a
+/// buffer or a `DynamicHeader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderField<S: AsRef<str> = String> {
+    Path(ObjectPath<S>),
+    Interface(InterfaceName<S>),
+    Member(MemberName<S>),
+    ErrorName(ErrorName<S>),
+    ReplySerial(NonZeroU32),
+    Destination(BusName<S>),
+    Sender(BusName<S>),
+    Signature(SignatureWrapper<S>),
+    UnixFds(u32),
+}
+
+impl<S: AsRef<str>> HeaderField<S> {
+    /// The code that identifies the field on the wire
+    pub fn code(&self) -> u8 {
+        match self {
+            HeaderField::Path(_) => 1,
+            HeaderField::Interface(_) => 2,
+            HeaderField::Member(_) => 3,
+            HeaderField::ErrorName(_) => 4,
+            HeaderField::ReplySerial(_) => 5,
+            HeaderField::Destination(_) => 6,
+            HeaderField::Sender(_) => 7,
+            HeaderField::Signature(_) => 8,
+            HeaderField::UnixFds(_) => 9,
+        }
+    }
+}
+
+impl<S: AsRef<str>> Signature for HeaderField<S> {
+    fn signature() -> signature::Type {
+        signature::Type::Container(signature::Container::Struct(
+            signature::StructTypes::new(vec![u8::signature(), Variant::signature()]).unwrap(),
+        ))
+    }
+    #[inline]
+    fn alignment() -> usize {
+        8
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        s_buf.push_static("(yv)");
+    }
+    fn has_sig(sig: &str) -> bool {
+        sig == "(yv)"
+    }
+}
+
+impl<S: AsRef<str>> Marshal for HeaderField<S> {
+    fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+        ctx.align_to(8);
+        self.code().marshal(ctx)?;
+        match self {
+            HeaderField::Path(path) => path.marshal_as_variant(ctx),
+            HeaderField::Interface(interface) => interface.marshal_as_variant(ctx),
+            HeaderField::Member(member) => member.marshal_as_variant(ctx),
+            HeaderField::ErrorName(error_name) => error_name.marshal_as_variant(ctx),
+            HeaderField::ReplySerial(serial) => serial.get().marshal_as_variant(ctx),
+            HeaderField::Destination(destination) => destination.marshal_as_variant(ctx),
+            HeaderField::Sender(sender) => sender.marshal_as_variant(ctx),
+            HeaderField::Signature(sig) => sig.marshal_as_variant(ctx),
+            HeaderField::UnixFds(fds) => fds.marshal_as_variant(ctx),
+        }
+    }
+}
+
+/// A header field with a code that is not part of the spec (yet), e.g. the container instance field used by dbus-broker.
+/// Received ones are kept in `DynamicHeader::unknown_fields`, which is marshalled again when the message is sent, and
+/// can be inspected with `unmarshal::unmarshal_dynamic_header_with` or `RecvConn::set_unknown_header_field_handler`.
+///
+/// The value is kept marshalled in the native byteorder like the values of a `VarDict`, so the field can be marshalled
+/// into messages of either byteorder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownHeaderField {
+    /// The serial of the message the field was found in, None for fields made with `new`
+    pub serial: Option<NonZeroU32>,
+    pub code: u8,
+    sig: signature::Type,
+    buf: Vec<u8>,
+}
+
+impl UnknownHeaderField {
+    /// Make a custom field to send along with a message. The code must not be one of the fields defined by the spec.
+    pub fn new<T: Marshal + Signature>(code: u8, value: T) -> Result<Self, MarshalError> {
+        if code <= MAX_KNOWN_CODE {
+            return Err(MarshalError::InvalidHeaderField(code));
+        }
+        let mut buf = Vec::new();
+        let mut fds = Vec::new();
+        value.marshal(&mut MarshalContext {
+            fds: &mut fds,
+            buf: &mut buf,
+            byteorder: ByteOrder::NATIVE,
+        })?;
+        // the header can not carry unix fds
+        if !fds.is_empty() {
+            return Err(MarshalError::InvalidHeaderField(code));
+        }
+        Ok(UnknownHeaderField {
+            serial: None,
+            code,
+            sig: T::signature(),
+            buf,
+        })
+    }
+
+    /// Unmarshal the code and the value of a field that was skipped by `HeaderField::unmarshal`
+    pub(crate) fn unmarshal_unknown(
+        serial: NonZeroU32,
+        ctx: &mut UnmarshalContext,
+    ) -> UnmarshalResult<Self> {
+        ctx.align_to(8)?;
+        let code = ctx.read_u8()?;
+        let sig = var_dict::single_type(ctx.read_signature()?)?;
+        let mut buf = Vec::new();
+        var_dict::transcode(
+            &sig,
+            ctx,
+            &mut MarshalContext {
+                fds: &mut Vec::new(),
+                buf: &mut buf,
+                byteorder: ByteOrder::NATIVE,
+            },
+        )?;
+        Ok(UnknownHeaderField {
+            serial: Some(serial),
+            code,
+            sig,
+            buf,
+        })
+    }
+
+    /// The type of the value
+    pub fn signature(&self) -> &signature::Type {
+        &self.sig
+    }
+
+    /// The value, to be unmarshalled with `Variant::get`
+    pub fn value(&self) -> Variant<'_, '_> {
+        Variant {
+            sig: self.sig.clone(),
+            sub_ctx: UnmarshalContext::new(&[], ByteOrder::NATIVE, &self.buf, 0),
+        }
+    }
+
+    /// Get the value, `UnmarshalError::WrongSignature` if it has a different type
+    pub fn get<'a, T: Unmarshal<'a, 'a>>(&'a self) -> UnmarshalResult<T> {
+        self.value().get()
+    }
+}
+
+impl Signature for UnknownHeaderField {
+    fn signature() -> signature::Type {
+        HeaderField::<&str>::signature()
+    }
+    #[inline]
+    fn alignment() -> usize {
+        8
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        s_buf.push_static("(yv)");
+    }
+    fn has_sig(sig: &str) -> bool {
+        sig == "(yv)"
+    }
+}
+
+impl Marshal for UnknownHeaderField {
+    fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+        if self.code <= MAX_KNOWN_CODE {
+            return Err(MarshalError::InvalidHeaderField(self.code));
+        }
+        ctx.align_to(8);
+        self.code.marshal(ctx)?;
+        let mut sig = String::new();
+        self.sig.to_str(&mut sig);
+        util::write_signature(&sig, ctx.buf);
+        let mut value = UnmarshalContext::new(&[], ByteOrder::NATIVE, &self.buf, 0);
+        var_dict::transcode(&self.sig, &mut value, ctx).map_err(MarshalError::InvalidValue)
+    }
+}
+
+/// The custom header field rustbus puts the deadline of a call into, see `MarshalledMessage::set_deadline`. The value is
+/// a `u64` with the microseconds since the unix epoch. Peers that do not know the field ignore it, as the spec requires.
+pub const DEADLINE_FIELD_CODE: u8 = 128;
+
+/// The highest code defined by the spec, `HeaderField::UnixFds`
+const MAX_KNOWN_CODE: u8 = 9;
+
+/// Fields with an unknown code are skipped, the context is advanced past them and `UnmarshalError::UnknownHeaderField`
+/// is returned. The spec requires ignoring them, so the caller can carry on with the next field.
+impl<'buf, 'fds, S> Unmarshal<'buf, 'fds> for HeaderField<S>
+where
+    S: AsRef<str> + From<&'buf str> + Unmarshal<'buf, 'fds>,
+{
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> UnmarshalResult<Self> {
+        ctx.align_to(8)?;
+        let code = ctx.read_u8()?;
+        let value = Variant::unmarshal(ctx)?;
+        Ok(match code {
+            0 => return Err(UnmarshalError::InvalidHeaderField),
+            1 => HeaderField::Path(value.get()?),
+            2 => HeaderField::Interface(value.get()?),
+            3 => HeaderField::Member(value.get()?),
+            4 => HeaderField::ErrorName(value.get()?),
+            5 => HeaderField::ReplySerial(
+                NonZeroU32::new(value.get()?).ok_or(UnmarshalError::InvalidHeaderField)?,
+            ),
+            6 => HeaderField::Destination(value.get()?),
+            7 => HeaderField::Sender(value.get()?),
+            8 => HeaderField::Signature(value.get()?),
+            9 => HeaderField::UnixFds(value.get()?),
+            _ => return Err(UnmarshalError::UnknownHeaderField),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_field_roundtrip() {
+        let fields: Vec<HeaderField<&str>> = vec![
+            HeaderField::Path(ObjectPath::new("/io/killing/spark").unwrap()),
+            HeaderField::Interface(InterfaceName::new("io.killing.spark").unwrap()),
+            HeaderField::Member(MemberName::new("Member").unwrap()),
+            HeaderField::ErrorName(ErrorName::new("io.killing.spark.Error").unwrap()),
+            HeaderField::ReplySerial(NonZeroU32::new(42).unwrap()),
+            HeaderField::Destination(BusName::new(":1.42").unwrap()),
+            HeaderField::Sender(BusName::new("io.killing.spark").unwrap()),
+            HeaderField::Signature(SignatureWrapper::new("").unwrap()),
+            HeaderField::UnixFds(3),
+        ];
+        for byteorder in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut buf = Vec::new();
+            let mut ctx = MarshalContext {
+                fds: &mut Vec::new(),
+                buf: &mut buf,
+                byteorder,
+            };
+            fields.as_slice().marshal(&mut ctx).unwrap();
+
+            let mut ctx = UnmarshalContext::new(&[], byteorder, &buf, 0);
+            let unmarshalled: Vec<HeaderField<String>> = Vec::unmarshal(&mut ctx).unwrap();
+            assert!(ctx.remainder().is_empty());
+            assert_eq!(unmarshalled.len(), fields.len());
+            for (unmarshalled, field) in unmarshalled.iter().zip(&fields) {
+                assert_eq!(unmarshalled.code(), field.code());
+                assert_eq!(format!("{:?}", unmarshalled), format!("{:?}", field));
+            }
+        }
+    }
+
+    #[test]
+    fn test_header_field_invalid() {
+        let unmarshal = |code: u8, value: &dyn Fn(&mut MarshalContext)| {
+            let mut buf = Vec::new();
+            let mut ctx = MarshalContext {
+                fds: &mut Vec::new(),
+                buf: &mut buf,
+                byteorder: ByteOrder::LittleEndian,
+            };
+            code.marshal(&mut ctx).unwrap();
+            value(&mut ctx);
+            let mut ctx = UnmarshalContext::new(&[], ByteOrder::LittleEndian, &buf, 0);
+            let res = HeaderField::<&str>::unmarshal(&mut ctx).map(|field| field.code());
+            (res, ctx.remainder().len())
+        };
+        let string =
+            |s: &'static str| move |ctx: &mut MarshalContext| s.marshal_as_variant(ctx).unwrap();
+
+        assert_eq!(
+            unmarshal(2, &string("not an interface")).0,
+            Err(UnmarshalError::Validation(
+                crate::params::validation::Error::InvalidInterface
+            ))
+        );
+        assert_eq!(
+            unmarshal(1, &string("/a/string/not/a/path")).0,
+            Err(UnmarshalError::WrongSignature)
+        );
+        assert_eq!(
+            unmarshal(5, &|ctx| 0u32.marshal_as_variant(ctx).unwrap()).0,
+            Err(UnmarshalError::InvalidHeaderField)
+        );
+        assert_eq!(
+            unmarshal(0, &string("x")).0,
+            Err(UnmarshalError::InvalidHeaderField)
+        );
+        // unknown fields are skipped
+        assert_eq!(
+            unmarshal(100, &string("whatever")),
+            (Err(UnmarshalError::UnknownHeaderField), 0)
+        );
+    }
+}
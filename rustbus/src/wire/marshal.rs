@@ -3,16 +3,23 @@
 //! * `base` and `container` are for the Param approach that map dbus concepts to enums/structs
 //! * `traits` is for the trait based approach
 
+use std::convert::TryFrom;
 use std::num::NonZeroU32;
 
 use crate::message_builder;
-use crate::params;
 use crate::ByteOrder;
 
 use crate::wire::util::*;
+use crate::wire::{
+    BusName, ErrorName, HeaderField, InterfaceName, MemberName, ObjectPath, SignatureWrapper,
+};
+use traits::Marshal;
 
+#[cfg(rustbus_params)]
 mod param;
+#[cfg(rustbus_params)]
 pub use param::base;
+#[cfg(rustbus_params)]
 pub use param::container;
 pub mod traits;
 
@@ -42,11 +49,9 @@ pub fn marshal(
     pad_to_align(8, buf);
 
     // set the correct message length
-    insert_u32(
-        msg.body.byteorder(),
-        msg.get_buf().len() as u32,
-        &mut buf[4..8],
-    );
+    let body_len = u32::try_from(msg.get_buf().len())
+        .map_err(|_| crate::wire::errors::MarshalError::MessageTooLarge)?;
+    insert_u32(msg.body.byteorder(), body_len, &mut buf[4..8]);
     Ok(())
 }
 
@@ -91,129 +96,55 @@ fn marshal_header(
     let pos = buf.len();
     buf.extend_from_slice(&[0, 0, 0, 0]);
 
-    if let Some(serial) = msg.dynheader.response_serial {
-        marshal_header_reply_serial(byteorder, serial, buf)?;
+    let hdr = &msg.dynheader;
+    let mut ctx = MarshalContext {
+        fds: &mut Vec::new(),
+        buf,
+        byteorder,
+    };
+    if let Some(serial) = hdr.response_serial {
+        HeaderField::<&str>::ReplySerial(serial).marshal(&mut ctx)?;
     }
-    if let Some(int) = &msg.dynheader.interface {
-        marshal_header_interface(byteorder, int, buf)?;
+    if let Some(int) = &hdr.interface {
+        HeaderField::Interface(InterfaceName::new(int.as_str())?).marshal(&mut ctx)?;
     }
-    if let Some(dest) = &msg.dynheader.destination {
-        marshal_header_destination(byteorder, dest, buf)?;
+    if let Some(dest) = &hdr.destination {
+        HeaderField::Destination(BusName::new(dest.as_str())?).marshal(&mut ctx)?;
     }
-    if let Some(sender) = &msg.dynheader.sender {
-        marshal_header_sender(byteorder, sender, buf)?;
+    if let Some(sender) = &hdr.sender {
+        HeaderField::Sender(BusName::new(sender.as_str())?).marshal(&mut ctx)?;
     }
-    if let Some(mem) = &msg.dynheader.member {
-        marshal_header_member(byteorder, mem, buf)?;
+    if let Some(mem) = &hdr.member {
+        HeaderField::Member(MemberName::new(mem.as_str())?).marshal(&mut ctx)?;
     }
-    if let Some(obj) = &msg.dynheader.object {
-        marshal_header_path(byteorder, obj, buf)?;
+    if let Some(obj) = &hdr.object {
+        HeaderField::Path(ObjectPath::new(obj.as_str())?).marshal(&mut ctx)?;
     }
-    if let Some(err_name) = &msg.dynheader.error_name {
-        marshal_header_errorname(byteorder, err_name, buf)?;
+    if let Some(err_name) = &hdr.error_name {
+        HeaderField::ErrorName(ErrorName::new(err_name.as_str())?).marshal(&mut ctx)?;
     }
     if !msg.get_buf().is_empty() {
-        marshal_header_signature(msg.get_sig(), buf)?;
+        HeaderField::Signature(SignatureWrapper::new(msg.get_sig())?).marshal(&mut ctx)?;
+    }
+    // the fds in the body are the ones that get sent, so they decide what goes into the header
+    let num_fds = u32::try_from(msg.body.get_fds().len())
+        .map_err(|_| crate::wire::errors::MarshalError::MessageTooLarge)?;
+    if let Some(header) = msg.dynheader.num_fds {
+        if header != num_fds {
+            return Err(crate::wire::errors::MarshalError::NumFdsMismatch {
+                header,
+                body: num_fds,
+            });
+        }
     }
-    if !msg.body.get_fds().is_empty() {
-        marshal_header_unix_fds(byteorder, msg.body.get_fds().len() as u32, buf)?;
+    if num_fds > 0 {
+        HeaderField::<&str>::UnixFds(num_fds).marshal(&mut ctx)?;
+    }
+    for field in &hdr.unknown_fields {
+        field.marshal(&mut ctx)?;
     }
     let len = buf.len() - pos - 4; // -4 the bytes for the length indicator do not count
     insert_u32(byteorder, len as u32, &mut buf[pos..pos + 4]);
 
     Ok(())
 }
-
-fn marshal_header_field(field_no: u8, sig: &str, buf: &mut Vec<u8>) {
-    pad_to_align(8, buf);
-    buf.push(field_no);
-    buf.push(sig.len() as u8);
-    buf.extend_from_slice(sig.as_bytes());
-    buf.push(0);
-    pad_to_align(4, buf);
-}
-
-fn marshal_header_path(byteorder: ByteOrder, path: &str, buf: &mut Vec<u8>) -> MarshalResult<()> {
-    params::validate_object_path(path)?;
-    marshal_header_field(1, "o", buf);
-    write_string(path, byteorder, buf);
-    Ok(())
-}
-
-fn marshal_header_interface(
-    byteorder: ByteOrder,
-    interface: &str,
-    buf: &mut Vec<u8>,
-) -> MarshalResult<()> {
-    params::validate_interface(interface)?;
-    marshal_header_field(2, "s", buf);
-    write_string(interface, byteorder, buf);
-    Ok(())
-}
-
-fn marshal_header_member(
-    byteorder: ByteOrder,
-    member: &str,
-    buf: &mut Vec<u8>,
-) -> MarshalResult<()> {
-    params::validate_membername(member)?;
-    marshal_header_field(3, "s", buf);
-    write_string(member, byteorder, buf);
-    Ok(())
-}
-
-fn marshal_header_errorname(
-    byteorder: ByteOrder,
-    error: &str,
-    buf: &mut Vec<u8>,
-) -> MarshalResult<()> {
-    params::validate_errorname(error)?;
-    marshal_header_field(4, "s", buf);
-    write_string(error, byteorder, buf);
-    Ok(())
-}
-
-fn marshal_header_reply_serial(
-    byteorder: ByteOrder,
-    serial: NonZeroU32,
-    buf: &mut Vec<u8>,
-) -> MarshalResult<()> {
-    marshal_header_field(5, "u", buf);
-    write_u32(serial.get(), byteorder, buf);
-    Ok(())
-}
-
-fn marshal_header_destination(
-    byteorder: ByteOrder,
-    destination: &str,
-    buf: &mut Vec<u8>,
-) -> MarshalResult<()> {
-    params::validate_busname(destination)?;
-    marshal_header_field(6, "s", buf);
-    write_string(destination, byteorder, buf);
-    Ok(())
-}
-
-fn marshal_header_sender(
-    byteorder: ByteOrder,
-    sender: &str,
-    buf: &mut Vec<u8>,
-) -> MarshalResult<()> {
-    params::validate_busname(sender)?;
-    marshal_header_field(7, "s", buf);
-    write_string(sender, byteorder, buf);
-    Ok(())
-}
-
-fn marshal_header_signature(signature: &str, buf: &mut Vec<u8>) -> MarshalResult<()> {
-    params::validate_signature(signature)?;
-    marshal_header_field(8, "g", buf);
-    write_signature(signature, buf);
-    Ok(())
-}
-
-fn marshal_header_unix_fds(byteorder: ByteOrder, fds: u32, buf: &mut Vec<u8>) -> MarshalResult<()> {
-    marshal_header_field(9, "u", buf);
-    write_u32(fds, byteorder, buf);
-    Ok(())
-}
@@ -56,6 +56,7 @@ fn marshal_variant(var: &params::Variant, ctx: &mut MarshalContext) -> Result<()
     Ok(())
 }
 
+#[allow(clippy::mutable_key_type)]
 fn marshal_dict(dict: &params::DictMap, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
     ctx.align_to(4);
     let len_pos = ctx.buf.len();
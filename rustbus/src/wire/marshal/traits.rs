@@ -62,11 +62,11 @@ pub use container::*;
 /// # Implementing for your own structs
 /// There are some rules you need to follow, or the messages will be malformed:
 /// 1. Structs need to be aligned to 8 bytes. Use `ctx.align_to(8);` to do that. If your type is marshalled as a primitive type
-///     you still need to align to that types alignment.
+///    you still need to align to that types alignment.
 /// 1. If you write your own dict type, you need to align every key-value pair at 8 bytes like a struct
 /// 1. The signature needs to be correct, or the message will be malformed
 /// 1. The alignment must report the correct number. This does not need to be a constant like in the example, but it needs to be consistent with the type
-///     the signature() function returns. If you are not sure, just use Self::signature().get_alignment().
+///    the signature() function returns. If you are not sure, just use Self::signature().get_alignment().
 pub trait Marshal: Signature {
     fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), crate::wire::errors::MarshalError>;
     fn marshal_as_variant(
@@ -259,6 +259,7 @@ impl<P: Marshal> Marshal for &P {
 
 #[cfg(test)]
 mod test {
+    #[cfg(rustbus_params)]
     use crate::wire::marshal::MarshalContext;
     use crate::wire::ObjectPath;
     use crate::wire::SignatureWrapper;
@@ -293,6 +294,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(rustbus_params)]
     fn test_empty_array_padding() {
         use crate::wire::marshal::container::marshal_container_param;
 
@@ -6,6 +6,7 @@ use crate::wire::marshal::MarshalContext;
 use crate::wire::util;
 use crate::wire::ObjectPath;
 use crate::wire::SignatureWrapper;
+use crate::wire::{BusName, ErrorName, InterfaceName, MemberName};
 use crate::Marshal;
 use crate::Signature;
 
@@ -333,6 +334,39 @@ impl<S: AsRef<str>> Marshal for ObjectPath<S> {
     }
 }
 
+/// The names are marshalled as plain strings
+macro_rules! name_wrapper_marshal {
+    ($($name:ident),*) => {
+        $(
+            impl<S: AsRef<str>> Signature for $name<S> {
+                #[inline]
+                fn signature() -> crate::signature::Type {
+                    String::signature()
+                }
+                #[inline]
+                fn alignment() -> usize {
+                    String::alignment()
+                }
+                #[inline]
+                fn sig_str(s_buf: &mut SignatureBuffer) {
+                    String::sig_str(s_buf)
+                }
+                #[inline]
+                fn has_sig(sig: &str) -> bool {
+                    String::has_sig(sig)
+                }
+            }
+            impl<S: AsRef<str>> Marshal for $name<S> {
+                #[inline]
+                fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+                    self.as_ref().marshal(ctx)
+                }
+            }
+        )*
+    };
+}
+name_wrapper_marshal!(BusName, InterfaceName, MemberName, ErrorName);
+
 impl<S: AsRef<str>> Signature for SignatureWrapper<S> {
     #[inline]
     fn signature() -> crate::signature::Type {
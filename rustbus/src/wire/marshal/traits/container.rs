@@ -260,9 +260,8 @@ impl<E: Signature> Signature for [E] {
         E::sig_str(s_buf);
     }
     fn has_sig(sig: &str) -> bool {
-        if let Some(_prefix) = sig.strip_prefix('a') {
-            let mut iter = SignatureIter::new(&sig[1..]);
-            E::has_sig(iter.next().unwrap())
+        if let Some(elem) = sig.strip_prefix('a') {
+            SignatureIter::new(elem).next().is_some_and(E::has_sig)
         } else {
             false
         }
@@ -408,12 +407,11 @@ impl<K: Signature, V: Signature> Signature for std::collections::HashMap<K, V> {
         s_buf.push_str("}");
     }
     fn has_sig(sig: &str) -> bool {
-        if sig.starts_with("a{") {
-            let mut iter = SignatureIter::new(&sig[2..sig.len() - 1]);
-            K::has_sig(iter.next().unwrap()) && V::has_sig(iter.next().unwrap())
-        } else {
-            false
-        }
+        let Some(sig) = sig.strip_prefix("a{").and_then(|sig| sig.strip_suffix('}')) else {
+            return false;
+        };
+        let mut iter = SignatureIter::new(sig);
+        iter.next().is_some_and(K::has_sig) && iter.next().is_some_and(V::has_sig)
     }
 }
 
@@ -0,0 +1,1172 @@
+//! A serde `Serializer` into the wire format, available with the `serde` feature. It is the counterpart of
+//! `wire::deserialize` and lets types that already implement `serde::Serialize` be put into a message without also
+//! deriving `Marshal` for them.
+//!
+//! Serde values do not know their dbus type, so the signature has to be given, see
+//! `MarshalledMessageBody::push_serialized`. It decides how each value is written:
+//! * integers are checked to fit into the integer type of the signature, they can also be written as doubles
+//! * sequences and tuples can be written as arrays and structs
+//! * maps are written as dicts, structs as dbus structs or as dicts keyed by the field names (e.g. `a{sv}`). Fields
+//!   that are `None` are left out of dicts, at any depth, `None` can not be written anywhere else.
+//! * strings can be written as strings, object paths and signatures, enums with unit variants as strings
+//! * for variants the signature is inferred from the value, see `signature_of`. Structs with named fields are put
+//!   into variants as `a{sv}`. Empty sequences and maps have no signature and can not be put into variants.
+//!
+//! ```rust
+//! use rustbus::message_builder::MarshalledMessageBody;
+//! use serde::Serialize;
+//!
+//! #[derive(Serialize)]
+//! #[serde(rename_all = "PascalCase")]
+//! struct Device {
+//!     name: String,
+//!     rssi: Option<i16>,
+//!     uuids: Vec<String>,
+//! }
+//!
+//! let device = Device { name: "speaker".into(), rssi: None, uuids: vec!["110a".into()] };
+//! let mut body = MarshalledMessageBody::new();
+//! body.push_serialized(&("/dev/1", &device), "oa{sv}").unwrap();
+//! assert_eq!(body.raw().0, "oa{sv}");
+//! ```
+//!
+//! Unix fds can not be serialized, their ownership can not be expressed through serde.
+
+use serde::ser::{self, Error as _, Impossible, Serialize};
+use thiserror::Error;
+
+use crate::signature::{Base, Container, StructTypes, Type};
+use crate::wire::errors::MarshalError;
+use crate::wire::marshal::MarshalContext;
+use crate::wire::util::{insert_u32, write_signature, write_string};
+
+/// Errors that can occur while serializing a value with serde
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum SerializeError {
+    /// The value could not be marshalled
+    #[error("The value could not be marshalled: {0}")]
+    Marshal(#[from] MarshalError),
+    /// The value does not fit the signature
+    #[error("Expected a value of type '{expected}' but got {found}")]
+    WrongType {
+        expected: String,
+        found: &'static str,
+    },
+    /// Dbus has no null value, `None` can only be left out of dicts
+    #[error("Dbus has no null value, None can only be left out of dicts")]
+    NoneValue,
+    /// The type that is serialized reported an error, or its signature could not be inferred
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl ser::Error for SerializeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        SerializeError::Custom(msg.to_string())
+    }
+}
+
+impl From<crate::params::validation::Error> for SerializeError {
+    fn from(e: crate::params::validation::Error) -> Self {
+        SerializeError::Marshal(e.into())
+    }
+}
+
+type Result<T> = std::result::Result<T, SerializeError>;
+
+/// Infer the signature of a value, as it is done for values in variants. Integers keep their width (`i8` becomes
+/// `n`, there is no signed byte), sequences become arrays of the type of their first element, tuples and tuple
+/// structs become dbus structs and maps become dicts. Structs with named fields become `a{sv}`, so their `None`
+/// fields can be left out. Empty sequences and maps and `None` have no signature.
+pub fn signature_of<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    let mut sig = String::new();
+    value.serialize(Inferrer)?.to_str(&mut sig);
+    crate::params::validate_signature(&sig)?;
+    Ok(sig)
+}
+
+/// Serialize `value` as the params described by `types`. A single param is serialized from the value itself,
+/// multiple params from a tuple, a struct or a sequence with one element per param.
+pub(crate) fn serialize_params<T: Serialize + ?Sized>(
+    ctx: &mut MarshalContext,
+    types: &[Type],
+    value: &T,
+) -> Result<()> {
+    match types {
+        [] => Err(SerializeError::custom("the signature is empty")),
+        [typ] => serialize_value(ctx, typ, value),
+        _ => {
+            let sig = Type::Container(Container::Struct(
+                StructTypes::new(types.to_vec()).map_err(MarshalError::from)?,
+            ));
+            // the params are not aligned like a struct would be, each of them is aligned on its own
+            value.serialize(Serializer { ctx, sig: &sig })
+        }
+    }
+}
+
+/// Serialize the value as `sig`. For variants the signature of the value is inferred and written first.
+fn serialize_value<T: Serialize + ?Sized>(
+    ctx: &mut MarshalContext,
+    sig: &Type,
+    value: &T,
+) -> Result<()> {
+    if *sig == Type::Container(Container::Variant) {
+        let inner = value.serialize(Inferrer)?;
+        let mut desc = String::new();
+        inner.to_str(&mut desc);
+        crate::params::validate_signature(&desc)?;
+        write_signature(&desc, ctx.buf);
+        return serialize_value(ctx, &inner, value);
+    }
+    ctx.align_to(sig.get_alignment());
+    value.serialize(Serializer { ctx, sig })
+}
+
+fn type_name(sig: &Type) -> String {
+    let mut name = String::new();
+    sig.to_str(&mut name);
+    name
+}
+
+/// Serializes the next value of type `sig` into the context
+struct Serializer<'a, 'fds, 'buf> {
+    ctx: &'a mut MarshalContext<'fds, 'buf>,
+    sig: &'a Type,
+}
+
+impl<'a, 'fds, 'buf> Serializer<'a, 'fds, 'buf> {
+    fn wrong_type<T>(&self, found: &'static str) -> Result<T> {
+        Err(SerializeError::WrongType {
+            expected: type_name(self.sig),
+            found,
+        })
+    }
+
+    fn base(&self) -> Option<Base> {
+        match self.sig {
+            Type::Base(base) => Some(*base),
+            Type::Container(_) => None,
+        }
+    }
+
+    fn serialize_int(self, value: i128, found: &'static str) -> Result<()> {
+        use std::convert::TryFrom;
+
+        let sig = self.sig;
+        let out_of_range = |_: std::num::TryFromIntError| {
+            SerializeError::custom(format!(
+                "{} does not fit into a value of type '{}'",
+                value,
+                type_name(sig)
+            ))
+        };
+        let base = self.base();
+        let byteorder = self.ctx.byteorder;
+        let buf = &mut *self.ctx.buf;
+        match base {
+            Some(Base::Byte) => buf.push(u8::try_from(value).map_err(out_of_range)?),
+            Some(Base::Int16) => {
+                let value = i16::try_from(value).map_err(out_of_range)?;
+                crate::wire::util::write_u16(value as u16, byteorder, buf);
+            }
+            Some(Base::Uint16) => {
+                let value = u16::try_from(value).map_err(out_of_range)?;
+                crate::wire::util::write_u16(value, byteorder, buf);
+            }
+            Some(Base::Int32) => {
+                let value = i32::try_from(value).map_err(out_of_range)?;
+                crate::wire::util::write_u32(value as u32, byteorder, buf);
+            }
+            Some(Base::Uint32) => {
+                let value = u32::try_from(value).map_err(out_of_range)?;
+                crate::wire::util::write_u32(value, byteorder, buf);
+            }
+            Some(Base::Int64) => {
+                let value = i64::try_from(value).map_err(out_of_range)?;
+                crate::wire::util::write_u64(value as u64, byteorder, buf);
+            }
+            Some(Base::Uint64) => {
+                let value = u64::try_from(value).map_err(out_of_range)?;
+                crate::wire::util::write_u64(value, byteorder, buf);
+            }
+            Some(Base::Double) => {
+                crate::wire::util::write_u64((value as f64).to_bits(), byteorder, buf)
+            }
+            _ => return self.wrong_type(found),
+        }
+        Ok(())
+    }
+
+    /// Start a struct, an array or a dict
+    fn compound(self, found: &'static str) -> Result<Compound<'a, 'fds, 'buf>> {
+        match self.sig {
+            Type::Container(Container::Struct(fields)) => Ok(Compound::Struct {
+                ctx: self.ctx,
+                fields: fields.as_ref().iter(),
+            }),
+            Type::Container(Container::Array(elem)) => {
+                let len_pos = self.ctx.buf.len();
+                self.ctx.buf.extend_from_slice(&[0; 4]);
+                // the padding before the first element is not part of the length
+                self.ctx.align_to(elem.get_alignment());
+                let start = self.ctx.buf.len();
+                Ok(Compound::Array {
+                    ctx: self.ctx,
+                    elem,
+                    len_pos,
+                    start,
+                })
+            }
+            Type::Container(Container::Dict(key, value)) => {
+                let len_pos = self.ctx.buf.len();
+                self.ctx.buf.extend_from_slice(&[0; 4]);
+                self.ctx.align_to(8);
+                let start = self.ctx.buf.len();
+                Ok(Compound::Dict {
+                    ctx: self.ctx,
+                    key: Type::Base(*key),
+                    value,
+                    len_pos,
+                    start,
+                })
+            }
+            _ => self.wrong_type(found),
+        }
+    }
+}
+
+impl<'a, 'fds, 'buf> ser::Serializer for Serializer<'a, 'fds, 'buf> {
+    type Ok = ();
+    type Error = SerializeError;
+    type SerializeSeq = Compound<'a, 'fds, 'buf>;
+    type SerializeTuple = Compound<'a, 'fds, 'buf>;
+    type SerializeTupleStruct = Compound<'a, 'fds, 'buf>;
+    type SerializeTupleVariant = Impossible<(), SerializeError>;
+    type SerializeMap = Compound<'a, 'fds, 'buf>;
+    type SerializeStruct = Compound<'a, 'fds, 'buf>;
+    type SerializeStructVariant = Impossible<(), SerializeError>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        if self.base() != Some(Base::Boolean) {
+            return self.wrong_type("a bool");
+        }
+        crate::wire::util::write_u32(u32::from(v), self.ctx.byteorder, self.ctx.buf);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.serialize_int(v.into(), "an integer")
+    }
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_int(v.into(), "an integer")
+    }
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_int(v.into(), "an integer")
+    }
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.serialize_int(v.into(), "an integer")
+    }
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.serialize_int(v, "an integer")
+    }
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.serialize_int(v.into(), "an integer")
+    }
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.serialize_int(v.into(), "an integer")
+    }
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.serialize_int(v.into(), "an integer")
+    }
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.serialize_int(v.into(), "an integer")
+    }
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        use std::convert::TryFrom;
+
+        match i128::try_from(v) {
+            Ok(v) => self.serialize_int(v, "an integer"),
+            Err(_) => Err(SerializeError::custom(format!(
+                "{} is too large for dbus",
+                v
+            ))),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.serialize_f64(v.into())
+    }
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        if self.base() != Some(Base::Double) {
+            return self.wrong_type("a float");
+        }
+        crate::wire::util::write_u64(v.to_bits(), self.ctx.byteorder, self.ctx.buf);
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        match self.base() {
+            Some(Base::String) => {
+                if v.contains('\0') {
+                    return Err(crate::params::validation::Error::StringContainsNullByte.into());
+                }
+                write_string(v, self.ctx.byteorder, self.ctx.buf);
+            }
+            Some(Base::ObjectPath) => {
+                crate::params::validate_object_path(v)?;
+                write_string(v, self.ctx.byteorder, self.ctx.buf);
+            }
+            Some(Base::Signature) => {
+                crate::params::validate_signature(v)?;
+                write_signature(v, self.ctx.buf);
+            }
+            _ => return self.wrong_type("a string"),
+        }
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        if *self.sig != Type::Container(Container::Array(Box::new(Type::Base(Base::Byte)))) {
+            return self.wrong_type("bytes");
+        }
+        if v.len() > crate::wire::unmarshal::MAX_ARRAY_LEN {
+            return Err(MarshalError::MessageTooLarge.into());
+        }
+        crate::wire::util::write_u32(v.len() as u32, self.ctx.byteorder, self.ctx.buf);
+        self.ctx.buf.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Err(SerializeError::NoneValue)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        serialize_value(self.ctx, self.sig, value)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.wrong_type("()")
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.wrong_type("a unit struct")
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        if self.base() != Some(Base::String) {
+            return self.wrong_type("an enum variant");
+        }
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        serialize_value(self.ctx, self.sig, value)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Err(SerializeError::custom(
+            "only enums with unit variants can be serialized",
+        ))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a, 'fds, 'buf>> {
+        if matches!(self.sig, Type::Container(Container::Dict(..))) {
+            return self.wrong_type("a sequence");
+        }
+        self.compound("a sequence")
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a, 'fds, 'buf>> {
+        self.serialize_seq(None)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a, 'fds, 'buf>> {
+        self.serialize_seq(None)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(SerializeError::custom(
+            "only enums with unit variants can be serialized",
+        ))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a, 'fds, 'buf>> {
+        if !matches!(self.sig, Type::Container(Container::Dict(..))) {
+            return self.wrong_type("a map");
+        }
+        self.compound("a map")
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a, 'fds, 'buf>> {
+        match self.sig {
+            Type::Container(Container::Dict(Base::String, _)) => self.compound("a struct"),
+            Type::Container(Container::Struct(_)) => self.compound("a struct"),
+            _ => self.wrong_type("a struct"),
+        }
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(SerializeError::custom(
+            "only enums with unit variants can be serialized",
+        ))
+    }
+}
+
+/// A struct, an array or a dict that is being serialized
+enum Compound<'a, 'fds, 'buf> {
+    Struct {
+        ctx: &'a mut MarshalContext<'fds, 'buf>,
+        fields: std::slice::Iter<'a, Type>,
+    },
+    Array {
+        ctx: &'a mut MarshalContext<'fds, 'buf>,
+        elem: &'a Type,
+        len_pos: usize,
+        start: usize,
+    },
+    Dict {
+        ctx: &'a mut MarshalContext<'fds, 'buf>,
+        key: Type,
+        value: &'a Type,
+        len_pos: usize,
+        start: usize,
+    },
+}
+
+impl Compound<'_, '_, '_> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let result = match self {
+            Compound::Struct { ctx, fields } => match fields.next() {
+                Some(field) => serialize_value(ctx, field, value),
+                None => Err(SerializeError::custom(
+                    "there are more values than the struct has fields",
+                )),
+            },
+            Compound::Array { ctx, elem, .. } => serialize_value(ctx, elem, value),
+            Compound::Dict { .. } => Err(SerializeError::custom(
+                "a dict can only be serialized from a map",
+            )),
+        };
+        // only None fields of dicts are left out, a NoneValue that reaches one of them comes from the field itself
+        result.map_err(|e| match e {
+            SerializeError::NoneValue => {
+                SerializeError::custom("None can only be left out of dicts")
+            }
+            e => e,
+        })
+    }
+
+    fn end(self) -> Result<()> {
+        match self {
+            Compound::Struct { fields, .. } => match fields.len() {
+                0 => Ok(()),
+                left => Err(SerializeError::custom(format!(
+                    "the struct has {} more fields than were serialized",
+                    left
+                ))),
+            },
+            Compound::Array {
+                ctx,
+                len_pos,
+                start,
+                ..
+            }
+            | Compound::Dict {
+                ctx,
+                len_pos,
+                start,
+                ..
+            } => {
+                let len = ctx.buf.len() - start;
+                if len > crate::wire::unmarshal::MAX_ARRAY_LEN {
+                    return Err(MarshalError::MessageTooLarge.into());
+                }
+                insert_u32(
+                    ctx.byteorder,
+                    len as u32,
+                    &mut ctx.buf[len_pos..len_pos + 4],
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeMap for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        match self {
+            Compound::Dict { ctx, key: sig, .. } => {
+                // dict entries are aligned like structs
+                ctx.align_to(8);
+                serialize_value(ctx, sig, key)
+            }
+            _ => Err(SerializeError::custom(
+                "only dicts can be serialized from a map",
+            )),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        match self {
+            Compound::Dict {
+                ctx, value: sig, ..
+            } => serialize_value(ctx, sig, value),
+            _ => Err(SerializeError::custom(
+                "only dicts can be serialized from a map",
+            )),
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_, '_, '_> {
+    type Ok = ();
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        match self {
+            Compound::Dict {
+                ctx,
+                key,
+                value: sig,
+                ..
+            } => {
+                let entry = ctx.buf.len();
+                ctx.align_to(8);
+                serialize_value(ctx, key, name)?;
+                match serialize_value(ctx, sig, value) {
+                    // fields that are None are left out, nested Nones are reported as other errors
+                    Err(SerializeError::NoneValue) => {
+                        ctx.buf.truncate(entry);
+                        Ok(())
+                    }
+                    result => result,
+                }
+            }
+            _ => self.element(value),
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+/// Infers the signature of a value, see `signature_of`
+struct Inferrer;
+
+impl Inferrer {
+    fn base(base: Base) -> Result<Type> {
+        Ok(Type::Base(base))
+    }
+
+    fn unsupported<T>(what: &str) -> Result<T> {
+        Err(SerializeError::custom(format!(
+            "the signature of {} can not be inferred",
+            what
+        )))
+    }
+}
+
+impl ser::Serializer for Inferrer {
+    type Ok = Type;
+    type Error = SerializeError;
+    type SerializeSeq = InferSeq;
+    type SerializeTuple = InferStruct;
+    type SerializeTupleStruct = InferStruct;
+    type SerializeTupleVariant = Impossible<Type, SerializeError>;
+    type SerializeMap = InferMap;
+    type SerializeStruct = InferVarDict;
+    type SerializeStructVariant = Impossible<Type, SerializeError>;
+
+    fn serialize_bool(self, _: bool) -> Result<Type> {
+        Self::base(Base::Boolean)
+    }
+    // there is no signed byte
+    fn serialize_i8(self, _: i8) -> Result<Type> {
+        Self::base(Base::Int16)
+    }
+    fn serialize_i16(self, _: i16) -> Result<Type> {
+        Self::base(Base::Int16)
+    }
+    fn serialize_i32(self, _: i32) -> Result<Type> {
+        Self::base(Base::Int32)
+    }
+    fn serialize_i64(self, _: i64) -> Result<Type> {
+        Self::base(Base::Int64)
+    }
+    fn serialize_u8(self, _: u8) -> Result<Type> {
+        Self::base(Base::Byte)
+    }
+    fn serialize_u16(self, _: u16) -> Result<Type> {
+        Self::base(Base::Uint16)
+    }
+    fn serialize_u32(self, _: u32) -> Result<Type> {
+        Self::base(Base::Uint32)
+    }
+    fn serialize_u64(self, _: u64) -> Result<Type> {
+        Self::base(Base::Uint64)
+    }
+    fn serialize_f32(self, _: f32) -> Result<Type> {
+        Self::base(Base::Double)
+    }
+    fn serialize_f64(self, _: f64) -> Result<Type> {
+        Self::base(Base::Double)
+    }
+    fn serialize_char(self, _: char) -> Result<Type> {
+        Self::base(Base::String)
+    }
+    fn serialize_str(self, _: &str) -> Result<Type> {
+        Self::base(Base::String)
+    }
+    fn serialize_bytes(self, _: &[u8]) -> Result<Type> {
+        Ok(Type::Container(Container::Array(Box::new(Type::Base(
+            Base::Byte,
+        )))))
+    }
+    fn serialize_none(self) -> Result<Type> {
+        Err(SerializeError::NoneValue)
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Type> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Type> {
+        Self::unsupported("()")
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Type> {
+        Self::unsupported("a unit struct")
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Type> {
+        Self::base(Base::String)
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Type> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Type> {
+        Self::unsupported("an enum variant with data")
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<InferSeq> {
+        Ok(InferSeq { elem: None })
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<InferStruct> {
+        Ok(InferStruct { fields: Vec::new() })
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<InferStruct> {
+        Ok(InferStruct { fields: Vec::new() })
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Self::unsupported("an enum variant with data")
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<InferMap> {
+        Ok(InferMap {
+            key: None,
+            value: None,
+        })
+    }
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<InferVarDict> {
+        Ok(InferVarDict)
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Self::unsupported("an enum variant with data")
+    }
+}
+
+/// Nested `None`s are not left out like `None` fields of dicts, they are errors
+fn infer_nested<T: Serialize + ?Sized>(value: &T) -> Result<Type> {
+    value.serialize(Inferrer).map_err(|e| match e {
+        SerializeError::NoneValue => {
+            SerializeError::custom("the signature of None can not be inferred")
+        }
+        e => e,
+    })
+}
+
+/// Arrays have the type of their first element, the others have to have the same type
+struct InferSeq {
+    elem: Option<Type>,
+}
+
+impl ser::SerializeSeq for InferSeq {
+    type Ok = Type;
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let typ = infer_nested(value)?;
+        match &self.elem {
+            Some(elem) if *elem != typ => Err(SerializeError::custom(format!(
+                "the elements of a sequence have different types '{}' and '{}'",
+                type_name(elem),
+                type_name(&typ)
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                self.elem = Some(typ);
+                Ok(())
+            }
+        }
+    }
+
+    fn end(self) -> Result<Type> {
+        match self.elem {
+            Some(elem) => Ok(Type::Container(Container::Array(Box::new(elem)))),
+            None => Inferrer::unsupported("an empty sequence"),
+        }
+    }
+}
+
+struct InferStruct {
+    fields: Vec<Type>,
+}
+
+impl InferStruct {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.fields.push(infer_nested(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Type> {
+        StructTypes::new(self.fields)
+            .map(|fields| Type::Container(Container::Struct(fields)))
+            .or_else(|_| Inferrer::unsupported("an empty struct"))
+    }
+}
+
+impl ser::SerializeTuple for InferStruct {
+    type Ok = Type;
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Type> {
+        InferStruct::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for InferStruct {
+    type Ok = Type;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Type> {
+        InferStruct::end(self)
+    }
+}
+
+/// Structs with named fields are `a{sv}`, the fields are inferred when they are serialized into their variants
+struct InferVarDict;
+
+impl ser::SerializeStruct for InferVarDict {
+    type Ok = Type;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _name: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn end(self) -> Result<Type> {
+        Ok(Type::Container(Container::Dict(
+            Base::String,
+            Box::new(Type::Container(Container::Variant)),
+        )))
+    }
+}
+
+/// Dicts have the key and value types of their first entry
+struct InferMap {
+    key: Option<Base>,
+    value: Option<Type>,
+}
+
+impl ser::SerializeMap for InferMap {
+    type Ok = Type;
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        match infer_nested(key)? {
+            Type::Base(base) => {
+                self.key.get_or_insert(base);
+                Ok(())
+            }
+            Type::Container(_) => Inferrer::unsupported("a dict with keys that are not base types"),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let typ = infer_nested(value)?;
+        self.value.get_or_insert(typ);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Type> {
+        match (self.key, self.value) {
+            (Some(key), Some(value)) => Ok(Type::Container(Container::Dict(key, Box::new(value)))),
+            _ => Inferrer::unsupported("an empty map"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{signature_of, SerializeError};
+    use crate::message_builder::MarshalledMessageBody;
+    use crate::wire::marshal::traits::Variant;
+    use crate::wire::{ObjectPath, VarDict};
+    use crate::ByteOrder;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum State {
+        Active,
+        Inactive,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Unit {
+        name: String,
+        path: String,
+        pid: u32,
+        state: State,
+        ratios: Vec<f64>,
+    }
+
+    #[test]
+    fn test_serialize_params() {
+        let unit = Unit {
+            name: "sshd.service".into(),
+            path: "/unit/sshd".into(),
+            pid: 42,
+            state: State::Active,
+            ratios: vec![0.5, 1.5],
+        };
+        for byteorder in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut body = MarshalledMessageBody::with_byteorder(byteorder);
+            body.push_param(7u8).unwrap();
+            body.push_serialized(&unit, "sovsad").unwrap();
+            assert_eq!(body.raw().0, "ysovsad");
+            assert!(body.validate().is_ok());
+
+            // the same bytes as with the Marshal impls
+            let mut expected = MarshalledMessageBody::with_byteorder(byteorder);
+            expected.push_param(7u8).unwrap();
+            expected
+                .push_param5(
+                    "sshd.service",
+                    ObjectPath::new("/unit/sshd").unwrap(),
+                    Variant(42u32),
+                    "Active",
+                    &[0.5f64, 1.5][..],
+                )
+                .unwrap();
+            assert_eq!(body.raw().1, expected.raw().1);
+
+            let (_, parsed): (u8, Unit) = {
+                let mut parser = body.parser();
+                let byte = parser.get::<u8>().unwrap();
+                let name = parser.get::<&str>().unwrap();
+                let path = parser.get::<ObjectPath<&str>>().unwrap();
+                let pid = parser
+                    .get::<crate::wire::unmarshal::traits::Variant>()
+                    .unwrap();
+                let state = parser.get::<&str>().unwrap();
+                let ratios = parser.get::<Vec<f64>>().unwrap();
+                (
+                    byte,
+                    Unit {
+                        name: name.into(),
+                        path: path.to_string(),
+                        pid: pid.get().unwrap(),
+                        state: if state == "Active" {
+                            State::Active
+                        } else {
+                            State::Inactive
+                        },
+                        ratios,
+                    },
+                )
+            };
+            assert_eq!(parsed, unit);
+        }
+
+        // a body with only the serialized params round trips through the Deserializer
+        let mut body = MarshalledMessageBody::new();
+        body.push_serialized(&unit, "sovsad").unwrap();
+        assert_eq!(body.parse_into::<Unit>().unwrap(), unit);
+    }
+
+    #[test]
+    fn test_serialize_dict() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        #[serde(rename_all = "PascalCase")]
+        struct Props {
+            name: String,
+            volume: u32,
+            muted: Option<bool>,
+            tags: BTreeMap<String, (u8, bool)>,
+            position: (i16, i16),
+        }
+
+        let mut tags = BTreeMap::new();
+        tags.insert("front".to_owned(), (1u8, true));
+        let props = Props {
+            name: "speaker".into(),
+            volume: 7,
+            muted: None,
+            tags,
+            position: (-1, 2),
+        };
+        let mut body = MarshalledMessageBody::new();
+        body.push_serialized(&props, "a{sv}").unwrap();
+        let dict: VarDict = body.parser().get().unwrap();
+        assert_eq!(dict.len(), 4);
+        assert_eq!(dict.get::<&str>("Name"), Ok(Some("speaker")));
+        assert_eq!(dict.get::<u32>("Volume"), Ok(Some(7)));
+        assert_eq!(dict.get::<bool>("Muted"), Ok(None));
+        assert_eq!(
+            dict.get::<HashMap<&str, (u8, bool)>>("Tags")
+                .unwrap()
+                .unwrap()["front"],
+            (1, true)
+        );
+        assert_eq!(dict.get::<(i16, i16)>("Position"), Ok(Some((-1, 2))));
+        assert_eq!(body.parse_into::<Props>().unwrap(), props);
+
+        // None fields are also left out of nested dicts and of structs in variants
+        #[derive(Serialize)]
+        struct Inner {
+            level: Option<u8>,
+            id: u32,
+        }
+        #[derive(Serialize)]
+        struct Outer {
+            inner: Option<Inner>,
+            missing: Option<Inner>,
+        }
+        let outer = Outer {
+            inner: Some(Inner { level: None, id: 3 }),
+            missing: None,
+        };
+        let mut body = MarshalledMessageBody::new();
+        body.push_serialized(&outer, "a{sv}").unwrap();
+        let dict: VarDict = body.parser().get().unwrap();
+        assert_eq!(dict.len(), 1);
+        let inner: VarDict = dict.get("inner").unwrap().unwrap();
+        assert_eq!(inner.len(), 1);
+        assert_eq!(inner.get::<u32>("id"), Ok(Some(3)));
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_serialized(&outer, "a{sa{sv}}").unwrap();
+        let dicts: HashMap<String, VarDict> = body.parser().get().unwrap();
+        assert_eq!(dicts.len(), 1);
+        assert_eq!(dicts["inner"].len(), 1);
+        assert_eq!(dicts["inner"].get::<u32>("id"), Ok(Some(3)));
+        // a None in a dbus struct inside of a dict is not left out
+        let mut body = MarshalledMessageBody::new();
+        assert!(matches!(
+            body.push_serialized(&outer, "a{s(yu)}"),
+            Err(SerializeError::Custom(_))
+        ));
+
+        // maps and typed dicts
+        let mut volumes = HashMap::new();
+        volumes.insert("left", 3u64);
+        let mut body = MarshalledMessageBody::new();
+        body.push_serialized(&volumes, "a{sy}").unwrap();
+        let parsed: HashMap<String, u8> = body.parser().get().unwrap();
+        assert_eq!(parsed["left"], 3);
+    }
+
+    #[test]
+    fn test_signature_of() {
+        assert_eq!(signature_of(&(1u8, -1i8, "a", 0.5f32)).unwrap(), "(ynsd)");
+        assert_eq!(signature_of(&vec![vec![1u32]]).unwrap(), "aau");
+        assert_eq!(
+            signature_of(&Unit {
+                name: "a".into(),
+                path: "/".into(),
+                pid: 1,
+                state: State::Active,
+                ratios: vec![],
+            })
+            .unwrap(),
+            "a{sv}"
+        );
+        let mut map = HashMap::new();
+        map.insert(1u16, Some(State::Inactive));
+        assert_eq!(signature_of(&map).unwrap(), "a{qs}");
+        assert!(matches!(
+            signature_of(&Vec::<u32>::new()),
+            Err(SerializeError::Custom(_))
+        ));
+        assert_eq!(
+            signature_of(&Option::<u32>::None),
+            Err(SerializeError::NoneValue)
+        );
+        assert!(matches!(
+            signature_of(&vec![Some(1u32), None]),
+            Err(SerializeError::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn test_serialize_errors() {
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(1u32).unwrap();
+        let before = body.raw().1.to_vec();
+
+        // nothing is left behind by a failed push
+        let errors = [
+            body.push_serialized(&300u32, "y").unwrap_err(),
+            body.push_serialized(&(1u32, "no path"), "uo").unwrap_err(),
+            body.push_serialized(&(1u32,), "(uu)").unwrap_err(),
+            body.push_serialized(&Option::<u32>::None, "u").unwrap_err(),
+            body.push_serialized(&"text", "u").unwrap_err(),
+            body.push_serialized(&1u32, "h").unwrap_err(),
+            body.push_serialized(&1u32, "").unwrap_err(),
+        ];
+        assert_eq!(body.raw().0, "u");
+        assert_eq!(body.raw().1, &before[..]);
+
+        assert!(matches!(errors[0], SerializeError::Custom(_)));
+        assert!(matches!(errors[1], SerializeError::Marshal(_)));
+        assert!(matches!(errors[2], SerializeError::Custom(_)));
+        assert_eq!(errors[3], SerializeError::NoneValue);
+        assert_eq!(
+            errors[4],
+            SerializeError::WrongType {
+                expected: "u".into(),
+                found: "a string"
+            }
+        );
+        assert!(matches!(errors[5], SerializeError::WrongType { .. }));
+        assert!(matches!(errors[6], SerializeError::Marshal(_)));
+    }
+}
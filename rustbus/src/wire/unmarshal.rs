@@ -11,21 +11,27 @@ use crate::message_builder::MarshalledMessage;
 use crate::message_builder::MarshalledMessageBody;
 use crate::message_builder::MessageType;
 use crate::params;
-use crate::signature;
 use crate::wire::errors::UnmarshalError;
 use crate::wire::util::*;
-use crate::wire::HeaderField;
+use crate::wire::{HeaderField, UnknownHeaderField};
 use crate::ByteOrder;
+use crate::Unmarshal;
 
+#[cfg(rustbus_params)]
 mod param;
+#[cfg(rustbus_params)]
 pub use param::base;
+#[cfg(rustbus_params)]
 pub use param::container;
+#[cfg(rustbus_params)]
 pub mod iter;
 pub mod traits;
 
+#[cfg(rustbus_params)]
 use container::*;
 
-use super::unmarshal_context::{Cursor, UnmarshalContext};
+use super::unmarshal_context::Cursor;
+use super::unmarshal_context::UnmarshalContext;
 use super::UnixFd;
 
 #[derive(Debug, Clone, Copy)]
@@ -55,6 +61,35 @@ impl UnmarshalError {
 pub type UnmarshalResult<T> = std::result::Result<T, UnmarshalError>;
 
 pub const HEADER_LEN: usize = 12;
+/// The maximum length of a whole message (header, padding and body) allowed by the dbus spec
+pub const MAX_MESSAGE_LEN: usize = 1 << 27;
+/// The maximum length of an array allowed by the dbus spec. This also limits the length of the header fields.
+pub const MAX_ARRAY_LEN: usize = 1 << 26;
+/// The most unix fds a message may announce in its header. Messages announcing more are rejected while the header is
+/// unmarshalled.
+pub const MAX_UNIX_FDS: u32 = 1024;
+
+/// Calculate the length of the whole message from the fixed header and the length of the header fields.
+/// This checks the lengths against the limits of the dbus spec before doing any arithmetic with them, so
+/// malicious values can not cause overflows or huge allocations.
+pub fn calc_message_len(header: &Header, header_fields_len: u32) -> UnmarshalResult<usize> {
+    let header_fields_len = header_fields_len as usize;
+    let body_len = header.body_len as usize;
+    if header_fields_len > MAX_ARRAY_LEN || body_len > MAX_MESSAGE_LEN {
+        return Err(UnmarshalError::MessageTooLarge);
+    }
+
+    // +4 because the length of the header fields does not count
+    let complete_header_size = HEADER_LEN + 4 + header_fields_len;
+    let padding_between_header_and_body = (8 - (complete_header_size % 8)) % 8;
+
+    let message_len = complete_header_size + padding_between_header_and_body + body_len;
+    if message_len > MAX_MESSAGE_LEN {
+        Err(UnmarshalError::MessageTooLarge)
+    } else {
+        Ok(message_len)
+    }
+}
 
 pub fn unmarshal_header(cursor: &mut Cursor) -> UnmarshalResult<Header> {
     if cursor.remainder().len() < HEADER_LEN {
@@ -94,15 +129,44 @@ pub fn unmarshal_dynamic_header(
     header: &Header,
     cursor: &mut Cursor,
 ) -> UnmarshalResult<DynamicHeader> {
-    let fields = unmarshal_header_fields(header, cursor)?;
+    unmarshal_header_fields_into(header, cursor, None)
+}
+
+/// Like `unmarshal_dynamic_header` but header fields with an unknown code are passed to `on_unknown` before they are
+/// skipped. This lets monitors and other tools see extensions of the protocol.
+pub fn unmarshal_dynamic_header_with(
+    header: &Header,
+    cursor: &mut Cursor,
+    on_unknown: &mut dyn FnMut(UnknownHeaderField),
+) -> UnmarshalResult<DynamicHeader> {
+    unmarshal_header_fields_into(header, cursor, Some(on_unknown))
+}
+
+fn unmarshal_header_fields_into(
+    header: &Header,
+    cursor: &mut Cursor,
+    mut on_unknown: Option<&mut dyn FnMut(UnknownHeaderField)>,
+) -> UnmarshalResult<DynamicHeader> {
+    let mut unknown_fields = Vec::new();
+    let fields = unmarshal_header_fields(header, cursor, &mut |field| {
+        if let Some(on_unknown) = on_unknown.as_mut() {
+            on_unknown(field.clone());
+        }
+        unknown_fields.push(field);
+    })?;
     let mut hdr = DynamicHeader {
         serial: Some(header.serial),
+        unknown_fields,
         ..Default::default()
     };
     collect_header_fields(&fields, &mut hdr);
-    Ok(hdr)
+    match hdr.num_fds {
+        Some(num_fds) if num_fds > MAX_UNIX_FDS => Err(UnmarshalError::TooManyUnixFds(num_fds)),
+        _ => Ok(hdr),
+    }
 }
 
+#[cfg(rustbus_params)]
 pub fn unmarshal_body(
     byteorder: ByteOrder,
     sigs: &[crate::signature::Type],
@@ -110,8 +174,30 @@ pub fn unmarshal_body(
     fds: &[crate::wire::UnixFd],
     offset: usize,
 ) -> UnmarshalResult<Vec<params::Param<'static, 'static>>> {
-    let mut params = Vec::new();
+    unmarshal_body_in(UnmarshalContext::new(fds, byteorder, buf, offset), sigs)
+}
+
+/// Like `unmarshal_body` but strings that are not valid UTF-8 are decoded with the invalid sequences replaced by U+FFFD,
+/// see `UnmarshalContext::set_lossy_strings`
+#[cfg(rustbus_params)]
+pub fn unmarshal_body_lossy(
+    byteorder: ByteOrder,
+    sigs: &[crate::signature::Type],
+    buf: &[u8],
+    fds: &[crate::wire::UnixFd],
+    offset: usize,
+) -> UnmarshalResult<Vec<params::Param<'static, 'static>>> {
     let mut ctx = UnmarshalContext::new(fds, byteorder, buf, offset);
+    ctx.set_lossy_strings(true);
+    unmarshal_body_in(ctx, sigs)
+}
+
+#[cfg(rustbus_params)]
+fn unmarshal_body_in(
+    mut ctx: UnmarshalContext,
+    sigs: &[crate::signature::Type],
+) -> UnmarshalResult<Vec<params::Param<'static, 'static>>> {
+    let mut params = Vec::new();
     for param_sig in sigs {
         let new_param = unmarshal_with_sig(param_sig, &mut ctx)?;
         params.push(new_param);
@@ -139,11 +225,12 @@ pub fn unmarshal_next_message(
         Ok(msg)
     } else {
         let offset = offset + padding;
+        let body_bytes = buf.len() - offset;
 
-        if buf[offset..].len() < (header.body_len as usize) {
+        if body_bytes < (header.body_len as usize) {
             return Err(UnmarshalError::NotEnoughBytes);
         }
-        if buf[offset..].len() != header.body_len as usize {
+        if body_bytes != header.body_len as usize {
             return Err(UnmarshalError::NotAllBytesUsed);
         }
 
@@ -157,40 +244,39 @@ pub fn unmarshal_next_message(
     }
 }
 
-fn unmarshal_header_fields(
+fn unmarshal_header_fields<'buf>(
     header: &Header,
-    cursor: &mut Cursor,
-) -> UnmarshalResult<Vec<HeaderField>> {
+    cursor: &mut Cursor<'buf>,
+    on_unknown: &mut dyn FnMut(UnknownHeaderField),
+) -> UnmarshalResult<Vec<HeaderField<&'buf str>>> {
     let header_fields_bytes = cursor.read_u32(header.byteorder)?;
 
+    if header_fields_bytes as usize > MAX_ARRAY_LEN {
+        return Err(UnmarshalError::MessageTooLarge);
+    }
     if cursor.remainder().len() < header_fields_bytes as usize {
         return Err(UnmarshalError::NotEnoughBytes);
     }
 
-    let mut cursor = Cursor::new(cursor.read_raw(header_fields_bytes as usize)?);
+    let fields_buf = cursor.read_raw(header_fields_bytes as usize)?;
+    let mut ctx = UnmarshalContext::new(&[], header.byteorder, fields_buf, 0);
     let mut fields = Vec::new();
 
-    while !cursor.remainder().is_empty() {
-        match unmarshal_header_field(header, &mut cursor) {
+    while !ctx.remainder().is_empty() {
+        // fields are aligned to 8, the fields start at a multiple of 8 in the message too
+        let start = (fields_buf.len() - ctx.remainder().len()).next_multiple_of(8);
+        match HeaderField::unmarshal(&mut ctx) {
             Ok(field) => {
                 fields.push(field);
             }
+            // the field was validated and skipped, the spec says to ignore unknown fields
             Err(UnmarshalError::UnknownHeaderField) => {
-                // try to validate that there is indeed a valid dbus variant. This is mandatory so the message follows the spec,
-                // even if we just ignore the contents.
-                match crate::wire::validate_raw::validate_marshalled(
-                    header.byteorder,
-                    0,
-                    cursor.remainder(),
-                    &crate::signature::Type::Container(crate::signature::Container::Variant),
-                ) {
-                    Ok(bytes) => {
-                        // ignore happy path, but increase counter.
-                        cursor.advance(bytes);
-                    }
-                    // if the unknown header contains invalid values this is still an error, and the message should be treated as unreadable
-                    Err((_bytes, err)) => return Err(err),
-                }
+                let raw = &fields_buf[start..fields_buf.len() - ctx.remainder().len()];
+                let mut field_ctx = UnmarshalContext::new(&[], header.byteorder, raw, 0);
+                on_unknown(UnknownHeaderField::unmarshal_unknown(
+                    header.serial,
+                    &mut field_ctx,
+                )?);
             }
             Err(e) => return Err(e),
         }
@@ -201,101 +287,17 @@ fn unmarshal_header_fields(
     Ok(fields)
 }
 
-fn unmarshal_header_field(header: &Header, cursor: &mut Cursor) -> UnmarshalResult<HeaderField> {
-    // align to 8 because the header fields are an array of structs `a(yv)`
-    cursor.align_to(8)?;
-
-    let typ = cursor.read_u8()?;
-
-    let sig_str = cursor.read_signature()?;
-    let mut sig =
-        signature::Type::parse_description(sig_str).map_err(|_| UnmarshalError::NoSignature)?;
-
-    if sig.len() != 1 {
-        // There must be exactly one type in the signature!
-        return Err(UnmarshalError::NoSignature);
-    }
-    let sig = sig.remove(0);
-    match typ {
-        1 => match sig {
-            signature::Type::Base(signature::Base::ObjectPath) => {
-                let objpath = cursor.read_str(header.byteorder)?;
-                crate::params::validate_object_path(objpath)?;
-                Ok(HeaderField::Path(objpath.to_owned()))
-            }
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        2 => match sig {
-            signature::Type::Base(signature::Base::String) => Ok(HeaderField::Interface(
-                cursor.read_str(header.byteorder)?.to_owned(),
-            )),
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        3 => match sig {
-            signature::Type::Base(signature::Base::String) => Ok(HeaderField::Member(
-                cursor.read_str(header.byteorder)?.to_owned(),
-            )),
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        4 => match sig {
-            signature::Type::Base(signature::Base::String) => Ok(HeaderField::ErrorName(
-                cursor.read_str(header.byteorder)?.to_owned(),
-            )),
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        5 => match sig {
-            signature::Type::Base(signature::Base::Uint32) => {
-                NonZeroU32::new(cursor.read_u32(header.byteorder)?)
-                    .ok_or(UnmarshalError::InvalidHeaderField)
-                    .map(HeaderField::ReplySerial)
-            }
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        6 => match sig {
-            signature::Type::Base(signature::Base::String) => Ok(HeaderField::Destination(
-                cursor.read_str(header.byteorder)?.to_owned(),
-            )),
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        7 => match sig {
-            signature::Type::Base(signature::Base::String) => Ok(HeaderField::Sender(
-                cursor.read_str(header.byteorder)?.to_owned(),
-            )),
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        8 => match sig {
-            signature::Type::Base(signature::Base::Signature) => {
-                let sig = cursor.read_signature()?;
-                // empty signature is allowed here
-                if !sig.is_empty() {
-                    crate::params::validate_signature(sig)?;
-                }
-                Ok(HeaderField::Signature(sig.to_owned()))
-            }
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        9 => match sig {
-            signature::Type::Base(signature::Base::Uint32) => {
-                Ok(HeaderField::UnixFds(cursor.read_u32(header.byteorder)?))
-            }
-            _ => Err(UnmarshalError::WrongSignature),
-        },
-        0 => Err(UnmarshalError::InvalidHeaderField),
-        _ => Err(UnmarshalError::UnknownHeaderField),
-    }
-}
-
-fn collect_header_fields(header_fields: &[HeaderField], hdr: &mut DynamicHeader) {
+fn collect_header_fields(header_fields: &[HeaderField<&str>], hdr: &mut DynamicHeader) {
     for h in header_fields {
         match h {
-            HeaderField::Destination(d) => hdr.destination = Some(d.clone()),
-            HeaderField::ErrorName(e) => hdr.error_name = Some(e.clone()),
-            HeaderField::Interface(s) => hdr.interface = Some(s.clone()),
-            HeaderField::Member(m) => hdr.member = Some(m.clone()),
-            HeaderField::Path(p) => hdr.object = Some(p.clone()),
+            HeaderField::Destination(d) => hdr.destination = Some(d.as_ref().to_owned()),
+            HeaderField::ErrorName(e) => hdr.error_name = Some(e.as_ref().to_owned()),
+            HeaderField::Interface(s) => hdr.interface = Some(s.as_ref().to_owned()),
+            HeaderField::Member(m) => hdr.member = Some(m.as_ref().to_owned()),
+            HeaderField::Path(p) => hdr.object = Some(p.as_ref().to_owned()),
             HeaderField::ReplySerial(r) => hdr.response_serial = Some(*r),
-            HeaderField::Sender(s) => hdr.sender = Some(s.clone()),
-            HeaderField::Signature(s) => hdr.signature = Some(s.clone()),
+            HeaderField::Sender(s) => hdr.sender = Some(s.as_ref().to_owned()),
+            HeaderField::Signature(s) => hdr.signature = Some(s.as_ref().to_owned()),
             HeaderField::UnixFds(u) => hdr.num_fds = Some(*u),
         }
     }
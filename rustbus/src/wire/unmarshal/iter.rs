@@ -5,7 +5,7 @@ use crate::params;
 use crate::signature;
 use crate::wire::errors::UnmarshalError;
 use crate::wire::unmarshal::base::unmarshal_base;
-use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::wire::unmarshal_context::{Cursor, UnmarshalContext};
 use crate::ByteOrder;
 
 pub struct MessageIter<'a> {
@@ -325,7 +325,7 @@ fn make_new_array_iter<'a>(
     el_sig: &'a signature::Type,
 ) -> Result<ArrayIter<'a>, UnmarshalError> {
     // get child array size
-    let array_len_bytes = crate::wire::util::parse_u32(&source[*offset..], byteorder)?;
+    let array_len_bytes = Cursor::new(&source[*offset..]).read_u32(byteorder)?;
 
     // move offset
     *offset += 4;
@@ -349,8 +349,9 @@ fn make_new_variant_iter<'a>(
     byteorder: ByteOrder,
 ) -> Result<VariantIter<'a>, UnmarshalError> {
     // get child array size
-    let (bytes, sig) = crate::wire::util::unmarshal_signature(&source[*offset..])?;
-    debug_assert_eq!(bytes, 4);
+    let mut cursor = Cursor::new(&source[*offset..]);
+    let sig = cursor.read_signature()?;
+    debug_assert_eq!(cursor.consumed(), 4);
 
     let sig = signature::Type::parse_description(sig)?.remove(0);
 
@@ -376,7 +377,7 @@ fn make_new_dict_iter<'a>(
     val_sig: &'a signature::Type,
 ) -> Result<DictIter<'a>, UnmarshalError> {
     // get child array size
-    let array_len_bytes = crate::wire::util::parse_u32(&source[*offset..], byteorder)?;
+    let array_len_bytes = Cursor::new(&source[*offset..]).read_u32(byteorder)?;
 
     // move offset
     *offset += 4;
@@ -2,7 +2,6 @@
 
 use crate::params;
 use crate::signature;
-use crate::wire::errors::UnmarshalError;
 use crate::wire::unmarshal::UnmarshalResult;
 use crate::wire::unmarshal_context::UnmarshalContext;
 
@@ -48,12 +47,17 @@ pub fn unmarshal_base(
             Ok(params::Base::Double(val))
         }
         signature::Base::Boolean => {
-            let val = ctx.read_u32()?;
-            match val {
-                0 => Ok(params::Base::Boolean(false)),
-                1 => Ok(params::Base::Boolean(true)),
-                _ => Err(UnmarshalError::InvalidBoolean),
+            let val = ctx.read_bool()?;
+            Ok(params::Base::Boolean(val))
+        }
+        signature::Base::String if ctx.lossy_strings() => {
+            let bytes = ctx.read_str_bytes()?;
+            if bytes.contains(&0) {
+                return Err(crate::params::validation::Error::StringContainsNullByte.into());
             }
+            Ok(params::Base::String(
+                String::from_utf8_lossy(bytes).into_owned(),
+            ))
         }
         signature::Base::String => {
             let string = ctx.read_str()?;
@@ -40,6 +40,7 @@ pub fn unmarshal_variant(
     Ok(params::Variant { sig, value: param })
 }
 
+#[allow(clippy::mutable_key_type)]
 pub fn unmarshal_container(
     typ: &signature::Container,
     ctx: &mut UnmarshalContext,
@@ -62,7 +62,7 @@ pub use container::*;
 ///         // This is necessary at the start of each struct! They need to be aligned to 8 bytes!
 ///         ctx.align_to(Self::alignment())?;
 ///
-///         // decode some stuff and adjust offset
+///         // decode some stuff. The context keeps track of the bytes that were consumed
 ///         let mycoolint = u64::unmarshal(ctx)?;
 ///         
 ///         // some more decoding if the struct had more fields
@@ -84,8 +84,8 @@ pub use container::*;
 ///
 /// ## Cool things you can do
 /// If the message contains some form of secondary marshalling, of another format, you can do this here too, instead of copying the bytes
-/// array around before doing the secondary unmarshalling. Just keep in mind that you have to report the accurate number of bytes used, and not to
-/// use any bytes in the message, not belonging to that byte array
+/// array around before doing the secondary unmarshalling. Just keep in mind to read the bytes through the context (e.g. with `read_u8_slice`)
+/// so it advances past them, and not to use any bytes in the message, not belonging to that byte array
 ///
 /// As an example, lets assume your message contains a byte-array that is actually json data. Then you can use serde_json to unmarshal that array
 /// directly here without having to do a separate step for that.
@@ -131,7 +131,6 @@ pub use container::*;
 ///     }
 /// }
 /// ```
-
 pub trait Unmarshal<'buf, 'fds>: Sized + Signature {
     fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self>;
 }
@@ -149,11 +148,13 @@ mod test {
     use super::unmarshal;
     use super::Unmarshal;
     use super::UnmarshalContext;
+    #[cfg(rustbus_params)]
     use super::Variant;
     use crate::wire::marshal::MarshalContext;
     use crate::wire::UnixFd;
     use crate::ByteOrder;
     use crate::Marshal;
+    #[cfg(rustbus_params)]
     use crate::Signature;
 
     // TODO this is more of a doc test?
@@ -322,6 +323,8 @@ mod test {
     }
 
     #[test]
+    #[cfg(rustbus_params)]
+    #[allow(clippy::mutable_key_type, clippy::needless_borrows_for_generic_args)]
     fn test_variant() {
         use crate::message_builder::MarshalledMessageBody;
         use crate::params::{Array, Base, Container, Dict, Param, Variant as ParamVariant};
@@ -1,10 +1,10 @@
 //! This contains the implementations for the `Unmarshal` trait for base types like integers and strings
 
-use crate::wire::errors::UnmarshalError;
 use crate::wire::unmarshal;
 use crate::wire::unmarshal_context::UnmarshalContext;
 use crate::wire::ObjectPath;
 use crate::wire::SignatureWrapper;
+use crate::wire::{BusName, ErrorName, InterfaceName, MemberName};
 use crate::Unmarshal;
 
 impl<'buf, 'fds> Unmarshal<'buf, 'fds> for u64 {
@@ -46,19 +46,13 @@ impl<'buf, 'fds> Unmarshal<'buf, 'fds> for u8 {
 
 impl<'buf, 'fds> Unmarshal<'buf, 'fds> for bool {
     fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self> {
-        let val = ctx.read_u32()?;
-        match val {
-            0 => Ok(false),
-            1 => Ok(true),
-            _ => Err(UnmarshalError::InvalidBoolean),
-        }
+        ctx.read_bool()
     }
 }
 
 impl<'buf, 'fds> Unmarshal<'buf, 'fds> for f64 {
     fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self> {
-        let val = ctx.read_u64()?;
-        Ok(f64::from_bits(val))
+        ctx.read_f64()
     }
 }
 
@@ -91,3 +85,18 @@ impl<'buf, 'fds, S: AsRef<str> + Unmarshal<'buf, 'fds>> Unmarshal<'buf, 'fds> fo
         Ok(path)
     }
 }
+
+macro_rules! name_wrapper_unmarshal {
+    ($($name:ident),*) => {
+        $(
+            impl<'buf, 'fds, S: AsRef<str> + Unmarshal<'buf, 'fds>> Unmarshal<'buf, 'fds> for $name<S> {
+                fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self> {
+                    let val = <S as Unmarshal>::unmarshal(ctx)?;
+                    let name = $name::new(val)?;
+                    Ok(name)
+                }
+            }
+        )*
+    };
+}
+name_wrapper_unmarshal!(BusName, InterfaceName, MemberName, ErrorName);
@@ -224,6 +224,8 @@ impl<'buf, 'fds, K: Unmarshal<'buf, 'fds> + std::hash::Hash + Eq, V: Unmarshal<'
     }
 }
 
+/// A variant whose value has not been unmarshalled yet. Like everywhere else the fds of the value are the
+/// `UnixFd`s of the message, so unmarshalling them shares their ownership with the message.
 #[derive(Debug)]
 pub struct Variant<'fds, 'buf> {
     pub(crate) sig: signature::Type,
@@ -255,9 +257,7 @@ impl<'buf, 'fds> Variant<'fds, 'buf> {
     ) -> UnmarshalResult<Self> {
         ctx.align_to(sig.get_alignment())?;
 
-        let val_bytes =
-            crate::wire::validate_raw::validate_marshalled(ctx.byteorder, 0, ctx.remainder(), &sig)
-                .map_err(|e| e.1)?;
+        let val_bytes = ctx.validate_next(&sig)?;
 
         Ok(Variant {
             sig,
@@ -322,6 +322,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_borrows_for_generic_args)]
     fn array() {
         let mut m = MarshalledMessageBody::new();
         m.push_param([0u8, 1, 2, 3, 4, 5]).unwrap(); // Array by value
@@ -1,17 +1,17 @@
 use crate::ByteOrder;
 
-use super::{
-    errors::UnmarshalError,
-    unmarshal::UnmarshalResult,
-    util::{parse_u16, parse_u32, parse_u64, unmarshal_signature, unmarshal_str},
-    UnixFd,
-};
+use std::convert::TryInto;
 
+use super::{errors::UnmarshalError, unmarshal::UnmarshalResult, UnixFd};
+
+/// All primitives read through the context advance it past the bytes they consumed (including padding),
+/// so Unmarshal impls never need to do any offset bookkeeping themselves.
 #[derive(Debug, Clone, Copy)]
 pub struct UnmarshalContext<'fds, 'buf> {
     pub byteorder: ByteOrder,
     fds: &'fds [crate::wire::UnixFd],
     cursor: Cursor<'buf>,
+    lossy_strings: bool,
 }
 
 impl<'fds, 'buf> UnmarshalContext<'fds, 'buf> {
@@ -25,12 +25,42 @@ impl<'fds, 'buf> UnmarshalContext<'fds, 'buf> {
             fds,
             byteorder,
             cursor: Cursor { buf, offset },
+            lossy_strings: false,
         }
     }
 
+    /// Strings are rejected with `InvalidUtf8` if they are not valid UTF-8. In lossy mode the params API decodes them
+    /// with invalid sequences replaced by U+FFFD instead, so tools looking at traffic of misbehaving peers still see the
+    /// rest of the message. Contexts created with `sub_context` inherit the mode.
+    pub fn set_lossy_strings(&mut self, lossy: bool) {
+        self.lossy_strings = lossy;
+    }
+
+    pub fn lossy_strings(&self) -> bool {
+        self.lossy_strings
+    }
+
+    /// A context for the next `length` bytes. It keeps the offset of the parent, so the alignment of values in it
+    /// stays the same.
     pub fn sub_context(&mut self, length: usize) -> UnmarshalResult<UnmarshalContext<'fds, 'buf>> {
-        let region = self.read_raw(length)?;
-        Ok(UnmarshalContext::new(self.fds, self.byteorder, region, 0))
+        let offset = self.cursor.offset;
+        self.read_raw(length)?;
+        let buf = &self.cursor.buf[..offset + length];
+        let mut sub = UnmarshalContext::new(self.fds, self.byteorder, buf, offset);
+        sub.lossy_strings = self.lossy_strings;
+        Ok(sub)
+    }
+
+    /// Check that a valid value of type `sig` follows and return how many bytes it takes up, including the padding
+    /// before it
+    pub fn validate_next(&self, sig: &crate::signature::Type) -> UnmarshalResult<usize> {
+        crate::wire::validate_raw::validate_marshalled(
+            self.byteorder,
+            self.cursor.offset,
+            self.cursor.buf,
+            sig,
+        )
+        .map_err(|e| e.1)
     }
 
     pub fn align_to(&mut self, alignment: usize) -> Result<usize, UnmarshalError> {
@@ -79,10 +109,22 @@ impl<'fds, 'buf> UnmarshalContext<'fds, 'buf> {
         self.cursor.read_u64(self.byteorder)
     }
 
+    pub fn read_f64(&mut self) -> UnmarshalResult<f64> {
+        self.cursor.read_u64(self.byteorder).map(f64::from_bits)
+    }
+
+    pub fn read_bool(&mut self) -> UnmarshalResult<bool> {
+        self.cursor.read_bool(self.byteorder)
+    }
+
     pub fn read_str(&mut self) -> UnmarshalResult<&'buf str> {
         self.cursor.read_str(self.byteorder)
     }
 
+    pub fn read_str_bytes(&mut self) -> UnmarshalResult<&'buf [u8]> {
+        self.cursor.read_str_bytes(self.byteorder)
+    }
+
     pub fn read_signature(&mut self) -> UnmarshalResult<&'buf str> {
         self.cursor.read_signature()
     }
@@ -103,7 +145,7 @@ pub struct Cursor<'a> {
 }
 
 impl<'buf> Cursor<'buf> {
-    pub fn new(buf: &[u8]) -> Cursor {
+    pub fn new(buf: &[u8]) -> Cursor<'_> {
         Cursor { buf, offset: 0 }
     }
 
@@ -135,15 +177,22 @@ impl<'buf> Cursor<'buf> {
         }
     }
 
+    fn read_array<const N: usize>(&mut self) -> UnmarshalResult<[u8; N]> {
+        // read_raw returns exactly N bytes so this can not fail
+        Ok(self.read_raw(N)?.try_into().unwrap())
+    }
+
     pub fn read_i16(&mut self, byteorder: ByteOrder) -> UnmarshalResult<i16> {
         self.read_u16(byteorder).map(|value| value as i16)
     }
 
     pub fn read_u16(&mut self, byteorder: ByteOrder) -> UnmarshalResult<u16> {
         self.align_to(2)?;
-        let value = parse_u16(self.remainder(), byteorder)?;
-        self.offset += 2;
-        Ok(value)
+        let bytes = self.read_array()?;
+        Ok(match byteorder {
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        })
     }
 
     pub fn read_i32(&mut self, byteorder: ByteOrder) -> UnmarshalResult<i32> {
@@ -152,9 +201,11 @@ impl<'buf> Cursor<'buf> {
 
     pub fn read_u32(&mut self, byteorder: ByteOrder) -> UnmarshalResult<u32> {
         self.align_to(4)?;
-        let value = parse_u32(self.remainder(), byteorder)?;
-        self.offset += 4;
-        Ok(value)
+        let bytes = self.read_array()?;
+        Ok(match byteorder {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        })
     }
 
     pub fn read_i64(&mut self, byteorder: ByteOrder) -> UnmarshalResult<i64> {
@@ -163,22 +214,60 @@ impl<'buf> Cursor<'buf> {
 
     pub fn read_u64(&mut self, byteorder: ByteOrder) -> UnmarshalResult<u64> {
         self.align_to(8)?;
-        let value = parse_u64(self.remainder(), byteorder)?;
-        self.offset += 8;
-        Ok(value)
+        let bytes = self.read_array()?;
+        Ok(match byteorder {
+            ByteOrder::LittleEndian => u64::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u64::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn read_bool(&mut self, byteorder: ByteOrder) -> UnmarshalResult<bool> {
+        match self.read_u32(byteorder)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(UnmarshalError::InvalidBoolean),
+        }
     }
 
+    /// Read a string. The cursor only advances if the string is valid.
     pub fn read_str(&mut self, byteorder: ByteOrder) -> UnmarshalResult<&'buf str> {
-        self.align_to(4)?;
-        let (bytes, value) = unmarshal_str(byteorder, &self.buf[self.offset..])?;
-        self.offset += bytes;
-        Ok(value)
-    }
-
+        let mut tmp = *self;
+        let bytes = tmp.read_str_bytes(byteorder)?;
+        let string = std::str::from_utf8(bytes)
+            .map_err(|_| crate::params::validation::Error::InvalidUtf8)?;
+        if string.contains('\0') {
+            return Err(crate::params::validation::Error::StringContainsNullByte.into());
+        }
+        *self = tmp;
+        Ok(string)
+    }
+
+    /// Read the bytes of a string without checking that they are valid UTF-8 and free of null bytes. The cursor only
+    /// advances if the length and the terminating null byte could be read.
+    pub fn read_str_bytes(&mut self, byteorder: ByteOrder) -> UnmarshalResult<&'buf [u8]> {
+        let mut tmp = *self;
+        tmp.align_to(4)?;
+        let len = tmp.read_u32(byteorder)? as usize;
+        // read_raw checks the length against the remaining bytes, so a huge length can not overflow anything
+        let bytes = tmp.read_raw(len)?;
+        // skip the terminating null byte
+        tmp.read_u8()?;
+        *self = tmp;
+        Ok(bytes)
+    }
+
+    /// Read a signature. This does not check that it is a valid signature, only that it is valid utf-8.
+    /// The cursor only advances if the signature could be read.
     pub fn read_signature(&mut self) -> UnmarshalResult<&'buf str> {
-        let (bytes, value) = unmarshal_signature(&self.buf[self.offset..])?;
-        self.offset += bytes;
-        Ok(value)
+        let mut tmp = *self;
+        let len = tmp.read_u8()? as usize;
+        let bytes = tmp.read_raw(len)?;
+        // skip the terminating null byte
+        tmp.read_u8()?;
+        let string = std::str::from_utf8(bytes)
+            .map_err(|_| crate::params::validation::Error::InvalidUtf8)?;
+        *self = tmp;
+        Ok(string)
     }
 
     pub fn read_u8_slice(&mut self, byteorder: ByteOrder) -> UnmarshalResult<&'buf [u8]> {
@@ -195,7 +284,7 @@ impl<'buf> Cursor<'buf> {
             return Err(UnmarshalError::NotEnoughBytes);
         }
 
-        let elements = &&self.buf[self.offset..][..length];
+        let elements = &self.buf[self.offset..][..length];
         self.offset += length;
 
         Ok(elements)
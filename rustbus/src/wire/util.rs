@@ -5,6 +5,7 @@ use std::io;
 use crate::wire::errors::MarshalError;
 use crate::wire::errors::UnmarshalError;
 use crate::wire::unmarshal::UnmarshalResult;
+use crate::wire::unmarshal_context::Cursor;
 use crate::ByteOrder;
 
 #[inline(always)]
@@ -16,6 +17,24 @@ pub fn pad_to_align(align_to: usize, buf: &mut Vec<u8>) {
     }
 }
 
+/// Overwrite all bytes of the allocation, including the unused capacity, with zeros and clear the buffer. `zeroize`
+/// makes sure the compiler does not optimize this away because the buffer is about to be freed.
+#[cfg(feature = "zeroize")]
+pub(crate) fn scrub(buf: &mut Vec<u8>) {
+    zeroize::Zeroize::zeroize(buf);
+}
+
+/// Whether `scrub` was called on the buffer and nothing was written to it since
+#[cfg(all(test, feature = "zeroize"))]
+pub(crate) fn is_scrubbed(buf: &mut Vec<u8>) -> bool {
+    // Safety: scrub initialized the whole capacity with zeros
+    buf.is_empty()
+        && buf
+            .spare_capacity_mut()
+            .iter()
+            .all(|byte| unsafe { byte.assume_init() } == 0)
+}
+
 pub fn write_u16(val: u16, byteorder: ByteOrder, buf: &mut Vec<u8>) {
     match byteorder {
         ByteOrder::LittleEndian => buf.extend_from_slice(&val.to_le_bytes()),
@@ -41,7 +60,7 @@ pub fn marshal_unixfd(
     ctx: &mut crate::wire::marshal::MarshalContext,
 ) -> Result<(), MarshalError> {
     if let Some(fd) = i.get_raw_fd() {
-        let new_fd = nix::unistd::dup(fd)
+        let new_fd = crate::wire::wrapper_types::unixfd::dup_raw_fd(fd)
             .map_err(|err| MarshalError::DupUnixFd(io::Error::from(err).kind()))?;
         ctx.fds.push(crate::wire::UnixFd::new(new_fd));
 
@@ -121,65 +140,19 @@ pub fn write_signature(val: &str, buf: &mut Vec<u8>) {
     buf.push(0);
 }
 
+#[deprecated = "use Cursor or UnmarshalContext, which keep track of the consumed bytes"]
 pub fn parse_u64(number: &[u8], byteorder: ByteOrder) -> UnmarshalResult<u64> {
-    if number.len() < 8 {
-        return Err(UnmarshalError::NotEnoughBytes);
-    }
-    let val = match byteorder {
-        ByteOrder::LittleEndian => {
-            (number[0] as u64)
-                + ((number[1] as u64) << 8)
-                + ((number[2] as u64) << 16)
-                + ((number[3] as u64) << 24)
-                + ((number[4] as u64) << 32)
-                + ((number[5] as u64) << 40)
-                + ((number[6] as u64) << 48)
-                + ((number[7] as u64) << 56)
-        }
-        ByteOrder::BigEndian => {
-            (number[7] as u64)
-                + ((number[6] as u64) << 8)
-                + ((number[5] as u64) << 16)
-                + ((number[4] as u64) << 24)
-                + ((number[3] as u64) << 32)
-                + ((number[2] as u64) << 40)
-                + ((number[1] as u64) << 48)
-                + ((number[0] as u64) << 56)
-        }
-    };
-    Ok(val)
+    Cursor::new(number).read_u64(byteorder)
 }
 
+#[deprecated = "use Cursor or UnmarshalContext, which keep track of the consumed bytes"]
 pub fn parse_u32(number: &[u8], byteorder: ByteOrder) -> UnmarshalResult<u32> {
-    if number.len() < 4 {
-        return Err(UnmarshalError::NotEnoughBytes);
-    }
-    let val = match byteorder {
-        ByteOrder::LittleEndian => {
-            (number[0] as u32)
-                + ((number[1] as u32) << 8)
-                + ((number[2] as u32) << 16)
-                + ((number[3] as u32) << 24)
-        }
-        ByteOrder::BigEndian => {
-            (number[3] as u32)
-                + ((number[2] as u32) << 8)
-                + ((number[1] as u32) << 16)
-                + ((number[0] as u32) << 24)
-        }
-    };
-    Ok(val)
+    Cursor::new(number).read_u32(byteorder)
 }
 
+#[deprecated = "use Cursor or UnmarshalContext, which keep track of the consumed bytes"]
 pub fn parse_u16(number: &[u8], byteorder: ByteOrder) -> UnmarshalResult<u16> {
-    if number.len() < 2 {
-        return Err(UnmarshalError::NotEnoughBytes);
-    }
-    let val = match byteorder {
-        ByteOrder::LittleEndian => (number[0] as u16) + ((number[1] as u16) << 8),
-        ByteOrder::BigEndian => (number[1] as u16) + ((number[0] as u16) << 8),
-    };
-    Ok(val)
+    Cursor::new(number).read_u16(byteorder)
 }
 
 pub fn align_offset(align_to: usize, buf: &[u8], offset: usize) -> Result<usize, UnmarshalError> {
@@ -190,7 +163,7 @@ pub fn align_offset(align_to: usize, buf: &[u8], offset: usize) -> Result<usize,
         padding_delete
     };
 
-    if buf[offset..].len() < padding_delete {
+    if buf.len() < offset || buf[offset..].len() < padding_delete {
         return Err(UnmarshalError::NotEnoughBytes);
     }
     for x in 0..padding_delete {
@@ -201,38 +174,41 @@ pub fn align_offset(align_to: usize, buf: &[u8], offset: usize) -> Result<usize,
     Ok(padding_delete)
 }
 
-pub fn unmarshal_signature(buf: &[u8]) -> UnmarshalResult<(usize, &str)> {
-    if buf.is_empty() {
-        return Err(UnmarshalError::NotEnoughBytes);
+/// Check the bytes following the last param of a body, `buf` has to start at the beginning of the body. The spec does
+/// not require padding after the last param but the body length governs where the body ends, so peers that pad the
+/// body up to the next 8 byte boundary produce valid messages. Such trailing bytes must be zero and must not reach
+/// past that boundary.
+pub fn check_body_end(buf: &[u8], used: usize) -> Result<(), UnmarshalError> {
+    let trailing = &buf[used..];
+    if !trailing.is_empty() && (trailing.len() >= 8 || buf.len() % 8 != 0) {
+        return Err(UnmarshalError::NotAllBytesUsed);
     }
-    let len = buf[0] as usize;
-    if buf.len() < len + 2 {
-        return Err(UnmarshalError::NotEnoughBytes);
+    if trailing.iter().any(|b| *b != b'\0') {
+        return Err(UnmarshalError::PaddingContainedData);
     }
-    let sig_buf = &buf[1..][..len];
-    let string =
-        std::str::from_utf8(sig_buf).map_err(|_| crate::params::validation::Error::InvalidUtf8)?;
-    Ok((len + 2, string))
+    Ok(())
+}
+
+#[deprecated = "use Cursor or UnmarshalContext, which keep track of the consumed bytes"]
+pub fn unmarshal_signature(buf: &[u8]) -> UnmarshalResult<(usize, &str)> {
+    let mut cursor = Cursor::new(buf);
+    let string = cursor.read_signature()?;
+    Ok((cursor.consumed(), string))
 }
 
+#[deprecated = "use Cursor or UnmarshalContext, which keep track of the consumed bytes"]
 pub fn unmarshal_string(byteorder: ByteOrder, buf: &[u8]) -> UnmarshalResult<(usize, String)> {
-    let (bytes, string) = unmarshal_str(byteorder, buf)?;
-    Ok((bytes, string.into()))
+    let mut cursor = Cursor::new(buf);
+    let string = cursor.read_str(byteorder)?;
+    Ok((cursor.consumed(), string.into()))
 }
 
+#[deprecated = "use Cursor or UnmarshalContext, which keep track of the consumed bytes"]
 pub fn unmarshal_str<'r, 'a: 'r>(
     byteorder: ByteOrder,
     buf: &'a [u8],
 ) -> UnmarshalResult<(usize, &'r str)> {
-    let len = parse_u32(buf, byteorder)? as usize;
-    if buf.len() < len + 5 {
-        return Err(UnmarshalError::NotEnoughBytes);
-    }
-    let str_buf = &buf[4..];
-    let string = std::str::from_utf8(&str_buf[..len])
-        .map_err(|_| crate::params::validation::Error::InvalidUtf8)?;
-    if string.contains('\0') {
-        return Err(crate::params::validation::Error::StringContainsNullByte.into());
-    }
-    Ok((len + 5, string))
+    let mut cursor = Cursor::new(buf);
+    let string = cursor.read_str(byteorder)?;
+    Ok((cursor.consumed(), string))
 }
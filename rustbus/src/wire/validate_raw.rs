@@ -5,6 +5,7 @@
 
 use crate::signature;
 use crate::wire::errors::UnmarshalError;
+use crate::wire::unmarshal_context::Cursor;
 use crate::ByteOrder;
 
 /// Either Ok(amount_of_bytes) or Err(position, ErrorCode)
@@ -15,18 +16,59 @@ pub fn validate_marshalled(
     offset: usize,
     raw: &[u8],
     sig: &signature::Type,
+) -> ValidationResult {
+    walk(byteorder, offset, raw, sig, None)
+}
+
+pub fn validate_marshalled_base(
+    byteorder: ByteOrder,
+    offset: usize,
+    buf: &[u8],
+    sig: signature::Base,
+) -> ValidationResult {
+    walk_base(byteorder, offset, buf, sig, None)
+}
+
+pub fn validate_marshalled_container(
+    byteorder: ByteOrder,
+    offset: usize,
+    buf: &[u8],
+    sig: &signature::Container,
+) -> ValidationResult {
+    walk_container(byteorder, offset, buf, sig, None)
+}
+
+/// Like `validate_marshalled` but booleans with a value other than 0 or 1 are not an error. Their positions are pushed to
+/// `invalid_bools` instead, so they can be reported or rewritten by the caller.
+pub fn find_invalid_booleans(
+    byteorder: ByteOrder,
+    offset: usize,
+    raw: &[u8],
+    sig: &signature::Type,
+    invalid_bools: &mut Vec<usize>,
+) -> ValidationResult {
+    walk(byteorder, offset, raw, sig, Some(invalid_bools))
+}
+
+fn walk(
+    byteorder: ByteOrder,
+    offset: usize,
+    raw: &[u8],
+    sig: &signature::Type,
+    invalid_bools: Option<&mut Vec<usize>>,
 ) -> ValidationResult {
     match sig {
-        signature::Type::Base(b) => validate_marshalled_base(byteorder, offset, raw, *b),
-        signature::Type::Container(c) => validate_marshalled_container(byteorder, offset, raw, c),
+        signature::Type::Base(b) => walk_base(byteorder, offset, raw, *b, invalid_bools),
+        signature::Type::Container(c) => walk_container(byteorder, offset, raw, c, invalid_bools),
     }
 }
 
-pub fn validate_marshalled_base(
+fn walk_base(
     byteorder: ByteOrder,
     offset: usize,
     buf: &[u8],
     sig: signature::Base,
+    invalid_bools: Option<&mut Vec<usize>>,
 ) -> ValidationResult {
     let padding = crate::wire::util::align_offset(sig.get_alignment(), buf, offset)
         .map_err(|err| (offset, err))?;
@@ -91,51 +133,55 @@ pub fn validate_marshalled_base(
                 return Err((offset + padding, UnmarshalError::NotEnoughBytes));
             }
             let offset = offset + padding;
-            let slice = &buf[offset..offset + 4];
-            let val =
-                crate::wire::util::parse_u32(slice, byteorder).map_err(|err| (offset, err))?;
-            match val {
-                0 => Ok(4 + padding),
-                1 => Ok(4 + padding),
-                _ => Err((offset, UnmarshalError::InvalidBoolean)),
+            let res = Cursor::new(&buf[offset..]).read_bool(byteorder);
+            match (res, invalid_bools) {
+                (Err(UnmarshalError::InvalidBoolean), Some(found)) => found.push(offset),
+                (res, _) => {
+                    res.map_err(|err| (offset, err))?;
+                }
             }
+            Ok(4 + padding)
         }
         signature::Base::String => {
             let offset = offset + padding;
-            let (bytes, _string) = crate::wire::util::unmarshal_str(byteorder, &buf[offset..])
-                .map_err(|err| (offset, err))?;
-            Ok(bytes + padding)
+            let mut cursor = Cursor::new(&buf[offset..]);
+            cursor.read_str(byteorder).map_err(|err| (offset, err))?;
+            Ok(cursor.consumed() + padding)
         }
         signature::Base::ObjectPath => {
             let offset = offset + padding;
-            let (bytes, string) = crate::wire::util::unmarshal_str(byteorder, &buf[offset..])
-                .map_err(|err| (offset, err))?;
+            let mut cursor = Cursor::new(&buf[offset..]);
+            let string = cursor.read_str(byteorder).map_err(|err| (offset, err))?;
             crate::params::validate_object_path(string).map_err(|e| (offset, e.into()))?;
-            Ok(bytes + padding)
+            Ok(cursor.consumed() + padding)
         }
         signature::Base::Signature => {
-            let (bytes, string) = crate::wire::util::unmarshal_signature(&buf[offset..])
+            let mut cursor = Cursor::new(&buf[offset..]);
+            let string = cursor
+                .read_signature()
                 .map_err(|err| (offset + padding, err))?;
             crate::params::validate_signature(string).map_err(|e| (offset, e.into()))?;
-            Ok(bytes + padding)
+            Ok(cursor.consumed() + padding)
         }
     }
 }
 
 use crate::wire::util;
 
-pub fn validate_marshalled_container(
+fn walk_container(
     byteorder: ByteOrder,
     offset: usize,
     buf: &[u8],
     sig: &signature::Container,
+    mut invalid_bools: Option<&mut Vec<usize>>,
 ) -> ValidationResult {
     match sig {
         signature::Container::Array(elem_sig) => {
             let padding = util::align_offset(4, buf, offset).map_err(|err| (offset, err))?;
             let offset = offset + padding;
-            let bytes_in_array =
-                util::parse_u32(&buf[offset..], byteorder).map_err(|err| (offset, err))?;
+            let bytes_in_array = Cursor::new(&buf[offset..])
+                .read_u32(byteorder)
+                .map_err(|err| (offset, err))?;
             let offset = offset + 4;
 
             if buf[offset..].len() < bytes_in_array as usize {
@@ -161,11 +207,12 @@ pub fn validate_marshalled_container(
                 let mut bytes_used_counter = 0;
                 let array_end = offset + bytes_in_array as usize;
                 while bytes_used_counter < bytes_in_array as usize {
-                    let bytes_used = validate_marshalled(
+                    let bytes_used = walk(
                         byteorder,
                         offset + bytes_used_counter,
                         &buf[..array_end],
                         elem_sig,
+                        invalid_bools.as_deref_mut(),
                     )?;
                     bytes_used_counter += bytes_used;
                 }
@@ -176,8 +223,9 @@ pub fn validate_marshalled_container(
         signature::Container::Dict(key_sig, val_sig) => {
             let padding = util::align_offset(4, buf, offset).map_err(|err| (offset, err))?;
             let offset = offset + padding;
-            let bytes_in_dict =
-                util::parse_u32(&buf[offset..], byteorder).map_err(|err| (offset, err))?;
+            let bytes_in_dict = Cursor::new(&buf[offset..])
+                .read_u32(byteorder)
+                .map_err(|err| (offset, err))?;
             let offset = offset + 4;
 
             if buf[offset..].len() < bytes_in_dict as usize {
@@ -201,18 +249,20 @@ pub fn validate_marshalled_container(
                     util::align_offset(8, buf_for_dict, offset + bytes_used_counter)
                         .map_err(|err| (offset + bytes_used_counter, err))?;
                 bytes_used_counter += element_padding;
-                let key_bytes = validate_marshalled_base(
+                let key_bytes = walk_base(
                     byteorder,
                     offset + bytes_used_counter,
                     buf_for_dict,
                     *key_sig,
+                    invalid_bools.as_deref_mut(),
                 )?;
                 bytes_used_counter += key_bytes;
-                let val_bytes = validate_marshalled(
+                let val_bytes = walk(
                     byteorder,
                     offset + bytes_used_counter,
                     buf_for_dict,
                     val_sig,
+                    invalid_bools.as_deref_mut(),
                 )?;
                 bytes_used_counter += val_bytes;
             }
@@ -224,15 +274,21 @@ pub fn validate_marshalled_container(
 
             let mut bytes_used_counter = 0;
             for field_sig in sigs.as_ref() {
-                let bytes_used =
-                    validate_marshalled(byteorder, offset + bytes_used_counter, buf, field_sig)?;
+                let bytes_used = walk(
+                    byteorder,
+                    offset + bytes_used_counter,
+                    buf,
+                    field_sig,
+                    invalid_bools.as_deref_mut(),
+                )?;
                 bytes_used_counter += bytes_used;
             }
             Ok(padding + bytes_used_counter)
         }
         signature::Container::Variant => {
-            let (sig_bytes_used, sig_str) =
-                util::unmarshal_signature(&buf[offset..]).map_err(|err| (offset, err))?;
+            let mut cursor = Cursor::new(&buf[offset..]);
+            let sig_str = cursor.read_signature().map_err(|err| (offset, err))?;
+            let sig_bytes_used = cursor.consumed();
             let mut sig =
                 signature::Type::parse_description(sig_str).map_err(|e| (offset, e.into()))?;
             if sig.len() != 1 {
@@ -242,7 +298,7 @@ pub fn validate_marshalled_container(
             let sig = sig.remove(0);
             let offset = offset + sig_bytes_used;
 
-            let param_bytes_used = validate_marshalled(byteorder, offset, buf, &sig)?;
+            let param_bytes_used = walk(byteorder, offset, buf, &sig, invalid_bools)?;
             Ok(sig_bytes_used + param_bytes_used)
         }
     }
@@ -115,9 +115,7 @@ macro_rules! dbus_variant_sig_unmarshal {
                     return Ok(Self::$name(v));
                 }
                 )+
-                $crate::wire::validate_raw::validate_marshalled(
-                    ctx.byteorder, 0, ctx.remainder(), &sig
-                ).map_err(|e| e.1)?;
+                ctx.validate_next(&sig)?;
 
                 Ok(Self::Catchall(sig))
             }
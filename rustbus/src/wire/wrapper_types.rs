@@ -1,6 +1,57 @@
 use std::convert::TryFrom;
 
+pub mod large_data;
+#[cfg(feature = "mock-fds")]
+pub mod mock_fds;
+pub mod path;
+pub mod raw_str;
+pub mod settings_map;
+#[cfg(any(feature = "chrono", feature = "time"))]
+pub mod timestamp;
 pub mod unixfd;
+pub mod var_dict;
+
+/// The impls all wrappers around a validated string share. They deref to `str` and display as the plain string,
+/// `Borrow<str>` lets maps keyed by them be queried with a `&str`.
+macro_rules! str_wrapper_impls {
+    ($name:ident) => {
+        impl<S: AsRef<str>> $name<S> {
+            pub fn as_str(&self) -> &str {
+                self.0.as_ref()
+            }
+            pub fn into_inner(self) -> S {
+                self.0
+            }
+        }
+        impl<S: AsRef<str>> std::ops::Deref for $name<S> {
+            type Target = str;
+            fn deref(&self) -> &str {
+                self.0.as_ref()
+            }
+        }
+        impl<S: AsRef<str>> std::fmt::Display for $name<S> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.0.as_ref())
+            }
+        }
+        // the derived Hash of the wrappers hashes just the wrapped value, so it agrees with the Hash of str
+        impl std::borrow::Borrow<str> for $name<String> {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+        impl std::borrow::Borrow<str> for $name<&str> {
+            fn borrow(&self) -> &str {
+                self.0
+            }
+        }
+        impl<S: AsRef<str>> From<$name<S>> for String {
+            fn from(value: $name<S>) -> String {
+                value.0.as_ref().to_owned()
+            }
+        }
+    };
+}
 
 #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 /// Wraps a String or a &str or whatever implements AsRef<str> and checks at creation, that it is a valid ObjectPath
@@ -36,7 +87,9 @@ impl TryFrom<String> for ObjectPath<String> {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+str_wrapper_impls!(ObjectPath);
+
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 /// Wraps a String or a &str or whatever implements AsRef<str> and checks at creation, that it is a valid Signature
 pub struct SignatureWrapper<S: AsRef<str>>(S);
 impl<S: AsRef<str>> SignatureWrapper<S> {
@@ -44,6 +97,9 @@ impl<S: AsRef<str>> SignatureWrapper<S> {
         crate::params::validate_signature(sig.as_ref())?;
         Ok(SignatureWrapper(sig))
     }
+    pub fn to_owned(&self) -> SignatureWrapper<String> {
+        SignatureWrapper(self.as_ref().to_owned())
+    }
 }
 impl<S: AsRef<str>> AsRef<str> for SignatureWrapper<S> {
     fn as_ref(&self) -> &str {
@@ -66,3 +122,115 @@ impl TryFrom<String> for SignatureWrapper<String> {
         SignatureWrapper::<String>::new(value)
     }
 }
+
+str_wrapper_impls!(SignatureWrapper);
+
+/// Defines a wrapper around a String or a &str that checks at creation that it is a valid name of some kind
+macro_rules! name_wrapper {
+    ($(#[$doc:meta])* $name:ident, $validate:path) => {
+        $(#[$doc])*
+        #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+        pub struct $name<S: AsRef<str>>(S);
+        impl<S: AsRef<str>> $name<S> {
+            pub fn new(name: S) -> Result<Self, crate::params::validation::Error> {
+                $validate(name.as_ref())?;
+                Ok($name(name))
+            }
+            pub fn to_owned(&self) -> $name<String> {
+                $name(self.as_ref().to_owned())
+            }
+        }
+        impl<S: AsRef<str>> AsRef<str> for $name<S> {
+            fn as_ref(&self) -> &str {
+                self.0.as_ref()
+            }
+        }
+
+        impl<'a> TryFrom<&'a str> for $name<&'a str> {
+            type Error = crate::params::validation::Error;
+
+            fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+                $name::<&'a str>::new(value)
+            }
+        }
+
+        impl TryFrom<String> for $name<String> {
+            type Error = crate::params::validation::Error;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                $name::<String>::new(value)
+            }
+        }
+
+        str_wrapper_impls!($name);
+    };
+}
+
+name_wrapper!(
+    /// Wraps a String or a &str or whatever implements AsRef<str> and checks at creation, that it is a valid unique or
+    /// well-known bus name
+    BusName,
+    crate::params::validate_busname
+);
+name_wrapper!(
+    /// Wraps a String or a &str or whatever implements AsRef<str> and checks at creation, that it is a valid interface name
+    InterfaceName,
+    crate::params::validate_interface
+);
+name_wrapper!(
+    /// Wraps a String or a &str or whatever implements AsRef<str> and checks at creation, that it is a valid member name
+    MemberName,
+    crate::params::validate_membername
+);
+name_wrapper!(
+    /// Wraps a String or a &str or whatever implements AsRef<str> and checks at creation, that it is a valid error name
+    ErrorName,
+    crate::params::validate_errorname
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_str_wrappers() {
+        use std::collections::{HashMap, HashSet};
+
+        let path = ObjectPath::new("/io/killing/spark").unwrap();
+        assert_eq!(path.to_string(), "/io/killing/spark");
+        assert_eq!(format!("{:?}", path), "ObjectPath(\"/io/killing/spark\")");
+        assert!(path.starts_with("/io"));
+        assert_eq!(String::from(path), "/io/killing/spark");
+
+        let mut map = HashMap::new();
+        map.insert(path.to_owned(), 1);
+        assert_eq!(map.get("/io/killing/spark"), Some(&1));
+
+        let sigs: HashSet<_> = ["a{sv}", "s", "s"]
+            .iter()
+            .copied()
+            .map(|sig| SignatureWrapper::new(sig).unwrap())
+            .collect();
+        assert_eq!(sigs.len(), 2);
+        assert!(sigs.contains("a{sv}"));
+        assert_eq!(
+            SignatureWrapper::new("s").unwrap().to_owned().into_inner(),
+            "s"
+        );
+        assert_eq!(MemberName::new("Ping").unwrap().as_str(), "Ping");
+    }
+
+    #[test]
+    fn test_unixfd_fmt() {
+        use super::unixfd::UnixFd;
+
+        let fd = UnixFd::new(1234);
+        assert_eq!(format!("{:?}", fd), "UnixFd(1234)");
+        assert_eq!(fd.to_string(), "fd 1234");
+        // do not close a fd that was never opened
+        let taken = fd.clone();
+        assert_eq!(taken.take_raw_fd(), Some(1234));
+        assert_eq!(format!("{:?}", fd), "UnixFd(<taken>)");
+        assert_eq!(fd.to_string(), "taken fd");
+    }
+}
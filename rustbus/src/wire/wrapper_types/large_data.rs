@@ -0,0 +1,269 @@
+//! Helpers for the common convention of transferring big payloads through a file descriptor.
+//!
+//! The bus refuses messages bigger than 128MiB and dbus-daemon is generally not happy about shoveling around
+//! huge messages. APIs that need to transfer a lot of data usually send a memfd (or the reading end of a pipe)
+//! instead of the data itself. [`LargeData`] implements that pattern: small payloads are sent inline as `ay`,
+//! everything above a threshold is written into a sealed memfd which is sent as `h`. Both cases are wrapped
+//! into a variant, so the signature of the message does not change depending on the size of the payload.
+//!
+//! ```rust,no_run
+//! use rustbus::wire::{LargeData, DEFAULT_INLINE_THRESHOLD};
+//! let mut sig = rustbus::MessageBuilder::new()
+//!     .signal("io.killing.spark", "Data", "/io/killing/spark")
+//!     .build();
+//! let data = vec![0u8; 10 * 1024 * 1024];
+//! sig.body
+//!     .push_param(LargeData::new(&data, DEFAULT_INLINE_THRESHOLD).unwrap())
+//!     .unwrap();
+//!
+//! // on the receiving side
+//! let received: LargeData = sig.body.parser().get().unwrap();
+//! let data = received.into_bytes(64 * 1024 * 1024).unwrap();
+//! ```
+
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, OwnedFd};
+
+use crate::wire::errors::{MarshalError, UnmarshalError};
+use crate::wire::marshal::traits::SignatureBuffer;
+use crate::wire::marshal::MarshalContext;
+use crate::wire::unmarshal;
+use crate::wire::unmarshal::traits::Variant;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::wire::UnixFd;
+use crate::{Marshal, Signature, Unmarshal};
+
+/// Payloads up to this size are sent inline by default
+pub const DEFAULT_INLINE_THRESHOLD: usize = 1024 * 1024;
+
+/// Errors that can occur while moving data into or out of a file descriptor
+#[derive(Debug, thiserror::Error)]
+pub enum LargeDataError {
+    /// Creating, writing or reading the fd failed
+    #[error("An io error occured: {0}")]
+    Io(#[from] io::Error),
+    /// The fd of the payload was already taken from the UnixFd
+    #[error("The fd of the payload was already taken")]
+    FdTaken,
+    /// The payload is bigger than the limit given by the caller
+    #[error("The payload is bigger than the allowed {limit} bytes")]
+    TooLarge { limit: usize },
+}
+
+impl From<nix::Error> for LargeDataError {
+    fn from(e: nix::Error) -> Self {
+        LargeDataError::Io(e.into())
+    }
+}
+
+/// A payload that is either sent inline as `ay` or through a file descriptor as `h`. On the wire this is a variant.
+#[derive(Debug, Clone)]
+pub enum LargeData {
+    Inline(Vec<u8>),
+    Fd(UnixFd),
+}
+
+impl LargeData {
+    /// Sends `data` inline if it is not bigger than `inline_threshold` bytes, and through a memfd otherwise.
+    pub fn new(data: &[u8], inline_threshold: usize) -> Result<Self, LargeDataError> {
+        let inline_threshold = usize::min(inline_threshold, crate::wire::unmarshal::MAX_ARRAY_LEN);
+        if data.len() <= inline_threshold {
+            Ok(LargeData::Inline(data.to_vec()))
+        } else {
+            Self::via_fd(data)
+        }
+    }
+
+    /// Always sends `data` through a memfd, regardless of its size
+    pub fn via_fd(data: &[u8]) -> Result<Self, LargeDataError> {
+        let fd = fd_with_contents(data)?;
+        Ok(LargeData::Fd(UnixFd::new(fd.into_raw_fd())))
+    }
+
+    /// Get the payload, reading it from the fd if necessary. Payloads bigger than `max_len` bytes are rejected.
+    ///
+    /// Regular files and memfds are read from the start, independent of the current offset of the fd. Other fds (e.g. pipes)
+    /// are read until EOF.
+    pub fn into_bytes(self, max_len: usize) -> Result<Vec<u8>, LargeDataError> {
+        match self {
+            LargeData::Inline(data) => {
+                if data.len() > max_len {
+                    Err(LargeDataError::TooLarge { limit: max_len })
+                } else {
+                    Ok(data)
+                }
+            }
+            LargeData::Fd(fd) => {
+                let raw_fd = fd.get_raw_fd().ok_or(LargeDataError::FdTaken)?;
+                // Safety: the UnixFd keeps the fd open while we hold on to it
+                let fd = unsafe { BorrowedFd::borrow_raw(raw_fd) };
+                read_contents(fd, max_len)
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn fd_with_contents(data: &[u8]) -> Result<OwnedFd, LargeDataError> {
+    use nix::fcntl::{fcntl, FcntlArg, SealFlag};
+    use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+    let fd = memfd_create(
+        std::ffi::CStr::from_bytes_with_nul(b"rustbus-large-data\0").unwrap(),
+        MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+    )?;
+    write_all(fd.as_fd(), data)?;
+    // Seal the memfd so the receiver can be sure the contents do not change under its feet
+    fcntl(
+        fd.as_raw_fd(),
+        FcntlArg::F_ADD_SEALS(
+            SealFlag::F_SEAL_SEAL
+                | SealFlag::F_SEAL_SHRINK
+                | SealFlag::F_SEAL_GROW
+                | SealFlag::F_SEAL_WRITE,
+        ),
+    )?;
+    Ok(fd)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn fd_with_contents(data: &[u8]) -> Result<OwnedFd, LargeDataError> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    // No memfds available, use an unlinked temporary file instead
+    let path = std::env::temp_dir().join(format!(
+        "rustbus-large-data-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    std::fs::remove_file(&path)?;
+    let fd = OwnedFd::from(file);
+    write_all(fd.as_fd(), data)?;
+    Ok(fd)
+}
+
+fn write_all(fd: BorrowedFd, mut data: &[u8]) -> Result<(), LargeDataError> {
+    while !data.is_empty() {
+        match nix::unistd::write(fd, data) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+            Ok(n) => data = &data[n..],
+            Err(nix::Error::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+fn read_contents(fd: BorrowedFd, max_len: usize) -> Result<Vec<u8>, LargeDataError> {
+    let mut data = Vec::new();
+    let mut chunk = vec![0u8; 64 * 1024];
+    // Use pread where possible so the offset of the fd does not matter. Fall back to read for pipes and sockets.
+    let mut seekable = true;
+    loop {
+        let res = if seekable {
+            nix::sys::uio::pread(fd, &mut chunk, data.len() as nix::libc::off_t)
+        } else {
+            nix::unistd::read(fd.as_raw_fd(), &mut chunk)
+        };
+        match res {
+            Ok(0) => return Ok(data),
+            Ok(n) => {
+                if data.len() + n > max_len {
+                    return Err(LargeDataError::TooLarge { limit: max_len });
+                }
+                data.extend_from_slice(&chunk[..n]);
+            }
+            Err(nix::Error::ESPIPE) if seekable && data.is_empty() => seekable = false,
+            Err(nix::Error::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+impl Signature for LargeData {
+    fn signature() -> crate::signature::Type {
+        crate::signature::Type::Container(crate::signature::Container::Variant)
+    }
+    fn alignment() -> usize {
+        1
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        s_buf.push_static("v");
+    }
+    fn has_sig(sig: &str) -> bool {
+        sig.starts_with('v')
+    }
+}
+
+impl Marshal for LargeData {
+    fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+        match self {
+            LargeData::Inline(data) => data.as_slice().marshal_as_variant(ctx),
+            LargeData::Fd(fd) => fd.marshal_as_variant(ctx),
+        }
+    }
+}
+
+impl<'buf, 'fds> Unmarshal<'buf, 'fds> for LargeData {
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self> {
+        let var = Variant::unmarshal(ctx)?;
+        if var.get_value_sig() == &UnixFd::signature() {
+            var.get::<UnixFd>().map(LargeData::Fd)
+        } else if var.get_value_sig() == &<Vec<u8>>::signature() {
+            var.get::<Vec<u8>>().map(LargeData::Inline)
+        } else {
+            Err(UnmarshalError::WrongSignature)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::MarshalledMessageBody;
+
+    #[test]
+    fn test_large_data() {
+        let small = vec![1u8; 100];
+        let big = vec![2u8; 200 * 1024];
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(LargeData::new(&small, 1024).unwrap())
+            .unwrap();
+        body.push_param(LargeData::new(&big, 1024).unwrap())
+            .unwrap();
+        assert_eq!(body.get_fds().len(), 1);
+
+        let mut parser = body.parser();
+        let small_recv: LargeData = parser.get().unwrap();
+        let big_recv: LargeData = parser.get().unwrap();
+        assert!(matches!(small_recv, LargeData::Inline(_)));
+        assert!(matches!(big_recv, LargeData::Fd(_)));
+        assert_eq!(small_recv.into_bytes(1024).unwrap(), small);
+        assert!(matches!(
+            big_recv.clone().into_bytes(1024),
+            Err(LargeDataError::TooLarge { limit: 1024 })
+        ));
+        assert_eq!(big_recv.into_bytes(big.len()).unwrap(), big);
+
+        let (read, write) = nix::unistd::pipe().unwrap();
+        write_all(write.as_fd(), b"through a pipe").unwrap();
+        drop(write);
+        let piped = LargeData::Fd(UnixFd::new(read.into_raw_fd()));
+        assert_eq!(piped.into_bytes(100).unwrap(), b"through a pipe");
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_variant(42u32).unwrap();
+        assert!(matches!(
+            body.parser().get::<LargeData>(),
+            Err(UnmarshalError::WrongSignature)
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+//! Fake file descriptors for tests that need UnixFds but should not depend on real ones.
+//!
+//! This module is only available with the `mock-fds` feature. Mock fds are plain numbers starting at [`MOCK_FD_BASE`],
+//! which are tracked in a process wide table. When rustbus dups or closes an fd that is in this table, only the table
+//! is updated and no syscall is made. All other fds are handled as usual.
+//!
+//! Mock fds can be marshalled into and unmarshalled from messages like any other fd, but they obviously can not be sent
+//! over a real connection.
+//!
+//! ```rust
+//! use rustbus::wire::mock_fds;
+//! let fd = mock_fds::new_mock_fd();
+//! let raw_fd = fd.get_raw_fd().unwrap();
+//! let dupped = fd.dup().unwrap();
+//! assert!(mock_fds::is_same_file(raw_fd, dupped.get_raw_fd().unwrap()));
+//! drop(fd);
+//! assert!(!mock_fds::is_open(raw_fd));
+//! ```
+
+use std::collections::BTreeMap;
+use std::os::unix::io::RawFd;
+
+use crate::wire::UnixFd;
+// a static, loom can not create its mutexes in statics
+use std::sync::Mutex;
+
+/// The first number handed out for a mock fd. This is far above what processes usually get as real fds.
+pub const MOCK_FD_BASE: RawFd = 1 << 24;
+
+struct MockTable {
+    next_fd: RawFd,
+    next_file: u64,
+    /// maps open mock fds to the "file" they refer to. Dups refer to the same file as the original.
+    open: BTreeMap<RawFd, u64>,
+}
+
+static TABLE: Mutex<MockTable> = Mutex::new(MockTable {
+    next_fd: MOCK_FD_BASE,
+    next_file: 0,
+    open: BTreeMap::new(),
+});
+
+fn with_table<T>(f: impl FnOnce(&mut MockTable) -> T) -> T {
+    // A test panicking while holding the lock should not break all other tests
+    let mut table = TABLE.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut table)
+}
+
+impl MockTable {
+    fn alloc(&mut self, file: u64) -> RawFd {
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.open.insert(fd, file);
+        fd
+    }
+}
+
+/// Create a new mock fd that refers to a new, unique "file"
+pub fn new_mock_fd() -> UnixFd {
+    let fd = with_table(|table| {
+        let file = table.next_file;
+        table.next_file += 1;
+        table.alloc(file)
+    });
+    UnixFd::new(fd)
+}
+
+/// Whether `fd` is a mock fd that has not been closed yet
+pub fn is_open(fd: RawFd) -> bool {
+    with_table(|table| table.open.contains_key(&fd))
+}
+
+/// Whether both fds are open mock fds that refer to the same "file", e.g. because one is a dup of the other
+pub fn is_same_file(fd1: RawFd, fd2: RawFd) -> bool {
+    with_table(|table| match (table.open.get(&fd1), table.open.get(&fd2)) {
+        (Some(file1), Some(file2)) => file1 == file2,
+        _ => false,
+    })
+}
+
+/// The number of mock fds that are currently open
+pub fn open_count() -> usize {
+    with_table(|table| table.open.len())
+}
+
+/// Dups a mock fd. Returns None if `fd` is not an open mock fd.
+pub(crate) fn dup(fd: RawFd) -> Option<RawFd> {
+    with_table(|table| {
+        let file = *table.open.get(&fd)?;
+        Some(table.alloc(file))
+    })
+}
+
+/// Closes a mock fd. Returns false if `fd` is not an open mock fd.
+pub(crate) fn close(fd: RawFd) -> bool {
+    with_table(|table| table.open.remove(&fd).is_some())
+}
@@ -0,0 +1,194 @@
+//! Marshal and Unmarshal for `std::path` and `std::ffi` types.
+//!
+//! Paths on unix are arbitrary bytes, so the plain impls for `PathBuf`, `&Path`, `OsString` and `&OsStr` marshal them as
+//! byte arrays (`ay`). This is lossless and what most services that pass around filenames use.
+//!
+//! Some APIs use strings (`s`) for paths instead. For those wrap the path into a [`PathAsString`] and choose how
+//! paths that are not valid UTF-8 are treated with the second type parameter: [`Strict`] refuses to marshal them,
+//! [`Lossy`] replaces invalid sequences with U+FFFD.
+
+use std::ffi::{OsStr, OsString};
+use std::marker::PhantomData;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use crate::params::validation::Error as ValidationError;
+use crate::wire::errors::MarshalError;
+use crate::wire::marshal::traits::SignatureBuffer;
+use crate::wire::marshal::MarshalContext;
+use crate::wire::unmarshal;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::{Marshal, Signature, Unmarshal};
+
+/// How paths that are not valid UTF-8 are marshalled as a string
+pub trait Utf8Policy {
+    /// Whether invalid UTF-8 should be replaced instead of producing an error
+    const LOSSY: bool;
+}
+
+/// Refuse to marshal paths that are not valid UTF-8. This is the default for [`PathAsString`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Strict;
+/// Replace invalid UTF-8 sequences with U+FFFD. Note that the receiver will not be able to find the original path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lossy;
+
+impl Utf8Policy for Strict {
+    const LOSSY: bool = false;
+}
+impl Utf8Policy for Lossy {
+    const LOSSY: bool = true;
+}
+
+macro_rules! impl_os_bytes {
+    ($typ:ty) => {
+        impl Signature for $typ {
+            fn signature() -> crate::signature::Type {
+                <&[u8]>::signature()
+            }
+            fn alignment() -> usize {
+                <&[u8]>::alignment()
+            }
+            #[inline]
+            fn sig_str(s_buf: &mut SignatureBuffer) {
+                <&[u8]>::sig_str(s_buf)
+            }
+            fn has_sig(sig: &str) -> bool {
+                <&[u8]>::has_sig(sig)
+            }
+        }
+        impl Marshal for $typ {
+            fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+                let os_str: &OsStr = self.as_ref();
+                os_str.as_bytes().marshal(ctx)
+            }
+        }
+    };
+}
+
+impl_os_bytes!(PathBuf);
+impl_os_bytes!(&Path);
+impl_os_bytes!(OsString);
+impl_os_bytes!(&OsStr);
+
+impl<'buf, 'fds> Unmarshal<'buf, 'fds> for PathBuf {
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self> {
+        OsString::unmarshal(ctx).map(PathBuf::from)
+    }
+}
+impl<'buf> Unmarshal<'buf, '_> for &'buf Path {
+    fn unmarshal(ctx: &mut UnmarshalContext<'_, 'buf>) -> unmarshal::UnmarshalResult<Self> {
+        <&OsStr>::unmarshal(ctx).map(Path::new)
+    }
+}
+impl<'buf, 'fds> Unmarshal<'buf, 'fds> for OsString {
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self> {
+        ctx.read_u8_slice()
+            .map(|bytes| OsString::from_vec(bytes.to_vec()))
+    }
+}
+impl<'buf> Unmarshal<'buf, '_> for &'buf OsStr {
+    fn unmarshal(ctx: &mut UnmarshalContext<'_, 'buf>) -> unmarshal::UnmarshalResult<Self> {
+        ctx.read_u8_slice().map(OsStr::from_bytes)
+    }
+}
+
+/// Wraps a path and marshals it as a string (`s`) instead of a byte array. The policy `P` decides what happens
+/// to paths that are not valid UTF-8.
+///
+/// ```rust
+/// use rustbus::wire::{Lossy, PathAsString};
+/// use std::path::PathBuf;
+/// let mut body = rustbus::message_builder::MarshalledMessageBody::new();
+/// body.push_param(PathAsString::<_, Lossy>::new(PathBuf::from("/tmp/file"))).unwrap();
+/// let path: PathAsString<PathBuf> = body.parser().get().unwrap();
+/// assert_eq!(path.into_inner(), PathBuf::from("/tmp/file"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathAsString<T, P = Strict>(pub T, PhantomData<P>);
+
+impl<T, P> PathAsString<T, P> {
+    pub fn new(t: T) -> Self {
+        PathAsString(t, PhantomData)
+    }
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T, P> Signature for PathAsString<T, P> {
+    fn signature() -> crate::signature::Type {
+        String::signature()
+    }
+    fn alignment() -> usize {
+        String::alignment()
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        String::sig_str(s_buf)
+    }
+    fn has_sig(sig: &str) -> bool {
+        String::has_sig(sig)
+    }
+}
+impl<T: AsRef<OsStr>, P: Utf8Policy> Marshal for PathAsString<T, P> {
+    fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+        let os_str = self.0.as_ref();
+        if os_str.as_bytes().contains(&0) {
+            return Err(ValidationError::StringContainsNullByte.into());
+        }
+        if P::LOSSY {
+            os_str.to_string_lossy().as_ref().marshal(ctx)
+        } else {
+            os_str
+                .to_str()
+                .ok_or(ValidationError::InvalidUtf8)?
+                .marshal(ctx)
+        }
+    }
+}
+impl<'buf, 'fds, T: From<&'buf str>, P> Unmarshal<'buf, 'fds> for PathAsString<T, P> {
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self> {
+        ctx.read_str().map(|s| PathAsString::new(T::from(s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::MarshalledMessageBody;
+
+    #[test]
+    fn test_paths() {
+        let utf8 = PathBuf::from("/home/user/file.txt");
+        let non_utf8 = PathBuf::from(OsStr::from_bytes(b"/home/user/\xff.txt"));
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(&non_utf8).unwrap();
+        body.push_param(non_utf8.as_os_str()).unwrap();
+        body.push_param(PathAsString::<_, Strict>::new(&utf8))
+            .unwrap();
+        body.push_param(PathAsString::<_, Lossy>::new(&non_utf8))
+            .unwrap();
+
+        let mut parser = body.parser();
+        assert_eq!(parser.get::<PathBuf>().unwrap(), non_utf8);
+        assert_eq!(parser.get::<&OsStr>().unwrap(), non_utf8.as_os_str());
+        assert_eq!(
+            parser.get::<PathAsString<PathBuf>>().unwrap().into_inner(),
+            utf8
+        );
+        assert_eq!(parser.get::<&str>().unwrap(), "/home/user/\u{FFFD}.txt");
+
+        assert_eq!(
+            body.push_param(PathAsString::<_, Strict>::new(&non_utf8)),
+            Err(MarshalError::Validation(ValidationError::InvalidUtf8))
+        );
+        assert_eq!(
+            body.push_param(PathAsString::<_, Lossy>::new(OsStr::from_bytes(b"a\0b"))),
+            Err(MarshalError::Validation(
+                ValidationError::StringContainsNullByte
+            ))
+        );
+    }
+}
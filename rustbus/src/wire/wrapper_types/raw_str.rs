@@ -0,0 +1,143 @@
+//! Strings as they were sent, even if a peer put invalid UTF-8 into them
+
+use std::borrow::Cow;
+
+use crate::signature;
+use crate::wire::marshal::traits::SignatureBuffer;
+use crate::wire::unmarshal::UnmarshalResult;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::{Signature, Unmarshal};
+
+/// The bytes of a string (`s`) without any checks. Unmarshalling a `&str` fails with `InvalidUtf8` if a peer sent
+/// invalid UTF-8, which makes the rest of the message unreadable too. Tools that look at traffic of such peers can use
+/// `RawStr` in place of `&str` and decide for themselves what to do with the bytes.
+///
+/// ```rust
+/// use rustbus::wire::RawStr;
+/// let mut body = rustbus::message_builder::MarshalledMessageBody::new();
+/// body.push_param2("name", 5u32).unwrap();
+/// let (name, number): (RawStr, u32) = body.parser().get2().unwrap();
+/// assert_eq!(name.to_str(), Some("name"));
+/// assert_eq!(number, 5);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RawStr<'buf>(&'buf [u8]);
+
+impl<'buf> RawStr<'buf> {
+    pub fn as_bytes(&self) -> &'buf [u8] {
+        self.0
+    }
+
+    /// The string if it is valid UTF-8 and has no null bytes, so it could be unmarshalled as `&str` too
+    pub fn to_str(&self) -> Option<&'buf str> {
+        std::str::from_utf8(self.0)
+            .ok()
+            .filter(|string| !string.contains('\0'))
+    }
+
+    /// Whether a peer sent a string that does not follow the spec
+    pub fn is_valid(&self) -> bool {
+        self.to_str().is_some()
+    }
+
+    /// The string with invalid UTF-8 sequences replaced by U+FFFD
+    pub fn to_string_lossy(&self) -> Cow<'buf, str> {
+        String::from_utf8_lossy(self.0)
+    }
+}
+
+/// Prints the string lossily
+impl std::fmt::Display for RawStr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_string_lossy())
+    }
+}
+
+impl Signature for RawStr<'_> {
+    fn signature() -> signature::Type {
+        String::signature()
+    }
+    fn alignment() -> usize {
+        String::alignment()
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        String::sig_str(s_buf)
+    }
+    fn has_sig(sig: &str) -> bool {
+        String::has_sig(sig)
+    }
+}
+
+impl<'buf> Unmarshal<'buf, '_> for RawStr<'buf> {
+    fn unmarshal(ctx: &mut UnmarshalContext<'_, 'buf>) -> UnmarshalResult<Self> {
+        ctx.read_str_bytes().map(RawStr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::MarshalledMessageBody;
+    use crate::wire::errors::UnmarshalError;
+    use crate::ByteOrder;
+
+    /// "a\xffb" followed by a u32 and an array with the strings "ok" and "\xc3", the signature is "suas"
+    fn invalid_utf8_body() -> Vec<u8> {
+        vec![
+            3, 0, 0, 0, b'a', 0xff, b'b', 0, 7, 0, 0, 0, 14, 0, 0, 0, 2, 0, 0, 0, b'o', b'k', 0, 0,
+            1, 0, 0, 0, 0xc3, 0,
+        ]
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let buf = invalid_utf8_body();
+        let body = MarshalledMessageBody::from_shared(
+            buf.clone().into(),
+            "suas".to_owned(),
+            ByteOrder::LittleEndian,
+        );
+        assert!(matches!(
+            body.parser().get::<&str>(),
+            Err(UnmarshalError::Validation(
+                crate::params::validation::Error::InvalidUtf8
+            ))
+        ));
+
+        let (first, number, list) = body.parser().get3::<RawStr, u32, Vec<RawStr>>().unwrap();
+        assert_eq!(first.as_bytes(), b"a\xffb");
+        assert!(!first.is_valid());
+        assert_eq!(first.to_string_lossy(), "a\u{fffd}b");
+        assert_eq!(number, 7);
+        assert_eq!(list[0].to_str(), Some("ok"));
+        assert_eq!(list[1].to_str(), None);
+        assert_eq!(list[1].to_string(), "\u{fffd}");
+    }
+
+    #[test]
+    #[cfg(rustbus_params)]
+    fn test_invalid_utf8_params() {
+        use crate::message_builder::MessageBuilder;
+
+        let buf = invalid_utf8_body();
+        let broken = || {
+            let mut msg = MessageBuilder::new()
+                .signal("io.killing.spark", "Broken", "/")
+                .build();
+            msg.body = MarshalledMessageBody::from_shared(
+                buf.clone().into(),
+                "suas".to_owned(),
+                ByteOrder::LittleEndian,
+            );
+            msg
+        };
+        assert!(broken().unmarshall_all().is_err());
+        let params = broken().unmarshall_all_lossy().unwrap().params;
+        assert_eq!(
+            params[0],
+            crate::params::Param::from("a\u{fffd}b".to_owned())
+        );
+        assert_eq!(params[1], crate::params::Param::from(7u32));
+    }
+}
@@ -0,0 +1,283 @@
+//! `a{sa{sv}}` maps: settings grouped into sections, like the connection settings of NetworkManager.
+//!
+//! ```rust
+//! use rustbus::wire::SettingsMap;
+//! let mut settings = SettingsMap::new();
+//! settings.insert("connection", "id", "Home").unwrap();
+//! settings.insert("connection", "type", "802-11-wireless").unwrap();
+//! settings.insert("802-11-wireless", "ssid", &b"home-net"[..]).unwrap();
+//! settings.insert("ipv4", "method", "auto").unwrap();
+//!
+//! let mut msg = rustbus::MessageBuilder::new()
+//!     .call("AddConnection")
+//!     .on("/org/freedesktop/NetworkManager/Settings")
+//!     .build();
+//! msg.body.push_param(&settings).unwrap();
+//!
+//! // on the receiving side
+//! let settings: SettingsMap = msg.body.parser().get().unwrap();
+//! assert_eq!(settings.get::<&str>("connection", "id"), Ok(Some("Home")));
+//! assert_eq!(settings.get::<&[u8]>("802-11-wireless", "ssid"), Ok(Some(&b"home-net"[..])));
+//! assert_eq!(settings.get_or("ipv6", "method", "ignore"), "ignore");
+//! ```
+
+use std::collections::HashMap;
+
+use crate::signature::{self, Base, Type};
+use crate::wire::errors::{MarshalError, UnmarshalError};
+use crate::wire::marshal::traits::SignatureBuffer;
+use crate::wire::marshal::MarshalContext;
+use crate::wire::unmarshal::UnmarshalResult;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::wire::VarDict;
+use crate::{Marshal, Signature, Unmarshal};
+
+/// A dict of `VarDict`s (`a{sa{sv}}`) with typed access to the values by section and key.
+///
+/// Sections that are not present and keys that are not present in a section are treated the same, `get` returns
+/// `Ok(None)` for both. Empty sections are kept, some services give them a meaning (e.g. an empty `ipv6` section).
+#[derive(Debug, Clone, Default)]
+pub struct SettingsMap {
+    sections: HashMap<String, VarDict>,
+}
+
+impl SettingsMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value into `section`, which is created if needed. Replaces the previous value of the key.
+    pub fn insert<S: Into<String>, K: Into<String>, T: Marshal + Signature>(
+        &mut self,
+        section: S,
+        key: K,
+        value: T,
+    ) -> Result<(), MarshalError> {
+        self.section_mut(section).insert(key, value)
+    }
+
+    /// Get the value of `key` in `section`. Returns `Ok(None)` if there is no such value and
+    /// `UnmarshalError::WrongSignature` if it has a different type.
+    pub fn get<'a, T: Unmarshal<'a, 'a>>(
+        &'a self,
+        section: &str,
+        key: &str,
+    ) -> Result<Option<T>, UnmarshalError> {
+        match self.sections.get(section) {
+            Some(values) => values.get(key),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the value of `key` in `section`, or `default` if there is no such value or it has a different type
+    pub fn get_or<'a, T: Unmarshal<'a, 'a>>(&'a self, section: &str, key: &str, default: T) -> T {
+        self.get(section, key).ok().flatten().unwrap_or(default)
+    }
+
+    pub fn section(&self, section: &str) -> Option<&VarDict> {
+        self.sections.get(section)
+    }
+
+    /// The values of `section`, an empty section is created if there is none yet
+    pub fn section_mut<S: Into<String>>(&mut self, section: S) -> &mut VarDict {
+        self.sections.entry(section.into()).or_default()
+    }
+
+    /// Replace the whole section
+    pub fn insert_section<S: Into<String>>(&mut self, section: S, values: VarDict) {
+        self.sections.insert(section.into(), values);
+    }
+
+    /// Remove the section and return its values
+    pub fn remove_section(&mut self, section: &str) -> Option<VarDict> {
+        self.sections.remove(section)
+    }
+
+    /// Remove `key` from `section`, returns whether it was present. The section is kept even if it is empty now.
+    pub fn remove(&mut self, section: &str, key: &str) -> bool {
+        self.sections
+            .get_mut(section)
+            .is_some_and(|values| values.remove(key))
+    }
+
+    /// Insert all values of `other`, replacing existing values with the same section and key. This is how the secrets
+    /// returned by NetworkManager's `GetSecrets` are added to the settings returned by `GetSettings`.
+    pub fn merge(&mut self, other: SettingsMap) {
+        for (section, values) in other.sections {
+            self.section_mut(section).merge(values);
+        }
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = (&str, &VarDict)> {
+        self.sections
+            .iter()
+            .map(|(section, values)| (section.as_str(), values))
+    }
+
+    pub fn contains_section(&self, section: &str) -> bool {
+        self.sections.contains_key(section)
+    }
+
+    /// The number of sections
+    pub fn len(&self) -> usize {
+        self.sections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+}
+
+impl Signature for SettingsMap {
+    fn signature() -> signature::Type {
+        Type::Container(signature::Container::Dict(
+            Base::String,
+            Box::new(VarDict::signature()),
+        ))
+    }
+    fn alignment() -> usize {
+        4
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        s_buf.push_static("a{sa{sv}}");
+    }
+    fn has_sig(sig: &str) -> bool {
+        sig == "a{sa{sv}}"
+    }
+}
+
+impl Marshal for SettingsMap {
+    fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+        self.sections.marshal(ctx)
+    }
+}
+
+impl<'buf, 'fds> Unmarshal<'buf, 'fds> for SettingsMap {
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> UnmarshalResult<Self> {
+        let sections = HashMap::unmarshal(ctx)?;
+        Ok(SettingsMap { sections })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::MarshalledMessageBody;
+    use crate::wire::ObjectPath;
+    use crate::ByteOrder;
+
+    #[test]
+    fn test_settings_map_roundtrip() {
+        let mut settings = SettingsMap::new();
+        settings.insert("connection", "id", "Home").unwrap();
+        settings.insert("connection", "autoconnect", false).unwrap();
+        settings
+            .insert("802-11-wireless", "ssid", &b"home-net"[..])
+            .unwrap();
+        settings
+            .insert(
+                "ipv4",
+                "address-data",
+                vec![HashMap::from([(
+                    "address",
+                    crate::wire::marshal::traits::Variant("10.0.0.2"),
+                )])],
+            )
+            .unwrap();
+        settings
+            .insert("ipv4", "dns", vec![0x0101_a8c0u32, 0x0808_0808])
+            .unwrap();
+        settings
+            .insert("ipv6", "addresses", Vec::<(Vec<u8>, u32, Vec<u8>)>::new())
+            .unwrap();
+        settings
+            .insert(
+                "vpn",
+                "gateway",
+                ObjectPath::new("/org/freedesktop/NetworkManager/Devices/1").unwrap(),
+            )
+            .unwrap();
+        settings.section_mut("proxy");
+
+        for byteorder in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut body = MarshalledMessageBody::with_byteorder(byteorder);
+            body.push_param2(1u8, &settings).unwrap();
+            assert_eq!(body.raw().0, "ya{sa{sv}}");
+            assert!(body.validate().is_ok());
+            let (_, received): (u8, SettingsMap) = body.parser().get2().unwrap();
+
+            assert_eq!(received.len(), 6);
+            assert_eq!(received.get::<&str>("connection", "id"), Ok(Some("Home")));
+            assert_eq!(
+                received.get::<bool>("connection", "autoconnect"),
+                Ok(Some(false))
+            );
+            assert_eq!(
+                received.get::<&[u8]>("802-11-wireless", "ssid"),
+                Ok(Some(&b"home-net"[..]))
+            );
+            let address_data = received
+                .get::<Vec<VarDict>>("ipv4", "address-data")
+                .unwrap()
+                .unwrap();
+            assert_eq!(address_data[0].get::<&str>("address"), Ok(Some("10.0.0.2")));
+            assert_eq!(
+                received.get::<Vec<u32>>("ipv4", "dns"),
+                Ok(Some(vec![0x0101_a8c0, 0x0808_0808]))
+            );
+            assert_eq!(
+                received.get::<Vec<(Vec<u8>, u32, Vec<u8>)>>("ipv6", "addresses"),
+                Ok(Some(vec![]))
+            );
+            assert_eq!(
+                received.get::<ObjectPath<&str>>("vpn", "gateway"),
+                Ok(Some(
+                    ObjectPath::new("/org/freedesktop/NetworkManager/Devices/1").unwrap()
+                ))
+            );
+            assert!(received.section("proxy").unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_settings_map_access() {
+        let mut settings = SettingsMap::new();
+        settings.insert("connection", "id", "Home").unwrap();
+        settings
+            .insert("802-11-wireless-security", "key-mgmt", "wpa-psk")
+            .unwrap();
+
+        assert_eq!(settings.get::<&str>("connection", "uuid"), Ok(None));
+        assert_eq!(settings.get::<&str>("missing", "id"), Ok(None));
+        assert_eq!(
+            settings.get::<u32>("connection", "id"),
+            Err(UnmarshalError::WrongSignature)
+        );
+        assert_eq!(settings.get_or("connection", "id", 0u32), 0);
+        assert_eq!(settings.get_or("connection", "id", ""), "Home");
+
+        // the secrets are added to the section that is there already
+        let mut secrets = SettingsMap::new();
+        secrets
+            .insert("802-11-wireless-security", "psk", "hunter22")
+            .unwrap();
+        settings.merge(secrets);
+        assert_eq!(
+            settings.get::<&str>("802-11-wireless-security", "psk"),
+            Ok(Some("hunter22"))
+        );
+        assert_eq!(
+            settings.get::<&str>("802-11-wireless-security", "key-mgmt"),
+            Ok(Some("wpa-psk"))
+        );
+
+        assert!(settings.remove("connection", "id"));
+        assert!(!settings.remove("connection", "id"));
+        assert!(settings.contains_section("connection"));
+        assert!(settings.remove_section("connection").unwrap().is_empty());
+        let mut sections: Vec<_> = settings.sections().map(|(name, _)| name).collect();
+        sections.sort();
+        assert_eq!(sections, ["802-11-wireless-security"]);
+    }
+}
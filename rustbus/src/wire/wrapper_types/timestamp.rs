@@ -0,0 +1,293 @@
+//! Marshal and Unmarshal for timestamps of the `chrono` and `time` crates.
+//!
+//! Many dbus APIs (most notably everything systemd/logind related) transfer points in time as a `u64`
+//! counting the microseconds since the unix epoch. The plain impls for `chrono::DateTime<Utc>` and
+//! `time::OffsetDateTime` follow that convention. If an API uses another resolution or counts from another
+//! epoch, wrap the value into a [`Timestamp`] and choose the resolution and epoch with its type parameters.
+//! Fields of derived structs can do the same with `#[rustbus(timestamp(resolution = "millis", epoch = "path::ToEpoch"))]`,
+//! both keys are optional.
+//!
+//! Points in time before the epoch cannot be represented and produce an error while marshalling.
+//! Sub-resolution parts of a timestamp are truncated while marshalling.
+
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+
+use crate::wire::errors::MarshalError;
+use crate::wire::errors::UnmarshalError;
+use crate::wire::marshal::traits::SignatureBuffer;
+use crate::wire::marshal::MarshalContext;
+use crate::wire::unmarshal;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::{Marshal, Signature, Unmarshal};
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// The resolution a timestamp is marshalled with
+pub trait Resolution {
+    /// How many units of this resolution make up one second
+    const PER_SECOND: u64;
+}
+
+/// Whole seconds since the unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seconds;
+/// Milliseconds since the unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Milliseconds;
+/// Microseconds since the unix epoch. This is what systemd uses and the default for [`Timestamp`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Microseconds;
+/// Nanoseconds since the unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nanoseconds;
+
+impl Resolution for Seconds {
+    const PER_SECOND: u64 = 1;
+}
+impl Resolution for Milliseconds {
+    const PER_SECOND: u64 = 1_000;
+}
+impl Resolution for Microseconds {
+    const PER_SECOND: u64 = 1_000_000;
+}
+impl Resolution for Nanoseconds {
+    const PER_SECOND: u64 = 1_000_000_000;
+}
+
+/// The point in time a timestamp counts from
+pub trait Epoch {
+    /// Seconds from the unix epoch to this epoch, negative if this epoch lies before the unix epoch
+    const UNIX_OFFSET_SECS: i64;
+}
+
+/// 1970-01-01 00:00:00 UTC. This is what systemd uses and the default for [`Timestamp`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnixEpoch;
+
+impl Epoch for UnixEpoch {
+    const UNIX_OFFSET_SECS: i64 = 0;
+}
+
+/// Types that represent a point in time and can be converted from/to nanoseconds since the unix epoch
+pub trait PointInTime: Sized {
+    fn to_unix_nanos(&self) -> i128;
+    fn from_unix_nanos(nanos: i128) -> Option<Self>;
+}
+
+#[cfg(feature = "chrono")]
+impl PointInTime for chrono::DateTime<chrono::Utc> {
+    fn to_unix_nanos(&self) -> i128 {
+        self.timestamp() as i128 * NANOS_PER_SEC + self.timestamp_subsec_nanos() as i128
+    }
+    fn from_unix_nanos(nanos: i128) -> Option<Self> {
+        let secs = i64::try_from(nanos.div_euclid(NANOS_PER_SEC)).ok()?;
+        let subsec_nanos = nanos.rem_euclid(NANOS_PER_SEC) as u32;
+        chrono::DateTime::from_timestamp(secs, subsec_nanos)
+    }
+}
+
+#[cfg(feature = "time")]
+impl PointInTime for time::OffsetDateTime {
+    fn to_unix_nanos(&self) -> i128 {
+        self.unix_timestamp_nanos()
+    }
+    fn from_unix_nanos(nanos: i128) -> Option<Self> {
+        time::OffsetDateTime::from_unix_timestamp_nanos(nanos).ok()
+    }
+}
+
+fn marshal_point_in_time<T: PointInTime, R: Resolution, E: Epoch>(
+    t: &T,
+    ctx: &mut MarshalContext,
+) -> Result<(), MarshalError> {
+    let nanos_per_unit = NANOS_PER_SEC / R::PER_SECOND as i128;
+    let since_epoch = t.to_unix_nanos() - E::UNIX_OFFSET_SECS as i128 * NANOS_PER_SEC;
+    let units = since_epoch.div_euclid(nanos_per_unit);
+    let units = u64::try_from(units).map_err(|_| MarshalError::TimestampOutOfRange)?;
+    units.marshal(ctx)
+}
+
+fn unmarshal_point_in_time<T: PointInTime, R: Resolution, E: Epoch>(
+    ctx: &mut UnmarshalContext,
+) -> unmarshal::UnmarshalResult<T> {
+    let units = ctx.read_u64()?;
+    let nanos_per_unit = NANOS_PER_SEC / R::PER_SECOND as i128;
+    let nanos = units as i128 * nanos_per_unit + E::UNIX_OFFSET_SECS as i128 * NANOS_PER_SEC;
+    T::from_unix_nanos(nanos).ok_or(UnmarshalError::TimestampOutOfRange)
+}
+
+/// Wraps a point in time and marshals it as a `u64` counting units of the resolution `R` since the epoch `E`.
+///
+/// ```rust,ignore
+/// use rustbus::wire::{Timestamp, Milliseconds};
+/// let now = Timestamp::<_, Milliseconds>::new(chrono::Utc::now());
+/// msg.body.push_param(now).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp<T, R = Microseconds, E = UnixEpoch>(pub T, PhantomData<(R, E)>);
+
+impl<T, R, E> Timestamp<T, R, E> {
+    pub fn new(t: T) -> Self {
+        Timestamp(t, PhantomData)
+    }
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T, R, E> Signature for Timestamp<T, R, E> {
+    fn signature() -> crate::signature::Type {
+        u64::signature()
+    }
+    fn alignment() -> usize {
+        u64::alignment()
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        u64::sig_str(s_buf)
+    }
+    fn has_sig(sig: &str) -> bool {
+        u64::has_sig(sig)
+    }
+}
+impl<T: PointInTime, R: Resolution, E: Epoch> Marshal for Timestamp<T, R, E> {
+    fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+        marshal_point_in_time::<T, R, E>(&self.0, ctx)
+    }
+}
+impl<'buf, 'fds, T: PointInTime, R: Resolution, E: Epoch> Unmarshal<'buf, 'fds>
+    for Timestamp<T, R, E>
+{
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> unmarshal::UnmarshalResult<Self> {
+        unmarshal_point_in_time::<T, R, E>(ctx).map(Timestamp::new)
+    }
+}
+
+macro_rules! impl_point_in_time_as_micros {
+    ($typ:ty) => {
+        impl Signature for $typ {
+            fn signature() -> crate::signature::Type {
+                u64::signature()
+            }
+            fn alignment() -> usize {
+                u64::alignment()
+            }
+            #[inline]
+            fn sig_str(s_buf: &mut SignatureBuffer) {
+                u64::sig_str(s_buf)
+            }
+            fn has_sig(sig: &str) -> bool {
+                u64::has_sig(sig)
+            }
+        }
+        impl Marshal for $typ {
+            fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+                marshal_point_in_time::<_, Microseconds, UnixEpoch>(self, ctx)
+            }
+        }
+        impl<'buf, 'fds> Unmarshal<'buf, 'fds> for $typ {
+            fn unmarshal(
+                ctx: &mut UnmarshalContext<'fds, 'buf>,
+            ) -> unmarshal::UnmarshalResult<Self> {
+                unmarshal_point_in_time::<_, Microseconds, UnixEpoch>(ctx)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "chrono")]
+impl_point_in_time_as_micros!(chrono::DateTime<chrono::Utc>);
+#[cfg(feature = "time")]
+impl_point_in_time_as_micros!(time::OffsetDateTime);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::MarshalledMessageBody;
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono_timestamps() {
+        let dt = chrono::DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap();
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(dt).unwrap();
+        body.push_param(Timestamp::<_, Milliseconds>::new(dt))
+            .unwrap();
+        assert_eq!(
+            body.parser().get2(),
+            Ok((1_700_000_000_123_456u64, 1_700_000_000_123u64))
+        );
+
+        let mut parser = body.parser();
+        let micros: chrono::DateTime<chrono::Utc> = parser.get().unwrap();
+        let millis: Timestamp<chrono::DateTime<chrono::Utc>, Milliseconds> = parser.get().unwrap();
+        assert_eq!(micros.timestamp_subsec_nanos(), 123_456_000);
+        assert_eq!(millis.into_inner().timestamp_subsec_nanos(), 123_000_000);
+
+        let before_epoch = chrono::DateTime::from_timestamp(-1, 0).unwrap();
+        assert_eq!(
+            body.push_param(before_epoch),
+            Err(MarshalError::TimestampOutOfRange)
+        );
+    }
+
+    /// The epoch of GPS time
+    struct GpsEpoch;
+    impl Epoch for GpsEpoch {
+        const UNIX_OFFSET_SECS: i64 = 315_964_800;
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_custom_epoch() {
+        let dt = chrono::DateTime::from_timestamp(315_964_800 + 10, 0).unwrap();
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(Timestamp::<_, Seconds, GpsEpoch>::new(dt))
+            .unwrap();
+        assert_eq!(body.parser().get(), Ok(10u64));
+        let parsed: Timestamp<chrono::DateTime<chrono::Utc>, Seconds, GpsEpoch> =
+            body.parser().get().unwrap();
+        assert_eq!(parsed.into_inner(), dt);
+
+        let unix_epoch = chrono::DateTime::from_timestamp(0, 0).unwrap();
+        assert_eq!(
+            body.push_param(Timestamp::<_, Seconds, GpsEpoch>::new(unix_epoch)),
+            Err(MarshalError::TimestampOutOfRange)
+        );
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time_timestamps() {
+        let dt =
+            time::OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789).unwrap();
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(dt).unwrap();
+        body.push_param(Timestamp::<_, Seconds>::new(dt)).unwrap();
+        assert_eq!(
+            body.parser().get2(),
+            Ok((1_700_000_000_123_456u64, 1_700_000_000u64))
+        );
+
+        let mut parser = body.parser();
+        let micros: time::OffsetDateTime = parser.get().unwrap();
+        assert_eq!(micros.unix_timestamp_nanos(), 1_700_000_000_123_456_000);
+
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(u64::MAX).unwrap();
+        assert_eq!(
+            body.parser()
+                .get::<Timestamp<time::OffsetDateTime, Nanoseconds>>()
+                .map(|_| ()),
+            Ok(())
+        );
+        assert_eq!(
+            body.parser().get::<time::OffsetDateTime>(),
+            Err(UnmarshalError::TimestampOutOfRange)
+        );
+    }
+}
@@ -4,10 +4,9 @@ use crate::wire::marshal::MarshalContext;
 use crate::wire::unmarshal_context::UnmarshalContext;
 use crate::{Marshal, Signature, Unmarshal};
 
+use crate::sync::{Arc, AtomicI32, Ordering};
 use std::io;
 use std::os::unix::io::RawFd;
-use std::sync::atomic::AtomicI32;
-use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DupError {
@@ -15,6 +14,24 @@ pub enum DupError {
     AlreadyTaken,
 }
 
+/// Dup an fd. With the `mock-fds` feature this only touches the mock table for mock fds.
+pub(crate) fn dup_raw_fd(fd: RawFd) -> nix::Result<RawFd> {
+    #[cfg(feature = "mock-fds")]
+    if let Some(new_fd) = super::mock_fds::dup(fd) {
+        return Ok(new_fd);
+    }
+    nix::unistd::dup(fd)
+}
+
+/// Close an fd. With the `mock-fds` feature this only touches the mock table for mock fds.
+pub(crate) fn close_raw_fd(fd: RawFd) {
+    #[cfg(feature = "mock-fds")]
+    if super::mock_fds::close(fd) {
+        return;
+    }
+    nix::unistd::close(fd).ok();
+}
+
 #[derive(Debug)]
 struct UnixFdInner {
     inner: AtomicI32,
@@ -22,7 +39,7 @@ struct UnixFdInner {
 impl Drop for UnixFdInner {
     fn drop(&mut self) {
         if let Some(fd) = self.take() {
-            nix::unistd::close(fd).ok();
+            close_raw_fd(fd);
         }
     }
 }
@@ -35,7 +52,7 @@ impl UnixFdInner {
     /// This is kinda like Cell::take it takes the FD and resets the atomic int to FD_INVALID which represents the invalid / taken state here.
     fn take(&self) -> Option<RawFd> {
         // load fd and see if it is already been taken
-        let loaded_fd: RawFd = self.inner.load(std::sync::atomic::Ordering::SeqCst);
+        let loaded_fd: RawFd = self.inner.load(Ordering::SeqCst);
         if loaded_fd == Self::FD_INVALID {
             None
         } else {
@@ -43,21 +60,17 @@ impl UnixFdInner {
             let swapped_fd = self.inner.compare_exchange(
                 loaded_fd,
                 Self::FD_INVALID,
-                std::sync::atomic::Ordering::SeqCst,
-                std::sync::atomic::Ordering::SeqCst,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
             );
             //  If swapped_fd == fd then we did a sucessful swap and we actually took the value
-            if let Ok(taken_fd) = swapped_fd {
-                Some(taken_fd)
-            } else {
-                None
-            }
+            swapped_fd.ok()
         }
     }
 
     /// This is kinda like Cell::get it returns the FD, FD_INVALID represents the invalid / taken state here.
     fn get(&self) -> Option<RawFd> {
-        let loaded = self.inner.load(std::sync::atomic::Ordering::SeqCst);
+        let loaded = self.inner.load(Ordering::SeqCst);
         if loaded == Self::FD_INVALID {
             None
         } else {
@@ -71,7 +84,7 @@ impl UnixFdInner {
             Some(fd) => fd,
             None => return Err(DupError::AlreadyTaken),
         };
-        match nix::unistd::dup(fd) {
+        match dup_raw_fd(fd) {
             Ok(new_fd) => Ok(Self {
                 inner: AtomicI32::new(new_fd),
             }),
@@ -88,10 +101,10 @@ impl UnixFdInner {
 ///
 /// ## UnixFds and messages
 /// 1. When a UnixFd is **marshalled** rustbus will dup() the FD so that the message and the original UnixFd do not depend on each others lifetime. You are free to use
-/// or close the original one.
+///    or close the original one.
 /// 1. When a UnixFd is **unmarshalled** rustbus will **NOT** dup() the FD. This means if you call take_raw_fd(), it is gone from the message too! If you do not want this,
-/// you have to call dup() and then get_raw_fd() or take_raw_fd()
-#[derive(Clone, Debug)]
+///    you have to call dup() and then get_raw_fd() or take_raw_fd()
+#[derive(Clone)]
 pub struct UnixFd(Arc<UnixFdInner>);
 impl UnixFd {
     pub fn new(fd: RawFd) -> Self {
@@ -120,6 +133,25 @@ impl UnixFd {
         self.0.dup().map(|new_inner| Self(Arc::new(new_inner)))
     }
 }
+/// Shows the raw fd, or that it has been taken
+impl std::fmt::Debug for UnixFd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.get_raw_fd() {
+            Some(fd) => f.debug_tuple("UnixFd").field(&fd).finish(),
+            None => f.write_str("UnixFd(<taken>)"),
+        }
+    }
+}
+
+impl std::fmt::Display for UnixFd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.get_raw_fd() {
+            Some(fd) => write!(f, "fd {}", fd),
+            None => f.write_str("taken fd"),
+        }
+    }
+}
+
 /// Allow for the comparison of `UnixFd` even after the `RawFd`
 /// has been taken, to see if they originally referred to the same thing.
 impl PartialEq<UnixFd> for UnixFd {
@@ -176,8 +208,8 @@ impl Signature for &dyn std::os::unix::io::AsRawFd {
 impl Marshal for &dyn std::os::unix::io::AsRawFd {
     fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
         let fd = self.as_raw_fd();
-        let new_fd = nix::unistd::dup(fd)
-            .map_err(|err| MarshalError::DupUnixFd(io::Error::from(err).kind()))?;
+        let new_fd =
+            dup_raw_fd(fd).map_err(|err| MarshalError::DupUnixFd(io::Error::from(err).kind()))?;
         ctx.fds.push(UnixFd::new(new_fd));
 
         let idx = ctx.fds.len() - 1;
@@ -202,11 +234,14 @@ fn test_fd_send() {
         let _x = x.get_raw_fd();
     });
 
-    let x = UnixFd::new(nix::unistd::dup(1).unwrap());
-    let fd = crate::params::Base::UnixFd(x);
-    std::thread::spawn(move || {
-        let _x = fd;
-    });
+    #[cfg(rustbus_params)]
+    {
+        let x = UnixFd::new(nix::unistd::dup(1).unwrap());
+        let fd = crate::params::Base::UnixFd(x);
+        std::thread::spawn(move || {
+            let _x = fd;
+        });
+    }
 }
 
 #[test]
@@ -266,3 +301,40 @@ fn test_unixfd_dup() {
     let _raw = fd.clone().take_raw_fd();
     assert_eq!(fd.dup(), Err(DupError::AlreadyTaken));
 }
+
+#[test]
+fn test_unixfd_in_variant() {
+    use crate::wire::marshal::traits::Variant;
+    use crate::wire::unmarshal::traits::Variant as UnVariant;
+
+    let fd = UnixFd::new(nix::unistd::dup(1).unwrap());
+    let mut msg = crate::message_builder::MessageBuilder::new()
+        .signal("io.killing.spark", "Sig", "/")
+        .build();
+    msg.body.push_param2(Variant(&fd), &fd).unwrap();
+
+    // fds in variants are UnixFds of the message, just like the ones outside of them
+    let (variant, outer) = msg.body.parser().get2::<UnVariant, UnixFd>().unwrap();
+    let inner = variant.get::<UnixFd>().unwrap();
+    assert_eq!(inner, msg.body.get_fds()[0]);
+    assert_eq!(outer, msg.body.get_fds()[1]);
+
+    #[cfg(rustbus_params)]
+    {
+        let mut parser = msg.body.parser();
+        let params = parser.get_param().unwrap();
+        let crate::params::Param::Container(crate::params::Container::Variant(variant)) = params
+        else {
+            panic!("expected a variant, got {:?}", params);
+        };
+        assert_eq!(
+            variant.value,
+            crate::params::Param::Base(crate::params::Base::UnixFd(inner.clone()))
+        );
+    }
+
+    // they share the ownership with the message, taking the fd from one takes it from all of them
+    let raw = inner.take_raw_fd().unwrap();
+    assert_eq!(msg.body.get_fds()[0].get_raw_fd(), None);
+    nix::unistd::close(raw).unwrap();
+}
@@ -0,0 +1,482 @@
+//! `a{sv}` maps, the common way to pass options and properties around.
+//!
+//! ```rust
+//! use rustbus::wire::VarDict;
+//! let mut options = VarDict::new();
+//! options.insert("timeout", 5000u32).unwrap();
+//! options.insert("title", "Hello").unwrap();
+//!
+//! let mut msg = rustbus::MessageBuilder::new()
+//!     .call("Notify")
+//!     .on("/io/killing/spark")
+//!     .build();
+//! msg.body.push_param(&options).unwrap();
+//!
+//! // on the receiving side
+//! let options: VarDict = msg.body.parser().get().unwrap();
+//! assert_eq!(options.get::<u32>("timeout"), Ok(Some(5000)));
+//! assert_eq!(options.get::<&str>("title"), Ok(Some("Hello")));
+//! assert_eq!(options.get_or("urgency", 1u8), 1);
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::signature::{self, Base, Container, Type};
+use crate::wire::errors::{MarshalError, UnmarshalError};
+use crate::wire::marshal::traits::SignatureBuffer;
+use crate::wire::marshal::MarshalContext;
+use crate::wire::unmarshal::traits::Variant;
+use crate::wire::unmarshal::UnmarshalResult;
+use crate::wire::unmarshal_context::UnmarshalContext;
+use crate::wire::{util, UnixFd};
+use crate::{ByteOrder, Marshal, Signature, Unmarshal};
+
+/// A value of a `VarDict`, marshalled into a buffer of its own starting at offset 0
+#[derive(Debug, Clone)]
+struct StoredValue {
+    sig: Type,
+    buf: Vec<u8>,
+    fds: Vec<UnixFd>,
+}
+
+impl StoredValue {
+    fn ctx(&self) -> UnmarshalContext<'_, '_> {
+        UnmarshalContext::new(&self.fds, ByteOrder::NATIVE, &self.buf, 0)
+    }
+}
+
+/// A dict of variants (`a{sv}`) with typed access to the values.
+///
+/// The values are kept marshalled, `get` unmarshals them into the requested type. Fds in the values are shared with
+/// the message they were unmarshalled from, or the messages the dict is marshalled into.
+#[derive(Debug, Clone, Default)]
+pub struct VarDict {
+    map: HashMap<String, StoredValue>,
+}
+
+impl VarDict {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value, replacing the previous value of the key
+    pub fn insert<K: Into<String>, T: Marshal + Signature>(
+        &mut self,
+        key: K,
+        value: T,
+    ) -> Result<(), MarshalError> {
+        let mut buf = Vec::new();
+        let mut fds = Vec::new();
+        value.marshal(&mut MarshalContext {
+            fds: &mut fds,
+            buf: &mut buf,
+            byteorder: ByteOrder::NATIVE,
+        })?;
+        let value = StoredValue {
+            sig: T::signature(),
+            buf,
+            fds,
+        };
+        self.map.insert(key.into(), value);
+        Ok(())
+    }
+
+    /// Insert the value of an unmarshalled variant, replacing the previous value of the key
+    pub fn insert_variant<K: Into<String>>(
+        &mut self,
+        key: K,
+        value: &Variant,
+    ) -> Result<(), UnmarshalError> {
+        let mut buf = Vec::new();
+        let mut fds = Vec::new();
+        transcode(
+            &value.sig,
+            &mut value.sub_ctx.clone(),
+            &mut MarshalContext {
+                fds: &mut fds,
+                buf: &mut buf,
+                byteorder: ByteOrder::NATIVE,
+            },
+        )?;
+        let value = StoredValue {
+            sig: value.sig.clone(),
+            buf,
+            fds,
+        };
+        self.map.insert(key.into(), value);
+        Ok(())
+    }
+
+    /// Get the value of `key`. Returns `Ok(None)` if there is no such key and `UnmarshalError::WrongSignature` if the
+    /// value has a different type.
+    pub fn get<'a, T: Unmarshal<'a, 'a>>(&'a self, key: &str) -> Result<Option<T>, UnmarshalError> {
+        let Some(value) = self.map.get(key) else {
+            return Ok(None);
+        };
+        if value.sig != T::signature() {
+            return Err(UnmarshalError::WrongSignature);
+        }
+        T::unmarshal(&mut value.ctx()).map(Some)
+    }
+
+    /// Get the value of `key`, or `default` if there is no such key or the value has a different type
+    pub fn get_or<'a, T: Unmarshal<'a, 'a>>(&'a self, key: &str, default: T) -> T {
+        self.get(key).ok().flatten().unwrap_or(default)
+    }
+
+    /// The value of `key` in a form that marshals as a variant
+    pub(crate) fn get_variant(&self, key: &str) -> Option<StoredVariant<'_>> {
+        self.map.get(key).map(StoredVariant)
+    }
+
+    /// A dict with only the entry of `key`
+    pub(crate) fn select(&self, key: &str) -> VarDict {
+        let map = self
+            .map
+            .get_key_value(key)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .into_iter()
+            .collect();
+        VarDict { map }
+    }
+
+    /// Insert all entries of `other`, replacing the values of keys that are present in both
+    pub fn merge(&mut self, other: VarDict) {
+        self.map.extend(other.map);
+    }
+
+    /// The type of the value of `key`
+    pub fn value_sig(&self, key: &str) -> Option<&Type> {
+        self.map.get(key).map(|value| &value.sig)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Remove `key`, returns whether it was present
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.map.remove(key).is_some()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.map.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+/// A value of a `VarDict` that marshals as a variant
+pub(crate) struct StoredVariant<'a>(&'a StoredValue);
+
+impl Signature for StoredVariant<'_> {
+    fn signature() -> signature::Type {
+        Type::Container(Container::Variant)
+    }
+    fn alignment() -> usize {
+        1
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        s_buf.push_static("v");
+    }
+    fn has_sig(sig: &str) -> bool {
+        sig == "v"
+    }
+}
+
+impl Marshal for StoredVariant<'_> {
+    fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+        let mut sig = String::new();
+        self.0.sig.to_str(&mut sig);
+        util::write_signature(&sig, ctx.buf);
+        transcode(&self.0.sig, &mut self.0.ctx(), ctx).map_err(MarshalError::InvalidValue)
+    }
+}
+
+/// Copy a value of type `typ` from `src` to `dst`. The padding depends on the position of the value and the byteorder
+/// may differ, so the bytes can not just be copied as they are.
+pub(crate) fn transcode(
+    typ: &Type,
+    src: &mut UnmarshalContext,
+    dst: &mut MarshalContext,
+) -> UnmarshalResult<()> {
+    match typ {
+        Type::Base(Base::Byte) => dst.buf.push(src.read_u8()?),
+        Type::Base(Base::Int16 | Base::Uint16) => {
+            let value = src.read_u16()?;
+            dst.align_to(2);
+            util::write_u16(value, dst.byteorder, dst.buf);
+        }
+        Type::Base(Base::Int32 | Base::Uint32) => {
+            let value = src.read_u32()?;
+            dst.align_to(4);
+            util::write_u32(value, dst.byteorder, dst.buf);
+        }
+        Type::Base(Base::Boolean) => {
+            let value = src.read_bool()?;
+            dst.align_to(4);
+            util::write_u32(value as u32, dst.byteorder, dst.buf);
+        }
+        Type::Base(Base::UnixFd) => {
+            dst.fds.push(src.read_unixfd()?);
+            dst.align_to(4);
+            util::write_u32((dst.fds.len() - 1) as u32, dst.byteorder, dst.buf);
+        }
+        Type::Base(Base::Int64 | Base::Uint64 | Base::Double) => {
+            let value = src.read_u64()?;
+            dst.align_to(8);
+            util::write_u64(value, dst.byteorder, dst.buf);
+        }
+        Type::Base(Base::String | Base::ObjectPath) => {
+            let value = src.read_str()?;
+            dst.align_to(4);
+            util::write_string(value, dst.byteorder, dst.buf);
+        }
+        Type::Base(Base::Signature) => util::write_signature(src.read_signature()?, dst.buf),
+        Type::Container(Container::Array(elem)) => {
+            transcode_array(elem.get_alignment(), src, dst, |src, dst| {
+                transcode(elem, src, dst)
+            })?;
+        }
+        Type::Container(Container::Dict(key, value)) => {
+            transcode_array(8, src, dst, |src, dst| {
+                src.align_to(8)?;
+                dst.align_to(8);
+                transcode(&Type::Base(*key), src, dst)?;
+                transcode(value, src, dst)
+            })?;
+        }
+        Type::Container(Container::Struct(fields)) => {
+            src.align_to(8)?;
+            dst.align_to(8);
+            for field in fields.as_ref() {
+                transcode(field, src, dst)?;
+            }
+        }
+        Type::Container(Container::Variant) => {
+            let sig = src.read_signature()?;
+            let typ = single_type(sig)?;
+            util::write_signature(sig, dst.buf);
+            transcode(&typ, src, dst)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the length and the padding of an array, `elem` copies a single element
+fn transcode_array(
+    alignment: usize,
+    src: &mut UnmarshalContext,
+    dst: &mut MarshalContext,
+    mut elem: impl FnMut(&mut UnmarshalContext, &mut MarshalContext) -> UnmarshalResult<()>,
+) -> UnmarshalResult<()> {
+    let len = src.read_u32()? as usize;
+    src.align_to(alignment)?;
+    let remaining = src
+        .remainder()
+        .len()
+        .checked_sub(len)
+        .ok_or(UnmarshalError::NotEnoughBytesForCollection)?;
+
+    dst.align_to(4);
+    let len_pos = dst.buf.len();
+    dst.buf.extend_from_slice(&[0; 4]);
+    dst.align_to(alignment);
+    let start = dst.buf.len();
+    while src.remainder().len() > remaining {
+        elem(src, dst)?;
+    }
+    if src.remainder().len() != remaining {
+        return Err(UnmarshalError::NotEnoughBytesForCollection);
+    }
+    let written = (dst.buf.len() - start) as u32;
+    util::insert_u32(dst.byteorder, written, &mut dst.buf[len_pos..len_pos + 4]);
+    Ok(())
+}
+
+pub(crate) fn single_type(sig: &str) -> UnmarshalResult<Type> {
+    let mut types = Type::parse_description(sig).map_err(|_| UnmarshalError::WrongSignature)?;
+    if types.len() != 1 {
+        return Err(UnmarshalError::WrongSignature);
+    }
+    Ok(types.remove(0))
+}
+
+impl Signature for VarDict {
+    fn signature() -> signature::Type {
+        Type::Container(Container::Dict(
+            Base::String,
+            Box::new(Type::Container(Container::Variant)),
+        ))
+    }
+    fn alignment() -> usize {
+        4
+    }
+    #[inline]
+    fn sig_str(s_buf: &mut SignatureBuffer) {
+        s_buf.push_static("a{sv}");
+    }
+    fn has_sig(sig: &str) -> bool {
+        sig == "a{sv}"
+    }
+}
+
+impl Marshal for VarDict {
+    fn marshal(&self, ctx: &mut MarshalContext) -> Result<(), MarshalError> {
+        ctx.align_to(4);
+        let len_pos = ctx.buf.len();
+        ctx.buf.extend_from_slice(&[0; 4]);
+        ctx.align_to(8);
+        let start = ctx.buf.len();
+
+        let mut sig = String::new();
+        for (key, value) in &self.map {
+            ctx.align_to(8);
+            key.marshal(ctx)?;
+            sig.clear();
+            value.sig.to_str(&mut sig);
+            util::write_signature(&sig, ctx.buf);
+            transcode(&value.sig, &mut value.ctx(), ctx).map_err(MarshalError::InvalidValue)?;
+        }
+
+        let written =
+            u32::try_from(ctx.buf.len() - start).map_err(|_| MarshalError::MessageTooLarge)?;
+        util::insert_u32(ctx.byteorder, written, &mut ctx.buf[len_pos..len_pos + 4]);
+        Ok(())
+    }
+}
+
+impl<'buf, 'fds> Unmarshal<'buf, 'fds> for VarDict {
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> UnmarshalResult<Self> {
+        let len = ctx.read_u32()? as usize;
+        ctx.align_to(8)?;
+        let remaining = ctx
+            .remainder()
+            .len()
+            .checked_sub(len)
+            .ok_or(UnmarshalError::NotEnoughBytesForCollection)?;
+
+        let mut map = HashMap::new();
+        while ctx.remainder().len() > remaining {
+            ctx.align_to(8)?;
+            let key = ctx.read_str()?;
+            let sig = single_type(ctx.read_signature()?)?;
+            let mut buf = Vec::new();
+            let mut fds = Vec::new();
+            transcode(
+                &sig,
+                ctx,
+                &mut MarshalContext {
+                    fds: &mut fds,
+                    buf: &mut buf,
+                    byteorder: ByteOrder::NATIVE,
+                },
+            )?;
+            map.insert(key.to_owned(), StoredValue { sig, buf, fds });
+        }
+        if ctx.remainder().len() != remaining {
+            return Err(UnmarshalError::NotEnoughBytesForCollection);
+        }
+        Ok(VarDict { map })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_builder::MarshalledMessageBody;
+    use crate::wire::ObjectPath;
+
+    #[test]
+    fn test_var_dict_roundtrip() {
+        let mut dict = VarDict::new();
+        dict.insert("byte", 1u8).unwrap();
+        dict.insert("bool", true).unwrap();
+        dict.insert("u64s", vec![1u64, 2, 3]).unwrap();
+        dict.insert("nested", vec![vec![7u64], vec![]]).unwrap();
+        dict.insert("path", ObjectPath::new("/io/killing/spark").unwrap())
+            .unwrap();
+        dict.insert("struct", (1u8, "text", 2u64)).unwrap();
+        dict.insert(
+            "map",
+            HashMap::from([(1u16, crate::wire::marshal::traits::Variant(3.5f64))]),
+        )
+        .unwrap();
+        let mut inner = VarDict::new();
+        inner.insert("inner", -5i32).unwrap();
+        dict.insert("dict", &inner).unwrap();
+
+        for byteorder in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut body = MarshalledMessageBody::with_byteorder(byteorder);
+            // misalign the dict, the padding of the values has to be recomputed
+            body.push_param2(3u8, &dict).unwrap();
+            assert!(body.validate().is_ok());
+            let (_, received): (u8, VarDict) = body.parser().get2().unwrap();
+
+            assert_eq!(received.len(), dict.len());
+            assert_eq!(received.get::<u8>("byte"), Ok(Some(1)));
+            assert_eq!(received.get::<bool>("bool"), Ok(Some(true)));
+            assert_eq!(received.get::<Vec<u64>>("u64s"), Ok(Some(vec![1, 2, 3])));
+            assert_eq!(
+                received.get::<Vec<Vec<u64>>>("nested"),
+                Ok(Some(vec![vec![7], vec![]]))
+            );
+            assert_eq!(
+                received.get::<ObjectPath<&str>>("path"),
+                Ok(Some(ObjectPath::new("/io/killing/spark").unwrap()))
+            );
+            assert_eq!(
+                received.get::<(u8, &str, u64)>("struct"),
+                Ok(Some((1, "text", 2)))
+            );
+            let map = received
+                .get::<HashMap<u16, crate::wire::unmarshal::traits::Variant>>("map")
+                .unwrap()
+                .unwrap();
+            assert_eq!(map[&1].get::<f64>(), Ok(3.5));
+            let inner = received.get::<VarDict>("dict").unwrap().unwrap();
+            assert_eq!(inner.get::<i32>("inner"), Ok(Some(-5)));
+        }
+    }
+
+    #[test]
+    fn test_var_dict_access() {
+        let mut dict = VarDict::new();
+        assert!(dict.is_empty());
+        dict.insert("key", "value").unwrap();
+        dict.insert("key", 42u32).unwrap();
+        assert_eq!(dict.len(), 1);
+        assert_eq!(dict.value_sig("key"), Some(&u32::signature()));
+        assert_eq!(dict.get::<u32>("key"), Ok(Some(42)));
+        assert_eq!(dict.get::<&str>("key"), Err(UnmarshalError::WrongSignature));
+        assert_eq!(dict.get::<u32>("missing"), Ok(None));
+        assert_eq!(dict.get_or("key", 0u32), 42);
+        assert_eq!(dict.get_or("key", 0u64), 0);
+        assert_eq!(dict.get_or("missing", 7u32), 7);
+        assert_eq!(dict.keys().collect::<Vec<_>>(), ["key"]);
+        assert!(dict.remove("key"));
+        assert!(!dict.contains_key("key"));
+    }
+
+    #[test]
+    fn test_var_dict_from_hashmap() {
+        // dicts sent by other implementations look the same as a HashMap<String, Variant>
+        let mut body = MarshalledMessageBody::new();
+        body.push_param(HashMap::from([
+            ("a", crate::wire::marshal::traits::Variant(1u32)),
+            ("b", crate::wire::marshal::traits::Variant(2u32)),
+        ]))
+        .unwrap();
+        let dict: VarDict = body.parser().get().unwrap();
+        assert_eq!(dict.get::<u32>("a"), Ok(Some(1)));
+        assert_eq!(dict.get::<u32>("b"), Ok(Some(2)));
+    }
+}
@@ -0,0 +1,136 @@
+//! Parsing of the `#[rustbus(...)]` attributes
+
+/// Attributes on the struct itself
+#[derive(Default)]
+pub struct ContainerAttrs {
+    /// `#[rustbus(dict)]`: marshal the struct as `a{sv}` with one entry per field instead of as a dbus struct
+    pub dict: bool,
+}
+
+/// Attributes on a field
+#[derive(Default)]
+pub struct FieldAttrs {
+    /// `#[rustbus(rename = "Key")]`: the key of the field in a dict struct, defaults to the field name
+    pub rename: Option<String>,
+    /// `#[rustbus(variant)]`: marshal the field as `v` instead of its own signature
+    pub variant: bool,
+    /// `#[rustbus(skip)]`: do not marshal the field, it is set to `Default::default()` when unmarshalling
+    pub skip: bool,
+    /// `#[rustbus(default)]`: use `Default::default()` if a dict does not contain the key of the field
+    pub default: bool,
+    /// `#[rustbus(timestamp(resolution = "millis", epoch = "path::ToEpoch"))]`: marshal the field as a
+    /// `rustbus::wire::Timestamp` with the given resolution and epoch
+    pub timestamp: Option<TimestampAttrs>,
+}
+
+/// The arguments of `#[rustbus(timestamp(...))]`
+#[derive(Default)]
+pub struct TimestampAttrs {
+    pub resolution: Option<syn::Ident>,
+    pub epoch: Option<syn::Path>,
+}
+
+impl TimestampAttrs {
+    /// The `rustbus::wire::Timestamp` type a field of type `ty` is marshalled as
+    pub fn wire_type(&self, ty: &syn::Type) -> proc_macro2::TokenStream {
+        let resolution = match &self.resolution {
+            Some(resolution) => quote::quote! { ::rustbus::wire::#resolution },
+            None => quote::quote! { ::rustbus::wire::Microseconds },
+        };
+        let epoch = match &self.epoch {
+            Some(epoch) => quote::quote! { #epoch },
+            None => quote::quote! { ::rustbus::wire::UnixEpoch },
+        };
+        quote::quote! { ::rustbus::wire::Timestamp<#ty, #resolution, #epoch> }
+    }
+}
+
+fn timestamp_attrs(meta: &syn::meta::ParseNestedMeta) -> syn::Result<TimestampAttrs> {
+    let mut parsed = TimestampAttrs::default();
+    if !meta.input.peek(syn::token::Paren) {
+        return Ok(parsed);
+    }
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("resolution") {
+            let name: syn::LitStr = meta.value()?.parse()?;
+            let resolution = match name.value().as_str() {
+                "seconds" => "Seconds",
+                "millis" => "Milliseconds",
+                "micros" => "Microseconds",
+                "nanos" => "Nanoseconds",
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        name,
+                        "unknown resolution, expected `seconds`, `millis`, `micros` or `nanos`",
+                    ))
+                }
+            };
+            parsed.resolution = Some(syn::Ident::new(resolution, name.span()));
+        } else if meta.path.is_ident("epoch") {
+            let path: syn::LitStr = meta.value()?.parse()?;
+            parsed.epoch = Some(path.parse()?);
+        } else {
+            return Err(meta.error("unknown timestamp argument, expected `resolution` or `epoch`"));
+        }
+        Ok(())
+    })?;
+    Ok(parsed)
+}
+
+fn rustbus_attrs(attrs: &[syn::Attribute]) -> impl Iterator<Item = &syn::Attribute> {
+    attrs.iter().filter(|attr| attr.path().is_ident("rustbus"))
+}
+
+pub fn container_attrs(attrs: &[syn::Attribute]) -> syn::Result<ContainerAttrs> {
+    let mut parsed = ContainerAttrs::default();
+    for attr in rustbus_attrs(attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("dict") {
+                parsed.dict = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown rustbus attribute, expected `dict`"))
+            }
+        })?;
+    }
+    Ok(parsed)
+}
+
+pub fn field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut parsed = FieldAttrs::default();
+    for attr in rustbus_attrs(&field.attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let name: syn::LitStr = meta.value()?.parse()?;
+                parsed.rename = Some(name.value());
+            } else if meta.path.is_ident("variant") {
+                parsed.variant = true;
+            } else if meta.path.is_ident("skip") {
+                parsed.skip = true;
+            } else if meta.path.is_ident("default") {
+                parsed.default = true;
+            } else if meta.path.is_ident("timestamp") {
+                parsed.timestamp = Some(timestamp_attrs(&meta)?);
+            } else {
+                return Err(meta.error(
+                    "unknown rustbus attribute, expected `rename`, `variant`, `skip`, `default` or `timestamp`",
+                ));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(parsed)
+}
+
+/// Fails if any of the fields carries a `#[rustbus(...)]` attribute
+pub fn reject_field_attrs<'a>(fields: impl IntoIterator<Item = &'a syn::Field>) -> syn::Result<()> {
+    for field in fields {
+        if let Some(attr) = rustbus_attrs(&field.attrs).next() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "rustbus attributes are not supported on the fields of enum variants",
+            ));
+        }
+    }
+    Ok(())
+}
@@ -1,45 +1,86 @@
+mod attrs;
 mod structs;
 mod variants;
 
-#[proc_macro_derive(Marshal)]
+/// Structs are marshalled as a dbus struct of their fields. This can be tuned with `#[rustbus(...)]` attributes:
+///
+/// * `#[rustbus(dict)]` on the struct marshals it as an `a{sv}` dict with one entry per field, keyed by the field name.
+///   This is the shape of most property maps in freedesktop APIs. Fields of type `Option<T>` are left out if they are
+///   `None` and are `None` if their key is missing, which fits partial maps like the ones in `PropertiesChanged`.
+/// * `#[rustbus(rename = "Key")]` on a field of a dict struct changes its key
+/// * `#[rustbus(default)]` on a field of a dict struct uses `Default::default()` if the key is missing when
+///   unmarshalling, otherwise `UnmarshalError::MissingDictKey` is returned. Unknown keys are ignored.
+/// * `#[rustbus(variant)]` on a field of a plain struct marshals it as `v` instead of its own signature
+/// * `#[rustbus(skip)]` on a field leaves it out, it is set to `Default::default()` when unmarshalling
+#[proc_macro_derive(Marshal, attributes(rustbus))]
 pub fn derive_marshal(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast: syn::DeriveInput = syn::parse(input).unwrap();
 
-    match ast.data {
-        syn::Data::Struct(data) => {
-            structs::make_struct_marshal_impl(&ast.ident, &ast.generics, &data.fields).into()
-        }
-        syn::Data::Enum(data) => {
-            variants::make_variant_marshal_impl(&ast.ident, &ast.generics, &data.variants).into()
-        }
-        _ => unimplemented!("Nothing but structs can be derived on right now"),
+    match &ast.data {
+        syn::Data::Struct(data) => match structs::parse_struct(&ast, &data.fields) {
+            Ok(def) => structs::make_struct_marshal_impl(&ast.ident, &ast.generics, &def).into(),
+            Err(err) => err.to_compile_error().into(),
+        },
+        syn::Data::Enum(data) => match variants::check_variants(&data.variants) {
+            Ok(()) => {
+                variants::make_variant_marshal_impl(&ast.ident, &ast.generics, &data.variants)
+                    .into()
+            }
+            Err(err) => err.to_compile_error().into(),
+        },
+        syn::Data::Union(_) => unsupported_union(&ast),
     }
 }
-#[proc_macro_derive(Unmarshal)]
+/// Structs are unmarshalled from a dbus struct of their fields, or from an `a{sv}` dict if they carry
+/// `#[rustbus(dict)]`. The `#[rustbus(...)]` attributes are described on the `Marshal` derive.
+///
+/// Enums are unmarshalled from a dbus variant: the variant whose fields match the signature in the variant is chosen,
+/// a single unnamed field is matched by its own signature and multiple fields by the struct of them. The first matching
+/// variant wins, if none matches `UnmarshalError::NoMatchingVariantFound` is returned.
+///
+/// Fields like `&'a str` or `&'a [u8]` borrow from the message buffer in structs and enums alike, nothing is copied.
+/// The lifetimes of the type are bound to the lifetime of the buffer.
+#[proc_macro_derive(Unmarshal, attributes(rustbus))]
 pub fn derive_unmarshal(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast: syn::DeriveInput = syn::parse(input).unwrap();
 
-    match ast.data {
-        syn::Data::Struct(data) => {
-            structs::make_struct_unmarshal_impl(&ast.ident, &ast.generics, &data.fields).into()
-        }
-        syn::Data::Enum(data) => {
-            variants::make_variant_unmarshal_impl(&ast.ident, &ast.generics, &data.variants).into()
-        }
-        _ => unimplemented!("Nothing but structs can be derived on right now"),
+    match &ast.data {
+        syn::Data::Struct(data) => match structs::parse_struct(&ast, &data.fields) {
+            Ok(def) => structs::make_struct_unmarshal_impl(&ast.ident, &ast.generics, &def).into(),
+            Err(err) => err.to_compile_error().into(),
+        },
+        syn::Data::Enum(data) => match variants::check_variants(&data.variants) {
+            Ok(()) => {
+                variants::make_variant_unmarshal_impl(&ast.ident, &ast.generics, &data.variants)
+                    .into()
+            }
+            Err(err) => err.to_compile_error().into(),
+        },
+        syn::Data::Union(_) => unsupported_union(&ast),
     }
 }
-#[proc_macro_derive(Signature)]
+#[proc_macro_derive(Signature, attributes(rustbus))]
 pub fn derive_signature(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast: syn::DeriveInput = syn::parse(input).unwrap();
 
-    match ast.data {
-        syn::Data::Struct(data) => {
-            structs::make_struct_signature_impl(&ast.ident, &ast.generics, &data.fields).into()
-        }
-        syn::Data::Enum(_data) => {
-            variants::make_variant_signature_imp(&ast.ident, &ast.generics).into()
-        }
-        _ => unimplemented!("Nothing but structs can be derived on right now"),
+    match &ast.data {
+        syn::Data::Struct(data) => match structs::parse_struct(&ast, &data.fields) {
+            Ok(def) => structs::make_struct_signature_impl(&ast.ident, &ast.generics, &def).into(),
+            Err(err) => err.to_compile_error().into(),
+        },
+        syn::Data::Enum(data) => match variants::check_variants(&data.variants) {
+            Ok(()) => variants::make_variant_signature_imp(&ast.ident, &ast.generics).into(),
+            Err(err) => err.to_compile_error().into(),
+        },
+        syn::Data::Union(_) => unsupported_union(&ast),
     }
 }
+
+fn unsupported_union(ast: &syn::DeriveInput) -> proc_macro::TokenStream {
+    syn::Error::new_spanned(
+        &ast.ident,
+        "unions can not be derived on, only structs and enums",
+    )
+    .to_compile_error()
+    .into()
+}
@@ -1,13 +1,145 @@
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 
+use crate::attrs::{self, FieldAttrs};
+
+/// A struct with its `#[rustbus(...)]` attributes parsed and checked
+pub struct StructDef {
+    dict: bool,
+    fields: Vec<Field>,
+}
+
+struct Field {
+    ident: syn::Ident,
+    ty: syn::Type,
+    attrs: FieldAttrs,
+    /// For `Option<T>` fields of dict structs the `T`. The key is left out for `None` and a missing key is `None`.
+    optional: Option<syn::Type>,
+}
+
+impl Field {
+    /// The key of the field in a dict struct
+    fn key(&self) -> String {
+        self.attrs
+            .rename
+            .clone()
+            .unwrap_or_else(|| self.ident.to_string())
+    }
+
+    /// The type of the value in a dict struct
+    fn value_ty(&self) -> &syn::Type {
+        self.optional.as_ref().unwrap_or(&self.ty)
+    }
+
+    /// The type that is actually marshalled for a value of type `ty`
+    fn wire_ty(&self, ty: &syn::Type) -> TokenStream {
+        match &self.attrs.timestamp {
+            Some(timestamp) => timestamp.wire_type(ty),
+            None => ty.to_token_stream(),
+        }
+    }
+
+    /// Converts a reference to the value of the field into a reference to its wire type
+    fn wire_value(&self, value: TokenStream) -> TokenStream {
+        match &self.attrs.timestamp {
+            Some(timestamp) => {
+                let wire_ty = timestamp.wire_type(&syn::parse_quote!(_));
+                quote! { &<#wire_ty>::new(::core::clone::Clone::clone(#value)) }
+            }
+            None => value,
+        }
+    }
+
+    /// Converts an unmarshalled value of the wire type into the type of the field
+    fn field_value(&self, value: TokenStream) -> TokenStream {
+        match &self.attrs.timestamp {
+            Some(_) => quote! { #value.into_inner() },
+            None => value,
+        }
+    }
+}
+
+/// Returns `T` if the type is spelled as `Option<T>`
+fn option_inner(ty: &syn::Type) -> Option<syn::Type> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    if path.qself.is_some() {
+        return None;
+    }
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.iter().collect::<Vec<_>>().as_slice() {
+        [syn::GenericArgument::Type(inner)] => Some(inner.clone()),
+        _ => None,
+    }
+}
+
+pub fn parse_struct(ast: &syn::DeriveInput, fields: &syn::Fields) -> syn::Result<StructDef> {
+    let dict = attrs::container_attrs(&ast.attrs)?.dict;
+    let mut parsed = Vec::new();
+    for field in fields {
+        let Some(ident) = field.ident.clone() else {
+            return Err(syn::Error::new_spanned(
+                field,
+                "only structs with named fields can be derived on",
+            ));
+        };
+        let field_attrs = attrs::field_attrs(field)?;
+        if !dict && (field_attrs.rename.is_some() || field_attrs.default) {
+            return Err(syn::Error::new_spanned(
+                field,
+                "`rename` and `default` only apply to the fields of #[rustbus(dict)] structs",
+            ));
+        }
+        if dict && field_attrs.variant {
+            return Err(syn::Error::new_spanned(
+                field,
+                "the values of #[rustbus(dict)] structs are always marshalled as variants",
+            ));
+        }
+        let optional = if dict { option_inner(&field.ty) } else { None };
+        if optional.is_some() && field_attrs.default {
+            return Err(syn::Error::new_spanned(
+                field,
+                "`Option` fields of dict structs are already `None` if the key is missing",
+            ));
+        }
+        parsed.push(Field {
+            ident,
+            ty: field.ty.clone(),
+            attrs: field_attrs,
+            optional,
+        });
+    }
+    if !dict && parsed.iter().all(|field| field.attrs.skip) {
+        return Err(syn::Error::new_spanned(
+            &ast.ident,
+            "structs need at least one field that is not skipped, empty structs are not allowed in dbus",
+        ));
+    }
+    Ok(StructDef {
+        dict,
+        fields: parsed,
+    })
+}
+
 pub fn make_struct_marshal_impl(
     ident: &syn::Ident,
     generics: &syn::Generics,
-    fields: &syn::Fields,
+    def: &StructDef,
 ) -> TokenStream {
     let (impl_gen, typ_gen, clause_gen) = generics.split_for_impl();
-    let marshal = struct_field_marshal(fields);
+    let marshal = if def.dict {
+        dict_field_marshal(&def.fields)
+    } else {
+        struct_field_marshal(&def.fields)
+    };
 
     quote! {
         impl #impl_gen ::rustbus::Marshal for #ident #typ_gen #clause_gen {
@@ -21,9 +153,13 @@ pub fn make_struct_marshal_impl(
 pub fn make_struct_unmarshal_impl(
     ident: &syn::Ident,
     generics: &syn::Generics,
-    fields: &syn::Fields,
+    def: &StructDef,
 ) -> TokenStream {
-    let marshal = struct_field_unmarshal(fields);
+    let marshal = if def.dict {
+        dict_field_unmarshal(&def.fields)
+    } else {
+        struct_field_unmarshal(&def.fields)
+    };
 
     let mut bufdef = syn::LifetimeParam {
         attrs: Vec::new(),
@@ -59,11 +195,38 @@ pub fn make_struct_unmarshal_impl(
 pub fn make_struct_signature_impl(
     ident: &syn::Ident,
     generics: &syn::Generics,
-    fields: &syn::Fields,
+    def: &StructDef,
 ) -> TokenStream {
     let (impl_gen, typ_gen, clause_gen) = generics.split_for_impl();
-    let signature = struct_field_sigs(fields);
-    let has_sig = struct_field_has_sigs(fields);
+
+    if def.dict {
+        return quote! {
+            impl #impl_gen ::rustbus::Signature for #ident #typ_gen #clause_gen {
+                #[inline]
+                fn signature() -> ::rustbus::signature::Type {
+                    ::rustbus::signature::Type::Container(::rustbus::signature::Container::Dict(
+                        ::rustbus::signature::Base::String,
+                        ::std::boxed::Box::new(::rustbus::signature::Type::Container(
+                            ::rustbus::signature::Container::Variant,
+                        )),
+                    ))
+                }
+                fn alignment() -> usize {
+                    4
+                }
+                #[inline]
+                fn sig_str(s_buf: &mut ::rustbus::wire::marshal::traits::SignatureBuffer) {
+                    s_buf.push_static("a{sv}");
+                }
+                fn has_sig(sig: &str) -> bool {
+                    sig == "a{sv}"
+                }
+            }
+        };
+    }
+
+    let signature = struct_field_sigs(&def.fields);
+    let has_sig = struct_field_has_sigs(&def.fields);
 
     quote! {
         impl #impl_gen ::rustbus::Signature for #ident #typ_gen #clause_gen {
@@ -81,51 +244,181 @@ pub fn make_struct_signature_impl(
     }
 }
 
-fn struct_field_marshal(fields: &syn::Fields) -> TokenStream {
-    let field_names = fields
+fn struct_field_marshal(fields: &[Field]) -> TokenStream {
+    let marshal = fields
         .iter()
-        .map(|field| field.ident.as_ref().unwrap().to_token_stream());
+        .filter(|field| !field.attrs.skip)
+        .map(|field| {
+            let name = &field.ident;
+            let value = field.wire_value(quote! { &self.#name });
+            if field.attrs.variant {
+                quote! { ::rustbus::Marshal::marshal_as_variant(#value, ctx)?; }
+            } else {
+                quote! { ::rustbus::Marshal::marshal(#value, ctx)?; }
+            }
+        });
 
     quote! {
             ctx.align_to(8);
             #(
-                self.#field_names.marshal(ctx)?;
+                #marshal
             )*
             Ok(())
     }
 }
-fn struct_field_unmarshal(fields: &syn::Fields) -> TokenStream {
-    let field_names = fields
-        .iter()
-        .map(|field| field.ident.as_ref().unwrap().to_token_stream());
-
-    let field_types = fields.iter().map(|field| field.ty.to_token_stream());
+fn struct_field_unmarshal(fields: &[Field]) -> TokenStream {
+    let field_names = fields.iter().map(|field| &field.ident);
+    let unmarshal = fields.iter().map(|field| {
+        let ty = field.wire_ty(&field.ty);
+        if field.attrs.skip {
+            quote! { ::core::default::Default::default() }
+        } else if field.attrs.variant {
+            field.field_value(quote! {
+                <::rustbus::wire::unmarshal::traits::Variant as ::rustbus::Unmarshal>::unmarshal(ctx)?
+                    .get::<#ty>()?
+            })
+        } else {
+            field.field_value(quote! { <#ty as ::rustbus::Unmarshal>::unmarshal(ctx)? })
+        }
+    });
 
     quote! {
             ctx.align_to(8)?;
 
             let this = Self{
                 #(
-                    #field_names: <#field_types as ::rustbus::Unmarshal>::unmarshal(ctx)?,
+                    #field_names: #unmarshal,
                 )*
             };
             Ok(this)
     }
 }
-fn struct_field_sigs(fields: &syn::Fields) -> TokenStream {
-    let field_types = fields
+fn dict_field_marshal(fields: &[Field]) -> TokenStream {
+    let entries = fields
         .iter()
-        .map(|field| field.ty.to_token_stream())
+        .filter(|field| !field.attrs.skip)
+        .map(|field| {
+            let name = &field.ident;
+            let key = field.key();
+            if field.optional.is_some() {
+                let value = field.wire_value(quote! { value });
+                quote! {
+                    if let Some(value) = &self.#name {
+                        ctx.align_to(8);
+                        ::rustbus::Marshal::marshal(&#key, ctx)?;
+                        ::rustbus::Marshal::marshal_as_variant(#value, ctx)?;
+                    }
+                }
+            } else {
+                let value = field.wire_value(quote! { &self.#name });
+                quote! {
+                    ctx.align_to(8);
+                    ::rustbus::Marshal::marshal(&#key, ctx)?;
+                    ::rustbus::Marshal::marshal_as_variant(#value, ctx)?;
+                }
+            }
+        });
+
+    quote! {
+            // always align to 4
+            ctx.align_to(4);
+            let size_pos = ctx.buf.len();
+            ctx.buf.extend_from_slice(&[0; 4]);
+            // always align to 8
+            ctx.align_to(8);
+            let size_before = ctx.buf.len();
+            #(
+                #entries
+            )*
+            let size_of_content = ctx.buf.len() - size_before;
+            if size_of_content > u32::MAX as usize {
+                return Err(::rustbus::wire::errors::MarshalError::MessageTooLarge);
+            }
+            ::rustbus::wire::util::insert_u32(
+                ctx.byteorder,
+                size_of_content as u32,
+                &mut ctx.buf[size_pos..size_pos + 4],
+            );
+            Ok(())
+    }
+}
+fn dict_field_unmarshal(fields: &[Field]) -> TokenStream {
+    let marshalled = fields.iter().filter(|field| !field.attrs.skip);
+    let keys = marshalled.clone().map(Field::key);
+    let slots = marshalled
+        .clone()
+        .map(|field| quote::format_ident!("__field_{}", field.ident))
         .collect::<Vec<_>>();
-    if field_types.is_empty() {
-        panic!("Signature can not be derived for empty structs!")
+    let slot_types = marshalled
+        .clone()
+        .map(|field| field.value_ty().to_token_stream());
+    let slot_values = marshalled.map(|field| {
+        let wire_ty = field.wire_ty(field.value_ty());
+        field.field_value(quote! { value.get::<#wire_ty>()? })
+    });
+
+    let field_names = fields.iter().map(|field| &field.ident);
+    let values = fields.iter().map(|field| {
+        let slot = quote::format_ident!("__field_{}", field.ident);
+        let key = field.key();
+        if field.attrs.skip {
+            quote! { ::core::default::Default::default() }
+        } else if field.optional.is_some() {
+            quote! { #slot }
+        } else if field.attrs.default {
+            quote! { #slot.unwrap_or_default() }
+        } else {
+            quote! {
+                #slot.ok_or_else(|| ::rustbus::wire::errors::UnmarshalError::MissingDictKey(#key.to_owned()))?
+            }
+        }
+    });
+
+    quote! {
+            let bytes_in_dict = ctx.read_u32()? as usize;
+            // align even if no entries are present
+            ctx.align_to(8)?;
+            let mut entries = ctx.sub_context(bytes_in_dict)?;
+
+            #(
+                let mut #slots: ::core::option::Option<#slot_types> = None;
+            )*
+            while !entries.remainder().is_empty() {
+                entries.align_to(8)?;
+                let key = entries.read_str()?;
+                let value = <::rustbus::wire::unmarshal::traits::Variant as ::rustbus::Unmarshal>::unmarshal(&mut entries)?;
+                match key {
+                    #(
+                        #keys => #slots = Some(#slot_values),
+                    )*
+                    // entries the struct does not know about are ignored
+                    _ => {}
+                }
+            }
+
+            let this = Self{
+                #(
+                    #field_names: #values,
+                )*
+            };
+            Ok(this)
     }
+}
+fn struct_field_sigs(fields: &[Field]) -> TokenStream {
+    let sigs = fields.iter().filter(|field| !field.attrs.skip).map(|field| {
+        let ty = field.wire_ty(&field.ty);
+        if field.attrs.variant {
+            quote! { ::rustbus::signature::Type::Container(::rustbus::signature::Container::Variant) }
+        } else {
+            quote! { <#ty as rustbus::Signature>::signature() }
+        }
+    });
 
     quote! {
             let mut sigs = vec![];
 
             #(
-                sigs.push(<#field_types as rustbus::Signature>::signature());
+                sigs.push(#sigs);
             )*
 
             ::rustbus::signature::Type::Container(::rustbus::signature::Container::Struct(
@@ -133,22 +426,26 @@ fn struct_field_sigs(fields: &syn::Fields) -> TokenStream {
             ))
     }
 }
-fn struct_field_has_sigs(fields: &syn::Fields) -> TokenStream {
-    let field_types = fields
+fn struct_field_has_sigs(fields: &[Field]) -> TokenStream {
+    let has_sigs = fields
         .iter()
-        .map(|field| field.ty.to_token_stream())
-        .collect::<Vec<_>>();
-    if field_types.is_empty() {
-        panic!("Signature can not be derived for empty structs!")
-    }
+        .filter(|field| !field.attrs.skip)
+        .map(|field| {
+            let ty = field.wire_ty(&field.ty);
+            if field.attrs.variant {
+                quote! { |sig: &str| sig == "v" }
+            } else {
+                quote! { <#ty as rustbus::Signature>::has_sig }
+            }
+        });
 
     quote! {
-        if sig.starts_with('(') {
-            let mut iter = ::rustbus::signature::SignatureIter::new(&sig[1..sig.len() - 1]);
+        if let Some(sig) = sig.strip_prefix('(').and_then(|sig| sig.strip_suffix(')')) {
+            let mut iter = ::rustbus::signature::SignatureIter::new(sig);
             let mut accu = true;
 
             #(
-                accu &= <#field_types as rustbus::Signature>::has_sig(iter.next().unwrap());
+                accu &= iter.next().is_some_and(#has_sigs);
             )*
 
             accu
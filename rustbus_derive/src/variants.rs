@@ -2,6 +2,17 @@ use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 use syn::{punctuated::Punctuated, token::Comma, Variant};
 
+/// Variants are marshalled as a dbus variant holding their fields, so each of them needs at least one field
+pub fn check_variants(variants: &Punctuated<Variant, Comma>) -> Result<(), syn::Error> {
+    if let Some(variant) = variants.iter().find(|variant| variant.fields.is_empty()) {
+        return Err(syn::Error::new_spanned(
+            variant,
+            "variants without fields can not be marshalled as a dbus variant",
+        ));
+    }
+    crate::attrs::reject_field_attrs(variants.iter().flat_map(|variant| &variant.fields))
+}
+
 pub fn make_variant_signature_imp(ident: &syn::Ident, generics: &syn::Generics) -> TokenStream {
     let (impl_gen, typ_gen, clause_gen) = generics.split_for_impl();
 
@@ -144,7 +155,7 @@ fn variant_marshal(enum_name: syn::Ident, variant: &syn::Variant) -> TokenStream
             }
         }
     } else {
-        panic!("Variants with no fields are not supported yet")
+        unreachable!("checked by check_variants")
     }
 }
 
@@ -269,6 +280,6 @@ fn variant_unmarshal(enum_name: syn::Ident, variant: &syn::Variant) -> TokenStre
             }
         }
     } else {
-        panic!("Variants with no fields are not supported yet")
+        unreachable!("checked by check_variants")
     }
 }
@@ -76,6 +76,12 @@ fn test_derive() {
         },
     };
     assert_eq!(b, sig.body.parser().get::<B>().unwrap());
+
+    // signatures of received messages are not trusted
+    assert!(<SubTypeA as rustbus::Signature>::has_sig("(yquts)"));
+    for invalid in ["", "(", ")", "(yq)", "(yqut", "a(yquts)"] {
+        assert!(!<SubTypeA as rustbus::Signature>::has_sig(invalid));
+    }
 }
 
 #[test]
@@ -163,3 +169,259 @@ pub fn test_enum_derive() {
         err
     );
 }
+
+#[test]
+fn test_attributes() {
+    use rustbus::wire::errors::UnmarshalError;
+    use rustbus::wire::unmarshal::traits::Variant;
+    use rustbus::wire::VarDict;
+    use rustbus::MessageBuilder;
+    use rustbus_derive::{Marshal, Signature, Unmarshal};
+
+    #[derive(Marshal, Unmarshal, Signature, Default, Debug, PartialEq, Eq)]
+    #[rustbus(dict)]
+    struct Props {
+        #[rustbus(rename = "Name")]
+        name: String,
+        volume: u32,
+        #[rustbus(default)]
+        muted: bool,
+        #[rustbus(skip)]
+        cache: Vec<u8>,
+    }
+
+    #[derive(Marshal, Unmarshal, Signature, Debug, PartialEq, Eq)]
+    struct Setting {
+        key: String,
+        #[rustbus(variant)]
+        value: u64,
+    }
+
+    assert!(<Props as rustbus::Signature>::has_sig("a{sv}"));
+    assert!(<Setting as rustbus::Signature>::has_sig("(sv)"));
+    assert!(!<Setting as rustbus::Signature>::has_sig("(st)"));
+
+    let props = Props {
+        name: "speaker".into(),
+        volume: 42,
+        muted: true,
+        cache: vec![1, 2, 3],
+    };
+    let setting = Setting {
+        key: "answer".into(),
+        value: 42,
+    };
+
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(&props).unwrap();
+    sig.body.push_param(&setting).unwrap();
+    assert_eq!(sig.get_sig(), "a{sv}(sv)");
+
+    let (dict, setting_2) = sig
+        .body
+        .parser()
+        .get2::<std::collections::HashMap<String, Variant>, Setting>()
+        .unwrap();
+    assert_eq!(dict.len(), 3);
+    assert_eq!(dict["Name"].get::<&str>().unwrap(), "speaker");
+    assert_eq!(dict["volume"].get::<u32>().unwrap(), 42);
+    assert!(dict["muted"].get::<bool>().unwrap());
+    assert_eq!(setting_2, setting);
+
+    let props_2 = sig.body.parser().get::<Props>().unwrap();
+    assert_eq!(
+        props_2,
+        Props {
+            cache: Vec::new(),
+            ..props
+        }
+    );
+
+    // missing keys fall back to the default if allowed, unknown keys are ignored
+    let mut partial = VarDict::new();
+    partial.insert("Name", "speaker").unwrap();
+    partial.insert("volume", 7u32).unwrap();
+    partial.insert("balance", 0.5f64).unwrap();
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(&partial).unwrap();
+    assert_eq!(
+        sig.body.parser().get::<Props>().unwrap(),
+        Props {
+            name: "speaker".into(),
+            volume: 7,
+            ..Props::default()
+        }
+    );
+
+    assert!(partial.remove("volume"));
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(&partial).unwrap();
+    assert_eq!(
+        sig.body.parser().get::<Props>(),
+        Err(UnmarshalError::MissingDictKey("volume".into()))
+    );
+}
+
+#[test]
+fn test_optional_dict_fields() {
+    use rustbus::wire::VarDict;
+    use rustbus::MessageBuilder;
+    use rustbus_derive::{Marshal, Signature, Unmarshal};
+
+    #[derive(Marshal, Unmarshal, Signature, Default, Debug, PartialEq, Eq)]
+    #[rustbus(dict)]
+    struct ChangedProps<'a> {
+        #[rustbus(rename = "ActiveState")]
+        active_state: Option<&'a str>,
+        #[rustbus(rename = "MainPID")]
+        main_pid: Option<u32>,
+    }
+
+    let changed = ChangedProps {
+        active_state: Some("active"),
+        main_pid: None,
+    };
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(&changed).unwrap();
+
+    let dict = sig.body.parser().get::<VarDict>().unwrap();
+    assert_eq!(dict.len(), 1);
+    assert_eq!(dict.get::<&str>("ActiveState"), Ok(Some("active")));
+    assert!(!dict.contains_key("MainPID"));
+    assert_eq!(sig.body.parser().get::<ChangedProps>(), Ok(changed));
+
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(VarDict::new()).unwrap();
+    assert_eq!(
+        sig.body.parser().get::<ChangedProps>(),
+        Ok(ChangedProps::default())
+    );
+}
+
+#[test]
+fn test_timestamp_fields() {
+    use rustbus::wire::{Epoch, VarDict};
+    use rustbus::MessageBuilder;
+    use rustbus_derive::{Marshal, Signature, Unmarshal};
+
+    type DateTime = chrono::DateTime<chrono::Utc>;
+
+    pub struct GpsEpoch;
+    impl Epoch for GpsEpoch {
+        const UNIX_OFFSET_SECS: i64 = 315_964_800;
+    }
+
+    #[derive(Marshal, Unmarshal, Signature, Debug, PartialEq, Eq)]
+    struct Times {
+        micros: DateTime,
+        #[rustbus(timestamp(resolution = "millis"))]
+        millis: DateTime,
+        #[rustbus(timestamp(resolution = "seconds", epoch = "GpsEpoch"))]
+        gps: DateTime,
+    }
+
+    #[derive(Marshal, Unmarshal, Signature, Debug, PartialEq, Eq)]
+    #[rustbus(dict)]
+    struct DictTimes {
+        #[rustbus(timestamp(resolution = "seconds"))]
+        since: DateTime,
+        #[rustbus(timestamp(resolution = "seconds"))]
+        until: Option<DateTime>,
+    }
+
+    let dt = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let times = Times {
+        micros: dt,
+        millis: dt,
+        gps: dt,
+    };
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(&times).unwrap();
+    assert_eq!(sig.get_sig(), "(ttt)");
+    assert_eq!(
+        sig.body.parser().get::<(u64, u64, u64)>(),
+        Ok((
+            1_700_000_000_000_000,
+            1_700_000_000_000,
+            1_700_000_000 - 315_964_800
+        ))
+    );
+    assert_eq!(sig.body.parser().get::<Times>(), Ok(times));
+
+    let dict_times = DictTimes {
+        since: dt,
+        until: None,
+    };
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    sig.body.push_param(&dict_times).unwrap();
+    let dict = sig.body.parser().get::<VarDict>().unwrap();
+    assert_eq!(dict.get::<u64>("since"), Ok(Some(1_700_000_000)));
+    assert!(!dict.contains_key("until"));
+    assert_eq!(sig.body.parser().get::<DictTimes>(), Ok(dict_times));
+}
+
+#[test]
+fn test_borrowed_enum_derive() {
+    use rustbus::message_builder::MarshalledMessage;
+    use rustbus::MessageBuilder;
+    use rustbus_derive::{Marshal, Signature, Unmarshal};
+
+    #[derive(Marshal, Unmarshal, Signature, PartialEq, Eq, Debug)]
+    enum Borrowed<'a, 'b> {
+        Name(&'a str),
+        Bytes(&'b [u8]),
+        Both { name: &'a str, bytes: &'b [u8] },
+    }
+
+    #[derive(Marshal, Unmarshal, Signature, PartialEq, Eq, Debug)]
+    struct Outer<'a> {
+        first: Borrowed<'a, 'a>,
+        rest: Vec<Borrowed<'a, 'a>>,
+    }
+
+    // the values borrow from the message, not from the parser
+    fn parse(msg: &MarshalledMessage) -> Outer<'_> {
+        msg.body.parser().get().unwrap()
+    }
+
+    let mut sig = MessageBuilder::new()
+        .signal("io.killing.spark", "TestSignal", "/io/killing/spark")
+        .build();
+    let outer = Outer {
+        first: Borrowed::Name("name"),
+        rest: vec![
+            Borrowed::Bytes(&[1, 2, 3]),
+            Borrowed::Both {
+                name: "both",
+                bytes: &[4],
+            },
+        ],
+    };
+    sig.body.push_param(&outer).unwrap();
+
+    let parsed = parse(&sig);
+    assert_eq!(parsed, outer);
+    let buf = sig.get_buf().as_ptr_range();
+    match parsed.first {
+        Borrowed::Name(name) => assert!(buf.contains(&name.as_ptr())),
+        other => panic!("unexpected variant {:?}", other),
+    }
+    match parsed.rest[0] {
+        Borrowed::Bytes(bytes) => assert!(buf.contains(&bytes.as_ptr())),
+        ref other => panic!("unexpected variant {:?}", other),
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * C API for building and parsing dbus messages with rustbus, see the crate docs of rustbus_ffi.
+ *
+ * Functions returning int return RUSTBUS_OK or one of the negative RUSTBUS_ERR_* codes. Messages are freed with
+ * rustbus_message_free, strings returned by the library live as long as their message.
+ */
+#ifndef RUSTBUS_H
+#define RUSTBUS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RUSTBUS_OK 0
+#define RUSTBUS_ERR_NULL (-1)
+#define RUSTBUS_ERR_INVALID_ARG (-2)
+#define RUSTBUS_ERR_MARSHAL (-3)
+#define RUSTBUS_ERR_UNMARSHAL (-4)
+#define RUSTBUS_ERR_NOT_ENOUGH_BYTES (-5)
+#define RUSTBUS_ERR_WRONG_TYPE (-6)
+#define RUSTBUS_ERR_OUT_OF_RANGE (-7)
+
+#define RUSTBUS_MESSAGE_TYPE_CALL 1
+#define RUSTBUS_MESSAGE_TYPE_REPLY 2
+#define RUSTBUS_MESSAGE_TYPE_ERROR 3
+#define RUSTBUS_MESSAGE_TYPE_SIGNAL 4
+
+#define RUSTBUS_HEADER_INTERFACE 0
+#define RUSTBUS_HEADER_MEMBER 1
+#define RUSTBUS_HEADER_OBJECT 2
+#define RUSTBUS_HEADER_DESTINATION 3
+#define RUSTBUS_HEADER_SENDER 4
+#define RUSTBUS_HEADER_ERROR_NAME 5
+
+typedef struct RustbusMessage RustbusMessage;
+
+/* destination and interface may be NULL */
+int rustbus_message_new_call(const char *destination, const char *object, const char *interface, const char *member,
+                             RustbusMessage **out);
+int rustbus_message_new_signal(const char *interface, const char *member, const char *object, RustbusMessage **out);
+int rustbus_message_new_reply(const RustbusMessage *call, RustbusMessage **out);
+/* text may be NULL */
+int rustbus_message_new_error(const RustbusMessage *call, const char *error_name, const char *text,
+                              RustbusMessage **out);
+void rustbus_message_free(RustbusMessage *msg);
+
+/* One of the RUSTBUS_MESSAGE_TYPE_* constants */
+int rustbus_message_type(const RustbusMessage *msg);
+/* 0 if not set */
+uint32_t rustbus_message_serial(const RustbusMessage *msg);
+uint32_t rustbus_message_reply_serial(const RustbusMessage *msg);
+/* One of the RUSTBUS_HEADER_* fields, NULL if not set */
+const char *rustbus_message_header(const RustbusMessage *msg, int field);
+
+size_t rustbus_message_arg_count(const RustbusMessage *msg);
+/* The first character of the signature of the param, e.g. 'u' or 'a'. 0 if there is no such param. */
+int rustbus_message_arg_type(const RustbusMessage *msg, size_t idx);
+
+int rustbus_message_push_byte(RustbusMessage *msg, uint8_t value);
+int rustbus_message_push_boolean(RustbusMessage *msg, bool value);
+int rustbus_message_push_int16(RustbusMessage *msg, int16_t value);
+int rustbus_message_push_uint16(RustbusMessage *msg, uint16_t value);
+int rustbus_message_push_int32(RustbusMessage *msg, int32_t value);
+int rustbus_message_push_uint32(RustbusMessage *msg, uint32_t value);
+int rustbus_message_push_int64(RustbusMessage *msg, int64_t value);
+int rustbus_message_push_uint64(RustbusMessage *msg, uint64_t value);
+int rustbus_message_push_double(RustbusMessage *msg, double value);
+int rustbus_message_push_string(RustbusMessage *msg, const char *value);
+int rustbus_message_push_object_path(RustbusMessage *msg, const char *value);
+
+int rustbus_message_get_byte(const RustbusMessage *msg, size_t idx, uint8_t *out);
+int rustbus_message_get_boolean(const RustbusMessage *msg, size_t idx, bool *out);
+int rustbus_message_get_int16(const RustbusMessage *msg, size_t idx, int16_t *out);
+int rustbus_message_get_uint16(const RustbusMessage *msg, size_t idx, uint16_t *out);
+int rustbus_message_get_int32(const RustbusMessage *msg, size_t idx, int32_t *out);
+int rustbus_message_get_uint32(const RustbusMessage *msg, size_t idx, uint32_t *out);
+int rustbus_message_get_int64(const RustbusMessage *msg, size_t idx, int64_t *out);
+int rustbus_message_get_uint64(const RustbusMessage *msg, size_t idx, uint64_t *out);
+int rustbus_message_get_double(const RustbusMessage *msg, size_t idx, double *out);
+/* Strings, object paths and signatures. The string lives as long as the message. */
+int rustbus_message_get_string(const RustbusMessage *msg, size_t idx, const char **out);
+
+/* serial must not be 0. Free the bytes with rustbus_bytes_free. */
+int rustbus_message_marshal(RustbusMessage *msg, uint32_t serial, uint8_t **out, size_t *out_len);
+void rustbus_bytes_free(uint8_t *bytes, size_t len);
+/* Parse the message at the start of bytes, consumed is set to its length */
+int rustbus_message_parse(const uint8_t *bytes, size_t len, size_t *consumed, RustbusMessage **out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
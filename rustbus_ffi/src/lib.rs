@@ -0,0 +1,779 @@
+//! A C API for the wire format of rustbus, so C components of mixed codebases can build and parse dbus messages with
+//! it. The declarations are in `include/rustbus.h`, link against the cdylib or the staticlib of this crate.
+//!
+//! Messages are opaque `RustbusMessage` handles that are freed with `rustbus_message_free`. Functions that can fail
+//! return `RUSTBUS_OK` or one of the negative `RUSTBUS_ERR_*` codes and hand out their results through out pointers.
+//! Strings are nul-terminated UTF-8, strings returned by the library live as long as the message they belong to.
+//!
+//! Only the basic types can be pushed and read. The params of a message are addressed by their index and their type
+//! is the first character of their signature, so containers in received messages can be recognized and skipped.
+//! Connections are not part of the API, the caller sends and receives the bytes.
+//!
+//! All pointers passed in have to be null or valid for the access the function makes, message handles have to come from
+//! this library and must not be used after they were freed. Null pointers are reported with `RUSTBUS_ERR_NULL`.
+#![allow(clippy::missing_safety_doc)]
+
+use std::convert::TryFrom;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::num::NonZeroU32;
+
+use rustbus::message_builder::{DynamicHeader, MarshalledMessage, MessageBuilder, MessageType};
+use rustbus::params::{validate_busname, validate_errorname, validate_interface};
+use rustbus::params::{validate_membername, validate_object_path, Error};
+use rustbus::signature::{Base, Type};
+use rustbus::wire::errors::UnmarshalError;
+use rustbus::wire::unmarshal::{self, HEADER_LEN};
+use rustbus::wire::unmarshal_context::{Cursor, UnmarshalContext};
+use rustbus::wire::ObjectPath;
+
+pub const RUSTBUS_OK: c_int = 0;
+/// A pointer argument was null
+pub const RUSTBUS_ERR_NULL: c_int = -1;
+/// A string argument is not UTF-8 or not a valid name or path
+pub const RUSTBUS_ERR_INVALID_ARG: c_int = -2;
+/// The message could not be marshalled
+pub const RUSTBUS_ERR_MARSHAL: c_int = -3;
+/// The bytes are not a valid message
+pub const RUSTBUS_ERR_UNMARSHAL: c_int = -4;
+/// The bytes do not contain a whole message yet
+pub const RUSTBUS_ERR_NOT_ENOUGH_BYTES: c_int = -5;
+/// The param has a different type than requested
+pub const RUSTBUS_ERR_WRONG_TYPE: c_int = -6;
+/// There is no param with that index
+pub const RUSTBUS_ERR_OUT_OF_RANGE: c_int = -7;
+
+pub const RUSTBUS_MESSAGE_TYPE_CALL: c_int = 1;
+pub const RUSTBUS_MESSAGE_TYPE_REPLY: c_int = 2;
+pub const RUSTBUS_MESSAGE_TYPE_ERROR: c_int = 3;
+pub const RUSTBUS_MESSAGE_TYPE_SIGNAL: c_int = 4;
+
+pub const RUSTBUS_HEADER_INTERFACE: c_int = 0;
+pub const RUSTBUS_HEADER_MEMBER: c_int = 1;
+pub const RUSTBUS_HEADER_OBJECT: c_int = 2;
+pub const RUSTBUS_HEADER_DESTINATION: c_int = 3;
+pub const RUSTBUS_HEADER_SENDER: c_int = 4;
+pub const RUSTBUS_HEADER_ERROR_NAME: c_int = 5;
+
+/// A param of a message as the C side sees it
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Byte(u8),
+    Boolean(bool),
+    Int16(i16),
+    Uint16(u16),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Double(f64),
+    /// Strings, object paths and signatures
+    Str(CString),
+    /// Containers and fds, only their type is reported
+    Other,
+}
+
+/// A message together with the nul-terminated copies of its header fields and params the C side borrows
+pub struct RustbusMessage {
+    msg: MarshalledMessage,
+    /// Indexed by the `RUSTBUS_HEADER_*` constants
+    header: [Option<CString>; 6],
+    /// The params of the body with the first character of their signature
+    args: Vec<(u8, Value)>,
+}
+
+impl RustbusMessage {
+    fn new(msg: MarshalledMessage, args: Vec<(u8, Value)>) -> Self {
+        let field = |f: &Option<String>| f.as_deref().and_then(|s| CString::new(s).ok());
+        let dynheader = &msg.dynheader;
+        let header = [
+            field(&dynheader.interface),
+            field(&dynheader.member),
+            field(&dynheader.object),
+            field(&dynheader.destination),
+            field(&dynheader.sender),
+            field(&dynheader.error_name),
+        ];
+        RustbusMessage { msg, header, args }
+    }
+
+    fn push<P: rustbus::Marshal>(&mut self, param: P, typ: u8, value: Value) -> c_int {
+        match self.msg.body.push_param(param) {
+            Ok(()) => {
+                self.args.push((typ, value));
+                RUSTBUS_OK
+            }
+            Err(_) => RUSTBUS_ERR_MARSHAL,
+        }
+    }
+
+    fn arg(&self, idx: usize) -> Result<&Value, c_int> {
+        self.args
+            .get(idx)
+            .map(|(_, value)| value)
+            .ok_or(RUSTBUS_ERR_OUT_OF_RANGE)
+    }
+}
+
+/// Borrow a string argument, null is allowed if `optional` is set
+unsafe fn str_arg<'a>(ptr: *const c_char, optional: bool) -> Result<Option<&'a str>, c_int> {
+    if ptr.is_null() {
+        return if optional {
+            Ok(None)
+        } else {
+            Err(RUSTBUS_ERR_NULL)
+        };
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map(Some)
+        .map_err(|_| RUSTBUS_ERR_INVALID_ARG)
+}
+
+/// Check a name with one of the validation functions of rustbus
+fn check<E>(name: Option<&str>, validate: impl FnOnce(&str) -> Result<(), E>) -> Result<(), c_int> {
+    match name {
+        Some(name) => validate(name).map_err(|_| RUSTBUS_ERR_INVALID_ARG),
+        None => Ok(()),
+    }
+}
+
+/// Hand a new message to the caller
+unsafe fn put(out: *mut *mut RustbusMessage, msg: RustbusMessage) -> c_int {
+    *out = Box::into_raw(Box::new(msg));
+    RUSTBUS_OK
+}
+
+/// Turn the result of building a message into a return code
+fn code(res: Result<c_int, c_int>) -> c_int {
+    res.unwrap_or_else(|code| code)
+}
+
+/// Create a method call. `destination` and `interface` may be null.
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_new_call(
+    destination: *const c_char,
+    object: *const c_char,
+    interface: *const c_char,
+    member: *const c_char,
+    out: *mut *mut RustbusMessage,
+) -> c_int {
+    code((|| {
+        let destination = str_arg(destination, true)?;
+        let object = str_arg(object, false)?;
+        let interface = str_arg(interface, true)?;
+        let member = str_arg(member, false)?;
+        if out.is_null() {
+            return Err(RUSTBUS_ERR_NULL);
+        }
+        check(destination, validate_busname)?;
+        check(object, validate_object_path)?;
+        check(interface, validate_interface)?;
+        check(member, validate_membername)?;
+
+        let mut call = MessageBuilder::new().call(member.unwrap_or_default());
+        if let Some(interface) = interface {
+            call = call.with_interface(interface);
+        }
+        let mut call = call.on(object.unwrap_or_default());
+        if let Some(destination) = destination {
+            call = call.at(destination);
+        }
+        Ok(put(out, RustbusMessage::new(call.build(), Vec::new())))
+    })())
+}
+
+/// Create a signal
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_new_signal(
+    interface: *const c_char,
+    member: *const c_char,
+    object: *const c_char,
+    out: *mut *mut RustbusMessage,
+) -> c_int {
+    code((|| {
+        let interface = str_arg(interface, false)?;
+        let member = str_arg(member, false)?;
+        let object = str_arg(object, false)?;
+        if out.is_null() {
+            return Err(RUSTBUS_ERR_NULL);
+        }
+        check(interface, validate_interface)?;
+        check(member, validate_membername)?;
+        check(object, validate_object_path)?;
+
+        let signal = MessageBuilder::new()
+            .signal(
+                interface.unwrap_or_default(),
+                member.unwrap_or_default(),
+                object.unwrap_or_default(),
+            )
+            .build();
+        Ok(put(out, RustbusMessage::new(signal, Vec::new())))
+    })())
+}
+
+/// Create the reply to `call`
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_new_reply(
+    call: *const RustbusMessage,
+    out: *mut *mut RustbusMessage,
+) -> c_int {
+    match call.as_ref() {
+        Some(call) if !out.is_null() => {
+            let reply = call.msg.dynheader.make_response();
+            put(out, RustbusMessage::new(reply, Vec::new()))
+        }
+        _ => RUSTBUS_ERR_NULL,
+    }
+}
+
+/// Create an error reply to `call`. `text` is the error message, it may be null.
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_new_error(
+    call: *const RustbusMessage,
+    error_name: *const c_char,
+    text: *const c_char,
+    out: *mut *mut RustbusMessage,
+) -> c_int {
+    code((|| {
+        let error_name = str_arg(error_name, false)?;
+        let text = str_arg(text, true)?;
+        let call = call.as_ref().ok_or(RUSTBUS_ERR_NULL)?;
+        if out.is_null() {
+            return Err(RUSTBUS_ERR_NULL);
+        }
+        check(error_name, validate_errorname)?;
+
+        let error = call
+            .msg
+            .dynheader
+            .make_error_response(error_name.unwrap_or_default(), text.map(str::to_owned));
+        let args = match text {
+            Some(text) => vec![(b's', Value::Str(CString::new(text).unwrap()))],
+            None => Vec::new(),
+        };
+        Ok(put(out, RustbusMessage::new(error, args)))
+    })())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_free(msg: *mut RustbusMessage) {
+    if !msg.is_null() {
+        drop(Box::from_raw(msg));
+    }
+}
+
+/// One of the `RUSTBUS_MESSAGE_TYPE_*` constants, 0 for null
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_type(msg: *const RustbusMessage) -> c_int {
+    match msg.as_ref().map(|msg| msg.msg.typ) {
+        Some(MessageType::Call) => RUSTBUS_MESSAGE_TYPE_CALL,
+        Some(MessageType::Reply) => RUSTBUS_MESSAGE_TYPE_REPLY,
+        Some(MessageType::Error) => RUSTBUS_MESSAGE_TYPE_ERROR,
+        Some(MessageType::Signal) => RUSTBUS_MESSAGE_TYPE_SIGNAL,
+        Some(MessageType::Invalid) | None => 0,
+    }
+}
+
+/// The serial of the message, 0 if it has none
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_serial(msg: *const RustbusMessage) -> u32 {
+    msg.as_ref()
+        .and_then(|msg| msg.msg.dynheader.serial)
+        .map_or(0, NonZeroU32::get)
+}
+
+/// The serial of the call this message answers, 0 if it has none
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_reply_serial(msg: *const RustbusMessage) -> u32 {
+    msg.as_ref()
+        .and_then(|msg| msg.msg.dynheader.response_serial)
+        .map_or(0, NonZeroU32::get)
+}
+
+/// One of the header fields named by the `RUSTBUS_HEADER_*` constants, null if it is not set
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_header(
+    msg: *const RustbusMessage,
+    field: c_int,
+) -> *const c_char {
+    msg.as_ref()
+        .and_then(|msg| msg.header.get(usize::try_from(field).ok()?)?.as_ref())
+        .map_or(std::ptr::null(), |s| s.as_ptr())
+}
+
+/// The number of params in the body
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_arg_count(msg: *const RustbusMessage) -> usize {
+    msg.as_ref().map_or(0, |msg| msg.args.len())
+}
+
+/// The first character of the signature of the param at `idx`, e.g. 'u' or 'a'. 0 if there is no such param.
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_arg_type(msg: *const RustbusMessage, idx: usize) -> c_int {
+    msg.as_ref()
+        .and_then(|msg| msg.args.get(idx))
+        .map_or(0, |(typ, _)| c_int::from(*typ))
+}
+
+macro_rules! basic_type {
+    ($push:ident, $get:ident, $t:ty, $typ:literal, $variant:ident) => {
+        /// Append a param to the body
+        #[no_mangle]
+        pub unsafe extern "C" fn $push(msg: *mut RustbusMessage, value: $t) -> c_int {
+            match msg.as_mut() {
+                Some(msg) => msg.push(value, $typ, Value::$variant(value)),
+                None => RUSTBUS_ERR_NULL,
+            }
+        }
+
+        /// Read the param at `idx` into `out`
+        #[no_mangle]
+        pub unsafe extern "C" fn $get(
+            msg: *const RustbusMessage,
+            idx: usize,
+            out: *mut $t,
+        ) -> c_int {
+            code((|| {
+                let msg = msg.as_ref().ok_or(RUSTBUS_ERR_NULL)?;
+                let out = out.as_mut().ok_or(RUSTBUS_ERR_NULL)?;
+                match msg.arg(idx)? {
+                    Value::$variant(value) => {
+                        *out = *value;
+                        Ok(RUSTBUS_OK)
+                    }
+                    _ => Err(RUSTBUS_ERR_WRONG_TYPE),
+                }
+            })())
+        }
+    };
+}
+
+basic_type!(
+    rustbus_message_push_byte,
+    rustbus_message_get_byte,
+    u8,
+    b'y',
+    Byte
+);
+basic_type!(
+    rustbus_message_push_boolean,
+    rustbus_message_get_boolean,
+    bool,
+    b'b',
+    Boolean
+);
+basic_type!(
+    rustbus_message_push_int16,
+    rustbus_message_get_int16,
+    i16,
+    b'n',
+    Int16
+);
+basic_type!(
+    rustbus_message_push_uint16,
+    rustbus_message_get_uint16,
+    u16,
+    b'q',
+    Uint16
+);
+basic_type!(
+    rustbus_message_push_int32,
+    rustbus_message_get_int32,
+    i32,
+    b'i',
+    Int32
+);
+basic_type!(
+    rustbus_message_push_uint32,
+    rustbus_message_get_uint32,
+    u32,
+    b'u',
+    Uint32
+);
+basic_type!(
+    rustbus_message_push_int64,
+    rustbus_message_get_int64,
+    i64,
+    b'x',
+    Int64
+);
+basic_type!(
+    rustbus_message_push_uint64,
+    rustbus_message_get_uint64,
+    u64,
+    b't',
+    Uint64
+);
+basic_type!(
+    rustbus_message_push_double,
+    rustbus_message_get_double,
+    f64,
+    b'd',
+    Double
+);
+
+/// Append a string to the body
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_push_string(
+    msg: *mut RustbusMessage,
+    value: *const c_char,
+) -> c_int {
+    code((|| {
+        let msg = msg.as_mut().ok_or(RUSTBUS_ERR_NULL)?;
+        let value = str_arg(value, false)?.unwrap_or_default();
+        let copy = CString::new(value).unwrap();
+        Ok(msg.push(value, b's', Value::Str(copy)))
+    })())
+}
+
+/// Append an object path to the body
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_push_object_path(
+    msg: *mut RustbusMessage,
+    value: *const c_char,
+) -> c_int {
+    code((|| {
+        let msg = msg.as_mut().ok_or(RUSTBUS_ERR_NULL)?;
+        let value = str_arg(value, false)?.unwrap_or_default();
+        let path = ObjectPath::new(value).map_err(|_| RUSTBUS_ERR_INVALID_ARG)?;
+        let copy = CString::new(value).unwrap();
+        Ok(msg.push(path, b'o', Value::Str(copy)))
+    })())
+}
+
+/// Read the string, object path or signature at `idx`. The string lives as long as the message.
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_get_string(
+    msg: *const RustbusMessage,
+    idx: usize,
+    out: *mut *const c_char,
+) -> c_int {
+    code((|| {
+        let msg = msg.as_ref().ok_or(RUSTBUS_ERR_NULL)?;
+        let out = out.as_mut().ok_or(RUSTBUS_ERR_NULL)?;
+        match msg.arg(idx)? {
+            Value::Str(value) => {
+                *out = value.as_ptr();
+                Ok(RUSTBUS_OK)
+            }
+            _ => Err(RUSTBUS_ERR_WRONG_TYPE),
+        }
+    })())
+}
+
+/// Marshal the whole message with `serial`, which must not be 0. The bytes are handed out in `out` and `out_len` and
+/// have to be freed with `rustbus_bytes_free`.
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_marshal(
+    msg: *mut RustbusMessage,
+    serial: u32,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    code((|| {
+        let msg = msg.as_mut().ok_or(RUSTBUS_ERR_NULL)?;
+        if out.is_null() || out_len.is_null() {
+            return Err(RUSTBUS_ERR_NULL);
+        }
+        let serial = NonZeroU32::new(serial).ok_or(RUSTBUS_ERR_INVALID_ARG)?;
+        msg.msg.dynheader.serial = Some(serial);
+        let mut buf = Vec::new();
+        msg.msg
+            .marshal_into(&mut buf)
+            .map_err(|_| RUSTBUS_ERR_MARSHAL)?;
+        let buf = buf.into_boxed_slice();
+        *out_len = buf.len();
+        *out = Box::into_raw(buf).cast::<u8>();
+        Ok(RUSTBUS_OK)
+    })())
+}
+
+/// Free bytes returned by `rustbus_message_marshal`
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_bytes_free(bytes: *mut u8, len: usize) {
+    if !bytes.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            bytes, len,
+        )));
+    }
+}
+
+/// Parse the message at the start of `bytes`. `consumed` is set to its length, so the bytes after it can be parsed next.
+/// `RUSTBUS_ERR_NOT_ENOUGH_BYTES` means the message is not complete yet.
+#[no_mangle]
+pub unsafe extern "C" fn rustbus_message_parse(
+    bytes: *const u8,
+    len: usize,
+    consumed: *mut usize,
+    out: *mut *mut RustbusMessage,
+) -> c_int {
+    if bytes.is_null() || consumed.is_null() || out.is_null() {
+        return RUSTBUS_ERR_NULL;
+    }
+    let bytes = std::slice::from_raw_parts(bytes, len);
+    match parse(bytes) {
+        Ok((msg, used)) => {
+            *consumed = used;
+            put(out, msg)
+        }
+        Err(UnmarshalError::NotEnoughBytes) => RUSTBUS_ERR_NOT_ENOUGH_BYTES,
+        Err(_) => RUSTBUS_ERR_UNMARSHAL,
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<(RustbusMessage, usize), UnmarshalError> {
+    let header = unmarshal::unmarshal_header(&mut Cursor::new(bytes))?;
+    let fields_len =
+        Cursor::new(bytes.get(HEADER_LEN..).unwrap_or_default()).read_u32(header.byteorder)?;
+    let len = unmarshal::calc_message_len(&header, fields_len)?;
+    let buf = bytes
+        .get(..len)
+        .ok_or(UnmarshalError::NotEnoughBytes)?
+        .to_vec();
+
+    let mut cursor = Cursor::new(&buf);
+    unmarshal::unmarshal_header(&mut cursor)?;
+    let dynheader: DynamicHeader = unmarshal::unmarshal_dynamic_header(&header, &mut cursor)?;
+    let offset = cursor.consumed();
+    let msg = unmarshal::unmarshal_next_message(&header, dynheader, buf, offset, Vec::new())?;
+    msg.body.validate()?;
+
+    // the fds are not passed along with the bytes, so values that contain them are only skipped and never unmarshalled
+    let mut args = Vec::new();
+    let mut ctx = UnmarshalContext::new(&[], msg.body.byteorder(), msg.get_buf(), 0);
+    for typ in Type::parse_description(msg.get_sig())? {
+        let mut sig = String::new();
+        typ.to_str(&mut sig);
+        let str_value = |s: &str| CString::new(s).map_err(|_| Error::StringContainsNullByte);
+        let value = match typ {
+            Type::Base(Base::Byte) => Value::Byte(ctx.read_u8()?),
+            Type::Base(Base::Boolean) => Value::Boolean(ctx.read_bool()?),
+            Type::Base(Base::Int16) => Value::Int16(ctx.read_i16()?),
+            Type::Base(Base::Uint16) => Value::Uint16(ctx.read_u16()?),
+            Type::Base(Base::Int32) => Value::Int32(ctx.read_i32()?),
+            Type::Base(Base::Uint32) => Value::Uint32(ctx.read_u32()?),
+            Type::Base(Base::Int64) => Value::Int64(ctx.read_i64()?),
+            Type::Base(Base::Uint64) => Value::Uint64(ctx.read_u64()?),
+            Type::Base(Base::Double) => Value::Double(f64::from_bits(ctx.read_u64()?)),
+            Type::Base(Base::String | Base::ObjectPath) => Value::Str(str_value(ctx.read_str()?)?),
+            Type::Base(Base::Signature) => Value::Str(str_value(ctx.read_signature()?)?),
+            Type::Base(Base::UnixFd) | Type::Container(_) => {
+                let len = ctx.validate_next(&typ)?;
+                ctx.read_raw(len)?;
+                Value::Other
+            }
+        };
+        args.push((sig.as_bytes()[0], value));
+    }
+    Ok((RustbusMessage::new(msg, args), len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    unsafe fn header(msg: *const RustbusMessage, field: c_int) -> Option<&'static str> {
+        let ptr = rustbus_message_header(msg, field);
+        (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_str().unwrap())
+    }
+
+    #[test]
+    fn roundtrip() {
+        unsafe {
+            let mut call = ptr::null_mut();
+            assert_eq!(
+                rustbus_message_new_call(
+                    c("io.killing.spark").as_ptr(),
+                    c("/io/killing/spark").as_ptr(),
+                    ptr::null(),
+                    c("Frobnicate").as_ptr(),
+                    &mut call,
+                ),
+                RUSTBUS_OK
+            );
+            assert_eq!(rustbus_message_push_uint32(call, 42), RUSTBUS_OK);
+            assert_eq!(rustbus_message_push_boolean(call, true), RUSTBUS_OK);
+            assert_eq!(rustbus_message_push_double(call, 0.5), RUSTBUS_OK);
+            assert_eq!(
+                rustbus_message_push_string(call, c("text").as_ptr()),
+                RUSTBUS_OK
+            );
+            assert_eq!(
+                rustbus_message_push_object_path(call, c("no path").as_ptr()),
+                RUSTBUS_ERR_INVALID_ARG
+            );
+            assert_eq!(rustbus_message_arg_count(call), 4);
+
+            let (mut bytes, mut len) = (ptr::null_mut(), 0);
+            assert_eq!(
+                rustbus_message_marshal(call, 0, &mut bytes, &mut len),
+                RUSTBUS_ERR_INVALID_ARG
+            );
+            assert_eq!(
+                rustbus_message_marshal(call, 7, &mut bytes, &mut len),
+                RUSTBUS_OK
+            );
+            rustbus_message_free(call);
+
+            let mut received = ptr::null_mut();
+            let mut consumed = 0;
+            assert_eq!(
+                rustbus_message_parse(bytes, len - 1, &mut consumed, &mut received),
+                RUSTBUS_ERR_NOT_ENOUGH_BYTES
+            );
+            assert_eq!(
+                rustbus_message_parse(bytes, len, &mut consumed, &mut received),
+                RUSTBUS_OK
+            );
+            rustbus_bytes_free(bytes, len);
+            assert_eq!(consumed, len);
+
+            assert_eq!(rustbus_message_type(received), RUSTBUS_MESSAGE_TYPE_CALL);
+            assert_eq!(rustbus_message_serial(received), 7);
+            assert_eq!(header(received, RUSTBUS_HEADER_MEMBER), Some("Frobnicate"));
+            assert_eq!(header(received, RUSTBUS_HEADER_INTERFACE), None);
+            assert_eq!(rustbus_message_arg_count(received), 4);
+            assert_eq!(rustbus_message_arg_type(received, 3), c_int::from(b's'));
+            assert_eq!(rustbus_message_arg_type(received, 4), 0);
+
+            let mut number = 0u32;
+            assert_eq!(
+                rustbus_message_get_uint32(received, 0, &mut number),
+                RUSTBUS_OK
+            );
+            assert_eq!(number, 42);
+            assert_eq!(
+                rustbus_message_get_uint32(received, 1, &mut number),
+                RUSTBUS_ERR_WRONG_TYPE
+            );
+            assert_eq!(
+                rustbus_message_get_uint32(received, 4, &mut number),
+                RUSTBUS_ERR_OUT_OF_RANGE
+            );
+            let mut double = 0.0;
+            assert_eq!(
+                rustbus_message_get_double(received, 2, &mut double),
+                RUSTBUS_OK
+            );
+            assert_eq!(double, 0.5);
+            let mut text = ptr::null();
+            assert_eq!(
+                rustbus_message_get_string(received, 3, &mut text),
+                RUSTBUS_OK
+            );
+            assert_eq!(CStr::from_ptr(text).to_str(), Ok("text"));
+
+            let mut error = ptr::null_mut();
+            assert_eq!(
+                rustbus_message_new_error(
+                    received,
+                    c("io.killing.spark.Error.Failed").as_ptr(),
+                    c("it broke").as_ptr(),
+                    &mut error,
+                ),
+                RUSTBUS_OK
+            );
+            rustbus_message_free(received);
+            assert_eq!(rustbus_message_type(error), RUSTBUS_MESSAGE_TYPE_ERROR);
+            assert_eq!(rustbus_message_reply_serial(error), 7);
+            assert_eq!(
+                header(error, RUSTBUS_HEADER_ERROR_NAME),
+                Some("io.killing.spark.Error.Failed")
+            );
+            assert_eq!(rustbus_message_get_string(error, 0, &mut text), RUSTBUS_OK);
+            assert_eq!(CStr::from_ptr(text).to_str(), Ok("it broke"));
+            rustbus_message_free(error);
+        }
+    }
+
+    #[test]
+    fn fds_and_containers() {
+        use std::os::unix::io::IntoRawFd;
+
+        let fd = || std::fs::File::open("/dev/null").unwrap().into_raw_fd();
+        let mut msg = MessageBuilder::new()
+            .signal("io.killing.spark", "Files", "/io/killing/spark")
+            .build();
+        msg.body.push_param(1u32).unwrap();
+        msg.body
+            .push_param(rustbus::wire::UnixFd::new(fd()))
+            .unwrap();
+        msg.body.push_param(vec![2u32, 3]).unwrap();
+        msg.body
+            .push_param((rustbus::wire::UnixFd::new(fd()), "nested"))
+            .unwrap();
+        msg.body.push_param("after").unwrap();
+        let mut bytes = Vec::new();
+        rustbus::wire::marshal::marshal(&msg, NonZeroU32::MIN, &mut bytes).unwrap();
+        bytes.extend_from_slice(msg.get_buf());
+
+        unsafe {
+            let mut received = ptr::null_mut();
+            let mut consumed = 0;
+            assert_eq!(
+                rustbus_message_parse(bytes.as_ptr(), bytes.len(), &mut consumed, &mut received),
+                RUSTBUS_OK
+            );
+            assert_eq!(rustbus_message_arg_count(received), 5);
+            let types: Vec<c_int> = (0..5)
+                .map(|idx| rustbus_message_arg_type(received, idx))
+                .collect();
+            assert_eq!(types, [b'u', b'h', b'a', b'(', b's'].map(c_int::from));
+            let mut number = 0u32;
+            assert_eq!(
+                rustbus_message_get_uint32(received, 1, &mut number),
+                RUSTBUS_ERR_WRONG_TYPE
+            );
+            let mut text = ptr::null();
+            assert_eq!(
+                rustbus_message_get_string(received, 4, &mut text),
+                RUSTBUS_OK
+            );
+            assert_eq!(CStr::from_ptr(text).to_str(), Ok("after"));
+            rustbus_message_free(received);
+        }
+    }
+
+    #[test]
+    fn invalid_input() {
+        unsafe {
+            let mut msg = ptr::null_mut();
+            assert_eq!(
+                rustbus_message_new_signal(
+                    c("no interface").as_ptr(),
+                    c("Member").as_ptr(),
+                    c("/").as_ptr(),
+                    &mut msg,
+                ),
+                RUSTBUS_ERR_INVALID_ARG
+            );
+            assert_eq!(
+                rustbus_message_new_signal(
+                    ptr::null(),
+                    c("Member").as_ptr(),
+                    c("/").as_ptr(),
+                    &mut msg
+                ),
+                RUSTBUS_ERR_NULL
+            );
+            assert_eq!(
+                rustbus_message_push_uint32(ptr::null_mut(), 1),
+                RUSTBUS_ERR_NULL
+            );
+            assert!(rustbus_message_header(ptr::null(), RUSTBUS_HEADER_MEMBER).is_null());
+
+            let garbage = [b'l', 7, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0];
+            let mut consumed = 0;
+            assert_eq!(
+                rustbus_message_parse(garbage.as_ptr(), garbage.len(), &mut consumed, &mut msg),
+                RUSTBUS_ERR_UNMARSHAL
+            );
+        }
+    }
+}